@@ -92,15 +92,15 @@ mod tests {
                 Observation::new(
                     "GodDoesnt".to_string(),
                     Some((
-                        NaiveDate::from_ymd(2013, 01, 04),
-                        NaiveDate::from_ymd(2018, 07, 28),
+                        NaiveDate::from_ymd_opt(2013, 1, 4).unwrap(),
+                        NaiveDate::from_ymd_opt(2018, 7, 28).unwrap(),
                     )),
                 ),
                 Observation::new(
                     "ConceptualJames".to_string(),
                     Some((
-                        NaiveDate::from_ymd(2018, 07, 29),
-                        NaiveDate::from_ymd(2022, 08, 05),
+                        NaiveDate::from_ymd_opt(2018, 7, 29).unwrap(),
+                        NaiveDate::from_ymd_opt(2022, 8, 5).unwrap(),
                     )),
                 ),
             ],
@@ -0,0 +1,43 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use memory_lol::db::codec;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((id, screen_name)) = codec::key_to_pair(data) {
+        let key = codec::pair_to_key(id, screen_name);
+
+        // Keys written before normalization may decode to unnormalized screen names
+        if codec::normalize_screen_name(screen_name) == screen_name {
+            assert_eq!(key, data);
+        }
+        assert_eq!(codec::key_to_pair(&key).unwrap().0, id);
+        assert_eq!(
+            codec::pair_to_key(id, codec::key_to_pair(&key).unwrap().1),
+            key
+        );
+    }
+
+    if let Ok(screen_name) = codec::key_to_screen_name(data) {
+        let key = codec::screen_name_to_key(screen_name);
+        assert!(codec::key_to_screen_name(&key).is_ok());
+    }
+
+    if let Ok(dates) = codec::value_to_dates(data) {
+        let value = codec::dates_to_value(&dates).unwrap();
+        assert_eq!(codec::value_to_dates(&value).unwrap(), dates);
+
+        let borrowed = codec::DayIds::new(data).unwrap();
+        assert_eq!(borrowed.len(), dates.len());
+        assert_eq!(borrowed.first(), dates.first().copied());
+        assert_eq!(borrowed.last(), dates.last().copied());
+    }
+
+    if let Ok(ids) = codec::value_to_ids(data) {
+        assert_eq!(codec::ids_to_value(&ids), data);
+    }
+
+    if let Ok(weights) = codec::value_to_weights(data) {
+        assert_eq!(codec::weights_to_value(&weights).unwrap(), data);
+    }
+});
@@ -0,0 +1,285 @@
+//! Settings that can be changed while a lookup server is running.
+//!
+//! Restarting a server drops its warmed block cache, so the log level, the block cache size, rate
+//! limits, and how much history untrusted clients can see are adjusted through an authenticated
+//! admin endpoint instead.
+use crate::db::SharedCache;
+use crate::ratelimit::{constant_time_eq, RateLimiter};
+use chrono::{Duration, NaiveDate};
+use log::LevelFilter;
+use serde_derive::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicI64, Ordering};
+
+/// Default number of days of observations that lookups by untrusted clients can see.
+pub const DEFAULT_UNTRUSTED_DAY_LIMIT: i64 = 60;
+
+#[derive(thiserror::Error, Debug, Eq, PartialEq)]
+pub enum Error {
+    #[error("Invalid log level: {0}")]
+    InvalidLogLevel(String),
+    #[error("No shared block cache (the server was started without a block cache size)")]
+    NoBlockCache,
+    #[error("Rate limiting is not enabled")]
+    NoRateLimiter,
+    #[error("Invalid rate limiting window")]
+    InvalidWindow,
+    #[error("The server has no trust levels")]
+    NoTrustPolicy,
+    #[error("Invalid day limit: {0}")]
+    InvalidDayLimit(i64),
+}
+
+/// How much history lookups by untrusted clients can see.
+#[derive(Debug)]
+pub struct TrustPolicy {
+    untrusted_day_limit: AtomicI64,
+}
+
+impl TrustPolicy {
+    pub fn new(untrusted_day_limit: i64) -> Self {
+        Self {
+            untrusted_day_limit: AtomicI64::new(untrusted_day_limit),
+        }
+    }
+
+    pub fn untrusted_day_limit(&self) -> i64 {
+        self.untrusted_day_limit.load(Ordering::Relaxed)
+    }
+
+    /// The earliest day whose observations untrusted clients can see, as of a given day.
+    pub fn earliest_untrusted_date(&self, today: NaiveDate) -> NaiveDate {
+        today - Duration::days(self.untrusted_day_limit())
+    }
+}
+
+impl Default for TrustPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_UNTRUSTED_DAY_LIMIT)
+    }
+}
+
+/// A server's current settings (those it doesn't support are left out).
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Settings {
+    pub log_level: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_cache_size: Option<usize>,
+    /// Bytes currently used by cached blocks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_cache_usage: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimitSettings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub untrusted_day_limit: Option<i64>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub struct RateLimitSettings {
+    pub requests: u32,
+    pub window_secs: u64,
+}
+
+/// Changes to make to the settings (missing values are left as they are).
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SettingsUpdate {
+    /// `off`, `error`, `warn`, `info`, `debug`, or `trace`
+    pub log_level: Option<String>,
+    /// Block cache capacity in bytes
+    pub block_cache_size: Option<usize>,
+    pub rate_limit: Option<RateLimitUpdate>,
+    pub untrusted_day_limit: Option<i64>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitUpdate {
+    pub requests: Option<u32>,
+    pub window_secs: Option<u64>,
+}
+
+/// The parts of a running server that can be reconfigured.
+pub struct Runtime<'a> {
+    pub cache: &'a SharedCache,
+    pub limiter: Option<&'a RateLimiter>,
+    pub trust: Option<&'a TrustPolicy>,
+}
+
+impl Runtime<'_> {
+    pub fn settings(&self) -> Settings {
+        Settings {
+            log_level: log::max_level().to_string().to_lowercase(),
+            block_cache_size: self.cache.capacity(),
+            block_cache_usage: self.cache.usage(),
+            rate_limit: self.limiter.map(|limiter| {
+                let (requests, window_secs) = limiter.limits();
+
+                RateLimitSettings {
+                    requests,
+                    window_secs,
+                }
+            }),
+            untrusted_day_limit: self.trust.map(TrustPolicy::untrusted_day_limit),
+        }
+    }
+
+    /// Apply an update, returning the new settings.
+    ///
+    /// Nothing is changed if any part of the update is invalid or unsupported.
+    pub fn apply(&self, update: &SettingsUpdate) -> Result<Settings, Error> {
+        let log_level = update
+            .log_level
+            .as_deref()
+            .map(|level| {
+                level
+                    .parse::<LevelFilter>()
+                    .map_err(|_| Error::InvalidLogLevel(level.to_string()))
+            })
+            .transpose()?;
+
+        if update.block_cache_size.is_some() && self.cache.capacity().is_none() {
+            return Err(Error::NoBlockCache);
+        }
+
+        let limits = match (update.rate_limit, self.limiter) {
+            (Some(rate_limit), Some(limiter)) => {
+                let (requests, window_secs) = limiter.limits();
+                let window_secs = rate_limit.window_secs.unwrap_or(window_secs);
+
+                if window_secs == 0 {
+                    return Err(Error::InvalidWindow);
+                }
+
+                Some((
+                    limiter,
+                    rate_limit.requests.unwrap_or(requests),
+                    window_secs,
+                ))
+            }
+            (Some(_), None) => return Err(Error::NoRateLimiter),
+            (None, _) => None,
+        };
+
+        let day_limit = match (update.untrusted_day_limit, self.trust) {
+            (Some(days), Some(_)) if days < 0 => return Err(Error::InvalidDayLimit(days)),
+            (Some(days), Some(trust)) => Some((trust, days)),
+            (Some(_), None) => return Err(Error::NoTrustPolicy),
+            (None, _) => None,
+        };
+
+        if let Some(level) = log_level {
+            log::set_max_level(level);
+        }
+
+        if let Some(size) = update.block_cache_size {
+            self.cache.set_capacity(size);
+        }
+
+        if let Some((limiter, requests, window_secs)) = limits {
+            limiter.set_limits(requests, window_secs);
+        }
+
+        if let Some((trust, days)) = day_limit {
+            trust.untrusted_day_limit.store(days, Ordering::Relaxed);
+        }
+
+        let settings = self.settings();
+        log::warn!("Settings changed: {:?}", settings);
+
+        Ok(settings)
+    }
+}
+
+/// Whether an `Authorization` header carries the admin token as a bearer token.
+pub fn is_authorized(token: &str, authorization: Option<&str>) -> bool {
+    !token.is_empty()
+        && authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|given| constant_time_eq(token.as_bytes(), given.trim().as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Database, DatabaseOptions, Writeable};
+    use crate::ratelimit::RateLimitConfig;
+
+    #[test]
+    fn apply_updates() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = DatabaseOptions {
+            block_cache_size: Some(1 << 20),
+            ..Default::default()
+        };
+        let _db = Database::<Writeable>::open_with_options(dir.path(), &options).unwrap();
+
+        let limiter = RateLimiter::new(RateLimitConfig {
+            requests: 10,
+            window_secs: 60,
+            allowed_networks: vec![],
+            api_keys: vec![],
+            state: None,
+        })
+        .unwrap();
+        let trust = TrustPolicy::default();
+        let runtime = Runtime {
+            cache: &options.cache,
+            limiter: Some(&limiter),
+            trust: Some(&trust),
+        };
+
+        let update = serde_json::from_str::<SettingsUpdate>(
+            r#"{"block_cache_size":2097152,"rate_limit":{"requests":5},"untrusted_day_limit":30}"#,
+        )
+        .unwrap();
+        let settings = runtime.apply(&update).unwrap();
+
+        assert_eq!(settings.block_cache_size, Some(2 << 20));
+        assert_eq!(
+            settings.rate_limit,
+            Some(RateLimitSettings {
+                requests: 5,
+                window_secs: 60
+            })
+        );
+        assert_eq!(limiter.limits(), (5, 60));
+        assert_eq!(
+            trust.earliest_untrusted_date(NaiveDate::from_ymd_opt(2022, 2, 1).unwrap()),
+            NaiveDate::from_ymd_opt(2022, 1, 2).unwrap()
+        );
+
+        // Invalid updates change nothing
+        let update = SettingsUpdate {
+            rate_limit: Some(RateLimitUpdate {
+                requests: Some(1),
+                window_secs: None,
+            }),
+            log_level: Some("loud".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            runtime.apply(&update),
+            Err(Error::InvalidLogLevel("loud".to_string()))
+        );
+        assert_eq!(limiter.limits(), (5, 60));
+
+        let unsupported = Runtime {
+            cache: &SharedCache::default(),
+            limiter: None,
+            trust: None,
+        };
+        assert_eq!(
+            unsupported.apply(&SettingsUpdate {
+                untrusted_day_limit: Some(7),
+                ..Default::default()
+            }),
+            Err(Error::NoTrustPolicy)
+        );
+        assert!(serde_json::from_str::<SettingsUpdate>(r#"{"cache":1}"#).is_err());
+
+        assert!(is_authorized("secret", Some("Bearer secret")));
+        assert!(!is_authorized("secret", Some("Bearer guess")));
+        assert!(!is_authorized("secret", Some("secret")));
+        assert!(!is_authorized("", Some("Bearer ")));
+    }
+}
@@ -0,0 +1,134 @@
+//! HTTP cache validators for lookup responses.
+//!
+//! Servers send an `ETag` computed from the response body and a `Last-Modified` time, and answer
+//! conditional requests whose validators still match with `304 Not Modified`, so that clients
+//! that repeat the same lookups (such as browser extensions) don't have to download the results
+//! again.
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use sha2::{Digest, Sha256};
+
+/// The format of dates in HTTP headers (RFC 9110's `IMF-fixdate`).
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+/// Number of bytes of the body's digest used in the entity tag.
+const ETAG_LEN: usize = 16;
+
+/// `Cache-Control` for compact lookups, which browser extensions make for every account they
+/// show, so shared caches can answer them and a stale copy is better than another request.
+pub const COMPACT_CACHE_CONTROL: &str = "public, max-age=3600, stale-while-revalidate=86400";
+
+/// `Cache-Control` for responses that never change (like evidence snapshots).
+pub const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// The validators for a response.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Validators {
+    /// A strong entity tag (including its quotes)
+    pub etag: String,
+    /// Truncated to whole seconds, since that's all HTTP dates can represent
+    pub last_modified: DateTime<Utc>,
+}
+
+impl Validators {
+    pub fn new(body: &[u8], last_modified: DateTime<Utc>) -> Self {
+        let digest = Sha256::digest(body);
+        let hex = digest[..ETAG_LEN]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+
+        Self {
+            etag: format!("\"{}\"", hex),
+            last_modified: Utc
+                .timestamp_opt(last_modified.timestamp(), 0)
+                .single()
+                .unwrap_or(last_modified),
+        }
+    }
+
+    /// The value of the `Last-Modified` header.
+    pub fn last_modified_header(&self) -> String {
+        format_http_date(self.last_modified)
+    }
+
+    /// Whether a conditional request's cached copy is still current, given the values of its
+    /// `If-None-Match` and `If-Modified-Since` headers.
+    ///
+    /// As in RFC 9110, `If-Modified-Since` is ignored when `If-None-Match` is present.
+    pub fn is_not_modified(
+        &self,
+        if_none_match: Option<&str>,
+        if_modified_since: Option<&str>,
+    ) -> bool {
+        match if_none_match {
+            Some(value) => value.split(',').map(str::trim).any(|tag| {
+                // Weak comparison, since a weak tag from an intermediary still names our content
+                tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == self.etag
+            }),
+            None => if_modified_since
+                .and_then(parse_http_date)
+                .is_some_and(|since| self.last_modified <= since),
+        }
+    }
+}
+
+/// The latest time the results of a database opened at the given time could have changed.
+///
+/// Read-only databases don't see later writes, but date-restricted results also change when the
+/// UTC date does.
+pub fn last_modified(opened: DateTime<Utc>, now: DateTime<Utc>) -> DateTime<Utc> {
+    let today = now.date_naive().and_time(chrono::NaiveTime::MIN).and_utc();
+
+    opened.max(today)
+}
+
+pub fn format_http_date(value: DateTime<Utc>) -> String {
+    value.format(HTTP_DATE_FORMAT).to_string()
+}
+
+pub fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(value.trim(), HTTP_DATE_FORMAT)
+        .ok()
+        .map(|value| value.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conditional_requests() {
+        let modified = Utc.with_ymd_and_hms(2022, 1, 2, 3, 4, 5).unwrap();
+        let validators = Validators::new(b"{}", modified + chrono::Duration::milliseconds(500));
+        let etag = validators.etag.clone();
+
+        assert_eq!(etag.len(), ETAG_LEN * 2 + 2);
+        assert_ne!(Validators::new(b"[]", modified).etag, etag);
+        assert_eq!(validators.last_modified, modified);
+        assert_eq!(
+            validators.last_modified_header(),
+            "Sun, 02 Jan 2022 03:04:05 GMT"
+        );
+        assert_eq!(
+            parse_http_date("Sun, 02 Jan 2022 03:04:05 GMT"),
+            Some(modified)
+        );
+
+        assert!(validators.is_not_modified(Some(&etag), None));
+        assert!(validators.is_not_modified(Some(&format!("\"abc\", W/{}", etag)), None));
+        assert!(validators.is_not_modified(Some("*"), None));
+        assert!(!validators.is_not_modified(Some("\"abc\""), None));
+        // The entity tag takes precedence
+        assert!(!validators.is_not_modified(Some("\"abc\""), Some("Sun, 02 Jan 2022 03:04:05 GMT")));
+        assert!(validators.is_not_modified(None, Some("Sun, 02 Jan 2022 03:04:05 GMT")));
+        assert!(!validators.is_not_modified(None, Some("Sun, 02 Jan 2022 03:04:04 GMT")));
+        assert!(!validators.is_not_modified(None, Some("yesterday")));
+        assert!(!validators.is_not_modified(None, None));
+
+        let now = Utc.with_ymd_and_hms(2022, 1, 3, 12, 0, 0).unwrap();
+        assert_eq!(
+            last_modified(modified, now),
+            Utc.with_ymd_and_hms(2022, 1, 3, 0, 0, 0).unwrap()
+        );
+        assert_eq!(last_modified(now, now), now);
+    }
+}
@@ -0,0 +1,194 @@
+//! Visually confusable screen names, for impersonation searches.
+//!
+//! Screen names can only contain ASCII letters, digits, and underscores, so a handle containing
+//! Unicode homoglyphs (as pasted from a display name, for example) is first mapped to the ASCII
+//! characters it resembles, and is then expanded into every combination of ASCII characters that
+//! look alike (`0` and `o`, `1`, `l`, and `i`, `rn` and `m`, etc.).
+
+/// Groups of ASCII strings that are easily mistaken for each other (case-insensitively).
+const CONFUSABLE_GROUPS: &[&[&str]] = &[
+    &["o", "0"],
+    &["l", "1", "i"],
+    &["s", "5"],
+    &["b", "8"],
+    &["z", "2"],
+    &["e", "3"],
+    &["g", "9"],
+    &["m", "rn"],
+    &["w", "vv"],
+    &["d", "cl"],
+];
+
+/// Non-ASCII characters and the ASCII characters they resemble.
+const HOMOGLYPHS: &[(char, char)] = &[
+    // Cyrillic
+    ('а', 'a'),
+    ('в', 'b'),
+    ('е', 'e'),
+    ('ё', 'e'),
+    ('һ', 'h'),
+    ('і', 'i'),
+    ('ї', 'i'),
+    ('ј', 'j'),
+    ('к', 'k'),
+    ('м', 'm'),
+    ('н', 'h'),
+    ('о', 'o'),
+    ('р', 'p'),
+    ('с', 'c'),
+    ('т', 't'),
+    ('у', 'y'),
+    ('х', 'x'),
+    ('ѕ', 's'),
+    ('ԁ', 'd'),
+    ('ԛ', 'q'),
+    ('ԝ', 'w'),
+    // Greek
+    ('α', 'a'),
+    ('β', 'b'),
+    ('ε', 'e'),
+    ('η', 'n'),
+    ('ι', 'i'),
+    ('κ', 'k'),
+    ('ν', 'v'),
+    ('ο', 'o'),
+    ('ρ', 'p'),
+    ('τ', 't'),
+    ('υ', 'u'),
+    ('χ', 'x'),
+    // Latin
+    ('ı', 'i'),
+    ('ȷ', 'j'),
+    ('ɡ', 'g'),
+    ('ǀ', 'l'),
+    ('ø', 'o'),
+    // Other
+    ('|', 'l'),
+    ('-', '_'),
+];
+
+/// The ASCII character a character resembles, if any.
+fn to_ascii(ch: char) -> Option<char> {
+    let lowercase = ch.to_lowercase().next().unwrap_or(ch);
+
+    if let Some((_, ascii)) = HOMOGLYPHS
+        .iter()
+        .find(|(homoglyph, _)| *homoglyph == lowercase)
+    {
+        Some(*ascii)
+    } else if ('\u{ff01}'..='\u{ff5e}').contains(&ch) {
+        // Fullwidth forms of printable ASCII characters
+        char::from_u32(ch as u32 - 0xff01 + 0x21)
+    } else if ch.is_ascii() {
+        Some(ch)
+    } else {
+        None
+    }
+}
+
+/// Map a handle to the lowercase ASCII screen name it resembles.
+///
+/// Returns `None` if the handle contains characters that can't appear in screen names and don't
+/// resemble any that can. A leading `@` is ignored.
+pub fn normalize(handle: &str) -> Option<String> {
+    let handle = handle.strip_prefix('@').unwrap_or(handle);
+
+    handle
+        .chars()
+        .map(|ch| {
+            to_ascii(ch)
+                .map(|ch| ch.to_ascii_lowercase())
+                .filter(|ch| ch.is_ascii_alphanumeric() || *ch == '_')
+        })
+        .collect()
+}
+
+/// Split a normalized screen name into groups of alternatives, matching greedily from the left.
+fn alternatives(screen_name: &str) -> Vec<Vec<&str>> {
+    let mut result = vec![];
+    let mut rest = screen_name;
+
+    while !rest.is_empty() {
+        // Prefer longer matches (so that `rn` is treated as a unit)
+        let group = CONFUSABLE_GROUPS
+            .iter()
+            .flat_map(|group| group.iter().map(move |member| (*group, *member)))
+            .filter(|(_, member)| rest.starts_with(member))
+            .max_by_key(|(_, member)| member.len());
+
+        match group {
+            Some((group, member)) => {
+                result.push(group.to_vec());
+                rest = &rest[member.len()..];
+            }
+            None => {
+                let len = rest.chars().next().map_or(1, char::len_utf8);
+                result.push(vec![&rest[..len]]);
+                rest = &rest[len..];
+            }
+        }
+    }
+
+    result
+}
+
+/// The number of variants of a normalized screen name.
+pub fn variant_count(screen_name: &str) -> usize {
+    alternatives(screen_name)
+        .iter()
+        .map(|group| group.len())
+        .fold(1, usize::saturating_mul)
+}
+
+/// Every screen name confusable with a normalized screen name (including itself), in sorted order.
+///
+/// The number of variants grows exponentially with the number of confusable characters, so
+/// callers should check [`variant_count`] first.
+pub fn variants(screen_name: &str) -> Vec<String> {
+    let mut variants = vec![String::new()];
+
+    for group in alternatives(screen_name) {
+        variants = variants
+            .iter()
+            .flat_map(|prefix| {
+                group
+                    .iter()
+                    .map(move |member| format!("{}{}", prefix, member))
+            })
+            .collect();
+    }
+
+    variants.sort();
+    variants.dedup();
+    variants
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_homoglyphs() {
+        // Cyrillic а and о, and a fullwidth digit
+        assert_eq!(
+            normalize("@Jаck_d\u{43e}rsey\u{ff11}"),
+            Some("jack_dorsey1".to_string())
+        );
+        assert_eq!(normalize("jack!"), None);
+        assert_eq!(normalize("ǀ-ǀ"), Some("l_l".to_string()));
+    }
+
+    #[test]
+    fn expand_variants() {
+        assert_eq!(
+            variants("bob"),
+            vec!["808", "80b", "8o8", "8ob", "b08", "b0b", "bo8", "bob"]
+        );
+        assert_eq!(variant_count("bob"), 8);
+        assert_eq!(variants("mo"), vec!["m0", "mo", "rn0", "rno"]);
+        assert_eq!(variants("rnx"), vec!["mx", "rnx"]);
+        assert_eq!(variants("hat"), vec!["hat"]);
+        assert_eq!(variant_count("hat"), 1);
+        assert_eq!(variants(""), vec![""]);
+    }
+}
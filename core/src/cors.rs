@@ -0,0 +1,129 @@
+//! Cross-origin resource sharing policies for lookup servers.
+use serde_derive::Deserialize;
+
+/// Origin value that allows requests from any origin.
+pub const ANY_ORIGIN: &str = "*";
+/// How long browsers may cache the result of a preflight request (in seconds).
+const PREFLIGHT_MAX_AGE: u32 = 86400;
+/// Methods supported by the lookup endpoints.
+const ALLOWED_METHODS: &str = "GET, POST, OPTIONS";
+/// Response headers that scripts can read besides the CORS-safelisted ones.
+const EXPOSED_HEADERS: &str = "ETag";
+
+/// Which origins browser scripts can call a server from.
+///
+/// Credentials are never allowed, so cross-origin requests only see results for the access level
+/// of a token passed in the request itself.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct CorsPolicy {
+    /// Origins allowed to read responses (for example `https://example.com`), or `*` for any
+    pub allowed_origins: Vec<String>,
+    /// Request headers allowed in addition to the CORS-safelisted ones
+    #[serde(default)]
+    pub allowed_headers: Vec<String>,
+}
+
+impl CorsPolicy {
+    /// A policy that allows scripts on any origin.
+    pub fn any_origin() -> Self {
+        Self {
+            allowed_origins: vec![ANY_ORIGIN.to_string()],
+            allowed_headers: vec![],
+        }
+    }
+
+    pub fn allows_any_origin(&self) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|origin| origin == ANY_ORIGIN)
+    }
+
+    /// The headers to add to a response, given the request's `Origin` header and whether it's a
+    /// preflight (`OPTIONS`) request.
+    pub fn response_headers(
+        &self,
+        origin: Option<&str>,
+        preflight: bool,
+    ) -> Vec<(&'static str, String)> {
+        let mut headers = vec![];
+
+        let allowed_origin = if self.allows_any_origin() {
+            Some(ANY_ORIGIN.to_string())
+        } else {
+            // The response depends on the origin, so caches must keep them apart
+            headers.push(("Vary", "Origin".to_string()));
+
+            origin
+                .filter(|origin| self.allowed_origins.iter().any(|allowed| allowed == origin))
+                .map(str::to_string)
+        };
+
+        if let Some(allowed_origin) = allowed_origin.filter(|_| origin.is_some()) {
+            headers.push(("Access-Control-Allow-Origin", allowed_origin));
+
+            if preflight {
+                headers.push(("Access-Control-Allow-Methods", ALLOWED_METHODS.to_string()));
+                headers.push(("Access-Control-Max-Age", PREFLIGHT_MAX_AGE.to_string()));
+
+                if !self.allowed_headers.is_empty() {
+                    headers.push((
+                        "Access-Control-Allow-Headers",
+                        self.allowed_headers.join(", "),
+                    ));
+                }
+            } else {
+                headers.push(("Access-Control-Expose-Headers", EXPOSED_HEADERS.to_string()));
+            }
+        }
+
+        headers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response_headers() {
+        let any = CorsPolicy::any_origin();
+
+        assert_eq!(any.response_headers(None, false), vec![]);
+        assert_eq!(
+            any.response_headers(Some("https://example.com"), false),
+            vec![
+                ("Access-Control-Allow-Origin", "*".to_string()),
+                ("Access-Control-Expose-Headers", "ETag".to_string())
+            ]
+        );
+
+        let restricted = CorsPolicy {
+            allowed_origins: vec!["https://example.com".to_string()],
+            allowed_headers: vec!["X-Requested-With".to_string()],
+        };
+
+        assert_eq!(
+            restricted.response_headers(Some("https://example.com"), true),
+            vec![
+                ("Vary", "Origin".to_string()),
+                (
+                    "Access-Control-Allow-Origin",
+                    "https://example.com".to_string()
+                ),
+                (
+                    "Access-Control-Allow-Methods",
+                    "GET, POST, OPTIONS".to_string()
+                ),
+                ("Access-Control-Max-Age", "86400".to_string()),
+                (
+                    "Access-Control-Allow-Headers",
+                    "X-Requested-With".to_string()
+                )
+            ]
+        );
+        assert_eq!(
+            restricted.response_headers(Some("https://example.org"), false),
+            vec![("Vary", "Origin".to_string())]
+        );
+    }
+}
@@ -1,14 +1,22 @@
 use super::{
+    codec::{
+        dates_to_stored_value, id_to_key_prefix, key_prefix_to_id, key_to_pair, open_stored_value,
+        pair_to_key, stored_value_to_dates, DayIds,
+    },
+    encryption::{self, Keyring, RotationReport},
+    options::DatabaseOptions,
     table::{Mode, Table, Writeable},
     util::is_valid_screen_name,
     Error,
 };
-use chrono::{Duration, NaiveDate};
-use rocksdb::{DBIterator, IteratorMode, MergeOperands, Options, DB};
+use chrono::NaiveDate;
+use rocksdb::{DBIterator, Direction, IteratorMode, MergeOperands, Options, DB};
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::marker::PhantomData;
 use std::path::Path;
+use std::sync::Arc;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct AccountTableCounts {
@@ -18,6 +26,7 @@ pub struct AccountTableCounts {
 
 pub struct AccountTable<M> {
     db: DB,
+    keyring: Option<Arc<Keyring>>,
     mode: PhantomData<M>,
 }
 
@@ -55,30 +64,114 @@ impl<M> Table for AccountTable<M> {
 }
 
 impl<M> AccountTable<M> {
-    pub fn pairs(&self) -> PairIterator {
+    pub fn keyring(&self) -> Option<&Keyring> {
+        self.keyring.as_deref()
+    }
+
+    fn encode(&self, key: &[u8], dates: &[NaiveDate]) -> Result<Vec<u8>, Error> {
+        dates_to_stored_value(key, dates, self.keyring.as_deref())
+    }
+
+    fn decode(&self, key: &[u8], value: &[u8]) -> Result<Vec<NaiveDate>, Error> {
+        stored_value_to_dates(key, value, self.keyring.as_deref())
+    }
+
+    pub fn pairs(&self) -> PairIterator<'_> {
         PairIterator {
             underlying: self.db.iterator(IteratorMode::Start),
+            keyring: self.keyring.as_deref(),
         }
     }
 
+    /// Pairs in key order, starting from the first pair with an ID at least as large as the given
+    /// one.
+    pub fn pairs_from(&self, id: u64) -> PairIterator<'_> {
+        PairIterator {
+            underlying: self.db.iterator(IteratorMode::From(
+                &id_to_key_prefix(id),
+                Direction::Forward,
+            )),
+            keyring: self.keyring.as_deref(),
+        }
+    }
+
+    /// The largest ID in the table.
+    pub fn last_id(&self) -> Result<Option<u64>, Error> {
+        self.db
+            .iterator(IteratorMode::End)
+            .next()
+            .map(|result| key_prefix_to_id(&result?.0))
+            .transpose()
+    }
+
     pub fn lookup(&self, id: u64) -> Result<HashMap<String, Vec<NaiveDate>>, Error> {
-        let prefix = id_to_key_prefix(id);
-        let iter = self.db.prefix_iterator(prefix);
         let mut results = HashMap::new();
 
-        for result in iter {
-            let (key, value) = result?;
-            let (next_id, next_screen_name) = key_to_pair(&key)?;
+        self.visit(id, |screen_name, dates| {
+            results.insert(screen_name.to_string(), dates.to_dates());
+        })?;
 
-            if next_id == id {
-                let dates = value_to_dates(&value)?;
-                results.insert(next_screen_name.to_string(), dates);
-            } else {
+        Ok(results)
+    }
+
+    /// Visit an account's screen names and dates without copying them.
+    ///
+    /// Each screen name and its dates are borrowed from the database for the duration of the call
+    /// (only sealed values are copied, to decrypt them), so callers that only need a summary (like
+    /// the number of dates or the latest one) don't allocate anything per pair.
+    pub fn visit<F: FnMut(&str, DayIds<'_>)>(&self, id: u64, mut visit: F) -> Result<(), Error> {
+        let mut iter = self.db.raw_iterator();
+        iter.seek(id_to_key_prefix(id));
+
+        while let Some((key, value)) = iter.item() {
+            let (next_id, screen_name) = key_to_pair(key)?;
+
+            if next_id != id {
                 break;
             }
+
+            let value = open_stored_value(key, value, self.keyring.as_deref())?;
+            visit(screen_name, DayIds::new(&value)?);
+            iter.next();
         }
 
-        Ok(results)
+        Ok(iter.status()?)
+    }
+
+    pub fn get(&self, id: u64, screen_name: &str) -> Result<Option<Vec<NaiveDate>>, Error> {
+        let key = pair_to_key(id, screen_name);
+
+        self.db
+            .get_pinned(&key)?
+            .map(|value| self.decode(&key, &value))
+            .transpose()
+    }
+
+    /// Look up several pairs with a single batched read.
+    pub fn get_many(&self, pairs: &[(u64, &str)]) -> Result<Vec<Option<Vec<NaiveDate>>>, Error> {
+        let keys = pairs
+            .iter()
+            .map(|(id, screen_name)| pair_to_key(*id, screen_name))
+            .collect::<Vec<_>>();
+
+        self.db
+            .multi_get(&keys)
+            .into_iter()
+            .zip(&keys)
+            .map(|(result, key)| result?.map(|value| self.decode(key, &value)).transpose())
+            .collect()
+    }
+
+    pub fn contains_id(&self, id: u64) -> Result<bool, Error> {
+        let prefix = id_to_key_prefix(id);
+
+        match self.db.prefix_iterator(prefix).next() {
+            Some(result) => {
+                let (key, _) = result?;
+                Ok(key_prefix_to_id(&key)? == id)
+            }
+            None => Ok(false),
+        }
     }
 
     pub fn limited_lookup(
@@ -86,23 +179,13 @@ impl<M> AccountTable<M> {
         id: u64,
         earliest: NaiveDate,
     ) -> Result<HashMap<String, Vec<NaiveDate>>, Error> {
-        let prefix = id_to_key_prefix(id);
-        let iter = self.db.prefix_iterator(prefix);
         let mut results = HashMap::new();
 
-        for result in iter {
-            let (key, value) = result?;
-            let (next_id, next_screen_name) = key_to_pair(&key)?;
-
-            if next_id == id {
-                let dates = value_to_dates(&value)?;
-                if dates.iter().any(|date| date >= &earliest) {
-                    results.insert(next_screen_name.to_string(), dates);
-                }
-            } else {
-                break;
+        self.visit(id, |screen_name, dates| {
+            if dates.last().is_some_and(|last| last >= earliest) {
+                results.insert(screen_name.to_string(), dates.to_dates());
             }
-        }
+        })?;
 
         Ok(results)
     }
@@ -112,8 +195,8 @@ impl<M> AccountTable<M> {
         let iter = self.db.iterator(IteratorMode::Start);
 
         for result in iter {
-            let (_, value) = result?;
-            let dates = value_to_dates(&value)?;
+            let (key, value) = result?;
+            let dates = self.decode(&key, &value)?;
 
             for date in dates {
                 let count = map.entry(date).or_default();
@@ -142,7 +225,7 @@ impl<M> AccountTable<M> {
                 let len = current.len();
 
                 if len >= min || queue.len() < k {
-                    queue.push((last_id, current.drain(..).collect()), len);
+                    queue.push((last_id, std::mem::take(&mut current)), len);
 
                     if queue.len() > k {
                         queue.pop_min();
@@ -162,9 +245,22 @@ impl<M> AccountTable<M> {
 
 impl<M: Mode> AccountTable<M> {
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::open_with_options(path, &DatabaseOptions::default())
+    }
+
+    pub fn open_with_options<P: AsRef<Path>>(
+        path: P,
+        database_options: &DatabaseOptions,
+    ) -> Result<Self, Error> {
+        let keyring = database_options.encryption.clone();
+        let merge_keyring = keyring.clone();
+
         let mut options = Options::default();
         options.create_if_missing(true);
-        options.set_merge_operator_associative("merge", merge);
+        options.set_merge_operator_associative("merge", move |key, existing_val, operands| {
+            merge(key, existing_val, operands, merge_keyring.as_deref())
+        });
+        database_options.apply(&mut options);
 
         let db = if M::is_read_only() {
             DB::open_for_read_only(&options, path, true)?
@@ -174,6 +270,7 @@ impl<M: Mode> AccountTable<M> {
 
         Ok(Self {
             db,
+            keyring,
             mode: PhantomData,
         })
     }
@@ -182,13 +279,8 @@ impl<M: Mode> AccountTable<M> {
 impl AccountTable<Writeable> {
     pub fn insert(&self, id: u64, screen_name: &str, dates: Vec<NaiveDate>) -> Result<(), Error> {
         if is_valid_screen_name(screen_name) {
-            let mut value = Vec::with_capacity(2 * dates.len());
-
-            for date in dates {
-                value.extend_from_slice(&date_to_day_id(&date)?.to_be_bytes());
-            }
-
-            self.db.merge(pair_to_key(id, screen_name), value)?;
+            let key = pair_to_key(id, screen_name);
+            self.db.merge(&key, self.encode(&key, &dates)?)?;
 
             Ok(())
         } else {
@@ -202,12 +294,52 @@ impl AccountTable<Writeable> {
         Ok(self.db.delete(key)?)
     }
 
+    /// Overwrite a pair's dates instead of merging them with the existing dates.
+    pub fn replace(&self, id: u64, screen_name: &str, dates: &[NaiveDate]) -> Result<(), Error> {
+        let key = pair_to_key(id, screen_name);
+
+        Ok(self.db.put(&key, self.encode(&key, dates)?)?)
+    }
+
+    /// Seal every value with the current key, so that earlier keys can be retired.
+    ///
+    /// Every value is rewritten, since a value that is the result of a merge is sealed with the
+    /// current key when it's read, even if its operands on disk were sealed with earlier keys.
+    pub fn reseal(&self) -> Result<RotationReport, Error> {
+        let keyring = self
+            .keyring
+            .as_deref()
+            .ok_or(encryption::Error::KeyRequired)?;
+        let mut report = RotationReport::default();
+
+        for result in self.db.iterator(IteratorMode::Start) {
+            let (key, value) = result?;
+            self.db
+                .put(&key, keyring.seal(&key, &keyring.open(&key, &value)?)?)?;
+            report.values_resealed += 1;
+        }
+
+        Ok(report)
+    }
+
     pub fn compact_ranges(&self) -> Result<(), Error> {
+        self.compact_ranges_until(|| false).map(|_| ())
+    }
+
+    /// Compact ranges, checking between pairs whether to stop early.
+    ///
+    /// Returns whether compaction was stopped. Compacting is idempotent, so running it again
+    /// completes it (pairs that have already been compacted are only read).
+    pub fn compact_ranges_until<F: Fn() -> bool>(&self, stop: F) -> Result<bool, Error> {
         let iter = self.db.iterator(IteratorMode::Start);
 
         for result in iter {
+            if stop() {
+                return Ok(true);
+            }
+
             let (key, value) = result?;
-            let mut dates = value_to_dates(&value)?;
+            let mut dates = self.decode(&key, &value)?;
 
             // If we don't have more than a range we don't need to compact
             if dates.len() > 2 {
@@ -230,38 +362,49 @@ impl AccountTable<Writeable> {
                     compacted_dates
                 };
 
-                let mut new_value = Vec::with_capacity(2 * compacted_dates.len());
-
-                for date in compacted_dates {
-                    new_value.extend_from_slice(&date_to_day_id(&date)?.to_be_bytes());
-                }
-
-                self.db.put(key, new_value)?;
+                self.db.put(&key, self.encode(&key, &compacted_dates)?)?;
             }
         }
 
-        Ok(())
+        Ok(false)
     }
 }
 
+/// Sealed values are opened, merged, and sealed again, and values that can't be opened fail the
+/// merge instead of being dropped.
 fn merge(
-    _new_key: &[u8],
+    new_key: &[u8],
     existing_val: Option<&[u8]>,
     operands: &MergeOperands,
+    keyring: Option<&Keyring>,
 ) -> Option<Vec<u8>> {
+    let open = |value| match keyring {
+        Some(keyring) => keyring
+            .open(new_key, value)
+            .map_err(|error| log::error!("Failed to open value for merge: {}", error))
+            .ok(),
+        None => Some(Cow::Borrowed(value)),
+    };
+
     let mut new_val = match existing_val {
-        Some(bytes) => bytes.to_vec(),
+        Some(bytes) => open(bytes)?.into_owned(),
         None => Vec::with_capacity(operands.len() * 2),
     };
 
     for operand in operands.iter() {
-        merge_for_pair(&mut new_val, operand);
+        merge_for_pair(&mut new_val, &open(operand)?);
     }
 
-    Some(new_val)
+    match keyring {
+        Some(keyring) => keyring
+            .seal(new_key, &new_val)
+            .map_err(|error| log::error!("Failed to seal merged value: {}", error))
+            .ok(),
+        None => Some(new_val),
+    }
 }
 
-fn merge_for_pair(a: &mut Vec<u8>, b: &[u8]) {
+pub(crate) fn merge_for_pair(a: &mut Vec<u8>, b: &[u8]) {
     let original_len = a.len();
     let mut i = 0;
 
@@ -300,6 +443,7 @@ fn merge_for_pair(a: &mut Vec<u8>, b: &[u8]) {
 
 pub struct PairIterator<'a> {
     underlying: DBIterator<'a>,
+    keyring: Option<&'a Keyring>,
 }
 
 impl Iterator for PairIterator<'_> {
@@ -309,72 +453,18 @@ impl Iterator for PairIterator<'_> {
         self.underlying.next().map(|result| {
             result
                 .map_err(Error::from)
-                .and_then(|(key, value)| kv_to_item(&key, &value))
+                .and_then(|(key, value)| kv_to_item(&key, &value, self.keyring))
         })
     }
 }
 
-fn kv_to_item(key: &[u8], value: &[u8]) -> Result<(u64, String, Vec<NaiveDate>), Error> {
+fn kv_to_item(
+    key: &[u8],
+    value: &[u8],
+    keyring: Option<&Keyring>,
+) -> Result<(u64, String, Vec<NaiveDate>), Error> {
     let (id, screen_name) = key_to_pair(key)?;
-    let dates = value_to_dates(value)?;
+    let dates = stored_value_to_dates(key, value, keyring)?;
 
     Ok((id, screen_name.to_string(), dates))
 }
-
-fn id_to_key_prefix(id: u64) -> [u8; 8] {
-    id.to_be_bytes()
-}
-
-fn key_prefix_to_id(key: &[u8]) -> Result<u64, Error> {
-    Ok(u64::from_be_bytes(
-        key[0..8]
-            .try_into()
-            .map_err(|_| Error::InvalidKey(key.to_vec()))?,
-    ))
-}
-
-fn pair_to_key(id: u64, screen_name: &str) -> Vec<u8> {
-    let screen_name_bytes = screen_name.as_bytes();
-    let mut prefix = Vec::with_capacity(8 + screen_name_bytes.len());
-    prefix.extend_from_slice(&id.to_be_bytes());
-    prefix.extend_from_slice(screen_name_bytes);
-    prefix
-}
-
-fn key_to_pair(key: &[u8]) -> Result<(u64, &str), Error> {
-    let id = key_prefix_to_id(key)?;
-    let screen_name = std::str::from_utf8(&key[8..])?;
-
-    Ok((id, screen_name))
-}
-
-lazy_static::lazy_static! {
-    /// Date of the first tweet
-    static ref TWITTER_EPOCH: NaiveDate = NaiveDate::from_ymd_opt(2006, 3, 21).unwrap();
-}
-
-fn date_to_day_id(date: &NaiveDate) -> Result<u16, Error> {
-    let day = (*date - *TWITTER_EPOCH).num_days();
-    day.try_into().map_err(|_| Error::InvalidDay(day))
-}
-
-fn day_id_to_date(day_id: u16) -> NaiveDate {
-    *TWITTER_EPOCH + Duration::days(day_id.into())
-}
-
-fn value_to_dates(value: &[u8]) -> Result<Vec<NaiveDate>, Error> {
-    let count = value.len() / 2;
-    let mut result = Vec::with_capacity(count);
-
-    for i in 0..count {
-        let day_id = u16::from_be_bytes(
-            value[i * 2..(i * 2 + 2)]
-                .try_into()
-                .map_err(|_| Error::InvalidValue(value.to_vec()))?,
-        );
-        result.push(day_id_to_date(day_id));
-    }
-
-    result.sort();
-    Ok(result)
-}
@@ -0,0 +1,221 @@
+//! Aggregate statistics that are safe to publish.
+//!
+//! Counts are released with Laplace noise calibrated to the contribution any single account can
+//! make, so that the published statistics satisfy ε-differential privacy with respect to the
+//! addition or removal of one account. Exact counts are never exposed by this module.
+use super::{accounts::AccountTable, Error};
+use chrono::{Duration, NaiveDate};
+use rand::Rng;
+use serde_derive::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Parameters for computing noisy aggregates.
+///
+/// The date range and bounds must not be derived from the data, since they determine which counts
+/// are published.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AggregateOptions {
+    /// Total privacy budget, split evenly between the published statistics
+    pub epsilon: f64,
+    /// First day of the published rename counts
+    pub first: NaiveDate,
+    /// Last day of the published rename counts
+    pub last: NaiveDate,
+    /// Renames counted for any one account (later renames are ignored)
+    pub max_renames_per_account: usize,
+    /// Largest history length reported separately (longer histories are counted with it)
+    pub max_history_length: usize,
+}
+
+/// Noisy statistics about screen name changes.
+///
+/// Every day in the requested range and every history length up to the maximum has an entry, so
+/// the presence of an entry reveals nothing. Noisy counts are rounded and clamped at zero.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Aggregates {
+    pub epsilon: f64,
+    /// Number of accounts first observed with a new screen name on each day
+    pub renames_per_day: BTreeMap<NaiveDate, u64>,
+    /// Number of accounts with each number of distinct screen names
+    pub history_lengths: BTreeMap<usize, u64>,
+}
+
+impl Aggregates {
+    pub fn compute<M, R: Rng>(
+        accounts: &AccountTable<M>,
+        options: &AggregateOptions,
+        rng: &mut R,
+    ) -> Result<Self, Error> {
+        if !(options.epsilon.is_finite() && options.epsilon > 0.0) {
+            return Err(Error::InvalidEpsilon(options.epsilon));
+        }
+
+        let mut renames_per_day = BTreeMap::new();
+        let mut history_lengths = BTreeMap::new();
+
+        let mut day = options.first;
+        while day <= options.last {
+            renames_per_day.insert(day, 0);
+            day += Duration::days(1);
+        }
+
+        for length in 1..=options.max_history_length {
+            history_lengths.insert(length, 0);
+        }
+
+        let mut current_id = None;
+        let mut first_dates = HashMap::new();
+
+        for pair in accounts.pairs() {
+            let (id, screen_name, dates) = pair?;
+
+            if current_id != Some(id) {
+                count_account(
+                    &mut first_dates,
+                    options,
+                    &mut renames_per_day,
+                    &mut history_lengths,
+                );
+                current_id = Some(id);
+            }
+
+            // Differently cased observations of the same screen name are not renames
+            let first_date = first_dates
+                .entry(screen_name.to_lowercase())
+                .or_insert(None);
+            if let Some(date) = dates.first() {
+                *first_date = Some(first_date.map_or(*date, |first: NaiveDate| first.min(*date)));
+            }
+        }
+
+        count_account(
+            &mut first_dates,
+            options,
+            &mut renames_per_day,
+            &mut history_lengths,
+        );
+
+        // Each statistic receives half of the budget
+        let epsilon = options.epsilon / 2.0;
+        let rename_scale = options.max_renames_per_account as f64 / epsilon;
+        let history_scale = 1.0 / epsilon;
+
+        for count in renames_per_day.values_mut() {
+            *count = add_noise(*count, rename_scale, rng);
+        }
+
+        for count in history_lengths.values_mut() {
+            *count = add_noise(*count, history_scale, rng);
+        }
+
+        Ok(Self {
+            epsilon: options.epsilon,
+            renames_per_day,
+            history_lengths,
+        })
+    }
+}
+
+/// Add an account's exact contributions, clamped to the configured bounds.
+fn count_account(
+    first_dates: &mut HashMap<String, Option<NaiveDate>>,
+    options: &AggregateOptions,
+    renames_per_day: &mut BTreeMap<NaiveDate, u64>,
+    history_lengths: &mut BTreeMap<usize, u64>,
+) {
+    let length = first_dates.len();
+
+    if length == 0 {
+        return;
+    }
+
+    if let Some(count) = history_lengths.get_mut(&length.min(options.max_history_length)) {
+        *count += 1;
+    }
+
+    let mut dates = first_dates
+        .drain()
+        .filter_map(|(_, date)| date)
+        .collect::<Vec<_>>();
+    dates.sort();
+
+    // The first screen name observed is not a rename
+    for date in dates.iter().skip(1).take(options.max_renames_per_account) {
+        if let Some(count) = renames_per_day.get_mut(date) {
+            *count += 1;
+        }
+    }
+}
+
+/// Add Laplace noise with the given scale, rounding and clamping the result.
+fn add_noise<R: Rng>(count: u64, scale: f64, rng: &mut R) -> u64 {
+    // The difference of two exponential samples is Laplace distributed (`1 - gen` is never zero)
+    let noise = scale * ((1.0 - rng.gen::<f64>()).ln() - (1.0 - rng.gen::<f64>()).ln());
+
+    (count as f64 + noise).round().max(0.0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixture::{self, date};
+
+    #[test]
+    fn aggregates() {
+        use rand::SeedableRng;
+
+        let (_dir, db) = fixture::database();
+
+        db.insert(123, "foo", vec![date(1), date(5)]).unwrap();
+        db.insert(123, "bar", vec![date(6)]).unwrap();
+        db.insert(123, "baz", vec![date(8)]).unwrap();
+        db.insert(123, "qux", vec![date(9)]).unwrap();
+        // Differently cased observations of one screen name aren't a rename
+        db.insert(456, "abc", vec![date(2)]).unwrap();
+        db.insert(456, "ABC", vec![date(6)]).unwrap();
+        db.insert(789, "def", vec![date(3)]).unwrap();
+        db.insert(789, "ghi", vec![date(6)]).unwrap();
+
+        let mut options = AggregateOptions {
+            epsilon: 1e12,
+            first: date(1),
+            last: date(7),
+            max_renames_per_account: 2,
+            max_history_length: 3,
+        };
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+
+        // With an enormous budget the noise rounds away, leaving the clamped exact counts
+        let result = db.aggregates(&options, &mut rng).unwrap();
+
+        let exact_renames = vec![
+            (date(1), 0),
+            (date(2), 0),
+            (date(3), 0),
+            (date(4), 0),
+            (date(5), 0),
+            (date(6), 2),
+            (date(7), 0),
+        ];
+
+        assert_eq!(
+            result.renames_per_day.into_iter().collect::<Vec<_>>(),
+            exact_renames
+        );
+        assert_eq!(
+            result.history_lengths.into_iter().collect::<Vec<_>>(),
+            vec![(1, 1), (2, 1), (3, 1)]
+        );
+
+        // With a small budget the counts are perturbed
+        options.epsilon = 0.1;
+        let result = db.aggregates(&options, &mut rng).unwrap();
+        assert_ne!(
+            result.renames_per_day.into_iter().collect::<Vec<_>>(),
+            exact_renames
+        );
+
+        options.epsilon = 0.0;
+        assert!(db.aggregates(&options, &mut rng).is_err());
+    }
+}
@@ -0,0 +1,463 @@
+use super::{
+    accounts,
+    codec::{
+        dates_to_stored_value, dates_to_value, key_to_pair, key_to_screen_name, pair_to_key,
+        screen_name_to_key, value_to_dates,
+    },
+    options::DatabaseOptions,
+    screen_names,
+    table::{Table, Writeable},
+    util::is_valid_screen_name,
+    Database, Error,
+};
+use chrono::NaiveDate;
+use rocksdb::{IngestExternalFileOptions, Options, SstFileWriter, DB};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Number of entries sorted in memory before being written to a run file.
+pub const DEFAULT_RUN_SIZE: usize = 4_000_000;
+
+/// Size at which an SST file is finished and the next one started.
+const SST_FILE_SIZE: u64 = 256 * 1024 * 1024;
+
+/// A key and a value.
+pub type Entry = (Vec<u8>, Vec<u8>);
+
+/// The result of a bulk load.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct BulkLoadReport {
+    pub pairs: usize,
+    pub accounts: usize,
+    pub screen_names: usize,
+    pub dates: usize,
+    /// Number of sorted runs spilled to the scratch directory
+    pub runs: usize,
+    /// Number of SST files ingested
+    pub sst_files: usize,
+}
+
+/// Loads pairs into an empty database by sorting them externally and ingesting SST files written
+/// directly, bypassing the memtable and the write-ahead log.
+///
+/// Pairs are buffered and sorted in memory, and spilled to run files in a scratch directory when
+/// the buffer is full. When the loader is finished, the runs are merged into SST files for the
+/// account table (written with memory-mapped writes), whose pairs are then sorted again by screen
+/// name to build the screen name table in the same way.
+pub struct BulkLoader {
+    scratch: PathBuf,
+    options: DatabaseOptions,
+    ranges: bool,
+    pairs: Sorter,
+}
+
+impl BulkLoader {
+    /// Start a bulk load into a database, which must not contain any pairs.
+    ///
+    /// If `ranges` is set, only the first and last dates of each pair are kept.
+    pub fn new<P: AsRef<Path>>(
+        db: &Database<Writeable>,
+        scratch: P,
+        options: &DatabaseOptions,
+        run_size: usize,
+        ranges: bool,
+    ) -> Result<Self, Error> {
+        if db.accounts.last_id()?.is_some() {
+            return Err(Error::NotEmpty);
+        }
+
+        let scratch = scratch.as_ref().to_path_buf();
+        std::fs::create_dir_all(&scratch)?;
+
+        Ok(Self {
+            pairs: Sorter::new(scratch.join("pairs"), run_size, accounts::merge_for_pair),
+            scratch,
+            options: options.clone(),
+            ranges,
+        })
+    }
+
+    pub fn add(&mut self, id: u64, screen_name: &str, dates: &[NaiveDate]) -> Result<(), Error> {
+        if is_valid_screen_name(screen_name) {
+            self.pairs
+                .push(pair_to_key(id, screen_name), dates_to_value(dates)?)
+        } else {
+            Err(Error::InvalidScreenName(screen_name.to_string()))
+        }
+    }
+
+    /// Write and ingest the tables, removing the scratch directory.
+    pub fn finish(self, db: &Database<Writeable>) -> Result<BulkLoadReport, Error> {
+        let mut report = BulkLoadReport::default();
+        let sst_options = self.sst_options();
+        let run_size = self.pairs.run_size;
+
+        let mut screen_name_sorter = Sorter::new(
+            self.scratch.join("screen-names"),
+            run_size,
+            screen_names::merge_for_screen_name,
+        );
+        let mut accounts_writer = SstWriter::new(&sst_options, self.scratch.join("accounts"));
+        let mut last_id = None;
+
+        let pairs = self.pairs.into_sorted()?;
+        report.runs += pairs.runs();
+
+        for entry in pairs {
+            let (key, value) = entry?;
+            let (id, screen_name) = key_to_pair(&key)?;
+            let mut dates = value_to_dates(&value)?;
+            dates.dedup();
+
+            if self.ranges && dates.len() > 2 {
+                dates.drain(1..dates.len() - 1);
+            }
+
+            screen_name_sorter.push(screen_name_to_key(screen_name), id.to_be_bytes().to_vec())?;
+            accounts_writer.put(
+                &key,
+                &dates_to_stored_value(&key, &dates, self.options.encryption.as_deref())?,
+            )?;
+
+            report.pairs += 1;
+            report.dates += dates.len();
+            if last_id != Some(id) {
+                report.accounts += 1;
+                last_id = Some(id);
+            }
+        }
+
+        report.sst_files += ingest(db.accounts.underlying(), accounts_writer.finish()?)?;
+
+        let mut screen_names_writer =
+            SstWriter::new(&sst_options, self.scratch.join("screen-names"));
+
+        let screen_names = screen_name_sorter.into_sorted()?;
+        report.runs += screen_names.runs();
+
+        for entry in screen_names {
+            let (key, value) = entry?;
+            screen_names_writer.put(&key, &value)?;
+            report.screen_names += 1;
+
+            // The substring and phonetic indexes are small enough to be written normally
+            let screen_name = key_to_screen_name(&key)?;
+
+            if let Some(ngrams) = &db.ngrams {
+                ngrams.insert(screen_name)?;
+            }
+
+            if let Some(phonetic) = &db.phonetic {
+                phonetic.insert(screen_name)?;
+            }
+        }
+
+        report.sst_files += ingest(db.screen_names.underlying(), screen_names_writer.finish()?)?;
+
+        std::fs::remove_dir_all(&self.scratch)?;
+
+        Ok(report)
+    }
+
+    fn sst_options(&self) -> Options {
+        let mut options = Options::default();
+        self.options.apply(&mut options);
+        options.set_allow_mmap_writes(true);
+        options
+    }
+}
+
+/// Move finished SST files into a table, returning the number ingested.
+fn ingest(db: &DB, paths: Vec<PathBuf>) -> Result<usize, Error> {
+    if !paths.is_empty() {
+        let mut options = IngestExternalFileOptions::default();
+        options.set_move_files(true);
+        db.ingest_external_file_opts(&options, paths.clone())?;
+    }
+
+    Ok(paths.len())
+}
+
+/// Writes sorted entries to a sequence of SST files of roughly [`SST_FILE_SIZE`] bytes.
+struct SstWriter<'a> {
+    options: &'a Options,
+    prefix: PathBuf,
+    current: Option<SstFileWriter<'a>>,
+    paths: Vec<PathBuf>,
+}
+
+impl<'a> SstWriter<'a> {
+    fn new(options: &'a Options, prefix: PathBuf) -> Self {
+        Self {
+            options,
+            prefix,
+            current: None,
+            paths: vec![],
+        }
+    }
+
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let writer = match &mut self.current {
+            Some(writer) => writer,
+            None => {
+                let path = self
+                    .prefix
+                    .with_extension(format!("{:05}.sst", self.paths.len()));
+                let writer = SstFileWriter::create(self.options);
+                writer.open(&path)?;
+                self.paths.push(path);
+                self.current.insert(writer)
+            }
+        };
+
+        writer.put(key, value)?;
+
+        if writer.file_size() >= SST_FILE_SIZE {
+            if let Some(mut writer) = self.current.take() {
+                writer.finish()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Vec<PathBuf>, Error> {
+        if let Some(mut writer) = self.current.take() {
+            writer.finish()?;
+        }
+
+        Ok(self.paths)
+    }
+}
+
+/// An external sort of key-value entries, combining the values of entries with the same key.
+///
+/// Entries are buffered and sorted in memory, and spilled to run files (named by adding an
+/// extension to a path prefix, in a directory that must exist) when the buffer is full.
+pub struct Sorter {
+    prefix: PathBuf,
+    run_size: usize,
+    combine: fn(&mut Vec<u8>, &[u8]),
+    buffer: Vec<Entry>,
+    runs: Vec<PathBuf>,
+}
+
+impl Sorter {
+    pub fn new(prefix: PathBuf, run_size: usize, combine: fn(&mut Vec<u8>, &[u8])) -> Self {
+        Self {
+            prefix,
+            run_size: run_size.max(1),
+            combine,
+            buffer: vec![],
+            runs: vec![],
+        }
+    }
+
+    pub fn push(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), Error> {
+        self.buffer.push((key, value));
+
+        if self.buffer.len() >= self.run_size {
+            self.spill()?;
+        }
+
+        Ok(())
+    }
+
+    /// Sort the buffer and write it to a new run file.
+    fn spill(&mut self) -> Result<(), Error> {
+        let mut buffer = std::mem::take(&mut self.buffer);
+        buffer.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+        let path = self
+            .prefix
+            .with_extension(format!("{:05}.run", self.runs.len()));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        let mut entries = buffer.into_iter().peekable();
+
+        while let Some((key, mut value)) = entries.next() {
+            while let Some((_, next_value)) = entries.next_if(|(next_key, _)| *next_key == key) {
+                (self.combine)(&mut value, &next_value);
+            }
+
+            write_record(&mut writer, &key, &value)?;
+        }
+
+        writer.flush()?;
+        self.runs.push(path);
+
+        Ok(())
+    }
+
+    /// Merge the runs (the run files are left in place).
+    pub fn into_sorted(mut self) -> Result<Sorted, Error> {
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+
+        let mut readers = self
+            .runs
+            .iter()
+            .map(|path| Ok(BufReader::new(File::open(path)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut heap = BinaryHeap::with_capacity(readers.len());
+
+        for (index, reader) in readers.iter_mut().enumerate() {
+            if let Some(entry) = read_record(reader)? {
+                heap.push(Reverse((entry, index)));
+            }
+        }
+
+        Ok(Sorted {
+            combine: self.combine,
+            readers,
+            heap,
+        })
+    }
+}
+
+/// The merged runs of a [`Sorter`], in key order with one entry for each key.
+pub struct Sorted {
+    combine: fn(&mut Vec<u8>, &[u8]),
+    readers: Vec<BufReader<File>>,
+    heap: BinaryHeap<Reverse<(Entry, usize)>>,
+}
+
+impl Sorted {
+    /// The number of runs being merged.
+    pub fn runs(&self) -> usize {
+        self.readers.len()
+    }
+
+    /// Remove the smallest entry, replacing it with the next entry from the same run.
+    fn pop(&mut self) -> Result<Option<Entry>, Error> {
+        match self.heap.pop() {
+            Some(Reverse((entry, index))) => {
+                if let Some(next) = read_record(&mut self.readers[index])? {
+                    self.heap.push(Reverse((next, index)));
+                }
+
+                Ok(Some(entry))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl Iterator for Sorted {
+    type Item = Result<Entry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, mut value) = match self.pop() {
+            Ok(Some(entry)) => entry,
+            Ok(None) => return None,
+            Err(error) => return Some(Err(error)),
+        };
+
+        // Each run has at most one entry for a key, but several runs may have one
+        while self
+            .heap
+            .peek()
+            .is_some_and(|Reverse(((next_key, _), _))| *next_key == key)
+        {
+            match self.pop() {
+                Ok(Some((_, next_value))) => (self.combine)(&mut value, &next_value),
+                Ok(None) => break,
+                Err(error) => return Some(Err(error)),
+            }
+        }
+
+        Some(Ok((key, value)))
+    }
+}
+
+/// Run files are a sequence of length-prefixed keys and values.
+fn write_record<W: Write>(writer: &mut W, key: &[u8], value: &[u8]) -> Result<(), Error> {
+    writer.write_all(&(key.len() as u32).to_be_bytes())?;
+    writer.write_all(key)?;
+    writer.write_all(&(value.len() as u32).to_be_bytes())?;
+    writer.write_all(value)?;
+
+    Ok(())
+}
+
+fn read_record<R: Read>(reader: &mut R) -> Result<Option<Entry>, Error> {
+    let mut len = [0; 4];
+
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error.into()),
+    }
+
+    let mut key = vec![0; u32::from_be_bytes(len) as usize];
+    reader.read_exact(&mut key)?;
+    reader.read_exact(&mut len)?;
+    let mut value = vec![0; u32::from_be_bytes(len) as usize];
+    reader.read_exact(&mut value)?;
+
+    Ok(Some((key, value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2020, 1, day).unwrap()
+    }
+
+    #[test]
+    fn bulk_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = DatabaseOptions::default();
+        let db = Database::<Writeable>::open(dir.path().join("db")).unwrap();
+
+        // A tiny run size forces several runs with entries for the same pair
+        let mut loader =
+            BulkLoader::new(&db, dir.path().join("scratch"), &options, 3, true).unwrap();
+
+        for day in [5, 1, 3] {
+            loader.add(2, "foo", &[date(day)]).unwrap();
+        }
+        loader.add(1, "Foo", &[date(2)]).unwrap();
+        loader.add(1, "bar", &[date(4)]).unwrap();
+        loader.add(2, "foo", &[date(3)]).unwrap();
+        assert!(loader.add(3, "a-b", &[date(1)]).is_err());
+
+        let report = loader.finish(&db).unwrap();
+
+        assert_eq!(
+            report,
+            BulkLoadReport {
+                pairs: 3,
+                accounts: 2,
+                screen_names: 2,
+                dates: 4,
+                runs: 3,
+                sst_files: 2,
+            }
+        );
+        assert!(!dir.path().join("scratch").exists());
+        assert_eq!(db.lookup_by_screen_name("foo").unwrap(), vec![1, 2]);
+        assert_eq!(
+            db.lookup_by_user_id(2).unwrap(),
+            HashMap::from([("foo".to_string(), vec![date(1), date(5)])])
+        );
+
+        // Later writes are merged with the ingested data as usual
+        db.insert(1, "bar", vec![date(9)]).unwrap();
+        assert_eq!(
+            db.accounts.get(1, "bar").unwrap(),
+            Some(vec![date(4), date(9)])
+        );
+
+        assert!(matches!(
+            BulkLoader::new(&db, dir.path().join("scratch"), &options, 3, true),
+            Err(Error::NotEmpty)
+        ));
+    }
+}
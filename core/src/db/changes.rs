@@ -0,0 +1,256 @@
+use super::{
+    codec::{dates_to_value, key_to_pair, pair_to_key, value_to_dates},
+    options::DatabaseOptions,
+    retention,
+    table::{Mode, Table, Writeable},
+    Database, Error,
+};
+use chrono::NaiveDate;
+use rocksdb::{Direction, IteratorMode, Options, WriteBatch, DB};
+use std::collections::HashSet;
+use std::marker::PhantomData;
+use std::path::Path;
+
+/// The observation dates each import added to the account table, so that an import can be rolled
+/// back.
+///
+/// Keys are the import's ID (the hash of its input, as recorded in the import ledger) and a zero
+/// byte, followed by the account table key of the pair. Values are encoded like account table
+/// values, but (like the weight table) aren't encrypted.
+pub struct ChangeTable<M> {
+    db: DB,
+    mode: PhantomData<M>,
+}
+
+/// A pair whose dates were changed by an import.
+pub type Change = (u64, String, Vec<NaiveDate>);
+
+impl<M> Table for ChangeTable<M> {
+    type Counts = u64;
+
+    fn underlying(&self) -> &DB {
+        &self.db
+    }
+
+    fn get_counts(&self) -> Result<Self::Counts, Error> {
+        let mut count = 0;
+
+        for result in self.db.iterator(IteratorMode::Start) {
+            result?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+}
+
+impl<M> ChangeTable<M> {
+    /// The dates an import added to each pair, ordered by pair.
+    pub fn changes(&self, import: &str) -> Result<Vec<Change>, Error> {
+        let prefix = import_prefix(import);
+        let mut changes = vec![];
+
+        for result in self
+            .db
+            .iterator(IteratorMode::From(&prefix, Direction::Forward))
+        {
+            let (key, value) = result?;
+
+            match key.strip_prefix(prefix.as_slice()) {
+                Some(pair_key) => {
+                    let (id, screen_name) = key_to_pair(pair_key)?;
+                    changes.push((id, screen_name.to_string(), value_to_dates(&value)?));
+                }
+                None => break,
+            }
+        }
+
+        Ok(changes)
+    }
+}
+
+impl<M: Mode> ChangeTable<M> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::open_with_options(path, &DatabaseOptions::default())
+    }
+
+    pub fn open_with_options<P: AsRef<Path>>(
+        path: P,
+        database_options: &DatabaseOptions,
+    ) -> Result<Self, Error> {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        database_options.apply(&mut options);
+
+        let db = if M::is_read_only() {
+            DB::open_for_read_only(&options, path, true)?
+        } else {
+            DB::open(&options, path)?
+        };
+
+        Ok(Self {
+            db,
+            mode: PhantomData,
+        })
+    }
+}
+
+impl ChangeTable<Writeable> {
+    /// Record dates that an import added to a pair (along with any it added earlier, for example
+    /// before it was interrupted).
+    pub fn record(
+        &self,
+        import: &str,
+        id: u64,
+        screen_name: &str,
+        dates: &[NaiveDate],
+    ) -> Result<(), Error> {
+        let mut key = import_prefix(import);
+        key.extend(pair_to_key(id, screen_name));
+
+        let mut all = match self.db.get_pinned(&key)? {
+            Some(value) => value_to_dates(&value)?,
+            None => vec![],
+        };
+        all.extend_from_slice(dates);
+        all.sort();
+        all.dedup();
+
+        Ok(self.db.put(key, dates_to_value(&all)?)?)
+    }
+
+    /// Forget an import's changes.
+    pub fn clear(&self, import: &str) -> Result<(), Error> {
+        let mut end = import.as_bytes().to_vec();
+        end.push(1);
+
+        let mut batch = WriteBatch::default();
+        batch.delete_range(import_prefix(import), end);
+
+        Ok(self.db.write(batch)?)
+    }
+}
+
+/// The dates and pairs removed by rolling back an import.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RollbackReport {
+    pub pairs_rewritten: u64,
+    pub pairs_removed: u64,
+    pub dates_removed: u64,
+    /// Pairs left alone because their accounts are under legal hold
+    pub pairs_held: u64,
+}
+
+impl std::fmt::Display for RollbackReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Pairs rewritten: {}", self.pairs_rewritten)?;
+        writeln!(f, "Pairs removed: {}", self.pairs_removed)?;
+        writeln!(f, "Dates removed: {}", self.dates_removed)?;
+        write!(f, "Pairs held: {}", self.pairs_held)
+    }
+}
+
+/// Remove the dates an import added from every pair it changed (except for accounts that are
+/// held), removing pairs that are left with no dates.
+///
+/// A date that a later import observed again is removed too, since the later import didn't add
+/// it.
+pub fn rollback(
+    db: &Database<Writeable>,
+    changes: &[Change],
+    held: &HashSet<u64>,
+) -> Result<RollbackReport, Error> {
+    let mut report = RollbackReport::default();
+
+    for (id, screen_name, added) in changes {
+        if held.contains(id) {
+            report.pairs_held += 1;
+            continue;
+        }
+
+        let dates = match db.accounts.get(*id, screen_name)? {
+            Some(dates) => dates,
+            None => continue,
+        };
+        let kept = dates
+            .iter()
+            .filter(|date| !added.contains(date))
+            .copied()
+            .collect::<Vec<_>>();
+
+        report.dates_removed += (dates.len() - kept.len()) as u64;
+
+        if kept.is_empty() {
+            report.pairs_removed += 1;
+            db.accounts.remove(*id, screen_name)?;
+            retention::remove_from_index(&db.accounts, &db.screen_names, *id, screen_name)?;
+        } else if kept.len() < dates.len() {
+            report.pairs_rewritten += 1;
+            db.accounts.replace(*id, screen_name, &kept)?;
+        }
+    }
+
+    Ok(report)
+}
+
+fn import_prefix(import: &str) -> Vec<u8> {
+    let mut prefix = import.as_bytes().to_vec();
+    prefix.push(0);
+    prefix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::CHANGES_TABLE;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2020, 1, day).unwrap()
+    }
+
+    #[test]
+    fn rollback() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::<Writeable>::open(dir.path()).unwrap();
+        let changes = ChangeTable::<Writeable>::open(dir.path().join(CHANGES_TABLE)).unwrap();
+
+        db.insert(1, "foo", vec![date(1)]).unwrap();
+
+        // The import extends one pair and adds three (one of them for a held account)
+        db.insert(1, "foo", vec![date(2)]).unwrap();
+        changes.record("abc", 1, "foo", &[date(2)]).unwrap();
+        for (id, screen_name) in [(2, "bar"), (2, "baz"), (3, "qux")] {
+            db.insert(id, screen_name, vec![date(3)]).unwrap();
+            changes.record("abc", id, screen_name, &[date(3)]).unwrap();
+        }
+        changes.record("abc", 2, "baz", &[date(4)]).unwrap();
+        changes.record("abcd", 2, "bar", &[date(1)]).unwrap();
+
+        // A later import observes one of the new pairs on another day
+        db.insert(2, "baz", vec![date(5)]).unwrap();
+
+        let recorded = changes.changes("abc").unwrap();
+        assert_eq!(recorded.len(), 4);
+        assert_eq!(recorded[2], (2, "baz".to_string(), vec![date(3), date(4)]));
+
+        assert_eq!(
+            super::rollback(&db, &recorded, &HashSet::from([3])).unwrap(),
+            RollbackReport {
+                pairs_rewritten: 2,
+                pairs_removed: 1,
+                dates_removed: 3,
+                pairs_held: 1,
+            }
+        );
+
+        assert_eq!(db.accounts.get(1, "foo").unwrap(), Some(vec![date(1)]));
+        assert_eq!(db.accounts.get(2, "bar").unwrap(), None);
+        assert!(db.lookup_by_screen_name("bar").unwrap().is_empty());
+        assert_eq!(db.accounts.get(2, "baz").unwrap(), Some(vec![date(5)]));
+        assert_eq!(db.accounts.get(3, "qux").unwrap(), Some(vec![date(3)]));
+
+        changes.clear("abc").unwrap();
+        assert!(changes.changes("abc").unwrap().is_empty());
+        assert_eq!(changes.changes("abcd").unwrap().len(), 1);
+    }
+}
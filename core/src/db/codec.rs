@@ -0,0 +1,437 @@
+//! Encoding of keys and values for the account, screen name, and weight tables.
+//!
+//! Every decoding function accepts arbitrary bytes and returns an error for malformed input
+//! instead of panicking, and decoding an encoded value always returns the original (with screen
+//! names in their normalized form). The `codec` fuzz target in `core/fuzz` checks these properties
+//! against arbitrary input (run it with `cargo fuzz run codec` from the `core` directory).
+use super::{
+    encryption::{self, Keyring},
+    Error,
+};
+use crate::ranges::DateRanges;
+use chrono::{Duration, NaiveDate};
+use std::borrow::Cow;
+use std::convert::TryInto;
+use unicode_normalization::UnicodeNormalization;
+
+/// Length of an encoded Twitter user ID.
+pub const ID_LEN: usize = 8;
+/// Length of an encoded day ID.
+pub const DAY_ID_LEN: usize = 2;
+/// Length of an encoded weight entry (a day ID followed by a `u32` count).
+pub const WEIGHT_ENTRY_LEN: usize = DAY_ID_LEN + 4;
+
+lazy_static::lazy_static! {
+    /// Date of the first tweet
+    static ref TWITTER_EPOCH: NaiveDate = NaiveDate::from_ymd_opt(2006, 3, 21).unwrap();
+}
+
+pub fn id_to_key_prefix(id: u64) -> [u8; ID_LEN] {
+    id.to_be_bytes()
+}
+
+pub fn key_prefix_to_id(key: &[u8]) -> Result<u64, Error> {
+    key.get(0..ID_LEN)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u64::from_be_bytes)
+        .ok_or_else(|| Error::InvalidKey(key.to_vec()))
+}
+
+/// The form screen names are stored in: NFC-normalized, and with any domain (in handles like
+/// `user@example.social` from federated platforms) in its ASCII (punycode) form.
+///
+/// Without this, the same handle written with composed and decomposed characters (or with a
+/// Unicode and a punycode domain) would be stored under different keys.
+pub fn normalize_screen_name(screen_name: &str) -> Cow<'_, str> {
+    if screen_name.is_ascii() && !screen_name.contains('@') {
+        return Cow::Borrowed(screen_name);
+    }
+
+    let normalized = screen_name.nfc().collect::<String>();
+
+    match normalized.rsplit_once('@') {
+        Some((user, domain)) if !user.is_empty() && !domain.is_empty() => {
+            match idna::domain_to_ascii(domain) {
+                Ok(domain) => Cow::Owned(format!("{}@{}", user, domain)),
+                Err(_) => Cow::Owned(normalized),
+            }
+        }
+        _ => Cow::Owned(normalized),
+    }
+}
+
+/// Account and weight table keys are the big-endian ID followed by the normalized screen name.
+pub fn pair_to_key(id: u64, screen_name: &str) -> Vec<u8> {
+    let screen_name = normalize_screen_name(screen_name);
+    let screen_name_bytes = screen_name.as_bytes();
+    let mut key = Vec::with_capacity(ID_LEN + screen_name_bytes.len());
+    key.extend_from_slice(&id_to_key_prefix(id));
+    key.extend_from_slice(screen_name_bytes);
+    key
+}
+
+pub fn key_to_pair(key: &[u8]) -> Result<(u64, &str), Error> {
+    let id = key_prefix_to_id(key)?;
+    let screen_name = std::str::from_utf8(&key[ID_LEN..])?;
+
+    Ok((id, screen_name))
+}
+
+/// Screen name table keys are the lowercased normalized screen name.
+pub fn screen_name_to_key(screen_name: &str) -> Vec<u8> {
+    // Lowercasing can produce unnormalized text (for example from `İ`)
+    normalize_screen_name(&normalize_screen_name(screen_name).to_lowercase())
+        .into_owned()
+        .into_bytes()
+}
+
+pub fn key_to_screen_name(key: &[u8]) -> Result<&str, Error> {
+    Ok(std::str::from_utf8(key)?)
+}
+
+pub fn date_to_day_id(date: &NaiveDate) -> Result<u16, Error> {
+    let day = (*date - *TWITTER_EPOCH).num_days();
+    day.try_into().map_err(|_| Error::InvalidDay(day))
+}
+
+pub fn day_id_to_date(day_id: u16) -> NaiveDate {
+    *TWITTER_EPOCH + Duration::days(day_id.into())
+}
+
+/// Account table values are big-endian day IDs, in no particular order.
+pub fn dates_to_value(dates: &[NaiveDate]) -> Result<Vec<u8>, Error> {
+    let mut value = Vec::with_capacity(DAY_ID_LEN * dates.len());
+
+    for date in dates {
+        value.extend_from_slice(&date_to_day_id(date)?.to_be_bytes());
+    }
+
+    Ok(value)
+}
+
+/// Decode an account table value, returning its dates in sorted order.
+pub fn value_to_dates(value: &[u8]) -> Result<Vec<NaiveDate>, Error> {
+    Ok(DayIds::new(value)?.to_dates())
+}
+
+/// A borrowed view of an account table value, which decodes its dates as they're read instead of
+/// allocating a vector for them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DayIds<'a> {
+    value: &'a [u8],
+}
+
+impl<'a> DayIds<'a> {
+    /// Check that a value is made up of whole day IDs.
+    pub fn new(value: &'a [u8]) -> Result<Self, Error> {
+        if value.len().is_multiple_of(DAY_ID_LEN) {
+            Ok(Self { value })
+        } else {
+            Err(Error::InvalidValue(value.to_vec()))
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.value.len() / DAY_ID_LEN
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.value.is_empty()
+    }
+
+    /// The dates in stored order (which isn't necessarily sorted).
+    pub fn iter(&self) -> impl Iterator<Item = NaiveDate> + 'a {
+        self.day_ids().map(day_id_to_date)
+    }
+
+    /// The earliest date.
+    pub fn first(&self) -> Option<NaiveDate> {
+        self.day_ids().min().map(day_id_to_date)
+    }
+
+    /// The latest date.
+    pub fn last(&self) -> Option<NaiveDate> {
+        self.day_ids().max().map(day_id_to_date)
+    }
+
+    /// The dates as runs of consecutive days.
+    pub fn to_ranges(&self) -> DateRanges {
+        DateRanges::from_dates(self.iter())
+    }
+
+    /// The dates in sorted order.
+    pub fn to_dates(&self) -> Vec<NaiveDate> {
+        let mut dates = self.iter().collect::<Vec<_>>();
+        dates.sort();
+        dates
+    }
+
+    fn day_ids(&self) -> impl Iterator<Item = u16> + 'a {
+        self.value
+            .chunks_exact(DAY_ID_LEN)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+}
+
+/// Encode an account table value, sealing it if there's a keyring (see [`encryption`]).
+pub fn dates_to_stored_value(
+    key: &[u8],
+    dates: &[NaiveDate],
+    keyring: Option<&Keyring>,
+) -> Result<Vec<u8>, Error> {
+    let value = dates_to_value(dates)?;
+
+    match keyring {
+        Some(keyring) => Ok(keyring.seal(key, &value)?),
+        None => Ok(value),
+    }
+}
+
+/// Decode an account table value that may be sealed.
+pub fn stored_value_to_dates(
+    key: &[u8],
+    value: &[u8],
+    keyring: Option<&Keyring>,
+) -> Result<Vec<NaiveDate>, Error> {
+    value_to_dates(&open_stored_value(key, value, keyring)?)
+}
+
+/// The plaintext of an account table value that may be sealed (only copied if it was sealed).
+pub fn open_stored_value<'a>(
+    key: &[u8],
+    value: &'a [u8],
+    keyring: Option<&Keyring>,
+) -> Result<Cow<'a, [u8]>, Error> {
+    match keyring {
+        Some(keyring) => Ok(keyring.open(key, value)?),
+        None if encryption::is_sealed(value) => Err(encryption::Error::KeyRequired.into()),
+        None => Ok(Cow::Borrowed(value)),
+    }
+}
+
+/// Screen name table values are big-endian IDs.
+pub fn ids_to_value(ids: &[u64]) -> Vec<u8> {
+    let mut value = Vec::with_capacity(ID_LEN * ids.len());
+
+    for id in ids {
+        value.extend_from_slice(&id.to_be_bytes());
+    }
+
+    value
+}
+
+pub fn value_to_ids(value: &[u8]) -> Result<Vec<u64>, Error> {
+    Ok(chunks(value, ID_LEN)?
+        .map(|bytes| u64::from_be_bytes(bytes.try_into().unwrap()))
+        .collect())
+}
+
+/// Weight table values are entries of a big-endian day ID followed by a big-endian count.
+pub fn weights_to_value(counts: &[(NaiveDate, u32)]) -> Result<Vec<u8>, Error> {
+    let mut value = Vec::with_capacity(WEIGHT_ENTRY_LEN * counts.len());
+
+    for (date, count) in counts {
+        value.extend_from_slice(&date_to_day_id(date)?.to_be_bytes());
+        value.extend_from_slice(&count.to_be_bytes());
+    }
+
+    Ok(value)
+}
+
+pub fn value_to_weights(value: &[u8]) -> Result<Vec<(NaiveDate, u32)>, Error> {
+    Ok(chunks(value, WEIGHT_ENTRY_LEN)?
+        .map(|entry| {
+            let day_id = u16::from_be_bytes([entry[0], entry[1]]);
+            let count = u32::from_be_bytes([entry[2], entry[3], entry[4], entry[5]]);
+
+            (day_id_to_date(day_id), count)
+        })
+        .collect())
+}
+
+/// Split a value into fixed-length entries, failing if there is a partial entry.
+fn chunks(value: &[u8], len: usize) -> Result<std::slice::ChunksExact<'_, u8>, Error> {
+    if value.len().is_multiple_of(len) {
+        Ok(value.chunks_exact(len))
+    } else {
+        Err(Error::InvalidValue(value.to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    const CASES: usize = 10_000;
+
+    fn random_screen_name(rng: &mut StdRng) -> String {
+        let len = rng.gen_range(0..20);
+
+        // Include non-ASCII characters, since keys are arbitrary UTF-8
+        (0..len)
+            .map(|_| match rng.gen_range(0..4) {
+                0 => rng.gen_range('a'..='z'),
+                1 => rng.gen_range('A'..='Z'),
+                2 => rng.gen_range('0'..='9'),
+                _ => rng.gen::<char>(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn day_id_round_trip() {
+        for day_id in 0..=u16::MAX {
+            assert_eq!(date_to_day_id(&day_id_to_date(day_id)).unwrap(), day_id);
+        }
+
+        assert!(date_to_day_id(&(*TWITTER_EPOCH - Duration::days(1))).is_err());
+        assert!(date_to_day_id(&(day_id_to_date(u16::MAX) + Duration::days(1))).is_err());
+    }
+
+    #[test]
+    fn pair_round_trip() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for _ in 0..CASES {
+            let id = rng.gen::<u64>();
+            let screen_name = random_screen_name(&mut rng);
+            let key = pair_to_key(id, &screen_name);
+
+            assert_eq!(
+                key_to_pair(&key).unwrap(),
+                (id, normalize_screen_name(&screen_name).as_ref())
+            );
+            assert_eq!(key_prefix_to_id(&key).unwrap(), id);
+        }
+    }
+
+    #[test]
+    fn screen_name_round_trip() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for _ in 0..CASES {
+            let screen_name = random_screen_name(&mut rng);
+            let key = screen_name_to_key(&screen_name);
+
+            assert_eq!(
+                key_to_screen_name(&key).unwrap(),
+                normalize_screen_name(&screen_name.to_lowercase())
+            );
+        }
+    }
+
+    #[test]
+    fn normalized_screen_names() {
+        // Composed and decomposed forms of the same handle share keys
+        let composed = "Jos\u{e9}";
+        let decomposed = "Jose\u{301}";
+
+        assert_eq!(pair_to_key(1, composed), pair_to_key(1, decomposed));
+        assert_eq!(screen_name_to_key(composed), screen_name_to_key(decomposed));
+        assert_eq!(
+            key_to_pair(&pair_to_key(1, decomposed)).unwrap(),
+            (1, composed)
+        );
+        assert_eq!(
+            key_to_screen_name(&screen_name_to_key(decomposed)).unwrap(),
+            "jos\u{e9}"
+        );
+
+        // Domains are stored as punycode
+        assert_eq!(
+            normalize_screen_name("foo@B\u{fc}cher.example"),
+            "foo@xn--bcher-kva.example"
+        );
+        assert_eq!(
+            screen_name_to_key("foo@xn--bcher-kva.example"),
+            screen_name_to_key("Foo@b\u{fc}cher.example")
+        );
+
+        // Twitter screen names are unchanged
+        assert!(matches!(
+            normalize_screen_name("foo_123"),
+            Cow::Borrowed("foo_123")
+        ));
+        assert_eq!(normalize_screen_name("@foo"), "@foo");
+    }
+
+    #[test]
+    fn dates_round_trip() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for _ in 0..CASES {
+            let len = rng.gen_range(0..16);
+            let mut dates = (0..len)
+                .map(|_| day_id_to_date(rng.gen()))
+                .collect::<Vec<_>>();
+            let value = dates_to_value(&dates).unwrap();
+
+            let borrowed = DayIds::new(&value).unwrap();
+            assert_eq!(borrowed.len(), len);
+            assert!(borrowed.iter().eq(dates.iter().copied()));
+
+            dates.sort();
+            assert_eq!(value_to_dates(&value).unwrap(), dates);
+            assert_eq!(borrowed.first(), dates.first().copied());
+            assert_eq!(borrowed.last(), dates.last().copied());
+        }
+    }
+
+    #[test]
+    fn ids_round_trip() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for _ in 0..CASES {
+            let len = rng.gen_range(0..16);
+            let ids = (0..len).map(|_| rng.gen()).collect::<Vec<u64>>();
+
+            assert_eq!(value_to_ids(&ids_to_value(&ids)).unwrap(), ids);
+        }
+    }
+
+    #[test]
+    fn weights_round_trip() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for _ in 0..CASES {
+            let len = rng.gen_range(0..16);
+            let weights = (0..len)
+                .map(|_| (day_id_to_date(rng.gen()), rng.gen()))
+                .collect::<Vec<_>>();
+            let value = weights_to_value(&weights).unwrap();
+
+            assert_eq!(value_to_weights(&value).unwrap(), weights);
+        }
+    }
+
+    #[test]
+    fn arbitrary_bytes() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for _ in 0..CASES {
+            let len = rng.gen_range(0..64);
+            let bytes = (0..len).map(|_| rng.gen()).collect::<Vec<u8>>();
+
+            // Decoding never panics, and only fails for partial entries or invalid UTF-8
+            assert_eq!(key_prefix_to_id(&bytes).is_ok(), len >= ID_LEN);
+            assert_eq!(
+                key_to_pair(&bytes).is_ok(),
+                len >= ID_LEN && std::str::from_utf8(&bytes[ID_LEN..]).is_ok()
+            );
+            assert_eq!(
+                key_to_screen_name(&bytes).is_ok(),
+                std::str::from_utf8(&bytes).is_ok()
+            );
+            assert_eq!(
+                value_to_dates(&bytes).is_ok(),
+                len.is_multiple_of(DAY_ID_LEN)
+            );
+            assert_eq!(value_to_ids(&bytes).is_ok(), len.is_multiple_of(ID_LEN));
+            assert_eq!(
+                value_to_weights(&bytes).is_ok(),
+                len.is_multiple_of(WEIGHT_ENTRY_LEN)
+            );
+        }
+    }
+}
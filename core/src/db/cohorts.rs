@@ -0,0 +1,228 @@
+//! Rename rates of accounts grouped by when they were first observed.
+//!
+//! Unlike [`super::aggregates`], these counts are exact, so they're meant for research on a
+//! private database rather than for publication.
+use super::{accounts::AccountTable, Error};
+use chrono::{Datelike, NaiveDate};
+use serde_derive::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+
+/// The length of time covered by a cohort.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    #[default]
+    Month,
+    Year,
+}
+
+impl Period {
+    /// The first day of the period containing a date.
+    pub fn start(&self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Month => date.with_day(1).unwrap_or(date),
+            Period::Year => date.with_ordinal(1).unwrap_or(date),
+        }
+    }
+
+    /// The number of whole periods from the period containing one date to the period containing
+    /// another.
+    pub fn offset(&self, from: NaiveDate, to: NaiveDate) -> i64 {
+        let years = to.year() as i64 - from.year() as i64;
+
+        match self {
+            Period::Month => years * 12 + to.month() as i64 - from.month() as i64,
+            Period::Year => years,
+        }
+    }
+}
+
+impl FromStr for Period {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "month" => Ok(Period::Month),
+            "year" => Ok(Period::Year),
+            other => Err(format!("Unknown period: {}", other)),
+        }
+    }
+}
+
+/// Parameters for grouping accounts into cohorts.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CohortOptions {
+    pub period: Period,
+    /// Number of periods after the first to report rename counts for
+    pub max_offset: usize,
+}
+
+/// Counts for the accounts first observed in one period.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Cohort {
+    pub accounts: u64,
+    /// Accounts observed with at least one new screen name after their first
+    pub renamed: u64,
+    /// Total number of new screen names observed
+    pub renames: u64,
+    /// Fraction of accounts that were renamed
+    pub rename_rate: f64,
+    /// Number of accounts first observed with a new screen name in each period since the cohort's
+    /// (starting with the cohort's own period)
+    pub renamed_by_offset: Vec<u64>,
+}
+
+/// Accounts grouped by the period they were first observed in, keyed by the first day of the
+/// period.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Cohorts {
+    pub period: Period,
+    pub cohorts: BTreeMap<NaiveDate, Cohort>,
+}
+
+impl Cohorts {
+    pub fn compute<M>(accounts: &AccountTable<M>, options: &CohortOptions) -> Result<Self, Error> {
+        let mut cohorts = BTreeMap::new();
+        let mut current_id = None;
+        let mut first_dates = HashMap::new();
+
+        for pair in accounts.pairs() {
+            let (id, screen_name, dates) = pair?;
+
+            if current_id != Some(id) {
+                count_account(&mut first_dates, options, &mut cohorts);
+                current_id = Some(id);
+            }
+
+            // Differently cased observations of the same screen name are not renames
+            if let Some(date) = dates.iter().min() {
+                let first_date = first_dates
+                    .entry(screen_name.to_lowercase())
+                    .or_insert(*date);
+                *first_date = (*first_date).min(*date);
+            }
+        }
+
+        count_account(&mut first_dates, options, &mut cohorts);
+
+        for cohort in cohorts.values_mut() {
+            cohort.rename_rate = cohort.renamed as f64 / cohort.accounts as f64;
+        }
+
+        Ok(Self {
+            period: options.period,
+            cohorts,
+        })
+    }
+}
+
+/// Add an account to the cohort of its earliest observation.
+fn count_account(
+    first_dates: &mut HashMap<String, NaiveDate>,
+    options: &CohortOptions,
+    cohorts: &mut BTreeMap<NaiveDate, Cohort>,
+) {
+    let mut dates = first_dates
+        .drain()
+        .map(|(_, date)| date)
+        .collect::<Vec<_>>();
+    dates.sort();
+
+    if let Some(first) = dates.first() {
+        let cohort = cohorts
+            .entry(options.period.start(*first))
+            .or_insert_with(|| Cohort {
+                renamed_by_offset: vec![0; options.max_offset + 1],
+                ..Default::default()
+            });
+
+        cohort.accounts += 1;
+
+        // The first screen name observed is not a rename
+        let renames = &dates[1..];
+
+        if !renames.is_empty() {
+            cohort.renamed += 1;
+            cohort.renames += renames.len() as u64;
+        }
+
+        let mut offsets = renames
+            .iter()
+            .map(|date| options.period.offset(*first, *date))
+            .collect::<Vec<_>>();
+        offsets.dedup();
+
+        for offset in offsets {
+            if let Some(count) = cohort.renamed_by_offset.get_mut(offset as usize) {
+                *count += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixture;
+
+    #[test]
+    fn cohorts() {
+        let (_dir, db) = fixture::database();
+        let date = |month, day| NaiveDate::from_ymd_opt(2020, month, day).unwrap();
+
+        db.insert(123, "foo", vec![date(1, 5), date(1, 20)])
+            .unwrap();
+        db.insert(123, "bar", vec![date(1, 25)]).unwrap();
+        db.insert(123, "baz", vec![date(3, 1)]).unwrap();
+        db.insert(123, "qux", vec![date(3, 9)]).unwrap();
+        // Differently cased observations of one screen name aren't a rename
+        db.insert(456, "abc", vec![date(1, 2)]).unwrap();
+        db.insert(456, "ABC", vec![date(2, 6)]).unwrap();
+        db.insert(789, "def", vec![date(2, 3)]).unwrap();
+        db.insert(789, "ghi", vec![date(8, 6)]).unwrap();
+        // Accounts without dates aren't in any cohort
+        db.insert(999, "jkl", vec![]).unwrap();
+
+        let options = CohortOptions {
+            period: Period::Month,
+            max_offset: 3,
+        };
+        let result = db.cohorts(&options).unwrap();
+
+        assert_eq!(
+            result.cohorts.into_iter().collect::<Vec<_>>(),
+            vec![
+                (
+                    date(1, 1),
+                    Cohort {
+                        accounts: 2,
+                        renamed: 1,
+                        renames: 3,
+                        rename_rate: 0.5,
+                        renamed_by_offset: vec![1, 0, 1, 0],
+                    }
+                ),
+                (
+                    date(2, 1),
+                    Cohort {
+                        accounts: 1,
+                        renamed: 1,
+                        renames: 1,
+                        rename_rate: 1.0,
+                        renamed_by_offset: vec![0, 0, 0, 0],
+                    }
+                ),
+            ]
+        );
+
+        let options = CohortOptions {
+            period: Period::Year,
+            max_offset: 0,
+        };
+        let result = db.cohorts(&options).unwrap();
+
+        assert_eq!(result.cohorts.len(), 1);
+        assert_eq!(result.cohorts[&date(1, 1)].renamed_by_offset, vec![2]);
+    }
+}
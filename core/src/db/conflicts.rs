@@ -0,0 +1,253 @@
+use super::{accounts::AccountTable, screen_names::ScreenNameTable, Error};
+use chrono::{Duration, NaiveDate};
+use priority_queue::DoublePriorityQueue;
+use serde_derive::Serialize;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+/// Observations that cannot all be correct, which usually indicates bad source data.
+///
+/// Each pair is treated as covering every day from its first to its last observation, so that
+/// pairs imported in range mode are compared in the same way as pairs with every day recorded.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Conflict {
+    /// A single account observed with multiple screen names over the same days
+    ScreenNames {
+        id: u64,
+        first: NaiveDate,
+        last: NaiveDate,
+        screen_names: Vec<String>,
+    },
+    /// A single screen name observed for multiple accounts over the same days
+    Ids {
+        screen_name: String,
+        first: NaiveDate,
+        last: NaiveDate,
+        ids: Vec<u64>,
+    },
+}
+
+impl Conflict {
+    /// The number of competing values observed over the conflicting days.
+    pub fn severity(&self) -> usize {
+        match self {
+            Conflict::ScreenNames { screen_names, .. } => screen_names.len(),
+            Conflict::Ids { ids, .. } => ids.len(),
+        }
+    }
+
+    /// The first day of the conflict.
+    pub fn date(&self) -> NaiveDate {
+        match self {
+            Conflict::ScreenNames { first, .. } => *first,
+            Conflict::Ids { first, .. } => *first,
+        }
+    }
+}
+
+/// Collects conflicts, keeping only the most severe if there is a limit.
+pub struct Conflicts {
+    queue: DoublePriorityQueue<Conflict, (usize, Reverse<NaiveDate>)>,
+    limit: Option<usize>,
+}
+
+impl Conflicts {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            queue: limit.map_or_else(DoublePriorityQueue::new, DoublePriorityQueue::with_capacity),
+            limit,
+        }
+    }
+
+    pub fn push(&mut self, conflict: Conflict) {
+        let priority = (conflict.severity(), Reverse(conflict.date()));
+        self.queue.push(conflict, priority);
+
+        if let Some(limit) = self.limit {
+            if self.queue.len() > limit {
+                self.queue.pop_min();
+            }
+        }
+    }
+
+    /// Conflicts by descending severity, then by date.
+    pub fn into_ranked(self) -> Vec<Conflict> {
+        self.queue.into_descending_sorted_vec()
+    }
+}
+
+/// Find overlapping observations of more than one screen name (compared case-insensitively) for
+/// an account.
+pub fn find_screen_name_conflicts<M>(
+    accounts: &AccountTable<M>,
+    conflicts: &mut Conflicts,
+) -> Result<(), Error> {
+    let mut current_id = None;
+    let mut ranges: BTreeMap<String, (String, NaiveDate, NaiveDate)> = BTreeMap::new();
+
+    for pair in accounts.pairs() {
+        let (id, screen_name, dates) = pair?;
+
+        if current_id != Some(id) {
+            if let Some(current_id) = current_id {
+                collect_screen_name_conflicts(current_id, &mut ranges, conflicts);
+            }
+            current_id = Some(id);
+        }
+
+        if let (Some(first), Some(last)) = (dates.first(), dates.last()) {
+            // Differently cased observations of the same screen name share a range
+            let range = ranges.entry(screen_name.to_lowercase()).or_insert((
+                screen_name.clone(),
+                *first,
+                *last,
+            ));
+            range.1 = range.1.min(*first);
+            range.2 = range.2.max(*last);
+        }
+    }
+
+    if let Some(current_id) = current_id {
+        collect_screen_name_conflicts(current_id, &mut ranges, conflicts);
+    }
+
+    Ok(())
+}
+
+fn collect_screen_name_conflicts(
+    id: u64,
+    ranges: &mut BTreeMap<String, (String, NaiveDate, NaiveDate)>,
+    conflicts: &mut Conflicts,
+) {
+    let ranges = std::mem::take(ranges).into_values().collect::<Vec<_>>();
+
+    for (first, last, screen_names) in overlaps(&ranges) {
+        conflicts.push(Conflict::ScreenNames {
+            id,
+            first,
+            last,
+            screen_names,
+        });
+    }
+}
+
+/// Find overlapping observations of a screen name (compared case-insensitively) for more than
+/// one account.
+pub fn find_id_conflicts<M, N>(
+    accounts: &AccountTable<M>,
+    screen_names: &ScreenNameTable<N>,
+    conflicts: &mut Conflicts,
+) -> Result<(), Error> {
+    for result in screen_names.reused() {
+        let (screen_name, ids) = result?;
+        let mut ranges = vec![];
+
+        for id in ids {
+            for (account_screen_name, dates) in accounts.lookup(id)? {
+                if account_screen_name.to_lowercase() == screen_name {
+                    if let (Some(first), Some(last)) = (dates.first(), dates.last()) {
+                        ranges.push((id, *first, *last));
+                    }
+                }
+            }
+        }
+
+        for (first, last, ids) in overlaps(&ranges) {
+            conflicts.push(Conflict::Ids {
+                screen_name: screen_name.clone(),
+                first,
+                last,
+                ids,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Find the spans of days covered by more than one of the given inclusive ranges.
+///
+/// Adjacent spans covered by the same values are combined, and values are sorted.
+fn overlaps<T: Clone + Ord>(
+    ranges: &[(T, NaiveDate, NaiveDate)],
+) -> Vec<(NaiveDate, NaiveDate, Vec<T>)> {
+    let mut boundaries = ranges
+        .iter()
+        .flat_map(|(_, first, last)| [*first, *last + Duration::days(1)])
+        .collect::<Vec<_>>();
+    boundaries.sort();
+    boundaries.dedup();
+
+    let mut results: Vec<(NaiveDate, NaiveDate, Vec<T>)> = vec![];
+
+    for window in boundaries.windows(2) {
+        let (start, end) = (window[0], window[1] - Duration::days(1));
+        let mut values = ranges
+            .iter()
+            .filter(|(_, first, last)| *first <= start && start <= *last)
+            .map(|(value, _, _)| value.clone())
+            .collect::<Vec<_>>();
+
+        if values.len() > 1 {
+            values.sort();
+
+            match results.last_mut() {
+                Some((_, last, last_values))
+                    if *last + Duration::days(1) == start && *last_values == values =>
+                {
+                    *last = end;
+                }
+                _ => results.push((start, end, values)),
+            }
+        }
+    }
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixture::{self, date};
+
+    #[test]
+    fn find_conflicts() {
+        let (_dir, db) = fixture::database();
+
+        db.insert(123, "foo", vec![date(1), date(5)]).unwrap();
+        db.insert(123, "bar", vec![date(5), date(9)]).unwrap();
+        db.insert(456, "FOO", vec![date(1)]).unwrap();
+        db.insert(789, "foo", vec![date(1)]).unwrap();
+        // Differently cased observations of one screen name aren't a conflict
+        db.insert(321, "qux", vec![date(1), date(3)]).unwrap();
+        db.insert(321, "Qux", vec![date(2)]).unwrap();
+        // Range-mode pairs only record first and last days, so overlapping ranges conflict
+        db.insert(654, "baz", vec![date(10), date(20)]).unwrap();
+        db.insert(987, "baz", vec![date(15), date(25)]).unwrap();
+
+        let expected = vec![
+            Conflict::Ids {
+                screen_name: "foo".to_string(),
+                first: date(1),
+                last: date(1),
+                ids: vec![123, 456, 789],
+            },
+            Conflict::ScreenNames {
+                id: 123,
+                first: date(5),
+                last: date(5),
+                screen_names: vec!["bar".to_string(), "foo".to_string()],
+            },
+            Conflict::Ids {
+                screen_name: "baz".to_string(),
+                first: date(15),
+                last: date(20),
+                ids: vec![654, 987],
+            },
+        ];
+
+        assert_eq!(db.find_conflicts(None).unwrap(), expected);
+        assert_eq!(db.find_conflicts(Some(2)).unwrap(), expected[0..2]);
+    }
+}
@@ -0,0 +1,390 @@
+use super::{
+    codec::{dates_to_value, key_prefix_to_id, value_to_dates, DAY_ID_LEN, ID_LEN},
+    options::DatabaseOptions,
+    table::{Mode, Table, Writeable},
+    Error,
+};
+use chrono::NaiveDate;
+use rocksdb::{IteratorMode, MergeOperands, Options, WriteBatch, DB};
+use std::collections::BTreeSet;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::str::FromStr;
+
+const INCOMING: u8 = b'i';
+const OUTGOING: u8 = b'o';
+const KEY_LEN: usize = 2 + 2 * ID_LEN;
+
+/// A kind of interaction between two accounts.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum EdgeKind {
+    Mention,
+    Reply,
+    Quote,
+    Retweet,
+}
+
+impl EdgeKind {
+    pub const ALL: [EdgeKind; 4] = [
+        EdgeKind::Mention,
+        EdgeKind::Reply,
+        EdgeKind::Quote,
+        EdgeKind::Retweet,
+    ];
+
+    fn to_byte(self) -> u8 {
+        match self {
+            EdgeKind::Mention => b'm',
+            EdgeKind::Reply => b'r',
+            EdgeKind::Quote => b'q',
+            EdgeKind::Retweet => b't',
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.to_byte() == byte)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EdgeKind::Mention => "mention",
+            EdgeKind::Reply => "reply",
+            EdgeKind::Quote => "quote",
+            EdgeKind::Retweet => "retweet",
+        }
+    }
+}
+
+impl std::fmt::Display for EdgeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for EdgeKind {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.name() == value)
+            .ok_or_else(|| Error::InvalidEdgeKind(value.to_string()))
+    }
+}
+
+/// An interaction from one account to another, with the days on which it was observed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Edge {
+    pub kind: EdgeKind,
+    /// The account that mentioned, replied to, quoted, or retweeted the target
+    pub source: u64,
+    pub target: u64,
+    pub dates: Vec<NaiveDate>,
+}
+
+/// Which of an account's edges to query.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Direction {
+    /// Edges targeting the account
+    Incoming,
+    /// Edges from the account
+    Outgoing,
+}
+
+/// Interactions between accounts (mentions, replies, quotes, and retweets).
+///
+/// Every edge is stored twice, so that both an account's incoming and outgoing edges are a range
+/// of keys. Keys are a direction byte, a kind byte, the big-endian ID of the account the key is
+/// indexed by, and the big-endian ID of the other account, and values are day IDs, which are
+/// merged as a set.
+pub struct EdgeTable<M> {
+    db: DB,
+    mode: PhantomData<M>,
+}
+
+impl<M> Table for EdgeTable<M> {
+    type Counts = u64;
+
+    fn underlying(&self) -> &DB {
+        &self.db
+    }
+
+    /// The number of edges.
+    fn get_counts(&self) -> Result<Self::Counts, Error> {
+        let mut count = 0;
+
+        for result in self.db.iterator(IteratorMode::Start) {
+            let (key, _) = result?;
+
+            if key.first() == Some(&OUTGOING) {
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+}
+
+impl<M> EdgeTable<M> {
+    /// An account's edges in one direction, optionally of a single kind, with their dates limited
+    /// to an inclusive range.
+    ///
+    /// Edges with no dates in the range are omitted. Results are ordered by kind and then by the
+    /// ID of the other account.
+    pub fn edges(
+        &self,
+        id: u64,
+        direction: Direction,
+        kind: Option<EdgeKind>,
+        since: Option<NaiveDate>,
+        until: Option<NaiveDate>,
+    ) -> Result<Vec<Edge>, Error> {
+        let kinds = match kind {
+            Some(kind) => vec![kind],
+            None => EdgeKind::ALL.to_vec(),
+        };
+        let mut results = vec![];
+
+        for kind in kinds {
+            let prefix = key_prefix(direction, kind, id);
+
+            for result in self.db.prefix_iterator(&prefix) {
+                let (key, value) = result?;
+
+                if !key.starts_with(&prefix) {
+                    break;
+                }
+
+                let (_, _, _, other) = parse_key(&key)?;
+                let dates = value_to_dates(&value)?
+                    .into_iter()
+                    .filter(|date| {
+                        since.is_none_or(|since| *date >= since)
+                            && until.is_none_or(|until| *date <= until)
+                    })
+                    .collect::<Vec<_>>();
+
+                if !dates.is_empty() {
+                    let (source, target) = match direction {
+                        Direction::Incoming => (other, id),
+                        Direction::Outgoing => (id, other),
+                    };
+
+                    results.push(Edge {
+                        kind,
+                        source,
+                        target,
+                        dates,
+                    });
+                }
+            }
+        }
+
+        Ok(results)
+    }
+}
+
+impl<M: Mode> EdgeTable<M> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::open_with_options(path, &DatabaseOptions::default())
+    }
+
+    pub fn open_with_options<P: AsRef<Path>>(
+        path: P,
+        database_options: &DatabaseOptions,
+    ) -> Result<Self, Error> {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        options.set_merge_operator_associative("merge", merge);
+        database_options.apply(&mut options);
+
+        let db = if M::is_read_only() {
+            DB::open_for_read_only(&options, path, true)?
+        } else {
+            DB::open(&options, path)?
+        };
+
+        Ok(Self {
+            db,
+            mode: PhantomData,
+        })
+    }
+}
+
+impl EdgeTable<Writeable> {
+    /// Record an interaction on the given days.
+    pub fn add(
+        &self,
+        kind: EdgeKind,
+        source: u64,
+        target: u64,
+        dates: &[NaiveDate],
+    ) -> Result<(), Error> {
+        let mut batch = WriteBatch::default();
+        add_to_batch(&mut batch, kind, source, target, dates)?;
+
+        Ok(self.db.write(batch)?)
+    }
+
+    /// Record many interactions with a single write, returning the number of edges written.
+    pub fn add_all<'a, I: IntoIterator<Item = &'a Edge>>(&self, edges: I) -> Result<usize, Error> {
+        let mut batch = WriteBatch::default();
+        let mut count = 0;
+
+        for edge in edges {
+            add_to_batch(&mut batch, edge.kind, edge.source, edge.target, &edge.dates)?;
+            count += 1;
+        }
+
+        self.db.write(batch)?;
+
+        Ok(count)
+    }
+}
+
+fn add_to_batch(
+    batch: &mut WriteBatch,
+    kind: EdgeKind,
+    source: u64,
+    target: u64,
+    dates: &[NaiveDate],
+) -> Result<(), Error> {
+    let value = dates_to_value(dates)?;
+
+    batch.merge(to_key(Direction::Outgoing, kind, source, target), &value);
+    batch.merge(to_key(Direction::Incoming, kind, target, source), &value);
+
+    Ok(())
+}
+
+fn key_prefix(direction: Direction, kind: EdgeKind, id: u64) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(KEY_LEN);
+    prefix.push(match direction {
+        Direction::Incoming => INCOMING,
+        Direction::Outgoing => OUTGOING,
+    });
+    prefix.push(kind.to_byte());
+    prefix.extend_from_slice(&id.to_be_bytes());
+    prefix
+}
+
+fn to_key(direction: Direction, kind: EdgeKind, id: u64, other: u64) -> Vec<u8> {
+    let mut key = key_prefix(direction, kind, id);
+    key.extend_from_slice(&other.to_be_bytes());
+    key
+}
+
+fn parse_key(key: &[u8]) -> Result<(Direction, EdgeKind, u64, u64), Error> {
+    let invalid = || Error::InvalidKey(key.to_vec());
+
+    if key.len() != KEY_LEN {
+        return Err(invalid());
+    }
+
+    let direction = match key[0] {
+        INCOMING => Direction::Incoming,
+        OUTGOING => Direction::Outgoing,
+        _ => return Err(invalid()),
+    };
+    let kind = EdgeKind::from_byte(key[1]).ok_or_else(invalid)?;
+
+    Ok((
+        direction,
+        kind,
+        key_prefix_to_id(&key[2..])?,
+        key_prefix_to_id(&key[2 + ID_LEN..])?,
+    ))
+}
+
+/// Take the union of day IDs.
+fn merge(
+    _new_key: &[u8],
+    existing_val: Option<&[u8]>,
+    operands: &MergeOperands,
+) -> Option<Vec<u8>> {
+    let mut day_ids = BTreeSet::new();
+
+    for value in existing_val.into_iter().chain(operands.iter()) {
+        if !value.len().is_multiple_of(DAY_ID_LEN) {
+            log::error!("{}", Error::InvalidValue(value.to_vec()));
+            return None;
+        }
+
+        day_ids.extend(
+            value
+                .chunks_exact(DAY_ID_LEN)
+                .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]])),
+        );
+    }
+
+    Some(
+        day_ids
+            .into_iter()
+            .flat_map(|day_id| day_id.to_be_bytes())
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixture::{self, date};
+
+    #[test]
+    fn edges() {
+        use {Direction, Edge, EdgeKind, EdgeTable};
+
+        let (_dir, edges) = fixture::open(EdgeTable::<Writeable>::open);
+        let edge = |kind, source, target, dates| Edge {
+            kind,
+            source,
+            target,
+            dates,
+        };
+
+        edges
+            .add(EdgeKind::Mention, 456, 123, &[date(3), date(1)])
+            .unwrap();
+        edges
+            .add_all(&[
+                edge(EdgeKind::Mention, 456, 123, vec![date(2), date(3)]),
+                edge(EdgeKind::Reply, 456, 123, vec![date(4)]),
+                edge(EdgeKind::Retweet, 789, 123, vec![date(5)]),
+                edge(EdgeKind::Quote, 123, 789, vec![date(6)]),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            edges
+                .edges(123, Direction::Incoming, None, None, None)
+                .unwrap(),
+            vec![
+                edge(EdgeKind::Mention, 456, 123, vec![date(1), date(2), date(3)]),
+                edge(EdgeKind::Reply, 456, 123, vec![date(4)]),
+                edge(EdgeKind::Retweet, 789, 123, vec![date(5)]),
+            ]
+        );
+        assert_eq!(
+            edges
+                .edges(123, Direction::Incoming, None, Some(date(2)), Some(date(4)))
+                .unwrap(),
+            vec![
+                edge(EdgeKind::Mention, 456, 123, vec![date(2), date(3)]),
+                edge(EdgeKind::Reply, 456, 123, vec![date(4)]),
+            ]
+        );
+        assert_eq!(
+            edges
+                .edges(123, Direction::Outgoing, Some(EdgeKind::Quote), None, None)
+                .unwrap(),
+            vec![edge(EdgeKind::Quote, 123, 789, vec![date(6)])]
+        );
+        assert!(edges
+            .edges(456, Direction::Incoming, None, None, None)
+            .unwrap()
+            .is_empty());
+        assert_eq!(edges.get_counts().unwrap(), 4);
+        assert_eq!("retweet".parse::<EdgeKind>().unwrap(), EdgeKind::Retweet);
+        assert!("like".parse::<EdgeKind>().is_err());
+    }
+}
@@ -0,0 +1,471 @@
+//! Encryption of account table values at rest.
+//!
+//! Values are sealed with AES-256-GCM under a random nonce, and authenticated together with their
+//! key, so that a value can't be moved to another pair without failing to decrypt. Keys (user IDs
+//! and screen names) are stored in the clear, since lookups and range scans depend on their order.
+//!
+//! A sealed value starts with a version byte and the ID of the key it was sealed with, followed by
+//! the nonce and the ciphertext. This makes its length odd, while plaintext date lists always have
+//! an even length, so a table can hold a mix of both while it's being converted.
+//!
+//! Random nonces are only safe for about 2³² encryptions under one key, and every merge re-seals
+//! the merged value, so keys should be rotated well before that many writes.
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Nonce,
+};
+use rand::RngCore;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// File in the database directory that records the keys values may be sealed with.
+pub const ENCRYPTION_FILE: &str = "encryption.json";
+
+const VERSION: u8 = 1;
+const KEY_LEN: usize = 32;
+const KEY_ID_LEN: usize = 4;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = 1 + KEY_ID_LEN + NONCE_LEN;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Invalid encryption key (expected 64 hex digits) from {0}")]
+    InvalidKey(String),
+    #[error("Environment variable {0} is not set")]
+    MissingVariable(String),
+    #[error("Key command failed: {0}")]
+    KeyCommand(String),
+    #[error("Invalid key source (expected file:<path>, env:<variable>, or command:<command>)")]
+    InvalidSource(String),
+    #[error("The database is encrypted, but no encryption key was given")]
+    KeyRequired,
+    #[error("The database has values sealed with key {0}, which isn't one of the given keys")]
+    UnknownKey(String),
+    #[error("Value could not be decrypted")]
+    Decryption,
+    #[error("Value could not be encrypted")]
+    Encryption,
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("JSON error")]
+    Json(#[from] serde_json::Error),
+}
+
+/// The values rewritten by rotating keys.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct RotationReport {
+    pub values_resealed: u64,
+}
+
+impl std::fmt::Display for RotationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Values resealed: {}", self.values_resealed)
+    }
+}
+
+/// Where to read a hex-encoded 256-bit key from.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(try_from = "String")]
+pub enum KeySource {
+    File(PathBuf),
+    Env(String),
+    /// A shell command that prints the key (for example one that asks a KMS to decrypt a data key)
+    Command(String),
+}
+
+impl FromStr for KeySource {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once(':') {
+            Some(("file", path)) => Ok(Self::File(PathBuf::from(path))),
+            Some(("env", variable)) => Ok(Self::Env(variable.to_string())),
+            Some(("command", command)) => Ok(Self::Command(command.to_string())),
+            _ => Err(Error::InvalidSource(value.to_string())),
+        }
+    }
+}
+
+impl TryFrom<String> for KeySource {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl std::fmt::Display for KeySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "file:{}", path.display()),
+            Self::Env(variable) => write!(f, "env:{}", variable),
+            Self::Command(command) => write!(f, "command:{}", command),
+        }
+    }
+}
+
+impl KeySource {
+    pub fn load(&self) -> Result<EncryptionKey, Error> {
+        let text = match self {
+            Self::File(path) => std::fs::read_to_string(path)?,
+            Self::Env(variable) => {
+                std::env::var(variable).map_err(|_| Error::MissingVariable(variable.to_string()))?
+            }
+            Self::Command(command) => {
+                let output = std::process::Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .output()?;
+
+                if !output.status.success() {
+                    return Err(Error::KeyCommand(output.status.to_string()));
+                }
+
+                String::from_utf8(output.stdout).map_err(|_| Error::InvalidKey(self.to_string()))?
+            }
+        };
+
+        parse_hex_key(text.trim())
+            .map(EncryptionKey::new)
+            .ok_or_else(|| Error::InvalidKey(self.to_string()))
+    }
+}
+
+/// The key to seal new values with, and earlier keys that values may still be sealed with.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+pub struct EncryptionConfig {
+    pub key: Option<KeySource>,
+    #[serde(default)]
+    pub previous_keys: Vec<KeySource>,
+}
+
+impl EncryptionConfig {
+    /// Load the keys, if there's a current key.
+    pub fn load(&self) -> Result<Option<Keyring>, Error> {
+        self.key
+            .as_ref()
+            .map(|key| {
+                Ok(Keyring {
+                    current: key.load()?,
+                    previous: self
+                        .previous_keys
+                        .iter()
+                        .map(KeySource::load)
+                        .collect::<Result<_, _>>()?,
+                })
+            })
+            .transpose()
+    }
+}
+
+pub struct EncryptionKey {
+    id: [u8; KEY_ID_LEN],
+    cipher: Aes256Gcm,
+}
+
+impl EncryptionKey {
+    pub fn new(bytes: [u8; KEY_LEN]) -> Self {
+        let digest = Sha256::digest(bytes);
+
+        Self {
+            id: digest[..KEY_ID_LEN].try_into().unwrap(),
+            cipher: Aes256Gcm::new(&bytes.into()),
+        }
+    }
+
+    /// A short fingerprint of the key, which identifies it without revealing it.
+    pub fn id(&self) -> String {
+        self.id.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+/// The keys a table's values are sealed and opened with.
+pub struct Keyring {
+    current: EncryptionKey,
+    previous: Vec<EncryptionKey>,
+}
+
+impl Keyring {
+    pub fn new(current: EncryptionKey, previous: Vec<EncryptionKey>) -> Self {
+        Self { current, previous }
+    }
+
+    pub fn current_id(&self) -> String {
+        self.current.id()
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &EncryptionKey> {
+        std::iter::once(&self.current).chain(&self.previous)
+    }
+
+    /// Encrypt a value with the current key, authenticating the table key along with it.
+    pub fn seal(&self, key: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut nonce = [0; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let ciphertext = self
+            .current
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: key,
+                },
+            )
+            .map_err(|_| Error::Encryption)?;
+
+        let mut value = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+        value.push(VERSION);
+        value.extend_from_slice(&self.current.id);
+        value.extend_from_slice(&nonce);
+        value.extend_from_slice(&ciphertext);
+
+        Ok(value)
+    }
+
+    /// Decrypt a sealed value, or return a plaintext value as it is.
+    pub fn open<'a>(&self, key: &[u8], value: &'a [u8]) -> Result<Cow<'a, [u8]>, Error> {
+        if !is_sealed(value) {
+            return Ok(Cow::Borrowed(value));
+        }
+
+        let key_id = &value[1..1 + KEY_ID_LEN];
+        let encryption_key = self
+            .keys()
+            .find(|candidate| candidate.id == key_id)
+            .ok_or_else(|| Error::UnknownKey(format_key_id(key_id)))?;
+
+        encryption_key
+            .cipher
+            .decrypt(
+                Nonce::from_slice(&value[1 + KEY_ID_LEN..HEADER_LEN]),
+                Payload {
+                    msg: &value[HEADER_LEN..],
+                    aad: key,
+                },
+            )
+            .map(Cow::Owned)
+            .map_err(|_| Error::Decryption)
+    }
+
+    /// Check that every key the database's values may be sealed with is available, and (when
+    /// opening it for writing) record that new values will be sealed with the current key.
+    pub(crate) fn check(keyring: Option<&Self>, base: &Path, writeable: bool) -> Result<(), Error> {
+        let stored = StoredKeys::load(base)?;
+
+        match keyring {
+            Some(keyring) => {
+                let ids = keyring
+                    .keys()
+                    .map(EncryptionKey::id)
+                    .collect::<BTreeSet<_>>();
+
+                if let Some(missing) = stored
+                    .iter()
+                    .flat_map(|stored| &stored.key_ids)
+                    .find(|id| !ids.contains(*id))
+                {
+                    return Err(Error::UnknownKey(missing.clone()));
+                }
+
+                if writeable {
+                    let mut key_ids = stored.map(|stored| stored.key_ids).unwrap_or_default();
+
+                    if key_ids.insert(keyring.current_id()) {
+                        StoredKeys { key_ids }.save(base)?;
+                    }
+                }
+
+                Ok(())
+            }
+            None if stored.is_some_and(|stored| !stored.key_ids.is_empty()) => {
+                Err(Error::KeyRequired)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Record that every value is now sealed with the current key.
+    pub(crate) fn save_rotated(&self, base: &Path) -> Result<(), Error> {
+        StoredKeys {
+            key_ids: BTreeSet::from([self.current_id()]),
+        }
+        .save(base)
+    }
+}
+
+// Key material is never printed
+impl std::fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Keyring")
+            .field("current", &self.current.id())
+            .field(
+                "previous",
+                &self
+                    .previous
+                    .iter()
+                    .map(EncryptionKey::id)
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl PartialEq for Keyring {
+    fn eq(&self, other: &Self) -> bool {
+        self.keys()
+            .map(|key| key.id)
+            .eq(other.keys().map(|key| key.id))
+    }
+}
+
+/// The contents of [`ENCRYPTION_FILE`].
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct StoredKeys {
+    key_ids: BTreeSet<String>,
+}
+
+impl StoredKeys {
+    fn load(base: &Path) -> Result<Option<Self>, Error> {
+        let path = base.join(ENCRYPTION_FILE);
+
+        if path.exists() {
+            Ok(Some(serde_json::from_slice(&std::fs::read(path)?)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Written atomically, since a reader that can't parse it would refuse to open the database.
+    fn save(&self, base: &Path) -> Result<(), Error> {
+        let path = base.join(ENCRYPTION_FILE);
+        let temporary = path.with_extension("json.tmp");
+        std::fs::write(&temporary, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(temporary, path)?;
+
+        Ok(())
+    }
+}
+
+pub(crate) fn is_sealed(value: &[u8]) -> bool {
+    value.len() >= HEADER_LEN && value.len() % 2 == 1 && value[0] == VERSION
+}
+
+fn format_key_id(id: &[u8]) -> String {
+    id.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn parse_hex_key(text: &str) -> Option<[u8; KEY_LEN]> {
+    if text.len() != 2 * KEY_LEN || !text.is_ascii() {
+        return None;
+    }
+
+    let mut bytes = [0; KEY_LEN];
+
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[2 * i..2 * i + 2], 16).ok()?;
+    }
+
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixture::date;
+
+    #[test]
+    fn seal_and_open() {
+        let old = Keyring::new(EncryptionKey::new([1; KEY_LEN]), vec![]);
+        let keyring = Keyring::new(
+            EncryptionKey::new([2; KEY_LEN]),
+            vec![EncryptionKey::new([1; KEY_LEN])],
+        );
+
+        let sealed = old.seal(b"pair", &[0, 1, 0, 2]).unwrap();
+        assert!(is_sealed(&sealed));
+        assert_eq!(keyring.open(b"pair", &sealed).unwrap(), &[0, 1, 0, 2][..]);
+        // The value is bound to its key
+        assert!(matches!(
+            keyring.open(b"other", &sealed),
+            Err(Error::Decryption)
+        ));
+        // Plaintext values are passed through
+        assert_eq!(keyring.open(b"pair", &[0, 3]).unwrap(), &[0, 3][..]);
+
+        let unknown = Keyring::new(EncryptionKey::new([3; KEY_LEN]), vec![]);
+        assert!(matches!(
+            unknown.open(b"pair", &sealed),
+            Err(Error::UnknownKey(_))
+        ));
+
+        assert_eq!(
+            "env:MEMORY_LOL_KEY".parse::<KeySource>().unwrap(),
+            KeySource::Env("MEMORY_LOL_KEY".to_string())
+        );
+        assert!("MEMORY_LOL_KEY".parse::<KeySource>().is_err());
+        assert_eq!(parse_hex_key(&"0f".repeat(KEY_LEN)), Some([15; KEY_LEN]));
+        assert_eq!(parse_hex_key("0f"), None);
+    }
+
+    #[test]
+    fn encrypted_database() {
+        use crate::db::{Database, DatabaseOptions, ReadOnly, Writeable};
+
+        let dir = tempfile::tempdir().unwrap();
+        let options = |current, previous: &[u8]| DatabaseOptions {
+            encryption: Some(std::sync::Arc::new(Keyring::new(
+                EncryptionKey::new([current; 32]),
+                previous
+                    .iter()
+                    .map(|byte| EncryptionKey::new([*byte; 32]))
+                    .collect(),
+            ))),
+            ..Default::default()
+        };
+
+        // Written in the clear, and then with a key
+        let db = Database::<Writeable>::open(dir.path()).unwrap();
+        db.insert(123, "foo", vec![date(1)]).unwrap();
+        drop(db);
+
+        let db = Database::<Writeable>::open_with_options(dir.path(), &options(1, &[])).unwrap();
+        db.insert(123, "foo", vec![date(2)]).unwrap();
+        db.insert(456, "bar", vec![date(3)]).unwrap();
+        drop(db);
+
+        assert!(matches!(
+            Database::<ReadOnly>::open(dir.path()),
+            Err(crate::db::Error::Encryption(Error::KeyRequired))
+        ));
+
+        let db = Database::<Writeable>::open_with_options(dir.path(), &options(2, &[1])).unwrap();
+        assert_eq!(
+            db.accounts.get(123, "foo").unwrap(),
+            Some(vec![date(1), date(2)])
+        );
+
+        assert_eq!(db.rotate_key(dir.path()).unwrap().values_resealed, 2);
+        drop(db);
+
+        // The earlier key is no longer needed
+        assert!(matches!(
+            Database::<ReadOnly>::open_with_options(dir.path(), &options(1, &[])),
+            Err(crate::db::Error::Encryption(Error::UnknownKey(_)))
+        ));
+        let db = Database::<ReadOnly>::open_with_options(dir.path(), &options(2, &[])).unwrap();
+        assert_eq!(db.lookup_by_screen_name("bar").unwrap(), vec![456]);
+        assert_eq!(
+            db.accounts.pairs().collect::<Result<Vec<_>, _>>().unwrap(),
+            vec![
+                (123, "foo".to_string(), vec![date(1), date(2)]),
+                (456, "bar".to_string(), vec![date(3)])
+            ]
+        );
+    }
+}
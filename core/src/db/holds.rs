@@ -0,0 +1,158 @@
+use super::{
+    options::DatabaseOptions,
+    table::{Mode, Table, Writeable},
+    Error,
+};
+use chrono::{DateTime, Utc};
+use rocksdb::{IteratorMode, Options, DB};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::marker::PhantomData;
+use std::path::Path;
+
+/// A preservation requirement on an account.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct LegalHold {
+    pub placed: DateTime<Utc>,
+    pub reason: String,
+}
+
+/// Account IDs under legal hold, whose records must not be deleted or pruned until the hold is
+/// released.
+pub struct HoldTable<M> {
+    db: DB,
+    mode: PhantomData<M>,
+}
+
+impl<M> Table for HoldTable<M> {
+    type Counts = u64;
+
+    fn underlying(&self) -> &DB {
+        &self.db
+    }
+
+    fn get_counts(&self) -> Result<Self::Counts, Error> {
+        let mut count = 0;
+
+        for result in self.db.iterator(IteratorMode::Start) {
+            result?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+}
+
+impl<M> HoldTable<M> {
+    pub fn get(&self, id: u64) -> Result<Option<LegalHold>, Error> {
+        self.db
+            .get_pinned(id.to_be_bytes())?
+            .map(|value| Ok(serde_json::from_slice(&value)?))
+            .transpose()
+    }
+
+    /// All holds, ordered by account ID.
+    pub fn holds(&self) -> Result<Vec<(u64, LegalHold)>, Error> {
+        let mut results = vec![];
+
+        for result in self.db.iterator(IteratorMode::Start) {
+            let (key, value) = result?;
+            results.push((key_to_id(&key)?, serde_json::from_slice(&value)?));
+        }
+
+        Ok(results)
+    }
+
+    pub fn ids(&self) -> Result<HashSet<u64>, Error> {
+        let mut ids = HashSet::new();
+
+        for result in self.db.iterator(IteratorMode::Start) {
+            let (key, _) = result?;
+            ids.insert(key_to_id(&key)?);
+        }
+
+        Ok(ids)
+    }
+}
+
+impl<M: Mode> HoldTable<M> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::open_with_options(path, &DatabaseOptions::default())
+    }
+
+    pub fn open_with_options<P: AsRef<Path>>(
+        path: P,
+        database_options: &DatabaseOptions,
+    ) -> Result<Self, Error> {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        database_options.apply(&mut options);
+
+        let db = if M::is_read_only() {
+            DB::open_for_read_only(&options, path, true)?
+        } else {
+            DB::open(&options, path)?
+        };
+
+        Ok(Self {
+            db,
+            mode: PhantomData,
+        })
+    }
+}
+
+impl HoldTable<Writeable> {
+    /// Place a hold on an account, replacing any existing hold.
+    pub fn place(&self, id: u64, hold: &LegalHold) -> Result<(), Error> {
+        Ok(self.db.put(id.to_be_bytes(), serde_json::to_vec(hold)?)?)
+    }
+
+    /// Release the hold on an account, returning whether there was one.
+    pub fn release(&self, id: u64) -> Result<bool, Error> {
+        let held = self.db.get_pinned(id.to_be_bytes())?.is_some();
+
+        if held {
+            self.db.delete(id.to_be_bytes())?;
+        }
+
+        Ok(held)
+    }
+}
+
+fn key_to_id(key: &[u8]) -> Result<u64, Error> {
+    Ok(u64::from_be_bytes(
+        key.try_into()
+            .map_err(|_| Error::InvalidKey(key.to_vec()))?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixture;
+
+    #[test]
+    fn holds() {
+        use chrono::{TimeZone, Utc};
+        use {HoldTable, LegalHold};
+
+        let (_dir, holds) = fixture::open(HoldTable::<Writeable>::open);
+        let hold = |reason: &str| LegalHold {
+            placed: Utc.timestamp_opt(1000, 0).unwrap(),
+            reason: reason.to_string(),
+        };
+
+        holds.place(123, &hold("case 1")).unwrap();
+        holds.place(456, &hold("case 2")).unwrap();
+        holds.place(123, &hold("case 3")).unwrap();
+
+        assert_eq!(holds.get(123).unwrap(), Some(hold("case 3")));
+        assert_eq!(holds.get(789).unwrap(), None);
+        assert!(holds.release(456).unwrap());
+        assert!(!holds.release(456).unwrap());
+        assert_eq!(holds.holds().unwrap(), vec![(123, hold("case 3"))]);
+        assert_eq!(holds.ids().unwrap(), HashSet::from([123]));
+        assert_eq!(holds.get_counts().unwrap(), 1);
+    }
+}
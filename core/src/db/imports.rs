@@ -0,0 +1,168 @@
+use super::{
+    options::DatabaseOptions,
+    table::{Mode, Table, Writeable},
+    Error,
+};
+use crate::model::ImportRecord;
+use rocksdb::{IteratorMode, Options, DB};
+use std::marker::PhantomData;
+use std::path::Path;
+
+/// A ledger of completed imports, ordered by completion time.
+pub struct ImportTable<M> {
+    db: DB,
+    mode: PhantomData<M>,
+}
+
+impl<M> Table for ImportTable<M> {
+    type Counts = u64;
+
+    fn underlying(&self) -> &DB {
+        &self.db
+    }
+
+    fn get_counts(&self) -> Result<Self::Counts, Error> {
+        let mut count = 0;
+
+        for result in self.db.iterator(IteratorMode::Start) {
+            result?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+}
+
+impl<M> ImportTable<M> {
+    pub fn records(&self) -> Result<Vec<ImportRecord>, Error> {
+        self.db
+            .iterator(IteratorMode::Start)
+            .map(|result| {
+                let (_, value) = result?;
+                value_to_record(&value)
+            })
+            .collect()
+    }
+
+    /// Find the most recent import of content with the given hash.
+    pub fn find_by_hash(&self, hash: &str) -> Result<Option<ImportRecord>, Error> {
+        for result in self.db.iterator(IteratorMode::End) {
+            let (_, value) = result?;
+            let record = value_to_record(&value)?;
+
+            if record.hash.as_deref() == Some(hash) {
+                return Ok(Some(record));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl<M: Mode> ImportTable<M> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::open_with_options(path, &DatabaseOptions::default())
+    }
+
+    pub fn open_with_options<P: AsRef<Path>>(
+        path: P,
+        database_options: &DatabaseOptions,
+    ) -> Result<Self, Error> {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        database_options.apply(&mut options);
+
+        let db = if M::is_read_only() {
+            DB::open_for_read_only(&options, path, true)?
+        } else {
+            DB::open(&options, path)?
+        };
+
+        Ok(Self {
+            db,
+            mode: PhantomData,
+        })
+    }
+}
+
+impl ImportTable<Writeable> {
+    pub fn add(&self, record: &ImportRecord) -> Result<(), Error> {
+        let value = serde_json::to_vec(record)?;
+
+        Ok(self.db.put(record_to_key(record), value)?)
+    }
+
+    /// Remove every record of imports of content with the given hash, returning how many there
+    /// were.
+    pub fn remove_by_hash(&self, hash: &str) -> Result<usize, Error> {
+        let mut removed = 0;
+
+        for result in self.db.iterator(IteratorMode::Start) {
+            let (key, value) = result?;
+
+            if value_to_record(&value)?.hash.as_deref() == Some(hash) {
+                self.db.delete(key)?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+fn record_to_key(record: &ImportRecord) -> Vec<u8> {
+    let source_bytes = record.source.as_bytes();
+    let mut key = Vec::with_capacity(8 + source_bytes.len());
+    key.extend_from_slice(&record.timestamp.timestamp_millis().to_be_bytes());
+    key.extend_from_slice(source_bytes);
+    key
+}
+
+fn value_to_record(value: &[u8]) -> Result<ImportRecord, Error> {
+    Ok(serde_json::from_slice(value)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixture;
+
+    #[test]
+    fn imports() {
+        use chrono::{TimeZone, Utc};
+
+        let (_dir, imports) = fixture::open(ImportTable::<Writeable>::open);
+        let record = |seconds, source: &str, hash: Option<&str>| crate::model::ImportRecord {
+            timestamp: Utc.timestamp_opt(seconds, 0).unwrap(),
+            source: source.to_string(),
+            hash: hash.map(|hash| hash.to_string()),
+            lines_read: 10,
+            lines_skipped: 1,
+            pairs: 5,
+            new_pairs: 2,
+            new_accounts: 1,
+            dates_added: 3,
+            duration_ms: 100,
+            version: "0.0.0".to_string(),
+        };
+
+        imports.add(&record(2000, "b.ndjson", Some("abc"))).unwrap();
+        imports.add(&record(1000, "a.ndjson", Some("abc"))).unwrap();
+        imports.add(&record(3000, "stdin", None)).unwrap();
+
+        assert_eq!(
+            imports.records().unwrap(),
+            vec![
+                record(1000, "a.ndjson", Some("abc")),
+                record(2000, "b.ndjson", Some("abc")),
+                record(3000, "stdin", None)
+            ]
+        );
+        assert_eq!(
+            imports.find_by_hash("abc").unwrap(),
+            Some(record(2000, "b.ndjson", Some("abc")))
+        );
+        assert_eq!(imports.find_by_hash("def").unwrap(), None);
+        assert_eq!(imports.get_counts().unwrap(), 3);
+    }
+}
@@ -0,0 +1,136 @@
+use super::Error;
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// File in the database directory where a query server publishes its recent latency, so that
+/// imports into the same database can back off when queries slow down.
+pub const QUERY_LATENCY_FILE: &str = "query-latency.json";
+
+/// How often a [`LatencyRecorder`] publishes by default.
+pub const DEFAULT_PUBLISH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The latency of the queries served during the last publishing interval.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct QueryLatency {
+    pub p99_us: u64,
+    pub queries: usize,
+    pub updated: DateTime<Utc>,
+}
+
+impl QueryLatency {
+    /// Read the latency published in a database directory, or `None` if none has been.
+    pub fn load<P: AsRef<Path>>(base: P) -> Result<Option<Self>, Error> {
+        let path = base.as_ref().join(QUERY_LATENCY_FILE);
+
+        if path.exists() {
+            Ok(Some(serde_json::from_slice(&std::fs::read(path)?)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Write the latency to a temporary file and then rename it, so that readers never see a
+    /// partly written file.
+    pub fn save<P: AsRef<Path>>(&self, base: P) -> Result<(), Error> {
+        let path = base.as_ref().join(QUERY_LATENCY_FILE);
+        let temporary = path.with_extension("json.tmp");
+
+        std::fs::write(&temporary, serde_json::to_vec_pretty(self)?)?;
+        Ok(std::fs::rename(temporary, path)?)
+    }
+
+    pub fn p99(&self) -> Duration {
+        Duration::from_micros(self.p99_us)
+    }
+
+    /// Whether the latency was published recently enough to reflect a running server.
+    pub fn is_current(&self, max_age: Duration) -> bool {
+        (Utc::now() - self.updated)
+            .to_std()
+            .map_or(true, |age| age <= max_age)
+    }
+}
+
+/// Collects the latencies of the queries a server handles, and periodically publishes their p99
+/// in [`QUERY_LATENCY_FILE`].
+pub struct LatencyRecorder {
+    base: PathBuf,
+    interval: Duration,
+    /// The latencies since the last publication, and when it happened
+    state: Mutex<(Vec<Duration>, Instant)>,
+}
+
+impl LatencyRecorder {
+    pub fn new<P: AsRef<Path>>(base: P, interval: Duration) -> Self {
+        Self {
+            base: base.as_ref().to_path_buf(),
+            interval,
+            state: Mutex::new((vec![], Instant::now())),
+        }
+    }
+
+    /// Record a query's latency, publishing if the interval has passed.
+    ///
+    /// Publishing failures are logged rather than returned, since they shouldn't fail queries.
+    pub fn record(&self, latency: Duration) {
+        let mut state = self
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (latencies, published) = &mut *state;
+        latencies.push(latency);
+
+        if published.elapsed() >= self.interval {
+            let latency = summarize(std::mem::take(latencies));
+            *published = Instant::now();
+
+            if let Err(error) = latency.save(&self.base) {
+                log::warn!("Failed to publish query latency: {}", error);
+            }
+        }
+    }
+}
+
+fn summarize(mut latencies: Vec<Duration>) -> QueryLatency {
+    latencies.sort_unstable();
+
+    let index = ((latencies.len() as f64 * 0.99).ceil() as usize).saturating_sub(1);
+
+    QueryLatency {
+        p99_us: latencies
+            .get(index)
+            .map_or(0, |latency| latency.as_micros() as u64),
+        queries: latencies.len(),
+        updated: Utc::now(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn publish() {
+        let dir = tempfile::tempdir().unwrap();
+        let recorder = LatencyRecorder::new(dir.path(), Duration::ZERO);
+        assert_eq!(QueryLatency::load(dir.path()).unwrap(), None);
+
+        for millis in 1..=200 {
+            recorder
+                .state
+                .lock()
+                .unwrap()
+                .0
+                .push(Duration::from_millis(millis));
+        }
+        recorder.record(Duration::from_millis(300));
+
+        let latency = QueryLatency::load(dir.path()).unwrap().unwrap();
+        assert_eq!(latency.queries, 201);
+        assert_eq!(latency.p99(), Duration::from_millis(199));
+        assert!(latency.is_current(Duration::from_secs(60)));
+    }
+}
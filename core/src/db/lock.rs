@@ -0,0 +1,86 @@
+use super::Error;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+
+/// File in the database directory that is locked by the process writing to the database.
+pub const WRITER_LOCK_FILE: &str = "writer.lock";
+
+/// An exclusive lock on a database directory, held for as long as a writeable database is open.
+///
+/// The lock is an advisory lock on [`WRITER_LOCK_FILE`], which also records the ID of the process
+/// holding it. The operating system releases it when the process exits, so a crashed writer never
+/// leaves the database locked.
+#[derive(Debug)]
+pub struct WriterLock {
+    _file: File,
+}
+
+impl WriterLock {
+    /// Lock a database directory for writing.
+    ///
+    /// If another process holds the lock, either wait for it to be released (so that writers run
+    /// one after another) or fail with [`Error::WriterActive`].
+    pub fn acquire<P: AsRef<Path>>(base: P, wait: bool) -> Result<Self, Error> {
+        let base = base.as_ref();
+        std::fs::create_dir_all(base)?;
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(base.join(WRITER_LOCK_FILE))?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) if wait => {
+                log::info!(
+                    "Waiting for the active writer (process {}) to finish",
+                    holder(&mut file)
+                );
+                file.lock()?;
+            }
+            Err(TryLockError::WouldBlock) => return Err(Error::WriterActive(holder(&mut file))),
+            Err(TryLockError::Error(error)) => return Err(error.into()),
+        }
+
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", std::process::id())?;
+        file.flush()?;
+
+        Ok(Self { _file: file })
+    }
+}
+
+/// A description of the process holding the lock, from the lock file.
+fn holder(file: &mut File) -> String {
+    let mut contents = String::new();
+
+    match file.read_to_string(&mut contents) {
+        Ok(_) if !contents.trim().is_empty() => contents.trim().to_string(),
+        _ => "unknown".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock = WriterLock::acquire(dir.path(), false).unwrap();
+
+        match WriterLock::acquire(dir.path(), false) {
+            Err(Error::WriterActive(holder)) => {
+                assert_eq!(holder, std::process::id().to_string())
+            }
+            other => panic!("Expected a locked database, got {:?}", other),
+        }
+
+        drop(lock);
+        assert!(WriterLock::acquire(dir.path(), false).is_ok());
+    }
+}
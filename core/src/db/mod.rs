@@ -1,16 +1,58 @@
 pub mod accounts;
+pub mod aggregates;
+pub mod bulk;
+pub mod changes;
+pub mod codec;
+pub mod cohorts;
+pub mod conflicts;
+pub mod edges;
+pub mod encryption;
+pub mod holds;
+pub mod imports;
+pub mod latency;
+pub mod lock;
+pub mod namespaces;
+pub mod ngrams;
+pub mod notes;
+pub mod options;
+pub mod phonetic;
+pub mod quarantine;
+pub mod retention;
+pub mod retry;
 pub mod screen_names;
+pub mod shards;
+pub mod slowlog;
+pub mod stats;
 pub mod table;
+pub mod tags;
+pub mod tiers;
 pub mod util;
+pub mod weights;
 
+use crate::ranges::DateRanges;
 use accounts::AccountTable;
 use chrono::NaiveDate;
+pub use options::{DatabaseOptions, SharedCache};
 use screen_names::ScreenNameTable;
-use std::collections::HashMap;
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 pub use table::{Mode, ReadOnly, Table, Writeable};
 
+/// Directory names of the tables within a database directory.
+pub const ACCOUNTS_TABLE: &str = "accounts";
+pub const SCREEN_NAMES_TABLE: &str = "screen-names";
+pub const CHANGES_TABLE: &str = "changes";
+pub const EDGES_TABLE: &str = "edges";
+pub const HOLDS_TABLE: &str = "holds";
+pub const IMPORTS_TABLE: &str = "imports";
+pub const NGRAMS_TABLE: &str = "ngrams";
+pub const NOTES_TABLE: &str = "notes";
+pub const PHONETIC_TABLE: &str = "phonetic";
+pub const STATS_TABLE: &str = "stats";
+pub const TAGS_TABLE: &str = "tags";
+pub const WEIGHTS_TABLE: &str = "weights";
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("RocksDb error")]
@@ -25,15 +67,62 @@ pub enum Error {
     InvalidDay(i64),
     #[error("Invalid Twitter screen name")]
     InvalidScreenName(String),
+    #[error("Invalid tag")]
+    InvalidTag(String),
+    #[error("Invalid privacy budget")]
+    InvalidEpsilon(f64),
+    #[error("Too many confusable variants")]
+    TooManyVariants(usize),
+    #[error("Invalid edge kind")]
+    InvalidEdgeKind(String),
+    #[error("Invalid namespace name")]
+    InvalidNamespace(String),
+    #[error("Database is locked by another writer (process {0})")]
+    WriterActive(String),
+    #[error("Bulk loading requires an empty database")]
+    NotEmpty,
+    #[error("Snapshot doesn't match its ID")]
+    CorruptSnapshot(String),
+    #[error("Snapshot store already holds {0} snapshots")]
+    SnapshotLimit(usize),
+    #[error("Database write still failing after {0} attempts")]
+    RetriesExhausted(u32, #[source] Box<Error>),
+    #[error("JSON error")]
+    Json(#[from] serde_json::Error),
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("Encryption error")]
+    Encryption(#[from] encryption::Error),
     #[error("Channel send error")]
     ChannelSend,
     #[error("Channel receive error")]
     ChannelRecv(#[from] std::sync::mpsc::RecvError),
 }
 
+/// The effect of recording a single observation.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ObservationOutcome {
+    /// The pair had not been observed before
+    New,
+    /// The pair was known and the date was added to its dates
+    Extended,
+    /// The date was already recorded for the pair
+    Unchanged,
+    /// The screen name is not valid, so the observation was not written
+    Invalid,
+}
+
 pub struct Database<M> {
     pub accounts: Arc<AccountTable<M>>,
     pub screen_names: ScreenNameTable<M>,
+    /// Substring index, which is only used and maintained if it has been built
+    pub ngrams: Option<ngrams::NgramTable<M>>,
+    /// Phonetic index, which is only used and maintained if it has been built
+    pub phonetic: Option<phonetic::PhoneticTable<M>>,
+    /// Held while the database is open for writing
+    _writer_lock: Option<lock::WriterLock>,
+    /// Applied to writes that fail with a transient error
+    retry: retry::RetryPolicy,
 }
 
 impl<M: Sync + Send + 'static> Database<M> {
@@ -60,6 +149,7 @@ impl<M: Sync + Send + 'static> Database<M> {
         Ok((account_counts, screen_name_counts))
     }
 
+    #[tracing::instrument(name = "db.lookup_by_user_id", skip(self))]
     pub fn lookup_by_user_id(
         &self,
         user_id: u64,
@@ -67,10 +157,39 @@ impl<M: Sync + Send + 'static> Database<M> {
         self.accounts.lookup(user_id)
     }
 
+    /// Like [`Database::lookup_by_user_id`], but with each screen name and its dates borrowed
+    /// from the database instead of collected into a map (see [`AccountTable::visit`]).
+    #[tracing::instrument(name = "db.visit_user_id", skip(self, visit))]
+    pub fn visit_user_id<F: FnMut(&str, codec::DayIds<'_>)>(
+        &self,
+        user_id: u64,
+        visit: F,
+    ) -> Result<(), Error> {
+        self.accounts.visit(user_id, visit)
+    }
+
+    /// Like [`Database::lookup_by_user_id`], but with each screen name's dates as runs of
+    /// consecutive days (which take far less memory for accounts observed daily).
+    #[tracing::instrument(name = "db.lookup_ranges_by_user_id", skip(self))]
+    pub fn lookup_ranges_by_user_id(
+        &self,
+        user_id: u64,
+    ) -> Result<HashMap<String, DateRanges>, Error> {
+        let mut results = HashMap::new();
+
+        self.accounts.visit(user_id, |screen_name, dates| {
+            results.insert(screen_name.to_string(), dates.to_ranges());
+        })?;
+
+        Ok(results)
+    }
+
+    #[tracing::instrument(name = "db.lookup_by_screen_name", skip(self))]
     pub fn lookup_by_screen_name(&self, screen_name: &str) -> Result<Vec<u64>, Error> {
         self.screen_names.lookup(screen_name)
     }
 
+    #[tracing::instrument(name = "db.lookup_by_screen_name_prefix", skip(self))]
     pub fn lookup_by_screen_name_prefix(
         &self,
         screen_name_prefix: &str,
@@ -80,6 +199,105 @@ impl<M: Sync + Send + 'static> Database<M> {
             .lookup_by_prefix(screen_name_prefix, limit)
     }
 
+    /// Find screen names that sound like a query, ranked by similarity.
+    pub fn similar_screen_names(
+        &self,
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<phonetic::SimilarScreenName>, Error> {
+        phonetic::similar(&self.screen_names, self.phonetic.as_ref(), query, limit)
+    }
+
+    /// Find screen names within an edit distance of a screen name (for example to enumerate
+    /// typosquatting variants of a handle), ranked by similarity.
+    pub fn screen_names_within_distance(
+        &self,
+        screen_name: &str,
+        max_distance: usize,
+    ) -> Result<Vec<phonetic::SimilarScreenName>, Error> {
+        let mut results = self
+            .screen_names
+            .within_distance(screen_name, max_distance)?
+            .into_iter()
+            .map(
+                |(screen_name, user_ids, distance)| phonetic::SimilarScreenName {
+                    screen_name,
+                    user_ids,
+                    distance,
+                },
+            )
+            .collect::<Vec<_>>();
+
+        phonetic::SimilarScreenName::rank(&mut results);
+
+        Ok(results)
+    }
+
+    /// Find screen names that are visually confusable with a handle (including the handle itself).
+    ///
+    /// The handle may contain Unicode homoglyphs. Only variants that have been used by at least
+    /// one account are returned, in sorted order.
+    #[tracing::instrument(name = "db.lookup_confusable", skip(self))]
+    pub fn lookup_confusable(
+        &self,
+        handle: &str,
+        max_variants: usize,
+    ) -> Result<Vec<(String, Vec<u64>)>, Error> {
+        let screen_name = crate::confusables::normalize(handle)
+            .ok_or_else(|| Error::InvalidScreenName(handle.to_string()))?;
+        let variant_count = crate::confusables::variant_count(&screen_name);
+
+        if variant_count > max_variants {
+            return Err(Error::TooManyVariants(variant_count));
+        }
+
+        let mut results = vec![];
+
+        for variant in crate::confusables::variants(&screen_name) {
+            let user_ids = self.screen_names.lookup(&variant)?;
+
+            if !user_ids.is_empty() {
+                results.push((variant, user_ids));
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Find conflicting observations, ranked by severity, keeping only the most severe if there
+    /// is a limit.
+    pub fn find_conflicts(&self, limit: Option<usize>) -> Result<Vec<conflicts::Conflict>, Error> {
+        let mut results = conflicts::Conflicts::new(limit);
+        conflicts::find_screen_name_conflicts(&self.accounts, &mut results)?;
+        conflicts::find_id_conflicts(&self.accounts, &self.screen_names, &mut results)?;
+
+        Ok(results.into_ranked())
+    }
+
+    /// Compute noisy aggregate statistics that are safe to publish.
+    pub fn aggregates<R: rand::Rng>(
+        &self,
+        options: &aggregates::AggregateOptions,
+        rng: &mut R,
+    ) -> Result<aggregates::Aggregates, Error> {
+        aggregates::Aggregates::compute(&self.accounts, options, rng)
+    }
+
+    /// Group accounts by the period they were first observed in, with their exact rename counts.
+    pub fn cohorts(&self, options: &cohorts::CohortOptions) -> Result<cohorts::Cohorts, Error> {
+        cohorts::Cohorts::compute(&self.accounts, options)
+    }
+
+    /// Find screen names containing a substring or matching a `*` wildcard pattern.
+    pub fn search_screen_names(
+        &self,
+        pattern: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, Vec<u64>)>, Error> {
+        ngrams::search(&self.screen_names, self.ngrams.as_ref(), pattern, limit)
+    }
+
+    #[tracing::instrument(name = "db.limited_lookup_by_user_id", skip(self))]
     pub fn limited_lookup_by_user_id(
         &self,
         user_id: u64,
@@ -94,44 +312,231 @@ impl<M: Sync + Send + 'static> Database<M> {
 
 impl<M: Mode> Database<M> {
     pub fn open<P: AsRef<Path>>(base: P) -> Result<Self, Error> {
-        Self::open_from_tables(
-            base.as_ref().join("accounts"),
-            base.as_ref().join("screen-names"),
-        )
+        Self::open_with_options(base, &DatabaseOptions::default())
     }
 
-    fn open_from_tables<P: AsRef<Path>>(
-        accounts_path: P,
-        screen_names_path: P,
+    /// Open the database, applying any compression settings stored in its directory.
+    pub fn open_with_options<P: AsRef<Path>>(
+        base: P,
+        options: &DatabaseOptions,
     ) -> Result<Self, Error> {
+        let options = options.with_stored_compression(&base)?;
+
+        let base = base.as_ref();
+
+        // Taken before any table is opened, so that a second writer gets a clear error (or waits)
+        let writer_lock = if M::is_read_only() {
+            None
+        } else {
+            Some(lock::WriterLock::acquire(base, options.wait_for_writer)?)
+        };
+
+        encryption::Keyring::check(options.encryption.as_deref(), base, !M::is_read_only())?;
+
         Ok(Self {
-            accounts: Arc::new(AccountTable::open(accounts_path)?),
-            screen_names: ScreenNameTable::open(screen_names_path)?,
+            accounts: Arc::new(AccountTable::open_with_options(
+                base.join(ACCOUNTS_TABLE),
+                &options,
+            )?),
+            screen_names: ScreenNameTable::open_with_options(
+                base.join(SCREEN_NAMES_TABLE),
+                &options,
+            )?,
+            ngrams: open_if_exists(base.join(NGRAMS_TABLE), |path| {
+                ngrams::NgramTable::open_with_options(path, &options)
+            })?,
+            phonetic: open_if_exists(base.join(PHONETIC_TABLE), |path| {
+                phonetic::PhoneticTable::open_with_options(path, &options)
+            })?,
+            _writer_lock: writer_lock,
+            retry: options.retry_policy(),
         })
     }
 }
 
+/// Open an optional table, if it has been created.
+fn open_if_exists<T, F: FnOnce(&Path) -> Result<T, Error>>(
+    path: PathBuf,
+    open: F,
+) -> Result<Option<T>, Error> {
+    if path.exists() {
+        open(&path).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
 impl Database<Writeable> {
+    /// Write a pair, retrying if the write fails with a transient error (every table's write is
+    /// idempotent, so repeating a partly completed insert is safe).
     pub fn insert(&self, id: u64, screen_name: &str, dates: Vec<NaiveDate>) -> Result<(), Error> {
-        self.accounts.insert(id, screen_name, dates)?;
-        self.screen_names.insert(screen_name, id)?;
-        Ok(())
+        self.retry.run(|| {
+            self.accounts.insert(id, screen_name, dates.clone())?;
+            self.screen_names.insert(screen_name, id)?;
+
+            if let Some(ngrams) = &self.ngrams {
+                ngrams.insert(screen_name)?;
+            }
+
+            if let Some(phonetic) = &self.phonetic {
+                phonetic.insert(screen_name)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Record a single observation and report how it changed the database.
+    pub fn insert_observation(
+        &self,
+        id: u64,
+        screen_name: &str,
+        date: NaiveDate,
+    ) -> Result<ObservationOutcome, Error> {
+        if !util::is_valid_screen_name(screen_name) {
+            log::debug!("Rejected observation: {}, {}", id, screen_name);
+            return Ok(ObservationOutcome::Invalid);
+        }
+
+        // Only an exact repeat is skipped (keeping pairs imported in range mode compact is up to
+        // the import, which knows its update mode)
+        let outcome = match self.accounts.get(id, screen_name)? {
+            None => ObservationOutcome::New,
+            Some(dates) if dates.binary_search(&date).is_ok() => ObservationOutcome::Unchanged,
+            Some(_) => ObservationOutcome::Extended,
+        };
+
+        if outcome != ObservationOutcome::Unchanged {
+            self.insert(id, screen_name, vec![date])?;
+        }
+
+        Ok(outcome)
+    }
+
+    /// Whether RocksDB is currently slowing down or stopping writes to the account or screen name
+    /// table (because compaction has fallen behind, for example).
+    pub fn write_stalled(&self) -> Result<bool, Error> {
+        for db in [self.accounts.underlying(), self.screen_names.underlying()] {
+            let stopped = db.property_int_value("rocksdb.is-write-stopped")?;
+            let delayed_rate = db.property_int_value("rocksdb.actual-delayed-write-rate")?;
+
+            if stopped.unwrap_or(0) > 0 || delayed_rate.unwrap_or(0) > 0 {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Write every table's in-memory data to disk (before stopping part-way through an import,
+    /// for example).
+    pub fn flush(&self) -> Result<(), Error> {
+        self.retry.run(|| {
+            self.accounts.underlying().flush()?;
+            self.screen_names.underlying().flush()?;
+
+            if let Some(ngrams) = &self.ngrams {
+                ngrams.underlying().flush()?;
+            }
+
+            if let Some(phonetic) = &self.phonetic {
+                phonetic.underlying().flush()?;
+            }
+
+            Ok(())
+        })
     }
 
     pub fn rebuild_index(&mut self) -> Result<(), Error> {
         self.screen_names.rebuild(&self.accounts)
     }
+
+    /// Seal every account table value with the current encryption key, and compact the table so
+    /// that no values sealed with earlier keys (or stored in the clear) remain on disk.
+    ///
+    /// Once this finishes, the database can be opened without the earlier keys.
+    pub fn rotate_key<P: AsRef<Path>>(&self, base: P) -> Result<encryption::RotationReport, Error> {
+        let report = self.accounts.reseal()?;
+        self.accounts.compact();
+
+        if let Some(keyring) = self.accounts.keyring() {
+            keyring.save_rotated(base.as_ref())?;
+        }
+
+        Ok(report)
+    }
+
+    /// Apply a retention policy (and its cutoff to the weight table if one is given) to every
+    /// account that isn't held, compacting the tables that were changed.
+    pub fn prune(
+        &self,
+        weights: Option<&weights::WeightTable<Writeable>>,
+        policy: &retention::RetentionPolicy,
+        held: &HashSet<u64>,
+        dry_run: bool,
+    ) -> Result<retention::PruneReport, Error> {
+        let report = retention::prune(
+            &self.accounts,
+            &self.screen_names,
+            weights,
+            policy,
+            held,
+            dry_run,
+        )?;
+
+        if !dry_run {
+            if report.pairs_rewritten > 0 || report.pairs_removed > 0 {
+                self.accounts.compact();
+            }
+
+            if report.pairs_removed > 0 {
+                self.screen_names.compact();
+            }
+
+            if let Some(weights) = weights.filter(|_| report.weight_dates_removed > 0) {
+                weights.compact();
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod fixture {
+    //! Setup shared by the database and table tests.
+    use super::{Database, Error, Writeable};
+    use chrono::NaiveDate;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    /// Open a table in a new temporary directory, which is removed when the guard is dropped.
+    pub fn open<T, F: FnOnce(PathBuf) -> Result<T, Error>>(open: F) -> (TempDir, T) {
+        let dir = tempfile::tempdir().unwrap();
+        let table = open(dir.path().to_path_buf()).unwrap();
+
+        (dir, table)
+    }
+
+    pub fn database() -> (TempDir, Database<Writeable>) {
+        open(Database::open)
+    }
+
+    /// A day in January 2020.
+    pub fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2020, 1, day).unwrap()
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::fixture::{self, date};
     use super::*;
     use std::collections::HashMap;
 
     #[test]
     fn insert() {
-        let dir = tempfile::tempdir().unwrap();
-        let db = Database::open(dir).unwrap();
+        let (_dir, db) = fixture::database();
         db.insert(123, "foo", vec![]).unwrap();
         db.insert(123, "bar", vec![]).unwrap();
         db.insert(456, "foo", vec![]).unwrap();
@@ -177,10 +582,107 @@ mod tests {
         );
     }
 
+    #[test]
+    fn insert_observation() {
+        let (_dir, db) = fixture::database();
+
+        assert_eq!(
+            db.insert_observation(123, "foo", date(10)).unwrap(),
+            ObservationOutcome::New
+        );
+        assert_eq!(
+            db.insert_observation(123, "foo", date(20)).unwrap(),
+            ObservationOutcome::Extended
+        );
+        assert_eq!(
+            db.insert_observation(123, "foo", date(15)).unwrap(),
+            ObservationOutcome::Extended
+        );
+        assert_eq!(
+            db.insert_observation(123, "foo", date(20)).unwrap(),
+            ObservationOutcome::Unchanged
+        );
+        assert_eq!(
+            db.insert_observation(123, "foo", date(1)).unwrap(),
+            ObservationOutcome::Extended
+        );
+        assert_eq!(
+            db.insert_observation(123, "foo bar", date(1)).unwrap(),
+            ObservationOutcome::Invalid
+        );
+
+        assert_eq!(
+            db.accounts.get(123, "foo").unwrap(),
+            Some(vec![date(1), date(10), date(15), date(20)])
+        );
+        assert!(db.lookup_by_screen_name("foo bar").unwrap().is_empty());
+    }
+
+    #[test]
+    fn visit_user_id() {
+        let (_dir, db) = fixture::database();
+        db.insert(123, "foo", vec![date(20), date(10)]).unwrap();
+        db.insert(123, "bar", vec![date(5)]).unwrap();
+        db.insert(124, "baz", vec![date(1)]).unwrap();
+
+        let mut visited = vec![];
+        db.visit_user_id(123, |screen_name, dates| {
+            visited.push((
+                screen_name.to_string(),
+                dates.len(),
+                dates.first(),
+                dates.last(),
+            ));
+        })
+        .unwrap();
+
+        assert_eq!(
+            visited,
+            vec![
+                ("bar".to_string(), 1, Some(date(5)), Some(date(5))),
+                ("foo".to_string(), 2, Some(date(10)), Some(date(20))),
+            ]
+        );
+        assert_eq!(
+            db.limited_lookup_by_user_id(123, Some(date(6))).unwrap(),
+            HashMap::from([("foo".to_string(), vec![date(10), date(20)])])
+        );
+        db.visit_user_id(122, |_, _| panic!("No pairs expected"))
+            .unwrap();
+
+        let ranges = db.lookup_ranges_by_user_id(123).unwrap();
+        assert_eq!(ranges["foo"].expand(), vec![date(10), date(20)]);
+        assert_eq!(ranges["bar"].ranges().len(), 1);
+    }
+
+    #[test]
+    fn rebuild_index() {
+        let (dir, mut db) = fixture::database();
+        db.insert(123, "foo", vec![]).unwrap();
+        db.insert(456, "FOO", vec![]).unwrap();
+        db.insert(789, "bar", vec![]).unwrap();
+
+        db.rebuild_index().unwrap();
+
+        assert_eq!(db.lookup_by_screen_name("foo").unwrap(), vec![123, 456]);
+        assert_eq!(db.lookup_by_screen_name("bar").unwrap(), vec![789]);
+        assert!(!dir.path().join("screen-names.rebuild").exists());
+        assert!(!dir.path().join("screen-names.old").exists());
+
+        // A failed rebuild leaves the existing index in place
+        db.accounts
+            .underlying()
+            .put([0, 0, 0, 0, 0, 0, 0, 1, 0xff], [])
+            .unwrap();
+
+        assert!(db.rebuild_index().is_err());
+        assert_eq!(db.lookup_by_screen_name("foo").unwrap(), vec![123, 456]);
+        assert!(!dir.path().join("screen-names.rebuild").exists());
+    }
+
     #[test]
     fn lookup_by_screen_name_prefix() {
-        let dir = tempfile::tempdir().unwrap();
-        let db = Database::open(dir).unwrap();
+        let (_dir, db) = fixture::database();
         db.insert(123, "foo", vec![]).unwrap();
         db.insert(123, "bar", vec![]).unwrap();
         db.insert(1000, "for", vec![]).unwrap();
@@ -198,5 +700,229 @@ mod tests {
             db.lookup_by_screen_name_prefix("fo", 128).unwrap(),
             expected
         );
+        assert_eq!(
+            db.lookup_by_screen_name_prefix("fo", 2).unwrap(),
+            expected[0..2]
+        );
+        assert_eq!(
+            db.screen_names
+                .with_prefix("FO")
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+            expected
+        );
+        assert_eq!(db.screen_names.with_prefix("").count(), 5);
+    }
+
+    #[test]
+    fn non_ascii_screen_names() {
+        let (_dir, db) = fixture::database();
+        // The same handle in decomposed and composed forms, as seen in mention data
+        db.insert(123, "Jose\u{301}", vec![]).unwrap();
+        db.insert(123, "Jos\u{e9}", vec![]).unwrap();
+        db.insert(456, "JOS\u{c9}", vec![]).unwrap();
+
+        let mut expected_by_id = HashMap::new();
+        expected_by_id.insert("Jos\u{e9}".to_string(), vec![]);
+
+        assert_eq!(db.lookup_by_user_id(123).unwrap(), expected_by_id);
+        assert_eq!(
+            db.lookup_by_screen_name("jose\u{301}").unwrap(),
+            vec![123, 456]
+        );
+        assert_eq!(
+            db.lookup_by_screen_name_prefix("JOS", 128).unwrap(),
+            vec![("jos\u{e9}".to_string(), vec![123, 456])]
+        );
+
+        // Handles with domains are stored with punycode domains
+        db.insert(789, "foo@b\u{fc}cher.example", vec![]).unwrap();
+
+        assert_eq!(
+            db.lookup_by_screen_name("FOO@xn--bcher-kva.example")
+                .unwrap(),
+            vec![789]
+        );
+        assert!(db
+            .lookup_by_user_id(789)
+            .unwrap()
+            .contains_key("foo@xn--bcher-kva.example"));
+        assert!(matches!(
+            db.insert(789, "foo!", vec![]),
+            Err(Error::InvalidScreenName(_))
+        ));
+    }
+
+    #[test]
+    fn lookup_confusable() {
+        let (_dir, db) = fixture::database();
+        db.insert(123, "jack", vec![]).unwrap();
+        db.insert(124, "JACK_", vec![]).unwrap();
+        db.insert(125, "jack_d0rsey", vec![]).unwrap();
+        db.insert(126, "jack_dorsey", vec![]).unwrap();
+        db.insert(127, "jack_clorsey", vec![]).unwrap();
+
+        assert_eq!(
+            db.lookup_confusable("@Jack_Dorsey", 128).unwrap(),
+            vec![
+                ("jack_clorsey".to_string(), vec![127]),
+                ("jack_d0rsey".to_string(), vec![125]),
+                ("jack_dorsey".to_string(), vec![126]),
+            ]
+        );
+        // Cyrillic а
+        assert_eq!(
+            db.lookup_confusable("j\u{430}ck", 128).unwrap(),
+            vec![("jack".to_string(), vec![123])]
+        );
+        assert!(matches!(
+            db.lookup_confusable("jack_dorsey", 2),
+            Err(Error::TooManyVariants(16))
+        ));
+        assert!(matches!(
+            db.lookup_confusable("jack!", 128),
+            Err(Error::InvalidScreenName(_))
+        ));
+    }
+
+    #[test]
+    fn similar_screen_names() {
+        let (dir, db) = fixture::database();
+        db.insert(123, "jacck", vec![]).unwrap();
+        db.insert(124, "Jack", vec![]).unwrap();
+        db.insert(125, "jak_", vec![]).unwrap();
+        db.insert(126, "jack", vec![]).unwrap();
+        db.insert(127, "jackson", vec![]).unwrap();
+
+        let similar = |db: &Database<Writeable>| {
+            db.similar_screen_names("@Jack", 3)
+                .unwrap()
+                .into_iter()
+                .map(|similar| (similar.screen_name, similar.user_ids, similar.distance))
+                .collect::<Vec<_>>()
+        };
+        let expected = vec![
+            ("jack".to_string(), vec![124, 126], 0),
+            ("jacck".to_string(), vec![123], 1),
+            ("jak_".to_string(), vec![125], 2),
+        ];
+
+        assert_eq!(similar(&db), expected);
+
+        let phonetic =
+            phonetic::PhoneticTable::<Writeable>::open(dir.path().join(PHONETIC_TABLE)).unwrap();
+        phonetic.build(&db.screen_names).unwrap();
+        drop(phonetic);
+        drop(db);
+
+        let db = Database::open(dir.path()).unwrap();
+        assert_eq!(similar(&db), expected);
+
+        db.insert(128, "jaq", vec![]).unwrap();
+        assert_eq!(db.similar_screen_names("jack", 10).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn search_screen_names() {
+        let (dir, db) = fixture::database();
+        db.insert(123, "CryptoSupport", vec![]).unwrap();
+        db.insert(124, "crypto_help_support", vec![]).unwrap();
+        db.insert(125, "supportcrypto", vec![]).unwrap();
+        db.insert(126, "bitcoin", vec![]).unwrap();
+
+        let search = |db: &Database<Writeable>, pattern| {
+            db.search_screen_names(pattern, 128)
+                .unwrap()
+                .into_iter()
+                .map(|(screen_name, _)| screen_name)
+                .collect::<Vec<_>>()
+        };
+        let check = |db: &Database<Writeable>| {
+            assert_eq!(
+                search(db, "SUPPORT"),
+                vec!["crypto_help_support", "cryptosupport", "supportcrypto"]
+            );
+            assert_eq!(
+                search(db, "crypto*support"),
+                vec!["crypto_help_support", "cryptosupport"]
+            );
+            assert_eq!(search(db, "*coin"), vec!["bitcoin"]);
+            // Too short for the trigram index
+            assert_eq!(search(db, "o_"), vec!["crypto_help_support"]);
+            assert!(search(db, "trop*sup").is_empty());
+            assert_eq!(db.search_screen_names("crypto", 1).unwrap().len(), 1);
+        };
+
+        assert!(db.ngrams.is_none());
+        check(&db);
+
+        let ngrams = ngrams::NgramTable::<Writeable>::open(dir.path().join(NGRAMS_TABLE)).unwrap();
+        ngrams.build(&db.screen_names).unwrap();
+        drop(ngrams);
+        drop(db);
+
+        let db = Database::open(dir.path()).unwrap();
+        assert!(db.ngrams.is_some());
+        check(&db);
+
+        // Screen names inserted after the index was built are indexed
+        db.insert(127, "cryptoxsupport", vec![]).unwrap();
+        assert_eq!(search(&db, "crypto*support").len(), 3);
+        assert_eq!(
+            db.search_screen_names("ptoxs", 128).unwrap(),
+            vec![("cryptoxsupport".to_string(), vec![127])]
+        );
+    }
+
+    #[test]
+    fn stored_compression() {
+        use options::Compression;
+
+        let dir = tempfile::tempdir().unwrap();
+        let stored = DatabaseOptions {
+            compression: Some(Compression::Zstd),
+            zstd_dictionary_bytes: Some(1024),
+            ..Default::default()
+        };
+
+        // Nothing is stored yet
+        assert_eq!(
+            DatabaseOptions::default()
+                .with_stored_compression(dir.path())
+                .unwrap(),
+            DatabaseOptions::default()
+        );
+
+        stored.save_compression(dir.path()).unwrap();
+
+        let options = DatabaseOptions {
+            write_buffer_size: Some(1 << 20),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            options.with_stored_compression(dir.path()).unwrap(),
+            DatabaseOptions {
+                write_buffer_size: Some(1 << 20),
+                ..stored
+            }
+        );
+
+        // Explicit settings take precedence
+        let explicit = DatabaseOptions {
+            compression_per_level: Some(vec![Compression::None, Compression::Snappy]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            explicit.with_stored_compression(dir.path()).unwrap(),
+            explicit
+        );
+
+        // Tables opened with the stored settings can be written and compacted
+        let db = Database::<Writeable>::open_with_options(dir.path(), &options).unwrap();
+        db.insert(123, "foo", vec![]).unwrap();
+        db.accounts.compact();
+        assert_eq!(db.lookup_by_screen_name("foo").unwrap(), vec![123]);
     }
 }
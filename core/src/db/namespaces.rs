@@ -0,0 +1,189 @@
+use super::{options::DatabaseOptions, table::ReadOnly, Database, Error};
+use crate::ratelimit::constant_time_eq;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Directory within a database directory that named namespaces are stored in.
+///
+/// Each namespace is a complete database of its own (with its own import ledger and stats), so
+/// every command works on it unchanged, and a single server can host several datasets that share
+/// its block cache without anything in one being visible through another.
+pub const NAMESPACES_DIR: &str = "namespaces";
+
+/// File within a namespace directory listing the API keys that may query it, one per line.
+///
+/// A namespace without API keys is public.
+pub const API_KEYS_FILE: &str = "api-keys";
+
+const MAX_NAME_LEN: usize = 64;
+
+/// Namespace names are used as directory names and URL path segments, so they're limited to
+/// lowercase ASCII letters, digits, hyphens, and underscores.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .chars()
+            .all(|ch| ch.is_ascii_lowercase() || ch.is_ascii_digit() || ch == '-' || ch == '_')
+}
+
+/// The directory of a namespace.
+pub fn path<P: AsRef<Path>>(base: P, name: &str) -> Result<PathBuf, Error> {
+    if is_valid_name(name) {
+        Ok(base.as_ref().join(NAMESPACES_DIR).join(name))
+    } else {
+        Err(Error::InvalidNamespace(name.to_string()))
+    }
+}
+
+/// The names of a database's namespaces, in order.
+pub fn list<P: AsRef<Path>>(base: P) -> Result<Vec<String>, Error> {
+    let dir = base.as_ref().join(NAMESPACES_DIR);
+
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut names = vec![];
+
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+
+        if entry.file_type()?.is_dir() {
+            if let Some(name) = entry
+                .file_name()
+                .to_str()
+                .filter(|name| is_valid_name(name))
+            {
+                names.push(name.to_string());
+            }
+        }
+    }
+
+    names.sort();
+
+    Ok(names)
+}
+
+/// The API keys that may query a namespace (empty if it's public).
+pub fn api_keys<P: AsRef<Path>>(namespace: P) -> Result<Vec<String>, Error> {
+    let path = namespace.as_ref().join(API_KEYS_FILE);
+
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Replace the API keys that may query a namespace (making it public if there are none).
+pub fn set_api_keys<P: AsRef<Path>>(namespace: P, keys: &[String]) -> Result<(), Error> {
+    let path = namespace.as_ref().join(API_KEYS_FILE);
+
+    if keys.is_empty() {
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+    } else {
+        let mut contents = keys.join("\n");
+        contents.push('\n');
+        std::fs::write(path, contents)?;
+    }
+
+    Ok(())
+}
+
+/// A namespace opened for serving lookups.
+pub struct Namespace {
+    pub db: Database<ReadOnly>,
+    api_keys: Vec<String>,
+}
+
+impl Namespace {
+    pub fn is_public(&self) -> bool {
+        self.api_keys.is_empty()
+    }
+
+    /// Whether a request with the given API key may query the namespace.
+    pub fn is_authorized(&self, api_key: Option<&str>) -> bool {
+        self.is_public()
+            || api_key.is_some_and(|api_key| {
+                self.api_keys
+                    .iter()
+                    .any(|key| constant_time_eq(key.as_bytes(), api_key.as_bytes()))
+            })
+    }
+}
+
+/// Open every namespace of a database read-only.
+///
+/// The options (and so the block cache) are shared by every namespace.
+pub fn open_all<P: AsRef<Path>>(
+    base: P,
+    options: &DatabaseOptions,
+) -> Result<BTreeMap<String, Namespace>, Error> {
+    let mut namespaces = BTreeMap::new();
+
+    for name in list(&base)? {
+        let path = path(&base, &name)?;
+        let namespace = Namespace {
+            db: Database::open_with_options(&path, options)?,
+            api_keys: api_keys(&path)?,
+        };
+
+        namespaces.insert(name, namespace);
+    }
+
+    Ok(namespaces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Writeable;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn namespaces() {
+        let dir = tempfile::tempdir().unwrap();
+        let date = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+
+        assert!(path(dir.path(), "../main").is_err());
+        assert!(path(dir.path(), "Case").is_err());
+        assert!(list(dir.path()).unwrap().is_empty());
+
+        let main = Database::<Writeable>::open(dir.path()).unwrap();
+        main.insert(1, "foo", vec![date]).unwrap();
+
+        for (name, screen_name) in [("public", "bar"), ("case-123", "baz")] {
+            let namespace = Database::<Writeable>::open(path(dir.path(), name).unwrap()).unwrap();
+            namespace.insert(2, screen_name, vec![date]).unwrap();
+        }
+        set_api_keys(path(dir.path(), "case-123").unwrap(), &["key".to_string()]).unwrap();
+
+        let namespaces = open_all(dir.path(), &DatabaseOptions::default()).unwrap();
+        assert_eq!(
+            namespaces.keys().collect::<Vec<_>>(),
+            vec!["case-123", "public"]
+        );
+
+        let public = &namespaces["public"];
+        assert!(public.is_authorized(None));
+        assert_eq!(public.db.lookup_by_screen_name("bar").unwrap(), vec![2]);
+        assert!(public.db.lookup_by_screen_name("foo").unwrap().is_empty());
+
+        let private = &namespaces["case-123"];
+        assert!(!private.is_public());
+        assert!(!private.is_authorized(None));
+        assert!(!private.is_authorized(Some("guess")));
+        assert!(private.is_authorized(Some("key")));
+
+        // Namespaces aren't visible through the main database
+        assert!(main.lookup_by_screen_name("bar").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,294 @@
+use super::{
+    codec::screen_name_to_key,
+    options::DatabaseOptions,
+    screen_names::ScreenNameTable,
+    table::{Mode, Table, Writeable},
+    Error,
+};
+use rocksdb::{DBRawIterator, Options, WriteBatch, DB};
+use std::marker::PhantomData;
+use std::path::Path;
+
+/// Length of the substrings indexed.
+pub const NGRAM_LEN: usize = 3;
+
+/// Number of index entries to accumulate before writing when building the index.
+const BUILD_BATCH_SIZE: usize = 100_000;
+
+/// A trigram index over (lowercased) screen names for substring search.
+///
+/// Keys are a trigram followed by a screen name containing it, with empty values, so the screen
+/// names for a trigram are a sorted range of keys, and the screen names containing several
+/// trigrams can be found by intersecting those ranges without reading any of them in full.
+pub struct NgramTable<M> {
+    db: DB,
+    mode: PhantomData<M>,
+}
+
+impl<M> Table for NgramTable<M> {
+    type Counts = u64;
+
+    fn underlying(&self) -> &DB {
+        &self.db
+    }
+
+    /// The number of index entries.
+    fn get_counts(&self) -> Result<Self::Counts, Error> {
+        let mut count = 0;
+        let mut iter = self.db.raw_iterator();
+        iter.seek_to_first();
+
+        while iter.valid() {
+            count += 1;
+            iter.next();
+        }
+
+        iter.status()?;
+        Ok(count)
+    }
+}
+
+impl<M> NgramTable<M> {
+    /// All indexed screen names containing every trigram of the given substrings, in sorted order.
+    ///
+    /// Since the trigrams can appear in other orders or positions, the results are candidates
+    /// that must still be checked. Returns `None` if none of the substrings have trigrams.
+    pub fn candidates(&self, substrings: &[&str]) -> Result<Option<Vec<String>>, Error> {
+        let keys = substrings
+            .iter()
+            .map(|substring| screen_name_to_key(substring))
+            .collect::<Vec<_>>();
+        let mut ngrams = keys
+            .iter()
+            .flat_map(|key| key.windows(NGRAM_LEN))
+            .collect::<Vec<_>>();
+        ngrams.sort_unstable();
+        ngrams.dedup();
+
+        if ngrams.is_empty() {
+            return Ok(None);
+        }
+
+        let mut iters = ngrams
+            .iter()
+            .map(|ngram| {
+                let mut iter = self.db.raw_iterator();
+                iter.seek(ngram);
+                iter
+            })
+            .collect::<Vec<_>>();
+        let mut results = vec![];
+
+        // Leapfrog intersection: repeatedly seek every iterator to the largest screen name any of
+        // them is positioned at, until they agree
+        let mut candidate = match suffix(&iters[0], ngrams[0])? {
+            Some(screen_name) => screen_name,
+            None => return Ok(Some(results)),
+        };
+
+        'search: loop {
+            for (iter, ngram) in iters.iter_mut().zip(&ngrams) {
+                iter.seek([*ngram, candidate.as_slice()].concat());
+
+                match suffix(iter, ngram)? {
+                    Some(screen_name) if screen_name == candidate => {}
+                    Some(screen_name) => {
+                        candidate = screen_name;
+                        continue 'search;
+                    }
+                    None => break 'search,
+                }
+            }
+
+            results.push(String::from_utf8(candidate).map_err(|error| error.utf8_error())?);
+
+            iters[0].next();
+            candidate = match suffix(&iters[0], ngrams[0])? {
+                Some(screen_name) => screen_name,
+                None => break,
+            };
+        }
+
+        Ok(Some(results))
+    }
+}
+
+impl<M: Mode> NgramTable<M> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::open_with_options(path, &DatabaseOptions::default())
+    }
+
+    pub fn open_with_options<P: AsRef<Path>>(
+        path: P,
+        database_options: &DatabaseOptions,
+    ) -> Result<Self, Error> {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        database_options.apply(&mut options);
+
+        let db = if M::is_read_only() {
+            DB::open_for_read_only(&options, path, true)?
+        } else {
+            DB::open(&options, path)?
+        };
+
+        Ok(Self {
+            db,
+            mode: PhantomData,
+        })
+    }
+}
+
+impl NgramTable<Writeable> {
+    pub fn insert(&self, screen_name: &str) -> Result<(), Error> {
+        let mut batch = WriteBatch::default();
+        add_to_batch(&mut batch, &screen_name_to_key(screen_name));
+
+        Ok(self.db.write(batch)?)
+    }
+
+    /// Index every screen name in the screen name index.
+    pub fn build<N>(&self, screen_names: &ScreenNameTable<N>) -> Result<(), Error> {
+        let mut batch = WriteBatch::default();
+
+        for result in screen_names.with_prefix("") {
+            let (screen_name, _) = result?;
+            add_to_batch(&mut batch, screen_name.as_bytes());
+
+            if batch.len() >= BUILD_BATCH_SIZE {
+                self.db.write(std::mem::take(&mut batch))?;
+            }
+        }
+
+        if !batch.is_empty() {
+            self.db.write(batch)?;
+        }
+
+        Ok(self.db.flush()?)
+    }
+}
+
+/// Find screen names matching a pattern, using the trigram index if there is one.
+///
+/// A pattern without `*` matches screen names containing it, and a pattern with `*` wildcards
+/// must match entire screen names. Matching is case-insensitive, and results are lowercased and
+/// sorted. Without a trigram index (or for patterns with no literal part of at least three
+/// characters), the screen name index is scanned.
+pub fn search<M, N>(
+    screen_names: &ScreenNameTable<M>,
+    ngrams: Option<&NgramTable<N>>,
+    pattern: &str,
+    limit: usize,
+) -> Result<Vec<(String, Vec<u64>)>, Error> {
+    let pattern = pattern.to_lowercase();
+    let pieces = if pattern.contains('*') {
+        pattern.split('*').collect::<Vec<_>>()
+    } else {
+        vec!["", pattern.as_str(), ""]
+    };
+
+    let candidates = match ngrams {
+        Some(ngrams) => ngrams.candidates(&pieces)?,
+        None => None,
+    };
+
+    let mut results = vec![];
+
+    match candidates {
+        Some(candidates) => {
+            for screen_name in candidates {
+                if results.len() >= limit {
+                    break;
+                }
+
+                if wildcard_match(&pieces, &screen_name) {
+                    // The trigram index isn't updated when accounts are removed
+                    let ids = screen_names.lookup(&screen_name)?;
+
+                    if !ids.is_empty() {
+                        results.push((screen_name, ids));
+                    }
+                }
+            }
+        }
+        None => {
+            for result in screen_names.with_prefix(pieces[0]) {
+                if results.len() >= limit {
+                    break;
+                }
+
+                let (screen_name, ids) = result?;
+
+                if wildcard_match(&pieces, &screen_name) {
+                    results.push((screen_name, ids));
+                }
+            }
+        }
+    }
+
+    Ok(results)
+}
+
+/// Match text against the literal pieces of a pattern that were separated by wildcards.
+fn wildcard_match(pieces: &[&str], text: &str) -> bool {
+    match pieces.split_first() {
+        None => text.is_empty(),
+        Some((only, [])) => text == *only,
+        Some((first, rest)) => {
+            let (last, middle) = rest.split_last().unwrap();
+
+            if !text.starts_with(first) {
+                return false;
+            }
+
+            let mut position = first.len();
+
+            for piece in middle {
+                match text[position..].find(piece) {
+                    Some(index) => position += index + piece.len(),
+                    None => return false,
+                }
+            }
+
+            text.len() >= position + last.len() && text[position..].ends_with(last)
+        }
+    }
+}
+
+fn add_to_batch(batch: &mut WriteBatch, screen_name_key: &[u8]) {
+    for ngram in screen_name_key.windows(NGRAM_LEN) {
+        batch.put([ngram, screen_name_key].concat(), b"");
+    }
+}
+
+/// The screen name at the iterator's position, if it is still within the trigram's range.
+fn suffix(iter: &DBRawIterator<'_>, ngram: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    match iter.key() {
+        Some(key) if key.starts_with(ngram) => Ok(Some(key[ngram.len()..].to_vec())),
+        _ => {
+            iter.status()?;
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcard_patterns() {
+        let pieces = |pattern: &'static str| pattern.split('*').collect::<Vec<_>>();
+
+        assert!(wildcard_match(&pieces("crypto*support"), "crypto_support"));
+        assert!(wildcard_match(&pieces("crypto*support"), "cryptosupport"));
+        assert!(!wildcard_match(&pieces("crypto*support"), "cryptosupport1"));
+        assert!(wildcard_match(&pieces("*bit*coin*"), "xbitycoinz"));
+        assert!(!wildcard_match(&pieces("*coin*bit*"), "bitcoin"));
+        // Pieces can't overlap
+        assert!(!wildcard_match(&pieces("ab*ba"), "aba"));
+        assert!(wildcard_match(&["", "oo", ""], "foo"));
+        assert!(wildcard_match(&pieces("foo"), "foo"));
+        assert!(!wildcard_match(&pieces("foo"), "food"));
+    }
+}
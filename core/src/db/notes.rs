@@ -0,0 +1,222 @@
+use super::{
+    options::DatabaseOptions,
+    table::{Mode, Table, Writeable},
+    Error,
+};
+use crate::model::Note;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use rocksdb::{IteratorMode, Options, DB};
+use std::convert::TryInto;
+use std::marker::PhantomData;
+use std::path::Path;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NoteTableCounts {
+    pub id_count: u64,
+    pub note_count: u64,
+}
+
+/// Free-text analyst notes attached to account IDs.
+pub struct NoteTable<M> {
+    db: DB,
+    mode: PhantomData<M>,
+}
+
+impl<M> Table for NoteTable<M> {
+    type Counts = NoteTableCounts;
+
+    fn underlying(&self) -> &DB {
+        &self.db
+    }
+
+    fn get_counts(&self) -> Result<Self::Counts, Error> {
+        let mut note_count = 0;
+        let mut id_count = 0;
+        let mut last_id = None;
+
+        let iter = self.db.iterator(IteratorMode::Start);
+
+        for result in iter {
+            let (key, _) = result?;
+            note_count += 1;
+
+            let (id, _) = key_to_pair(&key)?;
+
+            if last_id != Some(id) {
+                id_count += 1;
+                last_id = Some(id);
+            }
+        }
+
+        Ok(Self::Counts {
+            id_count,
+            note_count,
+        })
+    }
+}
+
+impl<M> NoteTable<M> {
+    pub fn lookup(&self, id: u64) -> Result<Vec<Note>, Error> {
+        let prefix = id.to_be_bytes();
+        let iter = self.db.prefix_iterator(prefix);
+        let mut results = vec![];
+
+        for result in iter {
+            let (key, value) = result?;
+            let (next_id, timestamp) = key_to_pair(&key)?;
+
+            if next_id == id {
+                results.push(value_to_note(timestamp, &value)?);
+            } else {
+                break;
+            }
+        }
+
+        Ok(results)
+    }
+}
+
+impl<M: Mode> NoteTable<M> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::open_with_options(path, &DatabaseOptions::default())
+    }
+
+    pub fn open_with_options<P: AsRef<Path>>(
+        path: P,
+        database_options: &DatabaseOptions,
+    ) -> Result<Self, Error> {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        database_options.apply(&mut options);
+
+        let db = if M::is_read_only() {
+            DB::open_for_read_only(&options, path, true)?
+        } else {
+            DB::open(&options, path)?
+        };
+
+        Ok(Self {
+            db,
+            mode: PhantomData,
+        })
+    }
+}
+
+impl NoteTable<Writeable> {
+    /// Add a note, returning the timestamp it was stored under.
+    ///
+    /// Notes are keyed by millisecond, so if the account already has a note with the same
+    /// timestamp, the new note is moved to the next free millisecond instead of replacing it.
+    pub fn add(&self, id: u64, note: &Note) -> Result<DateTime<Utc>, Error> {
+        let author_bytes = note.author.as_bytes();
+        let author_len: u16 = author_bytes
+            .len()
+            .try_into()
+            .map_err(|_| Error::InvalidValue(author_bytes.to_vec()))?;
+
+        let mut value = Vec::with_capacity(2 + author_bytes.len() + note.text.len());
+        value.extend_from_slice(&author_len.to_be_bytes());
+        value.extend_from_slice(author_bytes);
+        value.extend_from_slice(note.text.as_bytes());
+
+        let mut timestamp = note.timestamp;
+
+        while self.db.get_pinned(pair_to_key(id, &timestamp))?.is_some() {
+            timestamp += Duration::milliseconds(1);
+        }
+
+        self.db.put(pair_to_key(id, &timestamp), value)?;
+
+        Ok(timestamp)
+    }
+
+    pub fn remove(&self, id: u64, timestamp: &DateTime<Utc>) -> Result<(), Error> {
+        Ok(self.db.delete(pair_to_key(id, timestamp))?)
+    }
+}
+
+fn pair_to_key(id: u64, timestamp: &DateTime<Utc>) -> [u8; 16] {
+    let mut key = [0; 16];
+    key[0..8].copy_from_slice(&id.to_be_bytes());
+    key[8..16].copy_from_slice(&timestamp.timestamp_millis().to_be_bytes());
+    key
+}
+
+fn key_to_pair(key: &[u8]) -> Result<(u64, DateTime<Utc>), Error> {
+    let bytes: [u8; 16] = key
+        .try_into()
+        .map_err(|_| Error::InvalidKey(key.to_vec()))?;
+    let id = u64::from_be_bytes(bytes[0..8].try_into().unwrap());
+    let timestamp_millis = i64::from_be_bytes(bytes[8..16].try_into().unwrap());
+    let timestamp = Utc
+        .timestamp_millis_opt(timestamp_millis)
+        .single()
+        .ok_or_else(|| Error::InvalidKey(key.to_vec()))?;
+
+    Ok((id, timestamp))
+}
+
+fn value_to_note(timestamp: DateTime<Utc>, value: &[u8]) -> Result<Note, Error> {
+    let author_len = value
+        .get(0..2)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u16::from_be_bytes)
+        .ok_or_else(|| Error::InvalidValue(value.to_vec()))? as usize;
+    let author = value
+        .get(2..2 + author_len)
+        .ok_or_else(|| Error::InvalidValue(value.to_vec()))?;
+    let text = &value[2 + author_len..];
+
+    Ok(Note {
+        timestamp,
+        author: std::str::from_utf8(author)?.to_string(),
+        text: std::str::from_utf8(text)?.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixture;
+
+    #[test]
+    fn notes() {
+        use chrono::{TimeZone, Utc};
+
+        let (_dir, notes) = fixture::open(NoteTable::<Writeable>::open);
+        let note = |seconds, text: &str| crate::model::Note {
+            timestamp: Utc.timestamp_opt(seconds, 0).unwrap(),
+            author: "analyst".to_string(),
+            text: text.to_string(),
+        };
+
+        notes.add(123, &note(1000, "first")).unwrap();
+        notes.add(123, &note(2000, "second")).unwrap();
+        notes.add(456, &note(1500, "other")).unwrap();
+
+        assert_eq!(
+            notes.lookup(123).unwrap(),
+            vec![note(1000, "first"), note(2000, "second")]
+        );
+
+        notes.remove(123, &note(1000, "").timestamp).unwrap();
+
+        assert_eq!(notes.lookup(123).unwrap(), vec![note(2000, "second")]);
+        assert_eq!(
+            notes.get_counts().unwrap(),
+            NoteTableCounts {
+                id_count: 2,
+                note_count: 2
+            }
+        );
+
+        let stored = notes.add(456, &note(1500, "same time")).unwrap();
+        let lookup = notes.lookup(456).unwrap();
+
+        assert_eq!(stored.timestamp_millis(), 1_500_001);
+        assert_eq!(lookup.len(), 2);
+        assert_eq!(lookup[0], note(1500, "other"));
+        assert_eq!(lookup[1].timestamp, stored);
+        assert_eq!(lookup[1].text, "same time");
+    }
+}
@@ -0,0 +1,239 @@
+use super::{encryption::Keyring, retry::RetryPolicy, Error};
+use rocksdb::{BlockBasedOptions, Cache, DBCompressionType, Options};
+use serde_derive::{Deserialize, Serialize};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+
+/// Ratio of sampled training data to dictionary size (the Zstd recommendation is about 100).
+const ZSTD_TRAINING_FACTOR: i32 = 100;
+
+/// File in the database directory that records the compression settings the tables were written
+/// with.
+pub const COMPRESSION_FILE: &str = "compression.json";
+
+/// Compression algorithms available in this build of RocksDB.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Snappy,
+    Zstd,
+}
+
+impl From<Compression> for DBCompressionType {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::None => DBCompressionType::None,
+            Compression::Snappy => DBCompressionType::Snappy,
+            Compression::Zstd => DBCompressionType::Zstd,
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "none" => Ok(Compression::None),
+            "snappy" => Ok(Compression::Snappy),
+            "zstd" => Ok(Compression::Zstd),
+            other => Err(format!("Unsupported compression type: {}", other)),
+        }
+    }
+}
+
+/// RocksDB tuning options applied to every table in a database.
+///
+/// Unset values leave the RocksDB defaults in place.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct DatabaseOptions {
+    /// Shared LRU block cache size in bytes
+    pub block_cache_size: Option<usize>,
+    /// Memtable size in bytes
+    pub write_buffer_size: Option<usize>,
+    /// Maximum number of concurrent flushes and compactions for each table
+    pub max_background_jobs: Option<i32>,
+    /// Compression for each level, starting with level 0
+    pub compression_per_level: Option<Vec<Compression>>,
+    /// Bloom filter bits per key
+    pub bloom_bits: Option<f64>,
+    /// Compression for all levels (overridden by `compression_per_level`)
+    pub compression: Option<Compression>,
+    /// Maximum size of the per-file Zstd dictionaries RocksDB trains during compaction
+    pub zstd_dictionary_bytes: Option<i32>,
+    /// Wait for another process writing to the database to finish, instead of failing, when
+    /// opening it for writing
+    pub wait_for_writer: bool,
+    /// Number of times to retry a write that fails with a transient error (5 by default)
+    pub write_retries: Option<u32>,
+    /// Block cache shared by the tables opened with these options
+    #[serde(skip)]
+    pub cache: SharedCache,
+    /// Keys for sealing and opening account table values (which are stored in the clear if
+    /// missing)
+    #[serde(skip)]
+    pub encryption: Option<Arc<Keyring>>,
+}
+
+/// The block cache, created when the first table is opened and then shared by every other table
+/// opened with the same options (or a clone of them).
+///
+/// Its capacity can be changed while the tables are open.
+#[derive(Clone, Default)]
+pub struct SharedCache(Arc<OnceLock<(Cache, AtomicUsize)>>);
+
+impl SharedCache {
+    fn get(&self, size: usize) -> Cache {
+        self.0
+            .get_or_init(|| (Cache::new_lru_cache(size), AtomicUsize::new(size)))
+            .0
+            .clone()
+    }
+
+    /// The cache's capacity in bytes, or `None` if no table has been opened with a block cache
+    /// size.
+    pub fn capacity(&self) -> Option<usize> {
+        self.0
+            .get()
+            .map(|(_, capacity)| capacity.load(Ordering::Relaxed))
+    }
+
+    /// The bytes currently used by cached blocks.
+    pub fn usage(&self) -> Option<usize> {
+        self.0.get().map(|(cache, _)| cache.get_usage())
+    }
+
+    /// Change the capacity in bytes (evicting blocks if it shrinks), returning whether there's a
+    /// cache to change.
+    pub fn set_capacity(&self, size: usize) -> bool {
+        match self.0.get() {
+            Some((cache, capacity)) => {
+                cache.clone().set_capacity(size);
+                capacity.store(size, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl std::fmt::Debug for SharedCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SharedCache").finish()
+    }
+}
+
+// The cache is runtime state, not configuration
+impl PartialEq for SharedCache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+/// The compression fields of [`DatabaseOptions`], as stored in [`COMPRESSION_FILE`].
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+struct StoredCompression {
+    compression: Option<Compression>,
+    compression_per_level: Option<Vec<Compression>>,
+    zstd_dictionary_bytes: Option<i32>,
+}
+
+impl DatabaseOptions {
+    fn has_compression(&self) -> bool {
+        self.compression.is_some()
+            || self.compression_per_level.is_some()
+            || self.zstd_dictionary_bytes.is_some()
+    }
+
+    /// Record these compression settings in the database directory, so that later writes (which
+    /// may not specify any compression) keep using them.
+    pub fn save_compression<P: AsRef<Path>>(&self, base: P) -> Result<(), Error> {
+        let stored = StoredCompression {
+            compression: self.compression,
+            compression_per_level: self.compression_per_level.clone(),
+            zstd_dictionary_bytes: self.zstd_dictionary_bytes,
+        };
+
+        Ok(std::fs::write(
+            base.as_ref().join(COMPRESSION_FILE),
+            serde_json::to_vec_pretty(&stored)?,
+        )?)
+    }
+
+    /// Fill in the compression settings stored in the database directory, if there are any.
+    ///
+    /// Compression settings given explicitly take precedence over the stored ones.
+    pub fn with_stored_compression<P: AsRef<Path>>(&self, base: P) -> Result<Self, Error> {
+        let path = base.as_ref().join(COMPRESSION_FILE);
+
+        if self.has_compression() || !path.exists() {
+            return Ok(self.clone());
+        }
+
+        let stored: StoredCompression = serde_json::from_slice(&std::fs::read(path)?)?;
+
+        Ok(Self {
+            compression: stored.compression,
+            compression_per_level: stored.compression_per_level,
+            zstd_dictionary_bytes: stored.zstd_dictionary_bytes,
+            ..self.clone()
+        })
+    }
+
+    /// The retry policy for writes, with the default backoff.
+    pub fn retry_policy(&self) -> RetryPolicy {
+        let default = RetryPolicy::default();
+
+        RetryPolicy {
+            retries: self.write_retries.unwrap_or(default.retries),
+            ..default
+        }
+    }
+
+    pub(crate) fn apply(&self, options: &mut Options) {
+        if let Some(size) = self.write_buffer_size {
+            options.set_write_buffer_size(size);
+        }
+
+        if let Some(jobs) = self.max_background_jobs {
+            options.set_max_background_jobs(jobs);
+        }
+
+        if let Some(compression) = self.compression {
+            options.set_compression_type(compression.into());
+        }
+
+        if let Some(dictionary_bytes) = self.zstd_dictionary_bytes {
+            // Window bits, level, and strategy are the RocksDB defaults
+            options.set_compression_options(-14, 32767, 0, dictionary_bytes);
+            options.set_zstd_max_train_bytes(dictionary_bytes.saturating_mul(ZSTD_TRAINING_FACTOR));
+        }
+
+        if let Some(compression_per_level) = &self.compression_per_level {
+            let levels = compression_per_level
+                .iter()
+                .map(|compression| (*compression).into())
+                .collect::<Vec<DBCompressionType>>();
+            options.set_compression_per_level(&levels);
+        }
+
+        if self.block_cache_size.is_some() || self.bloom_bits.is_some() {
+            let mut block_options = BlockBasedOptions::default();
+
+            if let Some(size) = self.block_cache_size {
+                block_options.set_block_cache(&self.cache.get(size));
+            }
+
+            if let Some(bits) = self.bloom_bits {
+                block_options.set_bloom_filter(bits, false);
+            }
+
+            options.set_block_based_table_factory(&block_options);
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use super::{
+    options::DatabaseOptions,
+    screen_names::ScreenNameTable,
+    table::{Mode, Table, Writeable},
+    Error,
+};
+use rocksdb::{Direction, IteratorMode, Options, WriteBatch, DB};
+use std::marker::PhantomData;
+use std::path::Path;
+
+/// Number of index entries to accumulate before writing when building the index.
+const BUILD_BATCH_SIZE: usize = 100_000;
+
+/// Separates the code from the screen name in keys (so that no code is a prefix of another).
+const SEPARATOR: u8 = 0;
+
+/// A screen name that sounds like a query, with the accounts that have used it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SimilarScreenName {
+    pub screen_name: String,
+    pub user_ids: Vec<u64>,
+    /// Edit distance from the (lowercased) query
+    pub distance: usize,
+}
+
+impl SimilarScreenName {
+    /// Sort by edit distance, then by the number of accounts that have used the screen name.
+    pub(crate) fn rank(results: &mut [Self]) {
+        results.sort_by(|a, b| {
+            a.distance
+                .cmp(&b.distance)
+                .then_with(|| b.user_ids.len().cmp(&a.user_ids.len()))
+                .then_with(|| a.screen_name.cmp(&b.screen_name))
+        });
+    }
+}
+
+/// A phonetic index over (lowercased) screen names, for finding handles reported from memory.
+///
+/// Keys are a Soundex code followed by a screen name with that code, with empty values.
+pub struct PhoneticTable<M> {
+    db: DB,
+    mode: PhantomData<M>,
+}
+
+impl<M> Table for PhoneticTable<M> {
+    type Counts = u64;
+
+    fn underlying(&self) -> &DB {
+        &self.db
+    }
+
+    /// The number of indexed screen names.
+    fn get_counts(&self) -> Result<Self::Counts, Error> {
+        let mut count = 0;
+        let mut iter = self.db.raw_iterator();
+        iter.seek_to_first();
+
+        while iter.valid() {
+            count += 1;
+            iter.next();
+        }
+
+        iter.status()?;
+        Ok(count)
+    }
+}
+
+impl<M> PhoneticTable<M> {
+    /// All indexed screen names with the given code, in sorted order.
+    pub fn lookup(&self, code: &str) -> Result<Vec<String>, Error> {
+        let prefix = code_to_prefix(code);
+        let mut results = vec![];
+
+        for result in self
+            .db
+            .iterator(IteratorMode::From(&prefix, Direction::Forward))
+        {
+            let (key, _) = result?;
+
+            match key.strip_prefix(prefix.as_slice()) {
+                Some(screen_name) => results.push(std::str::from_utf8(screen_name)?.to_string()),
+                None => break,
+            }
+        }
+
+        Ok(results)
+    }
+}
+
+impl<M: Mode> PhoneticTable<M> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::open_with_options(path, &DatabaseOptions::default())
+    }
+
+    pub fn open_with_options<P: AsRef<Path>>(
+        path: P,
+        database_options: &DatabaseOptions,
+    ) -> Result<Self, Error> {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        database_options.apply(&mut options);
+
+        let db = if M::is_read_only() {
+            DB::open_for_read_only(&options, path, true)?
+        } else {
+            DB::open(&options, path)?
+        };
+
+        Ok(Self {
+            db,
+            mode: PhantomData,
+        })
+    }
+}
+
+impl PhoneticTable<Writeable> {
+    pub fn insert(&self, screen_name: &str) -> Result<(), Error> {
+        let screen_name = screen_name.to_lowercase();
+
+        match soundex(&screen_name) {
+            Some(code) => Ok(self.db.put(to_key(&code, &screen_name), b"")?),
+            None => Ok(()),
+        }
+    }
+
+    /// Index every screen name in the screen name index.
+    pub fn build<N>(&self, screen_names: &ScreenNameTable<N>) -> Result<(), Error> {
+        let mut batch = WriteBatch::default();
+
+        for result in screen_names.with_prefix("") {
+            let (screen_name, _) = result?;
+
+            if let Some(code) = soundex(&screen_name) {
+                batch.put(to_key(&code, &screen_name), b"");
+            }
+
+            if batch.len() >= BUILD_BATCH_SIZE {
+                self.db.write(std::mem::take(&mut batch))?;
+            }
+        }
+
+        if !batch.is_empty() {
+            self.db.write(batch)?;
+        }
+
+        Ok(self.db.flush()?)
+    }
+}
+
+/// Find screen names that sound like a query, using the phonetic index if there is one.
+///
+/// Results are ranked by edit distance from the query, then by the number of accounts that have
+/// used them. Without a phonetic index, the screen name index is scanned.
+pub fn similar<M, N>(
+    screen_names: &ScreenNameTable<M>,
+    phonetic: Option<&PhoneticTable<N>>,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<SimilarScreenName>, Error> {
+    let query = query.strip_prefix('@').unwrap_or(query).to_lowercase();
+    let code = match soundex(&query) {
+        Some(code) => code,
+        None => return Ok(vec![]),
+    };
+
+    let mut results = vec![];
+
+    match phonetic {
+        Some(phonetic) => {
+            for screen_name in phonetic.lookup(&code)? {
+                // The phonetic index isn't updated when accounts are removed
+                let user_ids = screen_names.lookup(&screen_name)?;
+
+                if !user_ids.is_empty() {
+                    results.push(SimilarScreenName {
+                        distance: edit_distance(&query, &screen_name),
+                        screen_name,
+                        user_ids,
+                    });
+                }
+            }
+        }
+        None => {
+            for result in screen_names.with_prefix("") {
+                let (screen_name, user_ids) = result?;
+
+                if soundex(&screen_name).as_ref() == Some(&code) {
+                    results.push(SimilarScreenName {
+                        distance: edit_distance(&query, &screen_name),
+                        screen_name,
+                        user_ids,
+                    });
+                }
+            }
+        }
+    }
+
+    SimilarScreenName::rank(&mut results);
+    results.truncate(limit);
+
+    Ok(results)
+}
+
+/// The Soundex code of the letters in a screen name, without the usual truncation to four
+/// characters (since handles are often several words run together).
+///
+/// Digits and underscores separate letters in the same way as vowels. Returns `None` if there are
+/// no letters.
+pub fn soundex(screen_name: &str) -> Option<String> {
+    let mut letters = screen_name
+        .chars()
+        .map(|ch| ch.to_ascii_uppercase())
+        .skip_while(|ch| !ch.is_ascii_alphabetic());
+    let first = letters.next()?;
+
+    let mut code = String::new();
+    code.push(first);
+    let mut last = digit(first);
+
+    for ch in letters {
+        match ch {
+            // These don't separate letters with the same code
+            'H' | 'W' => {}
+            _ => {
+                let current = digit(ch);
+
+                if let Some(current) = current {
+                    if last != Some(current) {
+                        code.push(current);
+                    }
+                }
+
+                last = current;
+            }
+        }
+    }
+
+    Some(code)
+}
+
+fn digit(ch: char) -> Option<char> {
+    match ch {
+        'B' | 'F' | 'P' | 'V' => Some('1'),
+        'C' | 'G' | 'J' | 'K' | 'Q' | 'S' | 'X' | 'Z' => Some('2'),
+        'D' | 'T' => Some('3'),
+        'L' => Some('4'),
+        'M' | 'N' => Some('5'),
+        'R' => Some('6'),
+        _ => None,
+    }
+}
+
+/// Levenshtein distance (over characters).
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, a_ch) in a.chars().enumerate() {
+        current[0] = i + 1;
+
+        for (j, b_ch) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_ch != *b_ch);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()]
+}
+
+fn code_to_prefix(code: &str) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(code.len() + 1);
+    prefix.extend_from_slice(code.as_bytes());
+    prefix.push(SEPARATOR);
+    prefix
+}
+
+fn to_key(code: &str, screen_name: &str) -> Vec<u8> {
+    let mut key = code_to_prefix(code);
+    key.extend_from_slice(screen_name.as_bytes());
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn soundex_codes() {
+        assert_eq!(soundex("Robert"), Some("R163".to_string()));
+        assert_eq!(soundex("rupert"), Some("R163".to_string()));
+        assert_eq!(soundex("Tymczak"), Some("T522".to_string()));
+        assert_eq!(soundex("Ashcraft"), Some("A2613".to_string()));
+        assert_eq!(soundex("jack"), soundex("jacck"));
+        assert_eq!(soundex("jack"), soundex("_jak_123"));
+        assert_eq!(soundex("123_"), None);
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("jack", "jack"), 0);
+        assert_eq!(edit_distance("jack", "jacck"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+}
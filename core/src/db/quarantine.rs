@@ -0,0 +1,121 @@
+use super::{table::Writeable, Database, Error};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Directory within a database directory that quarantined imports are written to.
+///
+/// The quarantine is a complete database of its own (with its own import ledger), so imports and
+/// lookups work on it unchanged, and nothing in it is visible to ordinary lookups on the main
+/// database until it's promoted.
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+/// The quarantine directory of a database.
+pub fn path<P: AsRef<Path>>(base: P) -> PathBuf {
+    base.as_ref().join(QUARANTINE_DIR)
+}
+
+/// The pairs copied from a quarantine into the main database.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PromoteReport {
+    pub accounts: usize,
+    /// Accounts that weren't already in the main database
+    pub new_accounts: usize,
+    pub pairs: usize,
+    /// Pairs that weren't already in the main database
+    pub new_pairs: usize,
+}
+
+impl std::fmt::Display for PromoteReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Accounts: {}", self.accounts)?;
+        writeln!(f, "New accounts: {}", self.new_accounts)?;
+        writeln!(f, "Pairs: {}", self.pairs)?;
+        write!(f, "New pairs: {}", self.new_pairs)
+    }
+}
+
+/// Insert every pair in a quarantine into the main database, merging observation dates with any
+/// that are already recorded.
+pub fn promote<M: Sync + Send + 'static>(
+    main: &Database<Writeable>,
+    quarantine: &Database<M>,
+) -> Result<PromoteReport, Error> {
+    let mut report = PromoteReport::default();
+    let mut current: Option<(u64, HashSet<String>)> = None;
+
+    for result in quarantine.accounts.pairs() {
+        let (id, screen_name, dates) = result?;
+
+        let known = match &mut current {
+            Some((current_id, known)) if *current_id == id => known,
+            _ => {
+                let known = main
+                    .lookup_by_user_id(id)?
+                    .into_keys()
+                    .collect::<HashSet<_>>();
+
+                report.accounts += 1;
+                if known.is_empty() {
+                    report.new_accounts += 1;
+                }
+
+                &mut current.insert((id, known)).1
+            }
+        };
+
+        report.pairs += 1;
+
+        if !known.contains(&screen_name) {
+            report.new_pairs += 1;
+        }
+
+        main.insert(id, &screen_name, dates)?;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::ReadOnly;
+    use chrono::NaiveDate;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2020, 1, day).unwrap()
+    }
+
+    #[test]
+    fn promote() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = Database::<Writeable>::open(dir.path()).unwrap();
+        main.insert(1, "foo", vec![date(1)]).unwrap();
+
+        {
+            let quarantine = Database::<Writeable>::open(path(dir.path())).unwrap();
+            quarantine.insert(1, "foo", vec![date(2)]).unwrap();
+            quarantine.insert(1, "bar", vec![date(3)]).unwrap();
+            quarantine.insert(2, "foo", vec![date(4)]).unwrap();
+        }
+
+        // Quarantined pairs aren't visible in the main database
+        assert_eq!(main.lookup_by_screen_name("foo").unwrap(), vec![1]);
+
+        let quarantine = Database::<ReadOnly>::open(path(dir.path())).unwrap();
+        assert_eq!(
+            super::promote(&main, &quarantine).unwrap(),
+            PromoteReport {
+                accounts: 2,
+                new_accounts: 1,
+                pairs: 3,
+                new_pairs: 2,
+            }
+        );
+
+        assert_eq!(main.lookup_by_screen_name("foo").unwrap(), vec![1, 2]);
+        assert_eq!(
+            main.lookup_by_user_id(1).unwrap()["foo"],
+            vec![date(1), date(2)]
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use super::{
+    accounts::AccountTable, screen_names::ScreenNameTable, table::Writeable, weights::WeightTable,
+    Error,
+};
+use chrono::NaiveDate;
+use serde_derive::Serialize;
+use std::collections::HashSet;
+
+/// Rules for discarding old or redundant observation dates.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RetentionPolicy {
+    /// Observation dates before this date are dropped
+    pub earliest: Option<NaiveDate>,
+    /// Keep only the first and last remaining dates for each pair
+    pub thin: bool,
+}
+
+impl RetentionPolicy {
+    /// Select the dates to keep from a pair's sorted dates.
+    pub fn apply(&self, dates: &[NaiveDate]) -> Vec<NaiveDate> {
+        let mut kept = dates
+            .iter()
+            .filter(|date| self.earliest.is_none_or(|earliest| **date >= earliest))
+            .copied()
+            .collect::<Vec<_>>();
+
+        if self.thin && kept.len() > 2 {
+            kept = vec![kept[0], kept[kept.len() - 1]];
+        }
+
+        kept
+    }
+}
+
+/// The changes made (or, for a dry run, that would be made) by applying a retention policy.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct PruneReport {
+    pub pairs_examined: u64,
+    /// Pairs left alone because their account is under legal hold
+    pub pairs_held: u64,
+    pub pairs_rewritten: u64,
+    pub pairs_removed: u64,
+    pub dates_removed: u64,
+    pub weight_dates_removed: u64,
+}
+
+impl std::fmt::Display for PruneReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Pairs examined: {}", self.pairs_examined)?;
+        writeln!(f, "Pairs held: {}", self.pairs_held)?;
+        writeln!(f, "Pairs rewritten: {}", self.pairs_rewritten)?;
+        writeln!(f, "Pairs removed: {}", self.pairs_removed)?;
+        writeln!(f, "Dates removed: {}", self.dates_removed)?;
+        write!(f, "Weight dates removed: {}", self.weight_dates_removed)
+    }
+}
+
+/// Apply a retention policy to every pair in the account table, and its cutoff to the weight table
+/// if there is one.
+///
+/// Pairs with no remaining dates are removed (along with their screen name index entries), but
+/// pairs that never had any dates are left alone, since there's nothing to say about their age.
+/// Accounts under legal hold are skipped entirely.
+pub fn prune(
+    accounts: &AccountTable<Writeable>,
+    screen_names: &ScreenNameTable<Writeable>,
+    weights: Option<&WeightTable<Writeable>>,
+    policy: &RetentionPolicy,
+    held: &HashSet<u64>,
+    dry_run: bool,
+) -> Result<PruneReport, Error> {
+    let mut report = PruneReport::default();
+
+    for pair in accounts.pairs() {
+        let (id, screen_name, dates) = pair?;
+        report.pairs_examined += 1;
+
+        if held.contains(&id) {
+            report.pairs_held += 1;
+            continue;
+        }
+
+        let kept = policy.apply(&dates);
+
+        if kept.len() < dates.len() {
+            report.dates_removed += (dates.len() - kept.len()) as u64;
+
+            if kept.is_empty() {
+                report.pairs_removed += 1;
+
+                if !dry_run {
+                    accounts.remove(id, &screen_name)?;
+                    remove_from_index(accounts, screen_names, id, &screen_name)?;
+                }
+            } else {
+                report.pairs_rewritten += 1;
+
+                if !dry_run {
+                    accounts.replace(id, &screen_name, &kept)?;
+                }
+            }
+        }
+    }
+
+    if let (Some(weights), Some(earliest)) = (weights, policy.earliest) {
+        report.weight_dates_removed = weights.prune(earliest, held, dry_run)?;
+    }
+
+    Ok(report)
+}
+
+/// Drop the account from the screen name's index entry, unless the account still has a pair for a
+/// differently cased form of the screen name.
+pub(crate) fn remove_from_index(
+    accounts: &AccountTable<Writeable>,
+    screen_names: &ScreenNameTable<Writeable>,
+    id: u64,
+    screen_name: &str,
+) -> Result<(), Error> {
+    let screen_name_lower = screen_name.to_lowercase();
+
+    if !accounts
+        .lookup(id)?
+        .keys()
+        .any(|other| other.to_lowercase() == screen_name_lower)
+    {
+        screen_names.remove(screen_name, id)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixture::{self, date};
+    use crate::db::WEIGHTS_TABLE;
+
+    #[test]
+    fn prune() {
+        let (dir, db) = fixture::database();
+        let weights = WeightTable::<Writeable>::open(dir.path().join(WEIGHTS_TABLE)).unwrap();
+
+        db.insert(123, "foo", vec![date(1), date(2)]).unwrap();
+        db.insert(123, "bar", vec![date(5), date(10), date(15), date(20)])
+            .unwrap();
+        db.insert(456, "baz", vec![]).unwrap();
+        db.insert(789, "foo", vec![date(9)]).unwrap();
+        // A differently cased pair keeps the account in the index
+        db.insert(321, "Qux", vec![date(1)]).unwrap();
+        db.insert(321, "qux", vec![date(9)]).unwrap();
+
+        weights
+            .add(123, "foo", &[(date(1), 2), (date(2), 1)])
+            .unwrap();
+        weights
+            .add(123, "bar", &[(date(5), 1), (date(10), 3)])
+            .unwrap();
+
+        // Held accounts keep everything
+        db.insert(555, "held", vec![date(1), date(2), date(3)])
+            .unwrap();
+        weights.add(555, "held", &[(date(1), 1)]).unwrap();
+        let held = HashSet::from([555]);
+
+        let policy = RetentionPolicy {
+            earliest: Some(date(8)),
+            thin: true,
+        };
+        let expected = PruneReport {
+            pairs_examined: 7,
+            pairs_held: 1,
+            pairs_rewritten: 1,
+            pairs_removed: 2,
+            dates_removed: 5,
+            weight_dates_removed: 3,
+        };
+
+        assert_eq!(
+            db.prune(Some(&weights), &policy, &held, true).unwrap(),
+            expected
+        );
+        assert_eq!(db.lookup_by_screen_name("foo").unwrap(), vec![123, 789]);
+        assert_eq!(weights.get(123, "foo").unwrap().len(), 2);
+
+        assert_eq!(
+            db.prune(Some(&weights), &policy, &held, false).unwrap(),
+            expected
+        );
+        assert_eq!(db.accounts.get(123, "foo").unwrap(), None);
+        assert_eq!(
+            db.accounts.get(123, "bar").unwrap(),
+            Some(vec![date(10), date(20)])
+        );
+        assert_eq!(db.accounts.get(456, "baz").unwrap(), Some(vec![]));
+        assert_eq!(db.lookup_by_screen_name("foo").unwrap(), vec![789]);
+        assert_eq!(db.lookup_by_screen_name("qux").unwrap(), vec![321]);
+        assert_eq!(db.lookup_by_screen_name("bar").unwrap(), vec![123]);
+        assert!(weights.get(123, "foo").unwrap().is_empty());
+        assert_eq!(weights.get(123, "bar").unwrap(), vec![(date(10), 3)]);
+        assert_eq!(db.accounts.get(555, "held").unwrap().unwrap().len(), 3);
+        assert_eq!(weights.get(555, "held").unwrap(), vec![(date(1), 1)]);
+    }
+}
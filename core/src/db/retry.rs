@@ -0,0 +1,138 @@
+use super::Error;
+use rocksdb::ErrorKind;
+use std::time::Duration;
+
+/// Error numbers for running out of file descriptors (`ENFILE` and `EMFILE` on Linux and macOS).
+const FILE_TABLE_OVERFLOW: i32 = 23;
+const TOO_MANY_OPEN_FILES: i32 = 24;
+
+/// How often and how patiently to retry writes that fail with a transient error.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt (zero disables retrying)
+    pub retries: u32,
+    /// Delay before the first retry, which doubles with each further retry
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 5,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Run an operation, retrying it with exponential backoff while it fails with a transient
+    /// error.
+    ///
+    /// The operation must be safe to repeat. Other errors are returned immediately, while a
+    /// transient error that outlasts every retry is returned as [`Error::RetriesExhausted`].
+    pub fn run<T, F: FnMut() -> Result<T, Error>>(&self, mut operation: F) -> Result<T, Error> {
+        let mut delay = self.initial_delay;
+        let mut attempts = 0;
+
+        loop {
+            attempts += 1;
+
+            match operation() {
+                Err(error) if is_transient(&error) => {
+                    if attempts > self.retries {
+                        return Err(Error::RetriesExhausted(attempts, Box::new(error)));
+                    }
+
+                    log::warn!(
+                        "Transient database error ({}), retrying in {:?}",
+                        describe(&error),
+                        delay
+                    );
+                    std::thread::sleep(delay);
+                    delay = (delay * 2).min(self.max_delay);
+                }
+                other => return other,
+            }
+        }
+    }
+}
+
+/// Whether an error may go away if the operation is retried (a write stall, or running out of
+/// file descriptors while compactions are holding files open, for example).
+pub fn is_transient(error: &Error) -> bool {
+    match error {
+        Error::Db(error) => match error.kind() {
+            ErrorKind::Busy | ErrorKind::TryAgain | ErrorKind::TimedOut => true,
+            ErrorKind::IOError | ErrorKind::Incomplete => {
+                let message = error.as_ref();
+                message.contains("Too many open files") || message.contains("Write stall")
+            }
+            _ => false,
+        },
+        Error::Io(error) => {
+            matches!(
+                error.raw_os_error(),
+                Some(FILE_TABLE_OVERFLOW | TOO_MANY_OPEN_FILES)
+            ) || matches!(
+                error.kind(),
+                std::io::ErrorKind::Interrupted
+                    | std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::WouldBlock
+            )
+        }
+        _ => false,
+    }
+}
+
+/// The underlying message, since the top-level messages of wrapped errors are generic.
+fn describe(error: &Error) -> String {
+    match error {
+        Error::Db(error) => error.to_string(),
+        Error::Io(error) => error.to_string(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_transient() {
+        let policy = RetryPolicy {
+            retries: 2,
+            initial_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+        };
+        let too_many_open_files = || Error::Io(std::io::Error::from_raw_os_error(24));
+
+        let mut attempts = 0;
+        let result = policy.run(|| {
+            attempts += 1;
+            if attempts < 3 {
+                Err(too_many_open_files())
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        match policy.run(|| Err::<(), _>(too_many_open_files())) {
+            Err(Error::RetriesExhausted(attempts, error)) => {
+                assert_eq!(attempts, 3);
+                assert!(is_transient(&error));
+            }
+            other => panic!("Expected exhausted retries, got {:?}", other),
+        }
+
+        let mut attempts = 0;
+        let result = policy.run(|| {
+            attempts += 1;
+            Err::<(), _>(Error::InvalidTag("".to_string()))
+        });
+        assert!(matches!(result, Err(Error::InvalidTag(_))));
+        assert_eq!(attempts, 1);
+    }
+}
@@ -1,13 +1,18 @@
 use super::{
     accounts::AccountTable,
+    codec::{ids_to_value, key_to_screen_name, screen_name_to_key, value_to_ids},
+    options::DatabaseOptions,
     table::{Mode, Table, Writeable},
     Error,
 };
-use rocksdb::{IteratorMode, MergeOperands, Options, DB};
+use rocksdb::{Direction, IteratorMode, MergeOperands, Options, WriteBatch, DB};
 use std::convert::TryInto;
 use std::marker::PhantomData;
 use std::path::Path;
 
+/// Number of merges to accumulate before writing when rebuilding the index.
+const REBUILD_BATCH_SIZE: usize = 100_000;
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct ScreenNameTableCounts {
     pub screen_name_count: u64,
@@ -16,6 +21,7 @@ pub struct ScreenNameTableCounts {
 
 pub struct ScreenNameTable<M> {
     db: Option<DB>,
+    options: DatabaseOptions,
     mode: PhantomData<M>,
 }
 
@@ -53,10 +59,11 @@ impl<M> Table for ScreenNameTable<M> {
 }
 
 impl<M> ScreenNameTable<M> {
-    fn make_options() -> Options {
+    fn make_options(database_options: &DatabaseOptions) -> Options {
         let mut options = Options::default();
         options.create_if_missing(true);
         options.set_merge_operator_associative("merge", merge);
+        database_options.apply(&mut options);
         options
     }
 
@@ -72,31 +79,146 @@ impl<M> ScreenNameTable<M> {
             .unwrap_or_else(|| Ok(vec![]))
     }
 
+    /// Look up several screen names with a single batched read.
+    pub fn lookup_many(&self, screen_names: &[&str]) -> Result<Vec<Vec<u64>>, Error> {
+        self.db
+            .as_ref()
+            .unwrap()
+            .multi_get(
+                screen_names
+                    .iter()
+                    .map(|screen_name| screen_name_to_key(screen_name)),
+            )
+            .into_iter()
+            .map(|result| {
+                result?
+                    .map(|value| value_to_ids(&value))
+                    .unwrap_or_else(|| Ok(vec![]))
+            })
+            .collect()
+    }
+
     pub fn lookup_by_prefix(
         &self,
         screen_name: &str,
         limit: usize,
     ) -> Result<Vec<(String, Vec<u64>)>, Error> {
-        let prefix = screen_name_to_key(screen_name);
-        let iter = self.db.as_ref().unwrap().prefix_iterator(&prefix);
-        let mut results = Vec::with_capacity(1);
+        self.with_prefix(screen_name).take(limit).collect()
+    }
 
-        for result in iter.take(limit) {
-            let (key, value) = result?;
+    /// All screen names (lowercased) starting with a prefix, in order, with their accounts.
+    ///
+    /// An empty prefix scans the entire index.
+    pub fn with_prefix(
+        &self,
+        screen_name_prefix: &str,
+    ) -> impl Iterator<Item = Result<(String, Vec<u64>), Error>> + '_ {
+        let prefix = screen_name_to_key(screen_name_prefix);
 
-            if key.starts_with(&prefix) {
-                let screen_name = key_to_screen_name(&key)?;
-                let ids = value_to_ids(&value)?;
+        self.db
+            .as_ref()
+            .unwrap()
+            .iterator(IteratorMode::From(&prefix, Direction::Forward))
+            .map_while(move |result| match result {
+                Ok((key, value)) if key.starts_with(&prefix) => {
+                    Some(key_to_screen_name(&key).and_then(|screen_name| {
+                        Ok((screen_name.to_string(), value_to_ids(&value)?))
+                    }))
+                }
+                Ok(_) => None,
+                Err(error) => Some(Err(error.into())),
+            })
+    }
 
-                results.push((screen_name.to_string(), ids));
-            } else {
-                break;
+    /// All screen names (lowercased) within an edit distance of a screen name, in order, with their
+    /// accounts and distances.
+    ///
+    /// The index is traversed as a trie: the Levenshtein matrix row for each key prefix is shared
+    /// by all keys with that prefix, and once a prefix is too far from the query, every key that
+    /// starts with it is skipped with a single seek. For small distances only a small part of the
+    /// index is read.
+    pub fn within_distance(
+        &self,
+        screen_name: &str,
+        max_distance: usize,
+    ) -> Result<Vec<(String, Vec<u64>, usize)>, Error> {
+        let query = screen_name_to_key(screen_name);
+        let mut iter = self.db.as_ref().unwrap().raw_iterator();
+        iter.seek_to_first();
+
+        // The matrix rows for each prefix of the previous key that has been processed
+        let mut rows: Vec<Vec<usize>> = vec![(0..=query.len()).collect()];
+        let mut previous_key = vec![];
+        let mut results = vec![];
+
+        while let Some(key) = iter.key() {
+            let key = key.to_vec();
+            let common_len = key
+                .iter()
+                .zip(&previous_key)
+                .take_while(|(a, b)| a == b)
+                .count();
+            rows.truncate(common_len + 1);
+
+            let mut pruned_len = None;
+
+            for (len, byte) in key.iter().enumerate().skip(rows.len() - 1) {
+                let row = next_distance_row(&rows[len], &query, *byte);
+                let min = row.iter().copied().min().unwrap_or_default();
+                rows.push(row);
+
+                if min > max_distance {
+                    pruned_len = Some(len + 1);
+                    break;
+                }
             }
+
+            match pruned_len {
+                Some(len) => match successor(&key[..len]) {
+                    Some(next) => iter.seek(next),
+                    None => break,
+                },
+                None => {
+                    let distance = rows[key.len()][query.len()];
+
+                    if distance <= max_distance {
+                        let ids = iter.value().map(value_to_ids).transpose()?;
+
+                        results.push((
+                            key_to_screen_name(&key)?.to_string(),
+                            ids.unwrap_or_default(),
+                            distance,
+                        ));
+                    }
+
+                    iter.next();
+                }
+            }
+
+            previous_key = key;
         }
 
+        iter.status()?;
         Ok(results)
     }
 
+    /// All screen names that are associated with more than one account.
+    pub fn reused(&self) -> impl Iterator<Item = Result<(String, Vec<u64>), Error>> + '_ {
+        self.db
+            .as_ref()
+            .unwrap()
+            .iterator(IteratorMode::Start)
+            .filter_map(|result| match result {
+                Ok((key, value)) if value.len() > 8 => {
+                    Some(key_to_screen_name(&key).and_then(|screen_name| {
+                        Ok((screen_name.to_string(), value_to_ids(&value)?))
+                    }))
+                }
+                Ok(_) => None,
+                Err(error) => Some(Err(error.into())),
+            })
+    }
+
     pub fn get_most_reused(&self, k: usize) -> Result<Vec<(String, Vec<u64>)>, Error> {
         let mut queue = priority_queue::DoublePriorityQueue::with_capacity(k);
         let iter = self.db.as_ref().unwrap().iterator(IteratorMode::Start);
@@ -124,7 +246,14 @@ impl<M> ScreenNameTable<M> {
 
 impl<M: Mode> ScreenNameTable<M> {
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
-        let options = Self::make_options();
+        Self::open_with_options(path, &DatabaseOptions::default())
+    }
+
+    pub fn open_with_options<P: AsRef<Path>>(
+        path: P,
+        database_options: &DatabaseOptions,
+    ) -> Result<Self, Error> {
+        let options = Self::make_options(database_options);
         let db = if M::is_read_only() {
             DB::open_for_read_only(&options, path, true)?
         } else {
@@ -133,6 +262,7 @@ impl<M: Mode> ScreenNameTable<M> {
 
         Ok(Self {
             db: Some(db),
+            options: database_options.clone(),
             mode: PhantomData,
         })
     }
@@ -147,24 +277,100 @@ impl ScreenNameTable<Writeable> {
             .merge(screen_name_to_key(screen_name), id.to_be_bytes())?)
     }
 
+    /// Remove an account from a screen name's entry, deleting the entry if no accounts remain.
+    pub fn remove(&self, screen_name: &str, id: u64) -> Result<(), Error> {
+        let db = self.db.as_ref().unwrap();
+        let key = screen_name_to_key(screen_name);
+        let mut ids = self.lookup(screen_name)?;
+        ids.retain(|other_id| *other_id != id);
+
+        if ids.is_empty() {
+            Ok(db.delete(key)?)
+        } else {
+            Ok(db.put(key, ids_to_value(&ids))?)
+        }
+    }
+
+    /// Rebuild the index from the account table.
+    ///
+    /// The new index is written to a sibling directory and only swapped in once it is complete, so
+    /// the existing index is left open and unchanged if the rebuild fails.
     pub fn rebuild<Mode>(&mut self, accounts: &AccountTable<Mode>) -> Result<(), Error> {
         let path = self.db.as_ref().unwrap().path().to_path_buf();
-        self.db.take().unwrap();
+        let new_path = path.with_extension("rebuild");
+        let old_path = path.with_extension("old");
+        let options = Self::make_options(&self.options);
 
-        let options = Self::make_options();
+        // Clear anything left behind by an interrupted rebuild
+        DB::destroy(&options, &new_path)?;
+        DB::destroy(&options, &old_path)?;
 
-        DB::destroy(&options, &path)?;
+        if let Err(error) = Self::build(&options, &new_path, accounts) {
+            DB::destroy(&options, &new_path)?;
+            return Err(error);
+        }
 
+        self.db.take();
+        std::fs::rename(&path, &old_path)?;
+        std::fs::rename(&new_path, &path)?;
         self.db = Some(DB::open(&options, &path)?);
 
+        DB::destroy(&options, &old_path)?;
+
+        Ok(())
+    }
+
+    fn build<Mode>(
+        options: &Options,
+        path: &Path,
+        accounts: &AccountTable<Mode>,
+    ) -> Result<(), Error> {
+        let db = DB::open(options, path)?;
+        let mut batch = WriteBatch::default();
+
         for pair in accounts.pairs() {
             let (id, screen_name, _) = pair?;
 
-            self.insert(&screen_name, id)?;
+            batch.merge(screen_name_to_key(&screen_name), id.to_be_bytes());
+
+            if batch.len() >= REBUILD_BATCH_SIZE {
+                db.write(std::mem::take(&mut batch))?;
+            }
         }
 
-        Ok(())
+        if !batch.is_empty() {
+            db.write(batch)?;
+        }
+
+        Ok(db.flush()?)
+    }
+}
+
+/// The Levenshtein matrix row for a key prefix extended by one byte.
+fn next_distance_row(row: &[usize], query: &[u8], byte: u8) -> Vec<usize> {
+    let mut next = Vec::with_capacity(row.len());
+    next.push(row[0] + 1);
+
+    for (j, query_byte) in query.iter().enumerate() {
+        let substitution = row[j] + usize::from(*query_byte != byte);
+        next.push(substitution.min(row[j + 1] + 1).min(next[j] + 1));
+    }
+
+    next
+}
+
+/// The first key after every key starting with the given prefix, if there is one.
+fn successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut next = prefix.to_vec();
+
+    while let Some(last) = next.pop() {
+        if last < u8::MAX {
+            next.push(last + 1);
+            return Some(next);
+        }
     }
+
+    None
 }
 
 fn merge(
@@ -184,7 +390,7 @@ fn merge(
     Some(new_val)
 }
 
-fn merge_for_screen_name(a: &mut Vec<u8>, b: &[u8]) {
+pub(crate) fn merge_for_screen_name(a: &mut Vec<u8>, b: &[u8]) {
     let original_len = a.len();
     let mut i = 0;
 
@@ -221,28 +427,57 @@ fn merge_for_screen_name(a: &mut Vec<u8>, b: &[u8]) {
     }
 }
 
-fn screen_name_to_key(screen_name: &str) -> Vec<u8> {
-    let form = screen_name.to_lowercase();
-    form.as_bytes().to_vec()
-}
+#[cfg(test)]
+mod tests {
+    use crate::db::{fixture, phonetic};
 
-fn key_to_screen_name(key: &[u8]) -> Result<&str, Error> {
-    Ok(std::str::from_utf8(key)?)
-}
+    #[test]
+    fn screen_names_within_distance() {
+        let (_dir, db) = fixture::database();
+        let screen_names = [
+            "jack", "JACK", "jacck", "jak", "jaxk", "jackie", "jill", "ajack", "black", "j",
+        ];
 
-fn value_to_ids(value: &[u8]) -> Result<Vec<u64>, Error> {
-    let mut result = Vec::with_capacity(value.len() / 8);
-    let mut i = 0;
+        for (id, screen_name) in screen_names.iter().enumerate() {
+            db.insert(id as u64, screen_name, vec![]).unwrap();
+        }
+
+        let within = |screen_name, max_distance| {
+            db.screen_names_within_distance(screen_name, max_distance)
+                .unwrap()
+                .into_iter()
+                .map(|similar| (similar.screen_name, similar.distance))
+                .collect::<Vec<_>>()
+        };
 
-    while i < value.len() {
-        let id = u64::from_be_bytes(
-            value[i..i + 8]
-                .try_into()
-                .map_err(|_| Error::InvalidValue(value.to_vec()))?,
+        assert_eq!(within("Jack", 0), vec![("jack".to_string(), 0)]);
+        assert_eq!(
+            within("jack", 1),
+            vec![
+                ("jack".to_string(), 0),
+                ("ajack".to_string(), 1),
+                ("jacck".to_string(), 1),
+                ("jak".to_string(), 1),
+                ("jaxk".to_string(), 1),
+            ]
+        );
+        assert_eq!(within("jack", 2).len(), 7);
+        assert_eq!(
+            db.screen_names_within_distance("jack", 0).unwrap()[0].user_ids,
+            vec![0, 1]
         );
-        result.push(id);
-        i += 8;
-    }
 
-    Ok(result)
+        // The traversal agrees with computing every distance
+        for max_distance in 0..5 {
+            let expected = screen_names
+                .iter()
+                .map(|screen_name| screen_name.to_lowercase())
+                .collect::<std::collections::BTreeSet<_>>()
+                .into_iter()
+                .filter(|screen_name| phonetic::edit_distance("jack", screen_name) <= max_distance)
+                .count();
+
+            assert_eq!(within("jack", max_distance).len(), expected);
+        }
+    }
 }
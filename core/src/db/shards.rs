@@ -0,0 +1,356 @@
+use super::{
+    accounts::AccountTableCounts,
+    options::DatabaseOptions,
+    screen_names::ScreenNameTableCounts,
+    table::{Mode, Table, Writeable},
+    Database, Error, ACCOUNTS_TABLE,
+};
+use chrono::NaiveDate;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+/// File in the root directory of a sharded database that records the number of shards.
+pub const SHARDS_FILE: &str = "shards.json";
+
+/// The shard layout stored in [`SHARDS_FILE`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct ShardLayout {
+    pub count: usize,
+}
+
+impl ShardLayout {
+    /// Read the layout of a sharded database, or `None` if the directory isn't sharded.
+    pub fn load<P: AsRef<Path>>(base: P) -> Result<Option<Self>, Error> {
+        let path = base.as_ref().join(SHARDS_FILE);
+
+        if path.exists() {
+            Ok(Some(serde_json::from_slice(&std::fs::read(path)?)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, base: P) -> Result<(), Error> {
+        Ok(std::fs::write(
+            base.as_ref().join(SHARDS_FILE),
+            serde_json::to_vec_pretty(self)?,
+        )?)
+    }
+
+    /// The index of the shard that stores an account.
+    pub fn shard_for(&self, id: u64) -> usize {
+        shard_for(id, self.count)
+    }
+}
+
+/// The directory of a shard within a sharded database directory.
+pub fn shard_path<P: AsRef<Path>>(base: P, index: usize) -> PathBuf {
+    base.as_ref().join(format!("shard-{:03}", index))
+}
+
+/// The index of the shard that stores an account, given the number of shards.
+///
+/// IDs are hashed (with the SplitMix64 finalizer) before being reduced, since both sequential and
+/// snowflake IDs are far from uniformly distributed modulo small numbers.
+pub fn shard_for(id: u64, count: usize) -> usize {
+    let mut hash = id;
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^= hash >> 31;
+
+    (hash % count.max(1) as u64) as usize
+}
+
+/// The result of moving pairs to the shards they belong in.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RebalanceReport {
+    pub pairs_kept: usize,
+    pub pairs_moved: usize,
+    pub shards_removed: usize,
+}
+
+/// Several databases, each storing the accounts whose IDs hash to it.
+///
+/// Lookups by ID go to a single shard, while lookups by screen name are sent to every shard and
+/// their results merged. Only the account and screen name tables (and their indexes) are sharded;
+/// other tables are still opened from the root directory.
+pub struct ShardedLookup<M> {
+    layout: ShardLayout,
+    shards: Vec<Database<M>>,
+}
+
+impl<M: Sync + Send + 'static> ShardedLookup<M> {
+    pub fn layout(&self) -> ShardLayout {
+        self.layout
+    }
+
+    pub fn shards(&self) -> &[Database<M>] {
+        &self.shards
+    }
+
+    /// The shard that stores an account.
+    pub fn shard(&self, id: u64) -> &Database<M> {
+        &self.shards[self.layout.shard_for(id)]
+    }
+
+    /// Counts for each shard, in shard order.
+    pub fn get_counts(&self) -> Result<Vec<(AccountTableCounts, ScreenNameTableCounts)>, Error> {
+        self.fan_out(|shard| {
+            Ok((
+                shard.accounts.get_counts()?,
+                shard.screen_names.get_counts()?,
+            ))
+        })
+    }
+
+    pub fn lookup_by_user_id(
+        &self,
+        user_id: u64,
+    ) -> Result<HashMap<String, Vec<NaiveDate>>, Error> {
+        self.shard(user_id).lookup_by_user_id(user_id)
+    }
+
+    pub fn lookup_by_screen_name(&self, screen_name: &str) -> Result<Vec<u64>, Error> {
+        let mut results = self
+            .fan_out(|shard| shard.lookup_by_screen_name(screen_name))?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        results.sort_unstable();
+        results.dedup();
+
+        Ok(results)
+    }
+
+    pub fn lookup_by_screen_name_prefix(
+        &self,
+        screen_name_prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, Vec<u64>)>, Error> {
+        let mut merged = BTreeMap::<String, Vec<u64>>::new();
+
+        // Each shard's first `limit` screen names include every screen name in the merged first
+        // `limit`
+        for results in
+            self.fan_out(|shard| shard.lookup_by_screen_name_prefix(screen_name_prefix, limit))?
+        {
+            for (screen_name, ids) in results {
+                merged.entry(screen_name).or_default().extend(ids);
+            }
+        }
+
+        Ok(merged
+            .into_iter()
+            .take(limit)
+            .map(|(screen_name, mut ids)| {
+                ids.sort_unstable();
+                ids.dedup();
+                (screen_name, ids)
+            })
+            .collect())
+    }
+
+    /// Run a query against every shard concurrently, returning the results in shard order.
+    fn fan_out<T: Send, F: Fn(&Database<M>) -> Result<T, Error> + Sync>(
+        &self,
+        query: F,
+    ) -> Result<Vec<T>, Error> {
+        std::thread::scope(|scope| {
+            let handles = self
+                .shards
+                .iter()
+                .map(|shard| scope.spawn(|| query(shard)))
+                .collect::<Vec<_>>();
+
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                })
+                .collect()
+        })
+    }
+}
+
+impl<M: Mode> ShardedLookup<M> {
+    /// Open a sharded database, or `None` if the directory isn't sharded.
+    pub fn open<P: AsRef<Path>>(base: P, options: &DatabaseOptions) -> Result<Option<Self>, Error> {
+        let base = base.as_ref();
+
+        match ShardLayout::load(base)? {
+            Some(layout) => Ok(Some(Self {
+                layout,
+                shards: (0..layout.count)
+                    .map(|index| Database::open_with_options(shard_path(base, index), options))
+                    .collect::<Result<_, _>>()?,
+            })),
+            None => Ok(None),
+        }
+    }
+}
+
+impl ShardedLookup<Writeable> {
+    pub fn insert(&self, id: u64, screen_name: &str, dates: Vec<NaiveDate>) -> Result<(), Error> {
+        self.shard(id).insert(id, screen_name, dates)
+    }
+
+    /// Move every pair to the shard it belongs in under a new number of shards.
+    ///
+    /// Pairs in the root directory's account table are also moved into the shards, so this both
+    /// splits an unsharded database (creating its layout) and distributes pairs that have since
+    /// been imported into the root directory. Other tables in the root directory are left in
+    /// place.
+    ///
+    /// Pairs are copied to their new shard before being removed from their old one, so an
+    /// interrupted rebalance can be completed by running it again with the same count.
+    pub fn rebalance<P: AsRef<Path>>(
+        base: P,
+        count: usize,
+        options: &DatabaseOptions,
+    ) -> Result<RebalanceReport, Error> {
+        let base = base.as_ref();
+        let layout = ShardLayout {
+            count: count.max(1),
+        };
+
+        // Shards left over from a larger layout (or an interrupted rebalance) are drained too
+        let mut existing = 0;
+        while shard_path(base, existing).exists() {
+            existing += 1;
+        }
+
+        let shards = (0..existing.max(layout.count))
+            .map(|index| {
+                let path = shard_path(base, index);
+                std::fs::create_dir_all(&path)?;
+                Database::<Writeable>::open_with_options(path, options)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Pairs in the root directory's own tables (an unsharded database, or later imports into
+        // the root directory) are moved into the shards
+        let root = if base.join(ACCOUNTS_TABLE).exists() {
+            Some(Database::<Writeable>::open_with_options(base, options)?)
+        } else {
+            None
+        };
+
+        // Every iterator is created before anything is moved, so that pairs are only visited in
+        // the shard they started in
+        let sources = root
+            .iter()
+            .map(|root| (None, root))
+            .chain(
+                shards
+                    .iter()
+                    .enumerate()
+                    .map(|(index, shard)| (Some(index), shard)),
+            )
+            .map(|(index, source)| (index, source, source.accounts.pairs()))
+            .collect::<Vec<_>>();
+
+        let mut report = RebalanceReport::default();
+
+        for (index, source, pairs) in sources {
+            for result in pairs {
+                let (id, screen_name, dates) = result?;
+                let target = layout.shard_for(id);
+
+                if Some(target) == index {
+                    report.pairs_kept += 1;
+                } else {
+                    shards[target].insert(id, &screen_name, dates)?;
+                    source.accounts.remove(id, &screen_name)?;
+                    source.screen_names.remove(&screen_name, id)?;
+                    report.pairs_moved += 1;
+                }
+            }
+        }
+
+        drop(root);
+        drop(shards);
+
+        for index in layout.count..existing {
+            std::fs::remove_dir_all(shard_path(base, index))?;
+            report.shards_removed += 1;
+        }
+
+        layout.save(base)?;
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::ReadOnly;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2020, 1, day).unwrap()
+    }
+
+    #[test]
+    fn rebalance() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = DatabaseOptions::default();
+
+        assert!(ShardedLookup::<ReadOnly>::open(dir.path(), &options)
+            .unwrap()
+            .is_none());
+
+        ShardedLookup::<Writeable>::rebalance(dir.path(), 2, &options).unwrap();
+        let db = ShardedLookup::<Writeable>::open(dir.path(), &options)
+            .unwrap()
+            .unwrap();
+
+        for id in 1..=20 {
+            db.insert(id, &format!("user{}", id), vec![date(1)])
+                .unwrap();
+            db.insert(id, "shared", vec![date(2)]).unwrap();
+        }
+        drop(db);
+
+        let report = ShardedLookup::<Writeable>::rebalance(dir.path(), 5, &options).unwrap();
+        assert_eq!(report.pairs_kept + report.pairs_moved, 40);
+        assert!(report.pairs_moved > 0);
+
+        let report = ShardedLookup::<Writeable>::rebalance(dir.path(), 3, &options).unwrap();
+        assert_eq!(report.shards_removed, 2);
+        assert!(!shard_path(dir.path(), 3).exists());
+
+        let db = ShardedLookup::<ReadOnly>::open(dir.path(), &options)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(db.layout(), ShardLayout { count: 3 });
+        assert_eq!(
+            db.lookup_by_screen_name("shared").unwrap(),
+            (1..=20).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            db.lookup_by_user_id(7).unwrap(),
+            HashMap::from([
+                ("user7".to_string(), vec![date(1)]),
+                ("shared".to_string(), vec![date(2)])
+            ])
+        );
+        assert_eq!(
+            db.lookup_by_screen_name_prefix("user1", 3).unwrap(),
+            vec![
+                ("user1".to_string(), vec![1]),
+                ("user10".to_string(), vec![10]),
+                ("user11".to_string(), vec![11])
+            ]
+        );
+
+        for (index, shard) in db.shards().iter().enumerate() {
+            for result in shard.accounts.pairs() {
+                assert_eq!(shard_for(result.unwrap().0, 3), index);
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use super::Error;
+use chrono::{DateTime, Utc};
+use rocksdb::perf::{set_perf_stats, PerfContext, PerfMetric, PerfStatsLevel};
+use serde_derive::Serialize;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// RocksDB perf-context counters for the work a query did, which show whether it was slow
+/// because of its size (many keys read) or because of a cold cache (many blocks read from disk).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct PerfCounters {
+    pub block_cache_hits: u64,
+    pub block_reads: u64,
+    pub block_read_bytes: u64,
+    pub memtable_gets: u64,
+    pub memtable_seeks: u64,
+    pub bloom_filter_hits: u64,
+    pub bloom_filter_misses: u64,
+    pub internal_keys_skipped: u64,
+    pub internal_deletes_skipped: u64,
+}
+
+impl PerfCounters {
+    fn from_context(context: &PerfContext) -> Self {
+        Self {
+            block_cache_hits: context.metric(PerfMetric::BlockCacheHitCount),
+            block_reads: context.metric(PerfMetric::BlockReadCount),
+            block_read_bytes: context.metric(PerfMetric::BlockReadByte),
+            memtable_gets: context.metric(PerfMetric::GetFromMemtableCount),
+            memtable_seeks: context.metric(PerfMetric::SeekOnMemtableCount),
+            bloom_filter_hits: context.metric(PerfMetric::BloomSstHitCount),
+            bloom_filter_misses: context.metric(PerfMetric::BloomSstMissCount),
+            internal_keys_skipped: context.metric(PerfMetric::InternalKeySkippedCount),
+            internal_deletes_skipped: context.metric(PerfMetric::InternalDeleteSkippedCount),
+        }
+    }
+}
+
+/// An entry in a slow query log.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct SlowQuery<'a> {
+    pub time: DateTime<Utc>,
+    pub query: &'a str,
+    pub duration_us: u64,
+    pub result_bytes: usize,
+    pub perf: PerfCounters,
+}
+
+/// Measures a query, counting the RocksDB operations done on the current thread.
+///
+/// Perf-context counters are thread-local, so the query must run on the thread that started the
+/// timer.
+pub struct QueryTimer {
+    started: Instant,
+    context: PerfContext,
+}
+
+impl QueryTimer {
+    pub fn start() -> Self {
+        // Counting is cheap, unlike timing every operation
+        set_perf_stats(PerfStatsLevel::EnableCount);
+
+        let mut context = PerfContext::default();
+        context.reset();
+
+        Self {
+            started: Instant::now(),
+            context,
+        }
+    }
+}
+
+/// Appends queries that take longer than a threshold to a file, one JSON object per line.
+pub struct SlowQueryLog {
+    threshold: Duration,
+    file: Mutex<File>,
+}
+
+impl SlowQueryLog {
+    pub fn open<P: AsRef<Path>>(path: P, threshold: Duration) -> Result<Self, Error> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+
+        Ok(Self {
+            threshold,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Log a query if it took at least the threshold, returning whether it did.
+    ///
+    /// Write failures are logged rather than returned, since they shouldn't fail queries.
+    pub fn record(&self, query: &str, timer: QueryTimer, result_bytes: usize) -> bool {
+        let duration = timer.started.elapsed();
+
+        if duration < self.threshold {
+            return false;
+        }
+
+        let entry = SlowQuery {
+            time: Utc::now(),
+            query,
+            duration_us: duration.as_micros() as u64,
+            result_bytes,
+            perf: PerfCounters::from_context(&timer.context),
+        };
+
+        let mut file = self
+            .file
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if let Err(error) = serde_json::to_string(&entry)
+            .map_err(Error::from)
+            .and_then(|line| Ok(writeln!(file, "{}", line)?))
+        {
+            log::warn!("Failed to write slow query log: {:?}", error);
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Database, Writeable};
+
+    #[test]
+    fn slow_queries() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::<Writeable>::open(dir.path().join("db")).unwrap();
+        db.insert(1, "foo", vec![]).unwrap();
+
+        let path = dir.path().join("slow-queries.ndjson");
+        let log = SlowQueryLog::open(&path, Duration::ZERO).unwrap();
+        let timer = QueryTimer::start();
+        db.lookup_by_user_id(1).unwrap();
+        assert!(log.record("/tw/id/1", timer, 42));
+
+        let never = SlowQueryLog::open(&path, Duration::from_secs(3600)).unwrap();
+        assert!(!never.record("/tw/foo", QueryTimer::start(), 0));
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines = contents.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 1);
+
+        let entry: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(entry["query"], "/tw/id/1");
+        assert_eq!(entry["result_bytes"], 42);
+        assert!(entry["perf"]["memtable_seeks"].as_u64().unwrap() > 0);
+    }
+}
@@ -0,0 +1,328 @@
+use super::{
+    codec::{date_to_day_id, day_id_to_date},
+    options::DatabaseOptions,
+    table::{Mode, Table, Writeable},
+    Error,
+};
+use crate::model::ImportRecord;
+use chrono::NaiveDate;
+use rocksdb::{Direction, IteratorMode, MergeOperands, Options, DB};
+use serde_derive::Serialize;
+use std::marker::PhantomData;
+use std::path::Path;
+
+const DAY_PREFIX: u8 = b'd';
+const TOTALS_KEY: &[u8] = b"t";
+const COUNTER_LEN: usize = 8;
+
+/// Counts of imported data for a single day (the day of the import, not of the observations).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct DailyIngest {
+    pub imports: u64,
+    pub lines_read: u64,
+    pub new_accounts: u64,
+    pub new_pairs: u64,
+    pub dates_added: u64,
+}
+
+impl DailyIngest {
+    fn to_value(self) -> Vec<u8> {
+        counters_to_value(&[
+            self.imports,
+            self.lines_read,
+            self.new_accounts,
+            self.new_pairs,
+            self.dates_added,
+        ])
+    }
+
+    fn from_value(value: &[u8]) -> Result<Self, Error> {
+        let counters = value_to_counters(value)?;
+
+        Ok(Self {
+            imports: counter(&counters, 0),
+            lines_read: counter(&counters, 1),
+            new_accounts: counter(&counters, 2),
+            new_pairs: counter(&counters, 3),
+            dates_added: counter(&counters, 4),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct Totals {
+    pub accounts: u64,
+    pub pairs: u64,
+}
+
+impl Totals {
+    fn to_value(self) -> Vec<u8> {
+        counters_to_value(&[self.accounts, self.pairs])
+    }
+
+    fn from_value(value: &[u8]) -> Result<Self, Error> {
+        let counters = value_to_counters(value)?;
+
+        Ok(Self {
+            accounts: counter(&counters, 0),
+            pairs: counter(&counters, 1),
+        })
+    }
+}
+
+/// Incrementally maintained dataset statistics, cheap enough to serve publicly.
+///
+/// Every recorded import adds to its day's ingest counts and to the totals. Removals and pruning
+/// are not tracked, so the totals should be reset from a full count after either.
+pub struct StatsTable<M> {
+    db: DB,
+    mode: PhantomData<M>,
+}
+
+impl<M> Table for StatsTable<M> {
+    type Counts = u64;
+
+    fn underlying(&self) -> &DB {
+        &self.db
+    }
+
+    /// The number of days with recorded imports.
+    fn get_counts(&self) -> Result<Self::Counts, Error> {
+        Ok(self.daily(None)?.len() as u64)
+    }
+}
+
+impl<M> StatsTable<M> {
+    pub fn totals(&self) -> Result<Totals, Error> {
+        self.db
+            .get_pinned(TOTALS_KEY)?
+            .map_or_else(|| Ok(Totals::default()), |value| Totals::from_value(&value))
+    }
+
+    /// Ingest counts for every day with recorded imports, starting from the given day.
+    pub fn daily(&self, since: Option<NaiveDate>) -> Result<Vec<(NaiveDate, DailyIngest)>, Error> {
+        let start = match since {
+            Some(date) => day_to_key(date_to_day_id(&date)?),
+            None => vec![DAY_PREFIX],
+        };
+        let mut results = vec![];
+
+        for result in self
+            .db
+            .iterator(IteratorMode::From(&start, Direction::Forward))
+        {
+            let (key, value) = result?;
+
+            if key.first() != Some(&DAY_PREFIX) {
+                break;
+            }
+
+            results.push((key_to_date(&key)?, DailyIngest::from_value(&value)?));
+        }
+
+        Ok(results)
+    }
+}
+
+impl<M: Mode> StatsTable<M> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::open_with_options(path, &DatabaseOptions::default())
+    }
+
+    pub fn open_with_options<P: AsRef<Path>>(
+        path: P,
+        database_options: &DatabaseOptions,
+    ) -> Result<Self, Error> {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        options.set_merge_operator_associative("merge", merge);
+        database_options.apply(&mut options);
+
+        let db = if M::is_read_only() {
+            DB::open_for_read_only(&options, path, true)?
+        } else {
+            DB::open(&options, path)?
+        };
+
+        Ok(Self {
+            db,
+            mode: PhantomData,
+        })
+    }
+}
+
+impl StatsTable<Writeable> {
+    /// Add a completed import to its day's counts and to the totals.
+    pub fn record_import(&self, record: &ImportRecord) -> Result<(), Error> {
+        let ingest = DailyIngest {
+            imports: 1,
+            lines_read: record.lines_read,
+            new_accounts: record.new_accounts,
+            new_pairs: record.new_pairs,
+            dates_added: record.dates_added,
+        };
+        let totals = Totals {
+            accounts: record.new_accounts,
+            pairs: record.new_pairs,
+        };
+        let day_id = date_to_day_id(&record.timestamp.date_naive())?;
+
+        self.db.merge(day_to_key(day_id), ingest.to_value())?;
+        Ok(self.db.merge(TOTALS_KEY, totals.to_value())?)
+    }
+
+    /// Add accounts and pairs that were written without an import (for example by promoting a
+    /// quarantine) to the totals.
+    pub fn add_totals(&self, totals: &Totals) -> Result<(), Error> {
+        Ok(self.db.merge(TOTALS_KEY, totals.to_value())?)
+    }
+
+    /// Replace the totals (for example with the results of a full count).
+    pub fn set_totals(&self, totals: &Totals) -> Result<(), Error> {
+        Ok(self.db.put(TOTALS_KEY, totals.to_value())?)
+    }
+}
+
+fn day_to_key(day_id: u16) -> Vec<u8> {
+    let mut key = Vec::with_capacity(3);
+    key.push(DAY_PREFIX);
+    key.extend_from_slice(&day_id.to_be_bytes());
+    key
+}
+
+fn key_to_date(key: &[u8]) -> Result<NaiveDate, Error> {
+    match key {
+        [DAY_PREFIX, first, second] => Ok(day_id_to_date(u16::from_be_bytes([*first, *second]))),
+        _ => Err(Error::InvalidKey(key.to_vec())),
+    }
+}
+
+/// Values are sequences of big-endian counters, so that fields can be added later.
+fn counters_to_value(counters: &[u64]) -> Vec<u8> {
+    counters
+        .iter()
+        .flat_map(|counter| counter.to_be_bytes())
+        .collect()
+}
+
+fn value_to_counters(value: &[u8]) -> Result<Vec<u64>, Error> {
+    if value.len().is_multiple_of(COUNTER_LEN) {
+        Ok(value
+            .chunks_exact(COUNTER_LEN)
+            .map(|bytes| u64::from_be_bytes(bytes.try_into().unwrap()))
+            .collect())
+    } else {
+        Err(Error::InvalidValue(value.to_vec()))
+    }
+}
+
+fn counter(counters: &[u64], index: usize) -> u64 {
+    counters.get(index).copied().unwrap_or_default()
+}
+
+/// Add counters element-wise.
+fn merge(
+    _new_key: &[u8],
+    existing_val: Option<&[u8]>,
+    operands: &MergeOperands,
+) -> Option<Vec<u8>> {
+    let mut counters = match existing_val.map(value_to_counters).transpose() {
+        Ok(counters) => counters.unwrap_or_default(),
+        Err(error) => {
+            log::error!("{}", error);
+            return None;
+        }
+    };
+
+    for operand in operands.iter() {
+        match value_to_counters(operand) {
+            Ok(operand) => {
+                if counters.len() < operand.len() {
+                    counters.resize(operand.len(), 0);
+                }
+
+                for (counter, value) in counters.iter_mut().zip(operand) {
+                    *counter = counter.saturating_add(value);
+                }
+            }
+            Err(error) => {
+                log::error!("{}", error);
+                return None;
+            }
+        }
+    }
+
+    Some(counters_to_value(&counters))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixture;
+
+    #[test]
+    fn stats() {
+        use chrono::{TimeZone, Utc};
+
+        let (_dir, stats) = fixture::open(StatsTable::<Writeable>::open);
+        let record = |day, new_accounts| crate::model::ImportRecord {
+            timestamp: Utc.with_ymd_and_hms(2023, 1, day, 12, 0, 0).unwrap(),
+            source: "stdin".to_string(),
+            hash: None,
+            lines_read: 10,
+            lines_skipped: 0,
+            pairs: 5,
+            new_pairs: 4,
+            new_accounts,
+            dates_added: 3,
+            duration_ms: 100,
+            version: "0.0.0".to_string(),
+        };
+        let date = |day| NaiveDate::from_ymd_opt(2023, 1, day).unwrap();
+
+        stats
+            .set_totals(&Totals {
+                accounts: 100,
+                pairs: 200,
+            })
+            .unwrap();
+        stats.record_import(&record(2, 1)).unwrap();
+        stats.record_import(&record(2, 2)).unwrap();
+        stats.record_import(&record(5, 3)).unwrap();
+
+        assert_eq!(
+            stats.totals().unwrap(),
+            Totals {
+                accounts: 106,
+                pairs: 212
+            }
+        );
+        assert_eq!(
+            stats.daily(None).unwrap(),
+            vec![
+                (
+                    date(2),
+                    DailyIngest {
+                        imports: 2,
+                        lines_read: 20,
+                        new_accounts: 3,
+                        new_pairs: 8,
+                        dates_added: 6
+                    }
+                ),
+                (
+                    date(5),
+                    DailyIngest {
+                        imports: 1,
+                        lines_read: 10,
+                        new_accounts: 3,
+                        new_pairs: 4,
+                        dates_added: 3
+                    }
+                )
+            ]
+        );
+        assert_eq!(stats.daily(Some(date(3))).unwrap().len(), 1);
+        assert_eq!(stats.get_counts().unwrap(), 2);
+    }
+}
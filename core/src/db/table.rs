@@ -1,5 +1,5 @@
 use super::Error;
-use rocksdb::DB;
+use rocksdb::{BottommostLevelCompaction, CompactOptions, DB};
 
 pub trait Mode {
     fn is_read_only() -> bool;
@@ -25,9 +25,26 @@ pub trait Table: Sized {
     fn underlying(&self) -> &DB;
     fn get_counts(&self) -> Result<Self::Counts, Error>;
 
+    /// Compact the entire table, rewriting every SST file with the current options (including
+    /// files already in the bottommost level, which RocksDB otherwise skips).
+    fn compact(&self) {
+        let mut options = CompactOptions::default();
+        options.set_bottommost_level_compaction(BottommostLevelCompaction::Force);
+
+        self.underlying()
+            .compact_range_opt(None::<&[u8]>, None::<&[u8]>, &options);
+    }
+
     fn get_estimated_key_count(&self) -> Result<Option<u64>, Error> {
         Ok(self
             .underlying()
             .property_int_value("rocksdb.estimate-num-keys")?)
     }
+
+    /// Estimated number of bytes compaction would need to rewrite to settle the LSM tree.
+    fn get_pending_compaction_bytes(&self) -> Result<Option<u64>, Error> {
+        Ok(self
+            .underlying()
+            .property_int_value("rocksdb.estimate-pending-compaction-bytes")?)
+    }
 }
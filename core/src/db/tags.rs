@@ -0,0 +1,278 @@
+use super::{
+    options::DatabaseOptions,
+    table::{Mode, Table, Writeable},
+    Error,
+};
+use rocksdb::{DBIterator, Direction, IteratorMode, Options, WriteBatch, DB};
+use std::convert::TryInto;
+use std::marker::PhantomData;
+use std::path::Path;
+
+const SEPARATOR: u8 = 0;
+
+/// Tags can't contain the separator, so keys starting with it are free for the per-ID index.
+const ID_INDEX_PREFIX: u8 = SEPARATOR;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TagTableCounts {
+    pub tag_count: u64,
+    pub membership_count: u64,
+}
+
+/// Named lists of account IDs (watchlists, investigation groups, etc.).
+pub struct TagTable<M> {
+    db: DB,
+    mode: PhantomData<M>,
+}
+
+impl<M> Table for TagTable<M> {
+    type Counts = TagTableCounts;
+
+    fn underlying(&self) -> &DB {
+        &self.db
+    }
+
+    fn get_counts(&self) -> Result<Self::Counts, Error> {
+        let mut membership_count = 0;
+        let mut tag_count = 0;
+        let mut last_tag = String::new();
+
+        let iter = self.tag_iterator();
+
+        for result in iter {
+            let (key, _) = result?;
+            membership_count += 1;
+
+            let (tag, _) = key_to_pair(&key)?;
+
+            if tag != last_tag {
+                tag_count += 1;
+                last_tag = tag.to_string();
+            }
+        }
+
+        Ok(Self::Counts {
+            tag_count,
+            membership_count,
+        })
+    }
+}
+
+impl<M> TagTable<M> {
+    pub fn members(&self, tag: &str) -> Result<Vec<u64>, Error> {
+        let prefix = tag_to_key_prefix(tag)?;
+        let iter = self.db.prefix_iterator(&prefix);
+        let mut results = vec![];
+
+        for result in iter {
+            let (key, _) = result?;
+
+            if key.starts_with(&prefix) {
+                let (_, id) = key_to_pair(&key)?;
+                results.push(id);
+            } else {
+                break;
+            }
+        }
+
+        Ok(results)
+    }
+
+    pub fn tags(&self) -> Result<Vec<(String, u64)>, Error> {
+        let mut results: Vec<(String, u64)> = vec![];
+        let iter = self.tag_iterator();
+
+        for result in iter {
+            let (key, _) = result?;
+            let (tag, _) = key_to_pair(&key)?;
+
+            match results.last_mut() {
+                Some((last_tag, count)) if last_tag.as_str() == tag => {
+                    *count += 1;
+                }
+                _ => {
+                    results.push((tag.to_string(), 1));
+                }
+            }
+        }
+
+        Ok(results)
+    }
+
+    pub fn tags_for_id(&self, id: u64) -> Result<Vec<String>, Error> {
+        let prefix = id_to_index_prefix(id);
+        let iter = self.db.prefix_iterator(prefix);
+        let mut results = vec![];
+
+        for result in iter {
+            let (key, _) = result?;
+
+            if key.starts_with(&prefix) {
+                let (_, tag) = index_key_to_pair(&key)?;
+                results.push(tag.to_string());
+            } else {
+                break;
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Iterate over the tag entries, skipping the per-ID index that sorts before them.
+    fn tag_iterator(&self) -> DBIterator<'_> {
+        self.db.iterator(IteratorMode::From(
+            &[ID_INDEX_PREFIX + 1],
+            Direction::Forward,
+        ))
+    }
+}
+
+impl<M: Mode> TagTable<M> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::open_with_options(path, &DatabaseOptions::default())
+    }
+
+    pub fn open_with_options<P: AsRef<Path>>(
+        path: P,
+        database_options: &DatabaseOptions,
+    ) -> Result<Self, Error> {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        database_options.apply(&mut options);
+
+        let db = if M::is_read_only() {
+            DB::open_for_read_only(&options, path, true)?
+        } else {
+            DB::open(&options, path)?
+        };
+
+        Ok(Self {
+            db,
+            mode: PhantomData,
+        })
+    }
+}
+
+impl TagTable<Writeable> {
+    pub fn add(&self, tag: &str, id: u64) -> Result<(), Error> {
+        let mut batch = WriteBatch::default();
+        batch.put(pair_to_key(tag, id)?, b"");
+        batch.put(pair_to_index_key(tag, id), b"");
+
+        Ok(self.db.write(batch)?)
+    }
+
+    pub fn remove(&self, tag: &str, id: u64) -> Result<(), Error> {
+        let mut batch = WriteBatch::default();
+        batch.delete(pair_to_key(tag, id)?);
+        batch.delete(pair_to_index_key(tag, id));
+
+        Ok(self.db.write(batch)?)
+    }
+}
+
+fn is_valid_tag(tag: &str) -> bool {
+    !tag.is_empty() && !tag.as_bytes().contains(&SEPARATOR)
+}
+
+fn tag_to_key_prefix(tag: &str) -> Result<Vec<u8>, Error> {
+    if is_valid_tag(tag) {
+        let mut prefix = Vec::with_capacity(tag.len() + 1);
+        prefix.extend_from_slice(tag.as_bytes());
+        prefix.push(SEPARATOR);
+        Ok(prefix)
+    } else {
+        Err(Error::InvalidTag(tag.to_string()))
+    }
+}
+
+fn pair_to_key(tag: &str, id: u64) -> Result<Vec<u8>, Error> {
+    let mut key = tag_to_key_prefix(tag)?;
+    key.extend_from_slice(&id.to_be_bytes());
+    Ok(key)
+}
+
+fn key_to_pair(key: &[u8]) -> Result<(&str, u64), Error> {
+    let split = key
+        .len()
+        .checked_sub(9)
+        .filter(|split| key[*split] == SEPARATOR)
+        .ok_or_else(|| Error::InvalidKey(key.to_vec()))?;
+    let tag = std::str::from_utf8(&key[0..split])?;
+    let id = u64::from_be_bytes(
+        key[split + 1..]
+            .try_into()
+            .map_err(|_| Error::InvalidKey(key.to_vec()))?,
+    );
+
+    Ok((tag, id))
+}
+
+fn id_to_index_prefix(id: u64) -> [u8; 9] {
+    let mut prefix = [ID_INDEX_PREFIX; 9];
+    prefix[1..].copy_from_slice(&id.to_be_bytes());
+    prefix
+}
+
+fn pair_to_index_key(tag: &str, id: u64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(9 + tag.len());
+    key.extend_from_slice(&id_to_index_prefix(id));
+    key.extend_from_slice(tag.as_bytes());
+    key
+}
+
+fn index_key_to_pair(key: &[u8]) -> Result<(u64, &str), Error> {
+    if key.len() < 9 || key[0] != ID_INDEX_PREFIX {
+        return Err(Error::InvalidKey(key.to_vec()));
+    }
+
+    let id = u64::from_be_bytes(
+        key[1..9]
+            .try_into()
+            .map_err(|_| Error::InvalidKey(key.to_vec()))?,
+    );
+    let tag = std::str::from_utf8(&key[9..])?;
+
+    Ok((id, tag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixture;
+
+    #[test]
+    fn tags() {
+        let (_dir, tags) = fixture::open(TagTable::<Writeable>::open);
+
+        tags.add("scams", 123).unwrap();
+        tags.add("scams", 456).unwrap();
+        tags.add("scams-2022", 456).unwrap();
+        tags.add("bots", 789).unwrap();
+        tags.remove("scams", 123).unwrap();
+
+        assert_eq!(tags.members("scams").unwrap(), vec![456]);
+        assert_eq!(tags.members("scams-2022").unwrap(), vec![456]);
+        assert_eq!(
+            tags.tags().unwrap(),
+            vec![
+                ("bots".to_string(), 1),
+                ("scams".to_string(), 1),
+                ("scams-2022".to_string(), 1)
+            ]
+        );
+        assert_eq!(
+            tags.tags_for_id(456).unwrap(),
+            vec!["scams".to_string(), "scams-2022".to_string()]
+        );
+        assert!(tags.tags_for_id(123).unwrap().is_empty());
+        assert_eq!(
+            tags.get_counts().unwrap(),
+            TagTableCounts {
+                tag_count: 3,
+                membership_count: 3
+            }
+        );
+        assert!(tags.add("", 123).is_err());
+    }
+}
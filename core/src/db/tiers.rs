@@ -0,0 +1,241 @@
+use super::{
+    options::DatabaseOptions,
+    table::{Mode, Writeable},
+    Database, Error,
+};
+use chrono::NaiveDate;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// File in the hot database directory that records where the cold database is.
+pub const TIERS_FILE: &str = "tiers.json";
+
+/// The tier configuration stored in [`TIERS_FILE`].
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct TierConfig {
+    /// Path of the cold database directory
+    pub cold: PathBuf,
+}
+
+impl TierConfig {
+    /// Read the tier configuration of a hot database, or `None` if it has no cold tier.
+    pub fn load<P: AsRef<Path>>(base: P) -> Result<Option<Self>, Error> {
+        let path = base.as_ref().join(TIERS_FILE);
+
+        if path.exists() {
+            Ok(Some(serde_json::from_slice(&std::fs::read(path)?)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, base: P) -> Result<(), Error> {
+        Ok(std::fs::write(
+            base.as_ref().join(TIERS_FILE),
+            serde_json::to_vec_pretty(self)?,
+        )?)
+    }
+}
+
+/// The accounts moved (or, for a dry run, that would be moved) to the cold tier.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct DemoteReport {
+    pub accounts_kept: usize,
+    pub accounts_demoted: usize,
+    pub pairs_demoted: usize,
+}
+
+impl std::fmt::Display for DemoteReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Accounts kept: {}", self.accounts_kept)?;
+        writeln!(f, "Accounts demoted: {}", self.accounts_demoted)?;
+        write!(f, "Pairs demoted: {}", self.pairs_demoted)
+    }
+}
+
+/// A database on fast storage holding recently observed accounts, backed by a database on bulk
+/// storage holding accounts that haven't been observed recently.
+///
+/// New observations are always written to the hot tier, so an account can have pairs in both
+/// tiers, and lookups merge the results from both.
+pub struct TieredLookup<M> {
+    pub hot: Database<M>,
+    pub cold: Option<Database<M>>,
+}
+
+impl<M: Sync + Send + 'static> TieredLookup<M> {
+    pub fn lookup_by_user_id(
+        &self,
+        user_id: u64,
+    ) -> Result<HashMap<String, Vec<NaiveDate>>, Error> {
+        let mut result = self.hot.lookup_by_user_id(user_id)?;
+
+        if let Some(cold) = &self.cold {
+            for (screen_name, dates) in cold.lookup_by_user_id(user_id)? {
+                let merged = result.entry(screen_name).or_default();
+                merged.extend(dates);
+                merged.sort();
+                merged.dedup();
+            }
+        }
+
+        Ok(result)
+    }
+
+    pub fn lookup_by_screen_name(&self, screen_name: &str) -> Result<Vec<u64>, Error> {
+        let mut result = self.hot.lookup_by_screen_name(screen_name)?;
+
+        if let Some(cold) = &self.cold {
+            result.extend(cold.lookup_by_screen_name(screen_name)?);
+            result.sort_unstable();
+            result.dedup();
+        }
+
+        Ok(result)
+    }
+}
+
+impl<M: Mode> TieredLookup<M> {
+    /// Open a hot database along with the cold database it's configured with (if any).
+    pub fn open<P: AsRef<Path>>(base: P, options: &DatabaseOptions) -> Result<Self, Error> {
+        let cold = TierConfig::load(&base)?.map(|config| config.cold);
+
+        Self::open_with_cold(base, cold, options)
+    }
+
+    /// Open a hot database with an explicitly given cold database.
+    pub fn open_with_cold<P: AsRef<Path>, Q: AsRef<Path>>(
+        base: P,
+        cold: Option<Q>,
+        options: &DatabaseOptions,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            hot: Database::open_with_options(base, options)?,
+            cold: cold
+                .map(|cold| Database::open_with_options(cold, options))
+                .transpose()?,
+        })
+    }
+}
+
+impl TieredLookup<Writeable> {
+    pub fn insert(&self, id: u64, screen_name: &str, dates: Vec<NaiveDate>) -> Result<(), Error> {
+        self.hot.insert(id, screen_name, dates)
+    }
+
+    /// Move every account that hasn't been observed on or after a date from the hot tier to the
+    /// cold tier (doing nothing if there is no cold tier).
+    pub fn demote(&self, cutoff: NaiveDate, dry_run: bool) -> Result<DemoteReport, Error> {
+        let mut report = DemoteReport::default();
+
+        let cold = match &self.cold {
+            Some(cold) => cold,
+            None => return Ok(report),
+        };
+
+        let mut pairs = self.hot.accounts.pairs().peekable();
+
+        while let Some(result) = pairs.next() {
+            let (id, screen_name, dates) = result?;
+            let mut account = vec![(screen_name, dates)];
+
+            while let Some(Ok((next_id, _, _))) = pairs.peek() {
+                if *next_id != id {
+                    break;
+                }
+
+                let (_, screen_name, dates) = pairs.next().unwrap()?;
+                account.push((screen_name, dates));
+            }
+
+            let last = account.iter().flat_map(|(_, dates)| dates).max().copied();
+
+            if last.is_some_and(|last| last >= cutoff) {
+                report.accounts_kept += 1;
+            } else {
+                report.accounts_demoted += 1;
+                report.pairs_demoted += account.len();
+
+                if !dry_run {
+                    for (screen_name, dates) in account {
+                        cold.insert(id, &screen_name, dates)?;
+                        self.hot.accounts.remove(id, &screen_name)?;
+                        self.hot.screen_names.remove(&screen_name, id)?;
+                    }
+                }
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::ReadOnly;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2020, 1, day).unwrap()
+    }
+
+    #[test]
+    fn demote() {
+        let hot_dir = tempfile::tempdir().unwrap();
+        let cold_dir = tempfile::tempdir().unwrap();
+        let options = DatabaseOptions::default();
+
+        let db = TieredLookup::<Writeable>::open_with_cold(
+            hot_dir.path(),
+            Some(cold_dir.path()),
+            &options,
+        )
+        .unwrap();
+        db.insert(1, "foo", vec![date(1), date(10)]).unwrap();
+        db.insert(1, "bar", vec![date(2)]).unwrap();
+        db.insert(2, "baz", vec![date(3)]).unwrap();
+        db.insert(2, "qux", vec![date(4)]).unwrap();
+        db.insert(3, "foo", vec![date(5)]).unwrap();
+
+        assert_eq!(
+            db.demote(date(5), true).unwrap(),
+            DemoteReport {
+                accounts_kept: 2,
+                accounts_demoted: 1,
+                pairs_demoted: 2,
+            }
+        );
+        assert!(db
+            .cold
+            .as_ref()
+            .unwrap()
+            .lookup_by_user_id(2)
+            .unwrap()
+            .is_empty());
+
+        db.demote(date(5), false).unwrap();
+        // A demoted account observed again has pairs in both tiers
+        db.insert(2, "baz", vec![date(20)]).unwrap();
+        drop(db);
+
+        TierConfig {
+            cold: cold_dir.path().to_path_buf(),
+        }
+        .save(hot_dir.path())
+        .unwrap();
+
+        let db = TieredLookup::<ReadOnly>::open(hot_dir.path(), &options).unwrap();
+
+        assert!(db.hot.lookup_by_screen_name("qux").unwrap().is_empty());
+        assert_eq!(db.lookup_by_screen_name("qux").unwrap(), vec![2]);
+        assert_eq!(db.lookup_by_screen_name("foo").unwrap(), vec![1, 3]);
+        assert_eq!(
+            db.lookup_by_user_id(2).unwrap(),
+            HashMap::from([
+                ("baz".to_string(), vec![date(3), date(20)]),
+                ("qux".to_string(), vec![date(4)])
+            ])
+        );
+    }
+}
@@ -1,5 +1,31 @@
+use crate::handles::Platform;
+use unicode_normalization::char::is_combining_mark;
+
+/// Whether a value can be stored as a screen name.
+///
+/// Screen names are letters, digits, and underscores in any script (mention data includes
+/// non-ASCII handles), optionally followed by a domain for handles from federated platforms
+/// (`user@example.social`). Anything that follows the rules of a supported [`Platform`] is also
+/// valid.
 pub fn is_valid_screen_name(value: &str) -> bool {
-    value
-        .chars()
-        .all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+    let is_name_char = |ch: char| ch.is_alphanumeric() || ch == '_' || is_combining_mark(ch);
+
+    let valid = match value.split_once('@') {
+        None => value.chars().all(is_name_char),
+        Some((user, domain)) => {
+            !user.is_empty()
+                && user.chars().all(is_name_char)
+                && domain.split('.').all(|label| {
+                    !label.is_empty()
+                        && label
+                            .chars()
+                            .all(|ch| ch.is_alphanumeric() || ch == '-' || is_combining_mark(ch))
+                })
+        }
+    };
+
+    valid
+        || Platform::ALL
+            .iter()
+            .any(|platform| platform.is_valid_handle(value))
 }
@@ -0,0 +1,224 @@
+use super::{
+    codec::{key_to_pair, pair_to_key, value_to_weights, weights_to_value, WEIGHT_ENTRY_LEN},
+    options::DatabaseOptions,
+    table::{Mode, Table, Writeable},
+    util::is_valid_screen_name,
+    Error,
+};
+use chrono::NaiveDate;
+use rocksdb::{IteratorMode, MergeOperands, Options, DB};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::marker::PhantomData;
+use std::path::Path;
+
+/// Per-day observation counts for ID-screen name pairs.
+///
+/// Keys are identical to those in the account table, and values accumulate counts when merged.
+pub struct WeightTable<M> {
+    db: DB,
+    mode: PhantomData<M>,
+}
+
+impl<M> Table for WeightTable<M> {
+    type Counts = u64;
+
+    fn underlying(&self) -> &DB {
+        &self.db
+    }
+
+    fn get_counts(&self) -> Result<Self::Counts, Error> {
+        let mut count = 0;
+
+        for result in self.db.iterator(IteratorMode::Start) {
+            result?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+}
+
+impl<M> WeightTable<M> {
+    pub fn get(&self, id: u64, screen_name: &str) -> Result<Vec<(NaiveDate, u32)>, Error> {
+        self.db
+            .get_pinned(pair_to_key(id, screen_name))?
+            .map(|value| value_to_weights(&value))
+            .unwrap_or_else(|| Ok(vec![]))
+    }
+
+    pub fn lookup(&self, id: u64) -> Result<HashMap<String, Vec<(NaiveDate, u32)>>, Error> {
+        let prefix = id.to_be_bytes();
+        let iter = self.db.prefix_iterator(prefix);
+        let mut results = HashMap::new();
+
+        for result in iter {
+            let (key, value) = result?;
+            let (next_id, next_screen_name) = key_to_pair(&key)?;
+
+            if next_id == id {
+                results.insert(next_screen_name.to_string(), value_to_weights(&value)?);
+            } else {
+                break;
+            }
+        }
+
+        Ok(results)
+    }
+}
+
+impl<M: Mode> WeightTable<M> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::open_with_options(path, &DatabaseOptions::default())
+    }
+
+    pub fn open_with_options<P: AsRef<Path>>(
+        path: P,
+        database_options: &DatabaseOptions,
+    ) -> Result<Self, Error> {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        options.set_merge_operator_associative("merge", merge);
+        database_options.apply(&mut options);
+
+        let db = if M::is_read_only() {
+            DB::open_for_read_only(&options, path, true)?
+        } else {
+            DB::open(&options, path)?
+        };
+
+        Ok(Self {
+            db,
+            mode: PhantomData,
+        })
+    }
+}
+
+impl WeightTable<Writeable> {
+    /// Add observation counts for the given dates.
+    pub fn add(
+        &self,
+        id: u64,
+        screen_name: &str,
+        counts: &[(NaiveDate, u32)],
+    ) -> Result<(), Error> {
+        if is_valid_screen_name(screen_name) {
+            self.db
+                .merge(pair_to_key(id, screen_name), weights_to_value(counts)?)?;
+
+            Ok(())
+        } else {
+            Err(Error::InvalidScreenName(screen_name.to_string()))
+        }
+    }
+
+    /// Drop counts for dates before the given date (except for held accounts), returning the
+    /// number of dates dropped.
+    pub fn prune(
+        &self,
+        earliest: NaiveDate,
+        held: &HashSet<u64>,
+        dry_run: bool,
+    ) -> Result<u64, Error> {
+        let mut dates_removed = 0;
+
+        for result in self.db.iterator(IteratorMode::Start) {
+            let (key, value) = result?;
+
+            if held.contains(&key_to_pair(&key)?.0) {
+                continue;
+            }
+
+            let weights = value_to_weights(&value)?;
+            let kept = weights
+                .iter()
+                .filter(|(date, _)| *date >= earliest)
+                .copied()
+                .collect::<Vec<_>>();
+
+            if kept.len() < weights.len() {
+                dates_removed += (weights.len() - kept.len()) as u64;
+
+                if !dry_run {
+                    if kept.is_empty() {
+                        self.db.delete(key)?;
+                    } else {
+                        self.db.put(key, weights_to_value(&kept)?)?;
+                    }
+                }
+            }
+        }
+
+        Ok(dates_removed)
+    }
+}
+
+/// Malformed values fail the merge (which RocksDB reports as an error) instead of being truncated.
+fn merge(
+    _new_key: &[u8],
+    existing_val: Option<&[u8]>,
+    operands: &MergeOperands,
+) -> Option<Vec<u8>> {
+    let mut counts = BTreeMap::new();
+
+    for value in existing_val.into_iter().chain(operands.iter()) {
+        if !is_valid_value(value) {
+            log::error!("Invalid weight value length: {}", value.len());
+            return None;
+        }
+
+        for entry in value.chunks_exact(WEIGHT_ENTRY_LEN) {
+            let day_id = u16::from_be_bytes([entry[0], entry[1]]);
+            let count = u32::from_be_bytes([entry[2], entry[3], entry[4], entry[5]]);
+            let total: &mut u32 = counts.entry(day_id).or_default();
+            *total = total.saturating_add(count);
+        }
+    }
+
+    let mut new_val = Vec::with_capacity(WEIGHT_ENTRY_LEN * counts.len());
+
+    for (day_id, count) in counts {
+        new_val.extend_from_slice(&day_id.to_be_bytes());
+        new_val.extend_from_slice(&count.to_be_bytes());
+    }
+
+    Some(new_val)
+}
+
+fn is_valid_value(value: &[u8]) -> bool {
+    value.len().is_multiple_of(WEIGHT_ENTRY_LEN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{
+        codec,
+        fixture::{self, date},
+    };
+
+    #[test]
+    fn weights() {
+        let (_dir, weights) = fixture::open(WeightTable::<Writeable>::open);
+
+        weights
+            .add(123, "foo", &[(date(2), 1), (date(1), 3)])
+            .unwrap();
+        weights.add(123, "foo", &[(date(2), 4)]).unwrap();
+        weights.add(123, "bar", &[(date(3), 1)]).unwrap();
+
+        assert_eq!(
+            weights.get(123, "foo").unwrap(),
+            vec![(date(1), 3), (date(2), 5)]
+        );
+        assert_eq!(weights.lookup(123).unwrap().len(), 2);
+        assert_eq!(weights.get(456, "foo").unwrap(), vec![]);
+
+        // A truncated entry is rejected rather than silently dropped
+        weights
+            .underlying()
+            .merge(codec::pair_to_key(123, "bar"), [0, 1, 0, 0, 0])
+            .unwrap();
+
+        assert!(weights.get(123, "bar").is_err());
+    }
+}
@@ -0,0 +1,145 @@
+//! Embeddable renderings of an account's screen name history.
+//!
+//! Journalists can embed these in articles as evidence: a self-contained HTML fragment listing
+//! the account's screen names and linking back to its page on the instance, or the same fragment
+//! in an [oEmbed](https://oembed.com/) document for publishing platforms that discover embeds from
+//! a pasted URL. Every value in the fragment is escaped, and it has no scripts or styles (only
+//! class names that the embedding page can style).
+use crate::summary::Summary;
+use serde_json::{json, Value};
+
+pub const PROVIDER_NAME: &str = "memory.lol";
+/// Width in pixels of embeds when the consumer doesn't ask for a narrower one.
+pub const DEFAULT_WIDTH: u32 = 480;
+
+const HEADER_HEIGHT: u32 = 72;
+const ROW_HEIGHT: u32 = 24;
+
+/// An HTML fragment of the account's screen names (the current one first) with the dates each was
+/// observed between, linking to the given URL.
+pub fn html(user_id: u64, summary: &Summary, link: &str) -> String {
+    let mut html = format!(
+        "<blockquote class=\"memory-lol-embed\" data-id=\"{}\"><p>Screen names of account {}</p><ul>",
+        user_id, user_id
+    );
+
+    html.push_str(&format!(
+        "<li class=\"memory-lol-current\">@{} <span>{} – {}</span></li>",
+        escape(&summary.current),
+        summary.current_since,
+        summary.last_seen
+    ));
+
+    for alias in summary.previous.iter().rev() {
+        html.push_str(&format!(
+            "<li>@{} <span>{} – {}</span></li>",
+            escape(&alias.screen_name),
+            alias.first,
+            alias.last
+        ));
+    }
+
+    html.push_str(&format!(
+        "</ul><p><a href=\"{}\">{}</a></p></blockquote>",
+        escape(link),
+        PROVIDER_NAME
+    ));
+
+    html
+}
+
+/// An oEmbed `rich` document for the account, with [`html`] as its content.
+///
+/// The height is an estimate from the number of screen names, since the fragment isn't rendered.
+pub fn oembed(
+    user_id: u64,
+    summary: &Summary,
+    link: &str,
+    provider_url: &str,
+    max_width: Option<u32>,
+) -> Value {
+    let rows = summary.previous.len() as u32 + 1;
+
+    json!({
+        "version": "1.0",
+        "type": "rich",
+        "provider_name": PROVIDER_NAME,
+        "provider_url": provider_url,
+        "title": summary.to_string(),
+        "html": html(user_id, summary, link),
+        "width": max_width.map_or(DEFAULT_WIDTH, |max_width| max_width.min(DEFAULT_WIDTH)),
+        "height": HEADER_HEIGHT + rows * ROW_HEIGHT,
+    })
+}
+
+/// The user ID of an account page URL (`<instance>/tw/id/<id>`), if it's an HTTP(S) URL of one.
+pub fn user_id_from_url(url: &str) -> Option<u64> {
+    if !(url.starts_with("https://") || url.starts_with("http://")) {
+        return None;
+    }
+
+    let path = url.split(['?', '#']).next()?.trim_end_matches('/');
+    let (_, user_id) = path.rsplit_once("/tw/id/")?;
+
+    user_id.parse().ok()
+}
+
+/// Escape text for use in HTML content or a quoted attribute value.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for ch in value.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            ch => escaped.push(ch),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use std::collections::HashMap;
+
+    #[test]
+    fn embeds() {
+        let date = |year, month| NaiveDate::from_ymd_opt(year, month, 1).unwrap();
+        let result = HashMap::from([
+            ("old".to_string(), vec![date(2019, 1), date(2021, 8)]),
+            ("<b>new</b>".to_string(), vec![date(2022, 1), date(2023, 2)]),
+        ]);
+        let summary = Summary::from_result(&result).unwrap();
+        let link = "https://example.com/v1/tw/id/1?a=1&b=\"2\"";
+
+        assert_eq!(
+            html(1, &summary, link),
+            concat!(
+                "<blockquote class=\"memory-lol-embed\" data-id=\"1\"><p>Screen names of account 1</p><ul>",
+                "<li class=\"memory-lol-current\">@&lt;b&gt;new&lt;/b&gt; <span>2022-01-01 – 2023-02-01</span></li>",
+                "<li>@old <span>2019-01-01 – 2021-08-01</span></li></ul>",
+                "<p><a href=\"https://example.com/v1/tw/id/1?a=1&amp;b=&quot;2&quot;\">memory.lol</a></p></blockquote>"
+            )
+        );
+
+        let document = oembed(1, &summary, link, "https://example.com", Some(320));
+        assert_eq!(document["type"], "rich");
+        assert_eq!(document["width"], 320);
+        assert_eq!(document["height"], HEADER_HEIGHT + 2 * ROW_HEIGHT);
+        assert_eq!(document["html"], html(1, &summary, link));
+
+        assert_eq!(
+            user_id_from_url("https://api.memory.lol/v1/tw/id/1326229737551912960/?x#y"),
+            Some(1326229737551912960)
+        );
+        assert_eq!(user_id_from_url("http://localhost:8080/tw/id/12"), Some(12));
+        assert_eq!(user_id_from_url("https://api.memory.lol/v1/tw/jack"), None);
+        assert_eq!(user_id_from_url("javascript:/tw/id/12"), None);
+    }
+}
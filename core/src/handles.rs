@@ -0,0 +1,149 @@
+//! Platform-specific handle rules.
+//!
+//! The database accepts any screen name made of letters, digits, and underscores, or any handle
+//! that's valid on one of these platforms (see [`crate::db::util::is_valid_screen_name`]). Imports
+//! can optionally hold handles to the stricter rules of the platform they come from, so that parser
+//! artifacts (URLs, trailing punctuation, non-ASCII lookalikes) don't end up in the screen name
+//! index.
+use std::str::FromStr;
+
+/// Maximum length of a Twitter screen name.
+pub const TWITTER_MAX_LEN: usize = 15;
+/// Maximum length of a domain name (and so of a Bluesky handle).
+const DOMAIN_MAX_LEN: usize = 253;
+/// Maximum length of a label in a domain name.
+const LABEL_MAX_LEN: usize = 63;
+
+/// A platform whose handle rules can be enforced.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Platform {
+    /// Up to 15 ASCII letters, digits, and underscores
+    #[default]
+    Twitter,
+    /// A username (ASCII letters, digits, and underscores, with dots and dashes inside) and the
+    /// domain of its instance (`user@example.social`)
+    Mastodon,
+    /// A domain name (`user.bsky.social`)
+    Bluesky,
+}
+
+impl Platform {
+    pub const ALL: [Platform; 3] = [Platform::Twitter, Platform::Mastodon, Platform::Bluesky];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Twitter => "twitter",
+            Platform::Mastodon => "mastodon",
+            Platform::Bluesky => "bluesky",
+        }
+    }
+
+    /// Whether a handle (without a leading `@`) follows the platform's rules.
+    ///
+    /// Handles are checked as stored, so domains must be ASCII (punycode).
+    pub fn is_valid_handle(&self, handle: &str) -> bool {
+        match self {
+            Platform::Twitter => {
+                !handle.is_empty()
+                    && handle.len() <= TWITTER_MAX_LEN
+                    && handle
+                        .bytes()
+                        .all(|byte| byte.is_ascii_alphanumeric() || byte == b'_')
+            }
+            Platform::Mastodon => match handle.split_once('@') {
+                Some((user, domain)) => is_valid_mastodon_username(user) && is_valid_domain(domain),
+                None => false,
+            },
+            Platform::Bluesky => is_valid_domain(handle),
+        }
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|platform| platform.name() == value)
+            .ok_or_else(|| format!("Unknown platform: {}", value))
+    }
+}
+
+impl std::fmt::Display for Platform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+fn is_valid_mastodon_username(user: &str) -> bool {
+    let bytes = user.as_bytes();
+
+    match (bytes.first(), bytes.last()) {
+        (Some(first), Some(last)) => {
+            let is_edge = |byte: &u8| byte.is_ascii_alphanumeric() || *byte == b'_';
+
+            is_edge(first)
+                && is_edge(last)
+                && bytes
+                    .iter()
+                    .all(|byte| is_edge(byte) || *byte == b'.' || *byte == b'-')
+        }
+        _ => false,
+    }
+}
+
+/// A hostname with at least two labels and a top-level domain that isn't numeric (so that IP
+/// addresses and bare words from URLs are rejected).
+fn is_valid_domain(domain: &str) -> bool {
+    let labels = domain.split('.').collect::<Vec<_>>();
+
+    domain.len() <= DOMAIN_MAX_LEN
+        && labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && label.len() <= LABEL_MAX_LEN
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+        })
+        && labels
+            .last()
+            .is_some_and(|tld| !tld.bytes().all(|byte| byte.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn platform_rules() {
+        let twitter = Platform::Twitter;
+        assert!(twitter.is_valid_handle("jack"));
+        assert!(twitter.is_valid_handle("Foo_123456789ab"));
+        assert!(!twitter.is_valid_handle("Foo_123456789abc"));
+        assert!(!twitter.is_valid_handle("jack."));
+        assert!(!twitter.is_valid_handle("https://t.co"));
+        assert!(!twitter.is_valid_handle("jos\u{e9}"));
+        assert!(!twitter.is_valid_handle(""));
+
+        let mastodon = Platform::Mastodon;
+        assert!(mastodon.is_valid_handle("Gargron@mastodon.social"));
+        assert!(mastodon.is_valid_handle("first.last@xn--bcher-kva.example"));
+        assert!(!mastodon.is_valid_handle("gargron"));
+        assert!(!mastodon.is_valid_handle("gargron.@mastodon.social"));
+        assert!(!mastodon.is_valid_handle("gargron@localhost"));
+        assert!(!mastodon.is_valid_handle("gargron@10.0.0.1"));
+
+        let bluesky = Platform::Bluesky;
+        assert!(bluesky.is_valid_handle("jay.bsky.team"));
+        assert!(!bluesky.is_valid_handle("jay"));
+        assert!(!bluesky.is_valid_handle("-jay.bsky.team"));
+        assert!(!bluesky.is_valid_handle("jay..bsky.team"));
+
+        assert_eq!("mastodon".parse(), Ok(Platform::Mastodon));
+        assert!("myspace".parse::<Platform>().is_err());
+    }
+}
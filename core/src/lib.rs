@@ -1,2 +1,16 @@
+pub mod admin;
+pub mod cache;
+pub mod confusables;
+pub mod cors;
 pub mod db;
+pub mod embed;
+pub mod handles;
 pub mod model;
+pub mod ranges;
+pub mod ratelimit;
+pub mod snapshots;
+pub mod snowflake;
+pub mod summary;
+pub mod timeline;
+pub mod timestamp;
+pub mod timestamping;
@@ -1,4 +1,5 @@
-use chrono::NaiveDate;
+use crate::db::codec::screen_name_to_key;
+use chrono::{DateTime, NaiveDate, Utc};
 use indexmap::IndexMap;
 use serde_derive::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -10,12 +11,12 @@ pub struct ScreenNameResult {
 
 impl ScreenNameResult {
     pub fn includes_screen_name(&self, screen_name: &str) -> bool {
-        let target_screen_name = screen_name.to_lowercase();
+        let target_screen_name = screen_name_to_key(screen_name);
         self.accounts.iter().any(|account| {
             account
                 .screen_names
                 .keys()
-                .any(|screen_name| screen_name.to_lowercase() == target_screen_name)
+                .any(|screen_name| screen_name_to_key(screen_name) == target_screen_name)
         })
     }
 }
@@ -25,10 +26,15 @@ pub struct Account {
     pub id: u64,
     #[serde(rename = "screen_names")]
     pub screen_names: IndexMap<String, Option<Vec<NaiveDate>>>,
+    /// Missing from responses of servers that predate it
+    #[serde(default)]
+    pub coverage: Coverage,
 }
 
 impl Account {
     pub fn from_raw_result(id: u64, result: HashMap<String, Vec<NaiveDate>>) -> Self {
+        let coverage = Coverage::new(&result);
+
         let mut sorted = result
             .into_iter()
             .map(|(screen_name, mut dates)| {
@@ -47,14 +53,113 @@ impl Account {
         sorted.sort_by(|screen_name_a, dates_a, screen_name_b, dates_b| {
             dates_a
                 .as_ref()
-                .and_then(|dates| dates.get(0))
-                .cmp(&dates_b.as_ref().and_then(|dates| dates.get(0)))
+                .and_then(|dates| dates.first())
+                .cmp(&dates_b.as_ref().and_then(|dates| dates.first()))
                 .then_with(|| screen_name_a.cmp(screen_name_b))
         });
 
         Self {
             id,
             screen_names: sorted,
+            coverage,
+        }
+    }
+}
+
+/// How well an account's history is attested in the database, so that a long, well-observed
+/// history can be told apart from a single stray mention.
+///
+/// Observations aren't stored with their sources, so repeated observations on distinct days are
+/// the measure of attestation.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Coverage {
+    /// Earliest observation of any of the account's screen names
+    pub first_seen: Option<NaiveDate>,
+    /// Latest observation of any of the account's screen names
+    pub last_seen: Option<NaiveDate>,
+    /// Number of observation dates stored for the account's screen names
+    pub observations: u64,
+    /// Number of distinct days the account was observed on (with any screen name)
+    pub days: u64,
+}
+
+impl Coverage {
+    pub fn new(result: &HashMap<String, Vec<NaiveDate>>) -> Self {
+        let mut days = result.values().flatten().copied().collect::<Vec<_>>();
+        let observations = days.len() as u64;
+        days.sort_unstable();
+        days.dedup();
+
+        Self {
+            first_seen: days.first().copied(),
+            last_seen: days.last().copied(),
+            observations,
+            days: days.len() as u64,
         }
     }
 }
+
+/// An account as served by the web API, which also includes the ID as a string (since JavaScript
+/// clients can't represent every ID as a number).
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ExtendedAccount {
+    pub id: u64,
+    pub id_str: String,
+    pub screen_names: IndexMap<String, Option<Vec<NaiveDate>>>,
+    #[serde(default)]
+    pub coverage: Coverage,
+}
+
+impl From<Account> for ExtendedAccount {
+    fn from(account: Account) -> Self {
+        Self {
+            id: account.id,
+            id_str: account.id.to_string(),
+            screen_names: account.screen_names,
+            coverage: account.coverage,
+        }
+    }
+}
+
+/// The accounts observed with a screen name, as served by the web API.
+#[derive(Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ExtendedScreenNameResult {
+    pub accounts: Vec<ExtendedAccount>,
+}
+
+impl ExtendedScreenNameResult {
+    pub fn includes_screen_name(&self, screen_name: &str) -> bool {
+        let target_screen_name = screen_name_to_key(screen_name);
+        self.accounts.iter().any(|account| {
+            account
+                .screen_names
+                .keys()
+                .any(|screen_name| screen_name_to_key(screen_name) == target_screen_name)
+        })
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Note {
+    pub timestamp: DateTime<Utc>,
+    pub author: String,
+    pub text: String,
+}
+
+/// A completed import, as recorded in the import ledger.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ImportRecord {
+    pub timestamp: DateTime<Utc>,
+    pub source: String,
+    pub hash: Option<String>,
+    pub lines_read: u64,
+    pub lines_skipped: u64,
+    pub pairs: u64,
+    pub new_pairs: u64,
+    /// Missing from records written before new accounts were tracked
+    #[serde(default)]
+    pub new_accounts: u64,
+    pub dates_added: u64,
+    pub duration_ms: u64,
+    pub version: String,
+}
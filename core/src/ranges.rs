@@ -0,0 +1,160 @@
+//! Observation dates as runs of consecutive days.
+//!
+//! An account that was observed daily for years has thousands of dates but only a handful of
+//! runs, so results in this form take a fraction of the memory, and say what most consumers want
+//! to know (when a screen name was in use) directly.
+use chrono::{Duration, NaiveDate};
+use serde_derive::{Deserialize, Serialize};
+
+/// A run of consecutive days.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct DateRange {
+    pub start: NaiveDate,
+    /// Last day of the run (inclusive)
+    pub end: NaiveDate,
+}
+
+impl DateRange {
+    pub fn days(&self) -> usize {
+        (self.end - self.start).num_days() as usize + 1
+    }
+
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.start <= date && date <= self.end
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = NaiveDate> {
+        let start = self.start;
+
+        (0..self.days() as i64).map(move |offset| start + Duration::days(offset))
+    }
+}
+
+/// A set of dates as sorted runs of consecutive days (no two runs overlap or touch).
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct DateRanges(Vec<DateRange>);
+
+impl DateRanges {
+    /// Group dates (in any order, possibly with duplicates) into runs.
+    pub fn from_dates<I: IntoIterator<Item = NaiveDate>>(dates: I) -> Self {
+        let mut dates = dates.into_iter().collect::<Vec<_>>();
+        dates.sort_unstable();
+        dates.dedup();
+
+        let mut ranges: Vec<DateRange> = vec![];
+
+        for date in dates {
+            match ranges.last_mut() {
+                Some(range) if range.end.succ_opt() == Some(date) => range.end = date,
+                _ => ranges.push(DateRange {
+                    start: date,
+                    end: date,
+                }),
+            }
+        }
+
+        Self(ranges)
+    }
+
+    pub fn ranges(&self) -> &[DateRange] {
+        &self.0
+    }
+
+    pub fn first(&self) -> Option<NaiveDate> {
+        self.0.first().map(|range| range.start)
+    }
+
+    pub fn last(&self) -> Option<NaiveDate> {
+        self.0.last().map(|range| range.end)
+    }
+
+    /// The number of dates (not runs).
+    pub fn len(&self) -> usize {
+        self.0.iter().map(DateRange::days).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        let index = self.0.partition_point(|range| range.end < date);
+
+        self.0.get(index).is_some_and(|range| range.contains(date))
+    }
+
+    /// The dates in order.
+    pub fn iter(&self) -> impl Iterator<Item = NaiveDate> + '_ {
+        self.0.iter().flat_map(DateRange::iter)
+    }
+
+    /// The dates in order, as in results that list every date.
+    pub fn expand(&self) -> Vec<NaiveDate> {
+        let mut dates = Vec::with_capacity(self.len());
+        dates.extend(self.iter());
+        dates
+    }
+}
+
+impl FromIterator<NaiveDate> for DateRanges {
+    fn from_iter<I: IntoIterator<Item = NaiveDate>>(dates: I) -> Self {
+        Self::from_dates(dates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn date_ranges() {
+        let date = |day| NaiveDate::from_ymd_opt(2020, 2, day).unwrap();
+        let march = NaiveDate::from_ymd_opt(2020, 3, 1).unwrap();
+        let dates = [
+            date(10),
+            date(1),
+            date(2),
+            date(3),
+            date(10),
+            march,
+            date(29),
+        ];
+        let ranges = dates.iter().copied().collect::<DateRanges>();
+
+        assert_eq!(
+            ranges.ranges(),
+            &[
+                DateRange {
+                    start: date(1),
+                    end: date(3)
+                },
+                DateRange {
+                    start: date(10),
+                    end: date(10)
+                },
+                // Runs continue across months
+                DateRange {
+                    start: date(29),
+                    end: march
+                },
+            ]
+        );
+        assert_eq!(ranges.len(), 6);
+        assert_eq!(ranges.first(), Some(date(1)));
+        assert_eq!(ranges.last(), Some(march));
+        assert!(ranges.contains(date(2)));
+        assert!(!ranges.contains(date(4)));
+        assert!(!ranges.contains(date(11)));
+        assert_eq!(
+            ranges.expand(),
+            vec![date(1), date(2), date(3), date(10), date(29), march]
+        );
+        assert_eq!(DateRanges::from_dates(ranges.expand()), ranges);
+        assert!(DateRanges::default().is_empty());
+        assert_eq!(
+            serde_json::to_value(&ranges).unwrap()[0],
+            serde_json::json!({ "start": "2020-02-01", "end": "2020-02-03" })
+        );
+    }
+}
@@ -0,0 +1,383 @@
+//! Per-client rate limiting for lookup servers.
+//!
+//! Clients are identified by IPv4 address, or by IPv6 /64 network, since a single host usually
+//! has a whole /64 to itself and could otherwise pick a new address for every request. Clients in
+//! allowlisted networks and requests with an API key are never limited. Counters can be saved to
+//! a file, so that restarting the server doesn't reset them.
+use crate::db::Error;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Header that carries an API key.
+pub const API_KEY_HEADER: &str = "X-API-Key";
+/// Length of the IPv6 prefix that identifies a client.
+pub const IPV6_CLIENT_PREFIX_LEN: u8 = 64;
+/// Default length of a rate limiting window (in seconds).
+pub const DEFAULT_WINDOW_SECS: u64 = 60;
+/// How often counters are saved (in seconds).
+const SAVE_INTERVAL_SECS: u64 = 10;
+
+/// A network in CIDR notation (`192.0.2.0/24`, `2001:db8::/32`, or a single address).
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Cidr {
+    address: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.address, canonical(address)) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                prefix_matches(&network.octets(), &address.octets(), self.prefix_len)
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                prefix_matches(&network.octets(), &address.octets(), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid network: {}", value);
+        let (address, prefix_len) = match value.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (value, None),
+        };
+
+        let address = canonical(address.parse::<IpAddr>().map_err(|_| invalid())?);
+        let max_len = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len.parse::<u8>().map_err(|_| invalid())?,
+            None => max_len,
+        };
+
+        if prefix_len <= max_len {
+            Ok(Self {
+                address,
+                prefix_len,
+            })
+        } else {
+            Err(invalid())
+        }
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Rate limiting settings.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct RateLimitConfig {
+    /// Requests allowed per client in each window
+    pub requests: u32,
+    /// Window length in seconds
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+    /// Networks whose clients are never limited
+    #[serde(default)]
+    pub allowed_networks: Vec<Cidr>,
+    /// Keys that exempt requests from limits
+    #[serde(default)]
+    pub api_keys: Vec<String>,
+    /// File to save counters in across restarts
+    pub state: Option<PathBuf>,
+}
+
+fn default_window_secs() -> u64 {
+    DEFAULT_WINDOW_SECS
+}
+
+/// The outcome of checking a request against the limits.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Decision {
+    /// The request is from an allowlisted network or has an API key
+    Exempt,
+    Allowed {
+        remaining: u32,
+    },
+    /// The client has used up its requests for the window, which ends in the given number of
+    /// seconds
+    Limited {
+        retry_after: u64,
+    },
+}
+
+impl Decision {
+    pub fn is_limited(&self) -> bool {
+        matches!(self, Decision::Limited { .. })
+    }
+}
+
+/// A client's requests in the current window.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+struct Counter {
+    window_start: u64,
+    requests: u32,
+}
+
+/// Counts requests per client in fixed windows.
+///
+/// The number of requests and the window length can be changed while the limiter is in use.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    requests: AtomicU32,
+    window_secs: AtomicU64,
+    /// Counters by client, and when they were last saved
+    state: Mutex<(HashMap<String, Counter>, u64)>,
+}
+
+impl RateLimiter {
+    /// Create a limiter, loading saved counters if the configuration has a state file that exists.
+    pub fn new(config: RateLimitConfig) -> Result<Self, Error> {
+        let counters = match &config.state {
+            Some(path) if path.exists() => serde_json::from_slice(&std::fs::read(path)?)?,
+            _ => HashMap::new(),
+        };
+
+        Ok(Self {
+            requests: AtomicU32::new(config.requests),
+            window_secs: AtomicU64::new(config.window_secs),
+            config,
+            state: Mutex::new((counters, 0)),
+        })
+    }
+
+    /// The requests allowed per client in each window, and the window length in seconds.
+    pub fn limits(&self) -> (u32, u64) {
+        (
+            self.requests.load(Ordering::Relaxed),
+            self.window_secs.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Change the limits, starting a new window for clients whose current one no longer lines
+    /// up with the new length.
+    pub fn set_limits(&self, requests: u32, window_secs: u64) {
+        self.requests.store(requests, Ordering::Relaxed);
+        self.window_secs.store(window_secs, Ordering::Relaxed);
+    }
+
+    /// Count a request from an address, with an API key if it has one, at a time in seconds
+    /// since the epoch.
+    ///
+    /// Saving failures are logged rather than returned, since they shouldn't fail requests.
+    pub fn check(&self, address: IpAddr, api_key: Option<&str>, now: u64) -> Decision {
+        let exempt = api_key.is_some_and(|api_key| {
+            self.config
+                .api_keys
+                .iter()
+                .any(|key| constant_time_eq(key.as_bytes(), api_key.as_bytes()))
+        }) || self
+            .config
+            .allowed_networks
+            .iter()
+            .any(|network| network.contains(address));
+
+        if exempt {
+            return Decision::Exempt;
+        }
+
+        let (requests, window_secs) = self.limits();
+        let window_secs = window_secs.max(1);
+        let window_start = now - now % window_secs;
+
+        let mut state = self
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let counter = state.0.entry(client_key(address)).or_insert(Counter {
+            window_start,
+            requests: 0,
+        });
+
+        if counter.window_start != window_start {
+            *counter = Counter {
+                window_start,
+                requests: 0,
+            };
+        }
+
+        let decision = if counter.requests < requests {
+            counter.requests += 1;
+
+            Decision::Allowed {
+                remaining: requests - counter.requests,
+            }
+        } else {
+            Decision::Limited {
+                retry_after: window_start + window_secs - now,
+            }
+        };
+
+        if let Some(path) = &self.config.state {
+            if now >= state.1 + SAVE_INTERVAL_SECS {
+                // Counters from earlier windows no longer matter
+                state
+                    .0
+                    .retain(|_, counter| counter.window_start == window_start);
+                state.1 = now;
+
+                if let Err(error) = save(path, &state.0) {
+                    log::warn!("Failed to save rate limiting state: {:?}", error);
+                }
+            }
+        }
+
+        decision
+    }
+}
+
+/// Write counters to a temporary file and then rename it, so that a crash never leaves a partly
+/// written file.
+fn save(path: &Path, counters: &HashMap<String, Counter>) -> Result<(), Error> {
+    let temporary = path.with_extension("tmp");
+
+    std::fs::write(&temporary, serde_json::to_vec(counters)?)?;
+    Ok(std::fs::rename(temporary, path)?)
+}
+
+/// The key that identifies a client: its IPv4 address, or its IPv6 /64 network.
+pub fn client_key(address: IpAddr) -> String {
+    match canonical(address) {
+        IpAddr::V4(address) => address.to_string(),
+        IpAddr::V6(address) => {
+            let mut octets = address.octets();
+            octets[IPV6_CLIENT_PREFIX_LEN as usize / 8..].fill(0);
+
+            format!(
+                "{}/{}",
+                std::net::Ipv6Addr::from(octets),
+                IPV6_CLIENT_PREFIX_LEN
+            )
+        }
+    }
+}
+
+/// IPv4-mapped IPv6 addresses are treated as the IPv4 addresses they represent.
+fn canonical(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(address, IpAddr::V4),
+        IpAddr::V4(_) => address,
+    }
+}
+
+fn prefix_matches(network: &[u8], address: &[u8], prefix_len: u8) -> bool {
+    let full_bytes = prefix_len as usize / 8;
+    let remaining_bits = prefix_len % 8;
+
+    network[..full_bytes] == address[..full_bytes]
+        && (remaining_bits == 0 || {
+            let mask = 0xff << (8 - remaining_bits);
+            network[full_bytes] & mask == address[full_bytes] & mask
+        })
+}
+
+/// Compare keys in time that doesn't depend on where they differ.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn networks() {
+        let network = "192.0.2.0/23".parse::<Cidr>().unwrap();
+        assert!(network.contains("192.0.3.255".parse().unwrap()));
+        assert!(!network.contains("192.0.4.0".parse().unwrap()));
+        assert!(network.contains("::ffff:192.0.2.1".parse().unwrap()));
+
+        let network = "2001:db8::/32".parse::<Cidr>().unwrap();
+        assert!(network.contains("2001:db8:ffff::1".parse().unwrap()));
+        assert!(!network.contains("2001:db9::1".parse().unwrap()));
+        assert!(!network.contains("192.0.2.1".parse().unwrap()));
+
+        assert!("10.0.0.1"
+            .parse::<Cidr>()
+            .unwrap()
+            .contains("10.0.0.1".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("example.com/8".parse::<Cidr>().is_err());
+
+        assert_eq!(
+            client_key("2001:db8:1:2:3:4:5:6".parse().unwrap()),
+            "2001:db8:1:2::/64"
+        );
+        assert_eq!(client_key("::ffff:192.0.2.1".parse().unwrap()), "192.0.2.1");
+    }
+
+    #[test]
+    fn limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = RateLimitConfig {
+            requests: 2,
+            window_secs: 60,
+            allowed_networks: vec!["10.0.0.0/8".parse().unwrap()],
+            api_keys: vec!["secret".to_string()],
+            state: Some(dir.path().join("rate-limits.json")),
+        };
+        let limiter = RateLimiter::new(config.clone()).unwrap();
+
+        let first = "2001:db8::1".parse().unwrap();
+        let same_network = "2001:db8::ffff:2".parse().unwrap();
+        let other_network = "2001:db8:0:1::1".parse().unwrap();
+
+        assert_eq!(
+            limiter.check(first, None, 120),
+            Decision::Allowed { remaining: 1 }
+        );
+        assert_eq!(
+            limiter.check(same_network, None, 125),
+            Decision::Allowed { remaining: 0 }
+        );
+        assert_eq!(
+            limiter.check(first, None, 130),
+            Decision::Limited { retry_after: 50 }
+        );
+        assert_eq!(
+            limiter.check(other_network, None, 130),
+            Decision::Allowed { remaining: 1 }
+        );
+        assert_eq!(limiter.check(first, Some("secret"), 130), Decision::Exempt);
+        assert!(limiter.check(first, Some("guess"), 130).is_limited());
+        assert_eq!(
+            limiter.check("10.1.2.3".parse().unwrap(), None, 130),
+            Decision::Exempt
+        );
+        assert_eq!(
+            limiter.check(first, None, 180),
+            Decision::Allowed { remaining: 1 }
+        );
+
+        // Counters survive a restart
+        limiter.check(other_network, None, 190);
+        let limiter = RateLimiter::new(config).unwrap();
+        assert_eq!(
+            limiter.check(other_network, None, 191),
+            Decision::Allowed { remaining: 0 }
+        );
+
+        limiter.set_limits(3, 60);
+        assert_eq!(
+            limiter.check(other_network, None, 192),
+            Decision::Allowed { remaining: 0 }
+        );
+    }
+}
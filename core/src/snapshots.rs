@@ -0,0 +1,262 @@
+//! Immutable evidence snapshots of lookup results.
+//!
+//! A snapshot records an account's result as it was served at a point in time, along with the
+//! SHA-256 of the result and the time it was taken. Each snapshot is stored as a file named by its
+//! ID, which is derived from the file's contents, so a permalink to it always serves exactly what
+//! was stored, even after the account's history changes or is redacted.
+//!
+//! Timestamp proofs of a snapshot (see [`crate::timestamping`]) are stored next to it, with the
+//! extensions their verification tools expect.
+use crate::db::Error;
+use crate::timestamping::Proof;
+use chrono::{DateTime, SubsecRound, Utc};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// Number of bytes of the snapshot's digest used in its ID.
+const ID_LEN: usize = 16;
+/// Default upper bound on the number of snapshots in a store.
+pub const DEFAULT_LIMIT: usize = 10_000;
+
+/// A stored result.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Snapshot {
+    pub id: u64,
+    pub taken: DateTime<Utc>,
+    /// Hex-encoded SHA-256 of the result's JSON
+    pub sha256: String,
+    pub result: Value,
+}
+
+impl Snapshot {
+    /// A snapshot of a result taken at the given time (truncated to whole seconds).
+    pub fn new(id: u64, result: Value, taken: DateTime<Utc>) -> Result<Self, Error> {
+        let sha256 = hex(&Sha256::digest(serde_json::to_vec(&result)?));
+
+        Ok(Self {
+            id,
+            taken: taken.trunc_subsecs(0),
+            sha256,
+            result,
+        })
+    }
+}
+
+/// A directory of snapshots.
+#[derive(Clone, Debug)]
+pub struct SnapshotStore {
+    dir: PathBuf,
+    /// Upper bound on the number of snapshots
+    limit: usize,
+}
+
+impl SnapshotStore {
+    /// A store in the given directory (holding at most [`DEFAULT_LIMIT`] snapshots), which is
+    /// created when the first snapshot is saved.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            limit: DEFAULT_LIMIT,
+        }
+    }
+
+    /// Change the number of snapshots the store can hold.
+    pub fn with_limit(self, limit: usize) -> Self {
+        Self { limit, ..self }
+    }
+
+    /// Save a snapshot, returning its ID.
+    ///
+    /// Saving the same snapshot again returns the same ID without changing the stored file. New
+    /// snapshots are refused once the store holds its limit.
+    pub fn save(&self, snapshot: &Snapshot) -> Result<String, Error> {
+        let contents = serde_json::to_vec(snapshot)?;
+        let id = hex(&Sha256::digest(&contents)[..ID_LEN]);
+        let path = self.path(&id);
+
+        if !path.exists() {
+            if self.count()? >= self.limit {
+                return Err(Error::SnapshotLimit(self.limit));
+            }
+
+            std::fs::create_dir_all(&self.dir)?;
+
+            // Write to a temporary file and then rename it, so that readers never see a partly
+            // written snapshot
+            let temporary = path.with_extension("json.tmp");
+            std::fs::write(&temporary, contents)?;
+            std::fs::rename(temporary, path)?;
+        }
+
+        Ok(id)
+    }
+
+    /// The stored contents of a snapshot, or `None` if there's no snapshot with the ID.
+    ///
+    /// The contents are returned exactly as stored, after checking that they still match the ID.
+    pub fn load(&self, id: &str) -> Result<Option<Vec<u8>>, Error> {
+        if !is_valid_id(id) {
+            return Ok(None);
+        }
+
+        let path = self.path(id);
+
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let contents = std::fs::read(path)?;
+
+        if hex(&Sha256::digest(&contents)[..ID_LEN]) == id {
+            Ok(Some(contents))
+        } else {
+            Err(Error::CorruptSnapshot(id.to_string()))
+        }
+    }
+
+    /// The SHA-256 of a snapshot's stored contents (which its timestamps are for), or `None` if
+    /// there's no snapshot with the ID.
+    pub fn digest(&self, id: &str) -> Result<Option<[u8; 32]>, Error> {
+        Ok(self
+            .load(id)?
+            .map(|contents| Sha256::digest(contents).into()))
+    }
+
+    /// Save a timestamp proof of a snapshot, replacing any earlier proof of the same kind.
+    pub fn save_proof(&self, id: &str, proof: Proof, contents: &[u8]) -> Result<(), Error> {
+        let path = self.proof_path(id, proof);
+        let temporary = path.with_extension(format!("{}.tmp", proof.extension()));
+
+        std::fs::write(&temporary, contents)?;
+        Ok(std::fs::rename(temporary, path)?)
+    }
+
+    /// A timestamp proof of a snapshot, or `None` if there isn't one of the kind.
+    pub fn load_proof(&self, id: &str, proof: Proof) -> Result<Option<Vec<u8>>, Error> {
+        if !is_valid_id(id) {
+            return Ok(None);
+        }
+
+        let path = self.proof_path(id, proof);
+
+        if path.exists() {
+            Ok(Some(std::fs::read(path)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The kinds of timestamp proofs stored for a snapshot.
+    pub fn proofs(&self, id: &str) -> Vec<Proof> {
+        Proof::ALL
+            .into_iter()
+            .filter(|proof| is_valid_id(id) && self.proof_path(id, *proof).exists())
+            .collect()
+    }
+
+    /// The number of snapshots in the store.
+    pub fn count(&self) -> Result<usize, Error> {
+        if !self.dir.exists() {
+            return Ok(0);
+        }
+
+        let mut count = 0;
+
+        for entry in std::fs::read_dir(&self.dir)? {
+            if entry?
+                .path()
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    fn proof_path(&self, id: &str, proof: Proof) -> PathBuf {
+        self.dir.join(format!("{}.json.{}", id, proof.extension()))
+    }
+}
+
+/// Whether a value has the form of a snapshot ID (which also keeps it from naming another path).
+fn is_valid_id(id: &str) -> bool {
+    id.len() == ID_LEN * 2
+        && id
+            .bytes()
+            .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    #[test]
+    fn snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SnapshotStore::new(dir.path().join("snapshots"));
+        let taken = Utc.with_ymd_and_hms(2022, 1, 2, 3, 4, 5).unwrap();
+        let snapshot = Snapshot::new(1, json!({ "screen_names": { "foo": null } }), taken).unwrap();
+
+        assert_eq!(snapshot.sha256.len(), 64);
+        assert_eq!(
+            store.load("0123456789abcdef0123456789abcdef").unwrap(),
+            None
+        );
+
+        let id = store.save(&snapshot).unwrap();
+        assert!(is_valid_id(&id));
+        assert_eq!(store.save(&snapshot).unwrap(), id);
+
+        let contents = store.load(&id).unwrap().unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Snapshot>(&contents).unwrap(),
+            snapshot
+        );
+
+        let later = Snapshot::new(
+            1,
+            snapshot.result.clone(),
+            taken + chrono::Duration::days(1),
+        );
+        assert_ne!(store.save(&later.unwrap()).unwrap(), id);
+
+        assert_eq!(store.load("../snapshots").unwrap(), None);
+
+        let digest = store.digest(&id).unwrap().unwrap();
+        assert!(hex(&digest).starts_with(&id));
+        assert!(store.proofs(&id).is_empty());
+
+        store
+            .save_proof(&id, Proof::OpenTimestamps, b"proof")
+            .unwrap();
+        assert_eq!(store.proofs(&id), vec![Proof::OpenTimestamps]);
+        assert_eq!(
+            store.load_proof(&id, Proof::OpenTimestamps).unwrap(),
+            Some(b"proof".to_vec())
+        );
+        assert_eq!(store.load_proof(&id, Proof::Rfc3161).unwrap(), None);
+
+        let full = store.clone().with_limit(2);
+        assert_eq!(full.count().unwrap(), 2);
+        assert_eq!(full.save(&snapshot).unwrap(), id);
+        let next = Snapshot::new(2, snapshot.result.clone(), taken).unwrap();
+        assert!(matches!(full.save(&next), Err(Error::SnapshotLimit(2))));
+
+        std::fs::write(store.path(&id), b"{}").unwrap();
+        assert!(matches!(store.load(&id), Err(Error::CorruptSnapshot(_))));
+    }
+}
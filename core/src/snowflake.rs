@@ -0,0 +1,72 @@
+//! Conversion between Twitter snowflake IDs and the times they were generated.
+//!
+//! Tweet IDs (and user IDs for accounts created since late 2013) are snowflakes, whose high bits
+//! are the number of milliseconds since a custom epoch, so an ID gives the time a tweet was posted
+//! or an account was created. Older, sequential IDs carry no time information.
+use chrono::{DateTime, TimeZone, Utc};
+
+/// Milliseconds since the Unix epoch of the Twitter snowflake epoch.
+pub const EPOCH_MILLIS: i64 = 1288834974657;
+
+/// The smallest value treated as a snowflake (sequential IDs never grew this large).
+pub const FIRST_SNOWFLAKE: u64 = 250000000000000;
+
+/// Number of low bits holding the worker and sequence numbers.
+const TIMESTAMP_SHIFT: u32 = 22;
+
+pub fn is_snowflake(id: u64) -> bool {
+    id >= FIRST_SNOWFLAKE
+}
+
+/// The time a snowflake ID was generated, or `None` if the ID isn't a snowflake.
+pub fn to_date_time(id: u64) -> Option<DateTime<Utc>> {
+    if is_snowflake(id) {
+        let millis = i64::try_from(id >> TIMESTAMP_SHIFT).ok()? + EPOCH_MILLIS;
+
+        Utc.timestamp_millis_opt(millis).single()
+    } else {
+        None
+    }
+}
+
+/// The smallest snowflake ID generated at a time (to the millisecond).
+///
+/// Every ID generated at or after the time is at least this value, so it can be used as a lower
+/// bound in range queries. Returns `None` for times before snowflakes were introduced.
+pub fn from_date_time(timestamp: &DateTime<Utc>) -> Option<u64> {
+    let millis = u64::try_from(timestamp.timestamp_millis() - EPOCH_MILLIS).ok()?;
+    let id = millis.checked_shl(TIMESTAMP_SHIFT)?;
+
+    if is_snowflake(id) && id >> TIMESTAMP_SHIFT == millis {
+        Some(id)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let id = 1_212_092_628_029_698_048;
+        let timestamp = to_date_time(id).unwrap();
+
+        assert_eq!(timestamp.to_rfc3339(), "2019-12-31T19:26:16.771+00:00");
+        assert_eq!(
+            from_date_time(&timestamp),
+            Some(id & !((1 << TIMESTAMP_SHIFT) - 1))
+        );
+        assert_eq!(
+            to_date_time(from_date_time(&timestamp).unwrap()),
+            Some(timestamp)
+        );
+
+        assert_eq!(to_date_time(12), None);
+        assert_eq!(
+            from_date_time(&Utc.with_ymd_and_hms(2009, 1, 1, 0, 0, 0).unwrap()),
+            None
+        );
+    }
+}
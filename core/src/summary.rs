@@ -0,0 +1,196 @@
+//! One-line summaries of an account's screen name history.
+//!
+//! Reports and bot replies embed these (like
+//! `@current (a.k.a. @old1 2019–2021, @old2 2021–2023)`), so they're derived here in one way
+//! instead of by every consumer.
+use crate::db::codec::screen_name_to_key;
+use crate::ranges::DateRanges;
+use chrono::{Datelike, NaiveDate};
+use serde_derive::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fmt::Display;
+
+/// A screen name the account used before its current one.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Alias {
+    pub screen_name: String,
+    pub first: NaiveDate,
+    pub last: NaiveDate,
+}
+
+/// An account's most recently observed screen name and the others it has used, oldest first.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Summary {
+    pub current: String,
+    pub previous: Vec<Alias>,
+    /// The date the current screen name was first observed
+    pub current_since: NaiveDate,
+    /// The date of the account's first observation under any screen name
+    pub first_seen: NaiveDate,
+    /// The date of the account's last observation
+    pub last_seen: NaiveDate,
+}
+
+impl Summary {
+    /// Summarize a lookup result, or return `None` if it has no observations.
+    pub fn from_result(result: &HashMap<String, Vec<NaiveDate>>) -> Option<Self> {
+        Self::from_aliases(result.iter().filter_map(|(screen_name, dates)| {
+            Some(Alias {
+                screen_name: screen_name.clone(),
+                first: *dates.iter().min()?,
+                last: *dates.iter().max()?,
+            })
+        }))
+    }
+
+    /// Summarize a lookup result in range form, or return `None` if it has no observations.
+    pub fn from_ranges(result: &HashMap<String, DateRanges>) -> Option<Self> {
+        Self::from_aliases(result.iter().filter_map(|(screen_name, ranges)| {
+            Some(Alias {
+                screen_name: screen_name.clone(),
+                first: ranges.first()?,
+                last: ranges.last()?,
+            })
+        }))
+    }
+
+    /// The summary as served by the APIs, with the summary string as `text`.
+    pub fn to_json(&self, user_id: u64) -> Value {
+        json!({
+            "id": user_id,
+            "text": self.to_string(),
+            "current": self.current,
+            "previous": self.previous,
+        })
+    }
+
+    /// The summary in the compact form served to browser extensions: only names and dates, with
+    /// the ID as a string, since JavaScript numbers can't represent every ID.
+    pub fn to_compact_json(&self, user_id: u64) -> Value {
+        json!({
+            "id": user_id.to_string(),
+            "current": self.current,
+            "previous": self
+                .previous
+                .iter()
+                .map(|alias| alias.screen_name.as_str())
+                .collect::<Vec<_>>(),
+            "first_seen": self.first_seen,
+            "last_seen": self.last_seen,
+        })
+    }
+
+    /// Whether the account has used the screen name (ignoring case).
+    pub fn includes_screen_name(&self, screen_name: &str) -> bool {
+        let target_screen_name = screen_name_to_key(screen_name);
+
+        std::iter::once(&self.current)
+            .chain(self.previous.iter().map(|alias| &alias.screen_name))
+            .any(|screen_name| screen_name_to_key(screen_name) == target_screen_name)
+    }
+
+    fn from_aliases<I: Iterator<Item = Alias>>(aliases: I) -> Option<Self> {
+        let mut aliases = aliases.collect::<Vec<_>>();
+
+        // The current screen name is the one observed last (and of those, the one first observed
+        // most recently), and ties are broken by name so that summaries are stable
+        aliases.sort_by(|a, b| {
+            (a.first, a.last, &a.screen_name).cmp(&(b.first, b.last, &b.screen_name))
+        });
+        let current_index = aliases
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, alias)| (alias.last, alias.first))
+            .map(|(index, _)| index)?;
+        let current = aliases.remove(current_index);
+        let first_seen = aliases
+            .first()
+            .map_or(current.first, |alias| alias.first.min(current.first));
+
+        Some(Self {
+            current: current.screen_name,
+            previous: aliases,
+            current_since: current.first,
+            first_seen,
+            last_seen: current.last,
+        })
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "@{}", self.current)?;
+
+        for (index, alias) in self.previous.iter().enumerate() {
+            let separator = if index == 0 { " (a.k.a. " } else { ", " };
+            let (first, last) = (alias.first.year(), alias.last.year());
+
+            if first == last {
+                write!(f, "{}@{} {}", separator, alias.screen_name, first)?;
+            } else {
+                write!(f, "{}@{} {}–{}", separator, alias.screen_name, first, last)?;
+            }
+        }
+
+        if !self.previous.is_empty() {
+            write!(f, ")")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summaries() {
+        let date = |year, month| NaiveDate::from_ymd_opt(year, month, 1).unwrap();
+        let mut result = HashMap::new();
+        result.insert("old2".to_string(), vec![date(2021, 9), date(2023, 2)]);
+        result.insert("current".to_string(), vec![date(2023, 3), date(2024, 1)]);
+        result.insert("old1".to_string(), vec![date(2019, 1), date(2021, 8)]);
+        result.insert("brief".to_string(), vec![date(2020, 5)]);
+
+        let summary = Summary::from_result(&result).unwrap();
+        assert_eq!(summary.current, "current");
+        assert_eq!(
+            summary.to_string(),
+            "@current (a.k.a. @old1 2019–2021, @brief 2020, @old2 2021–2023)"
+        );
+
+        let ranges = result
+            .iter()
+            .map(|(screen_name, dates)| {
+                (
+                    screen_name.clone(),
+                    DateRanges::from_dates(dates.iter().copied()),
+                )
+            })
+            .collect();
+        assert_eq!(Summary::from_ranges(&ranges).as_ref(), Some(&summary));
+        assert_eq!(
+            summary.to_json(1)["previous"][0],
+            serde_json::json!({ "screen_name": "old1", "first": "2019-01-01", "last": "2021-08-01" })
+        );
+
+        assert_eq!(
+            summary.to_compact_json(1),
+            serde_json::json!({
+                "id": "1",
+                "current": "current",
+                "previous": ["old1", "brief", "old2"],
+                "first_seen": "2019-01-01",
+                "last_seen": "2024-01-01",
+            })
+        );
+        assert!(summary.includes_screen_name("OLD2"));
+        assert!(!summary.includes_screen_name("old3"));
+
+        let single = HashMap::from([("foo".to_string(), vec![date(2020, 1)])]);
+        assert_eq!(Summary::from_result(&single).unwrap().to_string(), "@foo");
+        assert_eq!(Summary::from_result(&HashMap::new()), None);
+    }
+}
@@ -0,0 +1,401 @@
+//! Screen name histories as date ranges, for charting.
+use chrono::{Duration, NaiveDate};
+use serde_derive::Serialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+
+/// Observations further apart than this are shown as separate ranges (with a gap between them)
+/// by default.
+pub const DEFAULT_MAX_GAP_DAYS: i64 = 30;
+
+/// An account's screen name ranges and the gaps in its observations.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Timeline {
+    pub segments: Vec<Segment>,
+    pub gaps: Vec<Gap>,
+}
+
+impl Timeline {
+    pub fn new(result: &HashMap<String, Vec<NaiveDate>>, max_gap_days: i64) -> Self {
+        Self {
+            segments: segments(result, max_gap_days),
+            gaps: gaps(result, max_gap_days),
+        }
+    }
+}
+
+/// A range of days over which an account used a screen name.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Segment {
+    pub screen_name: String,
+    /// First day of the range
+    pub start: NaiveDate,
+    /// Last day of the range (inclusive)
+    pub end: NaiveDate,
+    /// Number of observation dates within the range
+    pub observations: usize,
+}
+
+/// A period with no observations of an account under any screen name.
+///
+/// Long gaps often correspond to suspensions or deactivations.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Gap {
+    /// First day without observations
+    pub start: NaiveDate,
+    /// Last day without observations (inclusive)
+    pub end: NaiveDate,
+    pub days: i64,
+}
+
+/// Find the periods longer than `min_days` between consecutive observations of an account.
+pub fn gaps(result: &HashMap<String, Vec<NaiveDate>>, min_days: i64) -> Vec<Gap> {
+    let mut dates = result.values().flatten().copied().collect::<Vec<_>>();
+    dates.sort();
+    dates.dedup();
+
+    dates
+        .windows(2)
+        .filter_map(|window| {
+            let days = (window[1] - window[0]).num_days() - 1;
+
+            if days > min_days.max(0) {
+                Some(Gap {
+                    start: window[0] + Duration::days(1),
+                    end: window[1] - Duration::days(1),
+                    days,
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Group an account's observation dates into non-overlapping ranges.
+///
+/// Each screen name's dates are split into ranges wherever consecutive observations are more than
+/// `max_gap_days` apart. Where ranges for different screen names overlap, the screen name whose
+/// range started later is shown for the overlapping days, since it is the more recent rename, and
+/// the earlier range is cut (or split in two if it continues past the later one). Segments are
+/// sorted by start date.
+pub fn segments(result: &HashMap<String, Vec<NaiveDate>>, max_gap_days: i64) -> Vec<Segment> {
+    let max_gap = Duration::days(max_gap_days.max(0));
+    let mut ranges = vec![];
+
+    for (screen_name, dates) in result {
+        let mut dates = dates.clone();
+        dates.sort();
+        dates.dedup();
+
+        let mut iter = dates.into_iter();
+
+        if let Some(first) = iter.next() {
+            let (mut start, mut end) = (first, first);
+
+            for date in iter {
+                if date - end > max_gap {
+                    ranges.push((start, end, screen_name.as_str()));
+                    start = date;
+                }
+                end = date;
+            }
+
+            ranges.push((start, end, screen_name.as_str()));
+        }
+    }
+
+    let mut boundaries = ranges
+        .iter()
+        .flat_map(|(start, end, _)| [*start, *end + Duration::days(1)])
+        .collect::<Vec<_>>();
+    boundaries.sort();
+    boundaries.dedup();
+
+    let mut segments: Vec<Segment> = vec![];
+
+    for window in boundaries.windows(2) {
+        let (start, end) = (window[0], window[1] - Duration::days(1));
+
+        // The latest-starting range covering these days wins (ties go to the first screen name)
+        let winner = ranges
+            .iter()
+            .filter(|(range_start, range_end, _)| *range_start <= start && start <= *range_end)
+            .max_by(|(start_a, _, screen_name_a), (start_b, _, screen_name_b)| {
+                start_a
+                    .cmp(start_b)
+                    .then_with(|| screen_name_b.cmp(screen_name_a))
+            });
+
+        if let Some((_, _, screen_name)) = winner {
+            match segments.last_mut() {
+                Some(last)
+                    if last.screen_name == *screen_name
+                        && last.end + Duration::days(1) == start =>
+                {
+                    last.end = end;
+                }
+                _ => segments.push(Segment {
+                    screen_name: screen_name.to_string(),
+                    start,
+                    end,
+                    observations: 0,
+                }),
+            }
+        }
+    }
+
+    for segment in &mut segments {
+        segment.observations = result[&segment.screen_name]
+            .iter()
+            .filter(|date| segment.start <= **date && **date <= segment.end)
+            .collect::<HashSet<_>>()
+            .len();
+    }
+
+    segments
+}
+
+/// How a screen name's status changed between two dates.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    /// First observed after the first date (and on or before the second)
+    Added,
+    /// Observed by the first date but not after it, while another screen name was
+    Dropped,
+    /// Observed both by the first date and after it
+    Kept,
+    /// Observed by the first date, with no later observations of the account to confirm it
+    Unconfirmed,
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Change::Added => "added",
+            Change::Dropped => "dropped",
+            Change::Kept => "kept",
+            Change::Unconfirmed => "unconfirmed",
+        })
+    }
+}
+
+/// A screen name's change between two dates.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct ScreenNameChange {
+    pub screen_name: String,
+    pub change: Change,
+    /// The first observation after the first date for added screen names, and the last
+    /// observation on or before the second date otherwise
+    pub date: NaiveDate,
+}
+
+/// Compare the screen names an account was known by on one date with those it was known by on a
+/// later date, based on the observations available.
+///
+/// Observations after `to` are ignored. Screen names are only reported as dropped if the account
+/// was observed under another screen name after `from`, since a lack of observations doesn't
+/// show that a screen name stopped being used. Changes are sorted by date.
+pub fn diff(
+    result: &HashMap<String, Vec<NaiveDate>>,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Vec<ScreenNameChange> {
+    let observed_since = result
+        .values()
+        .flatten()
+        .any(|date| from < *date && *date <= to);
+
+    let mut changes = result
+        .iter()
+        .filter_map(|(screen_name, dates)| {
+            let before = dates.iter().filter(|date| **date <= from).max();
+            let after = dates.iter().filter(|date| from < **date && **date <= to);
+
+            let (change, date) = match (before, after.clone().max()) {
+                (None, Some(_)) => (Change::Added, *after.min()?),
+                (Some(_), Some(last)) => (Change::Kept, *last),
+                (Some(last), None) if observed_since => (Change::Dropped, *last),
+                (Some(last), None) => (Change::Unconfirmed, *last),
+                (None, None) => return None,
+            };
+
+            Some(ScreenNameChange {
+                screen_name: screen_name.clone(),
+                change,
+                date,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    changes.sort_by(|a, b| (a.date, &a.screen_name).cmp(&(b.date, &b.screen_name)));
+    changes
+}
+
+/// A Vega-Lite specification for a chart of an account's timeline, with the data inlined.
+///
+/// Segments are drawn as bars, one row per screen name, and gaps are shaded across all rows.
+pub fn vega_lite_spec(id: u64, timeline: &Timeline) -> Value {
+    // Ranges are inclusive, so bars extend to the end of their last day
+    let transform = json!([
+        { "calculate": "timeOffset('date', datum.end, 1)", "as": "end_exclusive" }
+    ]);
+
+    json!({
+        "$schema": "https://vega.github.io/schema/vega-lite/v5.json",
+        "title": format!("Screen names for account {}", id),
+        "layer": [
+            {
+                "data": { "values": timeline.segments },
+                "transform": transform,
+                "mark": "bar",
+                "encoding": {
+                    "y": {
+                        "field": "screen_name",
+                        "type": "nominal",
+                        "sort": null,
+                        "title": "Screen name"
+                    },
+                    "x": { "field": "start", "type": "temporal", "title": "Date" },
+                    "x2": { "field": "end_exclusive" },
+                    "tooltip": [
+                        { "field": "screen_name", "type": "nominal" },
+                        { "field": "start", "type": "temporal" },
+                        { "field": "end", "type": "temporal" },
+                        { "field": "observations", "type": "quantitative" }
+                    ]
+                }
+            },
+            {
+                "data": { "values": timeline.gaps },
+                "transform": transform,
+                "mark": { "type": "rect", "color": "gray", "opacity": 0.2 },
+                "encoding": {
+                    "x": { "field": "start", "type": "temporal" },
+                    "x2": { "field": "end_exclusive" },
+                    "tooltip": [
+                        { "field": "start", "type": "temporal" },
+                        { "field": "end", "type": "temporal" },
+                        {
+                            "field": "days",
+                            "type": "quantitative",
+                            "title": "Days without observations"
+                        }
+                    ]
+                }
+            }
+        ]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2020, month, day).unwrap()
+    }
+
+    fn segment(
+        screen_name: &str,
+        start: NaiveDate,
+        end: NaiveDate,
+        observations: usize,
+    ) -> Segment {
+        Segment {
+            screen_name: screen_name.to_string(),
+            start,
+            end,
+            observations,
+        }
+    }
+
+    #[test]
+    fn segments_split_and_resolve() {
+        let mut result = HashMap::new();
+        // Two ranges separated by a long gap
+        result.insert(
+            "foo".to_string(),
+            vec![date(1, 1), date(1, 10), date(1, 20), date(6, 1), date(6, 5)],
+        );
+        // Overlaps the end of foo's first range
+        result.insert("bar".to_string(), vec![date(1, 15), date(2, 1)]);
+        // Falls entirely within bar's range
+        result.insert("baz".to_string(), vec![date(1, 25)]);
+
+        assert_eq!(
+            segments(&result, DEFAULT_MAX_GAP_DAYS),
+            vec![
+                segment("foo", date(1, 1), date(1, 14), 2),
+                segment("bar", date(1, 15), date(1, 24), 1),
+                segment("baz", date(1, 25), date(1, 25), 1),
+                segment("bar", date(1, 26), date(2, 1), 1),
+                segment("foo", date(6, 1), date(6, 5), 2),
+            ]
+        );
+
+        // With no gap allowed, every observation is its own range
+        assert_eq!(segments(&result, 0).len(), 8);
+        assert!(segments(&HashMap::new(), DEFAULT_MAX_GAP_DAYS).is_empty());
+    }
+
+    #[test]
+    fn gaps_between_observations() {
+        let mut result = HashMap::new();
+        result.insert("foo".to_string(), vec![date(1, 1), date(1, 20), date(6, 1)]);
+        // Observations of other screen names close gaps
+        result.insert("bar".to_string(), vec![date(3, 1)]);
+
+        assert_eq!(
+            gaps(&result, DEFAULT_MAX_GAP_DAYS),
+            vec![
+                Gap {
+                    start: date(1, 21),
+                    end: date(2, 29),
+                    days: 40
+                },
+                Gap {
+                    start: date(3, 2),
+                    end: date(5, 31),
+                    days: 91
+                }
+            ]
+        );
+        assert_eq!(gaps(&result, 90).len(), 1);
+        assert_eq!(Timeline::new(&result, 0).gaps.len(), 3);
+    }
+
+    #[test]
+    fn diff_between_dates() {
+        let mut result = HashMap::new();
+        result.insert("foo".to_string(), vec![date(1, 1), date(2, 1)]);
+        result.insert("bar".to_string(), vec![date(1, 10), date(4, 1)]);
+        result.insert("baz".to_string(), vec![date(3, 1), date(8, 1)]);
+        // Only observed after the second date
+        result.insert("qux".to_string(), vec![date(9, 1)]);
+
+        let change = |screen_name: &str, change, date| ScreenNameChange {
+            screen_name: screen_name.to_string(),
+            change,
+            date,
+        };
+
+        assert_eq!(
+            diff(&result, date(2, 15), date(6, 1)),
+            vec![
+                change("foo", Change::Dropped, date(2, 1)),
+                change("baz", Change::Added, date(3, 1)),
+                change("bar", Change::Kept, date(4, 1)),
+            ]
+        );
+        assert_eq!(
+            diff(&result, date(8, 15), date(8, 20)),
+            vec![
+                change("foo", Change::Unconfirmed, date(2, 1)),
+                change("bar", Change::Unconfirmed, date(4, 1)),
+                change("baz", Change::Unconfirmed, date(8, 1)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Validated parsing of observation timestamps.
+//!
+//! Every importer accepts the same forms: epoch seconds, epoch milliseconds, and ISO 8601 (RFC
+//! 3339) date-times or dates. Values that are negative or out of range are errors instead of
+//! panics or silently wrapped dates.
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use std::str::FromStr;
+
+/// Integers at least this large are taken to be epoch milliseconds.
+///
+/// As seconds they'd be after the year 5000, and as milliseconds they're after early 1973, so no
+/// plausible observation time is ambiguous.
+pub const MIN_EPOCH_MILLIS: i64 = 100_000_000_000;
+
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+pub enum Error {
+    #[error("Invalid timestamp: {0}")]
+    Invalid(String),
+    #[error("Negative timestamp: {0}")]
+    Negative(i64),
+    #[error("Timestamp out of range: {0}")]
+    OutOfRange(i64),
+}
+
+pub fn from_epoch_seconds(seconds: i64) -> Result<DateTime<Utc>, Error> {
+    if seconds < 0 {
+        return Err(Error::Negative(seconds));
+    }
+
+    Utc.timestamp_opt(seconds, 0)
+        .single()
+        .ok_or(Error::OutOfRange(seconds))
+}
+
+pub fn from_epoch_millis(millis: i64) -> Result<DateTime<Utc>, Error> {
+    if millis < 0 {
+        return Err(Error::Negative(millis));
+    }
+
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .ok_or(Error::OutOfRange(millis))
+}
+
+/// Parse epoch seconds, or epoch milliseconds if the value is at least [`MIN_EPOCH_MILLIS`].
+pub fn from_epoch(value: i64) -> Result<DateTime<Utc>, Error> {
+    if value >= MIN_EPOCH_MILLIS {
+        from_epoch_millis(value)
+    } else {
+        from_epoch_seconds(value)
+    }
+}
+
+/// Parse an integer epoch time (see [`from_epoch`]), an RFC 3339 date-time (for example
+/// `2022-01-01T12:00:00Z`), or a date (taken as midnight UTC).
+pub fn parse(value: &str) -> Result<DateTime<Utc>, Error> {
+    let value = value.trim();
+
+    if let Ok(epoch) = value.parse::<i64>() {
+        return from_epoch(epoch);
+    }
+
+    DateTime::parse_from_rfc3339(value)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .map(|timestamp| timestamp.and_utc())
+        })
+        .ok_or_else(|| Error::Invalid(value.to_string()))
+}
+
+/// The form timestamps in an input are expected in, when they aren't detected automatically.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Format {
+    /// Any of the forms [`parse`] accepts
+    #[default]
+    Auto,
+    Seconds,
+    Millis,
+    /// RFC 3339 date-times or dates
+    Iso,
+}
+
+impl Format {
+    pub fn parse(self, value: &str) -> Result<DateTime<Utc>, Error> {
+        let epoch = || {
+            value
+                .trim()
+                .parse::<i64>()
+                .map_err(|_| Error::Invalid(value.to_string()))
+        };
+
+        match self {
+            Format::Auto => parse(value),
+            Format::Seconds => from_epoch_seconds(epoch()?),
+            Format::Millis => from_epoch_millis(epoch()?),
+            Format::Iso if epoch().is_ok() => Err(Error::Invalid(value.to_string())),
+            Format::Iso => parse(value),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto" => Ok(Format::Auto),
+            "seconds" => Ok(Format::Seconds),
+            "millis" => Ok(Format::Millis),
+            "iso" => Ok(Format::Iso),
+            other => Err(format!("Unsupported timestamp format: {}", other)),
+        }
+    }
+}
+
+/// Parse a string of epoch milliseconds (like the `timestamp_ms` field of tweet objects).
+pub fn parse_millis(value: &str) -> Result<DateTime<Utc>, Error> {
+    Format::Millis.parse(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_timestamps() {
+        let expected = Utc.with_ymd_and_hms(2022, 1, 2, 3, 4, 5).unwrap();
+
+        assert_eq!(parse("1641092645").unwrap(), expected);
+        assert_eq!(parse("1641092645000").unwrap(), expected);
+        assert_eq!(parse_millis("1641092645000").unwrap(), expected);
+        assert_eq!(parse("2022-01-02T03:04:05Z").unwrap(), expected);
+        assert_eq!(parse("2022-01-02T04:04:05+01:00").unwrap(), expected);
+        assert_eq!(
+            parse("2022-01-02").unwrap(),
+            Utc.with_ymd_and_hms(2022, 1, 2, 0, 0, 0).unwrap()
+        );
+
+        assert_eq!(parse("-1"), Err(Error::Negative(-1)));
+        assert_eq!(
+            from_epoch_seconds(i64::MAX),
+            Err(Error::OutOfRange(i64::MAX))
+        );
+        assert_eq!(
+            from_epoch_millis(i64::MAX),
+            Err(Error::OutOfRange(i64::MAX))
+        );
+        assert_eq!(
+            parse("yesterday"),
+            Err(Error::Invalid("yesterday".to_string()))
+        );
+        assert!(parse_millis("2022-01-02").is_err());
+    }
+
+    #[test]
+    fn formats() {
+        let expected = Utc.with_ymd_and_hms(2022, 1, 2, 3, 4, 5).unwrap();
+
+        // Small millisecond values aren't mistaken for seconds
+        assert_eq!(
+            Format::Millis.parse("1000").unwrap(),
+            Utc.with_ymd_and_hms(1970, 1, 1, 0, 0, 1).unwrap()
+        );
+        assert_eq!(Format::Seconds.parse("1641092645").unwrap(), expected);
+        assert!(Format::Seconds.parse("2022-01-02T03:04:05Z").is_err());
+        assert_eq!(Format::Iso.parse("2022-01-02T03:04:05Z").unwrap(), expected);
+        assert!(Format::Iso.parse("1641092645").is_err());
+        assert_eq!("millis".parse::<Format>(), Ok(Format::Millis));
+        assert!("nanos".parse::<Format>().is_err());
+    }
+}
@@ -0,0 +1,226 @@
+//! Proofs that a snapshot existed at the time it was taken.
+//!
+//! A snapshot's SHA-256 can be timestamped by an RFC 3161 time-stamping authority, which signs it
+//! with the current time, or anchored in the Bitcoin blockchain through an
+//! [OpenTimestamps](https://opentimestamps.org/) calendar. This module builds the requests and the
+//! proof files (which standard tools verify: `openssl ts -verify` and `ots verify`), and servers
+//! send them.
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Content type of RFC 3161 requests.
+pub const RFC3161_REQUEST_CONTENT_TYPE: &str = "application/timestamp-query";
+/// Content type of RFC 3161 responses.
+pub const RFC3161_RESPONSE_CONTENT_TYPE: &str = "application/timestamp-reply";
+/// Accept header for OpenTimestamps calendar responses.
+pub const OPENTIMESTAMPS_ACCEPT: &str = "application/vnd.opentimestamps.v1";
+
+/// DER encoding of the SHA-256 `AlgorithmIdentifier` (with its `NULL` parameters).
+const SHA256_ALGORITHM: [u8; 15] = [
+    0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05, 0x00,
+];
+const OPENTIMESTAMPS_MAGIC: &[u8] =
+    b"\x00OpenTimestamps\x00\x00Proof\x00\xbf\x89\xe2\xe8\x84\xe8\x92\x94";
+const OPENTIMESTAMPS_VERSION: u8 = 1;
+const OPENTIMESTAMPS_SHA256: u8 = 0x08;
+
+/// Kinds of timestamp proofs.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Proof {
+    /// A signed RFC 3161 time-stamp response
+    Rfc3161,
+    /// An OpenTimestamps proof (pending until the calendar's next Bitcoin transaction, after
+    /// which `ots upgrade` completes it)
+    OpenTimestamps,
+}
+
+impl Proof {
+    pub const ALL: [Proof; 2] = [Proof::Rfc3161, Proof::OpenTimestamps];
+
+    /// The extension of the proof's file (the one its verification tool expects).
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Proof::Rfc3161 => "tsr",
+            Proof::OpenTimestamps => "ots",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Proof::Rfc3161 => RFC3161_RESPONSE_CONTENT_TYPE,
+            Proof::OpenTimestamps => "application/octet-stream",
+        }
+    }
+}
+
+impl Display for Proof {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Proof::Rfc3161 => "rfc3161",
+            Proof::OpenTimestamps => "opentimestamps",
+        })
+    }
+}
+
+impl FromStr for Proof {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rfc3161" => Ok(Proof::Rfc3161),
+            "opentimestamps" => Ok(Proof::OpenTimestamps),
+            other => Err(other.to_string()),
+        }
+    }
+}
+
+/// A DER-encoded RFC 3161 `TimeStampReq` for a SHA-256 digest, asking for the authority's
+/// certificate to be included so that the response can be verified on its own.
+pub fn rfc3161_request(digest: &[u8; 32], nonce: u64) -> Vec<u8> {
+    let mut message_imprint = SHA256_ALGORITHM.to_vec();
+    message_imprint.extend(der(0x04, digest));
+
+    // Integers are minimal and signed, so leading zeros are dropped unless one keeps the nonce
+    // positive
+    let nonce = nonce.to_be_bytes();
+    let leading_zeros = nonce.iter().take_while(|byte| **byte == 0).count().min(7);
+    let mut nonce_bytes = nonce[leading_zeros..].to_vec();
+
+    if nonce_bytes[0] & 0x80 != 0 {
+        nonce_bytes.insert(0, 0);
+    }
+
+    let mut request = der(0x02, &[1]);
+    request.extend(der(0x30, &message_imprint));
+    request.extend(der(0x02, &nonce_bytes));
+    request.extend(der(0x01, &[0xff]));
+
+    der(0x30, &request)
+}
+
+/// A random nonce for an RFC 3161 request, which the response must repeat.
+pub fn nonce() -> u64 {
+    rand::random()
+}
+
+/// Whether a DER-encoded RFC 3161 `TimeStampResp` grants the request (with or without
+/// modifications), which means that it contains a time-stamp token.
+pub fn rfc3161_granted(response: &[u8]) -> bool {
+    let status = read_der(response, 0x30)
+        .and_then(|(response, _)| read_der(response, 0x30))
+        .and_then(|(status_info, _)| read_der(status_info, 0x02));
+
+    matches!(status, Some((&[0] | &[1], _)))
+}
+
+/// An OpenTimestamps proof file for a SHA-256 digest, from a calendar's response to submitting the
+/// digest.
+pub fn opentimestamps_file(digest: &[u8; 32], calendar_response: &[u8]) -> Vec<u8> {
+    let mut file = OPENTIMESTAMPS_MAGIC.to_vec();
+    file.push(OPENTIMESTAMPS_VERSION);
+    file.push(OPENTIMESTAMPS_SHA256);
+    file.extend(digest);
+    file.extend(calendar_response);
+
+    file
+}
+
+/// Encode a DER value with a definite length.
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![tag];
+    let length = content.len().to_be_bytes();
+    let significant = &length[length.iter().take_while(|byte| **byte == 0).count()..];
+
+    match significant {
+        [] => encoded.push(0),
+        [byte] if *byte < 0x80 => encoded.push(*byte),
+        bytes => {
+            encoded.push(0x80 | bytes.len() as u8);
+            encoded.extend(bytes);
+        }
+    }
+
+    encoded.extend(content);
+    encoded
+}
+
+/// Read a DER value with the given tag, returning its content and the bytes after it.
+fn read_der(input: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    let (&actual_tag, input) = input.split_first()?;
+    let (&first, input) = input.split_first()?;
+
+    if actual_tag != tag {
+        return None;
+    }
+
+    let (length, input) = if first < 0x80 {
+        (first as usize, input)
+    } else {
+        let count = (first & 0x7f) as usize;
+
+        if count == 0 || count > std::mem::size_of::<usize>() || input.len() < count {
+            return None;
+        }
+
+        let (bytes, input) = input.split_at(count);
+        let length = bytes
+            .iter()
+            .fold(0usize, |length, byte| (length << 8) | *byte as usize);
+
+        (length, input)
+    };
+
+    (input.len() >= length).then(|| input.split_at(length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc3161_messages() {
+        let digest = [0xab; 32];
+        let request = rfc3161_request(&digest, 0x0102030405060708);
+
+        assert_eq!(request.len(), 69);
+        assert_eq!(&request[..5], &[0x30, 0x43, 0x02, 0x01, 0x01]);
+        assert_eq!(&request[5..7], &[0x30, 0x31]);
+        assert_eq!(&request[22..24], &[0x04, 0x20]);
+        assert_eq!(&request[24..56], &digest);
+        assert_eq!(
+            &request[56..],
+            &[0x02, 0x08, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x01, 0x01, 0xff]
+        );
+        assert_eq!(&rfc3161_request(&digest, 0x80)[56..59], &[0x02, 0x02, 0x00]);
+        assert_eq!(&rfc3161_request(&digest, 0)[56..59], &[0x02, 0x01, 0x00]);
+
+        // Status only, as for a rejection, and with a (truncated) token
+        assert!(!rfc3161_granted(&[
+            0x30, 0x05, 0x30, 0x03, 0x02, 0x01, 0x02
+        ]));
+        assert!(rfc3161_granted(&[
+            0x30, 0x07, 0x30, 0x03, 0x02, 0x01, 0x00, 0x30, 0x00
+        ]));
+        assert!(!rfc3161_granted(&[0x30, 0x05, 0x30]));
+
+        let long = der(0x04, &[0; 300]);
+        assert_eq!(&long[..4], &[0x04, 0x82, 0x01, 0x2c]);
+        assert_eq!(
+            read_der(&long, 0x04).map(|(content, _)| content.len()),
+            Some(300)
+        );
+    }
+
+    #[test]
+    fn opentimestamps_files() {
+        let file = opentimestamps_file(&[7; 32], &[0xf0, 0x10]);
+
+        assert_eq!(file.len(), OPENTIMESTAMPS_MAGIC.len() + 2 + 32 + 2);
+        assert_eq!(&file[OPENTIMESTAMPS_MAGIC.len()..][..2], &[0x01, 0x08]);
+        assert!(file.ends_with(&[7, 0xf0, 0x10]));
+
+        for proof in Proof::ALL {
+            assert_eq!(proof.to_string().parse::<Proof>(), Ok(proof));
+        }
+    }
+}
@@ -0,0 +1,312 @@
+use crate::Error;
+use chrono::{NaiveDate, Utc};
+use clap::Parser;
+use memory_lol::db::{Database, DatabaseOptions, ReadOnly};
+use serde_derive::{Deserialize, Serialize};
+use std::path::Path;
+
+/// File in the database directory where saved queries are stored.
+pub const ALERTS_FILE: &str = "alerts.json";
+
+#[derive(Debug, Parser)]
+pub enum AlertCommand {
+    /// Save a named query
+    Add {
+        /// Name of the alert (printed with each match)
+        name: String,
+        /// Match newly observed screen names against a regular expression (case-insensitive)
+        #[clap(long, conflicts_with = "handle", required_unless_present = "handle")]
+        pattern: Option<String>,
+        /// Match accounts newly observed with a watched screen name
+        #[clap(long)]
+        handle: Option<String>,
+    },
+    /// List saved queries in CSV format
+    List,
+    /// Remove a saved query
+    Remove {
+        /// Name of the alert
+        name: String,
+    },
+    /// Evaluate saved queries against recent changes, as `alert,id,screen_name,date` rows
+    ///
+    /// A pair matches if it was first observed on or after the day the alert was last run (or
+    /// created). Runs update that day unless `--since` is given, so matches first observed on
+    /// the day of the previous run may be reported again.
+    Run {
+        /// Only run this alert
+        name: Option<String>,
+        /// Report pairs first observed on or after this date instead
+        #[clap(long)]
+        since: Option<NaiveDate>,
+    },
+}
+
+/// A saved query.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum Query {
+    /// Screen names matching a regular expression
+    ScreenNamePattern { pattern: String },
+    /// Accounts observed with a screen name
+    NewHolder { screen_name: String },
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct Alert {
+    pub name: String,
+    #[serde(flatten)]
+    pub query: Query,
+    pub created: NaiveDate,
+    pub last_run: Option<NaiveDate>,
+}
+
+/// The saved queries stored in [`ALERTS_FILE`].
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct Alerts {
+    pub alerts: Vec<Alert>,
+}
+
+impl Alerts {
+    pub fn load<P: AsRef<Path>>(base: P) -> Result<Self, Error> {
+        let path = base.as_ref().join(ALERTS_FILE);
+
+        if path.exists() {
+            Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, base: P) -> Result<(), Error> {
+        Ok(std::fs::write(
+            base.as_ref().join(ALERTS_FILE),
+            serde_json::to_vec_pretty(self)?,
+        )?)
+    }
+}
+
+/// A pair matching a saved query.
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Match {
+    /// The first observation of the pair
+    pub date: NaiveDate,
+    pub id: u64,
+    pub screen_name: String,
+}
+
+impl Query {
+    /// Find the pairs matching the query that were first observed on or after a date.
+    pub fn evaluate<M: Sync + Send + 'static>(
+        &self,
+        db: &Database<M>,
+        since: NaiveDate,
+    ) -> Result<Vec<Match>, Error> {
+        let mut matches = vec![];
+
+        match self {
+            Query::ScreenNamePattern { pattern } => {
+                // Index keys are lowercased
+                let pattern = regex::RegexBuilder::new(pattern)
+                    .case_insensitive(true)
+                    .build()?;
+
+                for result in db.screen_names.with_prefix("") {
+                    let (screen_name, ids) = result?;
+
+                    if pattern.is_match(&screen_name) {
+                        new_pairs(db, &screen_name, &ids, since, &mut matches)?;
+                    }
+                }
+            }
+            Query::NewHolder { screen_name } => {
+                let ids = db.lookup_by_screen_name(screen_name)?;
+                new_pairs(db, screen_name, &ids, since, &mut matches)?;
+            }
+        }
+
+        matches.sort();
+
+        Ok(matches)
+    }
+}
+
+/// Add the accounts' pairs with the screen name (in any case) that were first observed on or
+/// after a date.
+fn new_pairs<M: Sync + Send + 'static>(
+    db: &Database<M>,
+    screen_name: &str,
+    ids: &[u64],
+    since: NaiveDate,
+    matches: &mut Vec<Match>,
+) -> Result<(), Error> {
+    for id in ids {
+        for (other, dates) in db.lookup_by_user_id(*id)? {
+            if other.eq_ignore_ascii_case(screen_name) {
+                if let Some(first) = dates.into_iter().min().filter(|first| *first >= since) {
+                    matches.push(Match {
+                        date: first,
+                        id: *id,
+                        screen_name: other,
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn run(db: &Path, options: &DatabaseOptions, command: AlertCommand) -> Result<(), Error> {
+    let mut alerts = Alerts::load(db)?;
+
+    match command {
+        AlertCommand::Add {
+            name,
+            pattern,
+            handle,
+        } => {
+            if alerts.alerts.iter().any(|alert| alert.name == name) {
+                return Err(Error::DuplicateAlert(name));
+            }
+
+            let query = match (pattern, handle) {
+                (Some(pattern), _) => {
+                    // Fail now rather than on every run
+                    regex::Regex::new(&pattern)?;
+                    Query::ScreenNamePattern { pattern }
+                }
+                (None, Some(screen_name)) => Query::NewHolder { screen_name },
+                (None, None) => unreachable!("clap requires a pattern or handle"),
+            };
+
+            alerts.alerts.push(Alert {
+                name,
+                query,
+                created: Utc::now().date_naive(),
+                last_run: None,
+            });
+            alerts.save(db)?;
+        }
+        AlertCommand::List => {
+            for alert in &alerts.alerts {
+                let (kind, value) = match &alert.query {
+                    Query::ScreenNamePattern { pattern } => ("pattern", pattern),
+                    Query::NewHolder { screen_name } => ("handle", screen_name),
+                };
+
+                println!(
+                    "{},{},{},{},{}",
+                    alert.name,
+                    kind,
+                    value,
+                    alert.created,
+                    alert
+                        .last_run
+                        .map(|date| date.to_string())
+                        .unwrap_or_default()
+                );
+            }
+        }
+        AlertCommand::Remove { name } => {
+            let count = alerts.alerts.len();
+            alerts.alerts.retain(|alert| alert.name != name);
+
+            if alerts.alerts.len() == count {
+                return Err(Error::NotFound(name));
+            }
+
+            alerts.save(db)?;
+        }
+        AlertCommand::Run { name, since } => {
+            if let Some(name) = &name {
+                if !alerts.alerts.iter().any(|alert| &alert.name == name) {
+                    return Err(Error::NotFound(name.clone()));
+                }
+            }
+
+            let database = Database::<ReadOnly>::open_with_options(db, options)?;
+            let today = Utc::now().date_naive();
+
+            for alert in alerts
+                .alerts
+                .iter_mut()
+                .filter(|alert| name.as_ref().is_none_or(|name| &alert.name == name))
+            {
+                let start = since.or(alert.last_run).unwrap_or(alert.created);
+
+                for found in alert.query.evaluate(&database, start)? {
+                    println!(
+                        "{},{},{},{}",
+                        alert.name, found.id, found.screen_name, found.date
+                    );
+                }
+
+                if since.is_none() {
+                    alert.last_run = Some(today);
+                }
+            }
+
+            if since.is_none() {
+                alerts.save(db)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memory_lol::db::Writeable;
+
+    #[test]
+    fn evaluate_queries() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::<Writeable>::open(dir.path()).unwrap();
+        let date = |day| NaiveDate::from_ymd_opt(2022, 1, day).unwrap();
+
+        db.insert(1, "Support_Crypto", vec![date(1), date(20)])
+            .unwrap();
+        db.insert(2, "crypto_support", vec![date(15)]).unwrap();
+        db.insert(3, "watched", vec![date(2)]).unwrap();
+        db.insert(4, "Watched", vec![date(12), date(10)]).unwrap();
+
+        let pattern = Query::ScreenNamePattern {
+            pattern: "support".to_string(),
+        };
+        assert_eq!(
+            pattern.evaluate(&db, date(10)).unwrap(),
+            vec![Match {
+                date: date(15),
+                id: 2,
+                screen_name: "crypto_support".to_string()
+            }]
+        );
+        assert_eq!(pattern.evaluate(&db, date(1)).unwrap().len(), 2);
+
+        let handle = Query::NewHolder {
+            screen_name: "WATCHED".to_string(),
+        };
+        assert_eq!(
+            handle.evaluate(&db, date(5)).unwrap(),
+            vec![Match {
+                date: date(10),
+                id: 4,
+                screen_name: "Watched".to_string()
+            }]
+        );
+
+        let alerts = Alerts {
+            alerts: vec![Alert {
+                name: "watched".to_string(),
+                query: handle,
+                created: date(1),
+                last_run: None,
+            }],
+        };
+        alerts.save(dir.path()).unwrap();
+        assert_eq!(Alerts::load(dir.path()).unwrap(), alerts);
+    }
+}
@@ -0,0 +1,1208 @@
+use crate::encoding::Encoding;
+use crate::events::Event;
+use crate::feed::Feed;
+use crate::http::{self, Reply, Request, Response};
+use crate::lookup;
+use crate::timestamper::Timestamper;
+use memory_lol::{
+    admin::{Runtime, SettingsUpdate},
+    cache::{COMPACT_CACHE_CONTROL, IMMUTABLE_CACHE_CONTROL},
+    cors::CorsPolicy,
+    db::{
+        namespaces::Namespace,
+        slowlog::{QueryTimer, SlowQueryLog},
+        Database, ReadOnly, SharedCache,
+    },
+    embed,
+    model::{Account, ExtendedAccount, ExtendedScreenNameResult},
+    ratelimit::{Decision, RateLimiter, API_KEY_HEADER},
+    snapshots::{Snapshot, SnapshotStore},
+    summary::Summary,
+    timeline::{Timeline, DEFAULT_MAX_GAP_DAYS},
+    timestamping::Proof,
+};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const LOOKUP_BY_PREFIX_LIMIT: usize = 100;
+const CONFUSABLE_VARIANTS_LIMIT: usize = 1024;
+const MAX_CALLBACK_LEN: usize = 128;
+const ADMIN_SETTINGS_PATH: &str = "/admin/settings";
+const EVENTS_PATH: &str = "/events";
+const EVENTS_SOCKET_PATH: &str = "/ws/events";
+const EVENTS_STREAM_PATH: &str = "/sse/events";
+const OEMBED_PATH: &str = "/oembed";
+
+/// Settings for the admin endpoint, which changes settings without a restart.
+pub struct Admin {
+    /// Bearer token that admin requests must send
+    pub token: String,
+    /// The database's block cache
+    pub cache: SharedCache,
+}
+
+/// Settings for evidence snapshots.
+pub struct Snapshots {
+    pub store: SnapshotStore,
+    /// Bearer token that requests taking snapshots must send (the admin token)
+    pub token: String,
+    /// Services that timestamp new snapshots
+    pub timestamper: Timestamper,
+}
+
+/// Serve the lookup endpoints of the public web service's API until the process is terminated.
+///
+/// Responses use the same schema as the hosted service, so existing clients and browser
+/// extensions can be pointed at a local database. All results are returned in full, as they are
+/// for trusted users of the hosted service.
+///
+/// Successful responses carry cache validators, and since the database is opened read-only, they
+/// can't have changed since the server started.
+///
+/// If there's a CORS policy, preflight requests are answered, and if it allows any origin, a
+/// `callback` query parameter wraps results as JSONP for clients that can't use CORS.
+///
+/// Results are JSON unless the `Accept` header prefers MessagePack (`application/msgpack`) or CBOR
+/// (`application/cbor`).
+///
+/// If there's a rate limiter, clients over their limit get `429 Too Many Requests` (preflight
+/// requests aren't counted).
+///
+/// Each namespace is served under `/ns/<name>/` (for example `/ns/case-123/tw/jack`). Requests
+/// for a namespace with API keys must send one of them in the API key header, or they get `401
+/// Unauthorized`.
+///
+/// If there's an event feed, imports on the same host can post their events to `/events` (by
+/// passing `--events http://localhost:<port>/events`), and WebSocket clients connecting to
+/// `/ws/events` receive them as they arrive, optionally filtered by a `pattern` for screen names
+/// or a `watchlist` tag. `/sse/events` streams the same events as server-sent events, for clients
+/// that can't use WebSockets.
+///
+/// If there's a slow query log, lookups that take longer than its threshold (including
+/// serialization) are written to it.
+///
+/// `/tw/id/<id>/embed` returns an HTML fragment of an account's screen names for embedding in
+/// articles, and `/oembed?url=<account URL>` returns it as an oEmbed document. Both link to the
+/// account at the public URL if there is one, or else at the request's `Host`.
+///
+/// If there's a snapshot store, `POST /tw/id/<id>/snapshot` saves the account's current result as
+/// an evidence snapshot and returns its permalink, `/snapshots/<snapshot id>`, which always serves
+/// exactly the saved snapshot. Snapshots of namespaced accounts can't be taken. If timestamping
+/// services are configured, new snapshots are timestamped, and their proofs are served at
+/// `/snapshots/<snapshot id>/rfc3161` and `/snapshots/<snapshot id>/opentimestamps`.
+///
+/// If admin settings are given, `/admin/settings` returns the current settings for `GET` requests
+/// and applies a JSON update for `POST` requests (see [`SettingsUpdate`]). Admin requests aren't
+/// rate limited.
+#[allow(clippy::too_many_arguments)]
+pub fn serve(
+    db: &Database<ReadOnly>,
+    namespaces: &BTreeMap<String, Namespace>,
+    address: &str,
+    public_url: Option<&str>,
+    tls: Option<&http::Tls>,
+    cors: Option<&CorsPolicy>,
+    limiter: Option<&RateLimiter>,
+    slow_queries: Option<&SlowQueryLog>,
+    admin: Option<&Admin>,
+    snapshots: Option<&Snapshots>,
+    feed: Option<&Feed>,
+) -> Result<(), http::Error> {
+    let started = chrono::Utc::now();
+    let tls = tls.map(http::Tls::server_config).transpose()?;
+
+    http::serve_upgradable(address, tls, |request| {
+        if let Some(reply) = feed.and_then(|feed| handle_feed(feed, cors, limiter, request)) {
+            return reply;
+        }
+
+        let response = if let Some(admin) =
+            admin.filter(|_| request.path_without_query() == ADMIN_SETTINGS_PATH)
+        {
+            handle_admin(admin, limiter, request)
+        } else if cors.is_some() && request.method == "OPTIONS" {
+            Response::new(204, "text/plain; charset=utf-8", vec![])
+        } else if let Some(retry_after) = limiter.and_then(|limiter| retry_after(limiter, request))
+        {
+            let mut response = Response::status(429);
+            response
+                .headers
+                .push(("Retry-After", retry_after.to_string()));
+            response
+        } else {
+            let timer = slow_queries.map(|_| QueryTimer::start());
+            let response = dispatch(db, namespaces, public_url, snapshots, request);
+
+            if let Some((slow_queries, timer)) = slow_queries.zip(timer) {
+                slow_queries.record(request.path_without_query(), timer, response.body.len());
+            }
+
+            match (cors, request.query_param("callback")) {
+                (Some(cors), Some(callback)) if cors.allows_any_origin() => {
+                    with_callback(response, &callback)
+                }
+                _ => response,
+            }
+            .cached(request, started)
+        };
+
+        Reply::Response(match cors {
+            Some(cors) => response.with_cors(request, cors),
+            None => response,
+        })
+    })
+}
+
+/// Accept events from local imports, or subscribe a WebSocket or server-sent events client to
+/// them.
+fn handle_feed(
+    feed: &Feed,
+    cors: Option<&CorsPolicy>,
+    limiter: Option<&RateLimiter>,
+    request: &Request,
+) -> Option<Reply> {
+    let path = request.path_without_query();
+
+    let response = match path {
+        EVENTS_PATH if request.method != "POST" => Response::status(405),
+        // Anyone who can post events can make them up, so they have to come from this host
+        EVENTS_PATH if !request.remote.is_some_and(|remote| remote.is_loopback()) => {
+            Response::status(403)
+        }
+        EVENTS_PATH => match serde_json::from_slice::<Vec<Event>>(&request.body) {
+            Ok(events) => {
+                feed.publish(events);
+                Response::new(204, "text/plain; charset=utf-8", vec![])
+            }
+            Err(_) => Response::status(400),
+        },
+        EVENTS_SOCKET_PATH | EVENTS_STREAM_PATH if request.method != "GET" => Response::status(405),
+        EVENTS_SOCKET_PATH | EVENTS_STREAM_PATH => {
+            if let Some(retry_after) = limiter.and_then(|limiter| retry_after(limiter, request)) {
+                let mut response = Response::status(429);
+                response
+                    .headers
+                    .push(("Retry-After", retry_after.to_string()));
+                return Some(Reply::Response(response));
+            }
+
+            let filter = match feed.filter(request) {
+                Ok(filter) => filter,
+                Err(response) => return Some(Reply::Response(response)),
+            };
+
+            if path == EVENTS_STREAM_PATH {
+                let headers = cors.map_or_else(Vec::new, |cors| {
+                    cors.response_headers(request.header("origin"), false)
+                });
+                let events = feed.subscribe();
+
+                return Some(Reply::Upgrade(Box::new(move |mut connection| {
+                    if let Err(error) =
+                        crate::feed::stream_sse(&filter, events, &headers, connection.as_mut())
+                    {
+                        log::info!("Event subscriber disconnected: {:?}", error);
+                    }
+                })));
+            }
+
+            let key = request
+                .header("sec-websocket-key")
+                .filter(|_| {
+                    request
+                        .header("upgrade")
+                        .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+                })
+                .map(str::to_string);
+
+            match key {
+                Some(key) => {
+                    let events = feed.subscribe();
+
+                    return Some(Reply::Upgrade(Box::new(move |mut connection| {
+                        if let Err(error) = crate::feed::stream_websocket(
+                            &key,
+                            &filter,
+                            events,
+                            connection.as_mut(),
+                        ) {
+                            log::info!("Event subscriber disconnected: {:?}", error);
+                        }
+                    })));
+                }
+                None => Response::status(400),
+            }
+        }
+        _ => return None,
+    };
+
+    Some(Reply::Response(response))
+}
+
+fn handle_admin(admin: &Admin, limiter: Option<&RateLimiter>, request: &Request) -> Response {
+    if !memory_lol::admin::is_authorized(&admin.token, request.header("authorization")) {
+        let mut response = Response::status(401);
+        response
+            .headers
+            .push(("WWW-Authenticate", "Bearer".to_string()));
+        return response;
+    }
+
+    let runtime = Runtime {
+        cache: &admin.cache,
+        limiter,
+        trust: None,
+    };
+
+    let settings = match request.method.as_str() {
+        "GET" => Ok(runtime.settings()),
+        "POST" => serde_json::from_slice::<SettingsUpdate>(&request.body)
+            .map_err(|error| error.to_string())
+            .and_then(|update| runtime.apply(&update).map_err(|error| error.to_string())),
+        _ => return Response::status(405),
+    };
+
+    match settings {
+        Ok(settings) => Response::json(&json!(settings)),
+        Err(message) => Response::new(400, "text/plain; charset=utf-8", message),
+    }
+}
+
+/// Count a request, returning the number of seconds to wait if the client is over its limit.
+fn retry_after(limiter: &RateLimiter, request: &Request) -> Option<u64> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+
+    match limiter.check(request.remote?, request.header(API_KEY_HEADER), now) {
+        Decision::Limited { retry_after } => Some(retry_after),
+        _ => None,
+    }
+}
+
+/// Wrap a successful JSON response in a call to a JavaScript function.
+fn with_callback(response: Response, callback: &str) -> Response {
+    if !is_valid_callback(callback) {
+        Response::status(400)
+    } else if response.status == 200 {
+        let mut body = format!("/**/{}(", callback).into_bytes();
+        body.extend_from_slice(&response.body);
+        body.extend_from_slice(b");");
+
+        Response::new(200, "application/javascript; charset=utf-8", body)
+    } else {
+        response
+    }
+}
+
+/// A JSONP callback must be a (possibly dotted) JavaScript identifier, so that it can't inject
+/// other code.
+fn is_valid_callback(callback: &str) -> bool {
+    !callback.is_empty()
+        && callback.len() <= MAX_CALLBACK_LEN
+        && callback.split('.').all(|part| {
+            part.chars()
+                .next()
+                .is_some_and(|first| !first.is_ascii_digit())
+                && part
+                    .chars()
+                    .all(|ch| ch.is_ascii_alphanumeric() || ch == '_' || ch == '$')
+        })
+}
+
+/// Route a request to the main database or to the namespace it names.
+fn dispatch(
+    db: &Database<ReadOnly>,
+    namespaces: &BTreeMap<String, Namespace>,
+    public_url: Option<&str>,
+    snapshots: Option<&Snapshots>,
+    request: &Request,
+) -> Response {
+    // The hosted service is mounted under a version prefix
+    let path = request.path_without_query();
+    let path = path.strip_prefix("/v1").unwrap_or(path);
+    let base = match public_url {
+        Some(public_url) => public_url.trim_end_matches('/').to_string(),
+        None => format!("http://{}", request.header("host").unwrap_or("localhost")),
+    };
+
+    match path.strip_prefix("/ns/") {
+        Some(rest) => {
+            let (name, path) = match rest.find('/') {
+                Some(index) => rest.split_at(index),
+                None => (rest, ""),
+            };
+
+            match namespaces.get(name) {
+                Some(namespace) if namespace.is_authorized(request.header(API_KEY_HEADER)) => {
+                    let base = format!("{}/ns/{}", base, name);
+
+                    handle(&namespace.db, path, &base, None, request)
+                }
+                Some(_) => Response::status(401),
+                None => Response::status(404),
+            }
+        }
+        None => handle(db, path, &base, snapshots, request),
+    }
+}
+
+/// Answer a request for the API at a base URL (which links to it from embeds).
+fn handle(
+    db: &Database<ReadOnly>,
+    path: &str,
+    base: &str,
+    snapshots: Option<&Snapshots>,
+    request: &Request,
+) -> Response {
+    if let Some(snapshots) = snapshots {
+        if let Some(response) = handle_snapshot(db, path, base, snapshots, request) {
+            return response;
+        }
+    }
+
+    if request.method != "GET" {
+        return Response::status(405);
+    }
+
+    if let Some(response) = handle_embed(db, path, base, request) {
+        return response;
+    }
+
+    // JSONP callbacks can only wrap JSON
+    let encoding = if request.query_param("callback").is_some() {
+        Encoding::Json
+    } else {
+        Encoding::negotiate(request.header("accept"))
+    };
+
+    if let Some(queries) = path.strip_prefix("/lookup/") {
+        return match by_queries(db, &http::percent_decode(queries)) {
+            Ok(value) => Response::encoded(&value, encoding),
+            Err(lookup::Error::InvalidId(_) | lookup::Error::EmptyQuery) => Response::status(400),
+            Err(error) => {
+                log::error!("Lookup error: {:?}", error);
+                Response::status(500)
+            }
+        };
+    }
+
+    let compact = path
+        .strip_prefix("/tw/")
+        .and_then(|query| query.strip_suffix("/compact"));
+
+    if let Some(screen_name) = compact {
+        return match compact_by_screen_name(db, &http::percent_decode(screen_name)) {
+            Ok(value) => {
+                let mut response = Response::encoded(&value, encoding);
+                response
+                    .headers
+                    .push(("Cache-Control", COMPACT_CACHE_CONTROL.to_string()));
+                response
+            }
+            Err(memory_lol::db::Error::InvalidScreenName(_)) => Response::status(400),
+            Err(error) => {
+                log::error!("Lookup error: {:?}", error);
+                Response::status(500)
+            }
+        };
+    }
+
+    let result = match path.strip_prefix("/tw/") {
+        Some(query) => match query.strip_prefix("id/") {
+            Some(rest) => match rest.split_once('/') {
+                Some((user_id, "timeline")) => user_id.parse::<u64>().ok().map(|user_id| {
+                    let max_gap_days = request
+                        .query_param("max_gap_days")
+                        .and_then(|value| value.parse().ok())
+                        .unwrap_or(DEFAULT_MAX_GAP_DAYS);
+
+                    timeline_by_user_id(db, user_id, max_gap_days)
+                }),
+                Some((user_id, "summary")) => user_id
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|user_id| summary_by_user_id(db, user_id).transpose()),
+                Some(_) => None,
+                None => rest
+                    .parse::<u64>()
+                    .ok()
+                    .map(|user_id| by_user_id(db, user_id)),
+            },
+            None if !query.is_empty() => Some(by_screen_name(db, &http::percent_decode(query))),
+            None => None,
+        },
+        None => None,
+    };
+
+    match result {
+        Some(Ok(value)) => Response::encoded(&value, encoding),
+        Some(Err(
+            memory_lol::db::Error::InvalidScreenName(_) | memory_lol::db::Error::TooManyVariants(_),
+        )) => Response::status(400),
+        Some(Err(error)) => {
+            log::error!("Lookup error: {:?}", error);
+            Response::status(500)
+        }
+        None => Response::status(404),
+    }
+}
+
+/// Take a snapshot of an account or serve one, or return `None` if the request is for neither.
+fn handle_snapshot(
+    db: &Database<ReadOnly>,
+    path: &str,
+    base: &str,
+    snapshots: &Snapshots,
+    request: &Request,
+) -> Option<Response> {
+    if let Some(user_id) = path
+        .strip_prefix("/tw/id/")
+        .and_then(|rest| rest.strip_suffix("/snapshot"))
+    {
+        let Ok(user_id) = user_id.parse::<u64>() else {
+            return Some(Response::status(404));
+        };
+
+        if request.method != "POST" {
+            return Some(Response::status(405));
+        }
+
+        // Taking a snapshot writes a file and contacts the timestamping services, so it's not
+        // open to anonymous clients
+        if !memory_lol::admin::is_authorized(&snapshots.token, request.header("authorization")) {
+            let mut response = Response::status(401);
+            response
+                .headers
+                .push(("WWW-Authenticate", "Bearer".to_string()));
+            return Some(response);
+        }
+
+        let saved = by_user_id(db, user_id).and_then(|result| {
+            let snapshot = Snapshot::new(user_id, result, chrono::Utc::now())?;
+            let id = snapshots.store.save(&snapshot)?;
+
+            Ok((id, snapshot))
+        });
+
+        return Some(match saved {
+            Ok((id, snapshot)) => {
+                let url = format!("{}/snapshots/{}", base, id);
+                let timestamps = snapshots
+                    .timestamper
+                    .stamp(&snapshots.store, &id)
+                    .into_iter()
+                    .map(|proof| (proof.to_string(), json!(format!("{}/{}", url, proof))))
+                    .collect::<Map<_, _>>();
+                let mut response = Response::json(&json!({
+                    "id": id,
+                    "url": url,
+                    "sha256": snapshot.sha256,
+                    "taken": snapshot.taken,
+                    "timestamps": timestamps,
+                }));
+                response.status = 201;
+                response.headers.push(("Location", url));
+                response
+            }
+            Err(memory_lol::db::Error::SnapshotLimit(limit)) => {
+                log::warn!(
+                    "Refused snapshot: the store already holds {} snapshots",
+                    limit
+                );
+                Response::status(507)
+            }
+            Err(error) => {
+                log::error!("Snapshot error: {:?}", error);
+                Response::status(500)
+            }
+        });
+    }
+
+    let id = path.strip_prefix("/snapshots/")?;
+
+    if request.method != "GET" {
+        return Some(Response::status(405));
+    }
+
+    if let Some((id, proof)) = id.split_once('/') {
+        let Ok(proof) = proof.parse::<Proof>() else {
+            return Some(Response::status(404));
+        };
+
+        return Some(match snapshots.store.load_proof(id, proof) {
+            Ok(Some(contents)) => Response::new(200, proof.content_type(), contents),
+            Ok(None) => Response::status(404),
+            Err(memory_lol::db::Error::SnapshotLimit(limit)) => {
+                log::warn!(
+                    "Refused snapshot: the store already holds {} snapshots",
+                    limit
+                );
+                Response::status(507)
+            }
+            Err(error) => {
+                log::error!("Snapshot error: {:?}", error);
+                Response::status(500)
+            }
+        });
+    }
+
+    Some(match snapshots.store.load(id) {
+        Ok(Some(contents)) => {
+            let mut response = Response::new(200, "application/json", contents);
+            response
+                .headers
+                .push(("Cache-Control", IMMUTABLE_CACHE_CONTROL.to_string()));
+            response
+        }
+        Ok(None) => Response::status(404),
+        Err(error) => {
+            log::error!("Snapshot error: {:?}", error);
+            Response::status(500)
+        }
+    })
+}
+
+/// Answer a request for an account's embeddable HTML fragment or oEmbed document, or return
+/// `None` if it's not one.
+fn handle_embed(
+    db: &Database<ReadOnly>,
+    path: &str,
+    base: &str,
+    request: &Request,
+) -> Option<Response> {
+    let user_id = if path == OEMBED_PATH {
+        // JSON is the only format we provide
+        if request
+            .query_param("format")
+            .is_some_and(|format| format != "json")
+        {
+            return Some(Response::status(501));
+        }
+
+        match request
+            .query_param("url")
+            .and_then(|url| embed::user_id_from_url(&url))
+        {
+            Some(user_id) => user_id,
+            None => return Some(Response::status(404)),
+        }
+    } else {
+        path.strip_prefix("/tw/id/")?
+            .strip_suffix("/embed")?
+            .parse::<u64>()
+            .ok()?
+    };
+
+    let summary = match db.lookup_ranges_by_user_id(user_id) {
+        Ok(result) => Summary::from_ranges(&result),
+        Err(error) => {
+            log::error!("Lookup error: {:?}", error);
+            return Some(Response::status(500));
+        }
+    };
+    let Some(summary) = summary else {
+        return Some(Response::status(404));
+    };
+    let link = format!("{}/tw/id/{}", base, user_id);
+
+    Some(if path == OEMBED_PATH {
+        let max_width = request
+            .query_param("maxwidth")
+            .and_then(|value| value.parse().ok());
+
+        Response::json(&embed::oembed(user_id, &summary, &link, base, max_width))
+    } else {
+        Response::new(
+            200,
+            "text/html; charset=utf-8",
+            embed::html(user_id, &summary, &link),
+        )
+    })
+}
+
+fn lookup_ids(
+    db: &Database<ReadOnly>,
+    user_ids: &[u64],
+) -> Result<Vec<ExtendedAccount>, memory_lol::db::Error> {
+    let mut accounts = Vec::with_capacity(user_ids.len());
+
+    for user_id in user_ids {
+        let result = db.lookup_by_user_id(*user_id)?;
+
+        if !result.is_empty() {
+            accounts.push(Account::from_raw_result(*user_id, result).into());
+        }
+    }
+
+    Ok(accounts)
+}
+
+fn by_user_id(db: &Database<ReadOnly>, user_id: u64) -> Result<Value, memory_lol::db::Error> {
+    let account: ExtendedAccount =
+        Account::from_raw_result(user_id, db.lookup_by_user_id(user_id)?).into();
+
+    Ok(json!(account))
+}
+
+fn timeline_by_user_id(
+    db: &Database<ReadOnly>,
+    user_id: u64,
+    max_gap_days: i64,
+) -> Result<Value, memory_lol::db::Error> {
+    let result = db.lookup_by_user_id(user_id)?;
+
+    Ok(memory_lol::timeline::vega_lite_spec(
+        user_id,
+        &Timeline::new(&result, max_gap_days),
+    ))
+}
+
+fn summary_by_user_id(
+    db: &Database<ReadOnly>,
+    user_id: u64,
+) -> Result<Option<Value>, memory_lol::db::Error> {
+    let result = db.lookup_ranges_by_user_id(user_id)?;
+
+    Ok(Summary::from_ranges(&result).map(|summary| summary.to_json(user_id)))
+}
+
+/// The accounts that have used a screen name, with only their names and first and last dates
+/// (see [`Summary::to_compact_json`]).
+fn compact_by_screen_name(
+    db: &Database<ReadOnly>,
+    screen_name: &str,
+) -> Result<Value, memory_lol::db::Error> {
+    let mut accounts = vec![];
+
+    for user_id in db.lookup_by_screen_name(screen_name)? {
+        let summary = Summary::from_ranges(&db.lookup_ranges_by_user_id(user_id)?);
+
+        if let Some(summary) = summary.filter(|summary| summary.includes_screen_name(screen_name)) {
+            accounts.push(summary.to_compact_json(user_id));
+        }
+    }
+
+    Ok(json!({ "accounts": accounts }))
+}
+
+/// Look up a query the way the web service does: a comma-separated list of screen names, a
+/// `~handle` impersonation search, a `prefix*` search, or a single screen name (even if it's
+/// numeric).
+fn by_screen_name(db: &Database<ReadOnly>, query: &str) -> Result<Value, memory_lol::db::Error> {
+    let pairs = if query.contains(',') {
+        query
+            .split(',')
+            .filter(|screen_name| !screen_name.is_empty())
+            .map(|screen_name| {
+                Ok((
+                    screen_name.to_string(),
+                    db.lookup_by_screen_name(screen_name)?,
+                ))
+            })
+            .collect::<Result<Vec<_>, memory_lol::db::Error>>()?
+    } else if let Some(handle) = query.strip_prefix('~') {
+        db.lookup_confusable(handle, CONFUSABLE_VARIANTS_LIMIT)?
+    } else if let Some(prefix) = query.strip_suffix('*') {
+        db.lookup_by_screen_name_prefix(prefix, LOOKUP_BY_PREFIX_LIMIT)?
+    } else {
+        let accounts = lookup_ids(db, &db.lookup_by_screen_name(query)?)?;
+        let result = ExtendedScreenNameResult { accounts };
+
+        let result = if result.includes_screen_name(query) {
+            result
+        } else {
+            ExtendedScreenNameResult::default()
+        };
+
+        return Ok(json!(result));
+    };
+
+    let mut map = Map::new();
+
+    for (screen_name, user_ids) in pairs {
+        let result = ExtendedScreenNameResult {
+            accounts: lookup_ids(db, &user_ids)?,
+        };
+
+        if result.includes_screen_name(&screen_name) {
+            map.insert(screen_name, json!(result));
+        }
+    }
+
+    Ok(Value::Object(map))
+}
+
+/// Look up a list of user IDs and screen names (see [`lookup::split_queries`]), keyed by input
+/// token.
+///
+/// Unlike `/tw/`, all-digit tokens are user IDs (unless prefixed with `@`), and tokens without
+/// results are included with no accounts.
+fn by_queries(db: &Database<ReadOnly>, queries: &str) -> Result<Value, lookup::Error> {
+    let mut map = Map::new();
+
+    for result in lookup::resolve_all(db, queries)? {
+        let accounts = result.accounts.into_iter().map(Into::into).collect();
+
+        map.insert(result.query, json!(ExtendedScreenNameResult { accounts }));
+    }
+
+    Ok(Value::Object(map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use memory_lol::db::Writeable;
+
+    fn get(db: &Database<ReadOnly>, path: &str) -> (u16, Value) {
+        get_with_key(db, &BTreeMap::new(), path, None)
+    }
+
+    fn get_with_key(
+        db: &Database<ReadOnly>,
+        namespaces: &BTreeMap<String, Namespace>,
+        path: &str,
+        api_key: Option<&str>,
+    ) -> (u16, Value) {
+        let response = dispatch(
+            db,
+            namespaces,
+            None,
+            None,
+            &Request {
+                method: "GET".to_string(),
+                path: path.to_string(),
+                headers: api_key
+                    .map(|key| (API_KEY_HEADER.to_string(), key.to_string()))
+                    .into_iter()
+                    .collect(),
+                body: vec![],
+                remote: None,
+            },
+        );
+
+        (
+            response.status,
+            serde_json::from_slice(&response.body).unwrap_or_default(),
+        )
+    }
+
+    #[test]
+    fn public_api_schema() {
+        let dir = tempfile::tempdir().unwrap();
+
+        {
+            let db = Database::<Writeable>::open(dir.path()).unwrap();
+            let date = |day| NaiveDate::from_ymd_opt(2022, 1, day).unwrap();
+
+            db.insert(1, "foo", vec![date(1), date(2)]).unwrap();
+            db.insert(1, "bar", vec![date(3)]).unwrap();
+            db.insert(2, "food", vec![]).unwrap();
+            db.insert(3, "123", vec![date(4)]).unwrap();
+        }
+
+        let db = Database::<ReadOnly>::open(dir.path()).unwrap();
+        let foo = json!({
+            "id": 1,
+            "id_str": "1",
+            "screen_names": { "bar": ["2022-01-03"], "foo": ["2022-01-01", "2022-01-02"] },
+            "coverage": {
+                "first_seen": "2022-01-01",
+                "last_seen": "2022-01-03",
+                "observations": 3,
+                "days": 3
+            }
+        });
+
+        assert_eq!(get(&db, "/v1/tw/FOO"), (200, json!({ "accounts": [foo] })));
+        assert_eq!(get(&db, "/tw/id/1"), (200, foo.clone()));
+        assert_eq!(
+            get(&db, "/tw/id/9"),
+            (
+                200,
+                json!({
+                    "id": 9,
+                    "id_str": "9",
+                    "screen_names": {},
+                    "coverage": {
+                        "first_seen": null,
+                        "last_seen": null,
+                        "observations": 0,
+                        "days": 0
+                    }
+                })
+            )
+        );
+        assert_eq!(get(&db, "/tw/missing"), (200, json!({ "accounts": [] })));
+        // Numeric queries are screen names, not IDs
+        assert_eq!(get(&db, "/tw/123").1["accounts"][0]["id"], 3);
+        assert_eq!(
+            get(&db, "/tw/foo,missing").1,
+            json!({ "foo": { "accounts": [foo] } })
+        );
+        assert_eq!(
+            get(&db, "/tw/fo*")
+                .1
+                .as_object()
+                .unwrap()
+                .keys()
+                .collect::<Vec<_>>(),
+            vec!["foo", "food"]
+        );
+        assert_eq!(get(&db, "/tw/id/1/timeline?max_gap_days=1").0, 200);
+        assert_eq!(
+            get(&db, "/tw/id/1/summary").1["text"],
+            "@bar (a.k.a. @foo 2022)"
+        );
+        assert_eq!(get(&db, "/tw/id/9/summary").0, 404);
+        assert_eq!(
+            get(&db, "/tw/FOO/compact"),
+            (
+                200,
+                json!({ "accounts": [{
+                    "id": "1",
+                    "current": "bar",
+                    "previous": ["foo"],
+                    "first_seen": "2022-01-01",
+                    "last_seen": "2022-01-03"
+                }] })
+            )
+        );
+        assert_eq!(
+            get(&db, "/tw/food/compact"),
+            (200, json!({ "accounts": [] }))
+        );
+        assert_eq!(get(&db, "/tw/id/foo").0, 404);
+        assert_eq!(get(&db, "/tw/~foo%20bar").0, 400);
+
+        assert_eq!(
+            get(&db, "/v1/lookup/foo,%20123,@123,missing"),
+            (
+                200,
+                json!({
+                    "foo": { "accounts": [foo] },
+                    "123": { "accounts": [] },
+                    "@123": { "accounts": [{
+                        "id": 3,
+                        "id_str": "3",
+                        "screen_names": { "123": ["2022-01-04"] },
+                        "coverage": {
+                            "first_seen": "2022-01-04",
+                            "last_seen": "2022-01-04",
+                            "observations": 1,
+                            "days": 1
+                        }
+                    }] },
+                    "missing": { "accounts": [] }
+                })
+            )
+        );
+        assert_eq!(get(&db, "/lookup/id:foo").0, 400);
+    }
+
+    #[test]
+    fn embeds() {
+        let dir = tempfile::tempdir().unwrap();
+        let date = |day| NaiveDate::from_ymd_opt(2022, 1, day).unwrap();
+
+        {
+            let db = Database::<Writeable>::open(dir.path()).unwrap();
+            db.insert(1, "foo", vec![date(1), date(2)]).unwrap();
+            db.insert(1, "bar", vec![date(3)]).unwrap();
+        }
+
+        let db = Database::<ReadOnly>::open(dir.path()).unwrap();
+        let get = |path: &str, public_url: Option<&str>| {
+            dispatch(
+                &db,
+                &BTreeMap::new(),
+                public_url,
+                None,
+                &Request {
+                    method: "GET".to_string(),
+                    path: path.to_string(),
+                    headers: vec![("Host".to_string(), "localhost:8080".to_string())],
+                    body: vec![],
+                    remote: None,
+                },
+            )
+        };
+
+        let response = get("/tw/id/1/embed", None);
+        assert_eq!(response.content_type, "text/html; charset=utf-8");
+        assert!(String::from_utf8(response.body).unwrap().ends_with(
+            "<a href=\"http://localhost:8080/tw/id/1\">memory.lol</a></p></blockquote>"
+        ));
+        assert_eq!(get("/tw/id/2/embed", None).status, 404);
+
+        let response = get(
+            "/v1/oembed?url=https%3A%2F%2Fapi.memory.lol%2Fv1%2Ftw%2Fid%2F1&maxwidth=300",
+            Some("https://api.memory.lol/v1/"),
+        );
+        let document: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(document["provider_url"], "https://api.memory.lol/v1");
+        assert_eq!(document["title"], "@bar (a.k.a. @foo 2022)");
+        assert_eq!(document["width"], 300);
+        assert!(document["html"]
+            .as_str()
+            .unwrap()
+            .contains("href=\"https://api.memory.lol/v1/tw/id/1\""));
+
+        assert_eq!(
+            get("/oembed?url=https://x/tw/id/1&format=xml", None).status,
+            501
+        );
+        assert_eq!(get("/oembed?url=https://x/tw/foo", None).status, 404);
+        assert_eq!(get("/oembed", None).status, 404);
+    }
+
+    #[test]
+    fn snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let date = NaiveDate::from_ymd_opt(2022, 1, 1).unwrap();
+        let snapshots = Snapshots {
+            store: SnapshotStore::new(dir.path().join("snapshots")),
+            token: "secret".to_string(),
+            timestamper: Timestamper::new(None, None).unwrap(),
+        };
+        let request = |method: &str, path: &str| Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            headers: vec![("authorization".to_string(), "Bearer secret".to_string())],
+            body: vec![],
+            remote: None,
+        };
+
+        Database::<Writeable>::open(dir.path().join("db"))
+            .unwrap()
+            .insert(1, "foo", vec![date])
+            .unwrap();
+
+        let db = Database::<ReadOnly>::open(dir.path().join("db")).unwrap();
+        let anonymous = Request {
+            headers: vec![],
+            ..request("POST", "/tw/id/1/snapshot")
+        };
+        assert_eq!(
+            dispatch(&db, &BTreeMap::new(), None, Some(&snapshots), &anonymous).status,
+            401
+        );
+
+        let created = dispatch(
+            &db,
+            &BTreeMap::new(),
+            Some("https://example.com/v1"),
+            Some(&snapshots),
+            &request("POST", "/v1/tw/id/1/snapshot"),
+        );
+        assert_eq!(created.status, 201);
+
+        let created: Value = serde_json::from_slice(&created.body).unwrap();
+        let url = created["url"].as_str().unwrap();
+        let path = url.strip_prefix("https://example.com").unwrap();
+        drop(db);
+
+        // Later observations don't change the snapshot
+        Database::<Writeable>::open(dir.path().join("db"))
+            .unwrap()
+            .insert(1, "bar", vec![date])
+            .unwrap();
+
+        let db = Database::<ReadOnly>::open(dir.path().join("db")).unwrap();
+        let get = |method: &str, path: &str| {
+            dispatch(
+                &db,
+                &BTreeMap::new(),
+                None,
+                Some(&snapshots),
+                &request(method, path),
+            )
+        };
+
+        let response = get("GET", path);
+        assert_eq!(response.status, 200);
+        assert!(response
+            .headers
+            .contains(&("Cache-Control", IMMUTABLE_CACHE_CONTROL.to_string())));
+
+        let snapshot: Snapshot = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(snapshot.sha256, created["sha256"]);
+        assert_eq!(
+            snapshot.result["screen_names"],
+            json!({ "foo": ["2022-01-01"] })
+        );
+
+        assert_eq!(created["timestamps"], json!({}));
+        assert_eq!(get("GET", &format!("{}/rfc3161", path)).status, 404);
+
+        let id = created["id"].as_str().unwrap();
+        snapshots
+            .store
+            .save_proof(id, Proof::Rfc3161, b"response")
+            .unwrap();
+        let response = get("GET", &format!("{}/rfc3161", path));
+        assert_eq!(response.content_type, "application/timestamp-reply");
+        assert_eq!(response.body, b"response");
+        assert_eq!(get("GET", &format!("{}/other", path)).status, 404);
+
+        // Proofs the snapshot already has aren't requested again (nothing listens on this port)
+        let timestamper = Timestamper::new(Some("http://127.0.0.1:9".to_string()), None).unwrap();
+        assert_eq!(
+            timestamper.stamp(&snapshots.store, id),
+            vec![Proof::Rfc3161]
+        );
+
+        assert_eq!(get("GET", "/tw/id/1/snapshot").status, 405);
+        assert_eq!(get("POST", path).status, 405);
+        assert_eq!(get("GET", "/snapshots/missing").status, 404);
+    }
+
+    #[test]
+    fn namespaces() {
+        let dir = tempfile::tempdir().unwrap();
+        let date = NaiveDate::from_ymd_opt(2022, 1, 1).unwrap();
+
+        Database::<Writeable>::open(dir.path())
+            .unwrap()
+            .insert(1, "foo", vec![date])
+            .unwrap();
+
+        for name in ["public", "private"] {
+            let path = memory_lol::db::namespaces::path(dir.path(), name).unwrap();
+            Database::<Writeable>::open(&path)
+                .unwrap()
+                .insert(2, "foo", vec![date])
+                .unwrap();
+        }
+        memory_lol::db::namespaces::set_api_keys(
+            memory_lol::db::namespaces::path(dir.path(), "private").unwrap(),
+            &["secret".to_string()],
+        )
+        .unwrap();
+
+        let db = Database::<ReadOnly>::open(dir.path()).unwrap();
+        let namespaces =
+            memory_lol::db::namespaces::open_all(dir.path(), &Default::default()).unwrap();
+        let get = |path, api_key| get_with_key(&db, &namespaces, path, api_key);
+
+        assert_eq!(get("/tw/foo", None).1["accounts"][0]["id"], 1);
+        assert_eq!(get("/v1/ns/public/tw/foo", None).1["accounts"][0]["id"], 2);
+        assert_eq!(get("/ns/private/tw/foo", None).0, 401);
+        assert_eq!(get("/ns/private/tw/foo", Some("guess")).0, 401);
+        assert_eq!(
+            get("/ns/private/tw/foo", Some("secret")).1["accounts"][0]["id"],
+            2
+        );
+        assert_eq!(get("/ns/missing/tw/foo", None).0, 404);
+        assert_eq!(get("/ns/public", None).0, 404);
+    }
+
+    #[test]
+    fn content_negotiation() {
+        let dir = tempfile::tempdir().unwrap();
+        let date = NaiveDate::from_ymd_opt(2022, 1, 1).unwrap();
+
+        Database::<Writeable>::open(dir.path())
+            .unwrap()
+            .insert(1, "foo", vec![date])
+            .unwrap();
+
+        let db = Database::<ReadOnly>::open(dir.path()).unwrap();
+        let get = |path: &str, accept: &str| {
+            dispatch(
+                &db,
+                &BTreeMap::new(),
+                None,
+                None,
+                &Request {
+                    method: "GET".to_string(),
+                    path: path.to_string(),
+                    headers: vec![("accept".to_string(), accept.to_string())],
+                    body: vec![],
+                    remote: None,
+                },
+            )
+        };
+
+        let json = get("/tw/id/1", "*/*");
+        assert_eq!(json.content_type, "application/json");
+        let value = serde_json::from_slice::<Value>(&json.body).unwrap();
+
+        for encoding in [Encoding::Msgpack, Encoding::Cbor] {
+            let response = get("/tw/id/1", encoding.content_type());
+            assert_eq!(response.content_type, encoding.content_type());
+            assert_eq!(response.body, encoding.encode(&value));
+            assert!(response.headers.contains(&("Vary", "Accept".to_string())));
+        }
+
+        // JSONP always wraps JSON
+        let response = get("/tw/id/1?callback=f", "application/cbor");
+        assert_eq!(response.content_type, "application/json");
+    }
+
+    #[test]
+    fn jsonp_callbacks() {
+        let response = with_callback(Response::json(&json!({ "a": 1 })), "app.handle_$1");
+        assert_eq!(response.body, b"/**/app.handle_$1({\"a\":1});");
+        assert_eq!(
+            response.content_type,
+            "application/javascript; charset=utf-8"
+        );
+
+        assert_eq!(with_callback(Response::status(404), "f").status, 404);
+        assert_eq!(
+            with_callback(Response::json(&json!({})), "alert(1)").status,
+            400
+        );
+        assert_eq!(
+            with_callback(Response::json(&json!({})), "a..b").status,
+            400
+        );
+        assert_eq!(with_callback(Response::json(&json!({})), "1a").status, 400);
+    }
+
+    #[test]
+    fn admin_settings() {
+        let admin = Admin {
+            token: "secret".to_string(),
+            cache: SharedCache::default(),
+        };
+        let limiter = RateLimiter::new(memory_lol::ratelimit::RateLimitConfig {
+            requests: 10,
+            window_secs: 60,
+            allowed_networks: vec![],
+            api_keys: vec![],
+            state: None,
+        })
+        .unwrap();
+        let request = |method: &str, token: &str, body: &str| Request {
+            method: method.to_string(),
+            path: ADMIN_SETTINGS_PATH.to_string(),
+            headers: vec![("authorization".to_string(), format!("Bearer {}", token))],
+            body: body.as_bytes().to_vec(),
+            remote: None,
+        };
+
+        let response = handle_admin(&admin, Some(&limiter), &request("GET", "guess", ""));
+        assert_eq!(response.status, 401);
+
+        let body = r#"{"rate_limit":{"requests":20,"window_secs":10}}"#;
+        let response = handle_admin(&admin, Some(&limiter), &request("POST", "secret", body));
+        assert_eq!(response.status, 200);
+        assert_eq!(limiter.limits(), (20, 10));
+
+        let body = r#"{"block_cache_size":1024}"#;
+        let response = handle_admin(&admin, Some(&limiter), &request("POST", "secret", body));
+        assert_eq!(response.status, 400);
+
+        let response = handle_admin(&admin, Some(&limiter), &request("GET", "secret", ""));
+        let settings: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(
+            settings["rate_limit"],
+            json!({ "requests": 20, "window_secs": 10 })
+        );
+    }
+}
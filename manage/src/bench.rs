@@ -0,0 +1,345 @@
+use crate::import::serialize_duration;
+use crate::lookup::{self, Query};
+use memory_lol::db::{Database, ReadOnly};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde_derive::Serialize;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::io::BufRead;
+use std::time::{Duration, Instant};
+
+/// Number of random seeks to attempt for each key requested before giving up on a small table.
+const SEEKS_PER_KEY: usize = 10;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("Lookup error")]
+    Lookup(#[from] lookup::Error),
+    #[error("Database error")]
+    Db(#[from] memory_lol::db::Error),
+    #[error("JSON error")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid Zipf exponent")]
+    InvalidExponent(f64),
+    #[error("No keys to query")]
+    EmptyWorkload,
+}
+
+/// How synthetic queries are distributed over the sampled keys.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, clap::ValueEnum)]
+pub enum ReadPattern {
+    /// Every sampled key is equally likely
+    Uniform,
+    /// A few hot keys receive most queries
+    Zipf,
+    /// Sampled keys are queried in sorted order, repeating as needed
+    Sequential,
+}
+
+/// Which kind of lookup synthetic queries perform.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, clap::ValueEnum)]
+pub enum Target {
+    Id,
+    ScreenName,
+}
+
+/// Parameters for generating a synthetic workload.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Workload {
+    pub queries: usize,
+    pub pattern: ReadPattern,
+    pub target: Target,
+    /// Number of distinct keys to sample from the account table
+    pub keys: usize,
+    pub zipf_exponent: f64,
+    pub seed: u64,
+}
+
+impl Workload {
+    /// Sample keys from the database and generate the queries to run.
+    ///
+    /// Keys are sampled by seeking to random IDs between the smallest and largest in the account
+    /// table, so that the sample covers the whole keyspace (in proportion to the ID range, not to
+    /// the number of accounts).
+    pub fn generate(&self, db: &Database<ReadOnly>) -> Result<Vec<Query>, Error> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut keys = self.sample(db, &mut rng)?;
+
+        if keys.is_empty() {
+            return Err(Error::EmptyWorkload);
+        }
+
+        let queries = match self.pattern {
+            ReadPattern::Uniform => (0..self.queries)
+                .map(|_| keys[rng.gen_range(0..keys.len())].clone())
+                .collect(),
+            ReadPattern::Zipf => {
+                if !(self.zipf_exponent.is_finite() && self.zipf_exponent > 0.0) {
+                    return Err(Error::InvalidExponent(self.zipf_exponent));
+                }
+
+                // Shuffle first so that the hottest keys aren't simply the lowest IDs
+                keys.shuffle(&mut rng);
+
+                let weights = (1..=keys.len())
+                    .map(|rank| 1.0 / (rank as f64).powf(self.zipf_exponent))
+                    .collect::<Vec<_>>();
+                let distribution = WeightedIndex::new(&weights)
+                    .map_err(|_| Error::InvalidExponent(self.zipf_exponent))?;
+
+                (0..self.queries)
+                    .map(|_| keys[distribution.sample(&mut rng)].clone())
+                    .collect()
+            }
+            ReadPattern::Sequential => {
+                keys.sort_by(|a, b| query_sort_key(a).cmp(&query_sort_key(b)));
+                keys.iter().cycle().take(self.queries).cloned().collect()
+            }
+        };
+
+        Ok(queries)
+    }
+
+    fn sample(&self, db: &Database<ReadOnly>, rng: &mut StdRng) -> Result<Vec<Query>, Error> {
+        let first_id = match db.accounts.pairs().next().transpose()? {
+            Some((id, _, _)) => id,
+            None => return Ok(vec![]),
+        };
+        let last_id = db.accounts.last_id()?.unwrap_or(first_id);
+
+        let mut keys = vec![];
+        let mut seen = HashSet::new();
+
+        for _ in 0..self.keys.saturating_mul(SEEKS_PER_KEY) {
+            if keys.len() >= self.keys {
+                break;
+            }
+
+            let seek_id = rng.gen_range(first_id..=last_id);
+
+            if let Some((id, screen_name, _)) =
+                db.accounts.pairs_from(seek_id).next().transpose()?
+            {
+                let key = match self.target {
+                    Target::Id => Query::Id(id),
+                    Target::ScreenName => Query::ScreenName(screen_name.to_lowercase()),
+                };
+
+                if seen.insert(key.clone()) {
+                    keys.push(key);
+                }
+            }
+        }
+
+        Ok(keys)
+    }
+}
+
+fn query_sort_key(query: &Query) -> (u64, &str) {
+    match query {
+        Query::Id(id) => (*id, ""),
+        Query::ScreenName(screen_name) => (0, screen_name),
+    }
+}
+
+/// Read the lookups to replay from a web server request log.
+///
+/// Lines that don't contain a lookup path (`/tw/id/<user ID>` or `/tw/<screen names>`) are skipped,
+/// as are route patterns (which contain `<`), and a request for several comma-separated screen
+/// names is replayed as one lookup for each.
+pub fn read_request_log<R: BufRead>(input: R) -> Result<Vec<Query>, Error> {
+    let mut queries = vec![];
+
+    for line in input.lines() {
+        let line = line?;
+
+        if let Some(path) = line
+            .split_whitespace()
+            .filter(|token| !token.contains('<'))
+            .find_map(|token| token.strip_prefix("/tw/"))
+        {
+            // Drop any query string and trailing log punctuation
+            let path = path
+                .split(['?', '#'])
+                .next()
+                .unwrap_or_default()
+                .trim_end_matches(':');
+
+            match path.strip_prefix("id/") {
+                Some(id) => queries.push(Query::parse_id(id)?),
+                None => queries.extend(
+                    path.split(',')
+                        .filter(|screen_name| !screen_name.is_empty())
+                        .map(|screen_name| Query::ScreenName(screen_name.to_string())),
+                ),
+            }
+        }
+    }
+
+    if queries.is_empty() {
+        Err(Error::EmptyWorkload)
+    } else {
+        Ok(queries)
+    }
+}
+
+/// Latency and throughput for a completed benchmark run.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct BenchReport {
+    pub queries: usize,
+    pub hits: usize,
+    #[serde(serialize_with = "serialize_duration")]
+    pub elapsed: Duration,
+    #[serde(serialize_with = "serialize_duration")]
+    pub p50: Duration,
+    #[serde(serialize_with = "serialize_duration")]
+    pub p90: Duration,
+    #[serde(serialize_with = "serialize_duration")]
+    pub p99: Duration,
+    #[serde(serialize_with = "serialize_duration")]
+    pub p999: Duration,
+    #[serde(serialize_with = "serialize_duration")]
+    pub max: Duration,
+}
+
+impl BenchReport {
+    pub fn to_json(&self) -> Result<Value, Error> {
+        let mut value = serde_json::to_value(self)?;
+
+        if let Some(fields) = value.as_object_mut() {
+            fields.insert("throughput".to_string(), self.throughput().into());
+        }
+
+        Ok(value)
+    }
+
+    /// Queries resolved per second.
+    pub fn throughput(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+
+        if seconds > 0.0 {
+            self.queries as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+impl std::fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Queries: {}", self.queries)?;
+        writeln!(f, "Hits: {}", self.hits)?;
+        writeln!(f, "Elapsed: {:.3}s", self.elapsed.as_secs_f64())?;
+        writeln!(f, "Throughput: {:.1} queries/s", self.throughput())?;
+        writeln!(f, "p50: {}µs", self.p50.as_micros())?;
+        writeln!(f, "p90: {}µs", self.p90.as_micros())?;
+        writeln!(f, "p99: {}µs", self.p99.as_micros())?;
+        writeln!(f, "p99.9: {}µs", self.p999.as_micros())?;
+        write!(f, "Max: {}µs", self.max.as_micros())
+    }
+}
+
+/// Resolve every query in order, timing each one.
+pub fn run(db: &Database<ReadOnly>, queries: &[Query]) -> Result<BenchReport, Error> {
+    let mut latencies = Vec::with_capacity(queries.len());
+    let mut hits = 0;
+    let started = Instant::now();
+
+    for query in queries {
+        let query_started = Instant::now();
+        let result = lookup::resolve(db, query)?;
+        latencies.push(query_started.elapsed());
+
+        if !result.accounts.is_empty() {
+            hits += 1;
+        }
+    }
+
+    let elapsed = started.elapsed();
+    latencies.sort_unstable();
+
+    let percentile = |p: f64| {
+        let index = ((latencies.len() as f64 * p).ceil() as usize).saturating_sub(1);
+        latencies.get(index).copied().unwrap_or_default()
+    };
+
+    Ok(BenchReport {
+        queries: queries.len(),
+        hits,
+        elapsed,
+        p50: percentile(0.5),
+        p90: percentile(0.9),
+        p99: percentile(0.99),
+        p999: percentile(0.999),
+        max: latencies.last().copied().unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memory_lol::db::Writeable;
+    use std::io::Cursor;
+
+    #[test]
+    fn request_log() {
+        let log = "\
+GET /tw/id/123 application/json:
+   >> Matched: (by_user_id) GET /tw/id/<user_id>
+GET /tw/foo,Bar?token=abc:
+GET /favicon.ico:
+POST /tw/id/456:
+";
+        let expected = vec![
+            Query::Id(123),
+            Query::ScreenName("foo".to_string()),
+            Query::ScreenName("Bar".to_string()),
+            Query::Id(456),
+        ];
+
+        assert_eq!(read_request_log(Cursor::new(log)).unwrap(), expected);
+        assert!(matches!(
+            read_request_log(Cursor::new("GET /favicon.ico:\n")),
+            Err(Error::EmptyWorkload)
+        ));
+    }
+
+    #[test]
+    fn sequential_workload() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::<Writeable>::open(dir.path()).unwrap();
+
+        for id in 1..=100 {
+            db.insert(id, &format!("user{}", id), vec![]).unwrap();
+        }
+
+        drop(db);
+
+        let db = Database::<ReadOnly>::open(dir.path()).unwrap();
+        let workload = Workload {
+            queries: 20,
+            pattern: ReadPattern::Sequential,
+            target: Target::Id,
+            keys: 10,
+            zipf_exponent: 1.0,
+            seed: 0,
+        };
+        let queries = workload.generate(&db).unwrap();
+        let ids = queries
+            .iter()
+            .map(|query| match query {
+                Query::Id(id) => *id,
+                Query::ScreenName(_) => panic!("Unexpected screen name query"),
+            })
+            .collect::<Vec<_>>();
+
+        // Ten distinct keys in order, repeated, drawn from across the table
+        assert_eq!(ids.len(), 20);
+        assert_eq!(ids[0..10], ids[10..20]);
+        assert!(ids[0..10].windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ids[9] > 50);
+    }
+}
@@ -0,0 +1,540 @@
+//! A `/whois` chat command for Slack and Discord.
+//!
+//! Both platforms can deliver slash commands to an HTTP endpoint instead of requiring a
+//! persistent connection: Slack posts form-encoded commands signed with an HMAC of the app's
+//! signing secret, and Discord posts JSON interactions signed with the app's Ed25519 key.
+use crate::http::{self, Reply, Request, Response};
+use crate::lookup::{self, Query, Source};
+use ed25519_dalek::{Signature, VerifyingKey};
+use hmac::{Hmac, Mac};
+use memory_lol::summary::Summary;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const SLACK_PATH: &str = "/slack/commands";
+pub const DISCORD_PATH: &str = "/discord/interactions";
+
+/// Requests signed longer ago than this are rejected, so that captured requests can't be
+/// replayed.
+const MAX_REQUEST_AGE_SECS: u64 = 300;
+/// The most accounts a reply lists (screen names used by many accounts would flood the channel).
+const MAX_ACCOUNTS: usize = 5;
+const RATE_LIMIT_WINDOW_SECS: u64 = 60;
+
+const DISCORD_PING: u64 = 1;
+const DISCORD_APPLICATION_COMMAND: u64 = 2;
+const DISCORD_PONG: u64 = 1;
+const DISCORD_CHANNEL_MESSAGE: u64 = 4;
+/// Message flag for replies only the user who ran the command can see.
+const DISCORD_EPHEMERAL: u64 = 64;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("HTTP error")]
+    Http(#[from] http::Error),
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid Discord public key")]
+    InvalidDiscordKey(String),
+}
+
+/// The platforms to answer commands for.
+pub struct Config {
+    pub slack_signing_secret: Option<Vec<u8>>,
+    pub discord_public_key: Option<VerifyingKey>,
+    /// Commands each channel can run per minute
+    pub channel_limit: u32,
+}
+
+impl Config {
+    /// Parse a Discord application public key (as shown in the developer portal, in hex).
+    pub fn parse_discord_key(value: &str) -> Result<VerifyingKey, Error> {
+        decode_hex(value.trim())
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+            .ok_or_else(|| Error::InvalidDiscordKey(value.to_string()))
+    }
+}
+
+/// Counts commands per channel (or room) in fixed one-minute windows.
+pub struct ChannelLimiter {
+    limit: u32,
+    windows: Mutex<HashMap<String, (u64, u32)>>,
+}
+
+impl ChannelLimiter {
+    pub fn new(limit: u32) -> Self {
+        Self {
+            limit,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count a command, returning the number of seconds to wait if the channel is over its limit.
+    pub fn check(&self, channel: &str, now: u64) -> Option<u64> {
+        let window_start = now - now % RATE_LIMIT_WINDOW_SECS;
+        let mut windows = self
+            .windows
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        windows.retain(|_, (start, _)| *start == window_start);
+
+        let (_, count) = windows
+            .entry(channel.to_string())
+            .or_insert((window_start, 0));
+
+        if *count >= self.limit {
+            Some(window_start + RATE_LIMIT_WINDOW_SECS - now)
+        } else {
+            *count += 1;
+            None
+        }
+    }
+}
+
+/// Answer Slack and Discord commands over HTTP until the process is terminated.
+///
+/// Both platforms give up on commands that aren't answered within three seconds, so commands are
+/// answered concurrently (by the server's worker threads).
+pub fn serve(db: &dyn Source, listener: TcpListener, config: &Config) -> Result<(), Error> {
+    let limiter = ChannelLimiter::new(config.channel_limit);
+
+    http::serve_listener(listener, None, |request| {
+        Reply::Response(handle(db, config, &limiter, request, now()))
+    })?;
+
+    Ok(())
+}
+
+fn handle(
+    db: &dyn Source,
+    config: &Config,
+    limiter: &ChannelLimiter,
+    request: &Request,
+    now: u64,
+) -> Response {
+    match (request.method.as_str(), request.path_without_query()) {
+        ("POST", SLACK_PATH) => match &config.slack_signing_secret {
+            Some(secret) if verify_slack(request, secret, now) => {
+                slack_command(db, limiter, &request.body, now)
+            }
+            Some(_) => Response::status(401),
+            None => Response::status(404),
+        },
+        ("POST", DISCORD_PATH) => match &config.discord_public_key {
+            Some(key) if verify_discord(request, key, now) => {
+                discord_interaction(db, limiter, &request.body, now)
+            }
+            Some(_) => Response::status(401),
+            None => Response::status(404),
+        },
+        (_, SLACK_PATH | DISCORD_PATH) => Response::status(405),
+        _ => Response::status(404),
+    }
+}
+
+fn slack_command(db: &dyn Source, limiter: &ChannelLimiter, body: &[u8], now: u64) -> Response {
+    let fields = form_fields(&String::from_utf8_lossy(body));
+    let field = |name: &str| fields.get(name).map(String::as_str).unwrap_or_default();
+
+    let reply = match limiter.check(field("channel_id"), now) {
+        Some(wait) => json!({
+            "response_type": "ephemeral",
+            "text": rate_limited(wait),
+        }),
+        None => json!({
+            "response_type": "in_channel",
+            "text": whois(db, field("text")),
+        }),
+    };
+
+    Response::json(&reply)
+}
+
+fn discord_interaction(
+    db: &dyn Source,
+    limiter: &ChannelLimiter,
+    body: &[u8],
+    now: u64,
+) -> Response {
+    let interaction = match serde_json::from_slice::<Value>(body) {
+        Ok(interaction) => interaction,
+        Err(_) => return Response::status(400),
+    };
+
+    match interaction["type"].as_u64() {
+        Some(DISCORD_PING) => Response::json(&json!({ "type": DISCORD_PONG })),
+        Some(DISCORD_APPLICATION_COMMAND) => {
+            let channel = interaction["channel_id"].as_str().unwrap_or_default();
+            let query = interaction["data"]["options"]
+                .as_array()
+                .and_then(|options| options.first())
+                .and_then(|option| option["value"].as_str())
+                .unwrap_or_default();
+
+            let data = match limiter.check(channel, now) {
+                Some(wait) => json!({ "content": rate_limited(wait), "flags": DISCORD_EPHEMERAL }),
+                None => json!({ "content": whois(db, query) }),
+            };
+
+            Response::json(&json!({ "type": DISCORD_CHANNEL_MESSAGE, "data": data }))
+        }
+        _ => Response::status(400),
+    }
+}
+
+/// The reply to a command like `/whois <handle|id>`: a summary of each matching account's screen
+/// names.
+pub fn whois(db: &dyn Source, text: &str) -> String {
+    let text = text.trim().trim_start_matches('@');
+
+    let query = match Query::infer(text) {
+        Ok(Some(query)) => query,
+        Ok(None) => return "Give a screen name or user ID to look up".to_string(),
+        Err(error) => return format!("Invalid query: {}", error),
+    };
+
+    let summaries = lookup::resolve(db, &query).and_then(|result| {
+        result
+            .accounts
+            .iter()
+            .map(|account| {
+                let result = db.lookup_by_user_id(account.id)?;
+
+                Ok(Summary::from_result(&result).map(|summary| (account.id, summary)))
+            })
+            .collect::<Result<Vec<_>, lookup::Error>>()
+    });
+
+    match summaries {
+        Ok(summaries) => {
+            let summaries = summaries.into_iter().flatten().collect::<Vec<_>>();
+
+            if summaries.is_empty() {
+                return format!("No history found for {}", text);
+            }
+
+            let mut lines = summaries
+                .iter()
+                .take(MAX_ACCOUNTS)
+                .map(|(id, summary)| format!("{}: {}", id, summary))
+                .collect::<Vec<_>>();
+
+            if summaries.len() > MAX_ACCOUNTS {
+                lines.push(format!(
+                    "…and {} more accounts",
+                    summaries.len() - MAX_ACCOUNTS
+                ));
+            }
+
+            lines.join("\n")
+        }
+        Err(error) => {
+            log::error!("Lookup error: {:?}", error);
+            "Lookup failed".to_string()
+        }
+    }
+}
+
+pub fn rate_limited(wait: u64) -> String {
+    format!(
+        "This channel has reached its lookup limit; try again in {} seconds",
+        wait
+    )
+}
+
+/// Check a Slack request's signature (an HMAC of `v0:<timestamp>:<body>`).
+fn verify_slack(request: &Request, secret: &[u8], now: u64) -> bool {
+    let (Some(timestamp), Some(signature)) = (
+        request.header("x-slack-request-timestamp"),
+        request.header("x-slack-signature"),
+    ) else {
+        return false;
+    };
+    let Some(signature) = signature.strip_prefix("v0=").and_then(decode_hex) else {
+        return false;
+    };
+
+    if !is_recent(timestamp, now) {
+        return false;
+    }
+
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret) else {
+        return false;
+    };
+    mac.update(format!("v0:{}:", timestamp).as_bytes());
+    mac.update(&request.body);
+
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// Check a Discord request's signature (of the timestamp followed by the body).
+fn verify_discord(request: &Request, key: &VerifyingKey, now: u64) -> bool {
+    let (Some(timestamp), Some(signature)) = (
+        request.header("x-signature-timestamp"),
+        request.header("x-signature-ed25519"),
+    ) else {
+        return false;
+    };
+    let Some(signature) = decode_hex(signature)
+        .and_then(|bytes| <[u8; 64]>::try_from(bytes).ok())
+        .map(|bytes| Signature::from_bytes(&bytes))
+    else {
+        return false;
+    };
+
+    if !is_recent(timestamp, now) {
+        return false;
+    }
+
+    let mut message = timestamp.as_bytes().to_vec();
+    message.extend_from_slice(&request.body);
+
+    key.verify_strict(&message, &signature).is_ok()
+}
+
+fn is_recent(timestamp: &str, now: u64) -> bool {
+    timestamp
+        .parse::<u64>()
+        .is_ok_and(|timestamp| now.abs_diff(timestamp) <= MAX_REQUEST_AGE_SECS)
+}
+
+fn form_fields(body: &str) -> HashMap<String, String> {
+    body.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| {
+            (
+                http::percent_decode(&key.replace('+', " ")),
+                http::percent_decode(&value.replace('+', " ")),
+            )
+        })
+        .collect()
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..value.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(value.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use ed25519_dalek::{Signer, SigningKey};
+    use memory_lol::db::{Database, ReadOnly, Writeable};
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn database(dir: &std::path::Path) -> Database<ReadOnly> {
+        {
+            let db = Database::<Writeable>::open(dir).unwrap();
+            let date = |year| NaiveDate::from_ymd_opt(year, 1, 1).unwrap();
+
+            db.insert(1, "foo", vec![date(2019), date(2020)]).unwrap();
+            db.insert(1, "bar", vec![date(2021)]).unwrap();
+        }
+
+        Database::<ReadOnly>::open(dir).unwrap()
+    }
+
+    fn request(path: &str, headers: Vec<(&str, String)>, body: &str) -> Request {
+        Request {
+            method: "POST".to_string(),
+            path: path.to_string(),
+            headers: headers
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+            body: body.as_bytes().to_vec(),
+            remote: None,
+        }
+    }
+
+    fn body(response: &Response) -> Value {
+        serde_json::from_slice(&response.body).unwrap()
+    }
+
+    #[test]
+    fn slack() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = database(dir.path());
+        let config = Config {
+            slack_signing_secret: Some(b"secret".to_vec()),
+            discord_public_key: None,
+            channel_limit: 1,
+        };
+        let limiter = ChannelLimiter::new(config.channel_limit);
+
+        let signed = |body: &str, secret: &[u8]| {
+            let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+            mac.update(format!("v0:{}:{}", NOW, body).as_bytes());
+            let signature = mac
+                .finalize()
+                .into_bytes()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>();
+
+            request(
+                SLACK_PATH,
+                vec![
+                    ("X-Slack-Request-Timestamp", NOW.to_string()),
+                    ("X-Slack-Signature", format!("v0={}", signature)),
+                ],
+                body,
+            )
+        };
+
+        let command = "command=%2Fwhois&text=%40foo&channel_id=C1";
+        let response = handle(&db, &config, &limiter, &signed(command, b"secret"), NOW);
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            body(&response),
+            json!({ "response_type": "in_channel", "text": "1: @bar (a.k.a. @foo 2019–2020)" })
+        );
+
+        // The channel is now over its limit
+        let response = handle(&db, &config, &limiter, &signed(command, b"secret"), NOW);
+        assert_eq!(body(&response)["response_type"], "ephemeral");
+
+        let response = handle(&db, &config, &limiter, &signed(command, b"guess"), NOW);
+        assert_eq!(response.status, 401);
+        let response = handle(
+            &db,
+            &config,
+            &limiter,
+            &signed(command, b"secret"),
+            NOW + MAX_REQUEST_AGE_SECS + 1,
+        );
+        assert_eq!(response.status, 401);
+
+        let response = handle(
+            &db,
+            &config,
+            &limiter,
+            &request(DISCORD_PATH, vec![], ""),
+            NOW,
+        );
+        assert_eq!(response.status, 404);
+    }
+
+    #[test]
+    fn discord() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = database(dir.path());
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let public_key = signing_key
+            .verifying_key()
+            .as_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+        let config = Config {
+            slack_signing_secret: None,
+            discord_public_key: Some(Config::parse_discord_key(&public_key).unwrap()),
+            channel_limit: 10,
+        };
+        let limiter = ChannelLimiter::new(config.channel_limit);
+
+        let signed = |body: &str| {
+            let signature = signing_key.sign(format!("{}{}", NOW, body).as_bytes());
+
+            request(
+                DISCORD_PATH,
+                vec![
+                    ("X-Signature-Timestamp", NOW.to_string()),
+                    (
+                        "X-Signature-Ed25519",
+                        signature
+                            .to_bytes()
+                            .iter()
+                            .map(|byte| format!("{:02x}", byte))
+                            .collect(),
+                    ),
+                ],
+                body,
+            )
+        };
+
+        let response = handle(&db, &config, &limiter, &signed(r#"{"type":1}"#), NOW);
+        assert_eq!(body(&response), json!({ "type": 1 }));
+
+        let command = json!({
+            "type": 2,
+            "channel_id": "1",
+            "data": { "name": "whois", "options": [{ "name": "query", "value": "1" }] },
+        })
+        .to_string();
+        let response = handle(&db, &config, &limiter, &signed(&command), NOW);
+        assert_eq!(
+            body(&response)["data"]["content"],
+            "1: @bar (a.k.a. @foo 2019–2020)"
+        );
+
+        let mut tampered = signed(&command);
+        tampered.body = command.replace("\"1\"}", "\"2\"}").into_bytes();
+        assert_eq!(handle(&db, &config, &limiter, &tampered, NOW).status, 401);
+        assert!(Config::parse_discord_key("abc").is_err());
+    }
+
+    #[test]
+    fn whois_replies() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = database(dir.path());
+
+        assert_eq!(whois(&db, "missing"), "No history found for missing");
+        assert!(whois(&db, " ").starts_with("Give"));
+    }
+
+    #[test]
+    fn concurrent_commands() {
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+
+        let dir = tempfile::tempdir().unwrap();
+        let db: &'static Database<ReadOnly> = Box::leak(Box::new(database(dir.path())));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let config = Config {
+            slack_signing_secret: None,
+            discord_public_key: None,
+            channel_limit: 1,
+        };
+        std::thread::spawn(move || serve(db, listener, &config));
+
+        // A client that never finishes its request doesn't hold up the next command
+        let mut stalled = TcpStream::connect(address).unwrap();
+        stalled
+            .write_all(b"POST /slack/commands HTTP/1.1\r\n")
+            .unwrap();
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(3)))
+            .unwrap();
+        stream
+            .write_all(b"POST /slack/commands HTTP/1.1\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 404"));
+    }
+}
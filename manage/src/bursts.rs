@@ -0,0 +1,210 @@
+use crate::Error;
+use chrono::NaiveDate;
+use memory_lol::db::{Database, ReadOnly};
+use serde_derive::Serialize;
+use std::io::Write;
+use std::str::FromStr;
+
+/// A number of days, written as `7d`, `2w`, or just `7`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Window(pub i64);
+
+impl FromStr for Window {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (number, multiplier) = if let Some(days) = value.strip_suffix('d') {
+            (days, 1)
+        } else if let Some(weeks) = value.strip_suffix('w') {
+            (weeks, 7)
+        } else {
+            (value, 1)
+        };
+
+        match number.parse::<i64>() {
+            Ok(count) if count > 0 => Ok(Self(count * multiplier)),
+            _ => Err(format!(
+                "Expected a number of days (e.g. 7d) or weeks (2w): {}",
+                value
+            )),
+        }
+    }
+}
+
+/// A period in which an account took on many new screen names.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Burst {
+    pub id: u64,
+    /// Number of screen names first observed in the window (not counting the account's first)
+    pub renames: usize,
+    /// First observation of the first screen name in the window
+    pub start: NaiveDate,
+    /// First observation of the last screen name in the window
+    pub end: NaiveDate,
+    /// The screen names in the window, in order of first observation
+    pub screen_names: Vec<String>,
+    /// Total number of screen names the account has been observed with
+    pub total_screen_names: usize,
+}
+
+/// Find the window with the most renames in an account's history, if it has at least the given
+/// number.
+///
+/// Each screen name is dated by its first observation, and the account's earliest screen name
+/// doesn't count as a rename. Ties go to the earliest window.
+pub fn detect(
+    id: u64,
+    mut history: Vec<(NaiveDate, String)>,
+    window: Window,
+    min_renames: usize,
+) -> Option<Burst> {
+    history.sort();
+
+    let renames = history.get(1..).unwrap_or_default();
+    let mut best: Option<(usize, usize)> = None;
+    let mut start = 0;
+
+    for end in 0..renames.len() {
+        while (renames[end].0 - renames[start].0).num_days() >= window.0 {
+            start += 1;
+        }
+
+        if best.is_none_or(|(best_start, best_end)| end - start > best_end - best_start) {
+            best = Some((start, end));
+        }
+    }
+
+    let (start, end) = best?;
+    let count = end - start + 1;
+
+    if count >= min_renames.max(1) {
+        Some(Burst {
+            id,
+            renames: count,
+            start: renames[start].0,
+            end: renames[end].0,
+            screen_names: renames[start..=end]
+                .iter()
+                .map(|(_, screen_name)| screen_name.clone())
+                .collect(),
+            total_screen_names: history.len(),
+        })
+    } else {
+        None
+    }
+}
+
+/// Write a JSON line for each account with a burst of renames, returning the number found.
+pub fn run<W: Write>(
+    db: &Database<ReadOnly>,
+    window: Window,
+    min_renames: usize,
+    mut writer: W,
+) -> Result<usize, Error> {
+    let mut count = 0;
+    let mut current: Option<(u64, Vec<(NaiveDate, String)>)> = None;
+
+    let mut flush =
+        |account: Option<(u64, Vec<(NaiveDate, String)>)>, writer: &mut W| -> Result<(), Error> {
+            if let Some(burst) =
+                account.and_then(|(id, history)| detect(id, history, window, min_renames))
+            {
+                serde_json::to_writer(&mut *writer, &burst)?;
+                writeln!(writer)?;
+                count += 1;
+            }
+
+            Ok(())
+        };
+
+    // Pairs are ordered by account
+    for pair in db.accounts.pairs() {
+        let (id, screen_name, dates) = pair?;
+
+        if current
+            .as_ref()
+            .is_none_or(|(current_id, _)| *current_id != id)
+        {
+            flush(current.take(), &mut writer)?;
+            current = Some((id, vec![]));
+        }
+
+        if let (Some((_, history)), Some(first)) = (current.as_mut(), dates.iter().min()) {
+            history.push((*first, screen_name));
+        }
+    }
+
+    flush(current, &mut writer)?;
+    writer.flush()?;
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memory_lol::db::Writeable;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2022, 1, day).unwrap()
+    }
+
+    #[test]
+    fn parse_windows() {
+        assert_eq!("7d".parse(), Ok(Window(7)));
+        assert_eq!("2w".parse(), Ok(Window(14)));
+        assert_eq!("3".parse(), Ok(Window(3)));
+        assert!("0d".parse::<Window>().is_err());
+        assert!("7h".parse::<Window>().is_err());
+    }
+
+    #[test]
+    fn detect_bursts() {
+        let history = vec![
+            (date(1), "original".to_string()),
+            (date(10), "a".to_string()),
+            (date(12), "b".to_string()),
+            (date(16), "c".to_string()),
+            (date(17), "d".to_string()),
+            (date(30), "e".to_string()),
+        ];
+
+        let burst = detect(1, history.clone(), Window(7), 3).unwrap();
+        assert_eq!(burst.renames, 3);
+        assert_eq!((burst.start, burst.end), (date(10), date(16)));
+        assert_eq!(burst.screen_names, vec!["a", "b", "c"]);
+        assert_eq!(burst.total_screen_names, 6);
+
+        assert_eq!(detect(1, history.clone(), Window(7), 4), None);
+        assert_eq!(detect(1, history, Window(8), 4).unwrap().renames, 4);
+        assert_eq!(
+            detect(1, vec![(date(1), "a".to_string())], Window(7), 1),
+            None
+        );
+    }
+
+    #[test]
+    fn write_bursts() {
+        let dir = tempfile::tempdir().unwrap();
+
+        {
+            let db = Database::<Writeable>::open(dir.path()).unwrap();
+
+            db.insert(1, "foo", vec![date(1), date(2)]).unwrap();
+            db.insert(1, "bar", vec![date(3)]).unwrap();
+            db.insert(1, "baz", vec![date(4), date(20)]).unwrap();
+            db.insert(2, "qux", vec![date(1)]).unwrap();
+            db.insert(2, "quux", vec![date(20)]).unwrap();
+        }
+
+        let db = Database::<ReadOnly>::open(dir.path()).unwrap();
+        let mut output = vec![];
+
+        assert_eq!(run(&db, Window(7), 2, &mut output).unwrap(), 1);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"id\":1,\"renames\":2,\"start\":\"2022-01-03\",\"end\":\"2022-01-04\",\
+             \"screen_names\":[\"bar\",\"baz\"],\"total_screen_names\":3}\n"
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use crate::{lookup::Format, Error};
+use memory_lol::db::{conflicts::Conflict, Database, ReadOnly};
+
+/// List days with conflicting observations, most severe first.
+pub fn run(db: &Database<ReadOnly>, limit: Option<usize>, format: Format) -> Result<(), Error> {
+    let conflicts = db.find_conflicts(limit)?;
+
+    match format {
+        Format::Csv => {
+            for conflict in conflicts {
+                let severity = conflict.severity();
+
+                match conflict {
+                    Conflict::ScreenNames {
+                        id,
+                        first,
+                        last,
+                        screen_names,
+                    } => {
+                        println!(
+                            "screen_names,{},{},{},{},{}",
+                            first,
+                            last,
+                            severity,
+                            id,
+                            screen_names.join(";")
+                        );
+                    }
+                    Conflict::Ids {
+                        screen_name,
+                        first,
+                        last,
+                        ids,
+                    } => {
+                        let id_strings = ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+                        println!(
+                            "ids,{},{},{},{},{}",
+                            first,
+                            last,
+                            severity,
+                            screen_name,
+                            id_strings.join(";")
+                        );
+                    }
+                }
+            }
+        }
+        Format::Json => {
+            println!("{}", serde_json::to_string_pretty(&conflicts)?);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,347 @@
+use crate::lookup::{self, Query, QueryResult};
+use memory_lol::db::{
+    latency::LatencyRecorder,
+    slowlog::{QueryTimer, SlowQueryLog},
+    Database, ReadOnly,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Upper bound on the size of a single frame (in bytes).
+const MAX_FRAME_LEN: u32 = 16 * 1024 * 1024;
+/// Upper bound on the number of screen names returned for a prefix request.
+const MAX_PREFIX_LIMIT: usize = 1000;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("JSON error")]
+    Json(#[from] serde_json::Error),
+    #[error("Database error")]
+    Db(#[from] memory_lol::db::Error),
+    #[error("Lookup error")]
+    Lookup(#[from] lookup::Error),
+    #[error("Frame too large")]
+    FrameTooLarge(u32),
+    #[error("Truncated frame")]
+    TruncatedFrame,
+    #[error("Path exists and is not a socket")]
+    NotASocket(PathBuf),
+    #[error("Another daemon is already listening")]
+    AlreadyListening(PathBuf),
+}
+
+/// A query sent to the daemon.
+///
+/// Requests and responses are JSON values, each preceded by its length as a big-endian `u32`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Request {
+    Id(u64),
+    ScreenName(String),
+    Prefix {
+        prefix: String,
+        limit: usize,
+    },
+    /// A user ID or screen name, interpreted as by [`Query::infer`]
+    Query(String),
+}
+
+impl Request {
+    pub fn from_query(query: Query) -> Self {
+        match query {
+            Query::Id(id) => Request::Id(id),
+            Query::ScreenName(screen_name) => match screen_name.strip_suffix('*') {
+                Some(prefix) => Request::Prefix {
+                    prefix: prefix.to_string(),
+                    limit: 100,
+                },
+                None => Request::ScreenName(screen_name),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Response {
+    Result(QueryResult),
+    Prefix(Vec<(String, Vec<u64>)>),
+    Error(ErrorResponse),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request could not be parsed
+    InvalidRequest,
+    /// The request was well-formed but its contents were not valid (e.g. a bad screen name)
+    InvalidInput,
+    /// The lookup failed
+    Database,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ErrorResponse {
+    fn new<E: std::error::Error>(code: ErrorCode, error: &E) -> Self {
+        let mut message = error.to_string();
+        let mut source = error.source();
+
+        while let Some(error) = source {
+            message.push_str(": ");
+            message.push_str(&error.to_string());
+            source = error.source();
+        }
+
+        Self { code, message }
+    }
+}
+
+/// Serve queries on a Unix domain socket until the process is terminated.
+///
+/// A stale socket left behind by a previous daemon is replaced, but we refuse to start if the path
+/// is some other kind of file or if another daemon is still listening on it. If a recorder is
+/// given, the latency of every request is recorded, and if there's a slow query log, requests that
+/// take longer than its threshold are written to it.
+pub fn serve<P: AsRef<Path>>(
+    db: &Database<ReadOnly>,
+    socket: P,
+    recorder: Option<&LatencyRecorder>,
+    slow_queries: Option<&SlowQueryLog>,
+) -> Result<(), Error> {
+    let socket = socket.as_ref();
+
+    if let Ok(metadata) = std::fs::symlink_metadata(socket) {
+        if !metadata.file_type().is_socket() {
+            return Err(Error::NotASocket(socket.to_path_buf()));
+        }
+
+        if UnixStream::connect(socket).is_ok() {
+            return Err(Error::AlreadyListening(socket.to_path_buf()));
+        }
+
+        std::fs::remove_file(socket)?;
+    }
+
+    let listener = UnixListener::bind(socket)?;
+    log::info!("Listening on {}", socket.to_string_lossy());
+
+    std::thread::scope(|scope| {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    scope.spawn(move || {
+                        if let Err(error) = handle_connection(db, stream, recorder, slow_queries) {
+                            log::warn!("Connection error: {:?}", error);
+                        }
+                    });
+                }
+                Err(error) => {
+                    log::error!("Accept error: {}", error);
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Send a single request to a running daemon.
+pub fn query<P: AsRef<Path>>(socket: P, request: &Request) -> Result<Response, Error> {
+    let mut stream = UnixStream::connect(socket)?;
+    write_frame(&mut stream, &serde_json::to_vec(request)?)?;
+
+    match read_frame(&mut stream)? {
+        Some(frame) => Ok(serde_json::from_slice(&frame)?),
+        None => Err(Error::Io(std::io::ErrorKind::UnexpectedEof.into())),
+    }
+}
+
+fn handle_connection(
+    db: &Database<ReadOnly>,
+    mut stream: UnixStream,
+    recorder: Option<&LatencyRecorder>,
+    slow_queries: Option<&SlowQueryLog>,
+) -> Result<(), Error> {
+    while let Some(frame) = read_frame(&mut stream)? {
+        let started = Instant::now();
+        let timer = slow_queries.map(|_| QueryTimer::start());
+        let response = match serde_json::from_slice::<Request>(&frame) {
+            Ok(request) => handle_request(db, request),
+            Err(error) => Response::Error(ErrorResponse::new(ErrorCode::InvalidRequest, &error)),
+        };
+
+        if let Some(recorder) = recorder {
+            recorder.record(started.elapsed());
+        }
+
+        let response = serde_json::to_vec(&response)?;
+
+        if let Some((slow_queries, timer)) = slow_queries.zip(timer) {
+            slow_queries.record(&String::from_utf8_lossy(&frame), timer, response.len());
+        }
+
+        write_frame(&mut stream, &response)?;
+    }
+
+    Ok(())
+}
+
+fn handle_request(db: &Database<ReadOnly>, request: Request) -> Response {
+    let result = match request {
+        Request::Id(id) => lookup::resolve(db, &Query::Id(id)).map(Response::Result),
+        Request::ScreenName(screen_name) => {
+            lookup::resolve(db, &Query::ScreenName(screen_name)).map(Response::Result)
+        }
+        Request::Prefix { prefix, limit } => db
+            .lookup_by_screen_name_prefix(&prefix, limit.min(MAX_PREFIX_LIMIT))
+            .map(Response::Prefix)
+            .map_err(lookup::Error::from),
+        Request::Query(input) => lookup::resolve_input(db, &input).map(Response::Result),
+    };
+
+    result.unwrap_or_else(|error| {
+        let code = match &error {
+            lookup::Error::Db(memory_lol::db::Error::InvalidScreenName(_))
+            | lookup::Error::InvalidId(_)
+            | lookup::Error::EmptyQuery => ErrorCode::InvalidInput,
+            _ => ErrorCode::Database,
+        };
+
+        Response::Error(ErrorResponse::new(code, &error))
+    })
+}
+
+fn read_frame<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>, Error> {
+    let mut len_bytes = [0; 4];
+    let mut read = 0;
+
+    // End of input is only clean between frames, not partway through a length header
+    while read < len_bytes.len() {
+        match reader.read(&mut len_bytes[read..]) {
+            Ok(0) if read == 0 => return Ok(None),
+            Ok(0) => return Err(Error::TruncatedFrame),
+            Ok(count) => read += count,
+            Err(error) if error.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error.into()),
+        }
+    }
+
+    let len = u32::from_be_bytes(len_bytes);
+
+    if len > MAX_FRAME_LEN {
+        return Err(Error::FrameTooLarge(len));
+    }
+
+    let mut frame = vec![0; len as usize];
+
+    match reader.read_exact(&mut frame) {
+        Ok(()) => Ok(Some(frame)),
+        Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => {
+            Err(Error::TruncatedFrame)
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+fn write_frame<W: Write>(writer: &mut W, frame: &[u8]) -> Result<(), Error> {
+    let len: u32 = frame
+        .len()
+        .try_into()
+        .map_err(|_| Error::FrameTooLarge(u32::MAX))?;
+
+    writer.write_all(&len.to_be_bytes())?;
+    writer.write_all(frame)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn frame_round_trip() {
+        let mut buffer = vec![];
+        write_frame(&mut buffer, b"first").unwrap();
+        write_frame(&mut buffer, b"").unwrap();
+        write_frame(&mut buffer, b"second").unwrap();
+
+        assert_eq!(&buffer[0..4], &5u32.to_be_bytes());
+
+        let mut reader = Cursor::new(buffer);
+
+        assert_eq!(read_frame(&mut reader).unwrap(), Some(b"first".to_vec()));
+        assert_eq!(read_frame(&mut reader).unwrap(), Some(vec![]));
+        assert_eq!(read_frame(&mut reader).unwrap(), Some(b"second".to_vec()));
+        assert_eq!(read_frame(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn truncated_frames() {
+        let mut partial_header = Cursor::new(vec![0, 0]);
+        assert!(matches!(
+            read_frame(&mut partial_header),
+            Err(Error::TruncatedFrame)
+        ));
+
+        let mut partial_body = Cursor::new(vec![0, 0, 0, 4, b'a', b'b']);
+        assert!(matches!(
+            read_frame(&mut partial_body),
+            Err(Error::TruncatedFrame)
+        ));
+    }
+
+    #[test]
+    fn oversized_frame() {
+        let mut reader = Cursor::new((MAX_FRAME_LEN + 1).to_be_bytes().to_vec());
+
+        assert!(matches!(
+            read_frame(&mut reader),
+            Err(Error::FrameTooLarge(len)) if len == MAX_FRAME_LEN + 1
+        ));
+    }
+
+    #[test]
+    fn refuse_non_socket_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("not-a-socket");
+        std::fs::write(&path, b"").unwrap();
+
+        drop(Database::<memory_lol::db::Writeable>::open(dir.path().join("db")).unwrap());
+        let db = Database::<ReadOnly>::open(dir.path().join("db")).unwrap();
+
+        assert!(matches!(
+            serve(&db, &path, None, None),
+            Err(Error::NotASocket(_))
+        ));
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn refuse_socket_in_use() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("daemon.sock");
+        let _listener = UnixListener::bind(&path).unwrap();
+
+        drop(Database::<memory_lol::db::Writeable>::open(dir.path().join("db")).unwrap());
+        let db = Database::<ReadOnly>::open(dir.path().join("db")).unwrap();
+
+        assert!(matches!(
+            serve(&db, &path, None, None),
+            Err(Error::AlreadyListening(_))
+        ));
+    }
+}
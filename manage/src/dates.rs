@@ -0,0 +1,101 @@
+//! How dates are shown in text output.
+use chrono::{NaiveDate, Utc};
+
+/// Forms of dates in text output.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum DateStyle {
+    /// ISO 8601 dates (like `2022-01-02`)
+    #[default]
+    Iso,
+    /// Time before or after today (like `3 weeks ago`)
+    Relative,
+    /// Epoch seconds at midnight UTC
+    Unix,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DateFormatter {
+    style: DateStyle,
+    today: NaiveDate,
+}
+
+impl DateFormatter {
+    pub fn new(style: DateStyle) -> Self {
+        Self::with_today(style, Utc::now().date_naive())
+    }
+
+    /// A formatter that shows relative dates as of the given day.
+    pub fn with_today(style: DateStyle, today: NaiveDate) -> Self {
+        Self { style, today }
+    }
+
+    pub fn format(&self, date: NaiveDate) -> String {
+        match self.style {
+            DateStyle::Iso => date.format("%Y-%m-%d").to_string(),
+            DateStyle::Relative => relative((self.today - date).num_days()),
+            DateStyle::Unix => date
+                .and_hms_opt(0, 0, 0)
+                .map(|timestamp| timestamp.and_utc().timestamp())
+                .unwrap_or_default()
+                .to_string(),
+        }
+    }
+
+    /// Format a run of days as `<first>..<last>`, or as a single date if it's one day long.
+    pub fn format_range(&self, start: NaiveDate, end: NaiveDate) -> String {
+        if start == end {
+            self.format(start)
+        } else {
+            format!("{}..{}", self.format(start), self.format(end))
+        }
+    }
+}
+
+fn relative(days_ago: i64) -> String {
+    let (count, unit) = match days_ago.abs() {
+        0 => return "today".to_string(),
+        1 if days_ago > 0 => return "yesterday".to_string(),
+        1 => return "tomorrow".to_string(),
+        days @ 2..=13 => (days, "day"),
+        days @ 14..=59 => (days / 7, "week"),
+        days @ 60..=729 => (days / 30, "month"),
+        days => (days / 365, "year"),
+    };
+    let plural = if count == 1 { "" } else { "s" };
+
+    if days_ago > 0 {
+        format!("{} {}{} ago", count, unit, plural)
+    } else {
+        format!("in {} {}{}", count, unit, plural)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_dates() {
+        let today = NaiveDate::from_ymd_opt(2022, 6, 15).unwrap();
+        let date = |year, month, day| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+        let relative = DateFormatter::with_today(DateStyle::Relative, today);
+
+        assert_eq!(relative.format(today), "today");
+        assert_eq!(relative.format(date(2022, 6, 14)), "yesterday");
+        assert_eq!(relative.format(date(2022, 6, 10)), "5 days ago");
+        assert_eq!(relative.format(date(2022, 5, 25)), "3 weeks ago");
+        assert_eq!(relative.format(date(2021, 12, 1)), "6 months ago");
+        assert_eq!(relative.format(date(2019, 6, 1)), "3 years ago");
+        assert_eq!(relative.format(date(2022, 6, 30)), "in 2 weeks");
+        assert_eq!(
+            relative.format_range(date(2022, 6, 10), date(2022, 6, 14)),
+            "5 days ago..yesterday"
+        );
+
+        let unix = DateFormatter::with_today(DateStyle::Unix, today);
+        assert_eq!(unix.format(date(2022, 1, 2)), "1641081600");
+
+        let iso = DateFormatter::with_today(DateStyle::Iso, today);
+        assert_eq!(iso.format_range(today, today), "2022-06-15");
+    }
+}
@@ -0,0 +1,268 @@
+use serde_json::Value;
+
+/// Binary alternatives to JSON for results, which are cheaper for bulk consumers to decode.
+///
+/// Values are encoded from their JSON representation, so the structure is the same in every
+/// encoding (and user IDs are integers, not strings, wherever they are in JSON).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum Encoding {
+    #[default]
+    Json,
+    /// MessagePack
+    Msgpack,
+    /// CBOR (RFC 8949)
+    Cbor,
+}
+
+impl Encoding {
+    const ALL: [Encoding; 3] = [Encoding::Json, Encoding::Msgpack, Encoding::Cbor];
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Encoding::Json => "application/json",
+            Encoding::Msgpack => "application/msgpack",
+            Encoding::Cbor => "application/cbor",
+        }
+    }
+
+    /// The encoding an `Accept` header prefers (JSON if it doesn't accept either of the others).
+    ///
+    /// Wildcards are taken to mean JSON, so that clients that don't ask for anything in particular
+    /// get what they always have.
+    pub fn negotiate(accept: Option<&str>) -> Self {
+        let mut best = (Encoding::Json, 0.0);
+
+        for range in accept.unwrap_or_default().split(',') {
+            let mut parts = range.split(';');
+            let media_type = parts.next().unwrap_or_default().trim();
+            let quality = parts
+                .filter_map(|parameter| parameter.trim().strip_prefix("q="))
+                .find_map(|value| value.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            let encoding = Self::ALL.into_iter().find(|encoding| {
+                media_type.eq_ignore_ascii_case(encoding.content_type())
+                    || (*encoding == Encoding::Msgpack
+                        && media_type.eq_ignore_ascii_case("application/x-msgpack"))
+            });
+
+            if let Some(encoding) = encoding {
+                if quality > best.1 {
+                    best = (encoding, quality);
+                }
+            }
+        }
+
+        best.0
+    }
+
+    pub fn encode(self, value: &Value) -> Vec<u8> {
+        let mut output = vec![];
+
+        match self {
+            Encoding::Json => output = value.to_string().into_bytes(),
+            Encoding::Msgpack => write_msgpack(value, &mut output),
+            Encoding::Cbor => write_cbor(value, &mut output),
+        }
+
+        output
+    }
+}
+
+fn write_msgpack(value: &Value, output: &mut Vec<u8>) {
+    match value {
+        Value::Null => output.push(0xc0),
+        Value::Bool(false) => output.push(0xc2),
+        Value::Bool(true) => output.push(0xc3),
+        Value::Number(number) => {
+            if let Some(value) = number.as_u64() {
+                match value {
+                    0..=0x7f => output.push(value as u8),
+                    0x80..=0xff => output.extend_from_slice(&[0xcc, value as u8]),
+                    0x100..=0xffff => {
+                        output.push(0xcd);
+                        output.extend_from_slice(&(value as u16).to_be_bytes());
+                    }
+                    0x1_0000..=0xffff_ffff => {
+                        output.push(0xce);
+                        output.extend_from_slice(&(value as u32).to_be_bytes());
+                    }
+                    _ => {
+                        output.push(0xcf);
+                        output.extend_from_slice(&value.to_be_bytes());
+                    }
+                }
+            } else if let Some(value) = number.as_i64() {
+                // Only negative values are left
+                if value >= -32 {
+                    output.push(value as u8);
+                } else {
+                    output.push(0xd3);
+                    output.extend_from_slice(&value.to_be_bytes());
+                }
+            } else {
+                output.push(0xcb);
+                output.extend_from_slice(&number.as_f64().unwrap_or(f64::NAN).to_be_bytes());
+            }
+        }
+        Value::String(value) => {
+            msgpack_length(value.len(), 0xa0, 32, [0xd9, 0xda, 0xdb], output);
+            output.extend_from_slice(value.as_bytes());
+        }
+        Value::Array(values) => {
+            msgpack_length(values.len(), 0x90, 16, [0, 0xdc, 0xdd], output);
+
+            for value in values {
+                write_msgpack(value, output);
+            }
+        }
+        Value::Object(map) => {
+            msgpack_length(map.len(), 0x80, 16, [0, 0xde, 0xdf], output);
+
+            for (key, value) in map {
+                write_msgpack(&Value::String(key.clone()), output);
+                write_msgpack(value, output);
+            }
+        }
+    }
+}
+
+/// Write the header of a string, array, or map, given the prefix and limit of its fixed-size
+/// form and its 8, 16, and 32-bit forms (arrays and maps have no 8-bit form).
+fn msgpack_length(len: usize, fixed: u8, fixed_limit: usize, forms: [u8; 3], output: &mut Vec<u8>) {
+    if len < fixed_limit {
+        output.push(fixed | len as u8);
+    } else if len <= 0xff && forms[0] != 0 {
+        output.extend_from_slice(&[forms[0], len as u8]);
+    } else if len <= 0xffff {
+        output.push(forms[1]);
+        output.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        output.push(forms[2]);
+        output.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+}
+
+const CBOR_UNSIGNED: u8 = 0;
+const CBOR_NEGATIVE: u8 = 1;
+const CBOR_TEXT: u8 = 3;
+const CBOR_ARRAY: u8 = 4;
+const CBOR_MAP: u8 = 5;
+
+fn write_cbor(value: &Value, output: &mut Vec<u8>) {
+    match value {
+        Value::Null => output.push(0xf6),
+        Value::Bool(false) => output.push(0xf4),
+        Value::Bool(true) => output.push(0xf5),
+        Value::Number(number) => {
+            if let Some(value) = number.as_u64() {
+                cbor_head(CBOR_UNSIGNED, value, output);
+            } else if let Some(value) = number.as_i64() {
+                // Negative integers are encoded as -1 minus the argument
+                cbor_head(CBOR_NEGATIVE, !(value as u64), output);
+            } else {
+                output.push(0xfb);
+                output.extend_from_slice(&number.as_f64().unwrap_or(f64::NAN).to_be_bytes());
+            }
+        }
+        Value::String(value) => {
+            cbor_head(CBOR_TEXT, value.len() as u64, output);
+            output.extend_from_slice(value.as_bytes());
+        }
+        Value::Array(values) => {
+            cbor_head(CBOR_ARRAY, values.len() as u64, output);
+
+            for value in values {
+                write_cbor(value, output);
+            }
+        }
+        Value::Object(map) => {
+            cbor_head(CBOR_MAP, map.len() as u64, output);
+
+            for (key, value) in map {
+                cbor_head(CBOR_TEXT, key.len() as u64, output);
+                output.extend_from_slice(key.as_bytes());
+                write_cbor(value, output);
+            }
+        }
+    }
+}
+
+fn cbor_head(major: u8, argument: u64, output: &mut Vec<u8>) {
+    let major = major << 5;
+
+    match argument {
+        0..=23 => output.push(major | argument as u8),
+        24..=0xff => output.extend_from_slice(&[major | 24, argument as u8]),
+        0x100..=0xffff => {
+            output.push(major | 25);
+            output.extend_from_slice(&(argument as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            output.push(major | 26);
+            output.extend_from_slice(&(argument as u32).to_be_bytes());
+        }
+        _ => {
+            output.push(major | 27);
+            output.extend_from_slice(&argument.to_be_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn negotiate() {
+        assert_eq!(Encoding::negotiate(None), Encoding::Json);
+        assert_eq!(Encoding::negotiate(Some("*/*")), Encoding::Json);
+        assert_eq!(
+            Encoding::negotiate(Some("application/msgpack")),
+            Encoding::Msgpack
+        );
+        assert_eq!(
+            Encoding::negotiate(Some("application/json;q=0.5, application/cbor")),
+            Encoding::Cbor
+        );
+        assert_eq!(
+            Encoding::negotiate(Some("application/cbor;q=0.2, application/x-msgpack;q=0.8")),
+            Encoding::Msgpack
+        );
+        assert_eq!(
+            Encoding::negotiate(Some("application/cbor;q=0")),
+            Encoding::Json
+        );
+    }
+
+    #[test]
+    fn encode() {
+        let value = json!({ "id": 1_000_000_000_000u64, "names": ["foo", null, true], "n": -40 });
+
+        assert_eq!(
+            Encoding::Msgpack.encode(&value),
+            [
+                vec![0x83, 0xa2, b'i', b'd', 0xcf, 0, 0, 0, 0xe8, 0xd4, 0xa5, 0x10, 0],
+                vec![0xa5, b'n', b'a', b'm', b'e', b's', 0x93, 0xa3, b'f', b'o', b'o', 0xc0, 0xc3],
+                vec![0xa1, b'n', 0xd3, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xd8],
+            ]
+            .concat()
+        );
+        assert_eq!(
+            Encoding::Cbor.encode(&value),
+            [
+                vec![0xa3, 0x62, b'i', b'd', 0x1b, 0, 0, 0, 0xe8, 0xd4, 0xa5, 0x10, 0],
+                vec![0x65, b'n', b'a', b'm', b'e', b's', 0x83, 0x63, b'f', b'o', b'o', 0xf6, 0xf5],
+                vec![0x61, b'n', 0x38, 0x27],
+            ]
+            .concat()
+        );
+        assert_eq!(Encoding::Msgpack.encode(&json!(-1)), [0xff]);
+        assert_eq!(Encoding::Cbor.encode(&json!(-1)), [0x20]);
+        assert_eq!(
+            Encoding::Cbor.encode(&json!(1.5)),
+            [0xfb, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0]
+        );
+    }
+}
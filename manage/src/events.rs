@@ -0,0 +1,401 @@
+use chrono::NaiveDate;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("HTTP error")]
+    Http(#[from] reqwest::Error),
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("JSON encoding error")]
+    Json(#[from] serde_json::Error),
+    #[error("NATS error: {0}")]
+    Nats(String),
+}
+
+/// A change observed while importing.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case", tag = "event")]
+pub enum Event {
+    /// An account that wasn't in the database
+    NewAccount {
+        id: u64,
+        screen_name: String,
+        date: NaiveDate,
+    },
+    /// A screen name that an account already in the database hadn't been observed with
+    NewScreenName {
+        id: u64,
+        screen_name: String,
+        date: NaiveDate,
+    },
+    /// A screen name already observed with other accounts
+    HandleChangedHands {
+        id: u64,
+        screen_name: String,
+        previous_ids: Vec<u64>,
+        date: NaiveDate,
+    },
+}
+
+impl Event {
+    /// The account the event is about.
+    pub fn id(&self) -> u64 {
+        match self {
+            Event::NewAccount { id, .. }
+            | Event::NewScreenName { id, .. }
+            | Event::HandleChangedHands { id, .. } => *id,
+        }
+    }
+
+    pub fn screen_name(&self) -> &str {
+        match self {
+            Event::NewAccount { screen_name, .. }
+            | Event::NewScreenName { screen_name, .. }
+            | Event::HandleChangedHands { screen_name, .. } => screen_name,
+        }
+    }
+
+    /// The events for a pair that wasn't in the database, given whether its account was, and the
+    /// accounts already observed with the screen name (in any case).
+    pub fn for_new_pair(
+        id: u64,
+        screen_name: &str,
+        date: NaiveDate,
+        new_account: bool,
+        holders: &[u64],
+    ) -> Vec<Self> {
+        let mut events = Vec::with_capacity(2);
+
+        if new_account {
+            events.push(Event::NewAccount {
+                id,
+                screen_name: screen_name.to_string(),
+                date,
+            });
+        } else {
+            events.push(Event::NewScreenName {
+                id,
+                screen_name: screen_name.to_string(),
+                date,
+            });
+        }
+
+        let previous_ids = holders
+            .iter()
+            .copied()
+            .filter(|holder| *holder != id)
+            .collect::<Vec<_>>();
+
+        if !previous_ids.is_empty() {
+            events.push(Event::HandleChangedHands {
+                id,
+                screen_name: screen_name.to_string(),
+                previous_ids,
+                date,
+            });
+        }
+
+        events
+    }
+}
+
+/// Where to publish events.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Target {
+    /// An HTTP endpoint that each batch is posted to as a JSON array
+    Webhook(String),
+    /// A topic endpoint of a Kafka REST proxy (`kafka+http://host:8082/topics/<topic>`)
+    Kafka(String),
+    /// A NATS server and subject (`nats://host:4222/<subject>`)
+    Nats { address: String, subject: String },
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.starts_with("http://") || value.starts_with("https://") {
+            Ok(Target::Webhook(value.to_string()))
+        } else if let Some(url) = value.strip_prefix("kafka+") {
+            if url.starts_with("http://") || url.starts_with("https://") {
+                Ok(Target::Kafka(url.to_string()))
+            } else {
+                Err(format!("Invalid Kafka REST proxy URL: {}", value))
+            }
+        } else if let Some(rest) = value.strip_prefix("nats://") {
+            match rest.split_once('/') {
+                Some((address, subject)) if !address.is_empty() && !subject.is_empty() => {
+                    Ok(Target::Nats {
+                        address: address.to_string(),
+                        subject: subject.to_string(),
+                    })
+                }
+                _ => Err(format!("Expected nats://host:port/subject: {}", value)),
+            }
+        } else {
+            Err(format!("Unsupported event target: {}", value))
+        }
+    }
+}
+
+/// Publishes import events to a target, if one is configured.
+///
+/// Publishing is best-effort: a batch that can't be delivered is logged and dropped rather than
+/// failing the import. Clones share their connection.
+#[derive(Clone, Default)]
+pub struct Events {
+    publisher: Option<Arc<Publisher>>,
+}
+
+struct Publisher {
+    target: Target,
+    http: Option<reqwest::blocking::Client>,
+    nats: Mutex<Option<BufReader<TcpStream>>>,
+}
+
+impl Events {
+    pub fn new(target: Option<Target>) -> Result<Self, Error> {
+        let publisher = match target {
+            Some(target) => {
+                let http = match target {
+                    Target::Nats { .. } => None,
+                    _ => Some(
+                        reqwest::blocking::Client::builder()
+                            .timeout(TIMEOUT)
+                            .build()?,
+                    ),
+                };
+
+                Some(Arc::new(Publisher {
+                    target,
+                    http,
+                    nats: Mutex::new(None),
+                }))
+            }
+            None => None,
+        };
+
+        Ok(Self { publisher })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.publisher.is_some()
+    }
+
+    /// Publish a batch of events, logging (and dropping it) if it can't be delivered.
+    pub fn publish(&self, events: &[Event]) {
+        if let Some(publisher) = self.publisher.as_ref().filter(|_| !events.is_empty()) {
+            if let Err(error) = publisher.publish(events) {
+                log::warn!("Failed to publish {} events: {:?}", events.len(), error);
+            }
+        }
+    }
+}
+
+impl Publisher {
+    fn publish(&self, events: &[Event]) -> Result<(), Error> {
+        match &self.target {
+            Target::Webhook(url) => self.post(url, "application/json", json!(events)),
+            Target::Kafka(url) => {
+                let records = events
+                    .iter()
+                    .map(|event| json!({ "value": event }))
+                    .collect::<Vec<_>>();
+
+                self.post(
+                    url,
+                    "application/vnd.kafka.json.v2+json",
+                    json!({ "records": records }),
+                )
+            }
+            Target::Nats { address, subject } => {
+                let mut connection = self
+                    .nats
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+                if let Some(stream) = connection.as_mut() {
+                    match nats_publish(stream, subject, events) {
+                        Ok(()) => return Ok(()),
+                        // The server may have closed the connection, so reconnect and retry once
+                        // (messages the server did accept are published twice)
+                        Err(error) => log::info!("Reconnecting to NATS after error: {:?}", error),
+                    }
+                }
+
+                *connection = None;
+                let mut stream = nats_connect(address)?;
+                nats_publish(&mut stream, subject, events)?;
+                *connection = Some(stream);
+
+                Ok(())
+            }
+        }
+    }
+
+    fn post(&self, url: &str, content_type: &str, body: Value) -> Result<(), Error> {
+        if let Some(http) = &self.http {
+            http.post(url)
+                .header(reqwest::header::CONTENT_TYPE, content_type)
+                .body(serde_json::to_vec(&body)?)
+                .send()?
+                .error_for_status()?;
+        }
+
+        Ok(())
+    }
+}
+
+fn nats_connect(address: &str) -> Result<BufReader<TcpStream>, Error> {
+    let stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let mut stream = BufReader::new(stream);
+
+    // The server starts by describing itself
+    let info = nats_read_line(&mut stream)?;
+
+    if !info.starts_with("INFO") {
+        return Err(Error::Nats(info));
+    }
+
+    stream
+        .get_mut()
+        .write_all(b"CONNECT {\"verbose\":false,\"pedantic\":false}\r\n")?;
+
+    Ok(stream)
+}
+
+/// Publish each event as a message, then wait for the server to answer a ping, so that we know
+/// the messages were accepted.
+fn nats_publish(
+    stream: &mut BufReader<TcpStream>,
+    subject: &str,
+    events: &[Event],
+) -> Result<(), Error> {
+    let mut buffer = vec![];
+
+    for event in events {
+        let payload = serde_json::to_vec(event)?;
+        write!(buffer, "PUB {} {}\r\n", subject, payload.len())?;
+        buffer.extend_from_slice(&payload);
+        buffer.extend_from_slice(b"\r\n");
+    }
+    buffer.extend_from_slice(b"PING\r\n");
+
+    stream.get_mut().write_all(&buffer)?;
+
+    loop {
+        let line = nats_read_line(stream)?;
+
+        if line == "PONG" {
+            return Ok(());
+        } else if line == "PING" {
+            stream.get_mut().write_all(b"PONG\r\n")?;
+        } else if line.starts_with("-ERR") {
+            return Err(Error::Nats(line));
+        }
+    }
+}
+
+fn nats_read_line(stream: &mut BufReader<TcpStream>) -> Result<String, Error> {
+    let mut line = String::new();
+
+    if stream.read_line(&mut line)? == 0 {
+        Err(Error::Nats("Connection closed".to_string()))
+    } else {
+        Ok(line.trim_end().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn parse_targets() {
+        assert_eq!(
+            "kafka+http://proxy:8082/topics/changes".parse(),
+            Ok(Target::Kafka(
+                "http://proxy:8082/topics/changes".to_string()
+            ))
+        );
+        assert_eq!(
+            "nats://localhost:4222/memory.changes".parse(),
+            Ok(Target::Nats {
+                address: "localhost:4222".to_string(),
+                subject: "memory.changes".to_string()
+            })
+        );
+        assert!("nats://localhost:4222".parse::<Target>().is_err());
+        assert!("ftp://example.com".parse::<Target>().is_err());
+    }
+
+    #[test]
+    fn publish_nats() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            writer.write_all(b"INFO {}\r\n").unwrap();
+
+            let mut received = vec![];
+            let mut line = String::new();
+
+            while reader.read_line(&mut line).unwrap() > 0 {
+                if line == "PING\r\n" {
+                    writer.write_all(b"PONG\r\n").unwrap();
+                    break;
+                }
+                received.push(line.trim_end().to_string());
+                line.clear();
+            }
+
+            received
+        });
+
+        let date = NaiveDate::from_ymd_opt(2022, 1, 1).unwrap();
+        let events = Event::for_new_pair(2, "foo", date, false, &[1, 2]);
+        let publisher = Events::new(Some(Target::Nats {
+            address,
+            subject: "changes".to_string(),
+        }))
+        .unwrap();
+        publisher.publish(&events);
+
+        let received = server.join().unwrap();
+        assert_eq!(
+            received[0],
+            "CONNECT {\"verbose\":false,\"pedantic\":false}"
+        );
+        assert_eq!(received[1], "PUB changes 74");
+        assert_eq!(
+            received[2],
+            "{\"event\":\"new_screen_name\",\"id\":2,\"screen_name\":\"foo\",\"date\":\"2022-01-01\"}"
+        );
+        assert_eq!(
+            serde_json::from_str::<Value>(&received[4]).unwrap(),
+            json!({
+                "event": "handle_changed_hands",
+                "id": 2,
+                "screen_name": "foo",
+                "previous_ids": [1],
+                "date": "2022-01-01"
+            })
+        );
+    }
+}
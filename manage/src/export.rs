@@ -0,0 +1,584 @@
+use crate::shutdown::Shutdown;
+use chrono::{DateTime, NaiveDate, Utc};
+use ed25519_dalek::{pkcs8::DecodePublicKey, Signature, VerifyingKey};
+use hmac::{Hmac, Mac};
+use memory_lol::db::{Database, ReadOnly, Table};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// Number of bytes of each HMAC digest included in anonymized output.
+const DIGEST_LEN: usize = 16;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("Database error")]
+    Db(#[from] memory_lol::db::Error),
+    #[error("JSON error")]
+    Json(#[from] serde_json::Error),
+    #[error("Empty anonymization key")]
+    EmptyKey,
+    #[error("Invalid export file name")]
+    InvalidFileName(PathBuf),
+    #[error("Invalid Ed25519 public key")]
+    InvalidPublicKey(PathBuf),
+    #[error("Missing manifest signature")]
+    MissingSignature(PathBuf),
+    #[error("Invalid manifest signature")]
+    InvalidSignature(PathBuf),
+    #[error("Input isn't listed in the manifest")]
+    Unlisted(PathBuf),
+    #[error("Input doesn't match its signed manifest")]
+    Unverified(Mismatch),
+}
+
+/// Suffix of the manifest written next to an export file.
+pub const MANIFEST_SUFFIX: &str = ".manifest.json";
+
+/// Suffix of the detached Ed25519 signature of a manifest.
+pub const SIGNATURE_SUFFIX: &str = ".sig";
+
+/// A description of an export that consumers can check it against before importing it.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Manifest {
+    pub created: DateTime<Utc>,
+    /// RocksDB sequence number of the account table when the export started
+    pub source_sequence: u64,
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ManifestFile {
+    /// Path relative to the manifest's directory
+    pub name: String,
+    /// Number of lines (every export format writes one record per line)
+    pub records: u64,
+    pub bytes: u64,
+    /// Lowercase hex SHA-256 digest of the file's contents
+    pub sha256: String,
+}
+
+impl ManifestFile {
+    fn describe(path: &Path, name: String) -> Result<Self, Error> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut hasher = Sha256::new();
+        let mut records = 0;
+        let mut bytes = 0;
+
+        loop {
+            let buffer = reader.fill_buf()?;
+
+            if buffer.is_empty() {
+                break;
+            }
+
+            hasher.update(buffer);
+            records += buffer.iter().filter(|byte| **byte == b'\n').count() as u64;
+            bytes += buffer.len() as u64;
+
+            let len = buffer.len();
+            reader.consume(len);
+        }
+
+        Ok(Self {
+            name,
+            records,
+            bytes,
+            sha256: hasher
+                .finalize()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+        })
+    }
+}
+
+/// The manifest path for an export file.
+pub fn manifest_path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_os_string();
+    path.push(MANIFEST_SUFFIX);
+    PathBuf::from(path)
+}
+
+/// The sequence number to record for an export from the database.
+pub fn source_sequence<M>(db: &Database<M>) -> u64 {
+    db.accounts.underlying().latest_sequence_number()
+}
+
+/// Describe a finished export file in a manifest next to it, returning the manifest.
+pub fn write_manifest(output: &Path, source_sequence: u64) -> Result<Manifest, Error> {
+    let name = output
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| Error::InvalidFileName(output.to_path_buf()))?;
+
+    write_manifest_for(&manifest_path(output), &[name], source_sequence)
+}
+
+/// Describe export files (named relative to the manifest's directory) in a manifest.
+pub fn write_manifest_for(
+    path: &Path,
+    names: &[&str],
+    source_sequence: u64,
+) -> Result<Manifest, Error> {
+    let base = path.parent().unwrap_or(Path::new("."));
+
+    let manifest = Manifest {
+        created: Utc::now(),
+        source_sequence,
+        files: names
+            .iter()
+            .map(|name| ManifestFile::describe(&base.join(name), name.to_string()))
+            .collect::<Result<_, _>>()?,
+    };
+
+    let temporary = path.with_extension("json.tmp");
+    std::fs::write(&temporary, serde_json::to_vec_pretty(&manifest)?)?;
+    std::fs::rename(temporary, path)?;
+
+    Ok(manifest)
+}
+
+/// A way that an export file doesn't match its manifest.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Mismatch {
+    pub name: String,
+    pub problem: &'static str,
+}
+
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.name, self.problem)
+    }
+}
+
+/// Check the files listed in a manifest, returning the manifest and any mismatches.
+pub fn verify(manifest_path: &Path) -> Result<(Manifest, Vec<Mismatch>), Error> {
+    let manifest: Manifest = serde_json::from_reader(BufReader::new(File::open(manifest_path)?))?;
+    let base = manifest_path.parent().unwrap_or(Path::new("."));
+    let mut mismatches = vec![];
+
+    for expected in &manifest.files {
+        let mismatch = |problem| Mismatch {
+            name: expected.name.clone(),
+            problem,
+        };
+        let path = base.join(&expected.name);
+
+        if !path.is_file() {
+            mismatches.push(mismatch("missing"));
+            continue;
+        }
+
+        let actual = ManifestFile::describe(&path, expected.name.clone())?;
+
+        if actual.bytes != expected.bytes {
+            mismatches.push(mismatch("size differs"));
+        } else if actual.sha256 != expected.sha256 {
+            mismatches.push(mismatch("SHA-256 digest differs"));
+        } else if actual.records != expected.records {
+            mismatches.push(mismatch("record count differs"));
+        }
+    }
+
+    Ok((manifest, mismatches))
+}
+
+/// Read an Ed25519 public key in PEM format (as written by `openssl pkey -pubout`).
+pub fn read_public_key(path: &Path) -> Result<VerifyingKey, Error> {
+    let pem = std::fs::read_to_string(path)?;
+
+    VerifyingKey::from_public_key_pem(&pem).map_err(|_| Error::InvalidPublicKey(path.to_path_buf()))
+}
+
+/// Check that an input file is described by a manifest signed with the given key, before any of
+/// it is imported.
+///
+/// The manifest defaults to the one an export writes next to the file. Its signature is read from
+/// the manifest path with `.sig` appended, and must be the 64 raw bytes of an Ed25519 signature of
+/// the manifest file (as written by `openssl pkeyutl -sign -rawin`).
+pub fn verify_signed(
+    input: &Path,
+    manifest: Option<&Path>,
+    public_key: &VerifyingKey,
+) -> Result<Manifest, Error> {
+    let manifest_path = manifest
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| manifest_path(input));
+
+    let mut signature_path = manifest_path.as_os_str().to_os_string();
+    signature_path.push(SIGNATURE_SUFFIX);
+    let signature_path = PathBuf::from(signature_path);
+
+    let signature = match std::fs::read(&signature_path) {
+        Ok(signature) => signature,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::MissingSignature(signature_path))
+        }
+        Err(error) => return Err(error.into()),
+    };
+    let signature = Signature::from_slice(&signature)
+        .map_err(|_| Error::InvalidSignature(signature_path.clone()))?;
+
+    let contents = std::fs::read(&manifest_path)?;
+    public_key
+        .verify_strict(&contents, &signature)
+        .map_err(|_| Error::InvalidSignature(signature_path))?;
+
+    let manifest: Manifest = serde_json::from_slice(&contents)?;
+    let base = manifest_path.parent().unwrap_or(Path::new("."));
+    let expected = manifest
+        .files
+        .iter()
+        .find(|file| same_file(&base.join(&file.name), input))
+        .ok_or_else(|| Error::Unlisted(input.to_path_buf()))?;
+
+    let actual = ManifestFile::describe(input, expected.name.clone())?;
+
+    let problem = if actual.bytes != expected.bytes {
+        Some("size differs")
+    } else if actual.sha256 != expected.sha256 {
+        Some("SHA-256 digest differs")
+    } else {
+        None
+    };
+
+    if let Some(problem) = problem {
+        return Err(Error::Unverified(Mismatch {
+            name: expected.name.clone(),
+            problem,
+        }));
+    }
+
+    Ok(manifest)
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Replaces user IDs and screen names with keyed hashes.
+///
+/// The same key always produces the same output, so anonymized exports made with a shared key can
+/// be compared, but the original values cannot be recovered (or confirmed by guessing) without it.
+/// Screen names are lowercased before hashing, so case variants of a screen name (which the
+/// screen name index treats as the same) hash identically.
+#[derive(Clone)]
+pub struct Anonymizer {
+    mac: Hmac<Sha256>,
+}
+
+impl Anonymizer {
+    pub fn new(key: &[u8]) -> Result<Self, Error> {
+        if key.is_empty() {
+            return Err(Error::EmptyKey);
+        }
+
+        Ok(Self {
+            // HMAC accepts keys of any length
+            mac: Hmac::new_from_slice(key).unwrap(),
+        })
+    }
+
+    pub fn id(&self, id: u64) -> String {
+        self.digest(b"id", id.to_string().as_bytes())
+    }
+
+    pub fn screen_name(&self, screen_name: &str) -> String {
+        self.digest(b"screen_name", screen_name.to_lowercase().as_bytes())
+    }
+
+    /// Values are prefixed with their kind, so that an ID and a numeric screen name never share a
+    /// hash.
+    fn digest(&self, kind: &[u8], value: &[u8]) -> String {
+        let mut mac = self.mac.clone();
+        mac.update(kind);
+        mac.update(b":");
+        mac.update(value);
+
+        mac.finalize().into_bytes()[..DIGEST_LEN]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+#[derive(Serialize)]
+struct AccountRecord {
+    id: Value,
+    screen_names: Vec<PairRecord>,
+}
+
+#[derive(Serialize)]
+struct PairRecord {
+    screen_name: String,
+    dates: Vec<NaiveDate>,
+}
+
+/// Write one JSON object per account, with its screen names and observation dates.
+///
+/// If an anonymizer is provided, IDs and screen names are replaced by their hashes, while the
+/// grouping of screen names by account and all dates are preserved. Accounts are written in ID
+/// order, starting after `after` if it's given, and the export stops between accounts if a
+/// shutdown is requested.
+///
+/// Returns the number of accounts written and, if the export was stopped, the ID of the last
+/// account written (which can be passed as `after` to resume).
+pub fn write_json<W: Write>(
+    db: &Database<ReadOnly>,
+    anonymizer: Option<&Anonymizer>,
+    after: Option<u64>,
+    shutdown: &Shutdown,
+    mut writer: W,
+) -> Result<(usize, Option<u64>), Error> {
+    let mut count = 0;
+    let mut current: Option<(u64, Vec<PairRecord>)> = None;
+
+    let pairs = match after {
+        Some(after) => match after.checked_add(1) {
+            Some(first) => db.accounts.pairs_from(first),
+            None => return Ok((0, None)),
+        },
+        None => db.accounts.pairs(),
+    };
+
+    for pair in pairs {
+        let (id, screen_name, dates) = pair?;
+
+        let screen_name = match anonymizer {
+            Some(anonymizer) => anonymizer.screen_name(&screen_name),
+            None => screen_name,
+        };
+
+        match current.as_mut() {
+            Some((current_id, screen_names)) if *current_id == id => {
+                screen_names.push(PairRecord { screen_name, dates });
+            }
+            _ => {
+                if let Some((current_id, screen_names)) =
+                    current.replace((id, vec![PairRecord { screen_name, dates }]))
+                {
+                    write_account(&mut writer, anonymizer, current_id, screen_names)?;
+                    count += 1;
+
+                    if shutdown.requested() {
+                        writer.flush()?;
+                        return Ok((count, Some(current_id)));
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some((id, screen_names)) = current {
+        write_account(&mut writer, anonymizer, id, screen_names)?;
+        count += 1;
+    }
+
+    writer.flush()?;
+
+    Ok((count, None))
+}
+
+fn write_account<W: Write>(
+    writer: &mut W,
+    anonymizer: Option<&Anonymizer>,
+    id: u64,
+    screen_names: Vec<PairRecord>,
+) -> Result<(), Error> {
+    let id = match anonymizer {
+        Some(anonymizer) => Value::from(anonymizer.id(id)),
+        None => Value::from(id),
+    };
+
+    serde_json::to_writer(&mut *writer, &AccountRecord { id, screen_names })?;
+    writeln!(writer)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memory_lol::db::Writeable;
+
+    fn export(anonymizer: Option<&Anonymizer>) -> Vec<Value> {
+        let dir = tempfile::tempdir().unwrap();
+
+        {
+            let db = Database::<Writeable>::open(dir.path()).unwrap();
+            let date = |day| NaiveDate::from_ymd_opt(2020, 1, day).unwrap();
+
+            db.insert(1, "foo", vec![date(1), date(2)]).unwrap();
+            db.insert(1, "bar", vec![date(3)]).unwrap();
+            db.insert(2, "FOO", vec![date(4)]).unwrap();
+        }
+
+        let db = Database::<ReadOnly>::open(dir.path()).unwrap();
+        let mut output = vec![];
+        assert_eq!(
+            write_json(&db, anonymizer, None, &Shutdown::default(), &mut output).unwrap(),
+            (2, None)
+        );
+
+        output
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn anonymize() {
+        let plain = export(None);
+        let anonymizer = Anonymizer::new(b"secret").unwrap();
+        let anonymized = export(Some(&anonymizer));
+
+        assert_eq!(plain[0]["id"], 1);
+        assert_eq!(plain[0]["screen_names"][0]["screen_name"], "bar");
+        assert_eq!(plain[0]["screen_names"][1]["dates"][1], "2020-01-02");
+
+        // Structure and dates are preserved
+        assert_eq!(anonymized.len(), plain.len());
+        for (plain, anonymized) in plain.iter().zip(&anonymized) {
+            let plain_pairs = plain["screen_names"].as_array().unwrap();
+            let anonymized_pairs = anonymized["screen_names"].as_array().unwrap();
+
+            assert_eq!(anonymized_pairs.len(), plain_pairs.len());
+            for (plain, anonymized) in plain_pairs.iter().zip(anonymized_pairs) {
+                assert_eq!(anonymized["dates"], plain["dates"]);
+            }
+        }
+
+        // Hashing is consistent within and across exports, and case-insensitive for screen names
+        assert_eq!(anonymized[0]["id"], anonymizer.id(1));
+        assert_eq!(
+            anonymized[0]["screen_names"][1]["screen_name"],
+            anonymized[1]["screen_names"][0]["screen_name"]
+        );
+        assert_eq!(export(Some(&anonymizer)), anonymized);
+        assert!(!serde_json::to_string(&anonymized).unwrap().contains("foo"));
+
+        // A different key produces different hashes
+        let other = Anonymizer::new(b"other").unwrap();
+        assert_ne!(other.id(1), anonymizer.id(1));
+        assert_ne!(anonymizer.id(1), anonymizer.screen_name("1"));
+        assert!(Anonymizer::new(b"").is_err());
+    }
+
+    #[test]
+    fn manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("accounts.ndjson");
+        std::fs::write(&output, "{\"id\":1}\n{\"id\":2}\n").unwrap();
+
+        let manifest = write_manifest(&output, 42).unwrap();
+        assert_eq!(manifest.source_sequence, 42);
+        assert_eq!(manifest.files[0].name, "accounts.ndjson");
+        assert_eq!(manifest.files[0].records, 2);
+        assert_eq!(manifest.files[0].bytes, 18);
+
+        let path = dir.path().join("accounts.ndjson.manifest.json");
+        assert_eq!(verify(&path).unwrap(), (manifest, vec![]));
+
+        std::fs::write(&output, "{\"id\":1}\n{\"id\":3}\n").unwrap();
+        assert_eq!(
+            verify(&path).unwrap().1,
+            vec![Mismatch {
+                name: "accounts.ndjson".to_string(),
+                problem: "SHA-256 digest differs"
+            }]
+        );
+
+        std::fs::remove_file(&output).unwrap();
+        assert_eq!(verify(&path).unwrap().1[0].problem, "missing");
+    }
+
+    #[test]
+    fn signed_manifest() {
+        use ed25519_dalek::{pkcs8::EncodePublicKey, Signer, SigningKey};
+
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("accounts.ndjson");
+        std::fs::write(&output, "{\"id\":1}\n").unwrap();
+        write_manifest(&output, 1).unwrap();
+
+        let manifest = manifest_path(&output);
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let key_path = dir.path().join("key.pub");
+        signing_key
+            .verifying_key()
+            .write_public_key_pem_file(&key_path, Default::default())
+            .unwrap();
+        let public_key = read_public_key(&key_path).unwrap();
+
+        let signature_path = dir.path().join("accounts.ndjson.manifest.json.sig");
+        assert!(matches!(
+            verify_signed(&output, None, &public_key),
+            Err(Error::MissingSignature(path)) if path == signature_path
+        ));
+
+        let signature = signing_key.sign(&std::fs::read(&manifest).unwrap());
+        std::fs::write(&signature_path, signature.to_bytes()).unwrap();
+        assert_eq!(
+            verify_signed(&output, None, &public_key).unwrap().files[0].records,
+            1
+        );
+
+        // Tampering with the input or the manifest is detected
+        std::fs::write(&output, "{\"id\":2}\n").unwrap();
+        assert!(matches!(
+            verify_signed(&output, None, &public_key),
+            Err(Error::Unverified(_))
+        ));
+
+        let other = dir.path().join("other.ndjson");
+        std::fs::write(&other, "{\"id\":1}\n").unwrap();
+        assert!(matches!(
+            verify_signed(&other, Some(&manifest), &public_key),
+            Err(Error::Unlisted(_))
+        ));
+
+        write_manifest(&output, 1).unwrap();
+        assert!(matches!(
+            verify_signed(&output, None, &public_key),
+            Err(Error::InvalidSignature(_))
+        ));
+    }
+
+    #[test]
+    fn interrupt_and_resume() {
+        let dir = tempfile::tempdir().unwrap();
+
+        {
+            let db = Database::<Writeable>::open(dir.path()).unwrap();
+
+            for id in 1..=3 {
+                db.insert(id, &format!("user{}", id), vec![]).unwrap();
+            }
+        }
+
+        let db = Database::<ReadOnly>::open(dir.path()).unwrap();
+        let shutdown = Shutdown::default();
+        shutdown.request();
+
+        let mut output = vec![];
+        assert_eq!(
+            write_json(&db, None, None, &shutdown, &mut output).unwrap(),
+            (1, Some(1))
+        );
+        assert_eq!(
+            write_json(&db, None, Some(1), &Shutdown::default(), &mut output).unwrap(),
+            (2, None)
+        );
+        assert_eq!(output.iter().filter(|byte| **byte == b'\n').count(), 3);
+    }
+}
@@ -0,0 +1,335 @@
+use crate::events::Event;
+use crate::http::{Connection, Request, Response};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use memory_lol::db::{tags::TagTable, ReadOnly};
+use regex::{Regex, RegexBuilder};
+use sha1::{Digest, Sha1};
+use std::collections::HashSet;
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Appended to a client's key to compute the handshake's accept key (RFC 6455).
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Subscribers are pinged (or sent a comment, for server-sent events) this often when there are
+/// no events, so that dead connections are noticed.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+/// Events are dropped for subscribers that fall this far behind.
+const SUBSCRIBER_BUFFER: usize = 1024;
+
+const TEXT_OPCODE: u8 = 0x1;
+const PING_OPCODE: u8 = 0x9;
+
+/// Which events a subscriber receives.
+///
+/// An event has to match every filter given.
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// Screen names matching a regular expression (case-insensitive)
+    pattern: Option<Regex>,
+    /// Accounts with a tag (including previous holders of a handle that changed hands)
+    watchlist: Option<HashSet<u64>>,
+}
+
+impl Filter {
+    pub fn matches(&self, event: &Event) -> bool {
+        self.pattern
+            .as_ref()
+            .is_none_or(|pattern| pattern.is_match(event.screen_name()))
+            && self.watchlist.as_ref().is_none_or(|ids| {
+                ids.contains(&event.id())
+                    || matches!(
+                        event,
+                        Event::HandleChangedHands { previous_ids, .. }
+                            if previous_ids.iter().any(|id| ids.contains(id))
+                    )
+            })
+    }
+}
+
+/// Fans the events that imports post to a server out to its WebSocket subscribers.
+pub struct Feed {
+    subscribers: Mutex<Vec<SyncSender<Arc<Event>>>>,
+    /// Tags that subscribers can use as watchlists
+    tags: Option<TagTable<ReadOnly>>,
+}
+
+impl Feed {
+    pub fn new(tags: Option<TagTable<ReadOnly>>) -> Self {
+        Self {
+            subscribers: Mutex::new(vec![]),
+            tags,
+        }
+    }
+
+    /// Send events to every subscriber (forgetting subscribers that have disconnected).
+    pub fn publish(&self, events: Vec<Event>) {
+        let mut subscribers = self
+            .subscribers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        for event in events {
+            let event = Arc::new(event);
+
+            subscribers.retain(|subscriber| match subscriber.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    log::warn!("Dropped an event for a subscriber that isn't keeping up");
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+        }
+    }
+
+    pub fn subscribe(&self) -> Receiver<Arc<Event>> {
+        let (sender, receiver) = sync_channel(SUBSCRIBER_BUFFER);
+
+        self.subscribers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(sender);
+
+        receiver
+    }
+
+    /// Read a subscriber's filter from the `pattern` and `watchlist` (tag) query parameters.
+    pub fn filter(&self, request: &Request) -> Result<Filter, Response> {
+        let pattern = request
+            .query_param("pattern")
+            .map(|pattern| {
+                RegexBuilder::new(&pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|_| Response::status(400))
+            })
+            .transpose()?;
+
+        let watchlist = match request.query_param("watchlist") {
+            Some(tag) => match &self.tags {
+                Some(tags) => Some(
+                    tags.members(&tag)
+                        .map_err(|_| Response::status(400))?
+                        .into_iter()
+                        .collect(),
+                ),
+                None => return Err(Response::status(404)),
+            },
+            None => None,
+        };
+
+        Ok(Filter { pattern, watchlist })
+    }
+}
+
+/// The `Sec-WebSocket-Accept` value for a client's `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.trim().as_bytes());
+    hasher.update(WEBSOCKET_GUID.as_bytes());
+
+    STANDARD.encode(hasher.finalize())
+}
+
+/// Complete a WebSocket handshake and send each matching event as a JSON text message until the
+/// client goes away.
+pub fn stream_websocket(
+    key: &str,
+    filter: &Filter,
+    events: Receiver<Arc<Event>>,
+    connection: &mut dyn Connection,
+) -> std::io::Result<()> {
+    write!(
+        connection,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    )?;
+    connection.flush()?;
+
+    forward(
+        filter,
+        events,
+        connection,
+        |connection, event| write_frame(connection, TEXT_OPCODE, event),
+        |connection| write_frame(connection, PING_OPCODE, &[]),
+    )
+}
+
+/// Send each matching event as a server-sent event with JSON data until the client goes away.
+///
+/// The headers (for example for CORS) are added to the response.
+pub fn stream_sse(
+    filter: &Filter,
+    events: Receiver<Arc<Event>>,
+    headers: &[(&str, String)],
+    connection: &mut dyn Connection,
+) -> std::io::Result<()> {
+    write!(
+        connection,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n"
+    )?;
+    for (name, value) in headers {
+        write!(connection, "{}: {}\r\n", name, value)?;
+    }
+    connection.write_all(b"\r\n")?;
+    connection.flush()?;
+
+    forward(
+        filter,
+        events,
+        connection,
+        |connection, event| {
+            // Serialized JSON doesn't contain newlines, so it fits on a single data line
+            connection.write_all(b"data: ")?;
+            connection.write_all(event)?;
+            connection.write_all(b"\n\n")?;
+            connection.flush()
+        },
+        |connection| {
+            connection.write_all(b": ping\n\n")?;
+            connection.flush()
+        },
+    )
+}
+
+/// Send each matching event (serialized as JSON) to a subscriber, and keep the connection alive
+/// while there aren't any, until the client goes away or the feed is dropped.
+fn forward<S, I>(
+    filter: &Filter,
+    events: Receiver<Arc<Event>>,
+    connection: &mut dyn Connection,
+    mut send: S,
+    mut idle: I,
+) -> std::io::Result<()>
+where
+    S: FnMut(&mut dyn Connection, &[u8]) -> std::io::Result<()>,
+    I: FnMut(&mut dyn Connection) -> std::io::Result<()>,
+{
+    loop {
+        match events.recv_timeout(PING_INTERVAL) {
+            Ok(event) => {
+                if filter.matches(&event) {
+                    send(connection, &serde_json::to_vec(&*event)?)?;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => idle(connection)?,
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+/// Write an unmasked, unfragmented frame (as servers send them).
+fn write_frame(connection: &mut dyn Connection, opcode: u8, payload: &[u8]) -> std::io::Result<()> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+
+    frame.extend_from_slice(payload);
+    connection.write_all(&frame)?;
+    connection.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn accept_keys() {
+        // The example from RFC 6455
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn filters() {
+        let date = NaiveDate::from_ymd_opt(2022, 1, 1).unwrap();
+        let events = Event::for_new_pair(2, "FooBar", date, false, &[1, 2]);
+        let filter = |pattern: Option<&str>, watchlist: Option<Vec<u64>>| Filter {
+            pattern: pattern.map(|pattern| {
+                RegexBuilder::new(pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .unwrap()
+            }),
+            watchlist: watchlist.map(|ids| ids.into_iter().collect()),
+        };
+
+        assert!(filter(None, None).matches(&events[0]));
+        assert!(filter(Some("^foo"), None).matches(&events[0]));
+        assert!(!filter(Some("^bar"), None).matches(&events[0]));
+        assert!(filter(Some("bar$"), Some(vec![2])).matches(&events[0]));
+        assert!(!filter(Some("bar$"), Some(vec![3])).matches(&events[0]));
+
+        // The previous holder of a handle is watching it change hands
+        assert!(!filter(None, Some(vec![1])).matches(&events[0]));
+        assert!(filter(None, Some(vec![1])).matches(&events[1]));
+    }
+
+    /// A feed's events for two accounts, and a filter that only matches the second.
+    fn subscription() -> (Receiver<Arc<Event>>, Filter) {
+        let feed = Feed::new(None);
+        let events = feed.subscribe();
+        let date = NaiveDate::from_ymd_opt(2022, 1, 1).unwrap();
+
+        feed.publish(Event::for_new_pair(1, "foo", date, true, &[]));
+        feed.publish(Event::for_new_pair(2, "bar", date, true, &[]));
+
+        let filter = Filter {
+            pattern: Some(Regex::new("^b").unwrap()),
+            watchlist: None,
+        };
+
+        (events, filter)
+    }
+
+    #[test]
+    fn stream_websocket_events() {
+        let (events, filter) = subscription();
+        let mut connection = std::io::Cursor::new(vec![]);
+        stream_websocket("dGhlIHNhbXBsZSBub25jZQ==", &filter, events, &mut connection).unwrap();
+
+        let output = connection.into_inner();
+        let body_start = output
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .unwrap()
+            + 4;
+        let payload = br#"{"event":"new_account","id":2,"screen_name":"bar","date":"2022-01-01"}"#;
+
+        assert!(output.starts_with(b"HTTP/1.1 101 Switching Protocols\r\n"));
+        assert_eq!(output[body_start], 0x81);
+        assert_eq!(output[body_start + 1] as usize, payload.len());
+        assert_eq!(&output[body_start + 2..], payload);
+    }
+
+    #[test]
+    fn stream_sse_events() {
+        let (events, filter) = subscription();
+        let headers = [("Access-Control-Allow-Origin", "*".to_string())];
+        let mut connection = std::io::Cursor::new(vec![]);
+        stream_sse(&filter, events, &headers, &mut connection).unwrap();
+
+        assert_eq!(
+            String::from_utf8(connection.into_inner()).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\
+             Access-Control-Allow-Origin: *\r\n\r\n\
+             data: {\"event\":\"new_account\",\"id\":2,\"screen_name\":\"bar\",\"date\":\"2022-01-01\"}\n\n"
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use chrono::{Duration, NaiveDate};
+use memory_lol::db::{Database, Writeable};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::collections::HashSet;
+
+/// Probability that a rename picks up a screen name another account has given up.
+const REUSE_PROBABILITY: f64 = 0.05;
+/// Probability that an account shares its first screen name with another account over the same
+/// days, which the conflict finder should report.
+const COLLISION_PROBABILITY: f64 = 0.01;
+const MAX_SCREEN_NAMES: usize = 4;
+const SYLLABLES: [&str; 16] = [
+    "al", "be", "ca", "do", "el", "fi", "go", "ha", "io", "ju", "ka", "lo", "mu", "no", "pi", "ra",
+];
+
+/// Counts for a generated fixture.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct FixtureReport {
+    pub accounts: usize,
+    pub pairs: usize,
+    pub renames: usize,
+    pub reused_screen_names: usize,
+    pub collisions: usize,
+}
+
+impl std::fmt::Display for FixtureReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Accounts: {}", self.accounts)?;
+        writeln!(f, "Pairs: {}", self.pairs)?;
+        writeln!(f, "Renames: {}", self.renames)?;
+        writeln!(f, "Reused screen names: {}", self.reused_screen_names)?;
+        write!(f, "Collisions: {}", self.collisions)
+    }
+}
+
+/// Deterministically populate a database with synthetic accounts.
+///
+/// Each account has one or more screen names over consecutive date ranges (recorded as first and
+/// last dates). Some renames pick up screen names that earlier accounts have released, and a few
+/// accounts share a screen name with another account over the same days. The same seed and
+/// account count always produce the same pairs.
+pub fn generate(
+    db: &Database<Writeable>,
+    accounts: usize,
+    seed: u64,
+) -> Result<FixtureReport, memory_lol::db::Error> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut report = FixtureReport::default();
+    let mut ids = HashSet::new();
+    let mut used = HashSet::new();
+    // Screen names released by their accounts, with the day they became available
+    let mut released: Vec<(String, NaiveDate)> = vec![];
+    let mut first_screen_names: Vec<(String, NaiveDate, NaiveDate)> = vec![];
+    let start = NaiveDate::from_ymd_opt(2010, 1, 1).unwrap();
+    let total_days = (NaiveDate::from_ymd_opt(2022, 12, 31).unwrap() - start).num_days();
+
+    while report.accounts < accounts {
+        let id = random_id(&mut rng);
+
+        if !ids.insert(id) {
+            continue;
+        }
+
+        let count = rng.gen_range(1..=MAX_SCREEN_NAMES);
+        let mut boundaries = (0..count + 1)
+            .map(|_| rng.gen_range(0..=total_days))
+            .collect::<Vec<_>>();
+        boundaries.sort_unstable();
+
+        for (i, window) in boundaries.windows(2).enumerate() {
+            let first = start + Duration::days(window[0]);
+            let last = start + Duration::days(window[1]);
+
+            let (screen_name, first, last) = if i == 0
+                && !first_screen_names.is_empty()
+                && rng.gen_bool(COLLISION_PROBABILITY)
+            {
+                // Take another account's first screen name along with its dates
+                report.collisions += 1;
+                first_screen_names.choose(&mut rng).unwrap().clone()
+            } else if i == 0 {
+                let screen_name = new_screen_name(&mut rng, &mut used);
+                first_screen_names.push((screen_name.clone(), first, last));
+                (screen_name, first, last)
+            } else {
+                report.renames += 1;
+
+                let reusable = released
+                    .iter()
+                    .position(|(_, available)| *available < first);
+
+                match reusable.filter(|_| rng.gen_bool(REUSE_PROBABILITY)) {
+                    Some(index) => {
+                        report.reused_screen_names += 1;
+                        (released.swap_remove(index).0, first, last)
+                    }
+                    None => (new_screen_name(&mut rng, &mut used), first, last),
+                }
+            };
+
+            db.insert(id, &screen_name, vec![first, last])?;
+            report.pairs += 1;
+
+            if i + 1 < count {
+                released.push((screen_name, last));
+            }
+        }
+
+        report.accounts += 1;
+    }
+
+    Ok(report)
+}
+
+/// IDs are a mix of small sequential-era IDs and large Snowflake-era IDs.
+fn random_id(rng: &mut StdRng) -> u64 {
+    if rng.gen_bool(0.5) {
+        rng.gen_range(1..1_500_000_000)
+    } else {
+        rng.gen_range(700_000_000_000_000_000..1_700_000_000_000_000_000)
+    }
+}
+
+fn new_screen_name(rng: &mut StdRng, used: &mut HashSet<String>) -> String {
+    loop {
+        let syllables = rng.gen_range(2..=4);
+        let mut screen_name = (0..syllables)
+            .map(|_| *SYLLABLES.choose(rng).unwrap())
+            .collect::<String>();
+
+        match rng.gen_range(0..3) {
+            0 => screen_name.push_str(&rng.gen_range(0..10_000).to_string()),
+            1 => screen_name.push('_'),
+            _ => {}
+        }
+
+        if used.insert(screen_name.to_lowercase()) {
+            return screen_name;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memory_lol::db::util::is_valid_screen_name;
+
+    fn pairs(accounts: usize, seed: u64) -> (FixtureReport, Vec<(u64, String, Vec<NaiveDate>)>) {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::<Writeable>::open(dir.path()).unwrap();
+        let report = generate(&db, accounts, seed).unwrap();
+        let pairs = db.accounts.pairs().collect::<Result<Vec<_>, _>>().unwrap();
+
+        (report, pairs)
+    }
+
+    #[test]
+    fn deterministic() {
+        let (report, first) = pairs(1000, 42);
+        let (_, second) = pairs(1000, 42);
+        let (_, other) = pairs(1000, 43);
+
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+        assert_eq!(report.accounts, 1000);
+        assert_eq!(report.pairs, first.len());
+        assert!(report.renames > 0);
+        assert!(report.reused_screen_names > 0);
+        assert!(report.collisions > 0);
+        assert!(first
+            .iter()
+            .all(|(_, screen_name, dates)| is_valid_screen_name(screen_name)
+                && screen_name.len() <= 15
+                && dates.len() <= 2));
+    }
+}
@@ -0,0 +1,408 @@
+//! An Arrow Flight service for bulk analytical reads.
+//!
+//! Clients like `pyarrow.flight` can fetch the whole dataset, or the results of a lookup, as a
+//! stream of Arrow record batches (for example straight into a pandas or Polars data frame),
+//! without going through an NDJSON export.
+//!
+//! Tickets (and command descriptors) are UTF-8 strings: `pairs` for every pair in the database,
+//! in user ID order, or `lookup:<queries>` for a comma-separated mix of user IDs and screen names
+//! (as for the `lookup` command).
+use crate::lookup;
+use arrow_array::{
+    builder::{Date32Builder, ListBuilder, StringBuilder, UInt64Builder},
+    ArrayRef, RecordBatch,
+};
+use arrow_flight::{
+    encode::FlightDataEncoderBuilder, error::FlightError, flight_service_server::FlightService,
+    flight_service_server::FlightServiceServer, Action, ActionType, Criteria, Empty, FlightData,
+    FlightDescriptor, FlightEndpoint, FlightInfo, HandshakeRequest, HandshakeResponse, PollInfo,
+    PutResult, SchemaAsIpc, SchemaResult, Ticket,
+};
+use arrow_ipc::writer::IpcWriteOptions;
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use chrono::{Datelike, NaiveDate};
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
+use memory_lol::db::{Database, ReadOnly};
+use std::sync::Arc;
+use tokio::sync::mpsc::channel;
+use tonic::{Request, Response, Status, Streaming};
+
+/// Rows per record batch.
+const BATCH_SIZE: usize = 8192;
+/// Batches that can be built ahead of a slow client.
+const BUFFERED_BATCHES: usize = 4;
+
+/// Days from the start of the common era to 1970-01-01 (Arrow dates count from the latter).
+const UNIX_EPOCH_DAYS_FROM_CE: i32 = 719_163;
+
+const PAIRS_TICKET: &str = "pairs";
+const LOOKUP_PREFIX: &str = "lookup:";
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Transport error")]
+    Transport(#[from] tonic::transport::Error),
+    #[error("Invalid address")]
+    Address(#[from] std::net::AddrParseError),
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+}
+
+/// What a ticket asks for.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Read {
+    Pairs,
+    Lookup(String),
+}
+
+impl Read {
+    fn parse(ticket: &[u8]) -> Result<Self, Status> {
+        match std::str::from_utf8(ticket) {
+            Ok(PAIRS_TICKET) => Ok(Read::Pairs),
+            Ok(ticket) => match ticket.strip_prefix(LOOKUP_PREFIX) {
+                Some(queries) => Ok(Read::Lookup(queries.to_string())),
+                None => Err(Status::invalid_argument("Unknown ticket")),
+            },
+            Err(_) => Err(Status::invalid_argument("Ticket is not UTF-8")),
+        }
+    }
+
+    /// Lookup results are labeled with the query (as given) that they were found for.
+    fn schema(&self) -> SchemaRef {
+        let mut fields = vec![];
+
+        if let Read::Lookup(_) = self {
+            fields.push(Field::new("query", DataType::Utf8, false));
+        }
+
+        fields.extend([
+            Field::new("id", DataType::UInt64, false),
+            Field::new("screen_name", DataType::Utf8, false),
+            Field::new(
+                "dates",
+                DataType::List(Arc::new(Field::new("item", DataType::Date32, true))),
+                false,
+            ),
+        ]);
+
+        Arc::new(Schema::new(fields))
+    }
+}
+
+/// Accumulates rows into record batches.
+struct BatchBuilder {
+    schema: SchemaRef,
+    query: Option<StringBuilder>,
+    id: UInt64Builder,
+    screen_name: StringBuilder,
+    dates: ListBuilder<Date32Builder>,
+    len: usize,
+}
+
+impl BatchBuilder {
+    fn new(schema: SchemaRef) -> Self {
+        Self {
+            query: schema
+                .column_with_name("query")
+                .map(|_| StringBuilder::new()),
+            schema,
+            id: UInt64Builder::new(),
+            screen_name: StringBuilder::new(),
+            dates: ListBuilder::new(Date32Builder::new()),
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, query: Option<&str>, id: u64, screen_name: &str, dates: &[NaiveDate]) {
+        if let Some(builder) = self.query.as_mut() {
+            builder.append_value(query.unwrap_or_default());
+        }
+
+        self.id.append_value(id);
+        self.screen_name.append_value(screen_name);
+
+        for date in dates {
+            self.dates.values().append_value(days_since_epoch(*date));
+        }
+        self.dates.append(true);
+
+        self.len += 1;
+    }
+
+    fn is_full(&self) -> bool {
+        self.len >= BATCH_SIZE
+    }
+
+    fn finish(&mut self) -> Result<RecordBatch, ArrowError> {
+        let mut columns: Vec<ArrayRef> = vec![];
+
+        if let Some(builder) = self.query.as_mut() {
+            columns.push(Arc::new(builder.finish()));
+        }
+
+        columns.push(Arc::new(self.id.finish()));
+        columns.push(Arc::new(self.screen_name.finish()));
+        columns.push(Arc::new(self.dates.finish()));
+        self.len = 0;
+
+        RecordBatch::try_new(self.schema.clone(), columns)
+    }
+}
+
+fn days_since_epoch(date: NaiveDate) -> i32 {
+    date.num_days_from_ce() - UNIX_EPOCH_DAYS_FROM_CE
+}
+
+/// Build the record batches a ticket asks for, passing each to `send` until it returns false
+/// (because the client has gone away).
+fn build_batches<F: FnMut(RecordBatch) -> bool>(
+    db: &Database<ReadOnly>,
+    read: &Read,
+    mut send: F,
+) -> Result<(), FlightError> {
+    let mut builder = BatchBuilder::new(read.schema());
+
+    match read {
+        Read::Pairs => {
+            for pair in db.accounts.pairs() {
+                let (id, screen_name, dates) = pair.map_err(external)?;
+                builder.push(None, id, &screen_name, &dates);
+
+                if builder.is_full() && !send(builder.finish()?) {
+                    return Ok(());
+                }
+            }
+        }
+        Read::Lookup(queries) => {
+            for result in lookup::resolve_all(db, queries).map_err(external)? {
+                for account in &result.accounts {
+                    for (screen_name, dates) in &account.screen_names {
+                        let dates = dates.as_deref().unwrap_or_default();
+                        builder.push(Some(&result.query), account.id, screen_name, dates);
+
+                        if builder.is_full() && !send(builder.finish()?) {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if builder.len > 0 {
+        send(builder.finish()?);
+    }
+
+    Ok(())
+}
+
+fn external<E: std::error::Error + Send + Sync + 'static>(error: E) -> FlightError {
+    FlightError::ExternalError(Box::new(error))
+}
+
+fn flight_info(read: &Read, descriptor: FlightDescriptor) -> Result<FlightInfo, Status> {
+    Ok(FlightInfo::new()
+        .try_with_schema(&read.schema())
+        .map_err(|error| Status::internal(error.to_string()))?
+        .with_endpoint(FlightEndpoint::new().with_ticket(Ticket::new(descriptor.cmd.clone())))
+        .with_descriptor(descriptor))
+}
+
+pub struct Service {
+    db: Arc<Database<ReadOnly>>,
+}
+
+#[tonic::async_trait]
+impl FlightService for Service {
+    type HandshakeStream = BoxStream<'static, Result<HandshakeResponse, Status>>;
+    type ListFlightsStream = BoxStream<'static, Result<FlightInfo, Status>>;
+    type DoGetStream = BoxStream<'static, Result<FlightData, Status>>;
+    type DoPutStream = BoxStream<'static, Result<PutResult, Status>>;
+    type DoActionStream = BoxStream<'static, Result<arrow_flight::Result, Status>>;
+    type ListActionsStream = BoxStream<'static, Result<ActionType, Status>>;
+    type DoExchangeStream = BoxStream<'static, Result<FlightData, Status>>;
+
+    /// The only flight that can be listed is the whole dataset.
+    async fn list_flights(
+        &self,
+        _request: Request<Criteria>,
+    ) -> Result<Response<Self::ListFlightsStream>, Status> {
+        let info = flight_info(&Read::Pairs, FlightDescriptor::new_cmd(PAIRS_TICKET))?;
+
+        Ok(Response::new(futures::stream::iter([Ok(info)]).boxed()))
+    }
+
+    async fn get_flight_info(
+        &self,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        let descriptor = request.into_inner();
+        let read = Read::parse(&descriptor.cmd)?;
+
+        Ok(Response::new(flight_info(&read, descriptor)?))
+    }
+
+    async fn get_schema(
+        &self,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<SchemaResult>, Status> {
+        let schema = Read::parse(&request.get_ref().cmd)?.schema();
+        let result = SchemaAsIpc::new(&schema, &IpcWriteOptions::default())
+            .try_into()
+            .map_err(|error: ArrowError| Status::internal(error.to_string()))?;
+
+        Ok(Response::new(result))
+    }
+
+    /// Stream the record batches for a ticket.
+    ///
+    /// Batches are built on a blocking thread (since database reads are synchronous), at most a
+    /// few ahead of the client.
+    async fn do_get(
+        &self,
+        request: Request<Ticket>,
+    ) -> Result<Response<Self::DoGetStream>, Status> {
+        let read = Read::parse(&request.get_ref().ticket)?;
+        let schema = read.schema();
+        let db = self.db.clone();
+        let (sender, receiver) = channel::<Result<RecordBatch, FlightError>>(BUFFERED_BATCHES);
+
+        tokio::task::spawn_blocking(move || {
+            if let Err(error) =
+                build_batches(&db, &read, |batch| sender.blocking_send(Ok(batch)).is_ok())
+            {
+                log::error!("Flight read error: {:?}", error);
+                let _ = sender.blocking_send(Err(error));
+            }
+        });
+
+        let batches = futures::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|batch| (batch, receiver))
+        });
+        let stream = FlightDataEncoderBuilder::new()
+            .with_schema(schema)
+            .build(batches)
+            .map_err(Status::from);
+
+        Ok(Response::new(stream.boxed()))
+    }
+
+    async fn handshake(
+        &self,
+        _request: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<Self::HandshakeStream>, Status> {
+        Err(Status::unimplemented("No authentication is required"))
+    }
+
+    async fn poll_flight_info(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<PollInfo>, Status> {
+        Err(Status::unimplemented("Flights are available immediately"))
+    }
+
+    async fn do_put(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoPutStream>, Status> {
+        Err(Status::unimplemented("The database is read-only"))
+    }
+
+    async fn do_action(
+        &self,
+        _request: Request<Action>,
+    ) -> Result<Response<Self::DoActionStream>, Status> {
+        Err(Status::unimplemented("There are no actions"))
+    }
+
+    async fn list_actions(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::ListActionsStream>, Status> {
+        Ok(Response::new(futures::stream::empty().boxed()))
+    }
+
+    async fn do_exchange(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoExchangeStream>, Status> {
+        Err(Status::unimplemented("The database is read-only"))
+    }
+}
+
+/// Serve the database until the process is stopped.
+pub fn serve(db: Database<ReadOnly>, address: &str) -> Result<(), Error> {
+    let address = address.parse()?;
+    let service = Service { db: Arc::new(db) };
+
+    log::info!("Serving Arrow Flight on {}", address);
+
+    tokio::runtime::Runtime::new()?.block_on(
+        tonic::transport::Server::builder()
+            .add_service(FlightServiceServer::new(service))
+            .serve(address),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::{
+        cast::AsArray,
+        types::{Date32Type, UInt64Type},
+    };
+    use memory_lol::db::Writeable;
+
+    #[test]
+    fn tickets() {
+        assert_eq!(Read::parse(b"pairs").unwrap(), Read::Pairs);
+        assert_eq!(
+            Read::parse(b"lookup:foo,123").unwrap(),
+            Read::Lookup("foo,123".to_string())
+        );
+        assert!(Read::parse(b"everything").is_err());
+        assert_eq!(Read::Pairs.schema().fields().len(), 3);
+        assert_eq!(Read::Lookup(String::new()).schema().fields().len(), 4);
+    }
+
+    #[test]
+    fn batches() {
+        let dir = tempfile::tempdir().unwrap();
+        let date = |day| NaiveDate::from_ymd_opt(1970, 1, day).unwrap();
+
+        {
+            let db = Database::<Writeable>::open(dir.path()).unwrap();
+            db.insert(1, "foo", vec![date(2), date(3)]).unwrap();
+            db.insert(2, "bar", vec![date(4)]).unwrap();
+        }
+
+        let db = Database::<ReadOnly>::open(dir.path()).unwrap();
+        let mut batches = vec![];
+        build_batches(&db, &Read::Pairs, |batch| {
+            batches.push(batch);
+            true
+        })
+        .unwrap();
+
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].num_rows(), 2);
+        let dates = batches[0].column(2).as_list::<i32>().value(0);
+        assert_eq!(dates.as_primitive::<Date32Type>().values(), &[1, 2]);
+
+        let mut batches = vec![];
+        build_batches(&db, &Read::Lookup("bar".to_string()), |batch| {
+            batches.push(batch);
+            true
+        })
+        .unwrap();
+
+        assert_eq!(batches[0].num_rows(), 1);
+        assert_eq!(batches[0].column(0).as_string::<i32>().value(0), "bar");
+        assert_eq!(
+            batches[0].column(1).as_primitive::<UInt64Type>().value(0),
+            2
+        );
+    }
+}
@@ -0,0 +1,82 @@
+use crate::Error;
+use chrono::Utc;
+use clap::Parser;
+use memory_lol::db::{
+    holds::{HoldTable, LegalHold},
+    DatabaseOptions, ReadOnly, Writeable, HOLDS_TABLE,
+};
+use std::collections::HashSet;
+use std::path::Path;
+
+#[derive(Debug, Parser)]
+pub enum HoldCommand {
+    /// Place a legal hold on a Twitter user ID, exempting its records from pruning and removal
+    Place {
+        /// Twitter user ID
+        id: u64,
+        /// Why the records must be preserved (for example a case or order reference)
+        #[clap(long)]
+        reason: String,
+    },
+    /// Release the legal hold on a Twitter user ID
+    Release {
+        /// Twitter user ID
+        id: u64,
+    },
+    /// List held accounts in CSV format
+    List,
+}
+
+pub fn run(db: &Path, options: &DatabaseOptions, command: HoldCommand) -> Result<(), Error> {
+    match command {
+        HoldCommand::Place { id, reason } => {
+            let holds = HoldTable::<Writeable>::open_with_options(db.join(HOLDS_TABLE), options)?;
+            let hold = LegalHold {
+                placed: Utc::now(),
+                reason,
+            };
+
+            holds.place(id, &hold)?;
+            log::info!("Placed legal hold on {}", id);
+        }
+        HoldCommand::Release { id } => {
+            let holds_path = db.join(HOLDS_TABLE);
+
+            if !holds_path.exists()
+                || !HoldTable::<Writeable>::open_with_options(holds_path, options)?.release(id)?
+            {
+                return Err(Error::NotFound(id.to_string()));
+            }
+
+            log::info!("Released legal hold on {}", id);
+        }
+        HoldCommand::List => {
+            if let Some(holds) = open(db, options)? {
+                for (id, hold) in holds.holds()? {
+                    println!("{},{},{}", id, hold.placed.to_rfc3339(), hold.reason);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Open the hold table, if any holds have ever been placed.
+pub fn open(db: &Path, options: &DatabaseOptions) -> Result<Option<HoldTable<ReadOnly>>, Error> {
+    let holds_path = db.join(HOLDS_TABLE);
+
+    if holds_path.exists() {
+        Ok(Some(HoldTable::open_with_options(holds_path, options)?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// The IDs of all held accounts.
+pub fn held_ids(db: &Path, options: &DatabaseOptions) -> Result<HashSet<u64>, Error> {
+    Ok(open(db, options)?
+        .map(|holds| holds.ids())
+        .transpose()?
+        .unwrap_or_default())
+}
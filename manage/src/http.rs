@@ -0,0 +1,628 @@
+use crate::encoding::Encoding;
+use chrono::{DateTime, Utc};
+use memory_lol::{cache::Validators, cors::CorsPolicy};
+use rustls::{server::WebPkiClientVerifier, RootCertStore, ServerConfig};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+/// Upper bound on the size of a request (in bytes).
+const MAX_REQUEST_LEN: usize = 1024 * 1024;
+const MAX_HEADERS: usize = 64;
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Number of threads handling connections.
+const WORKERS: usize = 16;
+/// Upper bound on the number of connections taken over by handlers at once (each has a thread of
+/// its own).
+const MAX_UPGRADES: usize = 256;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("HTTP parsing error")]
+    Parse(#[from] httparse::Error),
+    #[error("Request too large")]
+    RequestTooLarge,
+    #[error("TLS error")]
+    Tls(#[from] rustls::Error),
+    #[error("Client certificate verifier error")]
+    ClientVerifier(#[from] rustls::server::VerifierBuilderError),
+    #[error("No private key")]
+    MissingPrivateKey(PathBuf),
+}
+
+/// Certificate and key files for serving HTTPS.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Tls {
+    /// PEM file with the certificate chain
+    pub cert: PathBuf,
+    /// PEM file with the private key
+    pub key: PathBuf,
+    /// PEM file with the CA certificates that client certificates must be signed by (clients
+    /// without one are rejected)
+    pub client_ca: Option<PathBuf>,
+}
+
+impl Tls {
+    pub fn server_config(&self) -> Result<Arc<ServerConfig>, Error> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+
+        let builder = match &self.client_ca {
+            Some(client_ca) => {
+                let mut roots = RootCertStore::empty();
+
+                for cert in read_certs(client_ca)? {
+                    roots.add(cert)?;
+                }
+
+                builder.with_client_cert_verifier(
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                        .build()?,
+                )
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(&self.key)?))?
+            .ok_or_else(|| Error::MissingPrivateKey(self.key.clone()))?;
+
+        Ok(Arc::new(
+            builder.with_single_cert(read_certs(&self.cert)?, key)?,
+        ))
+    }
+}
+
+fn read_certs(path: &Path) -> Result<Vec<rustls::pki_types::CertificateDer<'static>>, Error> {
+    Ok(
+        rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))
+            .collect::<Result<Vec<_>, _>>()?,
+    )
+}
+
+/// A request read by [`serve`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Request {
+    pub method: String,
+    /// The path, including any query string
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// The client's address
+    pub remote: Option<IpAddr>,
+}
+
+impl Request {
+    /// The value of a query string parameter.
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        self.path
+            .split_once('?')?
+            .1
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| percent_decode(value))
+    }
+
+    /// The value of a header (the first, if it's repeated).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The path without its query string.
+    pub fn path_without_query(&self) -> &str {
+        self.path
+            .split_once('?')
+            .map_or(self.path.as_str(), |(path, _)| path)
+    }
+}
+
+/// A response to a request.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    /// Headers other than the content type and length
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new<B: Into<Vec<u8>>>(status: u16, content_type: &'static str, body: B) -> Self {
+        Self {
+            status,
+            content_type,
+            headers: vec![],
+            body: body.into(),
+        }
+    }
+
+    pub fn json(value: &serde_json::Value) -> Self {
+        let body = tracing::info_span!("serialize").in_scope(|| value.to_string());
+
+        Self::new(200, "application/json", body)
+    }
+
+    /// A successful response with a value in a negotiated encoding.
+    pub fn encoded(value: &serde_json::Value, encoding: Encoding) -> Self {
+        let body = tracing::info_span!("serialize").in_scope(|| encoding.encode(value));
+        let mut response = Self::new(200, encoding.content_type(), body);
+        response.headers.push(("Vary", "Accept".to_string()));
+
+        response
+    }
+
+    /// A response with only a status (and its reason phrase as the body).
+    pub fn status(status: u16) -> Self {
+        Self::new(status, "text/plain; charset=utf-8", reason(status))
+    }
+
+    /// Add cache validators to a successful response, or replace it with `304 Not Modified` if
+    /// the request's cached copy is still current.
+    ///
+    /// Clients are asked to revalidate before reusing a cached copy, unless the response already
+    /// has its own `Cache-Control`.
+    pub fn cached(self, request: &Request, last_modified: DateTime<Utc>) -> Self {
+        if self.status != 200 {
+            return self;
+        }
+
+        let cache_control = self
+            .headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("cache-control"))
+            .map_or_else(|| "no-cache".to_string(), |(_, value)| value.clone());
+        let validators = Validators::new(&self.body, last_modified);
+        let not_modified = validators.is_not_modified(
+            request.header("if-none-match"),
+            request.header("if-modified-since"),
+        );
+
+        let mut response = if not_modified {
+            Self::new(304, self.content_type, vec![])
+        } else {
+            self
+        };
+
+        response
+            .headers
+            .retain(|(name, _)| !name.eq_ignore_ascii_case("cache-control"));
+        response.headers.extend([
+            ("ETag", validators.etag.clone()),
+            ("Last-Modified", validators.last_modified_header()),
+            ("Cache-Control", cache_control),
+        ]);
+
+        response
+    }
+
+    /// Add the headers a CORS policy calls for.
+    pub fn with_cors(mut self, request: &Request, policy: &CorsPolicy) -> Self {
+        self.headers
+            .extend(policy.response_headers(request.header("origin"), request.method == "OPTIONS"));
+
+        self
+    }
+}
+
+/// A connection that a handler has taken over from the server.
+pub trait Connection: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Connection for T {}
+
+/// A function that takes over a connection.
+pub type Takeover = Box<dyn FnOnce(Box<dyn Connection>) + Send>;
+
+/// What a handler does with a request.
+pub enum Reply {
+    Response(Response),
+    /// Take over the connection after the request has been read (for example to speak the
+    /// WebSocket protocol on it), on a thread of its own
+    Upgrade(Takeover),
+}
+
+/// Serve HTTP requests (or HTTPS requests, given a TLS configuration) until the process is
+/// terminated.
+///
+/// Connections are handled by a fixed pool of worker threads (and closed after a single request),
+/// so a slow client only holds up its own worker. While every worker is busy, new connections wait
+/// to be accepted.
+pub fn serve<F: Fn(&Request) -> Response + Sync>(
+    address: &str,
+    tls: Option<Arc<ServerConfig>>,
+    handler: F,
+) -> Result<(), Error> {
+    serve_upgradable(address, tls, |request| Reply::Response(handler(request)))
+}
+
+/// Serve requests as [`serve`] does, but let the handler take over connections.
+///
+/// Requests to take over a connection while [`MAX_UPGRADES`] connections are already taken over
+/// are refused with a 503 response.
+pub fn serve_upgradable<F: Fn(&Request) -> Reply + Sync>(
+    address: &str,
+    tls: Option<Arc<ServerConfig>>,
+    handler: F,
+) -> Result<(), Error> {
+    serve_listener(TcpListener::bind(address)?, tls, handler)
+}
+
+/// Serve requests as [`serve_upgradable`] does, on a listener that's already bound.
+pub fn serve_listener<F: Fn(&Request) -> Reply + Sync>(
+    listener: TcpListener,
+    tls: Option<Arc<ServerConfig>>,
+    handler: F,
+) -> Result<(), Error> {
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    log::info!("Listening on {}://{}", scheme, listener.local_addr()?);
+
+    let upgrades = Arc::new(AtomicUsize::new(0));
+    let (sender, receiver) = mpsc::sync_channel::<TcpStream>(WORKERS);
+    let receiver = Mutex::new(receiver);
+
+    std::thread::scope(|scope| {
+        for _ in 0..WORKERS {
+            scope.spawn(|| loop {
+                // The lock is only held while waiting for a connection, not while handling it
+                let stream = match receiver.lock() {
+                    Ok(receiver) => receiver.recv(),
+                    Err(_) => break,
+                };
+
+                match stream {
+                    Ok(stream) => {
+                        if let Err(error) =
+                            handle_connection(stream, tls.as_ref(), &upgrades, &handler)
+                        {
+                            log::warn!("Connection error: {:?}", error);
+                        }
+                    }
+                    Err(_) => break,
+                }
+            });
+        }
+
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if sender.send(stream).is_err() {
+                        break;
+                    }
+                }
+                Err(error) => {
+                    log::error!("Accept error: {}", error);
+                }
+            }
+        }
+
+        drop(sender);
+    });
+
+    Ok(())
+}
+
+/// A connection taken over by a handler, counted until it's closed.
+struct Upgrade(Arc<AtomicUsize>);
+
+impl Upgrade {
+    fn acquire(upgrades: &Arc<AtomicUsize>) -> Option<Self> {
+        upgrades
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                (count < MAX_UPGRADES).then_some(count + 1)
+            })
+            .ok()
+            .map(|_| Self(upgrades.clone()))
+    }
+}
+
+impl Drop for Upgrade {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn handle_connection<F: Fn(&Request) -> Reply>(
+    mut stream: TcpStream,
+    tls: Option<&Arc<ServerConfig>>,
+    upgrades: &Arc<AtomicUsize>,
+    handler: &F,
+) -> Result<(), Error> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let remote = stream.peer_addr().ok().map(|address| address.ip());
+
+    match tls {
+        Some(config) => {
+            let connection = rustls::ServerConnection::new(config.clone())?;
+            let mut stream = rustls::StreamOwned::new(connection, stream);
+
+            match respond(&mut stream, remote, upgrades, handler)? {
+                Some((takeover, upgrade)) => {
+                    std::thread::spawn(move || {
+                        takeover(Box::new(stream));
+                        drop(upgrade);
+                    });
+                }
+                None => {
+                    stream.conn.send_close_notify();
+                    stream.flush()?;
+                }
+            }
+
+            Ok(())
+        }
+        None => {
+            if let Some((takeover, upgrade)) = respond(&mut stream, remote, upgrades, handler)? {
+                std::thread::spawn(move || {
+                    takeover(Box::new(stream));
+                    drop(upgrade);
+                });
+            }
+
+            Ok(())
+        }
+    }
+}
+
+/// Read a request from a stream and write the handler's response, or return the function that
+/// takes over the connection.
+fn respond<S: Read + Write, F: Fn(&Request) -> Reply>(
+    stream: &mut S,
+    remote: Option<IpAddr>,
+    upgrades: &Arc<AtomicUsize>,
+    handler: &F,
+) -> Result<Option<(Takeover, Upgrade)>, Error> {
+    let request = tracing::info_span!("http.read").in_scope(|| read_request(stream))?;
+
+    if let Some(mut request) = request {
+        request.remote = remote;
+
+        // The query is left out, since it may contain access tokens
+        let span = tracing::info_span!(
+            "http.request",
+            method = %request.method,
+            path = request.path_without_query(),
+            status = tracing::field::Empty
+        );
+        let _entered = span.enter();
+
+        let response = match handler(&request) {
+            Reply::Response(response) => response,
+            Reply::Upgrade(takeover) => match Upgrade::acquire(upgrades) {
+                Some(upgrade) => {
+                    span.record("status", 101);
+                    return Ok(Some((takeover, upgrade)));
+                }
+                None => {
+                    log::warn!("Refused upgrade: {} connections taken over", MAX_UPGRADES);
+                    Response::status(503)
+                }
+            },
+        };
+        span.record("status", response.status);
+
+        let _write = tracing::info_span!("http.write", bytes = response.body.len()).entered();
+
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            response.status,
+            reason(response.status),
+            response.content_type,
+            response.body.len()
+        )?;
+
+        for (name, value) in &response.headers {
+            write!(stream, "{}: {}\r\n", name, value)?;
+        }
+
+        stream.write_all(b"\r\n")?;
+        stream.write_all(&response.body)?;
+        stream.flush()?;
+    }
+
+    Ok(None)
+}
+
+/// Read a request, or `None` if the connection is closed before it's complete.
+fn read_request<R: Read>(reader: &mut R) -> Result<Option<Request>, Error> {
+    let mut buffer = vec![];
+    let mut chunk = [0; 8192];
+
+    // Read until the headers are complete, then until we have the whole body
+    let (method, path, headers, body_start, content_length) = loop {
+        let count = reader.read(&mut chunk)?;
+
+        if count == 0 {
+            return Ok(None);
+        }
+
+        buffer.extend_from_slice(&chunk[..count]);
+
+        if buffer.len() > MAX_REQUEST_LEN {
+            return Err(Error::RequestTooLarge);
+        }
+
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut request = httparse::Request::new(&mut headers);
+
+        if let httparse::Status::Complete(body_start) = request.parse(&buffer)? {
+            let content_length = request
+                .headers
+                .iter()
+                .find(|header| header.name.eq_ignore_ascii_case("content-length"))
+                .and_then(|header| std::str::from_utf8(header.value).ok())
+                .and_then(|value| value.trim().parse::<usize>().ok())
+                .unwrap_or(0);
+
+            let headers = request
+                .headers
+                .iter()
+                .map(|header| {
+                    (
+                        header.name.to_string(),
+                        String::from_utf8_lossy(header.value).into_owned(),
+                    )
+                })
+                .collect();
+
+            break (
+                request.method.unwrap_or_default().to_string(),
+                request.path.unwrap_or_default().to_string(),
+                headers,
+                body_start,
+                content_length,
+            );
+        }
+    };
+
+    if body_start + content_length > MAX_REQUEST_LEN {
+        return Err(Error::RequestTooLarge);
+    }
+
+    while buffer.len() < body_start + content_length {
+        let count = reader.read(&mut chunk)?;
+
+        if count == 0 {
+            return Ok(None);
+        }
+
+        buffer.extend_from_slice(&chunk[..count]);
+    }
+
+    Ok(Some(Request {
+        method,
+        path,
+        headers,
+        body: buffer[body_start..body_start + content_length].to_vec(),
+        remote: None,
+    }))
+}
+
+/// Decode `%XX` escapes.
+pub fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        match bytes[index] {
+            b'%' => match value
+                .get(index + 1..index + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                Some(byte) => {
+                    decoded.push(byte);
+                    index += 3;
+                    continue;
+                }
+                None => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+
+        index += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        429 => "Too Many Requests",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        507 => "Insufficient Storage",
+        _ => "Internal Server Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_requests() {
+        let mut input =
+            "POST /history?limit=2 HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello".as_bytes();
+        let request = read_request(&mut input).unwrap().unwrap();
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path_without_query(), "/history");
+        assert_eq!(request.query_param("limit"), Some("2".to_string()));
+        assert_eq!(request.header("content-length"), Some("5"));
+        assert_eq!(request.body, b"hello");
+
+        let mut truncated = "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhel".as_bytes();
+        assert_eq!(read_request(&mut truncated).unwrap(), None);
+
+        assert_eq!(percent_decode("foo%2Cbar%20%E2%9C%93%"), "foo,bar ✓%");
+    }
+
+    #[test]
+    fn conditional_responses() {
+        let modified = chrono::Utc::now();
+        let mut request = Request {
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            headers: vec![],
+            body: vec![],
+            remote: None,
+        };
+
+        let response = Response::json(&serde_json::json!({})).cached(&request, modified);
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"{}");
+
+        let etag = response.headers[0].1.clone();
+        request
+            .headers
+            .push(("If-None-Match".to_string(), etag.clone()));
+
+        let response = Response::json(&serde_json::json!({})).cached(&request, modified);
+        assert_eq!(response.status, 304);
+        assert!(response.body.is_empty());
+        assert_eq!(response.headers[0], ("ETag", etag));
+
+        let response = Response::json(&serde_json::json!([])).cached(&request, modified);
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.headers[2],
+            ("Cache-Control", "no-cache".to_string())
+        );
+
+        let mut public = Response::json(&serde_json::json!({}));
+        public
+            .headers
+            .push(("Cache-Control", "max-age=60".to_string()));
+        let response = public.cached(&request, modified);
+        assert_eq!(response.status, 304);
+        assert_eq!(
+            response.headers[2],
+            ("Cache-Control", "max-age=60".to_string())
+        );
+
+        assert_eq!(
+            Response::status(404).cached(&request, modified).headers,
+            vec![]
+        );
+    }
+}
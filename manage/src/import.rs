@@ -1,8 +1,27 @@
-use chrono::{DateTime, NaiveDate, TimeZone, Utc};
-use memory_lol::db::{table::Writeable, Database};
+use crate::events::{Event, Events};
+use crate::shutdown::Shutdown;
+use crate::throttle::{self, Throttle};
+use chrono::{DateTime, NaiveDate, Utc};
+use memory_lol::db::{
+    bulk::BulkLoader,
+    changes::ChangeTable,
+    edges::{Edge, EdgeKind, EdgeTable},
+    table::Writeable,
+    weights::WeightTable,
+    Database, ObservationOutcome,
+};
+use memory_lol::handles::Platform;
+use memory_lol::model::ImportRecord;
+use memory_lol::timestamp;
+use serde_derive::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
 use std::io::{BufRead, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -18,6 +37,10 @@ pub enum Error {
     InvalidJson(serde_json::Value),
     #[error("Database error")]
     Db(#[from] memory_lol::db::Error),
+    #[error("No input files in directory")]
+    EmptyDirectory(PathBuf),
+    #[error("Column not in header: {0}")]
+    MissingColumn(String),
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -33,7 +56,12 @@ impl ScreenNameEntry {
     }
 
     pub fn from_record(record: &csv::StringRecord) -> Result<Self, Error> {
-        Self::from_record_opt(record).ok_or_else(|| Error::InvalidCsvRecord(record.clone()))
+        Self::from_columns(record, &Columns::default())
+    }
+
+    pub fn from_columns(record: &csv::StringRecord, columns: &Columns) -> Result<Self, Error> {
+        Self::from_record_opt(record, columns)
+            .ok_or_else(|| Error::InvalidCsvRecord(record.clone()))
     }
 
     fn from_json_opt(value: &Value) -> Option<Self> {
@@ -42,8 +70,10 @@ impl ScreenNameEntry {
         let id = id_str_string.parse::<u64>().ok()?;
         let screen_name_value = value.get("screen_name")?;
         let screen_name = screen_name_value.as_str()?.to_string();
-        let snapshot_value = value.get("snapshot")?;
-        let snapshot = Utc.timestamp_opt(snapshot_value.as_i64()?, 0).single()?;
+        let snapshot = match value.get("snapshot")? {
+            Value::String(value) => timestamp::parse(value).ok()?,
+            value => timestamp::from_epoch(value.as_i64()?).ok()?,
+        };
         let snapshots = vec![snapshot];
 
         Some(Self {
@@ -53,11 +83,39 @@ impl ScreenNameEntry {
         })
     }
 
-    pub fn from_record_opt(record: &csv::StringRecord) -> Option<Self> {
-        let id = record.get(0).and_then(|value| value.parse::<u64>().ok())?;
-        let screen_name = record.get(1)?.to_string();
-        let snapshot_value = record.get(2).and_then(|value| value.parse::<i64>().ok())?;
-        let snapshot = Utc.timestamp_opt(snapshot_value, 0).single()?;
+    fn from_multi_record(record: &csv::StringRecord, format: timestamp::Format) -> Option<Self> {
+        let mut parts = record.iter();
+        let id = parts.next()?.parse::<u64>().ok()?;
+        let screen_name = parts.next()?.to_string();
+        let snapshots = parts
+            .map(|part| format.parse(part).ok())
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self {
+            id,
+            screen_name,
+            snapshots,
+        })
+    }
+
+    /// Parse a record of a user ID, a screen name, and an observation time (in any of the forms
+    /// [`timestamp::parse`] accepts).
+    ///
+    /// The observation time may be empty if the record has a tweet ID column (by default the
+    /// fifth) containing the ID of the tweet the observation came from, in which case it's taken
+    /// from the tweet ID.
+    pub fn from_record_opt(record: &csv::StringRecord, columns: &Columns) -> Option<Self> {
+        let id = record
+            .get(columns.id)
+            .and_then(|value| value.parse::<u64>().ok())?;
+        let screen_name = record.get(columns.screen_name)?.to_string();
+        let snapshot = match record.get(columns.date).filter(|value| !value.is_empty()) {
+            Some(value) => timestamp::parse(value).ok()?,
+            None => {
+                let tweet_id = record.get(columns.tweet_id?)?.parse::<u64>().ok()?;
+                memory_lol::snowflake::to_date_time(tweet_id)?
+            }
+        };
         let snapshots = vec![snapshot];
 
         Some(Self {
@@ -68,50 +126,548 @@ impl ScreenNameEntry {
     }
 }
 
+/// Positions of the fields in a CSV record.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Columns {
+    pub id: usize,
+    pub screen_name: usize,
+    pub date: usize,
+    /// ID of the account that made the mention
+    pub mentioner: Option<usize>,
+    /// ID of the tweet the observation came from
+    pub tweet_id: Option<usize>,
+}
+
+impl Default for Columns {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            screen_name: 1,
+            date: 2,
+            mentioner: Some(3),
+            tweet_id: Some(4),
+        }
+    }
+}
+
+/// Names of header columns to read fields from, instead of taking them by position.
+#[derive(Clone, Debug, Default, Eq, PartialEq, clap::Args)]
+pub struct ColumnNames {
+    /// Header of the column containing user IDs
+    #[clap(long, requires = "header")]
+    pub id_column: Option<String>,
+    /// Header of the column containing screen names
+    #[clap(long, requires = "header")]
+    pub name_column: Option<String>,
+    /// Header of the column containing observation times
+    #[clap(long, requires = "header")]
+    pub date_column: Option<String>,
+}
+
+impl ColumnNames {
+    pub fn is_empty(&self) -> bool {
+        self.id_column.is_none() && self.name_column.is_none() && self.date_column.is_none()
+    }
+
+    /// Find the named columns in a header row.
+    ///
+    /// Columns that aren't named keep their default positions, but the optional mentioner and
+    /// tweet ID columns are only read when no column is named, since a spreadsheet's fourth and
+    /// fifth columns could contain anything.
+    pub fn resolve(&self, headers: &csv::StringRecord) -> Result<Columns, Error> {
+        if self.is_empty() {
+            return Ok(Columns::default());
+        }
+
+        let find = |name: &Option<String>, default: usize| match name {
+            Some(name) => headers
+                .iter()
+                .position(|header| header == name)
+                .ok_or_else(|| Error::MissingColumn(name.clone())),
+            None => Ok(default),
+        };
+
+        Ok(Columns {
+            id: find(&self.id_column, 0)?,
+            screen_name: find(&self.name_column, 1)?,
+            date: find(&self.date_column, 2)?,
+            mentioner: None,
+            tweet_id: None,
+        })
+    }
+}
+
+/// How observation dates for a pair are written to the database.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, clap::ValueEnum)]
 pub enum UpdateMode {
+    /// Record every observed day as an exact observation
+    #[value(alias = "exact")]
     All,
+    /// Record only the first and last observed days (the database still merges these with any
+    /// existing dates)
     Range,
+    /// Record every observed day and also the number of times the pair was observed on each day
+    /// (in the weight table)
+    Weighted,
+}
+
+/// How the fields of a CSV input are delimited and quoted.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, clap::Args)]
+pub struct CsvDialect {
+    /// Field delimiter (a single character, or `tab`)
+    #[clap(long, default_value = ",", value_parser = parse_delimiter)]
+    pub delimiter: u8,
+    /// Treat double quotes as ordinary characters instead of as field quoting
+    #[clap(long)]
+    pub no_quoting: bool,
+    /// Skip a header row at the start of the input
+    #[clap(long)]
+    pub header: bool,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            no_quoting: false,
+            header: false,
+        }
+    }
+}
+
+impl CsvDialect {
+    /// A reader for the dialect that accepts rows with differing numbers of fields.
+    pub fn reader<R: Read>(&self, source: R) -> csv::Reader<R> {
+        csv::ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .quoting(!self.no_quoting)
+            .has_headers(self.header)
+            .flexible(true)
+            .from_reader(source)
+    }
+}
+
+fn parse_delimiter(value: &str) -> Result<u8, String> {
+    match value {
+        "tab" | "\\t" => Ok(b'\t'),
+        _ if value.len() == 1 => Ok(value.as_bytes()[0]),
+        _ => Err(format!(
+            "Delimiter must be a single ASCII character: {}",
+            value
+        )),
+    }
+}
+
+/// Summary statistics for a completed import.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ImportReport {
+    pub lines_read: usize,
+    pub lines_parsed: usize,
+    pub lines_skipped: usize,
+    pub pairs: usize,
+    pub new_pairs: usize,
+    pub new_accounts: usize,
+    pub new_screen_names: usize,
+    pub invalid_screen_names: usize,
+    pub dates_added: usize,
+    pub dates_unchanged: usize,
+    #[serde(serialize_with = "serialize_duration")]
+    pub elapsed: Duration,
+}
+
+impl ImportReport {
+    /// Input lines processed per second.
+    pub fn throughput(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+
+        if seconds > 0.0 {
+            self.lines_read as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    pub fn merge(&mut self, other: &ImportReport) {
+        self.lines_read += other.lines_read;
+        self.lines_parsed += other.lines_parsed;
+        self.lines_skipped += other.lines_skipped;
+        self.pairs += other.pairs;
+        self.new_pairs += other.new_pairs;
+        self.new_accounts += other.new_accounts;
+        self.new_screen_names += other.new_screen_names;
+        self.invalid_screen_names += other.invalid_screen_names;
+        self.dates_added += other.dates_added;
+        self.dates_unchanged += other.dates_unchanged;
+        self.elapsed += other.elapsed;
+    }
+
+    /// Count a single observation written by a stream import, returning the events for it if
+    /// it's a new pair.
+    fn add_observation(
+        &mut self,
+        db: &Database<Writeable>,
+        id: u64,
+        screen_name: &str,
+        date: NaiveDate,
+        outcome: ObservationOutcome,
+    ) -> Result<Vec<Event>, Error> {
+        self.pairs += 1;
+
+        match outcome {
+            ObservationOutcome::New => {
+                self.new_pairs += 1;
+                self.dates_added += 1;
+
+                // The pair has just been written, so it's the only one if the account or screen
+                // name is new
+                let new_account = db.accounts.lookup(id)?.len() == 1;
+                let holders = db.lookup_by_screen_name(screen_name)?;
+
+                if new_account {
+                    self.new_accounts += 1;
+                }
+                if holders == [id] {
+                    self.new_screen_names += 1;
+                }
+
+                return Ok(Event::for_new_pair(
+                    id,
+                    screen_name,
+                    date,
+                    new_account,
+                    &holders,
+                ));
+            }
+            ObservationOutcome::Extended => self.dates_added += 1,
+            ObservationOutcome::Unchanged => self.dates_unchanged += 1,
+            ObservationOutcome::Invalid => self.invalid_screen_names += 1,
+        }
+
+        Ok(vec![])
+    }
+
+    pub fn to_record(&self, source: &str, hash: Option<String>) -> ImportRecord {
+        ImportRecord {
+            timestamp: Utc::now(),
+            source: source.to_string(),
+            hash,
+            lines_read: self.lines_read as u64,
+            lines_skipped: self.lines_skipped as u64,
+            pairs: self.pairs as u64,
+            new_pairs: self.new_pairs as u64,
+            new_accounts: self.new_accounts as u64,
+            dates_added: self.dates_added as u64,
+            duration_ms: self.elapsed.as_millis() as u64,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<Value, Error> {
+        let mut value = serde_json::to_value(self)?;
+
+        if let Some(fields) = value.as_object_mut() {
+            fields.insert("throughput".to_string(), self.throughput().into());
+        }
+
+        Ok(value)
+    }
+}
+
+impl std::fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Lines read: {}", self.lines_read)?;
+        writeln!(f, "Lines parsed: {}", self.lines_parsed)?;
+        writeln!(f, "Lines skipped: {}", self.lines_skipped)?;
+        writeln!(f, "Pairs: {}", self.pairs)?;
+        writeln!(f, "New pairs: {}", self.new_pairs)?;
+        writeln!(f, "New accounts: {}", self.new_accounts)?;
+        writeln!(f, "New screen names: {}", self.new_screen_names)?;
+        writeln!(f, "Invalid screen names: {}", self.invalid_screen_names)?;
+        writeln!(f, "Dates added: {}", self.dates_added)?;
+        writeln!(f, "Dates unchanged: {}", self.dates_unchanged)?;
+        writeln!(f, "Elapsed: {:.3}s", self.elapsed.as_secs_f64())?;
+        write!(f, "Throughput: {:.1} lines/s", self.throughput())
+    }
+}
+
+/// Serialize a duration as fractional seconds.
+pub fn serialize_duration<S: serde::Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+impl UpdateMode {
+    /// Select the dates to write for a pair from all of its observed dates.
+    fn select(self, mut dates: Vec<NaiveDate>) -> Vec<NaiveDate> {
+        dates.sort();
+        dates.dedup();
+
+        match self {
+            UpdateMode::All | UpdateMode::Weighted => dates,
+            UpdateMode::Range => {
+                if dates.len() <= 2 {
+                    dates
+                } else {
+                    let mut range = Vec::with_capacity(2);
+
+                    if let Some(first) = dates.first() {
+                        range.push(*first);
+                    }
+                    if let Some(last) = dates.last() {
+                        range.push(*last);
+                    }
+
+                    range
+                }
+            }
+        }
+    }
+}
+
+/// What the batched reads found for a pair before it is written.
+struct Known {
+    existing: Option<Vec<NaiveDate>>,
+    new_account: bool,
+    holders: Vec<u64>,
+}
+
+/// File in the database directory recording how far an interrupted import got.
+pub const CHECKPOINT_FILE: &str = "import-checkpoint.json";
+
+/// The progress of an interrupted import, so that running the same import again resumes where it
+/// stopped (instead of, for example, counting weights twice).
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct Checkpoint {
+    /// Identifies the input (usually by its hash)
+    pub key: String,
+    /// The part of the input being written, for imports with several parts
+    pub part: usize,
+    /// The number of the part's pairs (in sorted order) already written
+    pub pairs: usize,
+}
+
+impl Checkpoint {
+    /// The start of one part of an input.
+    pub fn start(key: &str, part: usize) -> Self {
+        Self {
+            key: key.to_string(),
+            part,
+            pairs: 0,
+        }
+    }
+
+    pub fn load(base: &Path) -> Result<Option<Self>, Error> {
+        let path = base.join(CHECKPOINT_FILE);
+
+        if path.exists() {
+            Ok(Some(serde_json::from_slice(&std::fs::read(path)?)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn save(&self, base: &Path) -> Result<(), Error> {
+        Ok(std::fs::write(
+            base.join(CHECKPOINT_FILE),
+            serde_json::to_vec_pretty(self)?,
+        )?)
+    }
+
+    /// Remove the checkpoint once an import has completed.
+    pub fn clear(base: &Path) -> Result<(), Error> {
+        let path = base.join(CHECKPOINT_FILE);
+
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Which handles an import accepts, beyond what the database can store.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct HandleFilter {
+    pub platform: Platform,
+    /// Reject handles that don't follow the platform's rules (instead of accepting anything the
+    /// database can store)
+    pub strict: bool,
+}
+
+impl HandleFilter {
+    pub fn accepts(&self, screen_name: &str) -> bool {
+        !self.strict || self.platform.is_valid_handle(screen_name)
+    }
+}
+
+/// Where a session records the dates it adds, under its import's ID (the hash of its input), so
+/// that the import can be rolled back.
+#[derive(Clone)]
+pub struct ChangeLog {
+    table: Arc<ChangeTable<Writeable>>,
+    import: String,
+}
+
+impl ChangeLog {
+    pub fn new(table: Arc<ChangeTable<Writeable>>, import: &str) -> Self {
+        Self {
+            table,
+            import: import.to_string(),
+        }
+    }
 }
 
 #[derive(Default)]
 pub struct Session {
     data: HashMap<(u64, String), Vec<DateTime<Utc>>>,
+    /// Observation times for mention edges (from the mentioned account to the account that
+    /// mentioned it), if the input includes them
+    mentions: HashMap<(u64, u64), Vec<DateTime<Utc>>>,
+    lines_read: usize,
+    lines_skipped: usize,
+    loading: Duration,
+    throttle: Throttle,
+    events: Events,
+    /// Pairs dropped by [`Session::set_handles`]
+    invalid_handles: usize,
+    changes: Option<ChangeLog>,
 }
 
 impl Session {
+    /// Pace the session's writes (one batch at a time) when it's written to the database.
+    pub fn set_throttle(&mut self, throttle: Throttle) {
+        self.throttle = throttle;
+    }
+
+    /// Publish events for new accounts and pairs (one batch at a time) when the session is
+    /// written to the database.
+    pub fn set_events(&mut self, events: Events) {
+        self.events = events;
+    }
+
+    /// Record the dates the session adds (and the pairs it creates) when it's written to the
+    /// database.
+    pub fn set_changes(&mut self, changes: Option<ChangeLog>) {
+        self.changes = changes;
+    }
+
+    /// Drop pairs with handles the filter rejects (they're reported as invalid screen names).
+    pub fn set_handles(&mut self, handles: HandleFilter) {
+        let count = self.data.len();
+
+        self.data.retain(|(_, screen_name), _| {
+            let accepted = handles.accepts(screen_name);
+
+            if !accepted {
+                log::warn!("Invalid {} handle: {}", handles.platform, screen_name);
+            }
+
+            accepted
+        });
+
+        self.invalid_handles += count - self.data.len();
+    }
+
+    #[tracing::instrument(name = "import.load_json", skip_all)]
     pub fn load_json<R: BufRead>(source: R) -> Result<Self, Error> {
+        let started = Instant::now();
         let mut session = Session::default();
 
         for line in source.lines() {
             let line = line?;
+            session.lines_read += 1;
+
             match serde_json::from_str(&line) {
-                Ok(value) => {
-                    if let Some(entry) = ScreenNameEntry::from_json_opt(&value) {
+                Ok(value) => match ScreenNameEntry::from_json_opt(&value) {
+                    Some(entry) => {
                         session.add_entry(&entry);
                     }
-                }
+                    None => {
+                        session.lines_skipped += 1;
+                    }
+                },
                 Err(error) => {
                     log::warn!("JSON error: {}", error);
+                    session.lines_skipped += 1;
                 }
             }
         }
 
+        session.loading = started.elapsed();
+
         Ok(session)
     }
 
-    pub fn load_mentions<R: Read>(source: R) -> Result<Self, Error> {
+    #[tracing::instrument(name = "import.load_mentions", skip_all)]
+    pub fn load_mentions<R: Read>(
+        source: R,
+        dialect: CsvDialect,
+        names: &ColumnNames,
+    ) -> Result<Self, Error> {
+        let started = Instant::now();
         let mut session = Session::default();
-        let mut reader = csv::ReaderBuilder::new()
-            .has_headers(false)
-            .from_reader(source);
+        let mut reader = dialect.reader(source);
+        let columns = if dialect.header {
+            names.resolve(reader.headers()?)?
+        } else {
+            Columns::default()
+        };
+
+        for record in reader.records() {
+            let record = record?;
+            session.lines_read += 1;
+
+            let entry = ScreenNameEntry::from_columns(&record, &columns)?;
+            session.add_entry(&entry);
+
+            // An optional fourth column contains the ID of the account that made the mention
+            if let Some(mentioner_id) = columns
+                .mentioner
+                .and_then(|column| record.get(column))
+                .filter(|value| !value.is_empty())
+            {
+                let mentioner_id = mentioner_id
+                    .parse::<u64>()
+                    .map_err(|_| Error::InvalidCsvRecord(record.clone()))?;
+
+                session
+                    .mentions
+                    .entry((entry.id, mentioner_id))
+                    .or_default()
+                    .extend(&entry.snapshots);
+            }
+        }
+
+        session.loading = started.elapsed();
+
+        Ok(session)
+    }
+
+    /// Load CSV rows of a user ID, a screen name, and any number of timestamps in the given
+    /// format (which may differ from field to field if it's detected automatically).
+    #[tracing::instrument(name = "import.load_multi", skip_all)]
+    pub fn load_multi<R: Read>(
+        source: R,
+        dialect: CsvDialect,
+        format: timestamp::Format,
+    ) -> Result<Self, Error> {
+        let started = Instant::now();
+        let mut session = Session::default();
+        let mut reader = dialect.reader(source);
 
         for record in reader.records() {
             let record = record?;
-            let entry = ScreenNameEntry::from_record(&record)?;
+            session.lines_read += 1;
+
+            let entry = ScreenNameEntry::from_multi_record(&record, format)
+                .ok_or_else(|| Error::InvalidCsvRecord(record.clone()))?;
             session.add_entry(&entry);
         }
 
+        session.loading = started.elapsed();
+
         Ok(session)
     }
 
@@ -123,64 +679,1187 @@ impl Session {
         snapshots.extend(&entry.snapshots);
     }
 
+    /// Write the mention edges in the session, returning the number written.
+    pub fn update_mentions(&self, edges: &EdgeTable<Writeable>) -> Result<usize, Error> {
+        let mentions = self
+            .mentions
+            .iter()
+            .map(|((mentioned_id, mentioner_id), snapshots)| Edge {
+                kind: EdgeKind::Mention,
+                source: *mentioner_id,
+                target: *mentioned_id,
+                dates: to_dates(snapshots),
+            })
+            .collect::<Vec<_>>();
+
+        Ok(edges.add_all(&mentions)?)
+    }
+
+    /// Write a single pair, given what the batched reads found for it.
+    ///
+    /// Returns the dates added to the pair, if it was written (invalid screen names are counted
+    /// and skipped).
     fn insert(
         db: &Database<Writeable>,
         id: u64,
         screen_name: &str,
         dates: Vec<NaiveDate>,
-    ) -> Result<(), Error> {
+        known: Known,
+        report: &mut ImportReport,
+    ) -> Result<Option<Vec<NaiveDate>>, Error> {
+        let Known {
+            existing,
+            new_account,
+            holders,
+        } = known;
+
+        let new_screen_name = existing.is_none() && holders.is_empty();
+        let added = match &existing {
+            Some(existing) => dates
+                .iter()
+                .filter(|date| !existing.contains(date))
+                .copied()
+                .collect(),
+            None => dates.clone(),
+        };
+        let dates_added = added.len();
+        let dates_unchanged = dates.len() - dates_added;
+
         match db.insert(id, screen_name, dates) {
             Err(memory_lol::db::Error::InvalidScreenName(screen_name)) => {
                 log::warn!("Invalid screen name: {}", screen_name);
-                Ok(())
+                report.invalid_screen_names += 1;
+                Ok(None)
+            }
+            other => {
+                other?;
+
+                if existing.is_none() {
+                    report.new_pairs += 1;
+                }
+                if new_account {
+                    report.new_accounts += 1;
+                }
+                if new_screen_name {
+                    report.new_screen_names += 1;
+                }
+                report.dates_added += dates_added;
+                report.dates_unchanged += dates_unchanged;
+
+                Ok(Some(added))
+            }
+        }
+    }
+
+    /// Write every pair in the session, along with its per-day observation counts if a weight
+    /// table is given.
+    ///
+    /// The session is written as one part of an input (identified by a checkpoint at the part's
+    /// start), resuming from the database's checkpoint if it was left by the same input. Parts
+    /// before the checkpoint's part are skipped. If a shutdown is requested, the current batch is
+    /// finished and a checkpoint saved, and the returned flag is set.
+    ///
+    /// Each pair's weights are written immediately after its dates, so an import killed without
+    /// warning leaves at most one pair with dates but no weights.
+    pub fn update(
+        &self,
+        db: &Database<Writeable>,
+        base: &Path,
+        part: Checkpoint,
+        mode: UpdateMode,
+        weights: Option<&WeightTable<Writeable>>,
+        shutdown: &Shutdown,
+    ) -> Result<(ImportReport, bool), Error> {
+        let Checkpoint { key, part, .. } = part;
+        let start = match Checkpoint::load(base)?.filter(|checkpoint| checkpoint.key == key) {
+            Some(checkpoint) if checkpoint.part > part => {
+                log::info!("Skipping part {} of an interrupted import", part);
+                return Ok((ImportReport::default(), false));
+            }
+            Some(checkpoint) if checkpoint.part == part => {
+                log::info!(
+                    "Resuming an interrupted import after {} pairs",
+                    checkpoint.pairs
+                );
+                checkpoint.pairs
             }
-            other => Ok(other?),
+            _ => 0,
+        };
+
+        let (report, stopped) = self.update_from(db, mode, weights, start, shutdown)?;
+
+        match stopped {
+            Some(pairs) => {
+                db.flush()?;
+                Checkpoint { key, part, pairs }.save(base)?;
+                log::warn!(
+                    "Import interrupted after {} pairs; run it again to resume",
+                    pairs
+                );
+
+                Ok((report, true))
+            }
+            None => Ok((report, false)),
         }
     }
 
-    pub fn update(&self, db: &Database<Writeable>, mode: UpdateMode) -> Result<usize, Error> {
-        let mut count = 0;
+    /// Write the session's pairs, starting from a position in their sorted order and stopping
+    /// after the current batch if a shutdown is requested.
+    ///
+    /// Returns the report along with the position to resume from, if the import was stopped.
+    #[tracing::instrument(
+        name = "import.update",
+        skip(self, db, mode, weights, shutdown),
+        fields(pairs = self.data.len())
+    )]
+    fn update_from(
+        &self,
+        db: &Database<Writeable>,
+        mode: UpdateMode,
+        weights: Option<&WeightTable<Writeable>>,
+        start: usize,
+        shutdown: &Shutdown,
+    ) -> Result<(ImportReport, Option<usize>), Error> {
+        let started = Instant::now();
+        let mut report = ImportReport {
+            lines_read: self.lines_read,
+            lines_parsed: self.lines_read - self.lines_skipped,
+            lines_skipped: self.lines_skipped,
+            invalid_screen_names: self.invalid_handles,
+            ..Default::default()
+        };
 
-        for ((id, screen_name), snapshots) in &self.data {
-            let mut dates = to_dates(snapshots);
-            dates.sort();
-            dates.dedup();
+        // Sorting keeps each account's pairs together, so we only need to check whether an
+        // account exists once, and only if none of its pairs are already known
+        let mut pairs = self.data.iter().collect::<Vec<_>>();
+        pairs.sort_unstable_by_key(|(pair, _)| *pair);
 
-            match mode {
-                UpdateMode::All => {
-                    Self::insert(db, *id, screen_name, dates)?;
-                }
+        // The last account written, and whether it exists in the database
+        let mut current: Option<(u64, bool)> = None;
+
+        let mut position = start.min(pairs.len());
+
+        for chunk in pairs[position..].chunks(READ_BATCH_SIZE) {
+            let _batch = tracing::info_span!("import.batch", pairs = chunk.len()).entered();
+            let read = tracing::info_span!("db.read").entered();
+            let keys = chunk
+                .iter()
+                .map(|((id, screen_name), _)| (*id, screen_name.as_str()))
+                .collect::<Vec<_>>();
+            let existing = db.accounts.get_many(&keys)?;
+            let known_ids = keys
+                .iter()
+                .zip(&existing)
+                .filter(|(_, dates)| dates.is_some())
+                .map(|((id, _), _)| *id)
+                .collect::<HashSet<_>>();
+            let new_screen_names = keys
+                .iter()
+                .zip(&existing)
+                .filter(|(_, dates)| dates.is_none())
+                .map(|((_, screen_name), _)| *screen_name)
+                .collect::<Vec<_>>();
+            let mut indexed = db.screen_names.lookup_many(&new_screen_names)?.into_iter();
+            drop(read);
+
+            // Screen names claimed earlier in the chunk aren't visible to the batched read
+            let mut claimed: HashMap<String, Vec<u64>> = HashMap::new();
+            let mut chunk_bytes = 0;
+            let mut chunk_events = vec![];
 
-                UpdateMode::Range => {
-                    let range = if dates.len() <= 2 {
-                        dates
-                    } else {
-                        let mut range = Vec::with_capacity(2);
+            for (((id, screen_name), snapshots), existing) in chunk.iter().zip(existing) {
+                let id = *id;
+                let account_exists = match current {
+                    Some((current_id, exists)) if current_id == id => exists,
+                    _ => known_ids.contains(&id) || db.accounts.contains_id(id)?,
+                };
 
-                        if let Some(first) = dates.first() {
-                            range.push(*first);
+                let known = match existing {
+                    Some(existing) => Known {
+                        existing: Some(existing),
+                        new_account: false,
+                        holders: vec![],
+                    },
+                    None => {
+                        let mut holders = indexed.next().unwrap_or_default();
+
+                        if let Some(ids) = claimed.get(&screen_name.to_lowercase()) {
+                            for claimant in ids {
+                                if !holders.contains(claimant) {
+                                    holders.push(*claimant);
+                                }
+                            }
                         }
-                        if let Some(last) = dates.last() {
-                            range.push(*last);
+
+                        Known {
+                            existing: None,
+                            new_account: !account_exists,
+                            holders,
                         }
+                    }
+                };
+                let new_pair = known.existing.is_none();
+                let observed = to_dates(snapshots);
+                let selected = mode.select(observed.clone());
+                chunk_bytes += throttle::pair_bytes(screen_name, selected.len());
+
+                let pair_events = match observed.iter().min() {
+                    Some(date) if new_pair && self.events.is_enabled() => Event::for_new_pair(
+                        id,
+                        screen_name,
+                        *date,
+                        known.new_account,
+                        &known.holders,
+                    ),
+                    _ => vec![],
+                };
 
-                        range
-                    };
+                let added = Self::insert(db, id, screen_name, selected, known, &mut report)?;
+                let written = added.is_some();
+
+                // New pairs are recorded even without dates, so that rolling back removes them
+                if let (Some(changes), Some(added)) = (&self.changes, added) {
+                    if new_pair || !added.is_empty() {
+                        changes
+                            .table
+                            .record(&changes.import, id, screen_name, &added)?;
+                    }
+                }
 
-                    Self::insert(db, *id, screen_name, range)?;
+                if written {
+                    chunk_events.extend(pair_events);
                 }
+
+                if let Some(weights) = weights.filter(|_| written) {
+                    weights.add(id, screen_name, &count_days(&observed))?;
+                }
+
+                if written && new_pair {
+                    claimed
+                        .entry(screen_name.to_lowercase())
+                        .or_default()
+                        .push(id);
+                }
+
+                current = Some((id, account_exists || written));
+                report.pairs += 1;
             }
 
-            count += 1;
+            position += chunk.len();
+            self.events.publish(&chunk_events);
+            self.throttle.pace(db, chunk.len(), chunk_bytes)?;
+
+            if shutdown.requested() && position < pairs.len() {
+                report.elapsed = self.loading + started.elapsed();
+                return Ok((report, Some(position)));
+            }
         }
 
+        report.elapsed = self.loading + started.elapsed();
+
+        Ok((report, None))
+    }
+}
+
+/// Interactions between accounts collected from an input before they are written.
+#[derive(Default)]
+pub struct EdgeSession {
+    edges: HashMap<(EdgeKind, u64, u64), Vec<DateTime<Utc>>>,
+    pub lines_read: usize,
+    pub lines_skipped: usize,
+}
+
+impl EdgeSession {
+    /// Load interactions from NDJSON tweet objects.
+    ///
+    /// Retweets are recorded only as retweets (not as mentions of the accounts mentioned in the
+    /// retweeted text), and interactions of an account with itself (such as replies in threads)
+    /// are skipped.
+    #[tracing::instrument(name = "import.load_tweets", skip_all)]
+    pub fn load_tweets<R: BufRead>(source: R) -> Result<Self, Error> {
+        let mut session = Self::default();
+
+        for line in source.lines() {
+            let line = line?;
+            session.lines_read += 1;
+
+            match serde_json::from_str::<Value>(&line) {
+                Ok(value) => {
+                    if !session.add_tweet(&value) {
+                        session.lines_skipped += 1;
+                    }
+                }
+                Err(error) => {
+                    log::warn!("JSON error: {}", error);
+                    session.lines_skipped += 1;
+                }
+            }
+        }
+
+        Ok(session)
+    }
+
+    /// Load interactions from CSV rows of a source ID, a target ID, an edge kind (`mention`,
+    /// `reply`, `quote`, or `retweet`), and an observation time as epoch second.
+    #[tracing::instrument(name = "import.load_csv", skip_all)]
+    pub fn load_csv<R: Read>(source: R) -> Result<Self, Error> {
+        let mut session = Self::default();
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(source);
+
+        for record in reader.records() {
+            let record = record?;
+            session.lines_read += 1;
+
+            let (kind, source, target, timestamp) = Self::parse_record(&record)
+                .ok_or_else(|| Error::InvalidCsvRecord(record.clone()))?;
+            session.add(kind, source, target, timestamp);
+        }
+
+        Ok(session)
+    }
+
+    fn parse_record(record: &csv::StringRecord) -> Option<(EdgeKind, u64, u64, DateTime<Utc>)> {
+        let source = record.get(0)?.parse::<u64>().ok()?;
+        let target = record.get(1)?.parse::<u64>().ok()?;
+        let kind = record.get(2)?.parse::<EdgeKind>().ok()?;
+        let timestamp = timestamp::parse(record.get(3)?).ok()?;
+
+        Some((kind, source, target, timestamp))
+    }
+
+    /// Add a tweet's interactions, returning false if it isn't a valid tweet object.
+    fn add_tweet(&mut self, value: &Value) -> bool {
+        let source = match value.get("user").and_then(user_id) {
+            Some(source) => source,
+            None => return false,
+        };
+        let timestamp = match tweet_timestamp(value) {
+            Some(timestamp) => timestamp,
+            None => return false,
+        };
+
+        if let Some(retweeted) = value.get("retweeted_status") {
+            if let Some(target) = retweeted.get("user").and_then(user_id) {
+                self.add(EdgeKind::Retweet, source, target, timestamp);
+            }
+
+            return true;
+        }
+
+        if let Some(target) = value
+            .get("in_reply_to_user_id_str")
+            .and_then(|value| value.as_str())
+            .and_then(|value| value.parse::<u64>().ok())
+        {
+            self.add(EdgeKind::Reply, source, target, timestamp);
+        }
+
+        if let Some(target) = value
+            .get("quoted_status")
+            .and_then(|status| status.get("user"))
+            .and_then(user_id)
+        {
+            self.add(EdgeKind::Quote, source, target, timestamp);
+        }
+
+        let mentions = value
+            .get("entities")
+            .and_then(|entities| entities.get("user_mentions"))
+            .and_then(|mentions| mentions.as_array());
+
+        for target in mentions.into_iter().flatten().filter_map(user_id) {
+            self.add(EdgeKind::Mention, source, target, timestamp);
+        }
+
+        true
+    }
+
+    fn add(&mut self, kind: EdgeKind, source: u64, target: u64, timestamp: DateTime<Utc>) {
+        if source != target {
+            self.edges
+                .entry((kind, source, target))
+                .or_default()
+                .push(timestamp);
+        }
+    }
+
+    /// Write every interaction in the session with a single batch, returning the number of edges.
+    pub fn update(&self, edges: &EdgeTable<Writeable>) -> Result<usize, Error> {
+        let batch = self
+            .edges
+            .iter()
+            .map(|((kind, source, target), timestamps)| Edge {
+                kind: *kind,
+                source: *source,
+                target: *target,
+                dates: to_dates(timestamps),
+            })
+            .collect::<Vec<_>>();
+
+        Ok(edges.add_all(&batch)?)
+    }
+}
+
+fn user_id(user: &Value) -> Option<u64> {
+    user.get("id_str")?.as_str()?.parse::<u64>().ok()
+}
+
+/// A tweet's creation time, from `timestamp_ms` if it's present (as in streaming API output), then
+/// `created_at`, and finally its snowflake ID.
+fn tweet_timestamp(value: &Value) -> Option<DateTime<Utc>> {
+    let from_timestamp_ms = || timestamp::parse_millis(value.get("timestamp_ms")?.as_str()?).ok();
+    let from_created_at = || {
+        DateTime::parse_from_str(value.get("created_at")?.as_str()?, TWEET_DATE_FORMAT)
+            .ok()
+            .map(|timestamp| timestamp.with_timezone(&Utc))
+    };
+    let from_id = || {
+        let id = value.get("id_str")?.as_str()?.parse::<u64>().ok()?;
+        memory_lol::snowflake::to_date_time(id)
+    };
+
+    from_timestamp_ms()
+        .or_else(from_created_at)
+        .or_else(from_id)
+}
+
+/// The format of `created_at` in Twitter API v1.1 objects.
+const TWEET_DATE_FORMAT: &str = "%a %b %d %H:%M:%S %z %Y";
+
+/// Number of pairs to look up with each batched read when updating the database.
+const READ_BATCH_SIZE: usize = 1_024;
+
+/// Write each observation in an NDJSON stream as soon as it's read, instead of collecting a
+/// session first, so that long-running ingesters can report exactly what every line changed.
+///
+/// Each observation counts as a pair in the report. Reading stops after the current line if a
+/// shutdown is requested.
+#[tracing::instrument(name = "import.stream", skip_all)]
+pub fn import_stream<R: BufRead>(
+    db: &Database<Writeable>,
+    input: R,
+    shutdown: &Shutdown,
+    throttle: &Throttle,
+    events: &Events,
+    handles: &HandleFilter,
+) -> Result<ImportReport, Error> {
+    let started = Instant::now();
+    let mut report = ImportReport::default();
+
+    for line in input.lines() {
+        if shutdown.requested() {
+            log::warn!(
+                "Stream import interrupted after {} lines",
+                report.lines_read
+            );
+            break;
+        }
+
+        let line = line?;
+        report.lines_read += 1;
+
+        let entry = serde_json::from_str(&line)
+            .map_err(Error::from)
+            .and_then(|value| ScreenNameEntry::from_json(&value));
+
+        match entry {
+            Ok(entry) if !handles.accepts(&entry.screen_name) => {
+                log::warn!("Invalid {} handle: {}", handles.platform, entry.screen_name);
+                report.lines_parsed += 1;
+                report.invalid_screen_names += 1;
+            }
+            Ok(entry) => {
+                report.lines_parsed += 1;
+
+                let dates = to_dates(&entry.snapshots);
+                throttle.pace(db, 1, throttle::pair_bytes(&entry.screen_name, dates.len()))?;
+
+                let mut line_events = vec![];
+
+                for date in dates {
+                    let outcome = db.insert_observation(entry.id, &entry.screen_name, date)?;
+                    line_events.extend(report.add_observation(
+                        db,
+                        entry.id,
+                        &entry.screen_name,
+                        date,
+                        outcome,
+                    )?);
+                }
+
+                events.publish(&line_events);
+            }
+            Err(error) => {
+                log::warn!("Skipped line: {}", error);
+                report.lines_skipped += 1;
+            }
+        }
+    }
+
+    report.elapsed = started.elapsed();
+
+    Ok(report)
+}
+
+/// Add the pairs from comma-separated mention lines (in the format read by
+/// [`Session::load_mentions`]) to a bulk load, counting lines and invalid screen names in the
+/// report.
+pub fn bulk_load_mentions<R: Read>(
+    loader: &mut BulkLoader,
+    source: R,
+    handles: &HandleFilter,
+    report: &mut ImportReport,
+) -> Result<(), Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(source);
+
+    for record in reader.records() {
+        let record = record?;
+        report.lines_read += 1;
+
+        let entry = ScreenNameEntry::from_record(&record)?;
+        report.lines_parsed += 1;
+
+        if !handles.accepts(&entry.screen_name) {
+            log::warn!("Invalid {} handle: {}", handles.platform, entry.screen_name);
+            report.invalid_screen_names += 1;
+            continue;
+        }
+
+        match loader.add(entry.id, &entry.screen_name, &to_dates(&entry.snapshots)) {
+            Err(memory_lol::db::Error::InvalidScreenName(screen_name)) => {
+                log::warn!("Invalid screen name: {}", screen_name);
+                report.invalid_screen_names += 1;
+            }
+            other => other?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Compute a hex-encoded SHA-256 hash of the contents of one or more files, in order.
+pub fn hash_files<P: AsRef<Path>>(paths: &[P]) -> Result<String, Error> {
+    let mut hasher = Sha256::new();
+
+    for path in paths {
+        let mut file = File::open(path)?;
+        std::io::copy(&mut file, &mut hasher)?;
+    }
+
+    Ok(to_hex(hasher))
+}
+
+/// The files imported from a Twitter Stream Grab output directory.
+///
+/// Compressed files are used instead of uncompressed copies when both are present.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BatchInputs {
+    pub names: Option<PathBuf>,
+    pub profiles: Option<PathBuf>,
+}
+
+impl BatchInputs {
+    pub fn find(directory: &Path) -> Result<Self, Error> {
+        let first_existing = |file_names: [&str; 2]| {
+            file_names
+                .into_iter()
+                .map(|file_name| directory.join(file_name))
+                .find(|path| path.exists())
+        };
+
+        let inputs = Self {
+            names: first_existing(["names.csv.zst", "names.csv"]),
+            profiles: first_existing(["profiles.ndjson.zst", "profiles.ndjson"]),
+        };
+
+        if inputs.names.is_none() && inputs.profiles.is_none() {
+            Err(Error::EmptyDirectory(directory.to_path_buf()))
+        } else {
+            Ok(inputs)
+        }
+    }
+
+    /// The files that will be opened, in the order they're imported.
+    pub fn paths(&self) -> Vec<&Path> {
+        self.names
+            .iter()
+            .chain(self.profiles.iter())
+            .map(|path| path.as_path())
+            .collect()
+    }
+}
+
+/// Hashes everything read through it, for inputs like standard input that can only be read once.
+pub struct HashingReader<R> {
+    underlying: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(underlying: R) -> Self {
+        Self {
+            underlying,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// The hex-encoded SHA-256 hash of everything read so far, in the same format as
+    /// [`hash_files`].
+    pub fn finish(self) -> String {
+        to_hex(self.hasher)
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.underlying.read(buf)?;
+        self.hasher.update(&buf[..count]);
         Ok(count)
     }
 }
 
+fn to_hex(hasher: Sha256) -> String {
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn count_days(dates: &[NaiveDate]) -> Vec<(NaiveDate, u32)> {
+    let mut counts = HashMap::new();
+
+    for date in dates {
+        *counts.entry(*date).or_insert(0) += 1;
+    }
+
+    counts.into_iter().collect()
+}
+
 fn to_dates(timestamps: &[DateTime<Utc>]) -> Vec<NaiveDate> {
     timestamps
         .iter()
         .map(|timestamp| timestamp.naive_utc().date())
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use memory_lol::db::{edges::Direction, WEIGHTS_TABLE};
+
+    fn entry(id: u64, screen_name: &str, day: u32) -> ScreenNameEntry {
+        ScreenNameEntry {
+            id,
+            screen_name: screen_name.to_string(),
+            snapshots: vec![Utc.with_ymd_and_hms(2020, 1, day, 12, 0, 0).unwrap()],
+        }
+    }
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2020, 1, day).unwrap()
+    }
+
+    #[test]
+    fn entry_timestamps() {
+        let expected = Utc.with_ymd_and_hms(2020, 1, 2, 12, 0, 0).unwrap();
+
+        for snapshot in ["1577966400", "1577966400000", "2020-01-02T12:00:00Z"] {
+            let record = csv::StringRecord::from(vec!["1", "foo", snapshot]);
+            assert_eq!(
+                ScreenNameEntry::from_record(&record).unwrap().snapshots,
+                vec![expected]
+            );
+        }
+
+        let record =
+            csv::StringRecord::from(vec!["1", "foo", "1577966400", "2020-01-02T12:00:00Z"]);
+        assert_eq!(
+            ScreenNameEntry::from_multi_record(&record, timestamp::Format::Auto)
+                .unwrap()
+                .snapshots,
+            vec![expected, expected]
+        );
+        assert!(ScreenNameEntry::from_multi_record(&record, timestamp::Format::Seconds).is_none());
+
+        let value = serde_json::json!({ "id_str": "1", "screen_name": "foo", "snapshot": -1 });
+        assert!(ScreenNameEntry::from_json(&value).is_err());
+        let record = csv::StringRecord::from(vec!["1", "foo", "99999999999999999"]);
+        assert!(ScreenNameEntry::from_record(&record).is_err());
+    }
+
+    #[test]
+    fn session_update() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::<Writeable>::open(dir.path()).unwrap();
+        db.insert(1, "foo", vec![date(1)]).unwrap();
+        db.insert(2, "bar", vec![date(1)]).unwrap();
+
+        let mut session = Session::default();
+        // Unchanged
+        session.add_entry(&entry(1, "foo", 1));
+        // Extended
+        session.add_entry(&entry(2, "bar", 2));
+        // New pair, account, and screen name
+        session.add_entry(&entry(3, "baz", 1));
+        // The same new screen name claimed by two new accounts in one chunk
+        session.add_entry(&entry(4, "Qux", 1));
+        session.add_entry(&entry(5, "qux", 1));
+
+        let (report, _) = session
+            .update_from(&db, UpdateMode::All, None, 0, &Shutdown::default())
+            .unwrap();
+
+        assert_eq!(
+            report,
+            ImportReport {
+                pairs: 5,
+                new_pairs: 3,
+                new_accounts: 3,
+                new_screen_names: 2,
+                dates_added: 4,
+                dates_unchanged: 1,
+                elapsed: report.elapsed,
+                ..Default::default()
+            }
+        );
+        assert_eq!(db.lookup_by_screen_name("qux").unwrap(), vec![4, 5]);
+    }
+
+    #[test]
+    fn session_handle_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::<Writeable>::open(dir.path()).unwrap();
+
+        let mut session = Session::default();
+        session.add_entry(&entry(1, "foo", 1));
+        session.add_entry(&entry(2, "foo_bar_baz_qux_", 1));
+        session.add_entry(&entry(3, "jos\u{e9}", 1));
+        session.add_entry(&entry(4, "jay.bsky.social", 1));
+
+        // Lenient filters accept anything the database can store
+        let mut lenient = Session {
+            data: session.data.clone(),
+            ..Default::default()
+        };
+        lenient.set_handles(HandleFilter::default());
+        assert_eq!(lenient.data.len(), 4);
+
+        session.set_handles(HandleFilter {
+            platform: Platform::Twitter,
+            strict: true,
+        });
+
+        let (report, _) = session
+            .update_from(&db, UpdateMode::All, None, 0, &Shutdown::default())
+            .unwrap();
+
+        assert_eq!(report.pairs, 1);
+        assert_eq!(report.invalid_screen_names, 3);
+        assert!(db
+            .lookup_by_screen_name("jay.bsky.social")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn session_update_weights() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::<Writeable>::open(dir.path().join("db")).unwrap();
+        let weights = WeightTable::<Writeable>::open(dir.path().join(WEIGHTS_TABLE)).unwrap();
+
+        let mut session = Session::default();
+        session.add_entry(&entry(1, "foo", 1));
+        session.add_entry(&entry(1, "foo", 1));
+        session.add_entry(&entry(1, "foo", 3));
+        session.add_entry(&entry(2, "a-b", 1));
+
+        let (report, _) = session
+            .update_from(
+                &db,
+                UpdateMode::Range,
+                Some(&weights),
+                0,
+                &Shutdown::default(),
+            )
+            .unwrap();
+
+        assert_eq!(report.invalid_screen_names, 1);
+        assert_eq!(
+            weights.get(1, "foo").unwrap(),
+            vec![(date(1), 2), (date(3), 1)]
+        );
+        assert!(weights.lookup(2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn session_update_resume() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::<Writeable>::open(dir.path()).unwrap();
+        let weights = WeightTable::<Writeable>::open(dir.path().join(WEIGHTS_TABLE)).unwrap();
+
+        let mut session = Session::default();
+        for id in 0..READ_BATCH_SIZE as u64 + 10 {
+            session.add_entry(&entry(id, "foo", 1));
+        }
+
+        let shutdown = Shutdown::default();
+        shutdown.request();
+
+        let update = |part, shutdown: &Shutdown| {
+            session
+                .update(
+                    &db,
+                    dir.path(),
+                    Checkpoint::start("hash", part),
+                    UpdateMode::Weighted,
+                    Some(&weights),
+                    shutdown,
+                )
+                .unwrap()
+        };
+
+        let (report, interrupted) = update(1, &shutdown);
+        assert!(interrupted);
+        assert_eq!(report.pairs, READ_BATCH_SIZE);
+        assert_eq!(
+            Checkpoint::load(dir.path()).unwrap(),
+            Some(Checkpoint {
+                key: "hash".to_string(),
+                part: 1,
+                pairs: READ_BATCH_SIZE
+            })
+        );
+
+        // Earlier parts are skipped, and the interrupted part resumes where it stopped
+        assert_eq!(update(0, &Shutdown::default()).0.pairs, 0);
+        let (report, interrupted) = update(1, &Shutdown::default());
+        assert!(!interrupted);
+        assert_eq!(report.pairs, 10);
+        assert_eq!(weights.get(0, "foo").unwrap(), vec![(date(1), 1)]);
+
+        Checkpoint::clear(dir.path()).unwrap();
+        assert_eq!(Checkpoint::load(dir.path()).unwrap(), None);
+    }
+
+    #[test]
+    fn load_mentions() {
+        let dir = tempfile::tempdir().unwrap();
+        let edges = EdgeTable::<Writeable>::open(dir.path()).unwrap();
+        let input =
+            "1,foo,1577880000,10\n1,foo,1577966400,10\n1,foo,1577966400,11\n2,bar,1577880000,\n";
+
+        let session = Session::load_mentions(
+            input.as_bytes(),
+            CsvDialect::default(),
+            &ColumnNames::default(),
+        )
+        .unwrap();
+
+        assert_eq!(session.data.len(), 2);
+        assert_eq!(session.update_mentions(&edges).unwrap(), 2);
+        assert_eq!(
+            edges
+                .edges(1, Direction::Incoming, Some(EdgeKind::Mention), None, None)
+                .unwrap()
+                .into_iter()
+                .map(|edge| (edge.source, edge.dates))
+                .collect::<Vec<_>>(),
+            vec![(10, vec![date(1), date(2)]), (11, vec![date(2)])]
+        );
+        assert!(edges
+            .edges(2, Direction::Incoming, None, None, None)
+            .unwrap()
+            .is_empty());
+
+        assert!(matches!(
+            Session::load_mentions(
+                "1,foo,1577880000,bar\n".as_bytes(),
+                CsvDialect::default(),
+                &ColumnNames::default()
+            ),
+            Err(Error::InvalidCsvRecord(_))
+        ));
+    }
+
+    #[test]
+    fn csv_dialects() {
+        let tabs = CsvDialect {
+            delimiter: parse_delimiter("tab").unwrap(),
+            no_quoting: false,
+            header: true,
+        };
+        let input = "id\tscreen_name\tsnapshot\n1\t\"foo\"\t1577880000\n";
+        let session =
+            Session::load_mentions(input.as_bytes(), tabs, &ColumnNames::default()).unwrap();
+        assert_eq!(
+            to_dates(&session.data[&(1, "foo".to_string())]),
+            vec![date(1)]
+        );
+
+        // Rows can have any number of timestamps, and quoted fields can contain the delimiter
+        let input = "\"1\",\"foo\",\"2020-01-01\",1577966400\n2,bar,1577880000\n";
+        let session = Session::load_multi(
+            input.as_bytes(),
+            CsvDialect::default(),
+            timestamp::Format::Auto,
+        )
+        .unwrap();
+        assert_eq!(
+            to_dates(&session.data[&(1, "foo".to_string())]),
+            vec![date(1), date(2)]
+        );
+        assert!(session.data.contains_key(&(2, "bar".to_string())));
+
+        let unquoted = CsvDialect {
+            no_quoting: true,
+            ..CsvDialect::default()
+        };
+        assert!(matches!(
+            Session::load_multi(input.as_bytes(), unquoted, timestamp::Format::Auto),
+            Err(Error::InvalidCsvRecord(_))
+        ));
+        assert!(parse_delimiter(";;").is_err());
+    }
+
+    #[test]
+    fn column_names() {
+        let dialect = CsvDialect {
+            header: true,
+            ..CsvDialect::default()
+        };
+        let names = ColumnNames {
+            id_column: Some("user_id".to_string()),
+            name_column: Some("handle".to_string()),
+            date_column: Some("ts".to_string()),
+        };
+        let input = "ts,notes,handle,user_id\n1577880000,x,foo,1\n2020-01-02,,foo,1\n";
+        let session = Session::load_mentions(input.as_bytes(), dialect, &names).unwrap();
+
+        assert_eq!(
+            to_dates(&session.data[&(1, "foo".to_string())]),
+            vec![date(1), date(2)]
+        );
+        assert!(session.mentions.is_empty());
+
+        let names = ColumnNames {
+            id_column: Some("id".to_string()),
+            ..ColumnNames::default()
+        };
+        assert!(matches!(
+            Session::load_mentions(input.as_bytes(), dialect, &names),
+            Err(Error::MissingColumn(name)) if name == "id"
+        ));
+    }
+
+    #[test]
+    fn load_mentions_tweet_ids() {
+        // Tweet 1212092628029698048 was posted on 2019-12-31
+        let tweet_date = NaiveDate::from_ymd_opt(2019, 12, 31).unwrap();
+        let input = concat!(
+            "1,foo,,10,1212092628029698048\n",
+            "1,foo,1577880000,,1212092628029698048\n",
+            "2,bar,,,1212092628029698048\n",
+        );
+        let session = Session::load_mentions(
+            input.as_bytes(),
+            CsvDialect::default(),
+            &ColumnNames::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            to_dates(&session.data[&(1, "foo".to_string())]),
+            vec![tweet_date, date(1)]
+        );
+        assert_eq!(to_dates(&session.mentions[&(1, 10)]), vec![tweet_date]);
+        assert!(session.data.contains_key(&(2, "bar".to_string())));
+
+        // Neither a timestamp nor a snowflake tweet ID
+        assert!(matches!(
+            Session::load_mentions(
+                "1,foo,,,12\n".as_bytes(),
+                CsvDialect::default(),
+                &ColumnNames::default()
+            ),
+            Err(Error::InvalidCsvRecord(_))
+        ));
+    }
+
+    #[test]
+    fn edge_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let edges = EdgeTable::<Writeable>::open(dir.path()).unwrap();
+        let tweets = [
+            // A reply that quotes and mentions another account, and mentions its own author
+            r#"{"user":{"id_str":"1"},"timestamp_ms":"1577880000000","in_reply_to_user_id_str":"2","quoted_status":{"user":{"id_str":"3"}},"entities":{"user_mentions":[{"id_str":"2"},{"id_str":"1"}]}}"#,
+            // A retweet, whose text mentions the retweeted account
+            r#"{"user":{"id_str":"1"},"created_at":"Thu Jan 02 12:00:00 +0000 2020","retweeted_status":{"user":{"id_str":"2"}},"entities":{"user_mentions":[{"id_str":"2"}]}}"#,
+            // Only the ID gives the time
+            r#"{"id_str":"1212092628029698048","user":{"id_str":"4"},"in_reply_to_user_id_str":"1"}"#,
+            r#"{"delete":{}}"#,
+            "not json",
+        ];
+
+        let session = EdgeSession::load_tweets(tweets.join("\n").as_bytes()).unwrap();
+
+        assert_eq!((session.lines_read, session.lines_skipped), (5, 2));
+        assert_eq!(session.update(&edges).unwrap(), 5);
+        assert_eq!(
+            edges
+                .edges(1, Direction::Outgoing, None, None, None)
+                .unwrap()
+                .into_iter()
+                .map(|edge| (edge.kind, edge.target, edge.dates))
+                .collect::<Vec<_>>(),
+            vec![
+                (EdgeKind::Mention, 2, vec![date(1)]),
+                (EdgeKind::Reply, 2, vec![date(1)]),
+                (EdgeKind::Quote, 3, vec![date(1)]),
+                (EdgeKind::Retweet, 2, vec![date(2)]),
+            ]
+        );
+
+        assert_eq!(
+            edges
+                .edges(1, Direction::Incoming, None, None, None)
+                .unwrap(),
+            vec![Edge {
+                kind: EdgeKind::Reply,
+                source: 4,
+                target: 1,
+                dates: vec![NaiveDate::from_ymd_opt(2019, 12, 31).unwrap()],
+            }]
+        );
+
+        let session = EdgeSession::load_csv("4,5,reply,1577880000\n".as_bytes()).unwrap();
+        assert_eq!(session.update(&edges).unwrap(), 1);
+        assert_eq!(
+            edges
+                .edges(5, Direction::Incoming, None, None, None)
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(matches!(
+            EdgeSession::load_csv("4,5,like,1577880000\n".as_bytes()),
+            Err(Error::InvalidCsvRecord(_))
+        ));
+    }
+
+    #[test]
+    fn batch_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert!(matches!(
+            BatchInputs::find(dir.path()),
+            Err(Error::EmptyDirectory(_))
+        ));
+
+        std::fs::write(dir.path().join("names.csv"), "1,foo,0\n").unwrap();
+        std::fs::write(dir.path().join("names.csv.zst"), "compressed").unwrap();
+        std::fs::write(dir.path().join("profiles.ndjson"), "{}\n").unwrap();
+
+        let inputs = BatchInputs::find(dir.path()).unwrap();
+        let expected = [
+            dir.path().join("names.csv.zst"),
+            dir.path().join("profiles.ndjson"),
+        ];
+
+        assert_eq!(
+            inputs.paths(),
+            expected
+                .iter()
+                .map(|path| path.as_path())
+                .collect::<Vec<_>>()
+        );
+
+        // The uncompressed copy of the names file isn't imported, so it doesn't affect the hash
+        let hash = hash_files(&inputs.paths()).unwrap();
+        std::fs::write(dir.path().join("names.csv"), "2,bar,0\n").unwrap();
+
+        assert_eq!(hash_files(&inputs.paths()).unwrap(), hash);
+        assert_eq!(hash, hash_files(&expected).unwrap());
+    }
+
+    #[test]
+    fn hashing_reader() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("input.ndjson");
+        std::fs::write(&path, "first\nsecond\n").unwrap();
+
+        let mut reader = HashingReader::new(File::open(&path).unwrap());
+        std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+
+        assert_eq!(reader.finish(), hash_files(&[&path]).unwrap());
+    }
+
+    #[test]
+    fn stream_report() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::<Writeable>::open(dir.path()).unwrap();
+        db.insert(1, "foo", vec![date(1), date(3)]).unwrap();
+
+        let snapshot = |day| {
+            Utc.with_ymd_and_hms(2020, 1, day, 12, 0, 0)
+                .unwrap()
+                .timestamp()
+        };
+        let input = [
+            format!(
+                r#"{{"id_str":"1","screen_name":"foo","snapshot":{}}}"#,
+                snapshot(3)
+            ),
+            format!(
+                r#"{{"id_str":"1","screen_name":"foo","snapshot":{}}}"#,
+                snapshot(4)
+            ),
+            format!(
+                r#"{{"id_str":"1","screen_name":"bar","snapshot":{}}}"#,
+                snapshot(4)
+            ),
+            format!(
+                r#"{{"id_str":"2","screen_name":"foo","snapshot":{}}}"#,
+                snapshot(4)
+            ),
+            format!(
+                r#"{{"id_str":"3","screen_name":"a-b","snapshot":{}}}"#,
+                snapshot(4)
+            ),
+            "not json".to_string(),
+        ]
+        .join("\n");
+
+        let report = import_stream(
+            &db,
+            input.as_bytes(),
+            &Shutdown::default(),
+            &Throttle::default(),
+            &Events::default(),
+            &HandleFilter::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            report,
+            ImportReport {
+                lines_read: 6,
+                lines_parsed: 5,
+                lines_skipped: 1,
+                pairs: 5,
+                new_pairs: 2,
+                new_accounts: 1,
+                new_screen_names: 1,
+                invalid_screen_names: 1,
+                dates_added: 3,
+                dates_unchanged: 1,
+                elapsed: report.elapsed,
+            }
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use crate::Error;
+use clap::Parser;
+use memory_lol::db::{imports::ImportTable, DatabaseOptions, ReadOnly, IMPORTS_TABLE};
+use std::path::Path;
+
+#[derive(Debug, Parser)]
+pub enum ImportsCommand {
+    /// List completed imports in CSV format
+    List {
+        /// Only list imports whose source contains this string
+        #[clap(long)]
+        source: Option<String>,
+    },
+}
+
+pub fn run(db: &Path, options: &DatabaseOptions, command: ImportsCommand) -> Result<(), Error> {
+    match command {
+        ImportsCommand::List { source } => {
+            let imports =
+                ImportTable::<ReadOnly>::open_with_options(db.join(IMPORTS_TABLE), options)?;
+
+            for record in imports.records()? {
+                if source
+                    .as_ref()
+                    .map(|source| record.source.contains(source))
+                    .unwrap_or(true)
+                {
+                    println!(
+                        "{},{},{},{},{},{},{},{},{}ms,{}",
+                        record.timestamp.to_rfc3339(),
+                        record.source,
+                        record.hash.unwrap_or_default(),
+                        record.lines_read,
+                        record.lines_skipped,
+                        record.pairs,
+                        record.new_pairs,
+                        record.dates_added,
+                        record.duration_ms,
+                        record.version
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
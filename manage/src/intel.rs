@@ -0,0 +1,328 @@
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+use memory_lol::db::{tags::TagTable, Database, ReadOnly};
+use serde_json::{json, Value};
+use sha1::{Digest, Sha1};
+use std::io::Write;
+
+/// The namespace STIX 2.1 specifies for deterministic identifiers.
+const STIX_NAMESPACE: [u8; 16] = [
+    0x00, 0xab, 0xed, 0xb4, 0xaa, 0x42, 0x46, 0x6c, 0x9c, 0x01, 0xfe, 0xd2, 0x33, 0x15, 0xa9, 0xb7,
+];
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("Database error")]
+    Db(#[from] memory_lol::db::Error),
+    #[error("JSON error")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Formats understood by threat intelligence platforms.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, clap::ValueEnum)]
+pub enum IntelFormat {
+    /// A STIX 2.1 bundle with an identity object for each account
+    Stix,
+    /// A list of MISP attributes for each account's ID and screen names
+    Misp,
+}
+
+/// An account's screen names with their first and last observation dates, in order of first
+/// observation.
+struct History {
+    id: u64,
+    screen_names: Vec<(String, NaiveDate, NaiveDate)>,
+    tags: Vec<String>,
+}
+
+impl History {
+    fn load(
+        db: &Database<ReadOnly>,
+        tags: Option<&TagTable<ReadOnly>>,
+        id: u64,
+    ) -> Result<Option<Self>, Error> {
+        let mut screen_names = db
+            .lookup_by_user_id(id)?
+            .into_iter()
+            .filter_map(|(screen_name, dates)| {
+                let first = *dates.iter().min()?;
+                let last = *dates.iter().max()?;
+                Some((screen_name, first, last))
+            })
+            .collect::<Vec<_>>();
+
+        if screen_names.is_empty() {
+            return Ok(None);
+        }
+
+        screen_names.sort_by(|(screen_name_a, first_a, _), (screen_name_b, first_b, _)| {
+            first_a
+                .cmp(first_b)
+                .then_with(|| screen_name_a.cmp(screen_name_b))
+        });
+
+        let tags = match tags {
+            Some(tags) => tags.tags_for_id(id)?,
+            None => vec![],
+        };
+
+        Ok(Some(Self {
+            id,
+            screen_names,
+            tags,
+        }))
+    }
+
+    fn first_observed(&self) -> NaiveDate {
+        self.screen_names
+            .iter()
+            .map(|(_, first, _)| *first)
+            .min()
+            .unwrap_or_default()
+    }
+
+    fn last_observed(&self) -> NaiveDate {
+        self.screen_names
+            .iter()
+            .map(|(_, _, last)| *last)
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// The most recently observed screen name.
+    fn current_screen_name(&self) -> &str {
+        self.screen_names
+            .iter()
+            .max_by_key(|(_, _, last)| *last)
+            .map(|(screen_name, _, _)| screen_name.as_str())
+            .unwrap_or_default()
+    }
+
+    /// A STIX identity for the account.
+    ///
+    /// The identifier is derived from the user ID, so exporting an account again produces a new
+    /// version of the same object (its modification time is the latest observation).
+    fn to_stix(&self) -> Value {
+        let screen_names = self
+            .screen_names
+            .iter()
+            .map(|(screen_name, first, last)| {
+                json!({
+                    "screen_name": screen_name,
+                    "first_observed": timestamp(*first),
+                    "last_observed": timestamp(*last),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut identity = json!({
+            "type": "identity",
+            "spec_version": "2.1",
+            "id": format!(
+                "identity--{}",
+                uuid_v5(&STIX_NAMESPACE, &format!("twitter:{}", self.id))
+            ),
+            "created": timestamp(self.first_observed()),
+            "modified": timestamp(self.last_observed()),
+            "name": format!("@{}", self.current_screen_name()),
+            "description": format!(
+                "Twitter account {} (observed with {} screen names)",
+                self.id,
+                self.screen_names.len()
+            ),
+            "identity_class": "unknown",
+            "external_references": [{
+                "source_name": "twitter",
+                "external_id": self.id.to_string(),
+                "url": format!("https://twitter.com/intent/user?user_id={}", self.id),
+            }],
+            "x_memory_lol_screen_names": screen_names,
+        });
+
+        if !self.tags.is_empty() {
+            identity["labels"] = json!(self.tags);
+        }
+
+        identity
+    }
+
+    /// MISP attributes for the account's ID and each of its screen names.
+    fn to_misp(&self) -> Vec<Value> {
+        let tags = self
+            .tags
+            .iter()
+            .map(|tag| json!({ "name": tag }))
+            .collect::<Vec<_>>();
+
+        let mut attributes = vec![json!({
+            "type": "twitter-id",
+            "category": "Social network",
+            "value": self.id.to_string(),
+            "comment": "Twitter user ID",
+            "to_ids": false,
+            "first_seen": timestamp(self.first_observed()),
+            "last_seen": timestamp(self.last_observed()),
+            "Tag": tags,
+        })];
+
+        for (screen_name, first, last) in &self.screen_names {
+            attributes.push(json!({
+                "type": "twitter-id",
+                "category": "Social network",
+                "value": screen_name,
+                "comment": format!("Screen name of Twitter user ID {}", self.id),
+                "to_ids": false,
+                "first_seen": timestamp(*first),
+                "last_seen": timestamp(*last),
+                "Tag": tags,
+            }));
+        }
+
+        attributes
+    }
+}
+
+/// Write the handle histories of the given accounts in a threat intelligence format, returning
+/// the number of accounts written (unknown IDs are skipped with a warning).
+pub fn write_intel<W: Write>(
+    db: &Database<ReadOnly>,
+    tags: Option<&TagTable<ReadOnly>>,
+    ids: &[u64],
+    format: IntelFormat,
+    mut writer: W,
+) -> Result<usize, Error> {
+    let mut histories = Vec::with_capacity(ids.len());
+
+    for id in ids {
+        match History::load(db, tags, *id)? {
+            Some(history) => histories.push(history),
+            None => log::warn!("Skipping unknown account: {}", id),
+        }
+    }
+
+    let output = match format {
+        IntelFormat::Stix => json!({
+            "type": "bundle",
+            "id": format!("bundle--{}", uuid_v4()),
+            "objects": histories.iter().map(History::to_stix).collect::<Vec<_>>(),
+        }),
+        IntelFormat::Misp => json!({
+            "Attribute": histories.iter().flat_map(History::to_misp).collect::<Vec<_>>(),
+        }),
+    };
+
+    serde_json::to_writer_pretty(&mut writer, &output)?;
+    writeln!(writer)?;
+    writer.flush()?;
+
+    Ok(histories.len())
+}
+
+/// The start of the day, in the millisecond-precision format STIX uses.
+fn timestamp(date: NaiveDate) -> String {
+    DateTime::<Utc>::from_naive_utc_and_offset(date.and_hms_opt(0, 0, 0).unwrap_or_default(), Utc)
+        .to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// A name-based (SHA-1) UUID.
+fn uuid_v5(namespace: &[u8; 16], name: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(namespace);
+    hasher.update(name.as_bytes());
+
+    let mut bytes = [0; 16];
+    bytes.copy_from_slice(&hasher.finalize()[..16]);
+    bytes[6] = (bytes[6] & 0x0f) | 0x50;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    format_uuid(&bytes)
+}
+
+fn uuid_v4() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    format_uuid(&bytes)
+}
+
+fn format_uuid(bytes: &[u8; 16]) -> String {
+    let hex = bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memory_lol::db::Writeable;
+
+    #[test]
+    fn stix_and_misp() {
+        let dir = tempfile::tempdir().unwrap();
+
+        {
+            let db = Database::<Writeable>::open(dir.path()).unwrap();
+            let date = |day| NaiveDate::from_ymd_opt(2020, 1, day).unwrap();
+
+            db.insert(1, "foo", vec![date(1), date(2)]).unwrap();
+            db.insert(1, "bar", vec![date(3), date(5)]).unwrap();
+        }
+
+        let db = Database::<ReadOnly>::open(dir.path()).unwrap();
+        let mut output = vec![];
+        assert_eq!(
+            write_intel(&db, None, &[1, 2], IntelFormat::Stix, &mut output).unwrap(),
+            1
+        );
+
+        let bundle: Value = serde_json::from_slice(&output).unwrap();
+        let identity = &bundle["objects"][0];
+        assert_eq!(identity["type"], "identity");
+        // Deterministic identifiers are stable across exports
+        assert_eq!(
+            identity["id"],
+            "identity--b1ac9570-39ca-5288-ab0a-b1e1e2a4cdb7"
+        );
+        assert_eq!(identity["name"], "@bar");
+        assert_eq!(identity["created"], "2020-01-01T00:00:00.000Z");
+        assert_eq!(identity["modified"], "2020-01-05T00:00:00.000Z");
+        assert_eq!(
+            identity["x_memory_lol_screen_names"][0]["screen_name"],
+            "foo"
+        );
+        assert_eq!(
+            identity["x_memory_lol_screen_names"][1]["last_observed"],
+            "2020-01-05T00:00:00.000Z"
+        );
+
+        let mut output = vec![];
+        write_intel(&db, None, &[1], IntelFormat::Misp, &mut output).unwrap();
+
+        let attributes: Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(attributes["Attribute"].as_array().unwrap().len(), 3);
+        assert_eq!(attributes["Attribute"][0]["value"], "1");
+        assert_eq!(attributes["Attribute"][2]["value"], "bar");
+
+        // The DNS namespace example from RFC 4122's errata
+        let dns = [
+            0x6b, 0xa7, 0xb8, 0x10, 0x9d, 0xad, 0x11, 0xd1, 0x80, 0xb4, 0x00, 0xc0, 0x4f, 0xd4,
+            0x30, 0xc8,
+        ];
+        assert_eq!(
+            uuid_v5(&dns, "www.example.com"),
+            "2ed6657d-e927-568b-95e1-2665a8aea6a2"
+        );
+    }
+}
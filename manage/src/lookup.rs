@@ -0,0 +1,434 @@
+use crate::encoding::Encoding;
+use chrono::NaiveDate;
+use memory_lol::db::{
+    quarantine,
+    shards::ShardedLookup,
+    tiers::{TierConfig, TieredLookup},
+    Database, DatabaseOptions, ReadOnly,
+};
+use memory_lol::model::Account;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("JSON error")]
+    Json(#[from] serde_json::Error),
+    #[error("CSV error")]
+    Csv(#[from] csv::Error),
+    #[error("Database error")]
+    Db(#[from] memory_lol::db::Error),
+    #[error("Invalid user ID")]
+    InvalidId(String),
+    #[error("Empty query")]
+    EmptyQuery,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, clap::ValueEnum)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+/// Marks a line of input as a user ID (all-digit screen names are valid).
+pub const ID_MARKER: &str = "id:";
+
+/// A line of input interpreted as either a user ID or a screen name.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Query {
+    Id(u64),
+    ScreenName(String),
+}
+
+impl Query {
+    /// Parse a line as a screen name, or as a user ID if it starts with [`ID_MARKER`].
+    pub fn parse(line: &str) -> Result<Option<Self>, Error> {
+        let line = line.trim();
+
+        match line.strip_prefix(ID_MARKER) {
+            Some(id) => Self::parse_id(id).map(Some),
+            None => {
+                let screen_name = line.trim_start_matches('@');
+
+                Ok(if screen_name.is_empty() {
+                    None
+                } else {
+                    Some(Query::ScreenName(screen_name.to_string()))
+                })
+            }
+        }
+    }
+
+    /// Parse a line as a user ID, with or without [`ID_MARKER`].
+    pub fn parse_id(line: &str) -> Result<Self, Error> {
+        let id = line.trim();
+        let id = id.strip_prefix(ID_MARKER).unwrap_or(id).trim();
+
+        id.parse::<u64>()
+            .map(Query::Id)
+            .map_err(|_| Error::InvalidId(id.to_string()))
+    }
+
+    /// Interpret input as either a user ID or a screen name, so that callers don't need to
+    /// classify it themselves.
+    ///
+    /// All-digit input is a user ID, unless it starts with `@` (all-digit screen names are valid).
+    /// Input prefixed with [`ID_MARKER`] is always a user ID.
+    pub fn infer(input: &str) -> Result<Option<Self>, Error> {
+        let input = input.trim();
+
+        if input.starts_with(ID_MARKER)
+            || (!input.is_empty() && input.bytes().all(|byte| byte.is_ascii_digit()))
+        {
+            Self::parse_id(input).map(Some)
+        } else {
+            Self::parse(input)
+        }
+    }
+}
+
+impl std::fmt::Display for Query {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Query::Id(id) => write!(f, "{}", id),
+            Query::ScreenName(screen_name) => write!(f, "{}", screen_name),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QueryResult {
+    pub query: String,
+    pub accounts: Vec<Account>,
+}
+
+/// A database that queries can be resolved against (either a single directory or a set of
+/// shards), which can be shared by the threads serving requests.
+pub trait Source: Sync {
+    fn lookup_by_user_id(
+        &self,
+        user_id: u64,
+    ) -> Result<HashMap<String, Vec<NaiveDate>>, memory_lol::db::Error>;
+    fn lookup_by_screen_name(&self, screen_name: &str) -> Result<Vec<u64>, memory_lol::db::Error>;
+}
+
+impl Source for Database<ReadOnly> {
+    fn lookup_by_user_id(
+        &self,
+        user_id: u64,
+    ) -> Result<HashMap<String, Vec<NaiveDate>>, memory_lol::db::Error> {
+        Database::lookup_by_user_id(self, user_id)
+    }
+
+    fn lookup_by_screen_name(&self, screen_name: &str) -> Result<Vec<u64>, memory_lol::db::Error> {
+        Database::lookup_by_screen_name(self, screen_name)
+    }
+}
+
+impl Source for ShardedLookup<ReadOnly> {
+    fn lookup_by_user_id(
+        &self,
+        user_id: u64,
+    ) -> Result<HashMap<String, Vec<NaiveDate>>, memory_lol::db::Error> {
+        ShardedLookup::lookup_by_user_id(self, user_id)
+    }
+
+    fn lookup_by_screen_name(&self, screen_name: &str) -> Result<Vec<u64>, memory_lol::db::Error> {
+        ShardedLookup::lookup_by_screen_name(self, screen_name)
+    }
+}
+
+impl Source for TieredLookup<ReadOnly> {
+    fn lookup_by_user_id(
+        &self,
+        user_id: u64,
+    ) -> Result<HashMap<String, Vec<NaiveDate>>, memory_lol::db::Error> {
+        TieredLookup::lookup_by_user_id(self, user_id)
+    }
+
+    fn lookup_by_screen_name(&self, screen_name: &str) -> Result<Vec<u64>, memory_lol::db::Error> {
+        TieredLookup::lookup_by_screen_name(self, screen_name)
+    }
+}
+
+/// Open the database at a path as a sharded database, a tiered database, or a single directory,
+/// depending on how it has been set up.
+pub fn open_source(
+    path: &Path,
+    options: &DatabaseOptions,
+) -> Result<Box<dyn Source>, memory_lol::db::Error> {
+    if let Some(shards) = ShardedLookup::<ReadOnly>::open(path, options)? {
+        Ok(Box::new(shards))
+    } else if TierConfig::load(path)?.is_some() {
+        Ok(Box::new(TieredLookup::<ReadOnly>::open(path, options)?))
+    } else {
+        Ok(Box::new(Database::<ReadOnly>::open_with_options(
+            path, options,
+        )?))
+    }
+}
+
+/// Open the database at a path as [`open_source`] does, including the pairs in its quarantine (if
+/// it has one) if requested.
+pub fn open_source_with_quarantine(
+    path: &Path,
+    options: &DatabaseOptions,
+    include_quarantine: bool,
+) -> Result<Box<dyn Source>, memory_lol::db::Error> {
+    let main = open_source(path, options)?;
+    let quarantine_path = quarantine::path(path);
+
+    if include_quarantine && quarantine_path.exists() {
+        Ok(Box::new(WithQuarantine {
+            main,
+            quarantine: Database::open_with_options(quarantine_path, options)?,
+        }))
+    } else {
+        Ok(main)
+    }
+}
+
+/// A database along with the unreviewed pairs in its quarantine.
+struct WithQuarantine {
+    main: Box<dyn Source>,
+    quarantine: Database<ReadOnly>,
+}
+
+impl Source for WithQuarantine {
+    fn lookup_by_user_id(
+        &self,
+        user_id: u64,
+    ) -> Result<HashMap<String, Vec<NaiveDate>>, memory_lol::db::Error> {
+        let mut result = self.main.lookup_by_user_id(user_id)?;
+
+        for (screen_name, dates) in self.quarantine.lookup_by_user_id(user_id)? {
+            let merged = result.entry(screen_name).or_default();
+            merged.extend(dates);
+            merged.sort();
+            merged.dedup();
+        }
+
+        Ok(result)
+    }
+
+    fn lookup_by_screen_name(&self, screen_name: &str) -> Result<Vec<u64>, memory_lol::db::Error> {
+        let mut result = self.main.lookup_by_screen_name(screen_name)?;
+        result.extend(self.quarantine.lookup_by_screen_name(screen_name)?);
+        result.sort_unstable();
+        result.dedup();
+
+        Ok(result)
+    }
+}
+
+pub fn resolve<S: Source + ?Sized>(db: &S, query: &Query) -> Result<QueryResult, Error> {
+    let ids = match query {
+        Query::Id(id) => vec![*id],
+        Query::ScreenName(screen_name) => db.lookup_by_screen_name(screen_name)?,
+    };
+
+    let accounts = ids
+        .into_iter()
+        .map(|id| {
+            let result = db.lookup_by_user_id(id)?;
+            Ok(Account::from_raw_result(id, result))
+        })
+        .collect::<Result<Vec<_>, Error>>()?
+        .into_iter()
+        .filter(|account| !account.screen_names.is_empty())
+        .collect();
+
+    Ok(QueryResult {
+        query: query.to_string(),
+        accounts,
+    })
+}
+
+/// Resolve input that may be either a user ID or a screen name (see [`Query::infer`]).
+pub fn resolve_input<S: Source + ?Sized>(db: &S, input: &str) -> Result<QueryResult, Error> {
+    let query = Query::infer(input)?.ok_or(Error::EmptyQuery)?;
+
+    resolve(db, &query)
+}
+
+/// Split a list of queries separated by commas or whitespace (as pasted from a spreadsheet).
+pub fn split_queries(input: &str) -> impl Iterator<Item = &str> {
+    input
+        .split(|ch: char| ch == ',' || ch.is_whitespace())
+        .filter(|token| !token.is_empty())
+}
+
+/// Resolve each query in a list (see [`split_queries`]), keeping the input token as each result's
+/// query.
+pub fn resolve_all<S: Source + ?Sized>(db: &S, input: &str) -> Result<Vec<QueryResult>, Error> {
+    split_queries(input)
+        .map(|token| {
+            let mut result = resolve_input(db, token)?;
+            result.query = token.to_string();
+
+            Ok(result)
+        })
+        .collect()
+}
+
+/// Writes query results one at a time, so output can start before all queries are resolved.
+///
+/// JSON results are written as an array, and results in binary encodings as a sequence of
+/// self-delimiting values.
+pub enum ResultWriter<W: Write> {
+    Csv(Box<csv::Writer<W>>),
+    Json { writer: W, empty: bool },
+    Encoded { writer: W, encoding: Encoding },
+}
+
+impl<W: Write> ResultWriter<W> {
+    /// The encoding only applies to the JSON format.
+    pub fn new(writer: W, format: Format, encoding: Encoding) -> Result<Self, Error> {
+        match format {
+            Format::Csv => {
+                let mut writer = csv::Writer::from_writer(writer);
+                writer.write_record(["query", "id", "screen_name", "first", "last"])?;
+
+                Ok(Self::Csv(Box::new(writer)))
+            }
+            Format::Json if encoding == Encoding::Json => Ok(Self::Json {
+                writer,
+                empty: true,
+            }),
+            Format::Json => Ok(Self::Encoded { writer, encoding }),
+        }
+    }
+
+    pub fn write(&mut self, result: &QueryResult) -> Result<(), Error> {
+        match self {
+            Self::Csv(writer) => {
+                for account in &result.accounts {
+                    for (screen_name, dates) in &account.screen_names {
+                        let first = dates
+                            .as_ref()
+                            .and_then(|dates| dates.first())
+                            .map(|date| date.format("%Y-%m-%d").to_string())
+                            .unwrap_or_default();
+                        let last = dates
+                            .as_ref()
+                            .and_then(|dates| dates.last())
+                            .map(|date| date.format("%Y-%m-%d").to_string())
+                            .unwrap_or_default();
+
+                        writer.write_record([
+                            result.query.as_str(),
+                            &account.id.to_string(),
+                            screen_name,
+                            &first,
+                            &last,
+                        ])?;
+                    }
+                }
+
+                writer.flush()?;
+            }
+            Self::Json { writer, empty } => {
+                writer.write_all(if *empty { b"[\n" } else { b",\n" })?;
+                serde_json::to_writer(&mut *writer, result)?;
+                writer.flush()?;
+                *empty = false;
+            }
+            Self::Encoded { writer, encoding } => {
+                writer.write_all(&encoding.encode(&serde_json::to_value(result)?))?;
+                writer.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<(), Error> {
+        match self {
+            Self::Csv(mut writer) => writer.flush()?,
+            Self::Json { mut writer, empty } => {
+                writer.write_all(if empty { b"[]\n" } else { b"\n]\n" })?;
+                writer.flush()?;
+            }
+            Self::Encoded { mut writer, .. } => writer.flush()?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Resolve a query on each line of the input (showing progress), writing each result as soon as
+/// it is resolved.
+///
+/// If `ids` is set, every line is treated as a user ID.
+pub fn run_file<S: Source + ?Sized, R: BufRead, W: Write>(
+    db: &S,
+    input: R,
+    ids: bool,
+    format: Format,
+    encoding: Encoding,
+    writer: W,
+) -> Result<(), Error> {
+    let progress = indicatif::ProgressBar::new_spinner();
+    let mut writer = ResultWriter::new(writer, format, encoding)?;
+
+    for line in input.lines() {
+        let line = line?;
+
+        let query = if ids {
+            if line.trim().is_empty() {
+                None
+            } else {
+                Some(Query::parse_id(&line)?)
+            }
+        } else {
+            Query::parse(&line)?
+        };
+
+        if let Some(query) = query {
+            writer.write(&resolve(db, &query)?)?;
+            progress.inc(1);
+        }
+    }
+
+    progress.finish_and_clear();
+
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn infer_queries() {
+        assert_eq!(Query::infer(" 123456 ").unwrap(), Some(Query::Id(123456)));
+        assert_eq!(
+            Query::infer("@123456").unwrap(),
+            Some(Query::ScreenName("123456".to_string()))
+        );
+        assert_eq!(Query::infer("id:42").unwrap(), Some(Query::Id(42)));
+        assert_eq!(
+            Query::infer("@jack").unwrap(),
+            Some(Query::ScreenName("jack".to_string()))
+        );
+        assert_eq!(
+            Query::infer("jack2").unwrap(),
+            Some(Query::ScreenName("jack2".to_string()))
+        );
+        assert_eq!(Query::infer("  ").unwrap(), None);
+        assert!(Query::infer("99999999999999999999999").is_err());
+        assert!(Query::infer("id:jack").is_err());
+    }
+
+    #[test]
+    fn split_pasted_queries() {
+        assert_eq!(
+            split_queries("foo,bar, 123456\n@456,,").collect::<Vec<_>>(),
+            vec!["foo", "bar", "123456", "@456"]
+        );
+    }
+}
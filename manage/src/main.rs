@@ -1,56 +1,980 @@
-use crate::import::{Session, UpdateMode};
-use chrono::{TimeZone, Utc};
-use clap::{ArgAction, Parser};
-use memory_lol::db::{Database, ReadOnly, Table, Writeable};
+use crate::events::{Events, Target};
+use crate::import::{
+    ChangeLog, Checkpoint, ColumnNames, CsvDialect, HandleFilter, ImportReport, Session, UpdateMode,
+};
+use crate::shutdown::Shutdown;
+use crate::throttle::Throttle;
+use crate::{
+    alerts::AlertCommand, holds::HoldCommand, imports::ImportsCommand,
+    namespaces::NamespaceCommand, notes::NoteCommand, quarantine::QuarantineCommand,
+    shards::ShardCommand, tags::TagCommand,
+};
+use chrono::NaiveDate;
+use clap::{ArgAction, CommandFactory, Parser, ValueEnum};
+use dates::{DateFormatter, DateStyle};
+use memory_lol::db::{
+    bulk::{BulkLoader, DEFAULT_RUN_SIZE},
+    changes::{self, ChangeTable},
+    edges::{EdgeKind, EdgeTable},
+    encryption::{EncryptionConfig, Error as EncryptionError, KeySource},
+    imports::ImportTable,
+    latency::{LatencyRecorder, DEFAULT_PUBLISH_INTERVAL},
+    ngrams::NgramTable,
+    notes::NoteTable,
+    options::Compression,
+    phonetic::PhoneticTable,
+    slowlog::SlowQueryLog,
+    stats::StatsTable,
+    tags::TagTable,
+    weights::WeightTable,
+    Database, DatabaseOptions, ReadOnly, Table, Writeable, CHANGES_TABLE, EDGES_TABLE,
+    IMPORTS_TABLE, NGRAMS_TABLE, NOTES_TABLE, PHONETIC_TABLE, STATS_TABLE, TAGS_TABLE,
+    WEIGHTS_TABLE,
+};
+use memory_lol::handles::Platform;
+use memory_lol::model::ImportRecord;
+use memory_lol::ranges::DateRanges;
+use memory_lol::ratelimit::{Cidr, RateLimitConfig, RateLimiter};
+use memory_lol::summary::Summary;
+use memory_lol::timeline::Timeline;
 use simplelog::LevelFilter;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Read};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+use table::Layout;
 use zstd::stream::read::Decoder;
 
+mod alerts;
+mod api;
+mod bench;
+#[cfg(feature = "bot")]
+mod bot;
+mod bursts;
+mod conflicts;
+mod daemon;
+mod dates;
+mod encoding;
+mod events;
+mod export;
+mod feed;
+mod fixture;
+#[cfg(feature = "flight")]
+mod flight;
+mod holds;
+mod http;
 mod import;
+mod imports;
+mod intel;
+mod lookup;
+mod maintenance;
+mod maltego;
+#[cfg(feature = "bot")]
+mod matrix;
+mod namespaces;
+mod notes;
+mod patterns;
+mod presort;
+mod publish;
+mod quarantine;
+mod repl;
+mod report;
+mod shards;
+mod shutdown;
+mod table;
+mod tags;
+#[cfg(feature = "otel")]
+mod telemetry;
+mod throttle;
+mod timestamper;
+mod usage;
+mod warmup;
 
-fn main() -> Result<(), Error> {
-    let opts: Opts = Opts::parse();
+fn main() -> ExitCode {
+    let opts = match Opts::try_parse() {
+        Ok(opts) => opts,
+        Err(error) => return report_parse_error(error),
+    };
+    let error_format = opts.errors;
+
+    match run(opts) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            report_error(&error, error_format);
+            ExitCode::from(error.kind().exit_code())
+        }
+    }
+}
+
+fn run(opts: Opts) -> Result<(), Error> {
     init_logging(opts.verbose)?;
 
+    // Kept until the command finishes, so that buffered spans are flushed
+    #[cfg(feature = "otel")]
+    let _telemetry = opts
+        .otlp_endpoint
+        .as_deref()
+        .map(crate::telemetry::init)
+        .transpose()?;
+
+    let db_options = DatabaseOptions {
+        block_cache_size: opts.block_cache_size,
+        write_buffer_size: opts.write_buffer_size,
+        max_background_jobs: opts.max_background_jobs,
+        compression_per_level: if opts.compression_per_level.is_empty() {
+            None
+        } else {
+            Some(opts.compression_per_level)
+        },
+        bloom_bits: opts.bloom_bits,
+        compression: opts.compression,
+        zstd_dictionary_bytes: opts.zstd_dictionary_bytes,
+        wait_for_writer: opts.wait_for_writer,
+        write_retries: opts.write_retries,
+        encryption: EncryptionConfig {
+            key: opts.encryption_key,
+            previous_keys: opts.previous_encryption_key,
+        }
+        .load()
+        .map_err(memory_lol::db::Error::from)?
+        .map(Arc::new),
+        ..Default::default()
+    };
+
+    let db_path = match opts.db {
+        Some(db_path) => db_path,
+        None if !opts.command.requires_db() => String::new(),
+        None => return Err(Error::MissingDatabasePath),
+    };
+    // A namespace is a complete database of its own, so every command works on it unchanged
+    let db_path = match opts.namespace {
+        Some(name) if !db_path.is_empty() => memory_lol::db::namespaces::path(&db_path, &name)?
+            .to_string_lossy()
+            .into_owned(),
+        _ => db_path,
+    };
+
+    // Compression settings stored by train-dictionary apply to every table we open
+    let db_options = if db_path.is_empty() {
+        db_options
+    } else {
+        db_options.with_stored_compression(&db_path)?
+    };
+
+    let throttle = Throttle::new(opts.max_records_per_sec, opts.max_write_mbps);
+    let throttle = match opts.max_query_p99_ms {
+        Some(millis) if !db_path.is_empty() => {
+            throttle.with_max_query_p99(&db_path, Duration::from_millis(millis))
+        }
+        _ => throttle,
+    };
+    if opts.output_encoding != encoding::Encoding::Json
+        && !matches!(
+            opts.command,
+            Command::Lookup {
+                format: lookup::Format::Json,
+                ..
+            } | Command::LookupFile {
+                format: lookup::Format::Json,
+                ..
+            }
+        )
+    {
+        return Err(Error::Arguments(Opts::command().error(
+            clap::error::ErrorKind::ArgumentConflict,
+            "--output-encoding only applies to lookups with --format json",
+        )));
+    }
+    // Everything else about a quarantined import (its ledger, checkpoints, and the tables it
+    // writes) lives in the quarantine database
+    let db_path = if opts.quarantine {
+        if !opts.command.is_import() {
+            return Err(Error::Arguments(Opts::command().error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--quarantine only applies to import commands",
+            )));
+        }
+
+        memory_lol::db::quarantine::path(&db_path)
+            .to_string_lossy()
+            .into_owned()
+    } else {
+        db_path
+    };
+    let events = Events::new(opts.events)?;
+    let changes = if opts.track_changes && opts.command.is_import() {
+        Some(Arc::new(ChangeTable::<Writeable>::open_with_options(
+            Path::new(&db_path).join(CHANGES_TABLE),
+            &db_options,
+        )?))
+    } else {
+        None
+    };
+    let handles = HandleFilter {
+        platform: opts.platform,
+        strict: opts.strict_handles,
+    };
+
+    let dates = DateFormatter::new(opts.dates);
+
     match opts.command {
-        Command::LookupId { id } => {
-            let db = Database::<ReadOnly>::open(&opts.db)?;
+        Command::LookupId {
+            id,
+            notes,
+            tags,
+            weights,
+            ranges,
+            include_quarantine,
+            layout,
+            limit,
+            pager,
+        } => {
+            let db = lookup::open_source_with_quarantine(
+                Path::new(&db_path),
+                &db_options,
+                include_quarantine,
+            )?;
             let result = db.lookup_by_user_id(id)?;
+
             let mut results = result.iter().collect::<Vec<_>>();
             results.sort_by_key(|(screen_name, _)| screen_name.to_string());
+            let total = results.len();
+            results.truncate(limit.unwrap_or(total));
 
-            for (screen_name, dates) in results {
-                println!(
-                    "{}: {}",
-                    screen_name,
-                    dates
-                        .iter()
-                        .map(|date| date.to_string())
-                        .collect::<Vec<_>>()
-                        .join(", ")
-                );
+            let found = table::paged(pager, |out| -> Result<bool, Error> {
+                let mut found = total > 0;
+                let mut table = table::Table::new(match layout {
+                    Layout::Wide => vec!["screen_name", "first", "last", "days", "dates"],
+                    _ => vec!["screen_name", "first", "last", "days"],
+                });
+
+                for (screen_name, observed) in &results {
+                    let listed = if ranges {
+                        DateRanges::from_dates(observed.iter().copied())
+                            .ranges()
+                            .iter()
+                            .map(|range| dates.format_range(range.start, range.end))
+                            .collect::<Vec<_>>()
+                    } else {
+                        observed.iter().map(|date| dates.format(*date)).collect()
+                    };
+
+                    if layout == Layout::Plain {
+                        writeln!(out, "{}: {}", screen_name, listed.join(", "))?;
+                    } else {
+                        let date = |date: Option<&NaiveDate>| {
+                            date.map(|date| dates.format(*date)).unwrap_or_default()
+                        };
+                        let mut row = vec![
+                            screen_name.to_string(),
+                            date(observed.iter().min()),
+                            date(observed.iter().max()),
+                            observed.len().to_string(),
+                        ];
+
+                        if layout == Layout::Wide {
+                            row.push(listed.join(", "));
+                        }
+                        table.push(row);
+                    }
+                }
+
+                if layout != Layout::Plain && !results.is_empty() {
+                    table.write(out)?;
+                }
+
+                if results.len() < total {
+                    writeln!(out, "# {} more screen names", total - results.len())?;
+                }
+
+                if let Some(hold) = holds::open(Path::new(&db_path), &db_options)?
+                    .map(|holds| holds.get(id))
+                    .transpose()?
+                    .flatten()
+                {
+                    writeln!(out, "# legal hold ({}): {}", hold.placed, hold.reason)?;
+                    found = true;
+                }
+
+                if notes {
+                    let notes_path = Path::new(&db_path).join(NOTES_TABLE);
+
+                    if notes_path.exists() {
+                        let notes =
+                            NoteTable::<ReadOnly>::open_with_options(notes_path, &db_options)?;
+
+                        for note in notes.lookup(id)? {
+                            writeln!(out, "# {} ({}): {}", note.timestamp, note.author, note.text)?;
+                            found = true;
+                        }
+                    }
+                }
+
+                if weights {
+                    let weights_path = Path::new(&db_path).join(WEIGHTS_TABLE);
+
+                    if weights_path.exists() {
+                        let weights =
+                            WeightTable::<ReadOnly>::open_with_options(weights_path, &db_options)?;
+                        let mut results = weights.lookup(id)?.into_iter().collect::<Vec<_>>();
+                        results.sort();
+
+                        for (screen_name, counts) in results {
+                            found = true;
+                            writeln!(
+                                out,
+                                "# {}: {}",
+                                screen_name,
+                                counts
+                                    .iter()
+                                    .map(|(date, count)| {
+                                        format!("{}×{}", dates.format(*date), count)
+                                    })
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            )?;
+                        }
+                    }
+                }
+
+                if tags {
+                    let tags_path = Path::new(&db_path).join(TAGS_TABLE);
+
+                    if tags_path.exists() {
+                        let tags = TagTable::<ReadOnly>::open_with_options(tags_path, &db_options)?;
+                        let tags = tags.tags_for_id(id)?;
+
+                        if !tags.is_empty() {
+                            writeln!(out, "# tags: {}", tags.join(", "))?;
+                            found = true;
+                        }
+                    }
+                }
+
+                Ok(found)
+            })?;
+
+            if !found {
+                return Err(Error::NotFound(id.to_string()));
+            }
+        }
+        Command::Summary { id } => {
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+            let summary = Summary::from_ranges(&db.lookup_ranges_by_user_id(id)?)
+                .ok_or_else(|| Error::NotFound(id.to_string()))?;
+
+            println!("{}", summary);
+        }
+        Command::Lookup {
+            queries,
+            format,
+            include_quarantine,
+        } => {
+            let db = lookup::open_source_with_quarantine(
+                Path::new(&db_path),
+                &db_options,
+                include_quarantine,
+            )?;
+            let results = lookup::resolve_all(&*db, &queries)?;
+            let mut writer =
+                lookup::ResultWriter::new(std::io::stdout().lock(), format, opts.output_encoding)?;
+
+            for result in &results {
+                writer.write(result)?;
+            }
+
+            writer.finish()?;
+        }
+        Command::LookupFile {
+            input,
+            ids,
+            format,
+            output,
+            include_quarantine,
+        } => {
+            let input = BufReader::new(File::open(input)?);
+            let output: Box<dyn Write> = match output {
+                Some(output) => Box::new(File::create(output)?),
+                None => Box::new(std::io::stdout().lock()),
+            };
+
+            let db = lookup::open_source_with_quarantine(
+                Path::new(&db_path),
+                &db_options,
+                include_quarantine,
+            )?;
+            lookup::run_file(&*db, input, ids, format, opts.output_encoding, output)?;
+        }
+        Command::Maltego { listen } => {
+            let db = lookup::open_source(Path::new(&db_path), &db_options)?;
+            maltego::serve(&*db, &listen)?;
+        }
+        #[cfg(feature = "bot")]
+        Command::Bot {
+            listen,
+            slack_signing_secret,
+            discord_public_key,
+            channel_limit,
+        } => {
+            let config = bot::Config {
+                slack_signing_secret: slack_signing_secret
+                    .map(|path| std::fs::read_to_string(path).map(|secret| secret.trim().into()))
+                    .transpose()?,
+                discord_public_key: discord_public_key
+                    .as_deref()
+                    .map(bot::Config::parse_discord_key)
+                    .transpose()?,
+                channel_limit,
+            };
+            let db = lookup::open_source(Path::new(&db_path), &db_options)?;
+            bot::serve(&*db, std::net::TcpListener::bind(&listen)?, &config)?;
+        }
+        #[cfg(feature = "bot")]
+        Command::Matrix {
+            homeserver,
+            user_id,
+            device_id,
+            access_token,
+            store,
+            rooms,
+            room_limit,
+        } => {
+            let config = matrix::Config {
+                homeserver,
+                user_id,
+                device_id,
+                access_token: std::fs::read_to_string(access_token)?.trim().to_string(),
+                store,
+                rooms,
+                room_limit,
+            };
+            let db = lookup::open_source(Path::new(&db_path), &db_options)?;
+            let shutdown = Shutdown::install()?;
+            matrix::run(&*db, &config, &shutdown)?;
+        }
+        #[cfg(feature = "flight")]
+        Command::Flight { listen } => {
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+            flight::serve(db, &listen)?;
+        }
+        Command::Serve {
+            listen,
+            public_url,
+            cors_origins,
+            cors_headers,
+            no_cors,
+            rate_limit,
+            rate_limit_window,
+            rate_limit_exempt,
+            api_keys,
+            rate_limit_state,
+            tls_cert,
+            tls_key,
+            tls_client_ca,
+            slow_query_log,
+            slow_query_ms,
+            admin_token_file,
+            snapshot_dir,
+            snapshot_limit,
+            tsa_url,
+            opentimestamps_calendar,
+            warmup,
+            event_feed,
+        } => {
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+            warm_up(&db, warmup)?;
+            let namespaces = memory_lol::db::namespaces::open_all(&db_path, &db_options)?;
+            let cors = memory_lol::cors::CorsPolicy {
+                allowed_origins: cors_origins,
+                allowed_headers: cors_headers,
+            };
+
+            let limiter = match rate_limit {
+                Some(requests) => {
+                    let api_keys = match api_keys {
+                        Some(path) => std::fs::read_to_string(path)?
+                            .lines()
+                            .map(str::trim)
+                            .filter(|line| !line.is_empty())
+                            .map(str::to_string)
+                            .collect(),
+                        None => vec![],
+                    };
+
+                    Some(RateLimiter::new(RateLimitConfig {
+                        requests,
+                        window_secs: rate_limit_window,
+                        allowed_networks: rate_limit_exempt,
+                        api_keys,
+                        state: rate_limit_state,
+                    })?)
+                }
+                None => None,
+            };
+
+            let admin = match admin_token_file {
+                Some(path) => Some(api::Admin {
+                    token: std::fs::read_to_string(path)?.trim().to_string(),
+                    cache: db_options.cache.clone(),
+                }),
+                None => None,
+            };
+
+            let feed = if event_feed {
+                let tags_path = Path::new(&db_path).join(TAGS_TABLE);
+                let tags = if tags_path.exists() {
+                    Some(TagTable::<ReadOnly>::open_with_options(
+                        tags_path,
+                        &db_options,
+                    )?)
+                } else {
+                    None
+                };
+
+                Some(feed::Feed::new(tags))
+            } else {
+                None
+            };
+
+            let tls = tls_cert.zip(tls_key).map(|(cert, key)| http::Tls {
+                cert,
+                key,
+                client_ca: tls_client_ca,
+            });
+
+            let snapshots = match snapshot_dir.zip(admin.as_ref()) {
+                Some((snapshot_dir, admin)) => Some(api::Snapshots {
+                    store: memory_lol::snapshots::SnapshotStore::new(snapshot_dir)
+                        .with_limit(snapshot_limit),
+                    token: admin.token.clone(),
+                    timestamper: timestamper::Timestamper::new(tsa_url, opentimestamps_calendar)?,
+                }),
+                None => None,
+            };
+
+            api::serve(
+                &db,
+                &namespaces,
+                &listen,
+                public_url.as_deref(),
+                tls.as_ref(),
+                Some(&cors).filter(|_| !no_cors),
+                limiter.as_ref(),
+                open_slow_query_log(slow_query_log, slow_query_ms)?.as_ref(),
+                admin.as_ref(),
+                snapshots.as_ref(),
+                feed.as_ref(),
+            )?;
+        }
+        Command::Repl => {
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+            repl::run(&db)?;
+        }
+        Command::Bench {
+            queries,
+            read_pattern,
+            target,
+            keys,
+            zipf_exponent,
+            seed,
+            replay,
+        } => {
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+            let queries = match replay {
+                Some(replay) => {
+                    bench::read_request_log(BufReader::new(open_input(Path::new(&replay))?))?
+                }
+                None => bench::Workload {
+                    queries,
+                    pattern: read_pattern,
+                    target,
+                    keys,
+                    zipf_exponent,
+                    seed,
+                }
+                .generate(&db)?,
+            };
+
+            let report = bench::run(&db, &queries)?;
+
+            match opts.report {
+                OutputFormat::Text => println!("{}", report),
+                OutputFormat::Json => println!("{}", report.to_json()?),
+            }
+        }
+        Command::Warmup {
+            input,
+            request_log,
+            top,
+        } => {
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+            let queries = match (input, request_log) {
+                (_, Some(request_log)) => warmup::most_frequent(
+                    BufReader::new(open_input(Path::new(&request_log))?),
+                    top,
+                )?,
+                (Some(input), None) => {
+                    warmup::read_keys(BufReader::new(open_input(Path::new(&input))?))?
+                }
+                (None, None) => vec![],
+            };
+
+            let report = warmup::run(&db, &queries)?;
+
+            match opts.report {
+                OutputFormat::Text => println!("{}", report),
+                OutputFormat::Json => println!("{}", serde_json::to_string(&report)?),
+            }
+        }
+        Command::Daemon {
+            socket,
+            publish_latency,
+            slow_query_log,
+            slow_query_ms,
+            warmup,
+        } => {
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+            warm_up(&db, warmup)?;
+            let recorder = if publish_latency {
+                Some(LatencyRecorder::new(&db_path, DEFAULT_PUBLISH_INTERVAL))
+            } else {
+                None
+            };
+
+            let slow_queries = open_slow_query_log(slow_query_log, slow_query_ms)?;
+
+            daemon::serve(&db, socket, recorder.as_ref(), slow_queries.as_ref())?;
+        }
+        Command::Query { socket, query } => {
+            let query = lookup::Query::infer(&query)?.ok_or(Error::InvalidQuery(query))?;
+            let response = daemon::query(socket, &daemon::Request::from_query(query))?;
+
+            println!("{}", serde_json::to_string_pretty(&response)?);
+        }
+        Command::Snowflake { value } => {
+            if let Ok(id) = value.parse::<u64>() {
+                let timestamp = memory_lol::snowflake::to_date_time(id)
+                    .ok_or_else(|| Error::InvalidSnowflake(value.clone()))?;
+
+                println!("Timestamp: {}", timestamp.timestamp());
+                println!("Date: {}", timestamp.to_rfc3339());
+            } else {
+                let timestamp = memory_lol::timestamp::parse(&value)
+                    .map_err(|_| Error::InvalidSnowflake(value.clone()))?;
+                let id = memory_lol::snowflake::from_date_time(&timestamp)
+                    .ok_or_else(|| Error::InvalidSnowflake(value.clone()))?;
+
+                println!("{}", id);
+            }
+        }
+        Command::Completions { shell } => {
+            clap_complete::generate(
+                shell,
+                &mut Opts::command(),
+                env!("CARGO_BIN_NAME"),
+                &mut std::io::stdout(),
+            );
+        }
+        Command::Man => {
+            clap_mangen::Man::new(Opts::command()).render(&mut std::io::stdout())?;
+        }
+        Command::Imports { command } => imports::run(Path::new(&db_path), &db_options, command)?,
+        Command::Shard { command } => shards::run(Path::new(&db_path), &db_options, command)?,
+        Command::Conflicts { limit, format } => {
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+            conflicts::run(&db, limit, format)?;
+        }
+        Command::Aggregates {
+            epsilon,
+            first,
+            last,
+            max_renames_per_account,
+            max_history_length,
+        } => {
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+            let options = memory_lol::db::aggregates::AggregateOptions {
+                epsilon,
+                first,
+                last: last.unwrap_or_else(|| chrono::Utc::now().date_naive()),
+                max_renames_per_account,
+                max_history_length,
+            };
+
+            let aggregates = db.aggregates(&options, &mut rand::thread_rng())?;
+            println!("{}", serde_json::to_string(&aggregates)?);
+        }
+        Command::Cohorts { period, max_offset } => {
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+            let options = memory_lol::db::cohorts::CohortOptions { period, max_offset };
+
+            println!("{}", serde_json::to_string(&db.cohorts(&options)?)?);
+        }
+        Command::NamePatterns { top } => {
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+            let patterns = patterns::Patterns::compute(&db, top)?;
+            println!("{}", serde_json::to_string_pretty(&patterns)?);
+        }
+        Command::Bursts {
+            window,
+            min_renames,
+        } => {
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+            let count = bursts::run(&db, window, min_renames, std::io::stdout().lock())?;
+            log::info!("Found {} accounts with rename bursts", count);
+        }
+        Command::Timeline {
+            id,
+            max_gap_days,
+            data,
+        } => {
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+            let result = db.lookup_by_user_id(id)?;
+
+            if result.is_empty() {
+                return Err(Error::NotFound(id.to_string()));
+            }
+
+            let timeline = Timeline::new(&result, max_gap_days);
+
+            if data {
+                println!("{}", serde_json::to_string(&timeline)?);
+            } else {
+                println!("{}", memory_lol::timeline::vega_lite_spec(id, &timeline));
             }
         }
-        Command::Dump => {
-            let db = Database::<ReadOnly>::open(&opts.db)?;
-            for pair in db.accounts.pairs() {
-                let (id, screen_name, dates) = pair?;
+        Command::DiffAccount { id, from, to } => {
+            let to = to.unwrap_or_else(|| chrono::Utc::now().date_naive());
+
+            if to < from {
+                return Err(Error::InvalidDateRange(from, to));
+            }
+
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+            let result = db.lookup_by_user_id(id)?;
+
+            if result.is_empty() {
+                return Err(Error::NotFound(id.to_string()));
+            }
 
+            for change in memory_lol::timeline::diff(&result, from, to) {
                 println!(
                     "{},{},{}",
-                    id,
-                    screen_name,
-                    dates
-                        .iter()
-                        .map(|date| date.format("%Y-%m-%d").to_string())
-                        .collect::<Vec<_>>()
-                        .join(";")
+                    change.change,
+                    change.screen_name,
+                    dates.format(change.date)
                 );
             }
         }
+        Command::Gaps { tag, min_days } => {
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+            let tags = TagTable::<ReadOnly>::open_with_options(
+                Path::new(&db_path).join(TAGS_TABLE),
+                &db_options,
+            )?;
+
+            for id in tags.members(&tag)? {
+                for gap in memory_lol::timeline::gaps(&db.lookup_by_user_id(id)?, min_days) {
+                    println!(
+                        "{},{},{},{}",
+                        id,
+                        dates.format(gap.start),
+                        dates.format(gap.end),
+                        gap.days
+                    );
+                }
+            }
+        }
+        Command::Report { id, output } => report::run(
+            Path::new(&db_path),
+            &db_options,
+            id,
+            output.as_deref().map(Path::new),
+        )?,
+        Command::Note { command } => notes::run(Path::new(&db_path), &db_options, command)?,
+        Command::Namespace { command } => {
+            namespaces::run(Path::new(&db_path), &db_options, command)?
+        }
+        Command::Quarantine { command } => {
+            quarantine::run(Path::new(&db_path), &db_options, command)?
+        }
+        Command::Hold { command } => holds::run(Path::new(&db_path), &db_options, command)?,
+        Command::Alerts { command } => alerts::run(Path::new(&db_path), &db_options, command)?,
+        Command::Tag { command } => tags::run(Path::new(&db_path), &db_options, command)?,
+        Command::Dump { tag, after, output } => {
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+            let source_sequence = export::source_sequence(&db);
+            let shutdown = Shutdown::install()?;
+            let mut writer = open_output(output.as_deref(), after.is_some())?;
+            // The last account printed in full
+            let mut last = None;
+
+            match tag {
+                Some(tag) => {
+                    let tags = TagTable::<ReadOnly>::open_with_options(
+                        Path::new(&db_path).join(TAGS_TABLE),
+                        &db_options,
+                    )?;
+
+                    for id in tags.members(&tag)? {
+                        if after.is_some_and(|after| id <= after) {
+                            continue;
+                        }
+
+                        if shutdown.requested() {
+                            writer.flush()?;
+                            return Err(Error::Interrupted(last));
+                        }
+
+                        let mut results = db.lookup_by_user_id(id)?.into_iter().collect::<Vec<_>>();
+                        results.sort();
+
+                        for (screen_name, dates) in results {
+                            write_pair(&mut writer, id, &screen_name, &dates)?;
+                        }
+
+                        last = Some(id);
+                    }
+                }
+                None => {
+                    let pairs = match after.and_then(|after| after.checked_add(1)) {
+                        Some(first) => db.accounts.pairs_from(first),
+                        None => db.accounts.pairs(),
+                    };
+
+                    for pair in pairs {
+                        let (id, screen_name, dates) = pair?;
+
+                        if last.is_some_and(|last| last != id) && shutdown.requested() {
+                            writer.flush()?;
+                            return Err(Error::Interrupted(last));
+                        }
+
+                        write_pair(&mut writer, id, &screen_name, &dates)?;
+                        last = Some(id);
+                    }
+                }
+            }
+
+            writer.flush()?;
+
+            if let Some(output) = output {
+                export::write_manifest(&output, source_sequence)?;
+            }
+        }
+        Command::ExportIntel { format, tag, ids } => {
+            if tag.is_none() && ids.is_empty() {
+                return Err(Error::Arguments(Opts::command().error(
+                    clap::error::ErrorKind::MissingRequiredArgument,
+                    "export-intel needs account IDs or --tag",
+                )));
+            }
+
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+            let tags_path = Path::new(&db_path).join(TAGS_TABLE);
+            let tags = if tags_path.exists() {
+                Some(TagTable::<ReadOnly>::open_with_options(
+                    tags_path,
+                    &db_options,
+                )?)
+            } else {
+                None
+            };
+
+            let mut ids = ids;
+
+            if let Some(tag) = tag {
+                match &tags {
+                    Some(tags) => ids.extend(tags.members(&tag)?),
+                    None => return Err(Error::NotFound(tag)),
+                }
+            }
+
+            ids.sort_unstable();
+            ids.dedup();
+
+            let count =
+                intel::write_intel(&db, tags.as_ref(), &ids, format, std::io::stdout().lock())?;
+            log::info!("Exported {} accounts", count);
+        }
+        Command::ExportJson {
+            anonymize,
+            key_file,
+            after,
+            output,
+        } => {
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+            let source_sequence = export::source_sequence(&db);
+            let anonymizer = match key_file.filter(|_| anonymize) {
+                Some(key_file) => Some(read_anonymizer(&key_file)?),
+                None => None,
+            };
+
+            let shutdown = Shutdown::install()?;
+            let (_, stopped) = export::write_json(
+                &db,
+                anonymizer.as_ref(),
+                after,
+                &shutdown,
+                open_output(output.as_deref(), after.is_some())?,
+            )?;
+
+            if let Some(last) = stopped {
+                return Err(Error::Interrupted(Some(last)));
+            }
+
+            if let Some(output) = output {
+                export::write_manifest(&output, source_sequence)?;
+            }
+        }
+        Command::Publish {
+            output,
+            chunk_size,
+            anonymize,
+            key_file,
+            torrent,
+            tracker,
+        } => {
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+            let anonymizer = match key_file.filter(|_| anonymize) {
+                Some(key_file) => Some(read_anonymizer(&key_file)?),
+                None => None,
+            };
+
+            let shutdown = Shutdown::install()?;
+            let report = publish::run(
+                &db,
+                anonymizer.as_ref(),
+                &output,
+                chunk_size,
+                torrent.then_some(tracker.as_slice()),
+                &shutdown,
+            )?;
+
+            println!("{}", report);
+        }
+        Command::ExportVerify { manifest } => {
+            let (manifest, mismatches) = export::verify(&manifest)?;
+
+            for mismatch in &mismatches {
+                eprintln!("{}", mismatch);
+            }
+
+            if !mismatches.is_empty() {
+                return Err(Error::ExportMismatch(mismatches.len()));
+            }
+
+            println!(
+                "{} files, {} records, exported {} at sequence {}",
+                manifest.files.len(),
+                manifest.files.iter().map(|file| file.records).sum::<u64>(),
+                manifest.created.to_rfc3339(),
+                manifest.source_sequence
+            );
+        }
         Command::Stats => {
-            let db = Database::<ReadOnly>::open(&opts.db)?;
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
             if let Some(count) = db.accounts.get_estimated_key_count()? {
                 println!("Estimated account keys: {count}");
             }
@@ -64,24 +988,215 @@ fn main() -> Result<(), Error> {
             println!("Screen names: {}", screen_name_counts.screen_name_count);
             println!("Screen name mappings: {}", screen_name_counts.mapping_count);
         }
+        Command::RefreshStats => {
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+            let stats = StatsTable::<Writeable>::open_with_options(
+                Path::new(&db_path).join(STATS_TABLE),
+                &db_options,
+            )?;
+
+            let (account_counts, _) = db.get_counts()?;
+            let totals = memory_lol::db::stats::Totals {
+                accounts: account_counts.id_count,
+                pairs: account_counts.pair_count,
+            };
+
+            stats.set_totals(&totals)?;
+            println!("Accounts: {}", totals.accounts);
+            println!("Pairs: {}", totals.pairs);
+        }
+        Command::Du => usage::run(Path::new(&db_path), &db_options)?,
         Command::DateCounts => {
-            let db = Database::<ReadOnly>::open(&opts.db)?;
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
             let date_counts = db.accounts.get_date_counts()?;
 
             for (date, count) in date_counts {
-                println!("{},{}", date.format("%Y-%m-%d"), count);
+                println!("{},{}", dates.format(date), count);
             }
         }
         Command::MostScreenNames { count } => {
-            let db = Database::<ReadOnly>::open(&opts.db)?;
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
             let most_screen_names = db.accounts.get_most_screen_names(count)?;
 
             for (id, screen_names) in most_screen_names {
                 println!("{},{},{}", id, screen_names.len(), screen_names.join(";"));
             }
         }
+        Command::GrepNames {
+            pattern,
+            prefix,
+            limit,
+        } => {
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+            // Index keys are lowercased
+            let pattern = regex::RegexBuilder::new(&pattern)
+                .case_insensitive(true)
+                .build()?;
+
+            let matches = db
+                .screen_names
+                .with_prefix(prefix.as_deref().unwrap_or_default())
+                .filter(|result| {
+                    result
+                        .as_ref()
+                        .map_or(true, |(screen_name, _)| pattern.is_match(screen_name))
+                })
+                .take(limit.unwrap_or(usize::MAX));
+
+            for result in matches {
+                let (screen_name, ids) = result?;
+                println!("{},{}", screen_name, ids.len());
+            }
+        }
+        Command::SearchNames { pattern, limit } => {
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+
+            if db.ngrams.is_none() {
+                log::warn!("No trigram index (see build-ngram-index); scanning all screen names");
+            }
+
+            for (screen_name, ids) in db.search_screen_names(&pattern, limit)? {
+                println!("{},{}", screen_name, ids.len());
+            }
+        }
+        Command::MentionsOf { id, since, until } => {
+            print_edges(
+                &db_path,
+                &db_options,
+                id,
+                EdgeDirection::Incoming,
+                Some(EdgeKind::Mention),
+                since,
+                until,
+                &dates,
+            )?;
+        }
+        Command::Edges {
+            id,
+            direction,
+            kind,
+            since,
+            until,
+        } => {
+            print_edges(
+                &db_path,
+                &db_options,
+                id,
+                direction,
+                kind,
+                since,
+                until,
+                &dates,
+            )?;
+        }
+        Command::ImportEdges {
+            input,
+            csv,
+            verify_signature,
+            manifest,
+        } => {
+            verify_input(&input, verify_signature, manifest)?;
+            let edges = EdgeTable::<Writeable>::open_with_options(
+                Path::new(&db_path).join(EDGES_TABLE),
+                &db_options,
+            )?;
+            let source = open_input(Path::new(&input))?;
+
+            let session = if csv {
+                import::EdgeSession::load_csv(source)?
+            } else {
+                import::EdgeSession::load_tweets(BufReader::new(source))?
+            };
+            let count = session.update(&edges)?;
+
+            log::info!(
+                "Recorded {} edges from {} lines ({} skipped)",
+                count,
+                session.lines_read,
+                session.lines_skipped
+            );
+        }
+        Command::LookupSimilar {
+            handle,
+            limit,
+            max_distance,
+        } => {
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+
+            let results = match max_distance {
+                Some(max_distance) => {
+                    let handle = handle.strip_prefix('@').unwrap_or(&handle);
+                    let mut results = db.screen_names_within_distance(handle, max_distance)?;
+                    results.truncate(limit);
+                    results
+                }
+                None => {
+                    if db.phonetic.is_none() {
+                        log::warn!("No phonetic index built; scanning all screen names");
+                    }
+
+                    db.similar_screen_names(&handle, limit)?
+                }
+            };
+
+            for similar in results {
+                let id_strings = similar
+                    .user_ids
+                    .iter()
+                    .map(|id| id.to_string())
+                    .collect::<Vec<_>>();
+                println!(
+                    "{},{},{}",
+                    similar.screen_name,
+                    similar.distance,
+                    id_strings.join(";")
+                );
+            }
+        }
+        Command::BuildPhoneticIndex => {
+            let phonetic_path = Path::new(&db_path).join(PHONETIC_TABLE);
+
+            if phonetic_path.exists() {
+                log::info!("Removing existing phonetic index");
+                std::fs::remove_dir_all(&phonetic_path)?;
+            }
+
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+            let phonetic =
+                PhoneticTable::<Writeable>::open_with_options(&phonetic_path, &db_options)?;
+
+            log::info!("Building phonetic index");
+            phonetic.build(&db.screen_names)?;
+            log::info!("Indexed {} screen names", phonetic.get_counts()?);
+        }
+        Command::Confusables {
+            handle,
+            max_variants,
+        } => {
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+
+            for (screen_name, ids) in db.lookup_confusable(&handle, max_variants)? {
+                let id_strings = ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+                println!("{},{}", screen_name, id_strings.join(";"));
+            }
+        }
+        Command::BuildNgramIndex => {
+            let ngrams_path = Path::new(&db_path).join(NGRAMS_TABLE);
+
+            if ngrams_path.exists() {
+                log::info!("Removing existing trigram index");
+                std::fs::remove_dir_all(&ngrams_path)?;
+            }
+
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
+            let ngrams = NgramTable::<Writeable>::open_with_options(&ngrams_path, &db_options)?;
+
+            log::info!("Building trigram index");
+            ngrams.build(&db.screen_names)?;
+            log::info!("Indexed {} entries", ngrams.get_counts()?);
+        }
         Command::MostReused { count } => {
-            let db = Database::<ReadOnly>::open(&opts.db)?;
+            let db = Database::<ReadOnly>::open_with_options(&db_path, &db_options)?;
             let most_reused = db.screen_names.get_most_reused(count)?;
 
             for (screen_name, ids) in most_reused {
@@ -89,40 +1204,165 @@ fn main() -> Result<(), Error> {
                 println!("{},{},{}", screen_name, ids.len(), id_strings.join(";"));
             }
         }
-        Command::ImportMentions { input, zst } => {
-            let db = Database::<Writeable>::open(&opts.db)?;
-            let file = File::open(input)?;
+        Command::ImportMentions {
+            input,
+            zst,
+            force,
+            mode,
+            record_mentions,
+            verify_signature,
+            manifest,
+            dialect,
+            columns,
+        } => {
+            verify_input(&input, verify_signature, manifest)?;
+            let db = Database::<Writeable>::open_with_options(&db_path, &db_options)?;
+            let imports = ImportTable::<Writeable>::open_with_options(
+                Path::new(&db_path).join(IMPORTS_TABLE),
+                &db_options,
+            )?;
 
-            let source: Box<dyn Read> = if zst {
-                Box::new(Decoder::new(file)?)
-            } else {
-                Box::new(file)
-            };
+            let (mut session, hash) = load_input(&input, zst, &imports, force, |source| {
+                Ok(Session::load_mentions(source, dialect, &columns)?)
+            })?;
+            configure_session(
+                &mut session,
+                &throttle,
+                &events,
+                handles,
+                changes.as_ref(),
+                &hash,
+            );
+            let weights = open_weights(&db_path, &db_options, mode)?;
+            let shutdown = Shutdown::install()?;
+            let (report, interrupted) = session.update(
+                &db,
+                Path::new(&db_path),
+                Checkpoint::start(&hash, 0),
+                mode,
+                weights.as_ref(),
+                &shutdown,
+            )?;
+
+            if interrupted {
+                print_report(&report, opts.report)?;
+                return Err(Error::Interrupted(None));
+            }
 
-            let session = Session::load_mentions(source)?;
-            let count = session.update(&db, UpdateMode::Range)?;
+            if let Some(edges) = open_edges(&db_path, &db_options, record_mentions)? {
+                let count = session.update_mentions(&edges)?;
+                log::info!("Recorded {} mention edges", count);
+            }
+
+            record_import(
+                &db_path,
+                &db_options,
+                &imports,
+                &report.to_record(input_source(&input), Some(hash)),
+            )?;
+            Checkpoint::clear(Path::new(&db_path))?;
+            print_report(&report, opts.report)?;
 
-            log::info!("Updated {} entries", count);
+            if report.lines_skipped > 0 {
+                return Err(Error::PartialImport(report.lines_skipped));
+            }
         }
-        Command::ImportJson { input, zst } => {
-            let db = Database::<Writeable>::open(&opts.db)?;
-            let file = File::open(input)?;
+        Command::ImportJson {
+            input,
+            zst,
+            force,
+            mode,
+            verify_signature,
+            manifest,
+        } => {
+            verify_input(&input, verify_signature, manifest)?;
+            let db = Database::<Writeable>::open_with_options(&db_path, &db_options)?;
+            let imports = ImportTable::<Writeable>::open_with_options(
+                Path::new(&db_path).join(IMPORTS_TABLE),
+                &db_options,
+            )?;
 
-            let source: Box<dyn Read> = if zst {
-                Box::new(Decoder::new(file)?)
-            } else {
-                Box::new(file)
-            };
+            let (mut session, hash) = load_input(&input, zst, &imports, force, |source| {
+                Ok(Session::load_json(BufReader::new(source))?)
+            })?;
+            configure_session(
+                &mut session,
+                &throttle,
+                &events,
+                handles,
+                changes.as_ref(),
+                &hash,
+            );
+            let weights = open_weights(&db_path, &db_options, mode)?;
+            let shutdown = Shutdown::install()?;
+            let (report, interrupted) = session.update(
+                &db,
+                Path::new(&db_path),
+                Checkpoint::start(&hash, 0),
+                mode,
+                weights.as_ref(),
+                &shutdown,
+            )?;
+
+            if interrupted {
+                print_report(&report, opts.report)?;
+                return Err(Error::Interrupted(None));
+            }
+
+            record_import(
+                &db_path,
+                &db_options,
+                &imports,
+                &report.to_record(input_source(&input), Some(hash)),
+            )?;
+            Checkpoint::clear(Path::new(&db_path))?;
+            print_report(&report, opts.report)?;
+
+            if report.lines_skipped > 0 {
+                return Err(Error::PartialImport(report.lines_skipped));
+            }
+        }
+        Command::ImportStream { source } => {
+            let db = Database::<Writeable>::open_with_options(&db_path, &db_options)?;
+            let imports = ImportTable::<Writeable>::open_with_options(
+                Path::new(&db_path).join(IMPORTS_TABLE),
+                &db_options,
+            )?;
+
+            let shutdown = Shutdown::install()?;
+            let mut input = BufReader::new(import::HashingReader::new(std::io::stdin().lock()));
+            let report =
+                import::import_stream(&db, &mut input, &shutdown, &throttle, &events, &handles)?;
 
-            let reader = BufReader::new(source);
+            // Every line read has been written, so the partial import is still recorded
+            record_import(
+                &db_path,
+                &db_options,
+                &imports,
+                &report.to_record(&source, Some(input.into_inner().finish())),
+            )?;
+            print_report(&report, opts.report)?;
 
-            let session = Session::load_json(reader)?;
-            let count = session.update(&db, UpdateMode::Range)?;
+            if shutdown.requested() {
+                return Err(Error::Interrupted(None));
+            }
 
-            log::info!("Updated {} entries", count);
+            if report.lines_skipped > 0 {
+                return Err(Error::PartialImport(report.lines_skipped));
+            }
         }
-        Command::ImportBatch { input, prefix } => {
-            let db = Database::<Writeable>::open(&opts.db)?;
+        Command::ImportBatch {
+            input,
+            prefix,
+            force,
+            mode,
+            record_mentions,
+        } => {
+            let db = Database::<Writeable>::open_with_options(&db_path, &db_options)?;
+            let imports = ImportTable::<Writeable>::open_with_options(
+                Path::new(&db_path).join(IMPORTS_TABLE),
+                &db_options,
+            )?;
             let prefix = prefix.as_ref();
 
             let mut paths = std::fs::read_dir(&input)?
@@ -151,90 +1391,319 @@ fn main() -> Result<(), Error> {
                 .collect::<Result<Vec<_>, std::io::Error>>()?;
             paths.sort();
 
+            let mut total = ImportReport::default();
+            let weights = open_weights(&db_path, &db_options, mode)?;
+            let edges = open_edges(&db_path, &db_options, record_mentions)?;
+            let shutdown = Shutdown::install()?;
+
             for directory in paths {
                 log::info!("Importing directory: {}", directory.to_string_lossy());
 
-                let names_file_zst = directory.join("names.csv.zst");
-                let names_file = directory.join("names.csv");
-
-                let profiles_file_zst = directory.join("profiles.ndjson.zst");
-                let profiles_file = directory.join("profiles.ndjson");
+                let inputs = import::BatchInputs::find(&directory)?;
+                let hash = import::hash_files(&inputs.paths())?;
 
-                let names_source: Option<Box<dyn Read>> = if names_file_zst.exists() {
-                    let file = File::open(names_file_zst)?;
-                    Some(Box::new(Decoder::new(file)?))
-                } else if names_file.exists() {
-                    let file = File::open(names_file)?;
-                    Some(Box::new(file))
-                } else {
-                    None
-                };
+                if !force {
+                    if let Some(record) = imports.find_by_hash(&hash)? {
+                        log::warn!("Skipping directory already imported from {}", record.source);
+                        continue;
+                    }
+                }
 
-                let profiles_source: Option<Box<dyn Read>> = if profiles_file_zst.exists() {
-                    let file = File::open(profiles_file_zst)?;
-                    Some(Box::new(Decoder::new(file)?))
-                } else if profiles_file.exists() {
-                    let file = File::open(profiles_file)?;
-                    Some(Box::new(file))
-                } else {
-                    None
-                };
+                let names_source = inputs.names.as_deref().map(open_input).transpose()?;
+                let profiles_source = inputs.profiles.as_deref().map(open_input).transpose()?;
 
-                let mut count = 0;
+                let mut report = ImportReport::default();
 
                 if let Some(source) = names_source {
                     log::info!("Importing mentions");
-                    let session = Session::load_mentions(source)?;
-                    count += session.update(&db, UpdateMode::Range)?;
+                    let mut session = Session::load_mentions(
+                        source,
+                        CsvDialect::default(),
+                        &ColumnNames::default(),
+                    )?;
+                    configure_session(
+                        &mut session,
+                        &throttle,
+                        &events,
+                        handles,
+                        changes.as_ref(),
+                        &hash,
+                    );
+                    let (part_report, interrupted) = session.update(
+                        &db,
+                        Path::new(&db_path),
+                        Checkpoint::start(&hash, 0),
+                        mode,
+                        weights.as_ref(),
+                        &shutdown,
+                    )?;
+                    report.merge(&part_report);
+
+                    if interrupted {
+                        total.merge(&report);
+                        print_report(&total, opts.report)?;
+                        return Err(Error::Interrupted(None));
+                    }
+
+                    if let Some(edges) = &edges {
+                        let count = session.update_mentions(edges)?;
+                        log::info!("Recorded {} mention edges", count);
+                    }
                 }
 
                 if let Some(source) = profiles_source {
                     log::info!("Importing profiles");
                     let reader = BufReader::new(source);
-                    let session = Session::load_json(reader)?;
-                    count += session.update(&db, UpdateMode::Range)?;
+                    let mut session = Session::load_json(reader)?;
+                    configure_session(
+                        &mut session,
+                        &throttle,
+                        &events,
+                        handles,
+                        changes.as_ref(),
+                        &hash,
+                    );
+                    let (part_report, interrupted) = session.update(
+                        &db,
+                        Path::new(&db_path),
+                        Checkpoint::start(&hash, 1),
+                        mode,
+                        weights.as_ref(),
+                        &shutdown,
+                    )?;
+                    report.merge(&part_report);
+
+                    if interrupted {
+                        total.merge(&report);
+                        print_report(&total, opts.report)?;
+                        return Err(Error::Interrupted(None));
+                    }
                 }
 
-                log::info!("Updated {} entries", count);
+                log::info!("Updated {} entries", report.pairs);
+                record_import(
+                    &db_path,
+                    &db_options,
+                    &imports,
+                    &report.to_record(&directory.to_string_lossy(), Some(hash)),
+                )?;
+                Checkpoint::clear(Path::new(&db_path))?;
+                total.merge(&report);
+
+                if shutdown.requested() {
+                    print_report(&total, opts.report)?;
+                    return Err(Error::Interrupted(None));
+                }
             }
+
+            print_report(&total, opts.report)?;
+
+            if total.lines_skipped > 0 {
+                return Err(Error::PartialImport(total.lines_skipped));
+            }
+        }
+        Command::TrainDictionary { dictionary_bytes } => {
+            maintenance::train_dictionary(Path::new(&db_path), db_options, dictionary_bytes)?
+        }
+        Command::RotateKey => {
+            let db = Database::<Writeable>::open_with_options(&db_path, &db_options)?;
+            let report = db.rotate_key(&db_path)?;
+
+            println!("{}", report);
+        }
+        Command::Prune {
+            max_age_years,
+            thin,
+            dry_run,
+        } => {
+            let db = Database::<Writeable>::open_with_options(&db_path, &db_options)?;
+            // The weight table only exists if there have been weighted imports
+            let weights_path = Path::new(&db_path).join(WEIGHTS_TABLE);
+            let weights = if weights_path.exists() {
+                Some(WeightTable::<Writeable>::open_with_options(
+                    weights_path,
+                    &db_options,
+                )?)
+            } else {
+                None
+            };
+            let held = holds::held_ids(Path::new(&db_path), &db_options)?;
+            let report =
+                maintenance::prune(&db, weights.as_ref(), &held, max_age_years, thin, dry_run)?;
+
+            println!("{}", report);
+        }
+        Command::Demote {
+            older_than,
+            cold,
+            dry_run,
+        } => {
+            let report =
+                maintenance::demote(Path::new(&db_path), &db_options, cold, older_than, dry_run)?;
+
+            println!("{}", report);
         }
         Command::CompactRanges => {
-            let db = Database::<Writeable>::open(&opts.db)?;
-            db.accounts.compact_ranges()?;
+            let db = Database::<Writeable>::open_with_options(&db_path, &db_options)?;
+            let shutdown = Shutdown::install()?;
+
+            if db.accounts.compact_ranges_until(|| shutdown.requested())? {
+                db.flush()?;
+                log::warn!("Compaction interrupted; run it again to finish");
+                return Err(Error::Interrupted(None));
+            }
         }
-        Command::ImportMulti => {
-            let db = Database::<Writeable>::open(&opts.db)?;
-            let stdin = std::io::stdin();
-            for line in stdin.lock().lines() {
-                let line = line?;
-                let parts = line.split(',').collect::<Vec<_>>();
-                let user_id = parts
-                    .first()
-                    .and_then(|value| value.parse::<u64>().ok())
-                    .ok_or_else(|| Error::InvalidImportLine(line.clone()))?;
-                let screen_name = parts
-                    .get(1)
-                    .ok_or_else(|| Error::InvalidImportLine(line.clone()))?;
+        Command::GenerateFixture { accounts, seed } => {
+            let db = Database::<Writeable>::open_with_options(&db_path, &db_options)?;
+
+            if db.accounts.pairs().next().is_some() {
+                return Err(Error::NonEmptyDatabase(db_path));
+            }
+
+            println!("{}", fixture::generate(&db, accounts, seed)?);
+        }
+        Command::Presort {
+            input,
+            output,
+            format,
+            zst,
+            scratch,
+            run_size,
+        } => {
+            let file = File::open(&input)?;
+            let reader: Box<dyn BufRead> = if zst {
+                Box::new(BufReader::new(Decoder::new(file)?))
+            } else {
+                Box::new(BufReader::new(file))
+            };
+            let writer = std::io::BufWriter::new(File::create(&output)?);
+            let scratch = scratch.unwrap_or_else(|| PathBuf::from(format!("{}.tmp", output)));
+
+            let report = presort::presort(reader, writer, format, &scratch, run_size)?;
+            log::info!("Sorted {} lines from {} runs", report.lines, report.runs);
+        }
+        Command::BulkLoad {
+            input,
+            zst,
+            mode,
+            scratch,
+            run_size,
+        } => {
+            if mode == UpdateMode::Weighted {
+                return Err(Error::Arguments(Opts::command().error(
+                    clap::error::ErrorKind::InvalidValue,
+                    "bulk-load doesn't support weighted mode",
+                )));
+            }
+
+            let db = Database::<Writeable>::open_with_options(&db_path, &db_options)?;
+            let imports = ImportTable::<Writeable>::open_with_options(
+                Path::new(&db_path).join(IMPORTS_TABLE),
+                &db_options,
+            )?;
+
+            let started = std::time::Instant::now();
+            let hash = import::hash_files(&input)?;
+            let scratch = scratch.unwrap_or_else(|| Path::new(&db_path).join("bulk-load.tmp"));
+            let mut loader = BulkLoader::new(
+                &db,
+                scratch,
+                &db_options,
+                run_size,
+                mode == UpdateMode::Range,
+            )?;
+            let mut report = ImportReport::default();
 
-                let mut dates = vec![];
+            for path in &input {
+                log::info!("Sorting {}", path);
+                let file = File::open(path)?;
 
-                for part in &parts[2..] {
-                    let timestamp = part
-                        .parse::<i64>()
-                        .ok()
-                        .and_then(|value_timestamp| Utc.timestamp_opt(value_timestamp, 0).single())
-                        .ok_or_else(|| Error::InvalidImportLine(line.clone()))?;
-                    dates.push(timestamp.naive_utc().date());
+                if zst {
+                    import::bulk_load_mentions(
+                        &mut loader,
+                        Decoder::new(file)?,
+                        &handles,
+                        &mut report,
+                    )?;
+                } else {
+                    import::bulk_load_mentions(&mut loader, file, &handles, &mut report)?;
                 }
+            }
+
+            log::info!("Writing and ingesting SST files");
+            let loaded = loader.finish(&db)?;
+            log::info!(
+                "Ingested {} SST files from {} sorted runs",
+                loaded.sst_files,
+                loaded.runs
+            );
 
-                dates.sort();
-                dates.dedup();
+            // The database was empty, so everything loaded is new
+            report.pairs = loaded.pairs;
+            report.new_pairs = loaded.pairs;
+            report.new_accounts = loaded.accounts;
+            report.new_screen_names = loaded.screen_names;
+            report.dates_added = loaded.dates;
+            report.elapsed = started.elapsed();
 
-                db.insert(user_id, screen_name, dates)?;
+            record_import(
+                &db_path,
+                &db_options,
+                &imports,
+                &report.to_record(&input.join(","), Some(hash)),
+            )?;
+            print_report(&report, opts.report)?;
+        }
+        Command::ImportMulti {
+            source,
+            timestamp_format,
+            dialect,
+        } => {
+            let db = Database::<Writeable>::open_with_options(&db_path, &db_options)?;
+            let imports = ImportTable::<Writeable>::open_with_options(
+                Path::new(&db_path).join(IMPORTS_TABLE),
+                &db_options,
+            )?;
+
+            let mut input = BufReader::new(import::HashingReader::new(std::io::stdin().lock()));
+            let mut session = Session::load_multi(&mut input, dialect, timestamp_format)?;
+            let hash = input.into_inner().finish();
+            configure_session(
+                &mut session,
+                &throttle,
+                &events,
+                handles,
+                changes.as_ref(),
+                &hash,
+            );
+            let shutdown = Shutdown::install()?;
+            let (report, interrupted) = session.update(
+                &db,
+                Path::new(&db_path),
+                Checkpoint::start(&hash, 0),
+                UpdateMode::All,
+                None,
+                &shutdown,
+            )?;
+
+            if interrupted {
+                print_report(&report, opts.report)?;
+                return Err(Error::Interrupted(None));
             }
+
+            record_import(
+                &db_path,
+                &db_options,
+                &imports,
+                &report.to_record(&source, Some(hash)),
+            )?;
+            Checkpoint::clear(Path::new(&db_path))?;
+            print_report(&report, opts.report)?;
         }
         Command::Remove => {
-            let db = Database::<Writeable>::open(&opts.db)?;
+            let db = Database::<Writeable>::open_with_options(&db_path, &db_options)?;
+            let held = holds::held_ids(Path::new(&db_path), &db_options)?;
+            let mut held_skipped = 0;
             let stdin = std::io::stdin();
             for line in stdin.lock().lines() {
                 let line = line?;
@@ -247,12 +1716,69 @@ fn main() -> Result<(), Error> {
                     .get(1)
                     .ok_or_else(|| Error::InvalidImportLine(line.clone()))?;
 
-                db.accounts.remove(user_id, screen_name)?;
+                if held.contains(&user_id) {
+                    log::warn!("Not removing {},{}: under legal hold", user_id, screen_name);
+                    held_skipped += 1;
+                } else {
+                    db.accounts.remove(user_id, screen_name)?;
+                }
+            }
+
+            if held_skipped > 0 {
+                return Err(Error::LegalHold(held_skipped));
+            }
+        }
+        Command::RollbackImport { import } => {
+            let changes_path = Path::new(&db_path).join(CHANGES_TABLE);
+
+            if !changes_path.exists() {
+                return Err(Error::NotFound(import));
+            }
+
+            let db = Database::<Writeable>::open_with_options(&db_path, &db_options)?;
+            let changes = ChangeTable::<Writeable>::open_with_options(changes_path, &db_options)?;
+            let recorded = changes.changes(&import)?;
+
+            if recorded.is_empty() {
+                return Err(Error::NotFound(import));
+            }
+
+            let held = holds::held_ids(Path::new(&db_path), &db_options)?;
+            let report = changes::rollback(&db, &recorded, &held)?;
+
+            // Held pairs stay recorded, so the rollback can be finished once the holds are
+            // released
+            changes.clear(&import)?;
+            for (id, screen_name, dates) in &recorded {
+                if held.contains(id) {
+                    changes.record(&import, *id, screen_name, dates)?;
+                }
+            }
+
+            if report.pairs_held == 0 {
+                let imports = ImportTable::<Writeable>::open_with_options(
+                    Path::new(&db_path).join(IMPORTS_TABLE),
+                    &db_options,
+                )?;
+                imports.remove_by_hash(&import)?;
+            }
+
+            println!("{}", report);
+            log::info!("Run refresh-stats to update the dataset totals");
+
+            if report.pairs_held > 0 {
+                return Err(Error::LegalHold(report.pairs_held as usize));
             }
         }
-        Command::RebuildIndex => {
-            let mut db = Database::<Writeable>::open(&opts.db)?;
-            db.rebuild_index()?;
+        Command::RebuildIndex { which } => {
+            let mut db = Database::<Writeable>::open_with_options(&db_path, &db_options)?;
+
+            match which {
+                Index::ScreenNames => {
+                    log::info!("Rebuilding screen name index");
+                    db.rebuild_index()?;
+                }
+            }
         }
     }
 
@@ -265,6 +1791,24 @@ pub enum Error {
     AppDb(#[from] memory_lol::db::Error),
     #[error("Import error")]
     Import(#[from] crate::import::Error),
+    #[error("Lookup error")]
+    Lookup(#[from] crate::lookup::Error),
+    #[error("REPL error")]
+    Repl(#[from] crate::repl::Error),
+    #[error("Daemon error")]
+    Daemon(#[from] crate::daemon::Error),
+    #[error("HTTP server error")]
+    Http(#[from] crate::http::Error),
+    #[error("Export error")]
+    Export(#[from] crate::export::Error),
+    #[error("Presort error")]
+    Presort(#[from] crate::presort::Error),
+    #[error("Intelligence export error")]
+    Intel(#[from] crate::intel::Error),
+    #[error("Event publishing error")]
+    Events(#[from] crate::events::Error),
+    #[error("Benchmark error")]
+    Bench(#[from] crate::bench::Error),
     #[error("I/O error")]
     Io(#[from] std::io::Error),
     #[error("JSON error")]
@@ -273,32 +1817,1174 @@ pub enum Error {
     LogInitialization(#[from] log::SetLoggerError),
     #[error("Invalid import line")]
     InvalidImportLine(String),
+    #[error("Invalid timestamp")]
+    InvalidTimestamp(#[from] memory_lol::timestamp::Error),
+    #[error("Invalid query")]
+    InvalidQuery(String),
+    #[error("Invalid snowflake ID or date")]
+    InvalidSnowflake(String),
+    #[error("Missing database path")]
+    MissingDatabasePath,
+    #[error("Not found")]
+    NotFound(String),
+    #[error("Partial import failure")]
+    PartialImport(usize),
+    #[error("Publishing error")]
+    Publish(#[from] crate::publish::Error),
+    #[error("Export files don't match their manifest")]
+    ExportMismatch(usize),
+    #[error("Records under legal hold were not removed")]
+    LegalHold(usize),
+    #[error("Input has already been imported")]
+    DuplicateImport(String),
+    #[error("An alert with this name already exists")]
+    DuplicateAlert(String),
+    #[error("Invalid arguments")]
+    Arguments(#[source] clap::Error),
+    #[error("Invalid maximum age")]
+    InvalidMaxAge(u32),
+    #[error("Database is not empty")]
+    NonEmptyDatabase(String),
+    #[error("Database is not sharded")]
+    NotSharded(PathBuf),
+    #[error("Invalid shard count")]
+    InvalidShardCount(usize),
+    #[error("No cold tier configured")]
+    MissingColdTier(PathBuf),
+    #[error("Invalid date range: {0} is after {1}")]
+    InvalidDateRange(NaiveDate, NaiveDate),
+    /// Interrupted by a signal, with the last account written if the command can be resumed with
+    /// `--after`
+    #[error(
+        "Interrupted{}",
+        .0.map(|id| format!(" (rerun with --after {} to resume)", id)).unwrap_or_default()
+    )]
+    Interrupted(Option<u64>),
+    #[error("Invalid pattern")]
+    InvalidPattern(#[from] regex::Error),
+    #[error("Warm-up error")]
+    Warmup(#[from] crate::warmup::Error),
+    #[error("Timestamping error")]
+    Timestamper(#[from] crate::timestamper::Error),
+    #[cfg(feature = "otel")]
+    #[error("Telemetry error")]
+    Telemetry(#[from] crate::telemetry::Error),
+    #[cfg(feature = "flight")]
+    #[error("Arrow Flight server error")]
+    Flight(#[from] crate::flight::Error),
+    #[cfg(feature = "bot")]
+    #[error("Chat bot error")]
+    Bot(#[from] crate::bot::Error),
+    #[cfg(feature = "bot")]
+    #[error("Matrix bot error")]
+    Matrix(#[from] crate::matrix::Error),
+}
+
+/// Broad failure categories that determine the process exit code.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ErrorKind {
+    Other,
+    InvalidInput,
+    NotFound,
+    Database,
+    PartialImport,
+    /// Another process is writing to the database
+    Locked,
+    /// Stopped by a signal, after saving progress
+    Interrupted,
+}
+
+impl ErrorKind {
+    fn exit_code(self) -> u8 {
+        match self {
+            ErrorKind::Other => 1,
+            // Matches the exit code clap uses for invalid arguments
+            ErrorKind::InvalidInput => 2,
+            ErrorKind::NotFound => 3,
+            ErrorKind::Database => 4,
+            ErrorKind::PartialImport => 5,
+            ErrorKind::Locked => 6,
+            // The shell convention for processes stopped by SIGINT
+            ErrorKind::Interrupted => 130,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ErrorKind::Other => "other",
+            ErrorKind::InvalidInput => "invalid_input",
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::Database => "database",
+            ErrorKind::PartialImport => "partial_import",
+            ErrorKind::Locked => "locked",
+            ErrorKind::Interrupted => "interrupted",
+        }
+    }
+}
+
+fn db_error_kind(error: &memory_lol::db::Error) -> ErrorKind {
+    match error {
+        memory_lol::db::Error::InvalidScreenName(_)
+        | memory_lol::db::Error::InvalidTag(_)
+        | memory_lol::db::Error::InvalidEpsilon(_)
+        | memory_lol::db::Error::TooManyVariants(_)
+        | memory_lol::db::Error::InvalidNamespace(_) => ErrorKind::InvalidInput,
+        memory_lol::db::Error::NotEmpty => ErrorKind::InvalidInput,
+        memory_lol::db::Error::Encryption(
+            EncryptionError::InvalidKey(_)
+            | EncryptionError::MissingVariable(_)
+            | EncryptionError::InvalidSource(_)
+            | EncryptionError::KeyRequired
+            | EncryptionError::UnknownKey(_),
+        ) => ErrorKind::InvalidInput,
+        memory_lol::db::Error::WriterActive(_) => ErrorKind::Locked,
+        _ => ErrorKind::Database,
+    }
+}
+
+impl Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::AppDb(error) => db_error_kind(error),
+            Error::Import(crate::import::Error::Db(error)) => db_error_kind(error),
+            Error::Import(crate::import::Error::Io(_)) => ErrorKind::Other,
+            Error::Import(_) => ErrorKind::InvalidInput,
+            Error::Lookup(crate::lookup::Error::Db(error)) => db_error_kind(error),
+            Error::Lookup(
+                crate::lookup::Error::InvalidId(_) | crate::lookup::Error::EmptyQuery,
+            ) => ErrorKind::InvalidInput,
+            Error::Lookup(_) => ErrorKind::Other,
+            Error::Repl(crate::repl::Error::Db(error)) => db_error_kind(error),
+            Error::Repl(_) => ErrorKind::Other,
+            Error::Daemon(crate::daemon::Error::Db(error)) => db_error_kind(error),
+            Error::Daemon(_) => ErrorKind::Other,
+            Error::Http(_) => ErrorKind::Other,
+            #[cfg(feature = "otel")]
+            Error::Telemetry(_) => ErrorKind::Other,
+            #[cfg(feature = "flight")]
+            Error::Flight(crate::flight::Error::Address(_)) => ErrorKind::InvalidInput,
+            #[cfg(feature = "flight")]
+            Error::Flight(_) => ErrorKind::Other,
+            #[cfg(feature = "bot")]
+            Error::Bot(crate::bot::Error::InvalidDiscordKey(_)) => ErrorKind::InvalidInput,
+            #[cfg(feature = "bot")]
+            Error::Bot(_) => ErrorKind::Other,
+            #[cfg(feature = "bot")]
+            Error::Matrix(_) => ErrorKind::Other,
+            Error::Export(crate::export::Error::Db(error)) => db_error_kind(error),
+            Error::Export(
+                crate::export::Error::EmptyKey
+                | crate::export::Error::InvalidPublicKey(_)
+                | crate::export::Error::MissingSignature(_)
+                | crate::export::Error::InvalidSignature(_)
+                | crate::export::Error::Unlisted(_)
+                | crate::export::Error::Unverified(_),
+            ) => ErrorKind::InvalidInput,
+            Error::Export(_) => ErrorKind::Other,
+            Error::Publish(crate::publish::Error::Export(crate::export::Error::Db(error))) => {
+                db_error_kind(error)
+            }
+            Error::Publish(crate::publish::Error::NotEmpty(_)) => ErrorKind::InvalidInput,
+            Error::Publish(crate::publish::Error::Interrupted) => ErrorKind::Interrupted,
+            Error::Publish(_) => ErrorKind::Other,
+            Error::Presort(crate::presort::Error::InvalidLine(_)) => ErrorKind::InvalidInput,
+            Error::Presort(_) => ErrorKind::Other,
+            Error::Intel(crate::intel::Error::Db(error)) => db_error_kind(error),
+            Error::Intel(_) => ErrorKind::Other,
+            Error::Events(_) => ErrorKind::Other,
+            Error::Bench(crate::bench::Error::Db(error)) => db_error_kind(error),
+            Error::Bench(crate::bench::Error::Lookup(crate::lookup::Error::Db(error))) => {
+                db_error_kind(error)
+            }
+            Error::Bench(
+                crate::bench::Error::InvalidExponent(_)
+                | crate::bench::Error::EmptyWorkload
+                | crate::bench::Error::Lookup(crate::lookup::Error::InvalidId(_)),
+            ) => ErrorKind::InvalidInput,
+            Error::Bench(_) => ErrorKind::Other,
+            Error::Warmup(crate::warmup::Error::Lookup(crate::lookup::Error::Db(error))) => {
+                db_error_kind(error)
+            }
+            Error::Warmup(
+                crate::warmup::Error::Lookup(crate::lookup::Error::InvalidId(_))
+                | crate::warmup::Error::RequestLog(
+                    crate::bench::Error::EmptyWorkload | crate::bench::Error::Lookup(_),
+                ),
+            ) => ErrorKind::InvalidInput,
+            Error::Warmup(_) => ErrorKind::Other,
+            Error::Timestamper(_) => ErrorKind::Other,
+            Error::Json(_)
+            | Error::InvalidImportLine(_)
+            | Error::InvalidTimestamp(_)
+            | Error::InvalidQuery(_)
+            | Error::InvalidSnowflake(_)
+            | Error::MissingDatabasePath
+            | Error::DuplicateImport(_)
+            | Error::DuplicateAlert(_)
+            | Error::Arguments(_)
+            | Error::InvalidMaxAge(_)
+            | Error::NonEmptyDatabase(_)
+            | Error::NotSharded(_)
+            | Error::InvalidShardCount(_)
+            | Error::MissingColdTier(_)
+            | Error::InvalidDateRange(_, _)
+            | Error::InvalidPattern(_)
+            | Error::LegalHold(_)
+            | Error::ExportMismatch(_) => ErrorKind::InvalidInput,
+            Error::NotFound(_) => ErrorKind::NotFound,
+            Error::Interrupted(_) => ErrorKind::Interrupted,
+            Error::PartialImport(_) => ErrorKind::PartialImport,
+            Error::Io(_) | Error::LogInitialization(_) => ErrorKind::Other,
+        }
+    }
+
+    /// The error message followed by the messages of its sources.
+    fn messages(&self) -> Vec<String> {
+        let mut messages = vec![format!("{}", self)];
+        let mut source = std::error::Error::source(self);
+
+        while let Some(error) = source {
+            messages.push(error.to_string());
+            source = error.source();
+        }
+
+        messages
+    }
+}
+
+/// Output format for errors and import reports.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, clap::ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+fn report_error(error: &Error, format: OutputFormat) {
+    let kind = error.kind();
+
+    match format {
+        OutputFormat::Text => {
+            eprintln!("Error: {}", error.messages().join(": "));
+        }
+        OutputFormat::Json => {
+            let value = serde_json::json!({
+                "error": kind.name(),
+                "exit_code": kind.exit_code(),
+                "messages": error.messages(),
+                "debug": format!("{:?}", error),
+            });
+
+            eprintln!("{}", value);
+        }
+    }
+}
+
+/// Report a command-line parsing error, using the JSON reporter if `--errors json` was given.
+fn report_parse_error(error: clap::Error) -> ExitCode {
+    let format = error_format_from_args(&std::env::args().collect::<Vec<_>>());
+
+    // Help and version output also arrives as an error, and isn't written to stderr
+    if format == OutputFormat::Text || !error.use_stderr() {
+        let _ = error.print();
+        return ExitCode::from(u8::try_from(error.exit_code()).unwrap_or(1));
+    }
+
+    let error = Error::Arguments(error);
+    report_error(&error, format);
+    ExitCode::from(error.kind().exit_code())
+}
+
+/// Find the value of `--errors` in arguments that clap could not parse.
+fn error_format_from_args(args: &[String]) -> OutputFormat {
+    args.iter()
+        .enumerate()
+        .find_map(|(i, arg)| match arg.strip_prefix("--errors") {
+            Some("") => args.get(i + 1).map(|value| value.as_str()),
+            Some(value) => value.strip_prefix('='),
+            None => None,
+        })
+        .and_then(|value| OutputFormat::from_str(value, true).ok())
+        .unwrap_or(OutputFormat::Text)
+}
+
+fn print_report(report: &ImportReport, format: OutputFormat) -> Result<(), Error> {
+    match format {
+        OutputFormat::Text => println!("{}", report),
+        OutputFormat::Json => println!("{}", report.to_json()?),
+    }
+
+    Ok(())
+}
+
+/// Open an input file, decompressing it if it has a `.zst` extension.
+fn open_input(path: &Path) -> Result<Box<dyn Read>, Error> {
+    let file = File::open(path)?;
+
+    if path.extension().is_some_and(|extension| extension == "zst") {
+        Ok(Box::new(Decoder::new(file)?))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+/// Load an import input (a file path, or `-` for standard input), returning the result and the
+/// hash of the input.
+///
+/// A file is hashed and checked against the import ledger before it's loaded, but standard input
+/// can only be read once, so it's hashed while it's loaded and checked afterwards.
+fn load_input<T>(
+    input: &str,
+    zst: bool,
+    imports: &ImportTable<Writeable>,
+    force: bool,
+    load: impl FnOnce(Box<dyn Read + '_>) -> Result<T, Error>,
+) -> Result<(T, String), Error> {
+    let check = |hash: &str| {
+        if !force {
+            if let Some(record) = imports.find_by_hash(hash)? {
+                return Err(Error::DuplicateImport(record.source));
+            }
+        }
+
+        Ok(())
+    };
+
+    if input == "-" {
+        let mut hashing = import::HashingReader::new(std::io::stdin().lock());
+        let source: Box<dyn Read> = if zst {
+            Box::new(Decoder::new(&mut hashing)?)
+        } else {
+            Box::new(&mut hashing)
+        };
+        let value = load(source)?;
+
+        // Anything after the end of a compressed stream is still part of the input
+        std::io::copy(&mut hashing, &mut std::io::sink())?;
+        let hash = hashing.finish();
+        check(&hash)?;
+
+        Ok((value, hash))
+    } else {
+        let hash = import::hash_files(&[input])?;
+        check(&hash)?;
+        let file = File::open(input)?;
+
+        let source: Box<dyn Read> = if zst {
+            Box::new(Decoder::new(file)?)
+        } else {
+            Box::new(file)
+        };
+
+        Ok((load(source)?, hash))
+    }
+}
+
+/// The name recorded in the import ledger for an input path.
+fn input_source(input: &str) -> &str {
+    if input == "-" {
+        "stdin"
+    } else {
+        input
+    }
+}
+
+/// Check an input against its signed manifest, if a public key is given.
+fn verify_input(
+    input: &str,
+    public_key: Option<PathBuf>,
+    manifest: Option<PathBuf>,
+) -> Result<(), Error> {
+    if public_key.is_some() && input == "-" {
+        return Err(Error::Arguments(Opts::command().error(
+            clap::error::ErrorKind::ArgumentConflict,
+            "--verify-signature requires an input file",
+        )));
+    }
+
+    if let Some(public_key) = public_key {
+        let public_key = export::read_public_key(&public_key)?;
+        let manifest = export::verify_signed(Path::new(input), manifest.as_deref(), &public_key)?;
+
+        log::info!(
+            "Verified {} against a manifest signed at sequence {}",
+            input,
+            manifest.source_sequence
+        );
+    }
+
+    Ok(())
+}
+
+/// Add a completed import to the import ledger and to the dataset statistics.
+fn record_import(
+    db_path: &str,
+    options: &DatabaseOptions,
+    imports: &ImportTable<Writeable>,
+    record: &ImportRecord,
+) -> Result<(), Error> {
+    imports.add(record)?;
+
+    let stats =
+        StatsTable::<Writeable>::open_with_options(Path::new(db_path).join(STATS_TABLE), options)?;
+    stats.record_import(record)?;
+
+    Ok(())
+}
+
+/// Apply the options every import command shares to a loaded session, recording its changes
+/// under the input's hash if changes are tracked.
+fn configure_session(
+    session: &mut Session,
+    throttle: &Throttle,
+    events: &Events,
+    handles: HandleFilter,
+    changes: Option<&Arc<ChangeTable<Writeable>>>,
+    hash: &str,
+) {
+    session.set_throttle(throttle.clone());
+    session.set_events(events.clone());
+    session.set_handles(handles);
+    session.set_changes(changes.map(|table| ChangeLog::new(table.clone(), hash)));
+}
+
+/// Open the weight table if the update mode requires it.
+fn open_weights(
+    db_path: &str,
+    options: &DatabaseOptions,
+    mode: UpdateMode,
+) -> Result<Option<WeightTable<Writeable>>, Error> {
+    if mode == UpdateMode::Weighted {
+        Ok(Some(WeightTable::open_with_options(
+            Path::new(db_path).join(WEIGHTS_TABLE),
+            options,
+        )?))
+    } else {
+        Ok(None)
+    }
+}
+
+fn open_edges(
+    db_path: &str,
+    options: &DatabaseOptions,
+    record_mentions: bool,
+) -> Result<Option<EdgeTable<Writeable>>, Error> {
+    if record_mentions {
+        Ok(Some(EdgeTable::open_with_options(
+            Path::new(db_path).join(EDGES_TABLE),
+            options,
+        )?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Print an account's edges as `date,kind,source,target` rows in date order.
+#[allow(clippy::too_many_arguments)]
+fn print_edges(
+    db_path: &str,
+    options: &DatabaseOptions,
+    id: u64,
+    direction: EdgeDirection,
+    kind: Option<EdgeKind>,
+    since: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+    dates: &DateFormatter,
+) -> Result<(), Error> {
+    let edges =
+        EdgeTable::<ReadOnly>::open_with_options(Path::new(db_path).join(EDGES_TABLE), options)?;
+
+    let mut rows = edges
+        .edges(id, direction.into(), kind, since, until)?
+        .into_iter()
+        .flat_map(|edge| {
+            edge.dates
+                .into_iter()
+                .map(move |date| (date, edge.kind, edge.source, edge.target))
+        })
+        .collect::<Vec<_>>();
+    rows.sort();
+
+    for (date, kind, source, target) in rows {
+        println!("{},{},{},{}", dates.format(date), kind, source, target);
+    }
+
+    Ok(())
+}
+
+fn write_pair<W: Write>(
+    writer: &mut W,
+    id: u64,
+    screen_name: &str,
+    dates: &[NaiveDate],
+) -> Result<(), Error> {
+    Ok(writeln!(
+        writer,
+        "{},{},{}",
+        id,
+        screen_name,
+        dates
+            .iter()
+            .map(|date| date.format("%Y-%m-%d").to_string())
+            .collect::<Vec<_>>()
+            .join(";")
+    )?)
+}
+
+fn read_anonymizer(key_file: &str) -> Result<export::Anonymizer, Error> {
+    let key = std::fs::read(key_file)?;
+    // Ignore a trailing newline, which editors often add
+    let key = key.strip_suffix(b"\n").unwrap_or(&key);
+
+    Ok(export::Anonymizer::new(key)?)
+}
+
+/// Open an export's output file (appending to it when resuming), or stdout.
+fn open_output(path: Option<&Path>, append: bool) -> Result<Box<dyn Write>, Error> {
+    match path {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(append)
+                .truncate(!append)
+                .open(path)?;
+
+            Ok(Box::new(std::io::BufWriter::new(file)))
+        }
+        None => Ok(Box::new(std::io::stdout().lock())),
+    }
 }
 
 #[derive(Debug, Parser)]
-#[clap(name = "manage", version, author)]
+#[clap(name = env!("CARGO_BIN_NAME"), version, author)]
 struct Opts {
     /// Level of verbosity
     #[clap(short, long, action = ArgAction::Count)]
     verbose: u8,
-    /// Database directory path
+    /// Error output format
+    #[clap(long, value_enum, default_value = "text")]
+    errors: OutputFormat,
+    /// Import and benchmark report output format
+    #[clap(long, value_enum, default_value = "text")]
+    report: OutputFormat,
+    /// Encoding of lookup results in the JSON format (binary encodings are written as a sequence
+    /// of values, one per result, instead of an array)
+    #[clap(long, value_enum, default_value = "json")]
+    output_encoding: encoding::Encoding,
+    /// How dates are shown in text output
+    #[clap(long, value_enum, default_value = "iso")]
+    dates: DateStyle,
+    /// RocksDB block cache size in bytes
+    #[clap(long)]
+    block_cache_size: Option<usize>,
+    /// RocksDB write buffer size in bytes
+    #[clap(long)]
+    write_buffer_size: Option<usize>,
+    /// RocksDB maximum number of background jobs
+    #[clap(long)]
+    max_background_jobs: Option<i32>,
+    /// Comma-separated RocksDB compression types by level (none, snappy, or zstd)
+    #[clap(long, value_delimiter = ',')]
+    compression_per_level: Vec<Compression>,
+    /// RocksDB bloom filter bits per key
+    #[clap(long)]
+    bloom_bits: Option<f64>,
+    /// RocksDB compression type for all levels (none, snappy, or zstd)
+    #[clap(long)]
+    compression: Option<Compression>,
+    /// Maximum Zstd dictionary size in bytes (enables dictionary compression)
+    #[clap(long)]
+    zstd_dictionary_bytes: Option<i32>,
+    /// Wait for another process writing to the database to finish instead of failing
+    #[clap(long)]
+    wait_for_writer: bool,
+    /// Number of times to retry a write that fails with a transient RocksDB error, with
+    /// exponential backoff (0 to fail immediately)
+    #[clap(long)]
+    write_retries: Option<u32>,
+    /// Encrypt account table values with this key (file:<path>, env:<variable>, or
+    /// command:<command> printing 64 hex digits)
+    #[clap(long, global = true)]
+    encryption_key: Option<KeySource>,
+    /// Earlier encryption key that values may still be sealed with (repeatable)
+    #[clap(long, global = true, requires = "encryption_key")]
+    previous_encryption_key: Vec<KeySource>,
+    /// Maximum number of records an import writes per second
+    #[clap(long)]
+    max_records_per_sec: Option<f64>,
+    /// Maximum (approximate) megabytes of data an import writes per second
     #[clap(long)]
-    db: String,
+    max_write_mbps: Option<f64>,
+    /// Pause imports while a query server using the database (started with --publish-latency)
+    /// reports a p99 latency above this many milliseconds
+    #[clap(long)]
+    max_query_p99_ms: Option<u64>,
+    /// Publish events for new accounts, new screen names, and screen names that change hands
+    /// during imports, to a webhook (`https://...`), a Kafka REST proxy
+    /// (`kafka+http://host:8082/topics/<topic>`), or a NATS subject (`nats://host:4222/<subject>`)
+    #[clap(long)]
+    events: Option<Target>,
+    /// Platform whose handle rules imports enforce with --strict-handles (twitter, mastodon, or
+    /// bluesky)
+    #[clap(long, default_value = "twitter")]
+    platform: Platform,
+    /// Reject imported handles that don't follow the platform's rules (for example URLs or
+    /// punctuation picked up by a parser), instead of accepting anything the database can store
+    #[clap(long)]
+    strict_handles: bool,
+    /// Record the observation dates each import adds (and the pairs it creates), so that it can
+    /// be undone with rollback-import
+    #[clap(long)]
+    track_changes: bool,
+    /// Write an import to the database's quarantine instead of the main store, where it's only
+    /// visible to lookups with --include-quarantine until it's promoted
+    #[clap(long)]
+    quarantine: bool,
+    /// Work on a named namespace of the database (a separate dataset with its own ledger and
+    /// stats) instead of the main store
+    #[clap(long)]
+    namespace: Option<String>,
+    /// Export trace spans for lookups, imports, and served requests to an OTLP/HTTP endpoint
+    /// (for example http://localhost:4318/v1/traces)
+    #[cfg(feature = "otel")]
+    #[clap(long)]
+    otlp_endpoint: Option<String>,
+    /// Database directory path (required by every command except query, snowflake, presort,
+    /// completions, and man)
+    #[clap(long)]
+    db: Option<String>,
     #[clap(subcommand)]
     command: Command,
 }
 
+// Parsed once, so the size of the largest variant doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Parser)]
 enum Command {
     /// Look up a Twitter user ID in the database
     LookupId {
         /// Twitter user ID
         id: u64,
+        /// Include analyst notes
+        #[clap(long)]
+        notes: bool,
+        /// Include tags the account belongs to
+        #[clap(long)]
+        tags: bool,
+        /// Include per-day observation counts (recorded by weighted imports)
+        #[clap(long)]
+        weights: bool,
+        /// Print runs of consecutive dates as ranges (`<first>..<last>`)
+        #[clap(long)]
+        ranges: bool,
+        /// Include unreviewed pairs from the quarantine
+        #[clap(long)]
+        include_quarantine: bool,
+        /// How screen names and their dates are laid out
+        #[clap(long, value_enum, default_value = "plain")]
+        layout: Layout,
+        /// Print at most this many screen names
+        #[clap(long)]
+        limit: Option<usize>,
+        /// Page the output (through `$PAGER`, or `less`) when writing to a terminal
+        #[clap(long)]
+        pager: bool,
+    },
+    /// Print a one-line summary of an account's screen names (like
+    /// `@current (a.k.a. @old 2019–2021)`)
+    Summary {
+        /// Twitter user ID
+        id: u64,
+    },
+    /// Manage named lists of accounts
+    Tag {
+        #[clap(subcommand)]
+        command: TagCommand,
+    },
+    /// Look up a comma-separated list of Twitter user IDs and screen names
+    ///
+    /// All-digit values are treated as user IDs unless prefixed with "@". Each result is labeled
+    /// with the value it was found for.
+    Lookup {
+        /// User IDs and screen names (for example "foo,bar,123456")
+        queries: String,
+        /// Output format
+        #[clap(long, value_enum, default_value = "csv")]
+        format: lookup::Format,
+        /// Include unreviewed pairs from the quarantine
+        #[clap(long)]
+        include_quarantine: bool,
+    },
+    /// Look up every Twitter user ID or screen name listed in a file
+    LookupFile {
+        /// File with one screen name or user ID (prefixed with "id:") per line
+        #[clap(long)]
+        input: String,
+        /// Treat every line as a user ID
+        #[clap(long)]
+        ids: bool,
+        /// Output format
+        #[clap(long, value_enum, default_value = "csv")]
+        format: lookup::Format,
+        /// Output file path (defaults to stdout)
+        #[clap(long)]
+        output: Option<String>,
+        /// Include unreviewed pairs from the quarantine
+        #[clap(long)]
+        include_quarantine: bool,
+    },
+    /// Start an interactive prompt that keeps the database open
+    Repl,
+    /// Time lookups against a synthetic or replayed workload
+    Bench {
+        /// Number of synthetic queries
+        #[clap(long, default_value = "100000")]
+        queries: usize,
+        /// Distribution of synthetic queries over sampled keys
+        #[clap(long, value_enum, default_value = "uniform")]
+        read_pattern: bench::ReadPattern,
+        /// Kind of lookup for synthetic queries
+        #[clap(long, value_enum, default_value = "id")]
+        target: bench::Target,
+        /// Number of keys to sample from the database
+        #[clap(long, default_value = "10000")]
+        keys: usize,
+        /// Exponent for the Zipf read pattern
+        #[clap(long, default_value = "1.0")]
+        zipf_exponent: f64,
+        /// Random seed (use the same seed to repeat a workload)
+        #[clap(long, default_value = "0")]
+        seed: u64,
+        /// Replay the lookups in a web server request log instead (optionally Zstd-compressed)
+        #[clap(long)]
+        replay: Option<String>,
+    },
+    /// Look up frequently queried accounts to load their blocks into the operating system's page
+    /// cache, for example after a deploy (servers can do the same in their own block cache with
+    /// --warmup)
+    Warmup {
+        /// File of user IDs and screen names, one per line
+        #[clap(long, required_unless_present = "request_log")]
+        input: Option<String>,
+        /// Use the most frequent lookups in a web server request log instead (optionally
+        /// Zstd-compressed)
+        #[clap(long, conflicts_with = "input")]
+        request_log: Option<String>,
+        /// Number of distinct lookups to use from the request log
+        #[clap(long, default_value = "10000", requires = "request_log")]
+        top: usize,
+    },
+    /// Serve queries over a Unix domain socket
+    Daemon {
+        /// Socket path
+        #[clap(long)]
+        socket: String,
+        /// Periodically write the p99 query latency to the database directory, so that imports
+        /// run with --max-query-p99-ms can back off
+        #[clap(long)]
+        publish_latency: bool,
+        /// Append requests slower than --slow-query-ms to this file as NDJSON, with their RocksDB
+        /// perf-context counters
+        #[clap(long)]
+        slow_query_log: Option<PathBuf>,
+        /// Slow query threshold in milliseconds
+        #[clap(long, default_value = "100", requires = "slow_query_log")]
+        slow_query_ms: u64,
+        /// Look up the user IDs and screen names in this file (one per line) before listening, so
+        /// that their blocks are already cached when requests arrive
+        #[clap(long)]
+        warmup: Option<String>,
+    },
+    /// Serve a Maltego transform over HTTP that returns the screen name history of a Twitter
+    /// entity (by ID or screen name)
+    ///
+    /// Add it to Maltego as a local TRX transform with the URL `http://<address>/history`.
+    Maltego {
+        /// Address to listen on
+        #[clap(long, default_value = "127.0.0.1:8081")]
+        listen: String,
+    },
+    /// Answer `/whois <screen name or user ID>` commands from Slack or Discord with summaries of
+    /// the matching accounts' screen names
+    ///
+    /// Point a Slack slash command at `http://<address>/slack/commands`, or a Discord
+    /// application's interactions endpoint at `http://<address>/discord/interactions` (behind a
+    /// TLS-terminating proxy, since both platforms require HTTPS).
+    #[cfg(feature = "bot")]
+    Bot {
+        /// Address to listen on
+        #[clap(long, default_value = "127.0.0.1:8082")]
+        listen: String,
+        /// File containing the Slack app's signing secret
+        #[clap(long, required_unless_present = "discord_public_key")]
+        slack_signing_secret: Option<PathBuf>,
+        /// The Discord application's public key (in hex)
+        #[clap(long)]
+        discord_public_key: Option<String>,
+        /// Commands each channel can run per minute
+        #[clap(long, default_value = "10")]
+        channel_limit: u32,
+    },
+    /// Answer `!lol <screen name or user ID>` messages in Matrix rooms with summaries of the
+    /// matching accounts' screen names
+    #[cfg(feature = "bot")]
+    Matrix {
+        /// Base URL of the bot account's homeserver
+        #[clap(long)]
+        homeserver: String,
+        /// The bot account's user ID (like `@memory-lol:matrix.org`)
+        #[clap(long)]
+        user_id: String,
+        /// ID of the device the access token was issued for (returned by the login that created
+        /// it)
+        #[clap(long)]
+        device_id: String,
+        /// File containing the bot account's access token
+        #[clap(long)]
+        access_token: PathBuf,
+        /// Directory to keep the bot's encryption keys and sync state in (it must be kept between
+        /// runs for the bot to read encrypted rooms)
+        #[clap(long)]
+        store: PathBuf,
+        /// Room to join and answer commands in (an ID or alias; repeatable)
+        #[clap(long = "room", required = true)]
+        rooms: Vec<String>,
+        /// Commands each room can run per minute
+        #[clap(long, default_value = "10")]
+        room_limit: u32,
+    },
+    /// Serve the dataset and lookup results as Arrow record batches over Arrow Flight (gRPC)
+    ///
+    /// The ticket `pairs` streams every pair in user ID order, and `lookup:<queries>` the results
+    /// of a comma-separated list of user IDs and screen names, with `id`, `screen_name`, and
+    /// `dates` columns (and a `query` column for lookups).
+    #[cfg(feature = "flight")]
+    Flight {
+        /// Address to listen on
+        #[clap(long, default_value = "127.0.0.1:8815")]
+        listen: String,
+    },
+    /// Serve the lookup endpoints of the public memory.lol API over HTTP
+    ///
+    /// Responses use the hosted service's schema (`/tw/<screen names>`, `/tw/id/<user ID>`, with
+    /// or without the `/v1` prefix), so existing clients can be pointed at a self-hosted database.
+    ///
+    /// Browser scripts on any origin can call it unless CORS is restricted or disabled.
+    Serve {
+        /// Address to listen on
+        #[clap(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+        /// URL that embeds link back to (like `https://api.memory.lol/v1`; by default the
+        /// request's host over HTTP)
+        #[clap(long)]
+        public_url: Option<String>,
+        /// Origin allowed to call the server from a browser (`*` for any; repeatable)
+        #[clap(long = "cors-origin", default_value = "*")]
+        cors_origins: Vec<String>,
+        /// Request header allowed in cross-origin requests (repeatable)
+        #[clap(long = "cors-header")]
+        cors_headers: Vec<String>,
+        /// Don't send CORS headers or answer preflight requests
+        #[clap(long, conflicts_with_all = ["cors_origins", "cors_headers"])]
+        no_cors: bool,
+        /// Requests allowed per client (IPv4 address or IPv6 /64) in each window
+        #[clap(long)]
+        rate_limit: Option<u32>,
+        /// Rate limiting window in seconds
+        #[clap(long, default_value = "60", requires = "rate_limit")]
+        rate_limit_window: u64,
+        /// Network (in CIDR notation) whose clients aren't rate limited (repeatable)
+        #[clap(long, requires = "rate_limit")]
+        rate_limit_exempt: Vec<Cidr>,
+        /// File of API keys (one per line) that exempt requests sending them in an `X-API-Key`
+        /// header from rate limiting
+        #[clap(long, requires = "rate_limit")]
+        api_keys: Option<PathBuf>,
+        /// File to keep rate limiting counters in across restarts
+        #[clap(long, requires = "rate_limit")]
+        rate_limit_state: Option<PathBuf>,
+        /// PEM certificate chain file (serves HTTPS)
+        #[clap(long, requires = "tls_key")]
+        tls_cert: Option<PathBuf>,
+        /// PEM private key file
+        #[clap(long, requires = "tls_cert")]
+        tls_key: Option<PathBuf>,
+        /// PEM file of CA certificates that client certificates must be signed by (clients without
+        /// one are rejected)
+        #[clap(long, requires = "tls_cert")]
+        tls_client_ca: Option<PathBuf>,
+        /// Append requests slower than --slow-query-ms to this file as NDJSON, with their RocksDB
+        /// perf-context counters
+        #[clap(long)]
+        slow_query_log: Option<PathBuf>,
+        /// Slow query threshold in milliseconds
+        #[clap(long, default_value = "100", requires = "slow_query_log")]
+        slow_query_ms: u64,
+        /// File containing a token that enables /admin/settings for requests sending it as a
+        /// bearer token, to change the log level, block cache size, and rate limits without a
+        /// restart
+        #[clap(long)]
+        admin_token_file: Option<PathBuf>,
+        /// Save evidence snapshots taken with POST /tw/id/<id>/snapshot (by requests sending the
+        /// admin token) in this directory, and serve them at /snapshots/<snapshot id>
+        #[clap(long, requires = "admin_token_file")]
+        snapshot_dir: Option<PathBuf>,
+        /// Refuse new snapshots once the snapshot directory holds this many
+        #[clap(long, default_value_t = memory_lol::snapshots::DEFAULT_LIMIT)]
+        snapshot_limit: usize,
+        /// Timestamp new snapshots with this RFC 3161 time-stamping authority (like
+        /// `https://freetsa.org/tsr`)
+        #[clap(long, requires = "snapshot_dir")]
+        tsa_url: Option<String>,
+        /// Anchor new snapshots in Bitcoin with this OpenTimestamps calendar (like
+        /// `https://a.pool.opentimestamps.org`)
+        #[clap(long, requires = "snapshot_dir")]
+        opentimestamps_calendar: Option<String>,
+        /// Look up the user IDs and screen names in this file (one per line) before listening, so
+        /// that their blocks are already cached when requests arrive
+        #[clap(long)]
+        warmup: Option<String>,
+        /// Accept import events posted to /events from this host, and stream them to WebSocket
+        /// clients at /ws/events
+        #[clap(long)]
+        event_feed: bool,
+    },
+    /// Send a query (a user ID or screen name) to a running daemon
+    Query {
+        /// Socket path
+        #[clap(long)]
+        socket: String,
+        /// User ID, screen name (prefixed with "@" if it's all digits), or screen name prefix
+        /// ending in `*`
+        query: String,
+    },
+    /// Convert a snowflake ID to the time it was generated, or a time to the first snowflake ID
+    /// generated then
+    Snowflake {
+        /// Tweet or user ID, RFC 3339 date-time, or date (YYYY-MM-DD, for midnight UTC)
+        value: String,
+    },
+    /// Print a shell completion script
+    Completions {
+        /// Target shell
+        #[clap(value_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// Print a man page
+    Man,
+    /// Inspect the ledger of completed imports
+    Imports {
+        #[clap(subcommand)]
+        command: ImportsCommand,
+    },
+    /// Manage a database split across several directories by user ID
+    Shard {
+        #[clap(subcommand)]
+        command: ShardCommand,
+    },
+    /// List days with conflicting observations, most severe first
+    Conflicts {
+        /// Maximum number of conflicts to list
+        #[clap(long)]
+        limit: Option<usize>,
+        /// Output format
+        #[clap(long, value_enum, default_value = "csv")]
+        format: lookup::Format,
+    },
+    /// Print differentially private rename and history length counts as JSON for publication
+    Aggregates {
+        /// Privacy budget (smaller values add more noise)
+        #[clap(long, default_value = "1.0")]
+        epsilon: f64,
+        /// First day of the rename counts
+        #[clap(long, default_value = "2006-03-21")]
+        first: NaiveDate,
+        /// Last day of the rename counts (defaults to today)
+        #[clap(long)]
+        last: Option<NaiveDate>,
+        /// Renames counted for any one account
+        #[clap(long, default_value = "5")]
+        max_renames_per_account: usize,
+        /// Largest history length reported separately
+        #[clap(long, default_value = "10")]
+        max_history_length: usize,
+    },
+    /// Group accounts by when they were first observed and print each cohort's exact rename
+    /// counts as JSON
+    ///
+    /// Reports the share of each cohort that was later observed with a new screen name, and how
+    /// many of its accounts were renamed in each period after the cohort's.
+    Cohorts {
+        /// Cohort length (`month` or `year`)
+        #[clap(long, default_value = "month")]
+        period: memory_lol::db::cohorts::Period,
+        /// Number of periods after each cohort's to report rename counts for
+        #[clap(long, default_value = "12")]
+        max_offset: usize,
+    },
+    /// Break screen names into words, numbers, and years, and print naming patterns as JSON
+    ///
+    /// Reports how many screen names first observed each year end in a number of four or more
+    /// digits, contain a year, or contain an underscore, along with the most common words and
+    /// token sequences (for example `w_wd4` for a word, an underscore, a word, and four digits).
+    NamePatterns {
+        /// Number of token sequences and words to report
+        #[clap(long, default_value = "20")]
+        top: usize,
+    },
+    /// Print accounts that took on many new screen names within a short window as JSON lines
+    ///
+    /// Each screen name is dated by its first observation, and an account's earliest screen name
+    /// isn't counted. Each flagged account is reported once, with its busiest window.
+    Bursts {
+        /// Window length in days (`7d`) or weeks (`2w`)
+        #[clap(long, default_value = "7d")]
+        window: bursts::Window,
+        /// Minimum number of new screen names within the window
+        #[clap(long, default_value = "3")]
+        min_renames: usize,
+    },
+    /// Print an account's screen names as non-overlapping date ranges, with the gaps in its
+    /// observations, as a Vega-Lite chart
+    Timeline {
+        /// Twitter user ID
+        id: u64,
+        /// Report periods longer than this without observations as gaps
+        #[clap(long, default_value_t = memory_lol::timeline::DEFAULT_MAX_GAP_DAYS)]
+        max_gap_days: i64,
+        /// Print only the ranges and gaps instead of a complete chart specification
+        #[clap(long)]
+        data: bool,
+    },
+    /// Show which screen names an account gained or dropped between two dates, as
+    /// `change,screen_name,date` rows
+    DiffAccount {
+        /// Twitter user ID
+        id: u64,
+        /// The earlier date (for example, when the account was last checked)
+        #[clap(long)]
+        from: NaiveDate,
+        /// The later date (defaults to today)
+        #[clap(long)]
+        to: Option<NaiveDate>,
+    },
+    /// List periods without observations for tagged accounts (which may indicate suspensions)
+    Gaps {
+        /// Tag of the watched accounts
+        #[clap(long)]
+        tag: String,
+        /// Minimum number of days without observations
+        #[clap(long, default_value_t = memory_lol::timeline::DEFAULT_MAX_GAP_DAYS)]
+        min_days: i64,
+    },
+    /// Write a self-contained HTML timeline of an account's screen names and annotations
+    Report {
+        /// Twitter user ID
+        id: u64,
+        /// Output file (defaults to stdout)
+        #[clap(long)]
+        output: Option<String>,
+    },
+    /// Manage analyst notes attached to accounts
+    Note {
+        #[clap(subcommand)]
+        command: NoteCommand,
+    },
+    /// Manage named namespaces, which are served by serve under /ns/<name>/
+    Namespace {
+        #[clap(subcommand)]
+        command: NamespaceCommand,
+    },
+    /// Review imports made with --quarantine, and promote them into the main database or discard
+    /// them
+    Quarantine {
+        #[clap(subcommand)]
+        command: QuarantineCommand,
+    },
+    /// Manage legal holds, which exempt accounts' records from pruning and removal
+    Hold {
+        #[clap(subcommand)]
+        command: HoldCommand,
+    },
+    /// Manage saved queries and report their matches among recent changes (for example from cron)
+    Alerts {
+        #[clap(subcommand)]
+        command: AlertCommand,
     },
     /// Export all pairs with observation dates in CSV format
-    Dump,
+    Dump {
+        /// Only export accounts with this tag
+        #[clap(long)]
+        tag: Option<String>,
+        /// Only export accounts with larger IDs (to resume an interrupted export)
+        #[clap(long)]
+        after: Option<u64>,
+        /// Write to this file (appending with --after) instead of stdout, and describe it in a
+        /// manifest next to it when the export finishes
+        #[clap(long)]
+        output: Option<PathBuf>,
+    },
+    /// Export the handle histories of selected accounts as a STIX 2.1 bundle or MISP attributes
+    ExportIntel {
+        /// Output format
+        #[clap(long, value_enum, default_value_t = intel::IntelFormat::Stix)]
+        format: intel::IntelFormat,
+        /// Include the accounts with this tag
+        #[clap(long)]
+        tag: Option<String>,
+        /// Twitter user IDs
+        ids: Vec<u64>,
+    },
+    /// Export all accounts as JSON lines, with each account's screen names and dates
+    ExportJson {
+        /// Replace IDs and screen names with keyed hashes (HMAC-SHA256)
+        #[clap(long, requires = "key_file")]
+        anonymize: bool,
+        /// File containing the anonymization key (keep it private, and reuse it to produce
+        /// comparable exports)
+        #[clap(long)]
+        key_file: Option<String>,
+        /// Only export accounts with larger IDs (to resume an interrupted export)
+        #[clap(long)]
+        after: Option<u64>,
+        /// Write to this file (appending with --after) instead of stdout, and describe it in a
+        /// manifest next to it when the export finishes
+        #[clap(long)]
+        output: Option<PathBuf>,
+    },
+    /// Export all accounts as JSON lines split into fixed-size chunks for distribution, with an
+    /// index of checksums (and optionally a .torrent file)
+    Publish {
+        /// Empty (or new) directory to write the chunks to
+        #[clap(long)]
+        output: PathBuf,
+        /// Maximum chunk size in bytes, with an optional K, M, G, or T suffix
+        #[clap(long, default_value = "1G", value_parser = publish::parse_size)]
+        chunk_size: u64,
+        /// Replace IDs and screen names with keyed hashes (HMAC-SHA256)
+        #[clap(long, requires = "key_file")]
+        anonymize: bool,
+        /// File containing the anonymization key
+        #[clap(long)]
+        key_file: Option<String>,
+        /// Also write a torrent of the chunks and the index
+        #[clap(long)]
+        torrent: bool,
+        /// Tracker announce URL for the torrent (repeatable; trackerless if missing)
+        #[clap(long, requires = "torrent")]
+        tracker: Vec<String>,
+    },
+    /// Check export files against their manifest (file sizes, SHA-256 digests, and record counts)
+    ExportVerify {
+        /// Manifest written by an export with --output
+        manifest: PathBuf,
+    },
     /// Print account, screen name, and pair counts
     Stats,
+    /// Reset the published dataset totals from a full count (run after removing or pruning)
+    RefreshStats,
+    /// Print disk usage by table, with SST file counts and estimated compaction debt
+    Du,
     /// Print counts for dates
     DateCounts,
     /// List the accounts with the most screen names
@@ -306,28 +2992,174 @@ enum Command {
         #[clap(long, default_value = "100")]
         count: usize,
     },
+    /// List screen names matching a regular expression (case-insensitively), with account counts
+    GrepNames {
+        /// Regular expression (for example `^crypto.*support$`)
+        pattern: String,
+        /// Only scan screen names starting with this literal prefix (much faster than scanning
+        /// the whole index)
+        #[clap(long)]
+        prefix: Option<String>,
+        /// Maximum number of matches to list
+        #[clap(long)]
+        limit: Option<usize>,
+    },
+    /// List screen names containing a substring, or matching a pattern with `*` wildcards
+    ///
+    /// Uses the trigram index if it has been built, and scans the screen name index otherwise.
+    SearchNames {
+        /// Substring (for example `support`) or wildcard pattern (for example `crypto*support`)
+        pattern: String,
+        /// Maximum number of matches to list
+        #[clap(long, default_value = "1000")]
+        limit: usize,
+    },
+    /// List the accounts that mentioned an account, as `date,kind,source,target` rows in date order
+    ///
+    /// Mentions are recorded by mention imports with `--record-mentions` and by import-edges.
+    MentionsOf {
+        id: u64,
+        /// Only include mentions on or after this date
+        #[clap(long)]
+        since: Option<NaiveDate>,
+        /// Only include mentions on or before this date
+        #[clap(long)]
+        until: Option<NaiveDate>,
+    },
+    /// List an account's interactions (mentions, replies, quotes, and retweets), as
+    /// `date,kind,source,target` rows in date order
+    Edges {
+        id: u64,
+        /// Whether to list interactions targeting the account or made by it
+        #[clap(long, value_enum, default_value = "incoming")]
+        direction: EdgeDirection,
+        /// Only include one kind of interaction (mention, reply, quote, or retweet)
+        #[clap(long)]
+        kind: Option<EdgeKind>,
+        /// Only include interactions on or after this date
+        #[clap(long)]
+        since: Option<NaiveDate>,
+        /// Only include interactions on or before this date
+        #[clap(long)]
+        until: Option<NaiveDate>,
+    },
+    /// Import interactions between accounts from NDJSON tweet objects (optionally compressed)
+    ///
+    /// Replies, quotes, retweets, and mentions are all extracted from each tweet.
+    ImportEdges {
+        /// Input file path (decompressed if it ends in `.zst`)
+        #[clap(long)]
+        input: String,
+        /// Read CSV rows of a source ID, a target ID, a kind, and an observation time as epoch
+        /// second instead of tweets
+        #[clap(long)]
+        csv: bool,
+        /// Refuse to import the input unless its manifest is signed with this Ed25519 public key
+        /// (PEM), and the input matches the manifest
+        #[clap(long, value_name = "KEY")]
+        verify_signature: Option<PathBuf>,
+        /// Signed manifest listing the input (defaults to the manifest next to it)
+        #[clap(long, requires = "verify_signature")]
+        manifest: Option<PathBuf>,
+    },
+    /// List screen names that sound like a handle, closest first, with their accounts
+    ///
+    /// Prints the screen name, its edit distance from the handle, and account IDs. Uses the
+    /// phonetic index if it has been built, and scans the screen name index otherwise.
+    LookupSimilar {
+        handle: String,
+        /// Maximum number of screen names to list
+        #[clap(long, default_value = "25")]
+        limit: usize,
+        /// List every screen name within this edit distance instead of those that sound alike
+        /// (for enumerating typosquatting variants)
+        #[clap(long)]
+        max_distance: Option<usize>,
+    },
+    /// Build (or rebuild) the phonetic index used by lookup-similar
+    ///
+    /// Once built, the index is also updated by imports.
+    BuildPhoneticIndex,
+    /// List accounts that have used screen names visually confusable with a handle
+    ///
+    /// The handle may contain Unicode homoglyphs, and is expanded into every combination of
+    /// look-alike characters (`0` and `o`, `1`, `l`, and `i`, `rn` and `m`, etc.).
+    Confusables {
+        handle: String,
+        /// Fail instead of looking up more than this many variants
+        #[clap(long, default_value = "4096")]
+        max_variants: usize,
+    },
+    /// Build (or rebuild) the trigram index used for substring search
+    ///
+    /// Once built, the index is also updated by imports.
+    BuildNgramIndex,
     /// List the screen names used by the most accounts
     MostReused {
         #[clap(long, default_value = "100")]
         count: usize,
     },
     /// Import a CSV file containing mentions
+    ///
+    /// Rows are a user ID, a screen name, an observation time as an epoch second, and optionally
+    /// the ID of the mentioning account and the ID of the tweet. The observation time may be left
+    /// empty if the tweet ID is present, in which case the date is derived from the tweet ID.
     ImportMentions {
-        /// NDJSON file path
+        /// CSV file path (or `-` for standard input)
         #[clap(long)]
         input: String,
         /// Use ZSTD compression
         #[clap(long)]
         zst: bool,
+        /// Import even if the input's hash appears in the import ledger
+        #[clap(long)]
+        force: bool,
+        /// How observation dates are recorded
+        #[clap(long, value_enum, default_value = "range")]
+        mode: UpdateMode,
+        /// Record which account made each mention (from an optional fourth column containing its
+        /// ID) in the edge table
+        #[clap(long)]
+        record_mentions: bool,
+        /// Refuse to import the input unless its manifest is signed with this Ed25519 public key
+        /// (PEM), and the input matches the manifest
+        #[clap(long, value_name = "KEY")]
+        verify_signature: Option<PathBuf>,
+        /// Signed manifest listing the input (defaults to the manifest next to it)
+        #[clap(long, requires = "verify_signature")]
+        manifest: Option<PathBuf>,
+        #[clap(flatten)]
+        dialect: CsvDialect,
+        #[clap(flatten)]
+        columns: ColumnNames,
     },
     /// Import an NDJSON file
     ImportJson {
-        /// NDJSON file path
+        /// NDJSON file path (or `-` for standard input)
         #[clap(long)]
         input: String,
         /// Use ZSTD compression
         #[clap(long)]
         zst: bool,
+        /// Import even if the input's hash appears in the import ledger
+        #[clap(long)]
+        force: bool,
+        /// How observation dates are recorded
+        #[clap(long, value_enum, default_value = "range")]
+        mode: UpdateMode,
+        /// Refuse to import the input unless its manifest is signed with this Ed25519 public key
+        /// (PEM), and the input matches the manifest
+        #[clap(long, value_name = "KEY")]
+        verify_signature: Option<PathBuf>,
+        /// Signed manifest listing the input (defaults to the manifest next to it)
+        #[clap(long, requires = "verify_signature")]
+        manifest: Option<PathBuf>,
+    },
+    /// Import NDJSON from standard input, writing each observation as it's read
+    ImportStream {
+        /// Name recorded for this input in the import ledger
+        #[clap(long, default_value = "stdin")]
+        source: String,
     },
     /// Import a batch of Twitter Stream Grab output directories
     ImportBatch {
@@ -337,15 +3169,216 @@ enum Command {
         /// Directory prefix
         #[clap(long)]
         prefix: Option<String>,
+        /// Import even if the input's hash appears in the import ledger
+        #[clap(long)]
+        force: bool,
+        /// How observation dates are recorded
+        #[clap(long, value_enum, default_value = "range")]
+        mode: UpdateMode,
+        /// Record which account made each mention in the names files in the edge table
+        #[clap(long)]
+        record_mentions: bool,
+    },
+    /// Rewrite the account and screen name tables with Zstd compression and trained dictionaries
+    ///
+    /// The settings are stored in the database directory and applied whenever it is opened, so
+    /// that new files also use dictionaries.
+    TrainDictionary {
+        /// Maximum dictionary size in bytes
+        #[clap(long, default_value = "16384")]
+        dictionary_bytes: i32,
+    },
+    /// Seal every account table value with the current encryption key (encrypting a database
+    /// that was written without one), so that earlier keys can be retired
+    RotateKey,
+    /// Drop observation dates according to a retention policy
+    Prune {
+        /// Drop observation dates more than this many years old
+        #[clap(long)]
+        max_age_years: Option<u32>,
+        /// Keep only the first and last remaining dates for each pair
+        #[clap(long)]
+        thin: bool,
+        /// Report what would be changed without writing anything
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Move accounts that haven't been observed recently to a cold database on bulk storage
+    ///
+    /// Lookups read from both databases, while imports only write to the database itself.
+    Demote {
+        /// Demote accounts with no observations in this many days
+        #[clap(long)]
+        older_than: u32,
+        /// Cold database directory (only needed the first time, since it's recorded in the
+        /// database directory)
+        #[clap(long)]
+        cold: Option<PathBuf>,
+        /// Report what would be moved without writing anything
+        #[clap(long)]
+        dry_run: bool,
     },
     /// Compact ranges in database
     CompactRanges,
+    /// Populate an empty database with deterministic synthetic accounts for testing
+    GenerateFixture {
+        /// Number of accounts to generate
+        #[clap(long, default_value = "10000")]
+        accounts: usize,
+        /// Random seed (the same seed and account count always produce the same database)
+        #[clap(long, default_value = "42")]
+        seed: u64,
+    },
+    /// Sort a CSV or NDJSON input by user ID, so that importing it writes each account's pairs
+    /// together
+    ///
+    /// Inputs too large to sort in memory are sorted in runs written to a scratch directory, which
+    /// needs about as much free space as the input. The output is uncompressed.
+    Presort {
+        /// Input file path
+        #[clap(long)]
+        input: String,
+        /// Output file path
+        #[clap(long)]
+        output: String,
+        /// Input format
+        #[clap(long, value_enum, default_value = "csv")]
+        format: presort::InputFormat,
+        /// Use ZSTD compression (for the input)
+        #[clap(long)]
+        zst: bool,
+        /// Directory for sorted runs (the output path with a .tmp extension by default)
+        #[clap(long)]
+        scratch: Option<PathBuf>,
+        /// Number of lines sorted in memory before being written to a run
+        #[clap(long, default_value_t = DEFAULT_RUN_SIZE)]
+        run_size: usize,
+    },
+    /// Load mention CSV files into an empty database by sorting them externally and ingesting
+    /// SST files directly
+    ///
+    /// This bypasses RocksDB's write path, and is much faster than import-mentions for initial
+    /// loads of very large inputs. Sorted runs are written to a scratch directory, which needs
+    /// about as much free space as the input.
+    BulkLoad {
+        /// CSV file paths (in the import-mentions format)
+        #[clap(long, required = true)]
+        input: Vec<String>,
+        /// Use ZSTD compression
+        #[clap(long)]
+        zst: bool,
+        /// How observation dates are recorded (range or all)
+        #[clap(long, value_enum, default_value = "range")]
+        mode: UpdateMode,
+        /// Directory for sorted runs (bulk-load.tmp in the database directory by default)
+        #[clap(long)]
+        scratch: Option<PathBuf>,
+        /// Number of pairs sorted in memory before being written to a run
+        #[clap(long, default_value_t = DEFAULT_RUN_SIZE)]
+        run_size: usize,
+    },
     /// Import a CSV from stdin with multiple timestamps per row
-    ImportMulti,
-    /// Remove comma-separated ID-screen name pairs provided from stdin
+    ImportMulti {
+        /// Name recorded for this input in the import ledger
+        #[clap(long, default_value = "stdin")]
+        source: String,
+        /// Form of the timestamps (auto, seconds, millis, or iso); auto detects each field's
+        /// form, so inputs can mix them
+        #[clap(long, default_value = "auto")]
+        timestamp_format: memory_lol::timestamp::Format,
+        #[clap(flatten)]
+        dialect: CsvDialect,
+    },
+    /// Remove comma-separated ID-screen name pairs provided from stdin (except for held accounts)
     Remove,
-    /// Rebuild screen name index
-    RebuildIndex,
+    /// Remove the observation dates an import added (and the pairs left without dates), for an
+    /// import made with --track-changes
+    RollbackImport {
+        /// The import's input hash, as listed by `imports list`
+        import: String,
+    },
+    /// Drop and rebuild a reverse index from the account table
+    RebuildIndex {
+        /// Index to rebuild
+        #[clap(long, value_enum, default_value = "screen-names")]
+        which: Index,
+    },
+}
+
+impl Command {
+    /// Whether the command writes observations that can be quarantined.
+    fn is_import(&self) -> bool {
+        matches!(
+            self,
+            Command::ImportMentions { .. }
+                | Command::ImportJson { .. }
+                | Command::ImportStream { .. }
+                | Command::ImportBatch { .. }
+                | Command::ImportMulti { .. }
+                | Command::BulkLoad { .. }
+        )
+    }
+
+    fn requires_db(&self) -> bool {
+        !matches!(
+            self,
+            Command::Query { .. }
+                | Command::Snowflake { .. }
+                | Command::Presort { .. }
+                | Command::ExportVerify { .. }
+                | Command::Completions { .. }
+                | Command::Man
+        )
+    }
+}
+
+/// Which of an account's edges to list.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, clap::ValueEnum)]
+enum EdgeDirection {
+    /// Interactions targeting the account
+    Incoming,
+    /// Interactions made by the account
+    Outgoing,
+}
+
+impl From<EdgeDirection> for memory_lol::db::edges::Direction {
+    fn from(direction: EdgeDirection) -> Self {
+        match direction {
+            EdgeDirection::Incoming => Self::Incoming,
+            EdgeDirection::Outgoing => Self::Outgoing,
+        }
+    }
+}
+
+/// Reverse indices that can be rebuilt from the account table.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, clap::ValueEnum)]
+enum Index {
+    /// Screen name to user IDs
+    ScreenNames,
+}
+
+/// Look up the keys in a hot key file, if one is given, before a server starts listening.
+fn warm_up(db: &Database<ReadOnly>, path: Option<String>) -> Result<(), Error> {
+    if let Some(path) = path {
+        let queries = warmup::read_keys(BufReader::new(open_input(Path::new(&path))?))?;
+        let report = warmup::run(db, &queries)?;
+
+        log::info!(
+            "Warmed up with {} lookups in {:.2}s",
+            report.queries,
+            report.elapsed.as_secs_f64()
+        );
+    }
+
+    Ok(())
+}
+
+fn open_slow_query_log(
+    path: Option<PathBuf>,
+    threshold_ms: u64,
+) -> Result<Option<SlowQueryLog>, memory_lol::db::Error> {
+    path.map(|path| SlowQueryLog::open(path, Duration::from_millis(threshold_ms)))
+        .transpose()
 }
 
 fn select_log_level_filter(verbosity: u8) -> LevelFilter {
@@ -360,11 +3393,16 @@ fn select_log_level_filter(verbosity: u8) -> LevelFilter {
 }
 
 /// Initialize a default terminal logger with the indicated log level.
+///
+/// The logger itself accepts every level, so that the level can be raised at runtime.
 pub fn init_logging(verbosity: u8) -> Result<(), log::SetLoggerError> {
     simplelog::TermLogger::init(
-        select_log_level_filter(verbosity),
+        LevelFilter::Trace,
         simplelog::Config::default(),
         simplelog::TerminalMode::Stderr,
         simplelog::ColorChoice::Auto,
-    )
+    )?;
+    log::set_max_level(select_log_level_filter(verbosity));
+
+    Ok(())
 }
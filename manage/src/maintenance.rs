@@ -0,0 +1,140 @@
+use crate::Error;
+use chrono::{Days, Months, Utc};
+use memory_lol::db::{
+    options::Compression,
+    retention::{PruneReport, RetentionPolicy},
+    tiers::{DemoteReport, TierConfig, TieredLookup},
+    weights::WeightTable,
+    Database, DatabaseOptions, Table, Writeable,
+};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Rewrite the account and screen name tables with Zstd compression, training a dictionary for
+/// each table as its files are compacted.
+pub fn train_dictionary(
+    path: &Path,
+    options: DatabaseOptions,
+    dictionary_bytes: i32,
+) -> Result<(), Error> {
+    let options = DatabaseOptions {
+        compression: Some(Compression::Zstd),
+        // Per-level settings would take precedence over the Zstd setting
+        compression_per_level: None,
+        zstd_dictionary_bytes: Some(dictionary_bytes),
+        ..options
+    };
+    let db = Database::<Writeable>::open_with_options(path, &options)?;
+    // Later opens pick these up, so new files are also written with dictionaries
+    options.save_compression(path)?;
+
+    log::info!("Compacting account table");
+    db.accounts.compact();
+    log::info!("Compacting screen name table");
+    db.screen_names.compact();
+
+    Ok(())
+}
+
+/// Apply a retention policy relative to the current date, skipping held accounts.
+pub fn prune(
+    db: &Database<Writeable>,
+    weights: Option<&WeightTable<Writeable>>,
+    held: &HashSet<u64>,
+    max_age_years: Option<u32>,
+    thin: bool,
+    dry_run: bool,
+) -> Result<PruneReport, Error> {
+    let earliest = max_age_years
+        .map(|years| {
+            years
+                .checked_mul(12)
+                .and_then(|months| {
+                    Utc::now()
+                        .date_naive()
+                        .checked_sub_months(Months::new(months))
+                })
+                .ok_or(Error::InvalidMaxAge(years))
+        })
+        .transpose()?;
+
+    if let Some(earliest) = earliest {
+        log::info!("Dropping observation dates before {}", earliest);
+    }
+
+    if !held.is_empty() {
+        log::info!("Skipping {} accounts under legal hold", held.len());
+    }
+
+    Ok(db.prune(weights, &RetentionPolicy { earliest, thin }, held, dry_run)?)
+}
+
+/// Move accounts that haven't been observed in a number of days from the database to its cold
+/// tier, recording the cold tier's location if one is given.
+pub fn demote(
+    path: &Path,
+    options: &DatabaseOptions,
+    cold: Option<PathBuf>,
+    older_than_days: u32,
+    dry_run: bool,
+) -> Result<DemoteReport, Error> {
+    let config = match cold {
+        Some(cold) => TierConfig { cold },
+        None => {
+            TierConfig::load(path)?.ok_or_else(|| Error::MissingColdTier(path.to_path_buf()))?
+        }
+    };
+    let cutoff = Utc::now()
+        .date_naive()
+        .checked_sub_days(Days::new(older_than_days.into()))
+        .ok_or(Error::InvalidMaxAge(older_than_days))?;
+
+    log::info!("Demoting accounts not observed since {}", cutoff);
+
+    std::fs::create_dir_all(&config.cold)?;
+    // The recorded path shouldn't depend on the working directory
+    let config = TierConfig {
+        cold: config.cold.canonicalize()?,
+    };
+    let db = TieredLookup::<Writeable>::open_with_cold(path, Some(&config.cold), options)?;
+    let report = db.demote(cutoff, dry_run)?;
+
+    if !dry_run {
+        config.save(path)?;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memory_lol::db::options::COMPRESSION_FILE;
+
+    #[test]
+    fn train_dictionary_stores_compression() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::<Writeable>::open(dir.path()).unwrap();
+        db.insert(123, "foo", vec![]).unwrap();
+        drop(db);
+
+        let options = DatabaseOptions {
+            compression_per_level: Some(vec![Compression::None, Compression::Snappy]),
+            ..Default::default()
+        };
+
+        train_dictionary(dir.path(), options, 1024).unwrap();
+
+        assert!(dir.path().join(COMPRESSION_FILE).exists());
+        assert_eq!(
+            DatabaseOptions::default()
+                .with_stored_compression(dir.path())
+                .unwrap(),
+            DatabaseOptions {
+                compression: Some(Compression::Zstd),
+                zstd_dictionary_bytes: Some(1024),
+                ..Default::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use crate::http::{self, Response};
+use crate::lookup::{self, Query, Source};
+
+/// The path of the transform that returns an account's screen name history.
+pub const HISTORY_PATH: &str = "/history";
+
+/// The entity type Maltego uses for Twitter accounts.
+const TWITTER_ENTITY: &str = "maltego.affiliation.Twitter";
+
+/// The entity a transform was run on.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Request {
+    value: String,
+    /// The user ID from the entity's properties, if it has one
+    id: Option<u64>,
+    /// The maximum number of entities to return
+    limit: Option<usize>,
+}
+
+impl Request {
+    fn parse(xml: &str) -> Option<Self> {
+        let entity = element(xml, "Entity")?;
+        let value = unescape(element(entity, "Value")?.trim());
+
+        let id = ["twitter.id", "affiliation.uid"].iter().find_map(|name| {
+            fields(entity)
+                .find(|(field, _)| field == name)
+                .and_then(|(_, value)| value.trim().parse().ok())
+        });
+
+        let limit = xml
+            .find("SoftLimit=\"")
+            .map(|start| &xml[start + 11..])
+            .and_then(|rest| rest.split('"').next())
+            .and_then(|limit| limit.parse().ok());
+
+        Some(Self { value, id, limit })
+    }
+
+    /// Without an ID property, the value may be either, since Maltego users paste both.
+    fn query(&self) -> Result<Query, lookup::Error> {
+        match self.id {
+            Some(id) => Ok(Query::Id(id)),
+            None => Query::infer(&self.value)?.ok_or(lookup::Error::EmptyQuery),
+        }
+    }
+}
+
+/// Serve Maltego transforms over HTTP until the process is terminated.
+pub fn serve(db: &dyn Source, address: &str) -> Result<(), http::Error> {
+    http::serve(address, None, |request| {
+        match (request.method.as_str(), request.path_without_query()) {
+            ("POST", HISTORY_PATH) => Response::new(
+                200,
+                "text/xml; charset=utf-8",
+                history(db, &String::from_utf8_lossy(&request.body)),
+            ),
+            (_, HISTORY_PATH) => Response::status(405),
+            _ => Response::status(404),
+        }
+    })
+}
+
+/// Run the history transform on a request message, returning the response message.
+///
+/// Each screen name of each matching account is returned as a Twitter entity, with the user ID
+/// and the first and last observation dates as properties.
+fn history(db: &dyn Source, xml: &str) -> String {
+    let request = match Request::parse(xml) {
+        Some(request) => request,
+        None => return exception("Invalid transform request"),
+    };
+
+    let result = match request
+        .query()
+        .and_then(|query| lookup::resolve(db, &query))
+    {
+        Ok(result) => result,
+        Err(error) => return exception(&format!("Lookup failed: {}", error)),
+    };
+
+    let mut entities = String::new();
+    let mut count = 0;
+
+    for account in &result.accounts {
+        for (screen_name, dates) in &account.screen_names {
+            if request.limit.is_some_and(|limit| count >= limit) {
+                break;
+            }
+
+            let first = dates.as_ref().and_then(|dates| dates.first());
+            let last = dates.as_ref().and_then(|dates| dates.last());
+            let mut fields = vec![
+                ("twitter.id", "Twitter ID", account.id.to_string()),
+                ("twitter.screen-name", "Screen Name", screen_name.clone()),
+                ("affiliation.uid", "UID", account.id.to_string()),
+                ("affiliation.network", "Network", "Twitter".to_string()),
+            ];
+
+            if let (Some(first), Some(last)) = (first, last) {
+                fields.push((
+                    "memorylol.first-observed",
+                    "First observed",
+                    first.to_string(),
+                ));
+                fields.push(("memorylol.last-observed", "Last observed", last.to_string()));
+            }
+
+            entities.push_str(&format!(
+                "<Entity Type=\"{}\"><Value>{}</Value><Weight>100</Weight><AdditionalFields>",
+                TWITTER_ENTITY,
+                escape(screen_name)
+            ));
+
+            for (name, display_name, value) in fields {
+                entities.push_str(&format!(
+                    "<Field Name=\"{}\" DisplayName=\"{}\">{}</Field>",
+                    name,
+                    display_name,
+                    escape(&value)
+                ));
+            }
+
+            entities.push_str("</AdditionalFields></Entity>");
+            count += 1;
+        }
+    }
+
+    let message = if result.accounts.is_empty() {
+        format!("No history found for {}", escape(&result.query))
+    } else {
+        format!(
+            "Found {} accounts for {}",
+            result.accounts.len(),
+            escape(&result.query)
+        )
+    };
+
+    format!(
+        "<MaltegoMessage><MaltegoTransformResponseMessage><Entities>{}</Entities><UIMessages>\
+         <UIMessage MessageType=\"Inform\">{}</UIMessage></UIMessages>\
+         </MaltegoTransformResponseMessage></MaltegoMessage>",
+        entities, message
+    )
+}
+
+fn exception(message: &str) -> String {
+    format!(
+        "<MaltegoMessage><MaltegoTransformExceptionMessage><Exceptions><Exception>{}</Exception>\
+         </Exceptions></MaltegoTransformExceptionMessage></MaltegoMessage>",
+        escape(message)
+    )
+}
+
+/// The contents of the first element with the given name (Maltego messages are simple enough
+/// that we don't need a full XML parser).
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{}", name);
+    let close = format!("</{}>", name);
+
+    let start = xml.match_indices(&open).find_map(|(index, _)| {
+        let rest = &xml[index + open.len()..];
+
+        // Don't match longer element names that share the prefix
+        match rest.chars().next()? {
+            '>' | ' ' | '\t' | '\r' | '\n' => Some(index + open.len() + rest.find('>')? + 1),
+            _ => None,
+        }
+    })?;
+    let end = xml[start..].find(&close)?;
+
+    Some(&xml[start..start + end])
+}
+
+/// The names and values of an entity's additional fields.
+fn fields(entity: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    entity.split("<Field ").skip(1).filter_map(|field| {
+        let name = field.split("Name=\"").nth(1)?.split('"').next()?;
+        let value = field.split_once('>')?.1.split("</Field>").next()?;
+
+        Some((unescape(name), unescape(value)))
+    })
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use memory_lol::db::{Database, ReadOnly, Writeable};
+
+    const REQUEST: &str = r#"<MaltegoMessage>
+<MaltegoTransformRequestMessage>
+<Entities>
+<Entity Type="maltego.affiliation.Twitter">
+<AdditionalFields>
+<Field Name="twitter.id" DisplayName="Twitter ID">1</Field>
+<Field Name="person.fullname" DisplayName="Name">Foo &amp; Bar</Field>
+</AdditionalFields>
+<Value>@foo</Value>
+<Weight>100</Weight>
+</Entity>
+</Entities>
+<Limits SoftLimit="12" HardLimit="12"/>
+</MaltegoTransformRequestMessage>
+</MaltegoMessage>"#;
+
+    #[test]
+    fn parse_request() {
+        assert_eq!(
+            Request::parse(REQUEST),
+            Some(Request {
+                value: "@foo".to_string(),
+                id: Some(1),
+                limit: Some(12)
+            })
+        );
+        assert_eq!(
+            Request::parse("<Entity Type=\"maltego.Alias\"><Value>42</Value></Entity>")
+                .unwrap()
+                .query()
+                .unwrap(),
+            Query::Id(42)
+        );
+        assert_eq!(Request::parse("<Entities></Entities>"), None);
+    }
+
+    #[test]
+    fn history_transform() {
+        let dir = tempfile::tempdir().unwrap();
+
+        {
+            let db = Database::<Writeable>::open(dir.path()).unwrap();
+            let date = |day| NaiveDate::from_ymd_opt(2020, 1, day).unwrap();
+
+            db.insert(1, "foo", vec![date(1), date(2)]).unwrap();
+            db.insert(1, "bar", vec![date(3)]).unwrap();
+        }
+
+        let db = Database::<ReadOnly>::open(dir.path()).unwrap();
+        let response = history(&db, REQUEST);
+
+        assert!(response.contains("<Value>foo</Value>"));
+        assert!(response.contains("<Value>bar</Value>"));
+        assert!(response.contains(
+            "<Field Name=\"memorylol.first-observed\" DisplayName=\"First observed\">\
+             2020-01-01</Field>"
+        ));
+        assert!(response.contains("Found 1 accounts for 1"));
+
+        let response = history(&db, &REQUEST.replace("1</Field>", "2</Field>"));
+        assert!(response.contains("<Entities></Entities>"));
+        assert!(response.contains("No history found for 2"));
+
+        assert!(history(&db, "").contains("<Exception>Invalid transform request</Exception>"));
+    }
+}
@@ -0,0 +1,229 @@
+//! A Matrix bot that answers `!lol <query>` in configured rooms.
+//!
+//! The bot is a [matrix-sdk](https://docs.rs/matrix-sdk) client: it restores the session of an
+//! existing device, joins its rooms, and syncs for new messages (skipping any history from before
+//! it started), replying with notices, which other bots conventionally don't respond to. Its
+//! encryption keys and sync state are kept in a store directory, so that it can read and answer
+//! messages in encrypted rooms across restarts.
+use crate::bot::{self, ChannelLimiter};
+use crate::lookup::Source;
+use crate::shutdown::Shutdown;
+use matrix_sdk::{
+    config::SyncSettings,
+    matrix_auth::{MatrixSession, MatrixSessionTokens},
+    ruma::{
+        events::{
+            room::message::{MessageType, RoomMessageEventContent, SyncRoomMessageEvent},
+            AnySyncMessageLikeEvent, AnySyncTimelineEvent,
+        },
+        OwnedRoomId, RoomOrAliasId, UserId,
+    },
+    sync::SyncResponse,
+    Client, LoopCtrl, SessionMeta,
+};
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const COMMAND_PREFIX: &str = "!lol";
+
+/// Wait this long before syncing again after the first error in a row (a restarting homeserver,
+/// for example), doubling the wait after each further error up to [`MAX_RETRY_DELAY`].
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("Matrix client error")]
+    Client(#[from] matrix_sdk::Error),
+    #[error("Matrix client configuration error")]
+    Build(#[from] matrix_sdk::ClientBuildError),
+    #[error("Invalid Matrix identifier")]
+    InvalidId(#[from] matrix_sdk::ruma::IdParseError),
+}
+
+pub struct Config {
+    /// Base URL of the homeserver (for example `https://matrix.org`)
+    pub homeserver: String,
+    /// The bot account's user ID (for example `@memory-lol:matrix.org`)
+    pub user_id: String,
+    /// The device the access token was issued for
+    pub device_id: String,
+    pub access_token: String,
+    /// Directory for the client's encryption keys and sync state
+    pub store: PathBuf,
+    /// Room IDs or aliases
+    pub rooms: Vec<String>,
+    /// Commands each room can run per minute
+    pub room_limit: u32,
+}
+
+/// Answer commands until the process is asked to stop.
+pub fn run(db: &dyn Source, config: &Config, shutdown: &Shutdown) -> Result<(), Error> {
+    tokio::runtime::Runtime::new()?.block_on(run_client(db, config, shutdown))
+}
+
+async fn run_client(db: &dyn Source, config: &Config, shutdown: &Shutdown) -> Result<(), Error> {
+    let client = Client::builder()
+        .homeserver_url(&config.homeserver)
+        .sqlite_store(&config.store, None)
+        .build()
+        .await?;
+
+    let user_id = UserId::parse(&config.user_id)?;
+    client
+        .restore_session(MatrixSession {
+            meta: SessionMeta {
+                user_id: user_id.clone(),
+                device_id: config.device_id.as_str().into(),
+            },
+            tokens: MatrixSessionTokens {
+                access_token: config.access_token.clone(),
+                refresh_token: None,
+            },
+        })
+        .await?;
+
+    let mut rooms = HashSet::new();
+
+    for room in &config.rooms {
+        let room = RoomOrAliasId::parse(room)?;
+        let room = client.join_room_by_id_or_alias(&room, &[]).await?;
+        rooms.insert(room.room_id().to_owned());
+    }
+
+    log::info!("Answering {} commands as {}", COMMAND_PREFIX, user_id);
+
+    // Only messages sent after the bot starts are answered
+    let response = client.sync_once(SyncSettings::default()).await?;
+    let settings = SyncSettings::default().token(response.next_batch);
+
+    let limiter = ChannelLimiter::new(config.room_limit);
+    let retry_delay = Cell::new(INITIAL_RETRY_DELAY);
+
+    client
+        .sync_with_result_callback(settings, |response| {
+            let (client, rooms, limiter, user_id) = (&client, &rooms, &limiter, &user_id);
+            let retry_delay = &retry_delay;
+
+            async move {
+                match response {
+                    Ok(response) => {
+                        retry_delay.set(INITIAL_RETRY_DELAY);
+
+                        for (room_id, query) in commands(&response, rooms, user_id) {
+                            let reply = match limiter.check(room_id.as_str(), bot::now()) {
+                                Some(wait) => bot::rate_limited(wait),
+                                None => bot::whois(db, &query),
+                            };
+
+                            if let Err(error) = reply_in(client, &room_id, &reply).await {
+                                log::warn!("Failed to reply in {}: {:?}", room_id, error);
+                            }
+                        }
+                    }
+                    Err(error) => {
+                        let delay = retry_delay.get();
+                        retry_delay.set((delay * 2).min(MAX_RETRY_DELAY));
+
+                        log::warn!("Sync error (retrying in {:?}): {:?}", delay, error);
+                        tokio::time::sleep(delay).await;
+                    }
+                }
+
+                Ok(if shutdown.requested() {
+                    LoopCtrl::Break
+                } else {
+                    LoopCtrl::Continue
+                })
+            }
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Send a reply as a notice.
+async fn reply_in(client: &Client, room_id: &OwnedRoomId, reply: &str) -> Result<(), Error> {
+    if let Some(room) = client.get_room(room_id) {
+        room.send(RoomMessageEventContent::notice_html(reply, html(reply)))
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// The commands in a sync response's new (and decrypted) messages, as room IDs and queries.
+fn commands(
+    response: &SyncResponse,
+    rooms: &HashSet<OwnedRoomId>,
+    user_id: &UserId,
+) -> Vec<(OwnedRoomId, String)> {
+    let mut commands = vec![];
+
+    for (room_id, room) in response
+        .rooms
+        .join
+        .iter()
+        .filter(|(id, _)| rooms.contains(*id))
+    {
+        for event in &room.timeline.events {
+            let Ok(AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomMessage(
+                SyncRoomMessageEvent::Original(message),
+            ))) = event.event.deserialize()
+            else {
+                continue;
+            };
+
+            if message.sender.as_str() == user_id.as_str() {
+                continue;
+            }
+
+            if let MessageType::Text(text) = &message.content.msgtype {
+                if let Some(query) = query(&text.body) {
+                    commands.push((room_id.clone(), query.to_string()));
+                }
+            }
+        }
+    }
+
+    commands
+}
+
+/// The query in a message, if it's a command.
+fn query(body: &str) -> Option<&str> {
+    body.strip_prefix(COMMAND_PREFIX)
+        .filter(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+        .map(str::trim)
+}
+
+/// A reply as HTML, with its lines preserved.
+fn html(reply: &str) -> String {
+    reply
+        .lines()
+        .map(|line| {
+            line.replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+        })
+        .collect::<Vec<_>>()
+        .join("<br>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages() {
+        assert_eq!(query("!lol foo"), Some("foo"));
+        assert_eq!(query("!lol  12345 "), Some("12345"));
+        assert_eq!(query("!lol"), Some(""));
+        assert_eq!(query("!lolcats"), None);
+        assert_eq!(query("hello"), None);
+        assert_eq!(html("1: @a\n<b>"), "1: @a<br>&lt;b&gt;");
+    }
+}
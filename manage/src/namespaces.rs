@@ -0,0 +1,102 @@
+use crate::Error;
+use clap::{CommandFactory, Parser};
+use memory_lol::db::{
+    namespaces,
+    stats::{StatsTable, Totals},
+    Database, DatabaseOptions, ReadOnly, Writeable, STATS_TABLE,
+};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Parser)]
+pub enum NamespaceCommand {
+    /// Print each namespace's name, whether it requires an API key, and its account and pair
+    /// totals (as maintained by imports and refresh-stats) in CSV format
+    List,
+    /// Create an empty namespace
+    Create {
+        name: String,
+        /// File listing the API keys that may query the namespace, one per line (the namespace
+        /// is public without one)
+        #[clap(long)]
+        api_keys: Option<String>,
+    },
+    /// Replace the API keys that may query a namespace
+    SetKeys {
+        name: String,
+        /// File listing the API keys, one per line (the namespace is made public without one)
+        #[clap(long)]
+        api_keys: Option<String>,
+    },
+    /// Delete a namespace and everything in it
+    Remove { name: String },
+}
+
+pub fn run(db: &Path, options: &DatabaseOptions, command: NamespaceCommand) -> Result<(), Error> {
+    match command {
+        NamespaceCommand::List => {
+            for name in namespaces::list(db)? {
+                let path = namespaces::path(db, &name)?;
+                let stats_path = path.join(STATS_TABLE);
+                let totals = if stats_path.exists() {
+                    StatsTable::<ReadOnly>::open_with_options(stats_path, options)?.totals()?
+                } else {
+                    Totals::default()
+                };
+                let access = if namespaces::api_keys(&path)?.is_empty() {
+                    "public"
+                } else {
+                    "private"
+                };
+
+                println!("{},{},{},{}", name, access, totals.accounts, totals.pairs);
+            }
+        }
+        NamespaceCommand::Create { name, api_keys } => {
+            let path = namespaces::path(db, &name)?;
+
+            if path.exists() {
+                return Err(Error::Arguments(crate::Opts::command().error(
+                    clap::error::ErrorKind::ValueValidation,
+                    format!("namespace {} already exists", name),
+                )));
+            }
+
+            Database::<Writeable>::open_with_options(&path, options)?;
+            namespaces::set_api_keys(&path, &read_api_keys(api_keys.as_deref())?)?;
+            log::info!("Created namespace {}", name);
+        }
+        NamespaceCommand::SetKeys { name, api_keys } => {
+            let path = existing_path(db, &name)?;
+            namespaces::set_api_keys(&path, &read_api_keys(api_keys.as_deref())?)?;
+        }
+        NamespaceCommand::Remove { name } => {
+            let path = existing_path(db, &name)?;
+            std::fs::remove_dir_all(path)?;
+            log::info!("Removed namespace {}", name);
+        }
+    }
+
+    Ok(())
+}
+
+fn existing_path(db: &Path, name: &str) -> Result<PathBuf, Error> {
+    let path = namespaces::path(db, name)?;
+
+    if path.exists() {
+        Ok(path)
+    } else {
+        Err(Error::NotFound(name.to_string()))
+    }
+}
+
+fn read_api_keys(path: Option<&str>) -> Result<Vec<String>, Error> {
+    Ok(match path {
+        Some(path) => std::fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect(),
+        None => vec![],
+    })
+}
@@ -0,0 +1,88 @@
+use crate::Error;
+use chrono::Utc;
+use clap::Parser;
+use memory_lol::{
+    db::{notes::NoteTable, DatabaseOptions, ReadOnly, Writeable, NOTES_TABLE},
+    model::Note,
+};
+use std::path::Path;
+
+#[derive(Debug, Parser)]
+pub enum NoteCommand {
+    /// Attach a note to a Twitter user ID
+    Add {
+        /// Twitter user ID
+        id: u64,
+        /// Note text
+        text: String,
+        /// Note author (defaults to the current user)
+        #[clap(long)]
+        author: Option<String>,
+    },
+    /// List notes for a Twitter user ID
+    List {
+        /// Twitter user ID
+        id: u64,
+    },
+    /// Remove a note by its epoch-millisecond timestamp
+    Remove {
+        /// Twitter user ID
+        id: u64,
+        /// Note timestamp (as printed by the list command)
+        timestamp: i64,
+    },
+}
+
+pub fn run(db: &Path, options: &DatabaseOptions, command: NoteCommand) -> Result<(), Error> {
+    match command {
+        NoteCommand::Add { id, text, author } => {
+            let notes = NoteTable::<Writeable>::open_with_options(db.join(NOTES_TABLE), options)?;
+            let author = match author {
+                Some(author) => author,
+                None => std::env::var("USER").unwrap_or_default(),
+            };
+            let note = Note {
+                timestamp: Utc::now(),
+                author,
+                text,
+            };
+
+            let timestamp = notes.add(id, &note)?;
+            println!("{}", timestamp.timestamp_millis());
+        }
+        NoteCommand::List { id } => {
+            // A database where no notes have been added has no notes table
+            let notes_path = db.join(NOTES_TABLE);
+
+            if !notes_path.exists() {
+                return Ok(());
+            }
+
+            let notes = NoteTable::<ReadOnly>::open_with_options(notes_path, options)?;
+
+            for note in notes.lookup(id)? {
+                println!(
+                    "{},{},{},{}",
+                    note.timestamp.timestamp_millis(),
+                    note.timestamp.to_rfc3339(),
+                    note.author,
+                    note.text
+                );
+            }
+        }
+        NoteCommand::Remove { id, timestamp } => {
+            let notes_path = db.join(NOTES_TABLE);
+
+            if !notes_path.exists() {
+                return Ok(());
+            }
+
+            let notes = NoteTable::<Writeable>::open_with_options(notes_path, options)?;
+            let timestamp = memory_lol::timestamp::from_epoch_millis(timestamp)?;
+
+            notes.remove(id, &timestamp)?;
+        }
+    }
+
+    Ok(())
+}
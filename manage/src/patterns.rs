@@ -0,0 +1,253 @@
+use crate::Error;
+use chrono::Datelike;
+use memory_lol::db::{Database, ReadOnly};
+use serde_derive::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Digit runs of this length in this range are treated as years.
+const YEAR_RANGE: std::ops::RangeInclusive<u16> = 1900..=2039;
+/// Minimum length of a numeric suffix to count.
+const MIN_DIGIT_SUFFIX_LEN: usize = 4;
+
+/// A piece of a screen name.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Token {
+    /// A run of letters (split at lowercase-to-uppercase changes), lowercased
+    Word(String),
+    /// A run of digits that isn't a plausible year
+    Number(String),
+    Year(u16),
+    Underscore,
+}
+
+impl Token {
+    /// A short description of the kind of token, used to summarize screen names (`w` for a word,
+    /// `d<length>` for a number, `y` for a year, and `_`).
+    fn shape(&self) -> String {
+        match self {
+            Token::Word(_) => "w".to_string(),
+            Token::Number(digits) => format!("d{}", digits.len()),
+            Token::Year(_) => "y".to_string(),
+            Token::Underscore => "_".to_string(),
+        }
+    }
+}
+
+/// Split a screen name into words, numbers, years, and underscores.
+pub fn tokenize(screen_name: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut current = String::new();
+
+    let finish = |current: &mut String, tokens: &mut Vec<Token>| {
+        if let Some(first) = current.chars().next() {
+            let token = if first.is_numeric() {
+                match current.parse::<u16>() {
+                    Ok(year) if current.len() == 4 && YEAR_RANGE.contains(&year) => {
+                        Token::Year(year)
+                    }
+                    _ => Token::Number(current.clone()),
+                }
+            } else {
+                Token::Word(current.to_lowercase())
+            };
+
+            tokens.push(token);
+            current.clear();
+        }
+    };
+
+    let mut previous: Option<char> = None;
+
+    for ch in screen_name.chars() {
+        if ch == '_' {
+            finish(&mut current, &mut tokens);
+            tokens.push(Token::Underscore);
+        } else {
+            let boundary = previous.is_some_and(|previous| {
+                previous != '_'
+                    && (previous.is_numeric() != ch.is_numeric()
+                        || (previous.is_lowercase() && ch.is_uppercase()))
+            });
+
+            if boundary {
+                finish(&mut current, &mut tokens);
+            }
+
+            current.push(ch);
+        }
+
+        previous = Some(ch);
+    }
+
+    finish(&mut current, &mut tokens);
+
+    tokens
+}
+
+/// Counts of naming patterns among the screen names first observed in a year.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct YearPatterns {
+    pub screen_names: u64,
+    /// Screen names ending in a number of at least four digits (or a year)
+    pub digit_suffix: u64,
+    /// Screen names containing a plausible year
+    pub year: u64,
+    /// Screen names containing an underscore
+    pub underscore: u64,
+}
+
+/// Naming patterns across every screen name in the database.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct Patterns {
+    /// Counts by the year each pair (account and screen name) was first observed
+    pub by_year: BTreeMap<i32, YearPatterns>,
+    /// The most common token sequences (for example `w_wd4`), with their counts
+    pub shapes: Vec<(String, u64)>,
+    /// The most common words, with the number of screen names they appear in
+    pub words: Vec<(String, u64)>,
+}
+
+impl Patterns {
+    /// Tally the patterns of every pair, keeping the most common shapes and words.
+    pub fn compute(db: &Database<ReadOnly>, top: usize) -> Result<Self, Error> {
+        let mut by_year = BTreeMap::<i32, YearPatterns>::new();
+        let mut shapes = HashMap::<String, u64>::new();
+        let mut words = HashMap::<String, u64>::new();
+
+        for pair in db.accounts.pairs() {
+            let (_, screen_name, dates) = pair?;
+            let tokens = tokenize(&screen_name);
+
+            *shapes
+                .entry(tokens.iter().map(Token::shape).collect())
+                .or_default() += 1;
+
+            let mut seen = tokens
+                .iter()
+                .filter_map(|token| match token {
+                    Token::Word(word) => Some(word.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            seen.sort_unstable();
+            seen.dedup();
+
+            for word in seen {
+                *words.entry(word.to_string()).or_default() += 1;
+            }
+
+            if let Some(first) = dates.iter().min() {
+                let counts = by_year.entry(first.year()).or_default();
+                counts.screen_names += 1;
+
+                match tokens.last() {
+                    Some(Token::Number(digits)) if digits.len() >= MIN_DIGIT_SUFFIX_LEN => {
+                        counts.digit_suffix += 1;
+                    }
+                    Some(Token::Year(_)) => counts.digit_suffix += 1,
+                    _ => {}
+                }
+
+                if tokens.iter().any(|token| matches!(token, Token::Year(_))) {
+                    counts.year += 1;
+                }
+                if tokens.contains(&Token::Underscore) {
+                    counts.underscore += 1;
+                }
+            }
+        }
+
+        Ok(Self {
+            by_year,
+            shapes: most_common(shapes, top),
+            words: most_common(words, top),
+        })
+    }
+}
+
+/// The entries with the highest counts (ties broken alphabetically).
+fn most_common(counts: HashMap<String, u64>, top: usize) -> Vec<(String, u64)> {
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_unstable_by(|(value_a, count_a), (value_b, count_b)| {
+        count_b.cmp(count_a).then_with(|| value_a.cmp(value_b))
+    });
+    counts.truncate(top);
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use memory_lol::db::Writeable;
+
+    #[test]
+    fn tokenize_screen_names() {
+        assert_eq!(
+            tokenize("RealJohn_Smith1987"),
+            vec![
+                Token::Word("real".to_string()),
+                Token::Word("john".to_string()),
+                Token::Underscore,
+                Token::Word("smith".to_string()),
+                Token::Year(1987),
+            ]
+        );
+        assert_eq!(
+            tokenize("NASA2024x_00123"),
+            vec![
+                Token::Word("nasa".to_string()),
+                Token::Year(2024),
+                Token::Word("x".to_string()),
+                Token::Underscore,
+                Token::Number("00123".to_string()),
+            ]
+        );
+        assert!(tokenize("").is_empty());
+    }
+
+    #[test]
+    fn compute_patterns() {
+        let dir = tempfile::tempdir().unwrap();
+
+        {
+            let db = Database::<Writeable>::open(dir.path()).unwrap();
+            let date = |year| NaiveDate::from_ymd_opt(year, 6, 1).unwrap();
+
+            db.insert(1, "foo", vec![date(2019), date(2021)]).unwrap();
+            db.insert(1, "foo48213", vec![date(2021)]).unwrap();
+            db.insert(2, "foo_1990", vec![date(2021)]).unwrap();
+            db.insert(3, "Bar_Foo", vec![date(2019)]).unwrap();
+        }
+
+        let db = Database::<ReadOnly>::open(dir.path()).unwrap();
+        let patterns = Patterns::compute(&db, 2).unwrap();
+
+        assert_eq!(
+            patterns.by_year[&2019],
+            YearPatterns {
+                screen_names: 2,
+                digit_suffix: 0,
+                year: 0,
+                underscore: 1
+            }
+        );
+        assert_eq!(
+            patterns.by_year[&2021],
+            YearPatterns {
+                screen_names: 2,
+                digit_suffix: 2,
+                year: 1,
+                underscore: 1
+            }
+        );
+        assert_eq!(
+            patterns.shapes,
+            vec![("w".to_string(), 1), ("w_w".to_string(), 1)]
+        );
+        assert_eq!(
+            patterns.words,
+            vec![("foo".to_string(), 4), ("bar".to_string(), 1)]
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use memory_lol::db::bulk::Sorter;
+use serde_json::Value;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("Database error")]
+    Db(#[from] memory_lol::db::Error),
+    #[error("Line has no user ID")]
+    InvalidLine(String),
+}
+
+/// Formats of inputs that can be presorted.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, clap::ValueEnum)]
+pub enum InputFormat {
+    /// Lines starting with a user ID (as read by import-mentions and import-multi)
+    Csv,
+    /// JSON objects with an `id_str` field (as read by import-json)
+    Json,
+}
+
+impl InputFormat {
+    fn user_id(&self, line: &str) -> Option<u64> {
+        match self {
+            InputFormat::Csv => line.split(',').next()?.trim().parse().ok(),
+            InputFormat::Json => serde_json::from_str::<Value>(line)
+                .ok()?
+                .get("id_str")?
+                .as_str()?
+                .parse()
+                .ok(),
+        }
+    }
+}
+
+/// The result of sorting an input.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PresortReport {
+    pub lines: usize,
+    /// Number of sorted runs written to the scratch directory
+    pub runs: usize,
+}
+
+/// Sort the lines of an input by user ID, spilling sorted runs to a scratch directory (which is
+/// removed afterwards).
+///
+/// Lines with the same user ID keep their input order, and empty lines are dropped.
+pub fn presort<R: BufRead, W: Write>(
+    input: R,
+    mut output: W,
+    format: InputFormat,
+    scratch: &Path,
+    run_size: usize,
+) -> Result<PresortReport, Error> {
+    std::fs::create_dir_all(scratch)?;
+
+    // Keys are unique (the line number follows the ID), so values are never combined
+    let mut sorter = Sorter::new(scratch.join("lines"), run_size, |_, _| {});
+    let mut report = PresortReport::default();
+
+    for line in input.lines() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let id = format
+            .user_id(&line)
+            .ok_or_else(|| Error::InvalidLine(line.clone()))?;
+
+        let mut key = Vec::with_capacity(16);
+        key.extend_from_slice(&id.to_be_bytes());
+        key.extend_from_slice(&(report.lines as u64).to_be_bytes());
+
+        sorter.push(key, line.into_bytes())?;
+        report.lines += 1;
+    }
+
+    let sorted = sorter.into_sorted()?;
+    report.runs = sorted.runs();
+
+    for entry in sorted {
+        let (_, line) = entry?;
+        output.write_all(&line)?;
+        output.write_all(b"\n")?;
+    }
+
+    output.flush()?;
+    std::fs::remove_dir_all(scratch)?;
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presort_csv() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = "3,baz,1\n1,foo,2\n\n2,bar,3\n1,foo,1\n10,qux,1\n";
+        let mut output = vec![];
+
+        let report = presort(
+            input.as_bytes(),
+            &mut output,
+            InputFormat::Csv,
+            &dir.path().join("scratch"),
+            2,
+        )
+        .unwrap();
+
+        assert_eq!(report, PresortReport { lines: 5, runs: 3 });
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "1,foo,2\n1,foo,1\n2,bar,3\n3,baz,1\n10,qux,1\n"
+        );
+        assert!(!dir.path().join("scratch").exists());
+
+        let input = "{\"id_str\":\"2\"}\n{\"id_str\":\"1\"}\n{\"id\":3}\n";
+
+        assert!(matches!(
+            presort(
+                input.as_bytes(),
+                vec![],
+                InputFormat::Json,
+                &dir.path().join("scratch"),
+                2,
+            ),
+            Err(Error::InvalidLine(line)) if line == "{\"id\":3}"
+        ));
+    }
+}
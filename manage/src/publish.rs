@@ -0,0 +1,406 @@
+//! Splitting a JSON lines export into fixed-size chunks for distribution, with an index of
+//! checksums and optionally a .torrent file.
+use crate::export::{self, Anonymizer};
+use crate::shutdown::Shutdown;
+use memory_lol::db::{Database, ReadOnly};
+use serde_derive::Serialize;
+use sha1::{Digest, Sha1};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Name of the index file (an export manifest listing every chunk).
+pub const INDEX_FILE: &str = "manifest.json";
+/// Name of the generated torrent file.
+pub const TORRENT_FILE: &str = "dataset.torrent";
+
+/// Torrent pieces are at least this large, and doubled until there are few enough of them.
+const MIN_PIECE_LENGTH: u64 = 1 << 18;
+const MAX_PIECE_LENGTH: u64 = 1 << 24;
+const TARGET_PIECES: u64 = 2000;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("Export error")]
+    Export(#[from] export::Error),
+    #[error("Output directory is not empty")]
+    NotEmpty(PathBuf),
+    #[error("Interrupted (the output directory is incomplete)")]
+    Interrupted,
+}
+
+/// Parse a size in bytes, with an optional binary `K`, `M`, `G`, or `T` suffix (so `1G` is 2³⁰
+/// bytes).
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (digits, shift) = match value.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&value[..value.len() - 1], 10),
+        Some('M') => (&value[..value.len() - 1], 20),
+        Some('G') => (&value[..value.len() - 1], 30),
+        Some('T') => (&value[..value.len() - 1], 40),
+        _ => (value, 0),
+    };
+
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|count| count.checked_mul(1 << shift))
+        .filter(|size| *size > 0)
+        .ok_or_else(|| format!("Invalid size: {}", value))
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct PublishReport {
+    pub accounts: usize,
+    pub chunks: usize,
+    pub bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub torrent: Option<PathBuf>,
+}
+
+impl std::fmt::Display for PublishReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Accounts: {}", self.accounts)?;
+        writeln!(f, "Chunks: {}", self.chunks)?;
+        write!(f, "Bytes: {}", self.bytes)?;
+
+        if let Some(torrent) = &self.torrent {
+            write!(f, "\nTorrent: {}", torrent.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Export every account to chunks of at most `chunk_size` bytes in an empty directory, write an
+/// index of them, and optionally a torrent of the chunks and the index (announced to the given
+/// trackers, or trackerless if there are none).
+///
+/// Chunks are split between lines, so each one can be imported on its own (a line longer than the
+/// chunk size gets a chunk to itself).
+pub fn run(
+    db: &Database<ReadOnly>,
+    anonymizer: Option<&Anonymizer>,
+    dir: &Path,
+    chunk_size: u64,
+    torrent_trackers: Option<&[String]>,
+    shutdown: &Shutdown,
+) -> Result<PublishReport, Error> {
+    std::fs::create_dir_all(dir)?;
+
+    if std::fs::read_dir(dir)?.next().is_some() {
+        return Err(Error::NotEmpty(dir.to_path_buf()));
+    }
+
+    let source_sequence = export::source_sequence(db);
+    let mut writer = ChunkWriter::new(dir, chunk_size);
+    let (accounts, stopped) = export::write_json(db, anonymizer, None, shutdown, &mut writer)?;
+
+    if stopped.is_some() {
+        return Err(Error::Interrupted);
+    }
+
+    let names = writer.finish()?;
+    let names = names.iter().map(String::as_str).collect::<Vec<_>>();
+    let manifest = export::write_manifest_for(&dir.join(INDEX_FILE), &names, source_sequence)?;
+
+    let torrent = match torrent_trackers {
+        Some(trackers) => {
+            let mut files = names;
+            files.push(INDEX_FILE);
+
+            let path = dir.join(TORRENT_FILE);
+            write_torrent(dir, &files, trackers, &path)?;
+            Some(path)
+        }
+        None => None,
+    };
+
+    Ok(PublishReport {
+        accounts,
+        chunks: manifest.files.len(),
+        bytes: manifest.files.iter().map(|file| file.bytes).sum(),
+        torrent,
+    })
+}
+
+/// Writes lines to numbered chunk files, starting a new chunk before a line that wouldn't fit.
+struct ChunkWriter {
+    dir: PathBuf,
+    chunk_size: u64,
+    file: Option<BufWriter<File>>,
+    written: u64,
+    line: Vec<u8>,
+    names: Vec<String>,
+}
+
+impl ChunkWriter {
+    fn new(dir: &Path, chunk_size: u64) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            chunk_size,
+            file: None,
+            written: 0,
+            line: vec![],
+            names: vec![],
+        }
+    }
+
+    fn write_line(&mut self) -> std::io::Result<()> {
+        let len = self.line.len() as u64;
+
+        let file = match self.file.as_mut() {
+            Some(file) if self.written == 0 || self.written + len <= self.chunk_size => file,
+            _ => {
+                if let Some(mut file) = self.file.take() {
+                    file.flush()?;
+                }
+
+                let name = format!("accounts-{:05}.ndjson", self.names.len());
+                let file = BufWriter::new(File::create(self.dir.join(&name))?);
+                self.names.push(name);
+                self.written = 0;
+                self.file.insert(file)
+            }
+        };
+
+        file.write_all(&self.line)?;
+        self.written += len;
+        self.line.clear();
+
+        Ok(())
+    }
+
+    /// Write any incomplete last line, returning the chunk file names.
+    fn finish(mut self) -> std::io::Result<Vec<String>> {
+        if !self.line.is_empty() {
+            self.write_line()?;
+        }
+
+        self.flush()?;
+
+        Ok(self.names)
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for segment in buf.split_inclusive(|byte| *byte == b'\n') {
+            self.line.extend_from_slice(segment);
+
+            if segment.ends_with(b"\n") {
+                self.write_line()?;
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+enum Bencode {
+    Int(u64),
+    Bytes(Vec<u8>),
+    List(Vec<Bencode>),
+    Dict(BTreeMap<&'static str, Bencode>),
+}
+
+impl Bencode {
+    fn string(value: &str) -> Self {
+        Self::Bytes(value.as_bytes().to_vec())
+    }
+
+    fn encode(&self, output: &mut Vec<u8>) {
+        match self {
+            Self::Int(value) => output.extend_from_slice(format!("i{}e", value).as_bytes()),
+            Self::Bytes(bytes) => {
+                output.extend_from_slice(format!("{}:", bytes.len()).as_bytes());
+                output.extend_from_slice(bytes);
+            }
+            Self::List(values) => {
+                output.push(b'l');
+                values.iter().for_each(|value| value.encode(output));
+                output.push(b'e');
+            }
+            // Keys must be sorted by their bytes, which is the map's order for ASCII keys
+            Self::Dict(entries) => {
+                output.push(b'd');
+
+                for (key, value) in entries {
+                    Self::string(key).encode(output);
+                    value.encode(output);
+                }
+
+                output.push(b'e');
+            }
+        }
+    }
+}
+
+/// Write a multi-file torrent of files in a directory, announced to the given trackers.
+fn write_torrent(
+    dir: &Path,
+    names: &[&str],
+    trackers: &[String],
+    path: &Path,
+) -> Result<(), Error> {
+    let mut lengths = vec![];
+
+    for name in names {
+        lengths.push(std::fs::metadata(dir.join(name))?.len());
+    }
+
+    let total = lengths.iter().sum::<u64>();
+    let mut piece_length = MIN_PIECE_LENGTH;
+
+    while total / piece_length > TARGET_PIECES && piece_length < MAX_PIECE_LENGTH {
+        piece_length <<= 1;
+    }
+
+    // Pieces span file boundaries, as if the files were concatenated
+    let mut pieces = vec![];
+    let mut piece = Vec::with_capacity(piece_length as usize);
+    let mut buffer = vec![0; 1 << 16];
+
+    for name in names {
+        let mut file = File::open(dir.join(name))?;
+
+        loop {
+            let read = file.read(&mut buffer)?;
+
+            if read == 0 {
+                break;
+            }
+
+            let mut remaining = &buffer[..read];
+
+            while !remaining.is_empty() {
+                let take = remaining.len().min(piece_length as usize - piece.len());
+                piece.extend_from_slice(&remaining[..take]);
+                remaining = &remaining[take..];
+
+                if piece.len() == piece_length as usize {
+                    pieces.extend_from_slice(&Sha1::digest(&piece));
+                    piece.clear();
+                }
+            }
+        }
+    }
+
+    if !piece.is_empty() {
+        pieces.extend_from_slice(&Sha1::digest(&piece));
+    }
+
+    let files = names
+        .iter()
+        .zip(lengths)
+        .map(|(name, length)| {
+            Bencode::Dict(BTreeMap::from([
+                ("length", Bencode::Int(length)),
+                ("path", Bencode::List(vec![Bencode::string(name)])),
+            ]))
+        })
+        .collect();
+
+    let dir_name = dir
+        .canonicalize()?
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("memory-lol")
+        .to_string();
+
+    let mut torrent = BTreeMap::from([
+        ("created by", Bencode::string(env!("CARGO_BIN_NAME"))),
+        (
+            "creation date",
+            Bencode::Int(chrono::Utc::now().timestamp().max(0) as u64),
+        ),
+        (
+            "info",
+            Bencode::Dict(BTreeMap::from([
+                ("files", Bencode::List(files)),
+                ("name", Bencode::string(&dir_name)),
+                ("piece length", Bencode::Int(piece_length)),
+                ("pieces", Bencode::Bytes(pieces)),
+            ])),
+        ),
+    ]);
+
+    if let Some(tracker) = trackers.first() {
+        torrent.insert("announce", Bencode::string(tracker));
+    }
+
+    if trackers.len() > 1 {
+        torrent.insert(
+            "announce-list",
+            Bencode::List(
+                trackers
+                    .iter()
+                    .map(|tracker| Bencode::List(vec![Bencode::string(tracker)]))
+                    .collect(),
+            ),
+        );
+    }
+
+    let mut output = vec![];
+    Bencode::Dict(torrent).encode(&mut output);
+    std::fs::write(path, output)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memory_lol::db::Writeable;
+
+    #[test]
+    fn publish() {
+        assert_eq!(parse_size("1G"), Ok(1 << 30));
+        assert_eq!(parse_size("512k"), Ok(512 << 10));
+        assert_eq!(parse_size("100"), Ok(100));
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("G").is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+
+        {
+            let db = Database::<Writeable>::open(dir.path().join("db")).unwrap();
+
+            for id in 1..=10 {
+                db.insert(id, &format!("user{}", id), vec![]).unwrap();
+            }
+        }
+
+        let db = Database::<ReadOnly>::open(dir.path().join("db")).unwrap();
+        let out = dir.path().join("out");
+        let trackers = vec!["udp://tracker.example:1337/announce".to_string()];
+
+        // Each line is about 60 bytes, so two fit in a chunk
+        let report = run(&db, None, &out, 130, Some(&trackers), &Shutdown::default()).unwrap();
+        assert_eq!((report.accounts, report.chunks), (10, 5));
+
+        let (manifest, mismatches) = export::verify(&out.join(INDEX_FILE)).unwrap();
+        assert!(mismatches.is_empty());
+        assert!(manifest.files.iter().all(|file| file.records == 2));
+        assert_eq!(manifest.files[4].name, "accounts-00004.ndjson");
+
+        let torrent = std::fs::read(out.join(TORRENT_FILE)).unwrap();
+        assert!(torrent.starts_with(b"d8:announce35:udp://tracker.example:1337/announce"));
+        assert!(torrent.ends_with(b"ee"));
+
+        assert!(matches!(
+            run(&db, None, &out, 130, None, &Shutdown::default()),
+            Err(Error::NotEmpty(_))
+        ));
+    }
+}
@@ -0,0 +1,97 @@
+use crate::Error;
+use clap::Parser;
+use memory_lol::db::{
+    imports::ImportTable,
+    quarantine,
+    stats::{StatsTable, Totals},
+    Database, DatabaseOptions, ReadOnly, Writeable, IMPORTS_TABLE, STATS_TABLE,
+};
+use memory_lol::model::ImportRecord;
+use std::path::Path;
+
+#[derive(Debug, Parser)]
+pub enum QuarantineCommand {
+    /// Print account, screen name, and pair counts for the quarantine, and its imports in CSV
+    /// format
+    Status,
+    /// Merge every quarantined pair into the main database (recording the quarantined imports in
+    /// its import ledger), and empty the quarantine
+    Promote,
+    /// Delete everything in the quarantine
+    Discard,
+}
+
+pub fn run(db: &Path, options: &DatabaseOptions, command: QuarantineCommand) -> Result<(), Error> {
+    let quarantine_path = quarantine::path(db);
+
+    if !quarantine_path.exists() {
+        return Err(Error::NotFound(quarantine::QUARANTINE_DIR.to_string()));
+    }
+
+    match command {
+        QuarantineCommand::Status => {
+            let quarantine = Database::<ReadOnly>::open_with_options(&quarantine_path, options)?;
+            let (account_counts, screen_name_counts) = quarantine.get_counts()?;
+
+            println!("Accounts: {}", account_counts.id_count);
+            println!("Pairs: {}", account_counts.pair_count);
+            println!("Screen names: {}", screen_name_counts.screen_name_count);
+
+            for record in quarantined_imports(&quarantine_path, options)? {
+                println!(
+                    "{},{},{},{},{}",
+                    record.timestamp.to_rfc3339(),
+                    record.source,
+                    record.hash.unwrap_or_default(),
+                    record.pairs,
+                    record.new_pairs
+                );
+            }
+        }
+        QuarantineCommand::Promote => {
+            let main = Database::<Writeable>::open_with_options(db, options)?;
+            let imports =
+                ImportTable::<Writeable>::open_with_options(db.join(IMPORTS_TABLE), options)?;
+            let stats = StatsTable::<Writeable>::open_with_options(db.join(STATS_TABLE), options)?;
+
+            let report = {
+                let quarantine =
+                    Database::<ReadOnly>::open_with_options(&quarantine_path, options)?;
+                quarantine::promote(&main, &quarantine)?
+            };
+
+            // The quarantined imports' own counts are relative to the quarantine, so the totals
+            // are updated from the promotion instead
+            for record in quarantined_imports(&quarantine_path, options)? {
+                imports.add(&record)?;
+            }
+            stats.add_totals(&Totals {
+                accounts: report.new_accounts as u64,
+                pairs: report.new_pairs as u64,
+            })?;
+
+            std::fs::remove_dir_all(&quarantine_path)?;
+            println!("{}", report);
+        }
+        QuarantineCommand::Discard => {
+            std::fs::remove_dir_all(&quarantine_path)?;
+            log::info!("Discarded the quarantine");
+        }
+    }
+
+    Ok(())
+}
+
+/// The imports recorded in the quarantine's ledger.
+fn quarantined_imports(
+    quarantine_path: &Path,
+    options: &DatabaseOptions,
+) -> Result<Vec<ImportRecord>, Error> {
+    let imports_path = quarantine_path.join(IMPORTS_TABLE);
+
+    if imports_path.exists() {
+        Ok(ImportTable::<ReadOnly>::open_with_options(imports_path, options)?.records()?)
+    } else {
+        Ok(vec![])
+    }
+}
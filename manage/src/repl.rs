@@ -0,0 +1,191 @@
+use crate::lookup::{self, Query};
+use memory_lol::db::{Database, ReadOnly, Table};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::{Context, Editor, Helper, Highlighter, Hinter, Validator};
+
+const PROMPT: &str = "memory.lol> ";
+const COMMANDS: [&str; 7] = ["help", "id", "name", "prefix", "stats", "quit", "exit"];
+const COMPLETION_LIMIT: usize = 32;
+const PREFIX_LIMIT: usize = 100;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Readline error")]
+    Readline(#[from] ReadlineError),
+    #[error("Lookup error")]
+    Lookup(#[from] lookup::Error),
+    #[error("Database error")]
+    Db(#[from] memory_lol::db::Error),
+}
+
+#[derive(Helper, Hinter, Highlighter, Validator)]
+struct ReplHelper<'a> {
+    db: &'a Database<ReadOnly>,
+}
+
+impl Completer for ReplHelper<'_> {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+
+        match line.split_once(' ') {
+            None => Ok((
+                0,
+                COMMANDS
+                    .iter()
+                    .filter(|command| command.starts_with(line))
+                    .map(|command| command.to_string())
+                    .collect(),
+            )),
+            Some(("name" | "prefix", argument)) => {
+                let start = pos - argument.len();
+                let candidates = if argument.is_empty() {
+                    vec![]
+                } else {
+                    self.db
+                        .lookup_by_screen_name_prefix(argument, COMPLETION_LIMIT)
+                        .map(|results| {
+                            results
+                                .into_iter()
+                                .map(|(screen_name, _)| screen_name)
+                                .collect()
+                        })
+                        .unwrap_or_default()
+                };
+
+                Ok((start, candidates))
+            }
+            Some(_) => Ok((pos, vec![])),
+        }
+    }
+}
+
+/// Run an interactive prompt against a single open database.
+///
+/// Errors from individual commands are printed and the prompt continues; only end of input or an
+/// interrupt ends the session.
+pub fn run(db: &Database<ReadOnly>) -> Result<(), Error> {
+    let mut editor = Editor::<ReplHelper, DefaultHistory>::new()?;
+    editor.set_helper(Some(ReplHelper { db }));
+
+    loop {
+        match editor.readline(PROMPT) {
+            Ok(line) => {
+                let line = line.trim();
+
+                if line.is_empty() {
+                    continue;
+                }
+
+                editor.add_history_entry(line)?;
+
+                match execute(db, line) {
+                    Ok(true) => {}
+                    Ok(false) => break,
+                    Err(error) => print_error(&error),
+                }
+            }
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Err(error) => return Err(error.into()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Run a single command, returning false if the session should end.
+fn execute(db: &Database<ReadOnly>, line: &str) -> Result<bool, Error> {
+    match line.split_once(' ').unwrap_or((line, "")) {
+        ("quit" | "exit", _) => return Ok(false),
+        ("help", _) => print_help(),
+        ("stats", _) => {
+            if let Some(count) = db.accounts.get_estimated_key_count()? {
+                println!("Estimated account keys: {count}");
+            }
+            if let Some(count) = db.screen_names.get_estimated_key_count()? {
+                println!("Estimated screen name keys: {count}");
+            }
+        }
+        ("id", argument) => match argument.trim().parse::<u64>() {
+            Ok(id) => print_result(&lookup::resolve(db, &Query::Id(id))?),
+            Err(_) => println!("Invalid user ID: {}", argument.trim()),
+        },
+        ("lookup", argument) => match lookup::resolve_input(db, argument) {
+            Ok(result) => print_result(&result),
+            Err(lookup::Error::InvalidId(_) | lookup::Error::EmptyQuery) => {
+                println!("Invalid query: {}", argument.trim())
+            }
+            Err(error) => return Err(error.into()),
+        },
+        ("name", argument) => {
+            let query = Query::ScreenName(argument.trim().to_string());
+            print_result(&lookup::resolve(db, &query)?);
+        }
+        ("prefix", argument) => {
+            for (screen_name, ids) in
+                db.lookup_by_screen_name_prefix(argument.trim(), PREFIX_LIMIT)?
+            {
+                let id_strings = ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+                println!("{}: {}", screen_name, id_strings.join(", "));
+            }
+        }
+        (command, _) => println!("Unknown command: {} (try \"help\")", command),
+    }
+
+    Ok(true)
+}
+
+fn print_error(error: &Error) {
+    let mut message = error.to_string();
+    let mut source = std::error::Error::source(error);
+
+    while let Some(error) = source {
+        message.push_str(": ");
+        message.push_str(&error.to_string());
+        source = error.source();
+    }
+
+    eprintln!("Error: {}", message);
+}
+
+fn print_help() {
+    println!("id <user ID>          Look up a Twitter user ID");
+    println!("name <screen name>    Look up a screen name");
+    println!("lookup <query>        Look up a user ID or screen name (\"@123\" for numeric names)");
+    println!("prefix <prefix>       List screen names starting with a prefix");
+    println!("stats                 Print estimated key counts");
+    println!("quit                  Exit");
+}
+
+fn print_result(result: &lookup::QueryResult) {
+    if result.accounts.is_empty() {
+        println!("No results");
+    }
+
+    for account in &result.accounts {
+        println!("{}", account.id);
+
+        for (screen_name, dates) in &account.screen_names {
+            let dates = dates
+                .as_ref()
+                .map(|dates| {
+                    dates
+                        .iter()
+                        .map(|date| date.to_string())
+                        .collect::<Vec<_>>()
+                        .join(" to ")
+                })
+                .unwrap_or_default();
+
+            println!("  {}: {}", screen_name, dates);
+        }
+    }
+}
@@ -0,0 +1,399 @@
+use crate::Error;
+use chrono::NaiveDate;
+use memory_lol::{
+    db::{
+        notes::NoteTable, tags::TagTable, Database, DatabaseOptions, ReadOnly, NOTES_TABLE,
+        TAGS_TABLE,
+    },
+    model::Note,
+};
+use std::fmt::Write;
+use std::path::Path;
+
+const TIMELINE_WIDTH: f64 = 800.0;
+const TIMELINE_ROW_HEIGHT: usize = 24;
+const TIMELINE_LABEL_WIDTH: usize = 160;
+
+/// Everything known about one account, for an investigation write-up.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AccountReport {
+    pub id: u64,
+    /// Screen names with their sorted observation dates, in order of first observation
+    pub screen_names: Vec<(String, Vec<NaiveDate>)>,
+    /// Other accounts observed with each of the screen names
+    pub shared: Vec<(String, Vec<u64>)>,
+    pub tags: Vec<String>,
+    pub notes: Vec<Note>,
+}
+
+/// A dated entry in the report's timeline.
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum Event {
+    FirstObserved(String),
+    Renamed { from: String, to: String },
+    LastObserved(String),
+    Note { author: String, text: String },
+}
+
+impl AccountReport {
+    /// Collect the report for an account, returning `None` if the account is unknown.
+    pub fn load(
+        db: &Database<ReadOnly>,
+        notes: Option<&NoteTable<ReadOnly>>,
+        tags: Option<&TagTable<ReadOnly>>,
+        id: u64,
+    ) -> Result<Option<Self>, Error> {
+        let mut screen_names = db
+            .lookup_by_user_id(id)?
+            .into_iter()
+            .map(|(screen_name, mut dates)| {
+                dates.sort();
+                (screen_name, dates)
+            })
+            .collect::<Vec<_>>();
+
+        if screen_names.is_empty() {
+            return Ok(None);
+        }
+
+        screen_names.sort_by(|(screen_name_a, dates_a), (screen_name_b, dates_b)| {
+            dates_a
+                .first()
+                .cmp(&dates_b.first())
+                .then_with(|| screen_name_a.cmp(screen_name_b))
+        });
+
+        let mut shared = vec![];
+
+        for (screen_name, _) in &screen_names {
+            let mut ids = db.lookup_by_screen_name(screen_name)?;
+            ids.retain(|other_id| *other_id != id);
+
+            // Differently cased screen names share an index entry
+            if !ids.is_empty()
+                && !shared
+                    .iter()
+                    .any(|(other, _): &(String, _)| other.eq_ignore_ascii_case(screen_name))
+            {
+                ids.sort_unstable();
+                shared.push((screen_name.clone(), ids));
+            }
+        }
+
+        Ok(Some(Self {
+            id,
+            screen_names,
+            shared,
+            tags: tags.map_or_else(|| Ok(vec![]), |tags| tags.tags_for_id(id))?,
+            notes: notes.map_or_else(|| Ok(vec![]), |notes| notes.lookup(id))?,
+        }))
+    }
+
+    /// Screen name observations, renames, and notes in date order.
+    fn events(&self) -> Vec<(NaiveDate, Event)> {
+        let mut events = vec![];
+        let mut previous: Option<&str> = None;
+
+        for (screen_name, dates) in &self.screen_names {
+            if let (Some(first), Some(last)) = (dates.first(), dates.last()) {
+                match previous {
+                    Some(from) => events.push((
+                        *first,
+                        Event::Renamed {
+                            from: from.to_string(),
+                            to: screen_name.clone(),
+                        },
+                    )),
+                    None => events.push((*first, Event::FirstObserved(screen_name.clone()))),
+                }
+
+                events.push((*last, Event::LastObserved(screen_name.clone())));
+                previous = Some(screen_name);
+            }
+        }
+
+        for note in &self.notes {
+            events.push((
+                note.timestamp.date_naive(),
+                Event::Note {
+                    author: note.author.clone(),
+                    text: note.text.clone(),
+                },
+            ));
+        }
+
+        events.sort();
+        events
+    }
+
+    /// A self-contained HTML page (with no external resources) describing the account.
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+
+        // Writing to a string can't fail
+        let _ = write!(
+            html,
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+             <title>Account {id}</title><style>\
+             body {{ font-family: sans-serif; margin: 2em; }} \
+             td, th {{ text-align: left; padding: 0.2em 1em 0.2em 0; }} \
+             .bar {{ fill: #4a7ab5; }} .label {{ font-size: 12px; }}\
+             </style></head><body><h1>Account {id}</h1>",
+            id = self.id
+        );
+
+        if !self.tags.is_empty() {
+            let tags = self
+                .tags
+                .iter()
+                .map(|tag| escape(tag))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let _ = write!(html, "<p>Tags: {}</p>", tags);
+        }
+
+        html.push_str(
+            "<h2>Screen names</h2><table><tr><th>Screen name</th><th>First observed</th>\
+             <th>Last observed</th><th>Observations</th></tr>",
+        );
+
+        for (screen_name, dates) in &self.screen_names {
+            let _ = write!(
+                html,
+                "<tr><td>@{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(screen_name),
+                format_date(dates.first()),
+                format_date(dates.last()),
+                dates.len()
+            );
+        }
+
+        html.push_str("</table>");
+        self.write_timeline(&mut html);
+
+        html.push_str("<h2>Events</h2><table><tr><th>Date</th><th>Event</th></tr>");
+
+        for (date, event) in self.events() {
+            let description = match event {
+                Event::FirstObserved(screen_name) => {
+                    format!("First observed as @{}", escape(&screen_name))
+                }
+                Event::Renamed { from, to } => {
+                    format!("Renamed from @{} to @{}", escape(&from), escape(&to))
+                }
+                Event::LastObserved(screen_name) => {
+                    format!("Last observed as @{}", escape(&screen_name))
+                }
+                Event::Note { author, text } => {
+                    format!("Note by {}: {}", escape(&author), escape(&text))
+                }
+            };
+
+            let _ = write!(html, "<tr><td>{}</td><td>{}</td></tr>", date, description);
+        }
+
+        html.push_str("</table>");
+
+        if !self.shared.is_empty() {
+            html.push_str(
+                "<h2>Screen names used by other accounts</h2><table><tr><th>Screen name</th>\
+                 <th>Other account IDs</th></tr>",
+            );
+
+            for (screen_name, ids) in &self.shared {
+                let ids = ids
+                    .iter()
+                    .map(|id| id.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let _ = write!(
+                    html,
+                    "<tr><td>@{}</td><td>{}</td></tr>",
+                    escape(screen_name),
+                    ids
+                );
+            }
+
+            html.push_str("</table>");
+        }
+
+        html.push_str("</body></html>\n");
+        html
+    }
+
+    /// Draw each screen name's observed range as a bar on a shared time axis.
+    fn write_timeline(&self, html: &mut String) {
+        let dates = self
+            .screen_names
+            .iter()
+            .flat_map(|(_, dates)| dates.first().into_iter().chain(dates.last()))
+            .collect::<Vec<_>>();
+
+        let (Some(start), Some(end)) = (dates.iter().min(), dates.iter().max()) else {
+            return;
+        };
+
+        let days = ((**end - **start).num_days() + 1) as f64;
+        let height = TIMELINE_ROW_HEIGHT * self.screen_names.len();
+
+        let _ = write!(
+            html,
+            "<h2>Timeline</h2><p>{} to {}</p><svg width=\"{}\" height=\"{}\">",
+            start,
+            end,
+            TIMELINE_LABEL_WIDTH as f64 + TIMELINE_WIDTH,
+            height
+        );
+
+        for (row, (screen_name, dates)) in self.screen_names.iter().enumerate() {
+            let y = row * TIMELINE_ROW_HEIGHT;
+            let _ = write!(
+                html,
+                "<text class=\"label\" x=\"0\" y=\"{}\">@{}</text>",
+                y + TIMELINE_ROW_HEIGHT * 2 / 3,
+                escape(screen_name)
+            );
+
+            if let (Some(first), Some(last)) = (dates.first(), dates.last()) {
+                let x = (*first - **start).num_days() as f64 / days * TIMELINE_WIDTH;
+                // Single-day ranges are still drawn visibly
+                let width = ((*last - *first).num_days() + 1) as f64 / days * TIMELINE_WIDTH;
+
+                let _ = write!(
+                    html,
+                    "<rect class=\"bar\" x=\"{:.1}\" y=\"{}\" width=\"{:.1}\" height=\"{}\">\
+                     <title>@{}: {} to {}</title></rect>",
+                    TIMELINE_LABEL_WIDTH as f64 + x,
+                    y + 4,
+                    width.max(2.0),
+                    TIMELINE_ROW_HEIGHT - 8,
+                    escape(screen_name),
+                    first,
+                    last
+                );
+            }
+        }
+
+        html.push_str("</svg>");
+    }
+}
+
+fn format_date(date: Option<&NaiveDate>) -> String {
+    date.map(|date| date.to_string()).unwrap_or_default()
+}
+
+/// Escape text for inclusion in HTML content or attribute values.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+pub fn run(
+    db_path: &Path,
+    options: &DatabaseOptions,
+    id: u64,
+    output: Option<&Path>,
+) -> Result<(), Error> {
+    let db = Database::<ReadOnly>::open_with_options(db_path, options)?;
+
+    // Databases where no notes or tags have been added have no tables for them
+    let notes_path = db_path.join(NOTES_TABLE);
+    let notes = if notes_path.exists() {
+        Some(NoteTable::<ReadOnly>::open_with_options(
+            notes_path, options,
+        )?)
+    } else {
+        None
+    };
+    let tags_path = db_path.join(TAGS_TABLE);
+    let tags = if tags_path.exists() {
+        Some(TagTable::<ReadOnly>::open_with_options(tags_path, options)?)
+    } else {
+        None
+    };
+
+    let report = AccountReport::load(&db, notes.as_ref(), tags.as_ref(), id)?
+        .ok_or_else(|| Error::NotFound(id.to_string()))?;
+    let html = report.to_html();
+
+    match output {
+        Some(output) => std::fs::write(output, html)?,
+        None => print!("{}", html),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use memory_lol::db::Writeable;
+
+    #[test]
+    fn report() {
+        let dir = tempfile::tempdir().unwrap();
+        let date = |day| NaiveDate::from_ymd_opt(2020, 1, day).unwrap();
+
+        {
+            let db = Database::<Writeable>::open(dir.path()).unwrap();
+            db.insert(1, "foo", vec![date(1), date(5)]).unwrap();
+            db.insert(1, "bar", vec![date(6), date(9)]).unwrap();
+            db.insert(2, "bar", vec![date(10)]).unwrap();
+
+            let notes = NoteTable::<Writeable>::open(dir.path().join(NOTES_TABLE)).unwrap();
+            notes
+                .add(
+                    1,
+                    &Note {
+                        timestamp: Utc.with_ymd_and_hms(2020, 1, 7, 0, 0, 0).unwrap(),
+                        author: "analyst".to_string(),
+                        text: "<script>alert(1)</script>".to_string(),
+                    },
+                )
+                .unwrap();
+        }
+
+        let db = Database::<ReadOnly>::open(dir.path()).unwrap();
+        let notes = NoteTable::<ReadOnly>::open(dir.path().join(NOTES_TABLE)).unwrap();
+        let report = AccountReport::load(&db, Some(&notes), None, 1)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            report.screen_names,
+            vec![
+                ("foo".to_string(), vec![date(1), date(5)]),
+                ("bar".to_string(), vec![date(6), date(9)])
+            ]
+        );
+        assert_eq!(report.shared, vec![("bar".to_string(), vec![2])]);
+        assert_eq!(
+            report
+                .events()
+                .into_iter()
+                .map(|(date, _)| date)
+                .collect::<Vec<_>>(),
+            vec![date(1), date(5), date(6), date(7), date(9)]
+        );
+
+        let html = report.to_html();
+        assert!(html.contains("Renamed from @foo to @bar"));
+        assert!(html.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(!html.contains("<script>"));
+
+        assert!(AccountReport::load(&db, None, None, 3).unwrap().is_none());
+    }
+}
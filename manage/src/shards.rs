@@ -0,0 +1,49 @@
+use crate::Error;
+use clap::Parser;
+use memory_lol::db::{shards::ShardedLookup, DatabaseOptions, ReadOnly, Writeable};
+use std::path::Path;
+
+#[derive(Debug, Parser)]
+pub enum ShardCommand {
+    /// Print the number of accounts and pairs in each shard in CSV format
+    List,
+    /// Move every pair to the shard it belongs in under a new number of shards
+    ///
+    /// Pairs in the database directory's own tables (an unsharded database, or later imports) are
+    /// moved into the shards, while its other tables are left in place. The operation can be
+    /// rerun if it's interrupted.
+    Rebalance {
+        /// Number of shards
+        #[clap(long)]
+        shards: usize,
+    },
+}
+
+pub fn run(db: &Path, options: &DatabaseOptions, command: ShardCommand) -> Result<(), Error> {
+    match command {
+        ShardCommand::List => {
+            let shards = ShardedLookup::<ReadOnly>::open(db, options)?
+                .ok_or_else(|| Error::NotSharded(db.to_path_buf()))?;
+
+            for (index, (account_counts, _)) in shards.get_counts()?.into_iter().enumerate() {
+                println!(
+                    "{},{},{}",
+                    index, account_counts.id_count, account_counts.pair_count
+                );
+            }
+        }
+        ShardCommand::Rebalance { shards } => {
+            if shards == 0 {
+                return Err(Error::InvalidShardCount(shards));
+            }
+
+            let report = ShardedLookup::<Writeable>::rebalance(db, shards, options)?;
+
+            println!("Pairs kept: {}", report.pairs_kept);
+            println!("Pairs moved: {}", report.pairs_moved);
+            println!("Shards removed: {}", report.shards_removed);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,34 @@
+use signal_hook::consts::TERM_SIGNALS;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Set when the process is asked to stop, so that long operations can finish their current batch
+/// and save their progress before exiting.
+#[derive(Clone, Debug, Default)]
+pub struct Shutdown(Arc<AtomicBool>);
+
+impl Shutdown {
+    /// Watch for SIGINT, SIGTERM, and SIGQUIT.
+    ///
+    /// A second signal terminates the process immediately, in case the current batch is stuck.
+    pub fn install() -> std::io::Result<Self> {
+        let shutdown = Self::default();
+
+        for signal in TERM_SIGNALS {
+            // Registered first, so that it only fires if the flag was already set
+            signal_hook::flag::register_conditional_shutdown(*signal, 1, shutdown.0.clone())?;
+            signal_hook::flag::register(*signal, shutdown.0.clone())?;
+        }
+
+        Ok(shutdown)
+    }
+
+    pub fn requested(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    #[cfg(test)]
+    pub fn request(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,162 @@
+//! Aligned and optionally paged output for commands that can print long results.
+use std::io::{ErrorKind, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+const DEFAULT_PAGER: &str = "less -FRX";
+
+/// How a command's results are laid out.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum Layout {
+    /// One `<screen name>: <dates>` line per result
+    #[default]
+    Plain,
+    /// Aligned columns, including every date
+    Wide,
+    /// Aligned columns with only the first and last dates and the number of days
+    Narrow,
+}
+
+/// Rows of text printed with every column but the last padded to the width of its widest value.
+pub struct Table {
+    headers: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(headers: Vec<&'static str>) -> Self {
+        Self {
+            headers,
+            rows: vec![],
+        }
+    }
+
+    pub fn push(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    pub fn write<W: Write + ?Sized>(&self, writer: &mut W) -> std::io::Result<()> {
+        let mut widths = self
+            .headers
+            .iter()
+            .map(|header| header.chars().count())
+            .collect::<Vec<_>>();
+
+        for row in &self.rows {
+            for (width, value) in widths.iter_mut().zip(row) {
+                *width = (*width).max(value.chars().count());
+            }
+        }
+
+        let headers = self
+            .headers
+            .iter()
+            .map(|header| header.to_string())
+            .collect::<Vec<_>>();
+
+        for row in std::iter::once(&headers).chain(&self.rows) {
+            let last = row.len().saturating_sub(1);
+            let mut line = String::new();
+
+            for (index, (value, width)) in row.iter().zip(&widths).enumerate() {
+                if index > 0 {
+                    line.push_str("  ");
+                }
+                line.push_str(value);
+
+                if index < last {
+                    let padding = width - value.chars().count();
+                    line.extend(std::iter::repeat_n(' ', padding));
+                }
+            }
+
+            writeln!(writer, "{}", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Run a function that writes output, sending the output through a pager (`$PAGER`, or
+/// `less -FRX`) if paging is enabled and standard output is a terminal.
+///
+/// Quitting the pager before the end of the output isn't an error.
+pub fn paged<T, E: From<std::io::Error>>(
+    enabled: bool,
+    write: impl FnOnce(&mut dyn Write) -> Result<T, E>,
+) -> Result<T, E> {
+    if enabled && std::io::stdout().is_terminal() {
+        let pager = std::env::var("PAGER")
+            .ok()
+            .filter(|pager| !pager.trim().is_empty())
+            .unwrap_or_else(|| DEFAULT_PAGER.to_string());
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&pager)
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let input = child
+            .stdin
+            .take()
+            .ok_or_else(|| std::io::Error::other("Pager has no input"))?;
+
+        let mut input = IgnoreClosed(input);
+        let result = write(&mut input);
+        drop(input);
+        child.wait()?;
+
+        result
+    } else {
+        write(&mut std::io::stdout().lock())
+    }
+}
+
+/// A writer that discards output once its reader has gone away.
+struct IgnoreClosed<W>(W);
+
+impl<W: Write> Write for IgnoreClosed<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.0.write(buf) {
+            Err(error) if error.kind() == ErrorKind::BrokenPipe => Ok(buf.len()),
+            other => other,
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.0.flush() {
+            Err(error) if error.kind() == ErrorKind::BrokenPipe => Ok(()),
+            other => other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_table() {
+        let mut table = Table::new(vec!["screen_name", "days", "dates"]);
+        table.push(vec![
+            "foo".to_string(),
+            "2".to_string(),
+            "2020-01-01, 2020-01-02".to_string(),
+        ]);
+        table.push(vec![
+            "jörg_1234567".to_string(),
+            "10".to_string(),
+            "2020-01-01..2020-01-10".to_string(),
+        ]);
+
+        let mut output = vec![];
+        table.write(&mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                "screen_name   days  dates\n",
+                "foo           2     2020-01-01, 2020-01-02\n",
+                "jörg_1234567  10    2020-01-01..2020-01-10\n",
+            )
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use crate::Error;
+use clap::Parser;
+use memory_lol::db::{tags::TagTable, DatabaseOptions, ReadOnly, Writeable, TAGS_TABLE};
+use std::path::Path;
+
+#[derive(Debug, Parser)]
+pub enum TagCommand {
+    /// Add Twitter user IDs to a tag
+    Add {
+        /// Tag name
+        tag: String,
+        /// Twitter user IDs
+        #[clap(required = true)]
+        ids: Vec<u64>,
+    },
+    /// Remove Twitter user IDs from a tag
+    Remove {
+        /// Tag name
+        tag: String,
+        /// Twitter user IDs
+        #[clap(required = true)]
+        ids: Vec<u64>,
+    },
+    /// List the Twitter user IDs with a tag
+    Members {
+        /// Tag name
+        tag: String,
+    },
+    /// List all tags with member counts
+    List,
+}
+
+pub fn run(db: &Path, options: &DatabaseOptions, command: TagCommand) -> Result<(), Error> {
+    match command {
+        TagCommand::Add { tag, ids } => {
+            let tags = TagTable::<Writeable>::open_with_options(db.join(TAGS_TABLE), options)?;
+
+            for id in ids {
+                tags.add(&tag, id)?;
+            }
+        }
+        TagCommand::Remove { tag, ids } => {
+            let tags = TagTable::<Writeable>::open_with_options(db.join(TAGS_TABLE), options)?;
+
+            for id in ids {
+                tags.remove(&tag, id)?;
+            }
+        }
+        TagCommand::Members { tag } => {
+            let tags = TagTable::<ReadOnly>::open_with_options(db.join(TAGS_TABLE), options)?;
+
+            for id in tags.members(&tag)? {
+                println!("{}", id);
+            }
+        }
+        TagCommand::List => {
+            let tags = TagTable::<ReadOnly>::open_with_options(db.join(TAGS_TABLE), options)?;
+
+            for (tag, count) in tags.tags()? {
+                println!("{},{}", tag, count);
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,47 @@
+//! Export of tracing spans to an OpenTelemetry collector.
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{trace::SdkTracerProvider, Resource};
+use tracing_subscriber::layer::SubscriberExt;
+
+const SERVICE_NAME: &str = env!("CARGO_BIN_NAME");
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("OTLP exporter error")]
+    Exporter(#[from] opentelemetry_otlp::ExporterBuildError),
+    #[error("Tracing subscriber error")]
+    Subscriber(#[from] tracing::subscriber::SetGlobalDefaultError),
+}
+
+/// Exports spans until dropped, when any that are still buffered are flushed.
+pub struct Telemetry {
+    provider: SdkTracerProvider,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Err(error) = self.provider.shutdown() {
+            log::warn!("Failed to flush trace spans: {}", error);
+        }
+    }
+}
+
+/// Send spans in batches to an OTLP/HTTP traces endpoint (for example
+/// `http://localhost:4318/v1/traces`).
+pub fn init(endpoint: &str) -> Result<Telemetry, Error> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()?;
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+        .build();
+
+    let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("memory-lol"));
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))?;
+
+    Ok(Telemetry { provider })
+}
@@ -0,0 +1,213 @@
+use memory_lol::db::{latency::QueryLatency, Database, Writeable};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often to check whether writes should be paused.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Published query latency older than this is ignored (the server has probably stopped).
+const MAX_LATENCY_AGE: Duration = Duration::from_secs(60);
+
+/// Limits on how fast an import writes, so that it doesn't starve other processes sharing the
+/// disk (such as a query server) of I/O.
+///
+/// Writes are paced with a token bucket for each limit, which allows bursts of up to one second's
+/// worth of writes. Writing is also paused while RocksDB is stalling writes, and (if a maximum is
+/// given) while a query server publishing its latency in the database directory reports a p99
+/// above the maximum. Clones share their state.
+#[derive(Clone, Debug, Default)]
+pub struct Throttle {
+    records: Option<Arc<TokenBucket>>,
+    bytes: Option<Arc<TokenBucket>>,
+    /// The database directory and the maximum query p99
+    max_query_p99: Option<(PathBuf, Duration)>,
+    /// When we last checked whether to pause
+    checked: Arc<Mutex<Option<Instant>>>,
+}
+
+impl Throttle {
+    pub fn new(max_records_per_sec: Option<f64>, max_write_mbps: Option<f64>) -> Self {
+        Self {
+            records: max_records_per_sec
+                .filter(|rate| *rate > 0.0)
+                .map(|rate| Arc::new(TokenBucket::new(rate))),
+            bytes: max_write_mbps
+                .filter(|rate| *rate > 0.0)
+                .map(|rate| Arc::new(TokenBucket::new(rate * 1_000_000.0))),
+            ..Default::default()
+        }
+    }
+
+    /// Also pause while the latency published by a query server using the database exceeds a
+    /// maximum.
+    pub fn with_max_query_p99<P: AsRef<Path>>(self, base: P, max_p99: Duration) -> Self {
+        Self {
+            max_query_p99: Some((base.as_ref().to_path_buf(), max_p99)),
+            ..self
+        }
+    }
+
+    /// Wait until a batch of this many records (and approximately this many bytes) can be
+    /// written.
+    pub fn pace(
+        &self,
+        db: &Database<Writeable>,
+        records: usize,
+        bytes: usize,
+    ) -> Result<(), memory_lol::db::Error> {
+        self.wait_for_capacity(db)?;
+
+        let wait = [(&self.records, records), (&self.bytes, bytes)]
+            .into_iter()
+            .filter_map(|(bucket, amount)| bucket.as_ref().map(|bucket| bucket.take(amount)))
+            .max()
+            .unwrap_or_default();
+
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+
+        Ok(())
+    }
+
+    /// Wait while the database is stalling writes or queries are slow, checking at most once
+    /// every [`CHECK_INTERVAL`].
+    fn wait_for_capacity(&self, db: &Database<Writeable>) -> Result<(), memory_lol::db::Error> {
+        let mut checked = self
+            .checked
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if checked.is_some_and(|checked| checked.elapsed() < CHECK_INTERVAL) {
+            return Ok(());
+        }
+
+        let mut paused: Option<Instant> = None;
+
+        loop {
+            let reason = if db.write_stalled()? {
+                Some("RocksDB is stalling writes".to_string())
+            } else {
+                self.slow_query_p99()?
+                    .map(|p99| format!("query p99 is {}ms", p99.as_millis()))
+            };
+
+            match reason {
+                Some(reason) => {
+                    if paused.is_none() {
+                        log::warn!("Pausing import: {}", reason);
+                        paused = Some(Instant::now());
+                    }
+
+                    std::thread::sleep(CHECK_INTERVAL);
+                }
+                None => {
+                    if let Some(paused) = paused {
+                        log::info!("Resuming import after {:?}", paused.elapsed());
+                    }
+
+                    *checked = Some(Instant::now());
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// The published query p99, if it's current and above the maximum.
+    fn slow_query_p99(&self) -> Result<Option<Duration>, memory_lol::db::Error> {
+        match &self.max_query_p99 {
+            Some((base, max_p99)) => Ok(QueryLatency::load(base)?
+                .filter(|latency| latency.is_current(MAX_LATENCY_AGE))
+                .map(|latency| latency.p99())
+                .filter(|p99| p99 > max_p99)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The approximate number of bytes written for a pair (its account table entry and its screen
+/// name index entry).
+pub fn pair_bytes(screen_name: &str, dates: usize) -> usize {
+    2 * (screen_name.len() + 8) + 2 * dates
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    /// Available tokens (negative if the last batch was larger than the bucket) and when they
+    /// were counted
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    fn new(rate: f64) -> Self {
+        Self {
+            rate,
+            state: Mutex::new((rate, Instant::now())),
+        }
+    }
+
+    /// Take tokens, returning how long to wait before the tokens taken have been refilled.
+    fn take(&self, amount: usize) -> Duration {
+        let mut state = self
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (tokens, counted) = *state;
+        let now = Instant::now();
+
+        let available =
+            (tokens + now.duration_since(counted).as_secs_f64() * self.rate).min(self.rate);
+        let remaining = available - amount as f64;
+        *state = (remaining, now);
+
+        if remaining < 0.0 {
+            Duration::from_secs_f64(-remaining / self.rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bucket() {
+        let bucket = TokenBucket::new(100.0);
+
+        // The first second's worth is available immediately
+        assert_eq!(bucket.take(100), Duration::ZERO);
+
+        let wait = bucket.take(50);
+        assert!(wait > Duration::from_millis(450) && wait <= Duration::from_millis(500));
+
+        assert!(Throttle::new(None, Some(0.0)).bytes.is_none());
+    }
+
+    #[test]
+    fn slow_queries() {
+        let dir = tempfile::tempdir().unwrap();
+        let throttle =
+            Throttle::default().with_max_query_p99(dir.path(), Duration::from_millis(50));
+        assert_eq!(throttle.slow_query_p99().unwrap(), None);
+
+        let mut latency = QueryLatency {
+            p99_us: 80_000,
+            queries: 100,
+            updated: chrono::Utc::now(),
+        };
+        latency.save(dir.path()).unwrap();
+        assert_eq!(
+            throttle.slow_query_p99().unwrap(),
+            Some(Duration::from_millis(80))
+        );
+
+        // Latency published by a server that has since stopped is ignored
+        latency.updated -= chrono::Duration::minutes(5);
+        latency.save(dir.path()).unwrap();
+        assert_eq!(throttle.slow_query_p99().unwrap(), None);
+    }
+}
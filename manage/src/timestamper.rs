@@ -0,0 +1,125 @@
+//! Timestamping new snapshots with an RFC 3161 authority or an OpenTimestamps calendar.
+use memory_lol::snapshots::SnapshotStore;
+use memory_lol::timestamping::{self, Proof};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("HTTP error")]
+    Http(#[from] reqwest::Error),
+    #[error("Snapshot error")]
+    Store(#[from] memory_lol::db::Error),
+    #[error("Time-stamping authority didn't grant the request")]
+    NotGranted,
+    #[error("No such snapshot")]
+    MissingSnapshot(String),
+}
+
+/// The services that timestamp snapshots (if none are configured, snapshots aren't timestamped).
+pub struct Timestamper {
+    http: reqwest::blocking::Client,
+    /// URL of an RFC 3161 time-stamping authority
+    tsa_url: Option<String>,
+    /// Base URL of an OpenTimestamps calendar (like `https://a.pool.opentimestamps.org`)
+    calendar_url: Option<String>,
+}
+
+impl Timestamper {
+    pub fn new(tsa_url: Option<String>, calendar_url: Option<String>) -> Result<Self, Error> {
+        Ok(Self {
+            http: reqwest::blocking::Client::builder()
+                .timeout(TIMEOUT)
+                .build()?,
+            tsa_url,
+            calendar_url: calendar_url.map(|url| url.trim_end_matches('/').to_string()),
+        })
+    }
+
+    /// Timestamp a saved snapshot with each configured service, returning the kinds of proofs
+    /// stored for it.
+    ///
+    /// Services are only contacted for proofs the snapshot doesn't already have. Failures are
+    /// logged instead of returned, since the snapshot is still evidence without a timestamp.
+    pub fn stamp(&self, store: &SnapshotStore, id: &str) -> Vec<Proof> {
+        let existing = store.proofs(id);
+        let services = [
+            (Proof::Rfc3161, self.tsa_url.as_deref()),
+            (Proof::OpenTimestamps, self.calendar_url.as_deref()),
+        ];
+
+        services
+            .into_iter()
+            .filter_map(|(proof, url)| {
+                if existing.contains(&proof) {
+                    return Some(proof);
+                }
+
+                let url = url?;
+
+                match self.request_proof(store, id, proof, url) {
+                    Ok(()) => Some(proof),
+                    Err(error) => {
+                        log::warn!(
+                            "Failed to timestamp snapshot {} ({}): {:?}",
+                            id,
+                            proof,
+                            error
+                        );
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn request_proof(
+        &self,
+        store: &SnapshotStore,
+        id: &str,
+        proof: Proof,
+        url: &str,
+    ) -> Result<(), Error> {
+        let digest = store
+            .digest(id)?
+            .ok_or_else(|| Error::MissingSnapshot(id.to_string()))?;
+
+        let contents = match proof {
+            Proof::Rfc3161 => {
+                let request = timestamping::rfc3161_request(&digest, timestamping::nonce());
+                let response = self
+                    .http
+                    .post(url)
+                    .header(
+                        reqwest::header::CONTENT_TYPE,
+                        timestamping::RFC3161_REQUEST_CONTENT_TYPE,
+                    )
+                    .body(request)
+                    .send()?
+                    .error_for_status()?
+                    .bytes()?;
+
+                if !timestamping::rfc3161_granted(&response) {
+                    return Err(Error::NotGranted);
+                }
+
+                response.to_vec()
+            }
+            Proof::OpenTimestamps => {
+                let response = self
+                    .http
+                    .post(format!("{}/digest", url))
+                    .header(reqwest::header::ACCEPT, timestamping::OPENTIMESTAMPS_ACCEPT)
+                    .body(digest.to_vec())
+                    .send()?
+                    .error_for_status()?
+                    .bytes()?;
+
+                timestamping::opentimestamps_file(&digest, &response)
+            }
+        };
+
+        Ok(store.save_proof(id, proof, &contents)?)
+    }
+}
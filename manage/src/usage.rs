@@ -0,0 +1,148 @@
+use crate::Error;
+use memory_lol::db::{
+    accounts::AccountTable, edges::EdgeTable, imports::ImportTable, ngrams::NgramTable,
+    notes::NoteTable, phonetic::PhoneticTable, screen_names::ScreenNameTable, stats::StatsTable,
+    tags::TagTable, weights::WeightTable, DatabaseOptions, ReadOnly, Table, ACCOUNTS_TABLE,
+    EDGES_TABLE, IMPORTS_TABLE, NGRAMS_TABLE, NOTES_TABLE, PHONETIC_TABLE, SCREEN_NAMES_TABLE,
+    STATS_TABLE, TAGS_TABLE, WEIGHTS_TABLE,
+};
+use std::path::Path;
+
+/// Space used by a single table directory.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TableUsage {
+    pub table: &'static str,
+    pub category: &'static str,
+    pub files: u64,
+    pub sst_files: u64,
+    pub sst_bytes: u64,
+    pub total_bytes: u64,
+    pub pending_compaction_bytes: Option<u64>,
+}
+
+impl TableUsage {
+    fn read<T: Table>(
+        table: &'static str,
+        category: &'static str,
+        path: &Path,
+        open: impl FnOnce(&Path) -> Result<T, memory_lol::db::Error>,
+    ) -> Result<Option<Self>, Error> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let mut usage = Self {
+            table,
+            category,
+            pending_compaction_bytes: open(path)?.get_pending_compaction_bytes()?,
+            ..Default::default()
+        };
+
+        for entry in std::fs::read_dir(path)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+
+            if metadata.is_file() {
+                usage.files += 1;
+                usage.total_bytes += metadata.len();
+
+                if entry.path().extension().and_then(|ext| ext.to_str()) == Some("sst") {
+                    usage.sst_files += 1;
+                    usage.sst_bytes += metadata.len();
+                }
+            }
+        }
+
+        Ok(Some(usage))
+    }
+}
+
+/// Measure every table that exists in the database directory.
+pub fn measure(db: &Path, options: &DatabaseOptions) -> Result<Vec<TableUsage>, Error> {
+    let usages = vec![
+        TableUsage::read(
+            ACCOUNTS_TABLE,
+            "forward index",
+            &db.join(ACCOUNTS_TABLE),
+            |path| AccountTable::<ReadOnly>::open_with_options(path, options),
+        )?,
+        TableUsage::read(
+            SCREEN_NAMES_TABLE,
+            "reverse index",
+            &db.join(SCREEN_NAMES_TABLE),
+            |path| ScreenNameTable::<ReadOnly>::open_with_options(path, options),
+        )?,
+        TableUsage::read(
+            NGRAMS_TABLE,
+            "search index",
+            &db.join(NGRAMS_TABLE),
+            |path| NgramTable::<ReadOnly>::open_with_options(path, options),
+        )?,
+        TableUsage::read(
+            PHONETIC_TABLE,
+            "search index",
+            &db.join(PHONETIC_TABLE),
+            |path| PhoneticTable::<ReadOnly>::open_with_options(path, options),
+        )?,
+        TableUsage::read(IMPORTS_TABLE, "metadata", &db.join(IMPORTS_TABLE), |path| {
+            ImportTable::<ReadOnly>::open_with_options(path, options)
+        })?,
+        TableUsage::read(STATS_TABLE, "metadata", &db.join(STATS_TABLE), |path| {
+            StatsTable::<ReadOnly>::open_with_options(path, options)
+        })?,
+        TableUsage::read(
+            WEIGHTS_TABLE,
+            "observations",
+            &db.join(WEIGHTS_TABLE),
+            |path| WeightTable::<ReadOnly>::open_with_options(path, options),
+        )?,
+        TableUsage::read(EDGES_TABLE, "observations", &db.join(EDGES_TABLE), |path| {
+            EdgeTable::<ReadOnly>::open_with_options(path, options)
+        })?,
+        TableUsage::read(NOTES_TABLE, "annotations", &db.join(NOTES_TABLE), |path| {
+            NoteTable::<ReadOnly>::open_with_options(path, options)
+        })?,
+        TableUsage::read(TAGS_TABLE, "annotations", &db.join(TAGS_TABLE), |path| {
+            TagTable::<ReadOnly>::open_with_options(path, options)
+        })?,
+    ];
+
+    Ok(usages.into_iter().flatten().collect())
+}
+
+/// Print a CSV breakdown of space by table, followed by a total row.
+pub fn run(db: &Path, options: &DatabaseOptions) -> Result<(), Error> {
+    let usages = measure(db, options)?;
+
+    println!("table,category,files,sst_files,sst_bytes,total_bytes,pending_compaction_bytes");
+
+    for usage in &usages {
+        println!(
+            "{},{},{},{},{},{},{}",
+            usage.table,
+            usage.category,
+            usage.files,
+            usage.sst_files,
+            usage.sst_bytes,
+            usage.total_bytes,
+            usage
+                .pending_compaction_bytes
+                .map(|bytes| bytes.to_string())
+                .unwrap_or_default()
+        );
+    }
+
+    println!(
+        "total,,{},{},{},{},{}",
+        usages.iter().map(|usage| usage.files).sum::<u64>(),
+        usages.iter().map(|usage| usage.sst_files).sum::<u64>(),
+        usages.iter().map(|usage| usage.sst_bytes).sum::<u64>(),
+        usages.iter().map(|usage| usage.total_bytes).sum::<u64>(),
+        usages
+            .iter()
+            .filter_map(|usage| usage.pending_compaction_bytes)
+            .sum::<u64>()
+    );
+
+    Ok(())
+}
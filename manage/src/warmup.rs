@@ -0,0 +1,144 @@
+//! Preloading the blocks that frequently queried accounts are stored in, so that the first
+//! lookups after a restart don't all miss the cache.
+use crate::bench;
+use crate::import::serialize_duration;
+use crate::lookup::{self, Query};
+use memory_lol::db::{Database, ReadOnly};
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::time::{Duration, Instant};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("Lookup error")]
+    Lookup(#[from] lookup::Error),
+    #[error("Request log error")]
+    RequestLog(#[from] bench::Error),
+}
+
+/// Read hot keys, one user ID or screen name per line (interpreted as `lookup` interprets them).
+///
+/// Blank lines and lines starting with `#` are skipped.
+pub fn read_keys<R: BufRead>(input: R) -> Result<Vec<Query>, Error> {
+    let mut queries = vec![];
+
+    for line in input.lines() {
+        let line = line?;
+
+        if !line.trim_start().starts_with('#') {
+            queries.extend(Query::infer(&line)?);
+        }
+    }
+
+    Ok(queries)
+}
+
+/// The distinct lookups in a web server request log, most frequent first, keeping only the top
+/// ones.
+pub fn most_frequent<R: BufRead>(input: R, top: usize) -> Result<Vec<Query>, Error> {
+    let mut counts = HashMap::new();
+
+    for query in bench::read_request_log(input)? {
+        *counts.entry(query).or_insert(0usize) += 1;
+    }
+
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_unstable_by(|(a, a_count), (b, b_count)| {
+        b_count
+            .cmp(a_count)
+            .then_with(|| a.to_string().cmp(&b.to_string()))
+    });
+    counts.truncate(top);
+
+    Ok(counts.into_iter().map(|(query, _)| query).collect())
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct WarmupReport {
+    pub queries: usize,
+    /// Queries that found at least one account
+    pub hits: usize,
+    #[serde(serialize_with = "serialize_duration")]
+    pub elapsed: Duration,
+}
+
+impl std::fmt::Display for WarmupReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Queries: {}", self.queries)?;
+        writeln!(f, "Hits: {}", self.hits)?;
+        write!(f, "Elapsed: {:.2}s", self.elapsed.as_secs_f64())
+    }
+}
+
+/// Resolve every query, which reads the blocks its accounts are stored in.
+///
+/// Invalid screen names are skipped, since a hot key list shouldn't stop a server from starting.
+pub fn run(db: &Database<ReadOnly>, queries: &[Query]) -> Result<WarmupReport, Error> {
+    let started = Instant::now();
+    let mut report = WarmupReport::default();
+
+    for query in queries {
+        match lookup::resolve(db, query) {
+            Ok(result) => {
+                report.queries += 1;
+
+                if !result.accounts.is_empty() {
+                    report.hits += 1;
+                }
+            }
+            Err(lookup::Error::Db(memory_lol::db::Error::InvalidScreenName(screen_name))) => {
+                log::warn!("Skipping invalid screen name: {}", screen_name);
+            }
+            Err(error) => return Err(error.into()),
+        }
+    }
+
+    report.elapsed = started.elapsed();
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memory_lol::db::Writeable;
+    use std::io::Cursor;
+
+    #[test]
+    fn warm_up() {
+        let keys = read_keys(Cursor::new("# hot accounts\n123\n\n@456\nfoo\n")).unwrap();
+        assert_eq!(
+            keys,
+            vec![
+                Query::Id(123),
+                Query::ScreenName("456".to_string()),
+                Query::ScreenName("foo".to_string())
+            ]
+        );
+
+        let log = "\
+GET /tw/foo application/json:
+GET /tw/id/1 application/json:
+GET /tw/bar,foo application/json:
+GET /tw/id/1?token=abc application/json:
+GET /tw/id/1 application/json:
+";
+        assert_eq!(
+            most_frequent(Cursor::new(log), 2).unwrap(),
+            vec![Query::Id(1), Query::ScreenName("foo".to_string())]
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        Database::<Writeable>::open(dir.path())
+            .unwrap()
+            .insert(1, "foo", vec![])
+            .unwrap();
+        let db = Database::<ReadOnly>::open(dir.path()).unwrap();
+
+        let report = run(&db, &keys).unwrap();
+        assert_eq!((report.queries, report.hits), (3, 1));
+    }
+}
@@ -0,0 +1,81 @@
+use super::AppConfig;
+use memory_lol::{
+    admin::{is_authorized, Runtime, Settings, SettingsUpdate, TrustPolicy},
+    db::SharedCache,
+    ratelimit::RateLimiter,
+};
+use rocket::{
+    http::Status,
+    request::{FromRequest, Outcome, Request},
+    response::status::BadRequest,
+    serde::json::Json,
+    State,
+};
+
+/// A request guard for requests that send the configured admin token as a bearer token.
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let token = request
+            .rocket()
+            .state::<AppConfig>()
+            .and_then(|config| config.admin_token.as_deref());
+
+        match token {
+            Some(token) if is_authorized(token, request.headers().get_one("Authorization")) => {
+                Outcome::Success(Admin)
+            }
+            Some(_) => Outcome::Error((Status::Unauthorized, ())),
+            None => Outcome::Error((Status::NotFound, ())),
+        }
+    }
+}
+
+fn with_runtime<T>(
+    config: &AppConfig,
+    limiter: Option<&State<RateLimiter>>,
+    trust: &TrustPolicy,
+    f: impl FnOnce(Runtime<'_>) -> T,
+) -> T {
+    // Without a configured block cache size, tables use their own default caches
+    let no_cache = SharedCache::default();
+    let cache = config
+        .db_options
+        .as_ref()
+        .map_or(&no_cache, |options| &options.cache);
+
+    f(Runtime {
+        cache,
+        limiter: limiter.map(|limiter| limiter.inner()),
+        trust: Some(trust),
+    })
+}
+
+#[get("/admin/settings")]
+pub fn settings(
+    _admin: Admin,
+    config: &State<AppConfig>,
+    limiter: Option<&State<RateLimiter>>,
+    trust: &State<TrustPolicy>,
+) -> Json<Settings> {
+    Json(with_runtime(config, limiter, trust, |runtime| {
+        runtime.settings()
+    }))
+}
+
+#[post("/admin/settings", data = "<update>")]
+pub fn update_settings(
+    _admin: Admin,
+    update: Json<SettingsUpdate>,
+    config: &State<AppConfig>,
+    limiter: Option<&State<RateLimiter>>,
+    trust: &State<TrustPolicy>,
+) -> Result<Json<Settings>, BadRequest<String>> {
+    with_runtime(config, limiter, trust, |runtime| runtime.apply(&update))
+        .map(Json)
+        .map_err(|error| BadRequest(error.to_string()))
+}
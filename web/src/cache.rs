@@ -0,0 +1,89 @@
+use chrono::{DateTime, Utc};
+use memory_lol::cache::{
+    last_modified, Validators, COMPACT_CACHE_CONTROL, IMMUTABLE_CACHE_CONTROL,
+};
+use rocket::{
+    http::{ContentType, Status},
+    request::Request,
+    response::{Responder, Response, Result},
+};
+use serde::Serialize;
+use std::io::Cursor;
+
+/// When the database was opened (the read-only database doesn't see later writes).
+pub struct DatabaseOpened(pub DateTime<Utc>);
+
+/// A JSON response with cache validators, which answers conditional requests with
+/// `304 Not Modified` when the client's copy is still current.
+pub struct Cached<T>(pub T);
+
+impl<'r, 'o: 'r, T: Serialize> Responder<'r, 'o> for Cached<T> {
+    fn respond_to(self, req: &'r Request<'_>) -> Result<'o> {
+        respond(&self.0, req, "no-cache")
+    }
+}
+
+/// A [`Cached`] response that shared caches can keep and reuse without revalidating for a
+/// while, for results that are the same for every client (see [`COMPACT_CACHE_CONTROL`]).
+pub struct PubliclyCached<T>(pub T);
+
+impl<'r, 'o: 'r, T: Serialize> Responder<'r, 'o> for PubliclyCached<T> {
+    fn respond_to(self, req: &'r Request<'_>) -> Result<'o> {
+        respond(&self.0, req, COMPACT_CACHE_CONTROL)
+    }
+}
+
+/// A JSON document that never changes, served exactly as stored (like an evidence snapshot).
+pub struct Immutable(pub Vec<u8>);
+
+impl<'r, 'o: 'r> Responder<'r, 'o> for Immutable {
+    fn respond_to(self, req: &'r Request<'_>) -> Result<'o> {
+        respond_with_body(self.0, req, IMMUTABLE_CACHE_CONTROL)
+    }
+}
+
+fn respond<'o, T: Serialize>(
+    value: &T,
+    req: &Request<'_>,
+    cache_control: &'static str,
+) -> Result<'o> {
+    let body = serde_json::to_vec(value).map_err(|error| {
+        log::error!("JSON serialization error: {:?}", error);
+        Status::InternalServerError
+    })?;
+
+    respond_with_body(body, req, cache_control)
+}
+
+fn respond_with_body<'o>(
+    body: Vec<u8>,
+    req: &Request<'_>,
+    cache_control: &'static str,
+) -> Result<'o> {
+    let now = Utc::now();
+    let opened = req
+        .rocket()
+        .state::<DatabaseOpened>()
+        .map_or(now, |opened| opened.0);
+    let validators = Validators::new(&body, last_modified(opened, now));
+    let not_modified = validators.is_not_modified(
+        req.headers().get_one("If-None-Match"),
+        req.headers().get_one("If-Modified-Since"),
+    );
+
+    let mut response = Response::build();
+    response
+        .raw_header("ETag", validators.etag.clone())
+        .raw_header("Last-Modified", validators.last_modified_header())
+        .raw_header("Cache-Control", cache_control);
+
+    if not_modified {
+        response.status(Status::NotModified);
+    } else {
+        response
+            .header(ContentType::JSON)
+            .sized_body(body.len(), Cursor::new(body));
+    }
+
+    response.ok()
+}
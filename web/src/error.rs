@@ -13,7 +13,19 @@ pub enum Error {
     #[error("Database error")]
     Db(#[from] memory_lol::db::Error),
     #[error("Invalid Snowflake ID")]
-    InvalidSnowflake(i64),
+    InvalidSnowflake(u64),
+    #[error("No observations of account")]
+    NoObservations(u64),
+    #[error("Embeds are disabled")]
+    EmbedsDisabled,
+    #[error("Not an account URL")]
+    InvalidEmbedUrl(String),
+    #[error("Unsupported oEmbed format")]
+    UnsupportedEmbedFormat(String),
+    #[error("Snapshots are disabled")]
+    SnapshotsDisabled,
+    #[error("No such snapshot")]
+    NoSnapshot(String),
     #[error("OAuth 2.0 error")]
     Oauth2(#[from] rocket_oauth2::Error),
     #[error("Authorization error")]
@@ -29,7 +41,20 @@ pub enum Error {
 impl<'r, 'o: 'r> Responder<'r, 'o> for Error {
     fn respond_to(self, req: &'r Request<'_>) -> Result<'o> {
         match self {
-            Error::InvalidSnowflake(_) => Status::NotFound.respond_to(req),
+            Error::InvalidSnowflake(_)
+            | Error::NoObservations(_)
+            | Error::EmbedsDisabled
+            | Error::InvalidEmbedUrl(_)
+            | Error::SnapshotsDisabled
+            | Error::NoSnapshot(_) => Status::NotFound.respond_to(req),
+            Error::UnsupportedEmbedFormat(_) => Status::NotImplemented.respond_to(req),
+            Error::Db(memory_lol::db::Error::SnapshotLimit(_)) => {
+                Status::InsufficientStorage.respond_to(req)
+            }
+            Error::Db(
+                memory_lol::db::Error::InvalidScreenName(_)
+                | memory_lol::db::Error::TooManyVariants(_),
+            ) => Status::BadRequest.respond_to(req),
             _ => Status::InternalServerError.respond_to(req),
         }
     }
@@ -0,0 +1,346 @@
+use super::{
+    auth, error::Error, inclusions::Inclusions, ratelimit::WithinLimit, Auth, SqliteAuthorizer,
+};
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, Enum, Object, Result, Schema, SimpleObject, ID,
+};
+use async_graphql_rocket::{GraphQLRequest, GraphQLResponse};
+use chrono::{DateTime, NaiveDate, Utc};
+use memory_lol::{
+    admin::TrustPolicy,
+    db::{
+        codec::screen_name_to_key,
+        edges::{self, EdgeKind, EdgeTable},
+        notes::NoteTable,
+        table::ReadOnly,
+        tags::TagTable,
+        Database, DatabaseOptions, EDGES_TABLE, NOTES_TABLE, TAGS_TABLE,
+    },
+};
+use rocket::{http::CookieJar, State};
+use rocket_db_pools::Connection;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Queries can't nest accounts (through links) more deeply than this.
+const MAX_DEPTH: usize = 8;
+const MAX_COMPLEXITY: usize = 1000;
+
+pub type LookupSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+/// The tables queries are resolved from.
+///
+/// Annotations and links can only be queried if their tables have been created.
+pub struct Tables {
+    pub db: Database<ReadOnly>,
+    pub notes: Option<NoteTable<ReadOnly>>,
+    pub tags: Option<TagTable<ReadOnly>>,
+    pub edges: Option<EdgeTable<ReadOnly>>,
+}
+
+impl Tables {
+    /// Open the tables (with a handle of their own on the database, since the schema has to own
+    /// its data).
+    pub fn open<P: AsRef<Path>>(base: P, options: &DatabaseOptions) -> Result<Self, Error> {
+        let base = base.as_ref();
+
+        Ok(Self {
+            db: Database::open_with_options(base, options)?,
+            notes: open_if_exists(base.join(NOTES_TABLE), |path| {
+                NoteTable::open_with_options(path, options)
+            })?,
+            tags: open_if_exists(base.join(TAGS_TABLE), |path| {
+                TagTable::open_with_options(path, options)
+            })?,
+            edges: open_if_exists(base.join(EDGES_TABLE), |path| {
+                EdgeTable::open_with_options(path, options)
+            })?,
+        })
+    }
+}
+
+fn open_if_exists<T, F: FnOnce(&Path) -> Result<T, memory_lol::db::Error>>(
+    path: PathBuf,
+    open: F,
+) -> Result<Option<T>, memory_lol::db::Error> {
+    if path.exists() {
+        open(&path).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+/// The earliest observation date the requesting user can see (if they aren't trusted).
+struct Access {
+    earliest: Option<NaiveDate>,
+}
+
+pub fn schema(tables: Tables, inclusions: Inclusions) -> LookupSchema {
+    Schema::build(Query, EmptyMutation, EmptySubscription)
+        .data(tables)
+        .data(inclusions)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+}
+
+/// Answer a GraphQL query.
+///
+/// Like the REST routes, observations are limited by the trust policy for users who aren't
+/// trusted (except for accounts in the inclusion list), and annotations and links are only
+/// available to trusted users.
+#[post("/graphql", data = "<request>")]
+pub async fn query(
+    _limit: WithinLimit,
+    request: GraphQLRequest,
+    cookies: &CookieJar<'_>,
+    schema: &State<LookupSchema>,
+    trust: &State<TrustPolicy>,
+    authorizer: &State<SqliteAuthorizer>,
+    connection: Connection<Auth>,
+) -> Result<GraphQLResponse, Error> {
+    let earliest = if auth::lookup_is_trusted(cookies, authorizer, connection).await? {
+        None
+    } else {
+        Some(trust.earliest_untrusted_date(Utc::now().date_naive()))
+    };
+
+    Ok(request
+        .data(Access { earliest })
+        .execute(schema.inner())
+        .await)
+}
+
+fn lookup(ctx: &Context<'_>, id: u64) -> Result<HashMap<String, Vec<NaiveDate>>> {
+    let tables = ctx.data::<Tables>()?;
+    let earliest = ctx.data::<Access>()?.earliest;
+
+    Ok(if ctx.data::<Inclusions>()?.contains(id) {
+        tables.db.lookup_by_user_id(id)?
+    } else {
+        tables.db.limited_lookup_by_user_id(id, earliest)?
+    })
+}
+
+fn require_trusted(ctx: &Context<'_>) -> Result<()> {
+    if ctx.data::<Access>()?.earliest.is_none() {
+        Ok(())
+    } else {
+        Err("Annotations and links are only available to trusted users".into())
+    }
+}
+
+fn in_range(date: &NaiveDate, since: Option<NaiveDate>, until: Option<NaiveDate>) -> bool {
+    since.is_none_or(|since| *date >= since) && until.is_none_or(|until| *date <= until)
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// An account by user ID.
+    async fn account(&self, ctx: &Context<'_>, id: ID) -> Result<Option<Account>> {
+        let id = id.parse::<u64>().map_err(|_| "Invalid user ID")?;
+
+        Ok(if lookup(ctx, id)?.is_empty() {
+            None
+        } else {
+            Some(Account(id))
+        })
+    }
+
+    /// Accounts that have used a screen name (compared case-insensitively).
+    async fn screen_name(&self, ctx: &Context<'_>, screen_name: String) -> Result<Vec<Account>> {
+        let target = screen_name_to_key(&screen_name);
+        let mut accounts = vec![];
+
+        for id in ctx
+            .data::<Tables>()?
+            .db
+            .lookup_by_screen_name(&screen_name)?
+        {
+            // An untrusted user may not be able to see the account's use of the screen name
+            if lookup(ctx, id)?
+                .keys()
+                .any(|screen_name| screen_name_to_key(screen_name) == target)
+            {
+                accounts.push(Account(id));
+            }
+        }
+
+        Ok(accounts)
+    }
+}
+
+pub struct Account(u64);
+
+#[Object]
+impl Account {
+    async fn id(&self) -> ID {
+        ID(self.0.to_string())
+    }
+
+    /// The account's screen names in order of first observation, with their observation dates
+    /// limited to an inclusive range (screen names without dates in the range are omitted).
+    async fn screen_names(
+        &self,
+        ctx: &Context<'_>,
+        since: Option<NaiveDate>,
+        until: Option<NaiveDate>,
+    ) -> Result<Vec<ScreenName>> {
+        let mut screen_names = lookup(ctx, self.0)?
+            .into_iter()
+            .filter_map(|(screen_name, dates)| {
+                let dates = dates
+                    .into_iter()
+                    .filter(|date| in_range(date, since, until))
+                    .collect::<Vec<_>>();
+
+                if dates.is_empty() && (since.is_some() || until.is_some()) {
+                    None
+                } else {
+                    Some(ScreenName {
+                        screen_name,
+                        first_seen: dates.first().copied(),
+                        last_seen: dates.last().copied(),
+                        dates,
+                    })
+                }
+            })
+            .collect::<Vec<_>>();
+
+        screen_names.sort_by(|a, b| {
+            a.first_seen
+                .cmp(&b.first_seen)
+                .then_with(|| a.screen_name.cmp(&b.screen_name))
+        });
+
+        Ok(screen_names)
+    }
+
+    /// Analyst notes attached to the account, oldest first.
+    async fn notes(&self, ctx: &Context<'_>) -> Result<Vec<Note>> {
+        require_trusted(ctx)?;
+
+        Ok(match &ctx.data::<Tables>()?.notes {
+            Some(notes) => notes
+                .lookup(self.0)?
+                .into_iter()
+                .map(|note| Note {
+                    timestamp: note.timestamp,
+                    author: note.author,
+                    text: note.text,
+                })
+                .collect(),
+            None => vec![],
+        })
+    }
+
+    /// The tags the account has been given.
+    async fn tags(&self, ctx: &Context<'_>) -> Result<Vec<String>> {
+        require_trusted(ctx)?;
+
+        Ok(match &ctx.data::<Tables>()?.tags {
+            Some(tags) => tags.tags_for_id(self.0)?,
+            None => vec![],
+        })
+    }
+
+    /// Interactions with other accounts in one direction, optionally of a single kind, with their
+    /// dates limited to an inclusive range.
+    async fn links(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default_with = "LinkDirection::Outgoing")] direction: LinkDirection,
+        kind: Option<LinkKind>,
+        since: Option<NaiveDate>,
+        until: Option<NaiveDate>,
+    ) -> Result<Vec<Link>> {
+        require_trusted(ctx)?;
+
+        Ok(match &ctx.data::<Tables>()?.edges {
+            Some(edges) => edges
+                .edges(self.0, direction.into(), kind.map(Into::into), since, until)?
+                .into_iter()
+                .map(|edge| Link {
+                    kind: edge.kind.into(),
+                    source: Account(edge.source),
+                    target: Account(edge.target),
+                    dates: edge.dates,
+                })
+                .collect(),
+            None => vec![],
+        })
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct ScreenName {
+    screen_name: String,
+    first_seen: Option<NaiveDate>,
+    last_seen: Option<NaiveDate>,
+    dates: Vec<NaiveDate>,
+}
+
+#[derive(SimpleObject)]
+pub struct Note {
+    timestamp: DateTime<Utc>,
+    author: String,
+    text: String,
+}
+
+#[derive(SimpleObject)]
+pub struct Link {
+    kind: LinkKind,
+    /// The account that mentioned, replied to, quoted, or retweeted the target
+    source: Account,
+    target: Account,
+    dates: Vec<NaiveDate>,
+}
+
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq)]
+pub enum LinkDirection {
+    /// Interactions targeting the account
+    Incoming,
+    /// Interactions made by the account
+    Outgoing,
+}
+
+impl From<LinkDirection> for edges::Direction {
+    fn from(direction: LinkDirection) -> Self {
+        match direction {
+            LinkDirection::Incoming => Self::Incoming,
+            LinkDirection::Outgoing => Self::Outgoing,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq)]
+pub enum LinkKind {
+    Mention,
+    Reply,
+    Quote,
+    Retweet,
+}
+
+impl From<LinkKind> for EdgeKind {
+    fn from(kind: LinkKind) -> Self {
+        match kind {
+            LinkKind::Mention => Self::Mention,
+            LinkKind::Reply => Self::Reply,
+            LinkKind::Quote => Self::Quote,
+            LinkKind::Retweet => Self::Retweet,
+        }
+    }
+}
+
+impl From<EdgeKind> for LinkKind {
+    fn from(kind: EdgeKind) -> Self {
+        match kind {
+            EdgeKind::Mention => Self::Mention,
+            EdgeKind::Reply => Self::Reply,
+            EdgeKind::Quote => Self::Quote,
+            EdgeKind::Retweet => Self::Retweet,
+        }
+    }
+}
@@ -1,16 +1,20 @@
-use super::{error::Error, inclusions::Inclusions, ExtendedAccount, ExtendedScreenNameResult};
-use chrono::{Duration, NaiveDate, Utc};
+use super::{error::Error, inclusions::Inclusions};
+use chrono::{NaiveDate, Utc};
 use memory_lol::{
+    admin::TrustPolicy,
     db::{table::ReadOnly, Database},
-    model::Account,
+    embed,
+    model::{Account, ExtendedAccount, ExtendedScreenNameResult},
+    snapshots::{Snapshot, SnapshotStore},
+    summary::Summary,
 };
-use serde_json::{Map, Value};
+use serde_json::{json, Map, Value};
 
-const UNAUTHORIZED_DAY_LIMIT: i64 = 60;
 const LOOKUP_BY_PREFIX_LIMIT: usize = 100;
+const CONFUSABLE_VARIANTS_LIMIT: usize = 1024;
 
-fn get_unauthorized_first_date(limit: i64) -> NaiveDate {
-    Utc::now().naive_utc().date() - Duration::days(limit)
+fn get_unauthorized_first_date(trust: &TrustPolicy) -> NaiveDate {
+    trust.earliest_untrusted_date(Utc::now().naive_utc().date())
 }
 
 fn lookup_ids(
@@ -45,30 +49,163 @@ fn lookup_ids(
 pub(crate) fn by_user_id(
     db: &Database<ReadOnly>,
     user_id: u64,
+    trust: &TrustPolicy,
     is_trusted: bool,
 ) -> Result<ExtendedAccount, Error> {
     let result = if is_trusted {
         db.lookup_by_user_id(user_id)?
     } else {
-        db.limited_lookup_by_user_id(
-            user_id,
-            Some(get_unauthorized_first_date(UNAUTHORIZED_DAY_LIMIT)),
-        )?
+        db.limited_lookup_by_user_id(user_id, Some(get_unauthorized_first_date(trust)))?
     };
 
     Ok(Account::from_raw_result(user_id, result).into())
 }
 
+/// Save an evidence snapshot of the account, returning its ID.
+///
+/// Anyone with a snapshot's permalink can read it, so it holds the result untrusted users see
+/// (unless the account is included).
+pub(crate) fn snapshot_by_user_id(
+    db: &Database<ReadOnly>,
+    user_id: u64,
+    inclusions: &Inclusions,
+    trust: &TrustPolicy,
+    store: &SnapshotStore,
+) -> Result<(String, Snapshot), Error> {
+    let account = by_user_id(db, user_id, trust, inclusions.contains(user_id))?;
+    let snapshot = Snapshot::new(user_id, serde_json::to_value(account)?, Utc::now())?;
+    let id = store.save(&snapshot)?;
+
+    Ok((id, snapshot))
+}
+
+/// A Vega-Lite chart of the account's screen names as non-overlapping date ranges, with the gaps
+/// in its observations.
+pub(crate) fn timeline_by_user_id(
+    db: &Database<ReadOnly>,
+    user_id: u64,
+    max_gap_days: i64,
+    trust: &TrustPolicy,
+    is_trusted: bool,
+) -> Result<Value, Error> {
+    let result = if is_trusted {
+        db.lookup_by_user_id(user_id)?
+    } else {
+        db.limited_lookup_by_user_id(user_id, Some(get_unauthorized_first_date(trust)))?
+    };
+
+    let timeline = memory_lol::timeline::Timeline::new(&result, max_gap_days);
+
+    Ok(memory_lol::timeline::vega_lite_spec(user_id, &timeline))
+}
+
+fn summary(
+    db: &Database<ReadOnly>,
+    user_id: u64,
+    trust: &TrustPolicy,
+    is_trusted: bool,
+) -> Result<Summary, Error> {
+    let result = if is_trusted {
+        db.lookup_by_user_id(user_id)?
+    } else {
+        db.limited_lookup_by_user_id(user_id, Some(get_unauthorized_first_date(trust)))?
+    };
+
+    Summary::from_result(&result).ok_or(Error::NoObservations(user_id))
+}
+
+/// A one-line summary of the account's screen names (see [`memory_lol::summary`]).
+pub(crate) fn summary_by_user_id(
+    db: &Database<ReadOnly>,
+    user_id: u64,
+    trust: &TrustPolicy,
+    is_trusted: bool,
+) -> Result<Value, Error> {
+    Ok(summary(db, user_id, trust, is_trusted)?.to_json(user_id))
+}
+
+/// An HTML fragment of the account's screen names that links to it at the public URL (see
+/// [`memory_lol::embed`]).
+pub(crate) fn embed_by_user_id(
+    db: &Database<ReadOnly>,
+    user_id: u64,
+    trust: &TrustPolicy,
+    is_trusted: bool,
+    public_url: &str,
+) -> Result<String, Error> {
+    let summary = summary(db, user_id, trust, is_trusted)?;
+    let link = format!("{}/tw/id/{}", public_url.trim_end_matches('/'), user_id);
+
+    Ok(embed::html(user_id, &summary, &link))
+}
+
+/// An oEmbed document for an account page URL.
+pub(crate) fn oembed(
+    db: &Database<ReadOnly>,
+    url: &str,
+    format: Option<&str>,
+    max_width: Option<u32>,
+    trust: &TrustPolicy,
+    is_trusted: bool,
+    public_url: &str,
+) -> Result<Value, Error> {
+    if let Some(format) = format.filter(|format| *format != "json") {
+        return Err(Error::UnsupportedEmbedFormat(format.to_string()));
+    }
+
+    let user_id =
+        embed::user_id_from_url(url).ok_or_else(|| Error::InvalidEmbedUrl(url.to_string()))?;
+    let summary = summary(db, user_id, trust, is_trusted)?;
+    let public_url = public_url.trim_end_matches('/');
+    let link = format!("{}/tw/id/{}", public_url, user_id);
+
+    Ok(embed::oembed(
+        user_id, &summary, &link, public_url, max_width,
+    ))
+}
+
+/// The accounts that have used a screen name in compact form (see
+/// [`memory_lol::summary::Summary::to_compact_json`]).
+///
+/// Results are always limited as they are for untrusted users (except for included accounts), so
+/// that shared caches can serve them to anyone.
+pub(crate) fn compact_by_screen_name(
+    db: &Database<ReadOnly>,
+    screen_name: &str,
+    inclusions: &Inclusions,
+    trust: &TrustPolicy,
+) -> Result<Value, Error> {
+    let earliest = Some(get_unauthorized_first_date(trust));
+    let mut accounts = vec![];
+
+    for user_id in db.lookup_by_screen_name(screen_name)? {
+        let result = if inclusions.contains(user_id) {
+            db.lookup_by_user_id(user_id)?
+        } else {
+            db.limited_lookup_by_user_id(user_id, earliest)?
+        };
+
+        if let Some(summary) = Summary::from_result(&result)
+            .filter(|summary| summary.includes_screen_name(screen_name))
+        {
+            accounts.push(summary.to_compact_json(user_id));
+        }
+    }
+
+    Ok(json!({ "accounts": accounts }))
+}
+
 pub(crate) fn by_screen_name(
     db: &Database<ReadOnly>,
     screen_name: String,
     inclusions: &Inclusions,
+    trust: &TrustPolicy,
     is_trusted: bool,
 ) -> Result<Value, Error> {
     let earliest = if is_trusted {
         None
     } else {
-        Some(get_unauthorized_first_date(UNAUTHORIZED_DAY_LIMIT))
+        Some(get_unauthorized_first_date(trust))
     };
 
     if screen_name.contains(',') {
@@ -86,6 +223,21 @@ pub(crate) fn by_screen_name(
             }
         }
 
+        Ok(serde_json::to_value(map)?)
+    } else if let Some(handle) = screen_name.strip_prefix('~') {
+        // Impersonation search: every screen name that looks like the given handle
+        let mut map = Map::new();
+        let results = db.lookup_confusable(handle, CONFUSABLE_VARIANTS_LIMIT)?;
+
+        for (screen_name, user_ids) in results {
+            let accounts = lookup_ids(db, &user_ids, inclusions, earliest)?;
+            let result = ExtendedScreenNameResult { accounts };
+
+            if result.includes_screen_name(&screen_name) {
+                map.insert(screen_name.to_string(), serde_json::to_value(result)?);
+            }
+        }
+
         Ok(serde_json::to_value(map)?)
     } else if screen_name.ends_with('*') {
         let mut map = Map::new();
@@ -1,8 +1,21 @@
 #[macro_use]
 extern crate rocket;
 
-use memory_lol::db::{table::ReadOnly, Database};
-use memory_lol::model::Account;
+use memory_lol::db::{
+    encryption::EncryptionConfig,
+    latency::{LatencyRecorder, DEFAULT_PUBLISH_INTERVAL},
+    stats::StatsTable,
+    table::ReadOnly,
+    Database, DatabaseOptions, STATS_TABLE,
+};
+use memory_lol::{
+    admin::TrustPolicy,
+    cors::CorsPolicy,
+    model::ExtendedAccount,
+    ratelimit::{RateLimitConfig, RateLimiter},
+    snapshots::SnapshotStore,
+    timestamping::Proof,
+};
 use memory_lol_auth::{
     model::{
         providers::{GitHub, Google, Twitter},
@@ -14,24 +27,35 @@ use memory_lol_auth_sqlx::SqlxAuthDb;
 use rocket::{
     fairing::{AdHoc, Fairing},
     form::Form,
-    http::CookieJar,
+    http::{ContentType, CookieJar, Method, Status},
+    response::{content::RawHtml, status::Created},
     serde::json::Json,
     Build, Rocket, State,
 };
 use rocket_db_pools::{sqlx, Connection, Database as PoolDatabase};
 use rocket_oauth2::{OAuth2, OAuthConfig};
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use serde_json::Value;
+use std::time::Instant;
 
+mod admin;
 mod auth;
+mod cache;
 mod error;
+#[cfg(feature = "graphql")]
+mod graphql;
 mod inclusions;
 mod logic;
+mod ratelimit;
 mod snowflake;
-mod util;
+mod stats;
+mod timestamping;
 
+use cache::{Cached, DatabaseOpened, Immutable, PubliclyCached};
 use error::Error;
 use inclusions::Inclusions;
+use ratelimit::WithinLimit;
+use timestamping::Timestamper;
 
 fn provider_fairing<P: IsProvider>() -> impl Fairing {
     OAuth2::<P>::fairing(P::provider().name())
@@ -44,41 +68,41 @@ pub struct AppConfig {
     domain: Option<String>,
     default_login_redirect_uri: rocket::http::uri::Reference<'static>,
     inclusions: Option<String>,
+    db_options: Option<DatabaseOptions>,
+    /// Keys for an encrypted database (for example
+    /// `encryption = { key = "file:/etc/memory-lol/key", previous_keys = [] }`)
+    encryption: Option<EncryptionConfig>,
+    /// Serve public dataset statistics at `/stats`
+    stats: Option<bool>,
+    /// Periodically write the p99 request latency to the database directory, so that imports can
+    /// back off when requests slow down
+    publish_latency: Option<bool>,
+    /// Let browser scripts on these origins call the API (CORS is disabled if missing)
+    cors: Option<CorsPolicy>,
+    /// Limit lookups per client (lookups aren't limited if missing)
+    rate_limit: Option<RateLimitConfig>,
+    /// Days of observations that lookups by untrusted users can see (60 by default)
+    untrusted_day_limit: Option<i64>,
+    /// Token that enables `/admin/settings` for requests sending it as a bearer token
+    admin_token: Option<String>,
+    /// Serve a GraphQL API at `/graphql` (requires the `graphql` feature)
+    graphql: Option<bool>,
+    /// URL of the API that embeds link back to (like `https://api.memory.lol/v1`; embeds are
+    /// disabled if missing)
+    public_url: Option<String>,
+    /// Directory to save evidence snapshots in (snapshots are disabled if missing, and can only be
+    /// taken by requests sending the admin token)
+    snapshot_dir: Option<String>,
+    /// Refuse new snapshots once the snapshot directory holds this many (10,000 by default)
+    snapshot_limit: Option<usize>,
+    /// Timestamp new snapshots with this RFC 3161 time-stamping authority
+    tsa_url: Option<String>,
+    /// Anchor new snapshots in Bitcoin with this OpenTimestamps calendar
+    opentimestamps_calendar: Option<String>,
 }
 
-#[derive(Debug, Default, Eq, PartialEq, Serialize)]
-pub struct ExtendedScreenNameResult {
-    accounts: Vec<ExtendedAccount>,
-}
-
-impl ExtendedScreenNameResult {
-    pub fn includes_screen_name(&self, screen_name: &str) -> bool {
-        let target_screen_name = screen_name.to_lowercase();
-        self.accounts.iter().any(|account| {
-            account
-                .screen_names
-                .keys()
-                .any(|screen_name| screen_name.to_lowercase() == target_screen_name)
-        })
-    }
-}
-
-#[derive(Debug, Eq, PartialEq, Serialize)]
-pub struct ExtendedAccount {
-    pub id: u64,
-    pub id_str: String,
-    pub screen_names: indexmap::IndexMap<String, Option<Vec<chrono::NaiveDate>>>,
-}
-
-impl From<Account> for ExtendedAccount {
-    fn from(account: Account) -> Self {
-        Self {
-            id: account.id,
-            id_str: account.id.to_string(),
-            screen_names: account.screen_names,
-        }
-    }
-}
+/// When the current request started, for recording its latency.
+struct RequestStarted(Instant);
 
 #[derive(FromForm)]
 struct WithToken<'a> {
@@ -93,30 +117,222 @@ pub struct Auth(sqlx::SqlitePool);
 
 #[get("/tw/id/<user_id>")]
 async fn by_user_id(
+    _limit: WithinLimit,
     user_id: u64,
     cookies: &CookieJar<'_>,
     db: &State<Database<ReadOnly>>,
     inclusions: &State<Inclusions>,
+    trust: &State<TrustPolicy>,
     authorizer: &State<SqliteAuthorizer>,
     connection: Connection<Auth>,
-) -> Result<Json<ExtendedAccount>, Error> {
+) -> Result<Cached<ExtendedAccount>, Error> {
     let full_results = if inclusions.contains(user_id) {
         true
     } else {
         auth::lookup_is_trusted(cookies, authorizer, connection).await?
     };
 
-    let account = crate::logic::by_user_id(db, user_id, full_results)?;
+    let account = crate::logic::by_user_id(db, user_id, trust, full_results)?;
 
-    Ok(Json(account))
+    Ok(Cached(account))
+}
+
+#[get("/tw/id/<user_id>/timeline?<max_gap_days>")]
+async fn timeline_by_user_id(
+    _limit: WithinLimit,
+    user_id: u64,
+    max_gap_days: Option<i64>,
+    cookies: &CookieJar<'_>,
+    db: &State<Database<ReadOnly>>,
+    inclusions: &State<Inclusions>,
+    trust: &State<TrustPolicy>,
+    authorizer: &State<SqliteAuthorizer>,
+    connection: Connection<Auth>,
+) -> Result<Cached<Value>, Error> {
+    let full_results = if inclusions.contains(user_id) {
+        true
+    } else {
+        auth::lookup_is_trusted(cookies, authorizer, connection).await?
+    };
+
+    let spec = crate::logic::timeline_by_user_id(
+        db,
+        user_id,
+        max_gap_days.unwrap_or(memory_lol::timeline::DEFAULT_MAX_GAP_DAYS),
+        trust,
+        full_results,
+    )?;
+
+    Ok(Cached(spec))
+}
+
+#[get("/tw/id/<user_id>/summary")]
+async fn summary_by_user_id(
+    _limit: WithinLimit,
+    user_id: u64,
+    cookies: &CookieJar<'_>,
+    db: &State<Database<ReadOnly>>,
+    inclusions: &State<Inclusions>,
+    trust: &State<TrustPolicy>,
+    authorizer: &State<SqliteAuthorizer>,
+    connection: Connection<Auth>,
+) -> Result<Cached<Value>, Error> {
+    let full_results = if inclusions.contains(user_id) {
+        true
+    } else {
+        auth::lookup_is_trusted(cookies, authorizer, connection).await?
+    };
+
+    let summary = crate::logic::summary_by_user_id(db, user_id, trust, full_results)?;
+
+    Ok(Cached(summary))
+}
+
+#[get("/tw/id/<user_id>/embed")]
+async fn embed_by_user_id(
+    _limit: WithinLimit,
+    user_id: u64,
+    cookies: &CookieJar<'_>,
+    db: &State<Database<ReadOnly>>,
+    inclusions: &State<Inclusions>,
+    trust: &State<TrustPolicy>,
+    config: &State<AppConfig>,
+    authorizer: &State<SqliteAuthorizer>,
+    connection: Connection<Auth>,
+) -> Result<RawHtml<String>, Error> {
+    let public_url = config.public_url.as_deref().ok_or(Error::EmbedsDisabled)?;
+    let full_results = if inclusions.contains(user_id) {
+        true
+    } else {
+        auth::lookup_is_trusted(cookies, authorizer, connection).await?
+    };
+
+    let html = crate::logic::embed_by_user_id(db, user_id, trust, full_results, public_url)?;
+
+    Ok(RawHtml(html))
+}
+
+#[get("/oembed?<url>&<format>&<maxwidth>")]
+async fn oembed(
+    _limit: WithinLimit,
+    url: &str,
+    format: Option<&str>,
+    maxwidth: Option<u32>,
+    cookies: &CookieJar<'_>,
+    db: &State<Database<ReadOnly>>,
+    inclusions: &State<Inclusions>,
+    trust: &State<TrustPolicy>,
+    config: &State<AppConfig>,
+    authorizer: &State<SqliteAuthorizer>,
+    connection: Connection<Auth>,
+) -> Result<Json<Value>, Error> {
+    let public_url = config.public_url.as_deref().ok_or(Error::EmbedsDisabled)?;
+    let included = memory_lol::embed::user_id_from_url(url)
+        .is_some_and(|user_id| inclusions.contains(user_id));
+    let full_results = included || auth::lookup_is_trusted(cookies, authorizer, connection).await?;
+
+    let document =
+        crate::logic::oembed(db, url, format, maxwidth, trust, full_results, public_url)?;
+
+    Ok(Json(document))
+}
+
+#[post("/tw/id/<user_id>/snapshot")]
+async fn snapshot_by_user_id(
+    _admin: admin::Admin,
+    _limit: WithinLimit,
+    user_id: u64,
+    db: &State<Database<ReadOnly>>,
+    inclusions: &State<Inclusions>,
+    trust: &State<TrustPolicy>,
+    config: &State<AppConfig>,
+    timestamper: &State<Timestamper>,
+) -> Result<Created<Json<Value>>, Error> {
+    let store = config
+        .snapshot_dir
+        .as_ref()
+        .map(SnapshotStore::new)
+        .ok_or(Error::SnapshotsDisabled)?
+        .with_limit(
+            config
+                .snapshot_limit
+                .unwrap_or(memory_lol::snapshots::DEFAULT_LIMIT),
+        );
+    let (id, snapshot) = crate::logic::snapshot_by_user_id(db, user_id, inclusions, trust, &store)?;
+    let url = format!(
+        "{}/snapshots/{}",
+        config
+            .public_url
+            .as_deref()
+            .unwrap_or("")
+            .trim_end_matches('/'),
+        id
+    );
+    let proofs = timestamper.stamp(&store, &id).await;
+    let timestamps = proofs
+        .into_iter()
+        .map(|proof| (proof.to_string(), Value::from(format!("{}/{}", url, proof))))
+        .collect::<serde_json::Map<_, _>>();
+
+    Ok(Created::new(url.clone()).body(Json(serde_json::json!({
+        "id": id,
+        "url": url,
+        "sha256": snapshot.sha256,
+        "taken": snapshot.taken,
+        "timestamps": timestamps,
+    }))))
+}
+
+#[get("/snapshots/<id>")]
+async fn snapshot(
+    _limit: WithinLimit,
+    id: &str,
+    config: &State<AppConfig>,
+) -> Result<Immutable, Error> {
+    let store = config
+        .snapshot_dir
+        .as_ref()
+        .map(SnapshotStore::new)
+        .ok_or(Error::SnapshotsDisabled)?;
+    let contents = store
+        .load(id)?
+        .ok_or_else(|| Error::NoSnapshot(id.to_string()))?;
+
+    Ok(Immutable(contents))
+}
+
+#[get("/snapshots/<id>/<proof>")]
+async fn snapshot_timestamp(
+    _limit: WithinLimit,
+    id: &str,
+    proof: &str,
+    config: &State<AppConfig>,
+) -> Result<(ContentType, Vec<u8>), Error> {
+    let store = config
+        .snapshot_dir
+        .as_ref()
+        .map(SnapshotStore::new)
+        .ok_or(Error::SnapshotsDisabled)?;
+    let proof = proof
+        .parse::<Proof>()
+        .map_err(|_| Error::NoSnapshot(id.to_string()))?;
+    let contents = store
+        .load_proof(id, proof)?
+        .ok_or_else(|| Error::NoSnapshot(id.to_string()))?;
+    let content_type =
+        ContentType::parse_flexible(proof.content_type()).unwrap_or(ContentType::Binary);
+
+    Ok((content_type, contents))
 }
 
 #[post("/tw/id/<user_id>", data = "<with_token>")]
 async fn by_user_id_post(
+    _limit: WithinLimit,
     user_id: u64,
     with_token: Form<WithToken<'_>>,
     db: &State<Database<ReadOnly>>,
     inclusions: &State<Inclusions>,
+    trust: &State<TrustPolicy>,
     authorizer: &State<SqliteAuthorizer>,
     mut connection: Connection<Auth>,
 ) -> Result<Json<ExtendedAccount>, Error> {
@@ -143,32 +359,52 @@ async fn by_user_id_post(
         }
     };
 
-    let account = crate::logic::by_user_id(db, user_id, full_results)?;
+    let account = crate::logic::by_user_id(db, user_id, trust, full_results)?;
 
     Ok(Json(account))
 }
 
 #[get("/tw/<screen_name_query>")]
 async fn by_screen_name(
+    _limit: WithinLimit,
     screen_name_query: String,
     cookies: &CookieJar<'_>,
     db: &State<Database<ReadOnly>>,
     inclusions: &State<Inclusions>,
+    trust: &State<TrustPolicy>,
     authorizer: &State<SqliteAuthorizer>,
     connection: Connection<Auth>,
-) -> Result<Json<Value>, Error> {
+) -> Result<Cached<Value>, Error> {
     let is_trusted = auth::lookup_is_trusted(cookies, authorizer, connection).await?;
-    let result = crate::logic::by_screen_name(db, screen_name_query, inclusions, is_trusted)?;
+    let result =
+        crate::logic::by_screen_name(db, screen_name_query, inclusions, trust, is_trusted)?;
 
-    Ok(Json(result))
+    Ok(Cached(result))
+}
+
+/// A screen name's accounts with only their names and dates, for browser extensions that look up
+/// every account they show.
+#[get("/tw/<screen_name>/compact")]
+async fn compact_by_screen_name(
+    _limit: WithinLimit,
+    screen_name: String,
+    db: &State<Database<ReadOnly>>,
+    inclusions: &State<Inclusions>,
+    trust: &State<TrustPolicy>,
+) -> Result<PubliclyCached<Value>, Error> {
+    let result = crate::logic::compact_by_screen_name(db, &screen_name, inclusions, trust)?;
+
+    Ok(PubliclyCached(result))
 }
 
 #[post("/tw/<screen_name_query>", data = "<with_token>")]
 async fn by_screen_name_post(
+    _limit: WithinLimit,
     screen_name_query: String,
     with_token: Form<WithToken<'_>>,
     db: &State<Database<ReadOnly>>,
     inclusions: &State<Inclusions>,
+    trust: &State<TrustPolicy>,
     authorizer: &State<SqliteAuthorizer>,
     mut connection: Connection<Auth>,
 ) -> Result<Json<Value>, Error> {
@@ -190,7 +426,7 @@ async fn by_screen_name_post(
         }
         Some(authorization) => authorization.is_trusted(),
     };
-    let result = crate::logic::by_screen_name(db, screen_name_query, inclusions, access)?;
+    let result = crate::logic::by_screen_name(db, screen_name_query, inclusions, trust, access)?;
 
     Ok(Json(result))
 }
@@ -201,7 +437,7 @@ fn rocket() -> _ {
         .attach(AdHoc::config::<AppConfig>())
         .attach(AdHoc::try_on_ignite("Open database", |rocket| async {
             match init_db(&rocket) {
-                Some(db) => Ok(rocket.manage(db)),
+                Some(db) => Ok(rocket.manage(db).manage(DatabaseOpened(chrono::Utc::now()))),
                 None => Err(rocket),
             }
         }))
@@ -220,16 +456,157 @@ fn rocket() -> _ {
                 }
             },
         ))
+        .attach(AdHoc::try_on_ignite("Statistics", |rocket| async {
+            if rocket
+                .state::<AppConfig>()
+                .and_then(|config| config.stats)
+                .unwrap_or(false)
+            {
+                match init_stats(&rocket) {
+                    Some(stats) => Ok(rocket
+                        .manage(stats)
+                        .mount("/", routes![stats::page, stats::json, stats::json_file])),
+                    None => Err(rocket),
+                }
+            } else {
+                Ok(rocket)
+            }
+        }))
+        .attach(AdHoc::on_ignite("Request latency", |rocket| async {
+            let recorder = rocket
+                .state::<AppConfig>()
+                .filter(|config| config.publish_latency.unwrap_or(false))
+                .map(|config| LatencyRecorder::new(&config.db, DEFAULT_PUBLISH_INTERVAL));
+
+            match recorder {
+                Some(recorder) => rocket.manage(recorder),
+                None => rocket,
+            }
+        }))
+        .attach(AdHoc::try_on_ignite("Rate limiting", |rocket| async {
+            let config = rocket
+                .state::<AppConfig>()
+                .and_then(|config| config.rate_limit.clone());
+
+            match config.map(RateLimiter::new) {
+                Some(Ok(limiter)) => Ok(rocket.manage(limiter)),
+                Some(Err(error)) => {
+                    log::error!("Failed to load rate limiting state: {:?}", error);
+                    Err(rocket)
+                }
+                None => Ok(rocket),
+            }
+        }))
+        .attach(AdHoc::try_on_ignite("Timestamping", |rocket| async {
+            let urls = rocket.state::<AppConfig>().map(|config| {
+                (
+                    config.tsa_url.clone(),
+                    config.opentimestamps_calendar.clone(),
+                )
+            });
+            let (tsa_url, calendar_url) = urls.unwrap_or_default();
+
+            match Timestamper::new(tsa_url, calendar_url) {
+                Ok(timestamper) => Ok(rocket.manage(timestamper)),
+                Err(error) => {
+                    log::error!("Failed to create timestamping client: {:?}", error);
+                    Err(rocket)
+                }
+            }
+        }))
+        .attach(AdHoc::on_ignite("Trust policy", |rocket| async {
+            let policy = rocket
+                .state::<AppConfig>()
+                .and_then(|config| config.untrusted_day_limit)
+                .map_or_else(TrustPolicy::default, TrustPolicy::new);
+
+            rocket.manage(policy)
+        }))
+        .attach(AdHoc::on_ignite("Admin", |rocket| async {
+            if rocket
+                .state::<AppConfig>()
+                .is_some_and(|config| config.admin_token.is_some())
+            {
+                rocket.mount("/", routes![admin::settings, admin::update_settings])
+            } else {
+                rocket
+            }
+        }))
+        .attach(AdHoc::on_ignite("CORS", |rocket| async {
+            let policy = rocket
+                .state::<AppConfig>()
+                .and_then(|config| config.cors.clone());
+
+            match policy {
+                Some(policy) => rocket.manage(policy),
+                None => rocket,
+            }
+        }))
+        .attach(AdHoc::try_on_ignite("GraphQL", |rocket| async {
+            if rocket
+                .state::<AppConfig>()
+                .and_then(|config| config.graphql)
+                .unwrap_or(false)
+            {
+                init_graphql(rocket)
+            } else {
+                Ok(rocket)
+            }
+        }))
+        .attach(AdHoc::on_response("CORS headers", |request, response| {
+            Box::pin(async move {
+                if let Some(policy) = request.rocket().state::<CorsPolicy>() {
+                    let preflight = request.method() == Method::Options;
+
+                    // There are no OPTIONS routes, so preflight requests are answered here
+                    if preflight && response.status() == Status::NotFound {
+                        response.set_status(Status::NoContent);
+                        response.set_sized_body(0, std::io::Cursor::new(vec![]));
+                    }
+
+                    for (name, value) in
+                        policy.response_headers(request.headers().get_one("Origin"), preflight)
+                    {
+                        response.set_raw_header(name, value);
+                    }
+                }
+            })
+        }))
+        .attach(AdHoc::on_request("Start request timer", |request, _| {
+            Box::pin(async move {
+                request.local_cache(|| RequestStarted(Instant::now()));
+            })
+        }))
+        .attach(AdHoc::on_response(
+            "Record request latency",
+            |request, _| {
+                Box::pin(async move {
+                    if let Some(recorder) = request.rocket().state::<LatencyRecorder>() {
+                        let started = request.local_cache(|| RequestStarted(Instant::now()));
+                        recorder.record(started.0.elapsed());
+                    }
+                })
+            },
+        ))
         .attach(Auth::init())
         .attach(provider_fairing::<GitHub>())
         .attach(provider_fairing::<Google>())
         .attach(provider_fairing::<Twitter>())
+        .register("/", catchers![ratelimit::too_many_requests])
         .mount(
             "/",
             routes![
                 by_user_id,
                 by_user_id_post,
+                timeline_by_user_id,
+                summary_by_user_id,
+                embed_by_user_id,
+                oembed,
+                snapshot_by_user_id,
+                snapshot,
+                snapshot_timestamp,
                 by_screen_name,
+                compact_by_screen_name,
                 by_screen_name_post,
                 snowflake::info,
                 auth::login::status,
@@ -246,7 +623,56 @@ fn rocket() -> _ {
 
 fn init_db(rocket: &Rocket<Build>) -> Option<Database<ReadOnly>> {
     let config = rocket.state::<AppConfig>()?;
-    Database::<ReadOnly>::open(&config.db).ok()
+
+    Database::<ReadOnly>::open_with_options(&config.db, &database_options(config)?).ok()
+}
+
+fn database_options(config: &AppConfig) -> Option<DatabaseOptions> {
+    let mut options = config.db_options.clone().unwrap_or_default();
+
+    if let Some(encryption) = &config.encryption {
+        options.encryption = match encryption.load() {
+            Ok(keyring) => keyring.map(std::sync::Arc::new),
+            Err(error) => {
+                log::error!("Failed to load encryption keys: {:?}", error);
+                return None;
+            }
+        };
+    }
+
+    Some(options)
+}
+
+/// Open the tables the GraphQL API resolves queries from and mount its route.
+#[cfg(feature = "graphql")]
+fn init_graphql(rocket: Rocket<Build>) -> Result<Rocket<Build>, Rocket<Build>> {
+    let tables = rocket.state::<AppConfig>().and_then(|config| {
+        graphql::Tables::open(&config.db, &database_options(config)?)
+            .map_err(|error| log::error!("Failed to open GraphQL tables: {:?}", error))
+            .ok()
+    });
+
+    match tables.zip(init_inclusions(&rocket)) {
+        Some((tables, inclusions)) => Ok(rocket
+            .manage(graphql::schema(tables, inclusions))
+            .mount("/", routes![graphql::query])),
+        None => Err(rocket),
+    }
+}
+
+#[cfg(not(feature = "graphql"))]
+fn init_graphql(rocket: Rocket<Build>) -> Result<Rocket<Build>, Rocket<Build>> {
+    log::error!("GraphQL is enabled in the configuration, but this build doesn't include it");
+    Err(rocket)
+}
+
+fn init_stats(rocket: &Rocket<Build>) -> Option<StatsTable<ReadOnly>> {
+    let config = rocket.state::<AppConfig>()?;
+    StatsTable::<ReadOnly>::open_with_options(
+        std::path::Path::new(&config.db).join(STATS_TABLE),
+        &config.db_options.clone().unwrap_or_default(),
+    )
+    .ok()
 }
 
 fn init_inclusions(rocket: &Rocket<Build>) -> Option<Inclusions> {
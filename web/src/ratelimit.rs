@@ -0,0 +1,61 @@
+use memory_lol::ratelimit::{Decision, RateLimiter, API_KEY_HEADER};
+use rocket::{
+    http::{Header, Status},
+    request::{FromRequest, Outcome, Request},
+    response::{self, Responder, Response},
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A request guard that counts the request against its client's limit, failing with
+/// `429 Too Many Requests` if the client is over it.
+///
+/// Requests always succeed if rate limiting isn't configured.
+pub struct WithinLimit;
+
+/// How long a limited client must wait, for the catcher's `Retry-After` header.
+struct RetryAfter(Option<u64>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WithinLimit {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let limiter = request.rocket().state::<RateLimiter>();
+
+        if let (Some(limiter), Some(address)) = (limiter, request.client_ip()) {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs());
+
+            if let Decision::Limited { retry_after } =
+                limiter.check(address, request.headers().get_one(API_KEY_HEADER), now)
+            {
+                request.local_cache(|| RetryAfter(Some(retry_after)));
+
+                return Outcome::Error((Status::TooManyRequests, ()));
+            }
+        }
+
+        Outcome::Success(WithinLimit)
+    }
+}
+
+pub struct TooManyRequests(Option<u64>);
+
+impl<'r, 'o: 'r> Responder<'r, 'o> for TooManyRequests {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'o> {
+        let mut response = Response::build();
+        response.status(Status::TooManyRequests);
+
+        if let Some(retry_after) = self.0 {
+            response.header(Header::new("Retry-After", retry_after.to_string()));
+        }
+
+        response.ok()
+    }
+}
+
+#[catch(429)]
+pub fn too_many_requests(request: &Request<'_>) -> TooManyRequests {
+    TooManyRequests(request.local_cache(|| RetryAfter(None)).0)
+}
@@ -12,8 +12,8 @@ struct SnowflakeInfo {
 }
 
 #[get("/tw/util/snowflake/<id>")]
-pub fn info(id: i64) -> Result<Json<Value>, Error> {
-    let timestamp = crate::util::snowflake_to_date_time(id).ok_or(Error::InvalidSnowflake(id))?;
+pub fn info(id: u64) -> Result<Json<Value>, Error> {
+    let timestamp = memory_lol::snowflake::to_date_time(id).ok_or(Error::InvalidSnowflake(id))?;
 
     Ok(Json(serde_json::to_value(SnowflakeInfo {
         epoch_second: timestamp.timestamp(),
@@ -0,0 +1,144 @@
+use super::error::Error;
+use chrono::{Duration, NaiveDate, Utc};
+use memory_lol::db::{
+    stats::{DailyIngest, StatsTable, Totals},
+    table::ReadOnly,
+};
+use rocket::{response::content::RawHtml, serde::json::Json, State};
+use serde::Serialize;
+use std::fmt::Write;
+
+/// Number of days of ingest history shown.
+const HISTORY_DAYS: i64 = 90;
+/// Number of days summarized as recent growth.
+const GROWTH_DAYS: i64 = 30;
+
+const CHART_WIDTH: usize = 720;
+const CHART_HEIGHT: usize = 120;
+
+#[derive(Serialize)]
+pub struct DatasetStats {
+    totals: Totals,
+    /// New accounts and pairs over the last `GROWTH_DAYS` days
+    recent_growth: Totals,
+    daily: Vec<DailyStats>,
+}
+
+#[derive(Serialize)]
+struct DailyStats {
+    date: NaiveDate,
+    #[serde(flatten)]
+    ingest: DailyIngest,
+}
+
+impl DatasetStats {
+    fn read(stats: &StatsTable<ReadOnly>) -> Result<Self, Error> {
+        let today = Utc::now().date_naive();
+        let growth_start = today - Duration::days(GROWTH_DAYS);
+        let daily = stats.daily(Some(today - Duration::days(HISTORY_DAYS)))?;
+
+        let mut recent_growth = Totals::default();
+
+        for (date, ingest) in &daily {
+            if *date > growth_start {
+                recent_growth.accounts += ingest.new_accounts;
+                recent_growth.pairs += ingest.new_pairs;
+            }
+        }
+
+        Ok(Self {
+            totals: stats.totals()?,
+            recent_growth,
+            daily: daily
+                .into_iter()
+                .map(|(date, ingest)| DailyStats { date, ingest })
+                .collect(),
+        })
+    }
+
+    fn to_html(&self) -> String {
+        let mut html = String::new();
+
+        // Writing to a string can't fail
+        let _ = write!(
+            html,
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>memory.lol stats</title>\
+             <style>body {{ font-family: sans-serif; margin: 2em; }} \
+             td {{ padding-right: 2em; }} rect {{ fill: #4a7ab5; }}</style></head><body>\
+             <h1>Dataset statistics</h1><table>\
+             <tr><td>Accounts</td><td>{}</td></tr>\
+             <tr><td>Screen name pairs</td><td>{}</td></tr>\
+             <tr><td>New accounts (last {} days)</td><td>{}</td></tr>\
+             <tr><td>New pairs (last {} days)</td><td>{}</td></tr></table>",
+            self.totals.accounts,
+            self.totals.pairs,
+            GROWTH_DAYS,
+            self.recent_growth.accounts,
+            GROWTH_DAYS,
+            self.recent_growth.pairs,
+        );
+
+        self.write_chart(&mut html, "New accounts per day", |ingest| {
+            ingest.new_accounts
+        });
+        self.write_chart(&mut html, "New pairs per day", |ingest| ingest.new_pairs);
+        self.write_chart(&mut html, "Input lines per day", |ingest| ingest.lines_read);
+
+        html.push_str("<p>Also available as <a href=\"/stats.json\">JSON</a>.</p></body></html>");
+        html
+    }
+
+    /// Write a bar chart of one daily count over the history window.
+    fn write_chart<F: Fn(&DailyIngest) -> u64>(&self, html: &mut String, title: &str, value: F) {
+        let start = Utc::now().date_naive() - Duration::days(HISTORY_DAYS);
+        let max = self
+            .daily
+            .iter()
+            .map(|daily| value(&daily.ingest))
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        // The window includes both the start day and today
+        let bar_width = CHART_WIDTH / (HISTORY_DAYS as usize + 1);
+
+        let _ = write!(
+            html,
+            "<h2>{}</h2><svg width=\"{}\" height=\"{}\" role=\"img\" aria-label=\"{}\">",
+            title, CHART_WIDTH, CHART_HEIGHT, title
+        );
+
+        for daily in &self.daily {
+            let count = value(&daily.ingest);
+            let height = (count as f64 / max as f64 * CHART_HEIGHT as f64).ceil() as usize;
+            let x = (daily.date - start).num_days().max(0) as usize * bar_width;
+
+            let _ = write!(
+                html,
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"><title>{}: {}</title></rect>",
+                x,
+                CHART_HEIGHT - height,
+                bar_width.saturating_sub(1).max(1),
+                height,
+                daily.date,
+                count
+            );
+        }
+
+        html.push_str("</svg>");
+    }
+}
+
+#[get("/stats", format = "html")]
+pub fn page(stats: &State<StatsTable<ReadOnly>>) -> Result<RawHtml<String>, Error> {
+    Ok(RawHtml(DatasetStats::read(stats)?.to_html()))
+}
+
+#[get("/stats", rank = 2)]
+pub fn json(stats: &State<StatsTable<ReadOnly>>) -> Result<Json<DatasetStats>, Error> {
+    Ok(Json(DatasetStats::read(stats)?))
+}
+
+#[get("/stats.json")]
+pub fn json_file(stats: &State<StatsTable<ReadOnly>>) -> Result<Json<DatasetStats>, Error> {
+    json(stats)
+}